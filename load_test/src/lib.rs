@@ -0,0 +1,435 @@
+//! Drives in-process socket servers with many concurrent clients and reports
+//! throughput, errors and latency percentiles.
+
+use smart_socket_client::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Command, ProtocolError, Response};
+use smart_socket_server::server::{run_server, ConnectionContext, ServerConfig};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Commands sent by every client, each with a relative weight.
+#[derive(Debug, Clone)]
+pub struct CommandMix {
+    commands: Vec<(Command, u32)>,
+    total: u64,
+}
+
+impl CommandMix {
+    /// The command for a client's `n`-th request. The mix is walked as a cycle of
+    /// `total` slots, so over a full cycle each command is sent exactly its weight.
+    pub fn pick(&self, n: u64) -> &Command {
+        let mut slot = n % self.total;
+        for (command, weight) in &self.commands {
+            match slot.checked_sub(u64::from(*weight)) {
+                Some(rest) => slot = rest,
+                None => return command,
+            }
+        }
+        unreachable!("slot is below the sum of the weights")
+    }
+}
+
+impl Default for CommandMix {
+    fn default() -> Self {
+        "STATUS=8,ON=1,OFF=1".parse().expect("default mix is valid")
+    }
+}
+
+/// Parses `<command>=<weight>,...` using wire syntax, e.g. `STATUS=8,ON,PULSE:50=2`.
+/// A command without a weight counts once.
+impl FromStr for CommandMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let commands = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (command, weight) = match entry.rsplit_once('=') {
+                    Some((command, weight)) => (
+                        command,
+                        weight
+                            .trim()
+                            .parse()
+                            .ok()
+                            .filter(|weight| *weight > 0)
+                            .ok_or_else(|| format!("Invalid weight in '{}'", entry))?,
+                    ),
+                    None => (entry, 1),
+                };
+                let command = command
+                    .parse()
+                    .map_err(|e| format!("Invalid command in '{}': {}", entry, e))?;
+                Ok((command, weight))
+            })
+            .collect::<Result<Vec<(Command, u32)>, String>>()?;
+        if commands.is_empty() {
+            return Err("Command mix is empty".to_string());
+        }
+        let total = commands.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        Ok(Self { commands, total })
+    }
+}
+
+impl fmt::Display for CommandMix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(command, weight)| format!("{}={}", command, weight))
+            .collect();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// Servers started in-process, each with its own device; clients are spread
+    /// across them in turn.
+    pub sockets: usize,
+    pub clients: usize,
+    /// Commands per second each client aims for; 0 sends the next command as soon
+    /// as the previous one is answered.
+    pub rate: f64,
+    /// How long clients keep sending; requests in flight at the end are completed.
+    pub duration: Duration,
+    pub mix: CommandMix,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            sockets: 1,
+            clients: 10,
+            rate: 20.0,
+            duration: Duration::from_secs(10),
+            mix: CommandMix::default(),
+        }
+    }
+}
+
+/// What one client thread saw.
+#[derive(Debug, Default)]
+struct ClientResult {
+    requests: u64,
+    /// Round trips of every answered request, including error responses.
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl ClientResult {
+    fn error(&mut self, label: String) {
+        *self.errors.entry(label).or_default() += 1;
+    }
+}
+
+/// Sends `config.mix` to the server in `client_config` until `deadline`,
+/// reconnecting after the connection is lost. `offset` staggers where in the
+/// mix each client starts.
+fn run_client(
+    client_config: ClientConfig,
+    offset: u64,
+    config: &LoadConfig,
+    deadline: Instant,
+) -> ClientResult {
+    let mut result = ClientResult::default();
+    let interval = (config.rate > 0.0).then(|| Duration::from_secs_f64(1.0 / config.rate));
+    let mut client: Option<SmartSocketClient<ClientStream>> = None;
+    let mut next = Instant::now();
+    let mut n = offset;
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        if let Some(interval) = interval {
+            if next > now {
+                thread::sleep((next - now).min(deadline - now));
+                continue;
+            }
+            next += interval;
+        }
+
+        result.requests += 1;
+        let connection = match client.as_mut() {
+            Some(connection) => connection,
+            None => match SmartSocketClient::with_config(client_config.clone()) {
+                Ok(connection) => client.insert(connection),
+                Err(e) => {
+                    result.error(format!("connect: {}", error_label(&e)));
+                    continue;
+                }
+            },
+        };
+
+        let command = config.mix.pick(n).clone();
+        n += 1;
+        let started = Instant::now();
+        match connection.send_command(command) {
+            Ok(Response::Error(error)) => {
+                result.latencies.push(started.elapsed());
+                let code = error.split(':').next().unwrap_or_default();
+                result.error(code.to_string());
+            }
+            Ok(_) => result.latencies.push(started.elapsed()),
+            Err(e) => {
+                result.error(error_label(&e));
+                if !connection.is_connected() {
+                    client = None;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Groups protocol errors by kind, leaving out details such as peer addresses.
+fn error_label(error: &ProtocolError) -> String {
+    match error {
+        ProtocolError::InvalidCommand(_) => "invalid command".to_string(),
+        ProtocolError::InvalidResponse(_) => "invalid response".to_string(),
+        ProtocolError::ConnectionError(_) => "connection error".to_string(),
+        ProtocolError::ConnectionClosed => "connection closed".to_string(),
+        ProtocolError::ParseError(_) => "parse error".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Value below which `p` percent of `sorted` falls, by the nearest-rank method.
+pub fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub sockets: usize,
+    pub clients: usize,
+    pub elapsed: Duration,
+    /// Requests attempted, including those that failed to connect.
+    pub requests: u64,
+    /// Failures by error code or kind.
+    pub errors: BTreeMap<String, u64>,
+    /// Round trips of answered requests, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Failed share of all requests, from 0 to 1.
+    pub fn error_rate(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.error_count() as f64 / requests as f64,
+        }
+    }
+
+    /// Requests per second over the whole run.
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        percentile(&self.latencies, p)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = |p: f64| {
+            self.percentile(p).map_or("-".to_string(), |d| {
+                format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+            })
+        };
+        writeln!(
+            f,
+            "{} clients against {} socket(s) for {:.1}s",
+            self.clients,
+            self.sockets,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "Requests: {} ({:.1}/s)",
+            self.requests,
+            self.throughput()
+        )?;
+        writeln!(
+            f,
+            "Errors: {} ({:.2}%)",
+            self.error_count(),
+            self.error_rate() * 100.0
+        )?;
+        for (label, count) in &self.errors {
+            writeln!(f, "  {}: {}", label, count)?;
+        }
+        write!(
+            f,
+            "Latency: p50={} p95={} p99={} max={}",
+            millis(50.0),
+            millis(95.0),
+            millis(99.0),
+            millis(100.0)
+        )
+    }
+}
+
+/// Starts `config.sockets` servers on ephemeral loopback ports, runs the clients
+/// for `config.duration` and shuts the servers down again.
+pub fn run(config: &LoadConfig) -> Result<Report, Box<dyn Error>> {
+    if config.sockets == 0 || config.clients == 0 {
+        return Err("At least one socket and one client are required".into());
+    }
+    if !config.rate.is_finite() || config.rate < 0.0 {
+        return Err(format!("Invalid rate {}", config.rate).into());
+    }
+
+    let servers = (1..=config.sockets)
+        .map(|index| {
+            let server_config = ServerConfig::builder()
+                .address(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .socket_name(format!("Load Socket {}", index))
+                .build()?;
+            let context = ConnectionContext::from_config(&server_config)?;
+            Ok(run_server(&server_config.addresses, false, context)?)
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let client_configs = servers
+        .iter()
+        .map(|server| ClientConfig::builder().address(server.local_addr()).build())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let started = Instant::now();
+    let deadline = started + config.duration;
+    let results: Vec<ClientResult> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..config.clients)
+            .map(|index| {
+                let client_config = client_configs[index % client_configs.len()].clone();
+                scope.spawn(move || run_client(client_config, index as u64, config, deadline))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    for server in servers {
+        server
+            .shutdown()
+            .map_err(|_| "Server thread panicked during shutdown")?;
+    }
+
+    let mut report = Report {
+        sockets: config.sockets,
+        clients: config.clients,
+        elapsed,
+        requests: 0,
+        errors: BTreeMap::new(),
+        latencies: Vec::new(),
+    };
+    for result in results {
+        report.requests += result.requests;
+        report.latencies.extend(result.latencies);
+        for (label, count) in result.errors {
+            *report.errors.entry(label).or_default() += count;
+        }
+    }
+    report.latencies.sort_unstable();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        let mix: CommandMix = "STATUS=8, ON ,PULSE:50=2".parse().unwrap();
+        assert_eq!(mix.to_string(), "STATUS=8,ON=1,PULSE:50=2");
+        assert_eq!(CommandMix::default().to_string(), "STATUS=8,ON=1,OFF=1");
+
+        for invalid in ["", " , ", "FLY=1", "STATUS=0", "STATUS=x", "ON:=1"] {
+            assert!(invalid.parse::<CommandMix>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_mix_follows_weights() {
+        let mix: CommandMix = "STATUS=3,ON,OFF".parse().unwrap();
+        let picks: Vec<String> = (0..10).map(|n| mix.pick(n).to_string()).collect();
+        assert_eq!(
+            picks,
+            [
+                "STATUS", "STATUS", "STATUS", "ON", "OFF", "STATUS", "STATUS", "STATUS", "ON",
+                "OFF"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 99.0), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&sorted, 100.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&sorted, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(
+            percentile(&sorted[..1], 95.0),
+            Some(Duration::from_millis(1))
+        );
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_smoke_run() {
+        smart_socket_client::set_logging(false);
+        smart_socket_server::server::set_logging(false);
+        let report = run(&LoadConfig {
+            sockets: 2,
+            clients: 4,
+            rate: 50.0,
+            duration: Duration::from_millis(300),
+            mix: "STATUS=3,ON,OFF,PING".parse().unwrap(),
+        })
+        .unwrap();
+
+        assert!(report.requests >= 4, "{}", report);
+        assert_eq!(report.error_count(), 0, "{}", report);
+        assert!(report.percentile(99.0).is_some());
+        assert!(report.elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_error_responses_are_counted() {
+        smart_socket_client::set_logging(false);
+        smart_socket_server::server::set_logging(false);
+        let report = run(&LoadConfig {
+            clients: 2,
+            rate: 0.0,
+            duration: Duration::from_millis(100),
+            mix: "STATUS,SET_NAME:=1".parse().unwrap(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let invalid = report.errors["E_INVALID_ARGUMENT"];
+        assert!(invalid > 0, "{}", report);
+        assert_eq!(report.error_count(), invalid);
+        assert!((0.3..0.7).contains(&report.error_rate()), "{}", report);
+    }
+}
@@ -0,0 +1,61 @@
+use load_test::{run, LoadConfig};
+use std::error::Error;
+use std::time::Duration;
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, Box<dyn Error>>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value for {}: {}", flag, e).into())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut config = LoadConfig::default();
+    // Share of failed requests, from 0 to 1, above which the run fails.
+    let mut max_error_rate = 0.01;
+    let mut verbose = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sockets" => config.sockets = parse_value(&arg, args.next())?,
+            "--clients" => config.clients = parse_value(&arg, args.next())?,
+            "--rate" => config.rate = parse_value(&arg, args.next())?,
+            "--duration" => {
+                config.duration = Duration::try_from_secs_f64(parse_value(&arg, args.next())?)?
+            }
+            "--mix" => config.mix = parse_value(&arg, args.next())?,
+            "--max-error-rate" => max_error_rate = parse_value(&arg, args.next())?,
+            "--verbose" => verbose = true,
+            other => return Err(format!("Unknown argument: {}", other).into()),
+        }
+    }
+    // Hundreds of clients logging every command would drown the report and
+    // serialize the threads on stdout.
+    smart_socket_client::set_logging(verbose);
+    smart_socket_server::server::set_logging(verbose);
+
+    println!(
+        "Running {} clients against {} socket(s) at {}/s each for {:.1}s, mix {}",
+        config.clients,
+        config.sockets,
+        config.rate,
+        config.duration.as_secs_f64(),
+        config.mix
+    );
+    let report = run(&config)?;
+    println!("{}", report);
+
+    if report.error_rate() > max_error_rate {
+        eprintln!(
+            "Error rate {:.2}% exceeds the limit of {:.2}%",
+            report.error_rate() * 100.0,
+            max_error_rate * 100.0
+        );
+        std::process::exit(1);
+    }
+    Ok(())
+}
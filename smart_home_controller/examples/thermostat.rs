@@ -0,0 +1,82 @@
+//! A thermostat built only on the controller: it polls a thermometer and
+//! switches a heater socket with hysteresis.
+//!
+//! Usage: `thermostat <socket address> <thermometer query address>`; the
+//! setpoint and hysteresis (°C) come from `THERMOSTAT_SETPOINT` (default 20)
+//! and `THERMOSTAT_HYSTERESIS` (default 1).
+
+use smart_home_controller::{ControllerConfig, SmartHomeController};
+use smart_socket_client::ClientConfig;
+use smart_socket_protocol::shutdown::{Shutdown, EXIT_STARTUP_FAILED, GRACE_PERIOD};
+use std::error::Error;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn env_f64(name: &str, default: f64) -> Result<f64, Box<dyn Error>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(value
+            .parse()
+            .map_err(|_| format!("{} must be a number, got '{}'", name, value))?),
+        Err(_) => Ok(default),
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(socket), Some(thermometer)) = (args.next(), args.next()) else {
+        eprintln!("Usage: thermostat <socket address> <thermometer query address>");
+        std::process::exit(EXIT_STARTUP_FAILED);
+    };
+    let setpoint = env_f64("THERMOSTAT_SETPOINT", 20.0)?;
+    let hysteresis = env_f64("THERMOSTAT_HYSTERESIS", 1.0)?;
+
+    let controller = SmartHomeController::new(
+        ControllerConfig::default()
+            .socket("heater", ClientConfig::new(&socket)?)
+            .thermometer("room", ClientConfig::new(&thermometer)?),
+    );
+
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
+    ctrlc::set_handler(move || s.interrupt(GRACE_PERIOD))?;
+
+    let mut heater_on = None;
+    while !shutdown.is_requested() {
+        match controller.thermometer("room").reading() {
+            Ok(reading) => {
+                let wanted = if reading.celsius < setpoint {
+                    Some(true)
+                } else if reading.celsius > setpoint + hysteresis {
+                    Some(false)
+                } else {
+                    None
+                };
+                if let Some(on) = wanted.filter(|on| heater_on != Some(*on)) {
+                    let heater = controller.socket("heater");
+                    let switched = if on {
+                        heater.turn_on()
+                    } else {
+                        heater.turn_off()
+                    };
+                    match switched {
+                        Ok(()) => {
+                            println!(
+                                "{:.1}°C: heater {}",
+                                reading.celsius,
+                                if on { "on" } else { "off" }
+                            );
+                            heater_on = Some(on);
+                        }
+                        Err(e) => eprintln!("Cannot switch the heater: {}", e),
+                    }
+                }
+            }
+            Err(e) => eprintln!("Cannot read the thermometer: {}", e),
+        }
+        shutdown.wait_timeout(POLL_INTERVAL);
+    }
+
+    controller.shutdown();
+    Ok(())
+}
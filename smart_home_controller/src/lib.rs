@@ -0,0 +1,426 @@
+//! One façade over the devices of a home: socket servers, driven with the
+//! socket client, and thermometers, read through their query sockets. Devices
+//! are addressed by name, connected on first use and reconnected under a single
+//! [`ReconnectPolicy`].
+//!
+//! ```no_run
+//! use smart_home_controller::{ControllerConfig, SmartHomeController};
+//! use smart_socket_client::ClientConfig;
+//!
+//! let config = ControllerConfig::default()
+//!     .socket("kitchen", ClientConfig::new("10.0.0.5:8080")?)
+//!     .thermometer("kitchen", ClientConfig::new("10.0.0.6:9082")?);
+//! let controller = SmartHomeController::new(config);
+//! if controller.thermometer("kitchen").reading()?.celsius < 19.0 {
+//!     controller.socket("kitchen").turn_on()?;
+//! }
+//! controller.shutdown();
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use smart_socket_client::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Command, ProtocolError, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How a device whose connection is lost, or cannot be made, is tried again.
+/// Only a lost connection is retried; a device that answers with an error is not.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Connections tried per operation, the first one included.
+    pub attempts: u32,
+    /// Wait before the second attempt, doubled before every further one.
+    pub backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ControllerConfig {
+    /// Socket servers, by the name passed to [`SmartHomeController::socket`].
+    pub sockets: BTreeMap<String, ClientConfig>,
+    /// Query sockets of thermometer servers (`THERMOMETER_QUERY_ADDRESS`), by the
+    /// name passed to [`SmartHomeController::thermometer`]. Query sockets send
+    /// no banner, so `banner_timeout` is ignored.
+    pub thermometers: BTreeMap<String, ClientConfig>,
+    pub reconnect: ReconnectPolicy,
+}
+
+impl ControllerConfig {
+    pub fn socket(mut self, name: impl Into<String>, config: ClientConfig) -> Self {
+        self.sockets.insert(name.into(), config);
+        self
+    }
+
+    pub fn thermometer(mut self, name: impl Into<String>, config: ClientConfig) -> Self {
+        self.thermometers.insert(name.into(), config);
+        self
+    }
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum ControllerError {
+    /// No device of that kind is configured under the name.
+    UnknownDevice(String),
+    /// The device answered with an error, e.g. `ERROR:E_UNAUTHORIZED:...`.
+    Rejected(String),
+    Protocol(ProtocolError),
+    /// [`SmartHomeController::shutdown`] has been called.
+    ShutDown,
+}
+
+impl fmt::Display for ControllerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerError::UnknownDevice(name) => write!(f, "No device named '{}'", name),
+            ControllerError::Rejected(message) => write!(f, "Device refused: {}", message),
+            ControllerError::Protocol(e) => write!(f, "{}", e),
+            ControllerError::ShutDown => write!(f, "Controller has been shut down"),
+        }
+    }
+}
+
+impl Error for ControllerError {}
+
+impl From<ProtocolError> for ControllerError {
+    fn from(e: ProtocolError) -> Self {
+        ControllerError::Protocol(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketStatus {
+    pub is_on: bool,
+    pub power: u32,
+    /// Switched off by overload protection until reset.
+    pub tripped: bool,
+    /// A child lock refuses switching.
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermometerReading {
+    pub celsius: f64,
+    /// Time since the thermometer server received the reading.
+    pub age: Duration,
+}
+
+/// One configured device and its connection, made on first use.
+struct Device {
+    config: ClientConfig,
+    client: Mutex<Option<SmartSocketClient<ClientStream>>>,
+}
+
+impl Device {
+    fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            client: Mutex::new(None),
+        }
+    }
+
+    /// Sends `command`, connecting first if needed. The connection is held for
+    /// the whole exchange, so commands to one device never interleave.
+    fn send(
+        &self,
+        command: Command,
+        policy: &ReconnectPolicy,
+        closed: &AtomicBool,
+    ) -> Result<Response, ControllerError> {
+        let mut slot = lock(&self.client);
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+        loop {
+            if closed.load(Ordering::SeqCst) {
+                return Err(ControllerError::ShutDown);
+            }
+            let result = match slot.as_mut() {
+                Some(client) => client.send_command(command.clone()),
+                None => SmartSocketClient::with_config(self.config.clone())
+                    .and_then(|client| slot.insert(client).send_command(command.clone())),
+            };
+            match result {
+                Ok(Response::Error(message)) => return Err(ControllerError::Rejected(message)),
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    // An unexpected reply leaves the connection usable; only a
+                    // lost (or never made) connection is worth another attempt.
+                    if slot.as_ref().is_some_and(|client| client.is_connected()) {
+                        return Err(e.into());
+                    }
+                    *slot = None;
+                    if attempt >= policy.attempts {
+                        return Err(e.into());
+                    }
+                }
+            }
+            thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+            attempt += 1;
+        }
+    }
+
+    fn close(&self) {
+        if let Some(mut client) = lock(&self.client).take() {
+            let _ = client.close();
+        }
+    }
+}
+
+/// Drives every configured device. Share it between threads by reference or
+/// in an `Arc`; each device is used by one thread at a time.
+pub struct SmartHomeController {
+    sockets: HashMap<String, Device>,
+    thermometers: HashMap<String, Device>,
+    reconnect: ReconnectPolicy,
+    closed: AtomicBool,
+}
+
+impl SmartHomeController {
+    /// Nothing is connected until a device is first used.
+    pub fn new(config: ControllerConfig) -> Self {
+        let thermometers = config
+            .thermometers
+            .into_iter()
+            .map(|(name, mut client)| {
+                client.banner_timeout = None;
+                (name, Device::new(client))
+            })
+            .collect();
+        Self {
+            sockets: config
+                .sockets
+                .into_iter()
+                .map(|(name, client)| (name, Device::new(client)))
+                .collect(),
+            thermometers,
+            reconnect: config.reconnect,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// The socket named `name`; an unknown name is reported by the first command.
+    pub fn socket<'a>(&'a self, name: &'a str) -> SocketHandle<'a> {
+        SocketHandle {
+            controller: self,
+            name,
+        }
+    }
+
+    /// The thermometer named `name`; an unknown name is reported by the first read.
+    pub fn thermometer<'a>(&'a self, name: &'a str) -> ThermometerHandle<'a> {
+        ThermometerHandle {
+            controller: self,
+            name,
+        }
+    }
+
+    /// Closes every open connection. Later commands fail with
+    /// [`ControllerError::ShutDown`]; one in progress is finished first.
+    pub fn shutdown(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for device in self.sockets.values().chain(self.thermometers.values()) {
+            device.close();
+        }
+    }
+
+    fn send(
+        &self,
+        devices: &HashMap<String, Device>,
+        name: &str,
+        command: Command,
+    ) -> Result<Response, ControllerError> {
+        devices
+            .get(name)
+            .ok_or_else(|| ControllerError::UnknownDevice(name.to_string()))?
+            .send(command, &self.reconnect, &self.closed)
+    }
+}
+
+impl Drop for SmartHomeController {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn unexpected(response: Response) -> ControllerError {
+    ControllerError::Protocol(ProtocolError::InvalidResponse(response.to_string()))
+}
+
+pub struct SocketHandle<'a> {
+    controller: &'a SmartHomeController,
+    name: &'a str,
+}
+
+impl SocketHandle<'_> {
+    pub fn turn_on(&self) -> Result<(), ControllerError> {
+        self.send(Command::TurnOn).map(|_| ())
+    }
+
+    pub fn turn_off(&self) -> Result<(), ControllerError> {
+        self.send(Command::TurnOff).map(|_| ())
+    }
+
+    pub fn status(&self) -> Result<SocketStatus, ControllerError> {
+        match self.send(Command::GetStatus)? {
+            Response::Status {
+                is_on,
+                power,
+                tripped,
+                locked,
+            } => Ok(SocketStatus {
+                is_on,
+                power,
+                tripped,
+                locked,
+            }),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn send(&self, command: Command) -> Result<Response, ControllerError> {
+        self.controller
+            .send(&self.controller.sockets, self.name, command)
+    }
+}
+
+pub struct ThermometerHandle<'a> {
+    controller: &'a SmartHomeController,
+    name: &'a str,
+}
+
+impl ThermometerHandle<'_> {
+    /// The latest temperature the thermometer server has received.
+    pub fn reading(&self) -> Result<ThermometerReading, ControllerError> {
+        let response =
+            self.controller
+                .send(&self.controller.thermometers, self.name, Command::GetStatus)?;
+        match response {
+            Response::Temperature { celsius, age_secs } => Ok(ThermometerReading {
+                celsius,
+                age: Duration::from_secs(age_secs),
+            }),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, serialize_message};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::Arc;
+
+    /// A server that answers `STATUS` on each connection `replies_per_connection`
+    /// times and then drops it; returns its address and the number of connections.
+    fn flaky_server(replies_per_connection: usize) -> (SocketAddr, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(0));
+        let count = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                *lock(&count) += 1;
+                for _ in 0..replies_per_connection {
+                    if read_message(&mut stream).is_err() {
+                        break;
+                    }
+                    let reply = serialize_message("STATUS:ON:1200").unwrap();
+                    if stream.write_all(&reply).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        (addr, connections)
+    }
+
+    fn controller(addr: SocketAddr) -> SmartHomeController {
+        let client = ClientConfig::builder()
+            .address(addr)
+            .banner_timeout(None)
+            .build()
+            .unwrap();
+        SmartHomeController::new(
+            ControllerConfig::default()
+                .socket("kitchen", client)
+                .reconnect(ReconnectPolicy {
+                    attempts: 2,
+                    backoff: Duration::from_millis(10),
+                }),
+        )
+    }
+
+    #[test]
+    fn test_connection_is_made_lazily_and_reused() {
+        let (addr, connections) = flaky_server(usize::MAX);
+        let controller = controller(addr);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(*lock(&connections), 0);
+
+        for _ in 0..3 {
+            assert!(controller.socket("kitchen").status().unwrap().is_on);
+        }
+        assert_eq!(*lock(&connections), 1);
+    }
+
+    #[test]
+    fn test_lost_connection_is_made_again() {
+        let (addr, connections) = flaky_server(1);
+        let controller = controller(addr);
+        let expected = SocketStatus {
+            is_on: true,
+            power: 1200,
+            tripped: false,
+            locked: false,
+        };
+        assert_eq!(controller.socket("kitchen").status().unwrap(), expected);
+        assert_eq!(controller.socket("kitchen").status().unwrap(), expected);
+        assert_eq!(*lock(&connections), 2);
+    }
+
+    #[test]
+    fn test_unknown_devices_and_shutdown() {
+        let (addr, _) = flaky_server(usize::MAX);
+        let controller = controller(addr);
+        assert!(matches!(
+            controller.socket("hall").turn_on(),
+            Err(ControllerError::UnknownDevice(name)) if name == "hall"
+        ));
+        assert!(matches!(
+            controller.thermometer("kitchen").reading(),
+            Err(ControllerError::UnknownDevice(_))
+        ));
+
+        controller.socket("kitchen").status().unwrap();
+        controller.shutdown();
+        assert!(matches!(
+            controller.socket("kitchen").status(),
+            Err(ControllerError::ShutDown)
+        ));
+    }
+}
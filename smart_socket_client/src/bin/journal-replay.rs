@@ -0,0 +1,87 @@
+//! Re-issues the state-changing commands of a server journal against a target server.
+//!
+//! Usage: `journal-replay <journal> [address]`. `SMART_SOCKET_TOKEN` is used to authenticate,
+//! since journals never contain AUTH tokens.
+
+use smart_socket_client::{ClientConfig, SmartSocketClient};
+use smart_socket_protocol::journal;
+use smart_socket_protocol::Response;
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("Usage: journal-replay <journal> [address]");
+        return ExitCode::FAILURE;
+    };
+    let address = match args.next().map(|address| address.parse()) {
+        Some(Ok(address)) => address,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        None => ClientConfig::default().address,
+    };
+
+    let entries = match File::open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| journal::read_entries(BufReader::new(file)).map_err(|e| e.to_string()))
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read journal {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let commands = journal::replayable(&entries);
+    println!(
+        "Replaying {} of {} journal entries against {}",
+        commands.len(),
+        entries.len(),
+        address
+    );
+
+    let mut config = ClientConfig::builder().address(address);
+    if let Ok(token) = std::env::var("SMART_SOCKET_TOKEN") {
+        config = config.auth_token(token);
+    }
+    let config = match config.build() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut client = match SmartSocketClient::with_config(config) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut failures = 0;
+    for command in commands {
+        match client.send_command(command.clone()) {
+            Ok(Response::Error(err)) => {
+                failures += 1;
+                eprintln!("{} -> ERROR:{}", command, err);
+            }
+            Ok(response) => println!("{} -> {}", command, response),
+            Err(e) => {
+                eprintln!("{} -> {}", command, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let _ = client.close();
+
+    if failures > 0 {
+        eprintln!("{} command(s) were rejected", failures);
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
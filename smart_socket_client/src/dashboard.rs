@@ -0,0 +1,204 @@
+//! The `watch` console command: a status dashboard redrawn after every poll.
+//!
+//! Everything shown comes from [`WatchState`], which the polls update, and
+//! [`render`] is a pure function of it, so the dashboard is tested without a
+//! terminal. The last change is the first poll that saw a different status,
+//! so it is only as precise as the interval.
+
+use crate::status_rows;
+use crate::style::{self, Color, Style};
+use smart_socket_client::messages::{self, Locale};
+use smart_socket_client::watch::SocketStatus;
+use smart_socket_protocol::ProtocolError;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest interval `watch` accepts, to keep it from flooding the server.
+pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Parses a `watch` interval: seconds, optionally with an `s` suffix, or
+/// milliseconds with an `ms` suffix, e.g. `2`, `0.5s` or `500ms`.
+pub fn parse_interval(text: &str) -> Option<Duration> {
+    let text = text.trim().to_ascii_lowercase();
+    let interval = match text.strip_suffix("ms") {
+        Some(millis) => Duration::from_millis(millis.parse().ok()?),
+        None => Duration::try_from_secs_f64(text.strip_suffix('s').unwrap_or(&text).parse().ok()?)
+            .ok()?,
+    };
+    (interval >= MIN_INTERVAL).then_some(interval)
+}
+
+/// What the dashboard knows after the polls so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchState {
+    pub interval: Duration,
+    pub device_name: Option<String>,
+    /// The last status received; `None` until a poll succeeds.
+    pub status: Option<SocketStatus>,
+    /// When a poll first saw the status differ from the one before it.
+    pub last_change: Option<SystemTime>,
+    /// Failed polls since the watch started.
+    pub errors: u64,
+    /// Why the latest poll failed; cleared by the next successful one.
+    pub last_error: Option<String>,
+}
+
+impl WatchState {
+    pub fn new(interval: Duration, device_name: Option<String>) -> Self {
+        Self {
+            interval,
+            device_name,
+            status: None,
+            last_change: None,
+            errors: 0,
+            last_error: None,
+        }
+    }
+
+    /// Records the outcome of a poll answered at `now`.
+    pub fn observe(&mut self, poll: Result<SocketStatus, ProtocolError>, now: SystemTime) {
+        match poll {
+            Ok(status) => {
+                if self.status.is_some_and(|previous| previous != status) {
+                    self.last_change = Some(now);
+                }
+                self.status = Some(status);
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.errors += 1;
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// The dashboard for `state` as of `now`, without any screen control codes.
+pub fn render(state: &WatchState, now: SystemTime, locale: Locale, style: Style) -> String {
+    let title = messages::format(
+        "watch.title",
+        locale,
+        &[("interval", &format!("{:?}", state.interval))],
+    );
+    let mut rows = match &state.status {
+        Some(status) => status_rows(status, state.device_name.as_deref(), locale, style),
+        None => vec![(
+            messages::get("label.state", locale),
+            messages::get("watch.waiting", locale).to_string(),
+        )],
+    };
+    let last_change = match state.last_change {
+        Some(at) => messages::format(
+            "watch.ago",
+            locale,
+            &[(
+                "secs",
+                &now.duration_since(at).unwrap_or_default().as_secs(),
+            )],
+        ),
+        None => messages::get("watch.no_change", locale).to_string(),
+    };
+    rows.push((messages::get("label.last_change", locale), last_change));
+    let errors = state.errors.to_string();
+    rows.push((
+        messages::get("label.errors", locale),
+        if state.errors > 0 {
+            style.paint(Color::Red, &errors)
+        } else {
+            errors
+        },
+    ));
+    if let Some(error) = &state.last_error {
+        rows.push((
+            messages::get("label.last_error", locale),
+            style.paint(Color::Red, error),
+        ));
+    }
+    format!(
+        "{}\n\n{}",
+        style.paint(Color::Bold, &title),
+        style::columns(&rows)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON: SocketStatus = SocketStatus {
+        is_on: true,
+        power: 1500,
+        tripped: false,
+        locked: false,
+    };
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_interval("0.5S"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_interval("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_interval("50ms"), None);
+        assert_eq!(parse_interval("0"), None);
+        assert_eq!(parse_interval("-1"), None);
+        assert_eq!(parse_interval("soon"), None);
+    }
+
+    #[test]
+    fn test_last_change_comes_from_observed_transitions() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut state = WatchState::new(DEFAULT_INTERVAL, None);
+        state.observe(Ok(ON), start);
+        assert_eq!(state.last_change, None);
+        state.observe(Ok(ON), start + Duration::from_secs(1));
+        assert_eq!(state.last_change, None);
+
+        let off = SocketStatus {
+            is_on: false,
+            power: 0,
+            tripped: false,
+            locked: false,
+        };
+        state.observe(
+            Err(ProtocolError::ConnectionClosed),
+            start + Duration::from_secs(2),
+        );
+        assert_eq!((state.errors, state.status), (1, Some(ON)));
+        state.observe(Ok(off), start + Duration::from_secs(3));
+        assert_eq!(state.last_change, Some(start + Duration::from_secs(3)));
+        assert_eq!(state.last_error, None);
+    }
+
+    #[test]
+    fn test_render() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let plain = Style::new(false);
+        let mut state = WatchState::new(Duration::from_millis(500), Some("Hall".to_string()));
+        let waiting = render(&state, start, Locale::En, plain);
+        assert!(waiting.starts_with("Watching every 500ms; press Enter to stop\n\n"));
+        assert!(waiting.contains("waiting for the first reply"));
+        assert!(waiting.contains("none seen"));
+
+        state.observe(Ok(ON), start);
+        state.observe(
+            Ok(SocketStatus { power: 40, ..ON }),
+            start + Duration::from_secs(2),
+        );
+        state.observe(
+            Err(ProtocolError::ConnectionClosed),
+            start + Duration::from_secs(3),
+        );
+        let text = render(&state, start + Duration::from_secs(14), Locale::En, plain);
+        for line in [
+            "State:       ON",
+            "Power:       40W",
+            "Name:        Hall",
+            "Last change: 12s ago",
+            "Errors:      1",
+            "Last error:  Connection closed",
+        ] {
+            assert!(text.contains(line), "{:?} not in {}", line, text);
+        }
+        assert!(render(&state, start, Locale::Ru, plain).contains("Ошибки"));
+    }
+}
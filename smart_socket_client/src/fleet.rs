@@ -0,0 +1,252 @@
+//! Sends one command to many sockets at once, so switching off a room full of
+//! them takes about as long as switching off the slowest one.
+
+use crate::{ClientConfig, SmartSocketClient};
+use smart_socket_protocol::{Address, Command, ConfigError, IntoAddress, ProtocolError, Response};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct FleetConfig {
+    /// Settings for every connection; the address is replaced by each device's own.
+    pub client: ClientConfig,
+    /// Most devices talked to at once.
+    pub parallelism: usize,
+    /// How long one device may take, connecting included, before it is given up on.
+    pub timeout: Duration,
+}
+
+impl Default for FleetConfig {
+    fn default() -> Self {
+        Self {
+            client: ClientConfig::default(),
+            parallelism: 8,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Sockets addressed together. Every [`broadcast`](Self::broadcast) opens a
+/// fresh connection to each, so a device that restarted in between is no
+/// different from one that did not.
+pub struct SmartSocketFleet {
+    config: FleetConfig,
+    addresses: Vec<Address>,
+}
+
+impl SmartSocketFleet {
+    /// Checks the addresses and the config right away, so a typo is reported
+    /// before anything connects.
+    pub fn new<A: IntoAddress>(
+        config: FleetConfig,
+        addresses: impl IntoIterator<Item = A>,
+    ) -> Result<Self, ConfigError> {
+        if config.parallelism == 0 {
+            return Err(ConfigError::InvalidValue {
+                field: "parallelism",
+                reason: "at least one device must be talked to at a time".to_string(),
+            });
+        }
+        if config.timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("timeout"));
+        }
+        let addresses = addresses
+            .into_iter()
+            .map(IntoAddress::into_address)
+            .collect::<Result<Vec<_>, _>>()?;
+        if addresses.is_empty() {
+            return Err(ConfigError::NoAddresses);
+        }
+        Ok(Self { config, addresses })
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    /// Sends `command` to every device, up to `parallelism` at a time, and
+    /// returns each device's address and outcome in the order they were given.
+    /// A device that does not answer within the timeout fails on its own; the
+    /// others are not held up beyond that.
+    pub fn broadcast(&self, command: Command) -> Vec<(String, Result<Response, ProtocolError>)> {
+        let next = AtomicUsize::new(0);
+        let (results, received) = mpsc::channel();
+        let workers = self.config.parallelism.min(self.addresses.len());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let results = results.clone();
+                let (next, command) = (&next, &command);
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(address) = self.addresses.get(index) else {
+                        break;
+                    };
+                    let _ = results.send((index, self.execute(address, command.clone())));
+                });
+            }
+        });
+        drop(results);
+
+        let mut outcomes: Vec<_> = received.into_iter().collect();
+        outcomes.sort_by_key(|(index, _)| *index);
+        outcomes
+            .into_iter()
+            .map(|(index, outcome)| (self.addresses[index].to_string(), outcome))
+            .collect()
+    }
+
+    /// Connects to one device and sends `command`, all within the timeout.
+    fn execute(&self, address: &Address, command: Command) -> Result<Response, ProtocolError> {
+        let started = Instant::now();
+        let timeout = self.config.timeout;
+        let config = ClientConfig {
+            address: address.clone(),
+            connect_timeout: timeout,
+            write_timeout: timeout,
+            banner_timeout: self
+                .config
+                .client
+                .banner_timeout
+                .map(|wait| wait.min(timeout)),
+            keepalive_interval: None,
+            ..self.config.client.clone()
+        };
+        let mut client = SmartSocketClient::with_config(config)?;
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(ProtocolError::ConnectionError(format!(
+                "No response within {} ms",
+                timeout.as_millis()
+            )));
+        }
+        client.send_command_with_timeout(command, remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, serialize_message};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener};
+
+    /// Answers every command with `OK:<port>`, so replies show which device sent them.
+    fn spawn_device() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                thread::spawn(move || {
+                    let reply = format!("OK:{}", addr.port());
+                    while read_message(&mut stream).is_ok() {
+                        if stream
+                            .write_all(&serialize_message(&reply).unwrap())
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// Accepts connections and reads commands but never answers them.
+    fn spawn_silent_device() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                thread::spawn(move || while read_message(&mut stream).is_ok() {});
+            }
+        });
+        addr
+    }
+
+    fn fleet(addresses: &[SocketAddr], parallelism: usize, timeout: Duration) -> SmartSocketFleet {
+        let config = FleetConfig {
+            client: ClientConfig {
+                banner_timeout: None,
+                ..Default::default()
+            },
+            parallelism,
+            timeout,
+        };
+        SmartSocketFleet::new(config, addresses.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn test_broadcast_keeps_input_order() {
+        let addresses: Vec<_> = (0..6).map(|_| spawn_device()).collect();
+        let outcomes = fleet(&addresses, 2, Duration::from_secs(2)).broadcast(Command::TurnOff);
+
+        assert_eq!(outcomes.len(), addresses.len());
+        for ((label, outcome), addr) in outcomes.iter().zip(&addresses) {
+            assert_eq!(label, &addr.to_string());
+            assert_eq!(
+                outcome.as_ref().unwrap(),
+                &Response::Ok(Some(addr.port().to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_silent_device_costs_one_timeout() {
+        let timeout = Duration::from_millis(300);
+        let mut addresses: Vec<_> = (0..4).map(|_| spawn_device()).collect();
+        addresses.insert(1, spawn_silent_device());
+        addresses.push(spawn_silent_device());
+
+        let started = Instant::now();
+        let outcomes = fleet(&addresses, addresses.len(), timeout).broadcast(Command::TurnOff);
+        let elapsed = started.elapsed();
+
+        // Two silent devices one after the other would take twice as long.
+        assert!(elapsed >= timeout, "{:?}", elapsed);
+        assert!(elapsed < timeout * 2, "{:?}", elapsed);
+        let failed: Vec<_> = outcomes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, outcome))| outcome.is_err().then_some(i))
+            .collect();
+        assert_eq!(failed, [1, 5]);
+    }
+
+    #[test]
+    fn test_unreachable_device_fails_alone() {
+        // Bound and dropped at once, so nothing listens on it.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let outcomes =
+            fleet(&[spawn_device(), closed], 4, Duration::from_secs(1)).broadcast(Command::TurnOn);
+        assert!(outcomes[0].1.is_ok());
+        assert!(matches!(
+            outcomes[1].1,
+            Err(ProtocolError::ConnectionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_new_checks_config() {
+        let config = || FleetConfig::default();
+        assert!(matches!(
+            SmartSocketFleet::new(config(), Vec::<&str>::new()),
+            Err(ConfigError::NoAddresses)
+        ));
+        assert!(SmartSocketFleet::new(config(), ["127.0.0.1"]).is_err());
+        let serial = FleetConfig {
+            parallelism: 0,
+            ..config()
+        };
+        assert!(SmartSocketFleet::new(serial, ["127.0.0.1:8080"]).is_err());
+        let fleet = SmartSocketFleet::new(config(), ["127.0.0.1:8080", "lamp.lan:8080"]).unwrap();
+        assert_eq!(fleet.addresses().len(), 2);
+    }
+}
@@ -0,0 +1,2068 @@
+pub mod fleet;
+pub mod messages;
+pub mod metrics;
+pub mod offline;
+pub mod pool;
+pub mod reverse;
+pub mod socks5;
+pub mod watch;
+
+use messages::Locale;
+use metrics::ClientMetrics;
+use smart_socket_protocol::binfmt::{self, WireFormat};
+use smart_socket_protocol::cipher::{self, CipherStream, Psk};
+use smart_socket_protocol::logging::Logger;
+use smart_socket_protocol::{
+    read_response_text, serialize_message, Address, Command, ConfigError, DeviceInfo, IntoAddress,
+    ProtocolError, Response, ServerStats,
+};
+use socks5::Socks5Proxy;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use watch::StatusWatcher;
+
+/// Disabled by [`set_logging`]; tools running many clients may not want their chatter.
+static LOGGER: Logger = Logger::new();
+
+/// Turns the client's log output on or off for the whole process.
+pub fn set_logging(enabled: bool) {
+    LOGGER.set_enabled(enabled);
+}
+
+fn log(message: &str) {
+    LOGGER.log(message);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Default [`ClientConfig::connect_timeout`].
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default [`ClientConfig::banner_timeout`]. Servers send the banner as soon as they
+/// accept, so it normally arrives within one round trip.
+pub const BANNER_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Resolves `address` and connects to the first reachable result, trying IPv6
+/// addresses before IPv4 ones. All attempts together take at most `timeout`;
+/// resolving the name comes on top. Returns the stream and the address it is
+/// connected to.
+fn connect<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+) -> Result<(TcpStream, SocketAddr), ProtocolError> {
+    let mut addrs: Vec<SocketAddr> = address
+        .to_socket_addrs()
+        .map_err(|e| ProtocolError::ConnectionError(format!("Failed to resolve: {}", e)))?
+        .collect();
+    // Stable, so the resolver's order is kept within each family.
+    addrs.sort_by_key(|addr| addr.is_ipv4());
+    connect_within(&addrs, timeout, TcpStream::connect_timeout)
+}
+
+/// Tries `addrs` in order with `attempt`, each getting whatever is left of
+/// `timeout`. The error names the last address tried and every earlier failure.
+fn connect_within<S>(
+    addrs: &[SocketAddr],
+    timeout: Duration,
+    mut attempt: impl FnMut(&SocketAddr, Duration) -> io::Result<S>,
+) -> Result<(S, SocketAddr), ProtocolError> {
+    let deadline = Instant::now() + timeout;
+    let mut failures: Vec<(SocketAddr, io::Error)> = Vec::new();
+    for addr in addrs {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        match attempt(addr, remaining) {
+            Ok(stream) => return Ok((stream, *addr)),
+            Err(e) => failures.push((*addr, e)),
+        }
+    }
+
+    let Some((addr, error)) = failures.pop() else {
+        return Err(ProtocolError::ConnectionError(
+            "Failed to connect: no addresses resolved".to_string(),
+        ));
+    };
+    let mut message = if error.kind() == io::ErrorKind::TimedOut {
+        format!("Connection timed out after {:?} to {}", timeout, addr)
+    } else {
+        format!("Failed to connect to {}: {}", addr, error)
+    };
+    if !failures.is_empty() {
+        let tried: Vec<String> = failures
+            .iter()
+            .map(|(addr, e)| format!("{}: {}", addr, e))
+            .collect();
+        message.push_str(&format!(" (also tried {})", tried.join(", ")));
+    }
+    let untried = addrs.len() - failures.len() - 1;
+    if untried > 0 {
+        message.push_str(&format!("; {} more addresses not tried", untried));
+    }
+    Err(ProtocolError::ConnectionError(message))
+}
+
+/// Applies the configured read and write timeouts to a freshly opened stream.
+fn set_timeouts(stream: &TcpStream, config: &ClientConfig) -> Result<(), ProtocolError> {
+    Stream::set_read_timeout(stream, config.read_timeout)?;
+    stream
+        .set_write_timeout(Some(config.write_timeout))
+        .map_err(|e| ProtocolError::ConnectionError(format!("Failed to set write timeout: {}", e)))
+}
+
+fn read_timeout_error(e: io::Error) -> ProtocolError {
+    ProtocolError::ConnectionError(format!("Failed to set read timeout: {}", e))
+}
+
+pub trait Stream: Read + Write {
+    fn shutdown(&self, _: Shutdown) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// How long a read may block; streams that never block can ignore it.
+    fn set_read_timeout(&self, _timeout: Duration) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+}
+
+impl Stream for TcpStream {
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Duration) -> Result<(), ProtocolError> {
+        TcpStream::set_read_timeout(self, Some(timeout)).map_err(read_timeout_error)
+    }
+}
+
+/// Transport used by connected clients: plain TCP, TCP with frames encrypted
+/// by a pre-shared key, or TCP wrapped in TLS.
+pub enum ClientStream {
+    Plain(TcpStream),
+    Psk(Box<CipherStream<TcpStream, Psk>>),
+    #[cfg(feature = "tls")]
+    Tls(Box<smart_socket_protocol::tls::TlsClientStream>),
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.read(buf),
+            ClientStream::Psk(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.write(buf),
+            ClientStream::Psk(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Plain(stream) => stream.flush(),
+            ClientStream::Psk(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Stream for ClientStream {
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match self {
+            ClientStream::Plain(stream) => stream.shutdown(how),
+            ClientStream::Psk(stream) => stream.get_ref().shutdown(how),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.sock.shutdown(how),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Duration) -> Result<(), ProtocolError> {
+        match self {
+            ClientStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+            ClientStream::Psk(stream) => stream.get_ref().set_read_timeout(Some(timeout)),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.sock.set_read_timeout(Some(timeout)),
+        }
+        .map_err(read_timeout_error)
+    }
+}
+
+impl ClientStream {
+    /// Plain TCP, with frames encrypted if a pre-shared key is configured.
+    fn tcp(stream: TcpStream, psk: Option<&Psk>) -> Self {
+        match psk {
+            Some(psk) => ClientStream::Psk(Box::new(CipherStream::new(stream, psk.clone()))),
+            None => ClientStream::Plain(stream),
+        }
+    }
+
+    /// Reads the `INFO` frame a server may send right after accepting. A server
+    /// without a banner is recognised by `wait` passing without a single byte; one
+    /// that closes right away is left for the first command to notice.
+    fn read_banner(
+        &mut self,
+        wait: Duration,
+        read_timeout: Duration,
+        on_received: Option<&FrameHook>,
+    ) -> Result<Option<DeviceInfo>, ProtocolError> {
+        self.set_read_timeout(wait)?;
+        let mut first = [0u8; 1];
+        let read = loop {
+            match self.read(&mut first) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                other => break other,
+            }
+        };
+        self.set_read_timeout(read_timeout)?;
+        match read {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let text = read_response_text(&mut (&first[..]).chain(&mut *self))?;
+                if let Some(hook) = on_received {
+                    hook.call(&text);
+                }
+                match text.parse()? {
+                    Response::Info(info) => Ok(Some(info)),
+                    other => Err(ProtocolError::InvalidResponse(format!(
+                        "unexpected banner: {}",
+                        other
+                    ))),
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) if cipher::is_auth_failure(&e) => Err(ProtocolError::AuthFailed),
+            Err(e) => Err(ProtocolError::ConnectionError(format!(
+                "Failed to read banner: {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// Observer of frame payloads, for logging and tracing; see
+/// [`ClientConfig::on_frame_sent`]. It only sees the text, so it cannot change
+/// what is sent or received.
+#[derive(Clone)]
+pub struct FrameHook(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl FrameHook {
+    pub fn new(hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    fn call(&self, payload: &str) {
+        (self.0)(payload)
+    }
+}
+
+impl fmt::Debug for FrameHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FrameHook")
+    }
+}
+
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub struct TlsClientConfig {
+    pub ca_cert: PathBuf,
+    pub server_name: String,
+}
+
+/// Built with [`ClientConfig::builder`], which checks the values; the fields stay
+/// public for reading and for adjusting a config that has been built.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ClientConfig {
+    pub read_timeout: Duration,
+    /// Read timeouts for commands that legitimately take longer, keyed by
+    /// [`Command::kind`], e.g. `"PULSE"`; other commands use `read_timeout`.
+    pub timeouts: HashMap<&'static str, Duration>,
+    pub write_timeout: Duration,
+    /// How long connecting may take in all, across every address the host
+    /// name resolves to.
+    pub connect_timeout: Duration,
+    pub address: Address,
+    pub auth_token: Option<String>,
+    /// Reject responses whose kind does not match the command sent. Disable to talk to
+    /// servers that reply with response kinds this client does not know about yet.
+    pub strict: bool,
+    /// Language of messages shown to people; responses themselves are unaffected.
+    pub locale: Locale,
+    /// When set, a background thread sends PING after this long without traffic and
+    /// marks the client disconnected if the server does not answer.
+    pub keepalive_interval: Option<Duration>,
+    /// How long to wait for the server's `INFO` banner after connecting. `None` skips
+    /// the wait, for servers known not to send one.
+    pub banner_timeout: Option<Duration>,
+    /// When set, the connection is made through this SOCKS5 proxy; TLS, if
+    /// configured, runs end to end through the tunnel.
+    pub socks5_proxy: Option<Socks5Proxy>,
+    /// How the server encodes responses; [`WireFormat::Binary`] is negotiated right
+    /// after the banner and fails the connection if the server does not support it.
+    pub wire_format: WireFormat,
+    /// Pre-shared key that every frame is encrypted with; the server must have
+    /// the same one.
+    pub psk: Option<Psk>,
+    /// Called with the payload of every frame once it was written, keepalive
+    /// pings included, even if no response follows.
+    pub on_frame_sent: Option<FrameHook>,
+    /// Called with the text of every response as it was read, the banner
+    /// included, before it is parsed. Binary responses are rendered as text.
+    pub on_frame_received: Option<FrameHook>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsClientConfig>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            read_timeout: Duration::from_secs(5),
+            timeouts: HashMap::new(),
+            write_timeout: Duration::from_secs(5),
+            connect_timeout: CONNECT_TIMEOUT,
+            address: Address::Ip(SocketAddr::from(([127, 0, 0, 1], 8080))),
+            auth_token: None,
+            strict: true,
+            locale: Locale::default(),
+            keepalive_interval: None,
+            banner_timeout: Some(BANNER_TIMEOUT),
+            socks5_proxy: None,
+            wire_format: WireFormat::default(),
+            psk: None,
+            on_frame_sent: None,
+            on_frame_received: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// The default config for the server at `address`, which is checked right away
+    /// so a typo is reported before anything connects.
+    pub fn new(address: &str) -> Result<Self, ConfigError> {
+        Self::builder().address(address).build()
+    }
+
+    /// Starts from the defaults.
+    pub fn builder() -> ClientConfigBuilder {
+        ClientConfigBuilder {
+            config: Self::default(),
+            error: None,
+        }
+    }
+}
+
+/// Sets up a [`ClientConfig`]; nothing is checked until [`build`](Self::build).
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct ClientConfigBuilder {
+    config: ClientConfig,
+    /// The first address that failed to parse, reported by `build`.
+    error: Option<ConfigError>,
+}
+
+impl ClientConfigBuilder {
+    pub fn address(mut self, address: impl IntoAddress) -> Self {
+        match address.into_address() {
+            Ok(address) => self.config.address = address,
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// The read timeout for commands of `kind`, e.g. `"PULSE"`.
+    pub fn timeout(mut self, kind: &'static str, timeout: Duration) -> Self {
+        self.config.timeouts.insert(kind, timeout);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.config.auth_token = Some(token.into());
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.config.locale = locale;
+        self
+    }
+
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.config.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// `None` skips waiting for a banner.
+    pub fn banner_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.banner_timeout = timeout;
+        self
+    }
+
+    pub fn socks5_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.config.socks5_proxy = Some(proxy);
+        self
+    }
+
+    pub fn wire_format(mut self, format: WireFormat) -> Self {
+        self.config.wire_format = format;
+        self
+    }
+
+    pub fn on_frame_sent(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.config.on_frame_sent = Some(FrameHook::new(hook));
+        self
+    }
+
+    pub fn on_frame_received(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.config.on_frame_received = Some(FrameHook::new(hook));
+        self
+    }
+
+    /// Encrypts frames with the 32-byte key given as 64 hex digits.
+    pub fn psk(mut self, hex: &str) -> Self {
+        match Psk::from_hex(hex) {
+            Ok(psk) => self.config.psk = Some(psk),
+            Err(reason) => {
+                self.error.get_or_insert(ConfigError::InvalidValue {
+                    field: "psk",
+                    reason,
+                });
+            }
+        }
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsClientConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Checks the config: the address must have parsed and no timeout may be
+    /// zero, which the socket would reject on the first connect.
+    pub fn build(self) -> Result<ClientConfig, ConfigError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let config = self.config;
+        if config.read_timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("read_timeout"));
+        }
+        if config.write_timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("write_timeout"));
+        }
+        if config.connect_timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("connect_timeout"));
+        }
+        if let Some(kind) = config
+            .timeouts
+            .iter()
+            .find_map(|(kind, timeout)| timeout.is_zero().then_some(kind))
+        {
+            return Err(ConfigError::InvalidValue {
+                field: "timeouts",
+                reason: format!("the {} timeout must be greater than zero", kind),
+            });
+        }
+        if config
+            .keepalive_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(ConfigError::ZeroDuration("keepalive_interval"));
+        }
+        if config
+            .banner_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return Err(ConfigError::ZeroDuration("banner_timeout"));
+        }
+        #[cfg(feature = "tls")]
+        if config.tls.is_some() && config.psk.is_some() {
+            return Err(ConfigError::InvalidValue {
+                field: "psk",
+                reason: "a pre-shared key cannot be combined with TLS".to_string(),
+            });
+        }
+        Ok(config)
+    }
+}
+
+/// The stream and what is known about it, shared with the keepalive thread.
+/// Whoever holds the lock owns the stream for a whole request/response exchange,
+/// so frames from user commands and pings never interleave.
+struct Connection<T> {
+    stream: T,
+    connected: bool,
+    /// Responses are [`binfmt`] frames, once negotiated.
+    binary: bool,
+    /// What the server reported spending on the last response read, if anything.
+    server_time: Option<Duration>,
+    /// When a response was last received; pings are only sent once this is old enough.
+    last_used: Instant,
+    on_sent: Option<FrameHook>,
+    on_received: Option<FrameHook>,
+}
+
+impl<T: Read + Write> Connection<T> {
+    /// Frames `payloads` and writes them at once; the sent hook sees each of
+    /// them once the write went through.
+    fn write(&mut self, payloads: &[&str], what: &str) -> Result<(), ProtocolError> {
+        let data = payloads
+            .iter()
+            .map(|payload| serialize_message(payload))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        self.stream.write_all(&data).map_err(|e| {
+            log(&format!("Failed to send {}: {}", what, e));
+            self.connected = false;
+            ProtocolError::ConnectionError(format!("Failed to send {}: {}", what, e))
+        })?;
+        if let Some(hook) = &self.on_sent {
+            payloads.iter().for_each(|payload| hook.call(payload));
+        }
+        Ok(())
+    }
+
+    /// Reads one response as text, with the decoded response if it came as a
+    /// binary frame, which has no text of its own. Only transport failures mark
+    /// the connection lost, since an unparsable response still leaves the stream
+    /// at a frame boundary.
+    fn read_text(&mut self) -> Result<(String, Option<Response>), ProtocolError> {
+        self.server_time = None;
+        let frame = if self.binary {
+            binfmt::read_response_timed(&mut self.stream).map(|(response, server_time)| {
+                self.server_time = server_time;
+                (response.to_string(), Some(response))
+            })
+        } else {
+            read_response_text(&mut self.stream).map(|text| (text, None))
+        };
+        match frame {
+            Err(
+                e @ (ProtocolError::ConnectionClosed
+                | ProtocolError::ConnectionError(_)
+                | ProtocolError::PeerUnreachable(_)),
+            ) => {
+                self.connected = false;
+                Err(e)
+            }
+            frame => {
+                self.last_used = Instant::now();
+                if let (Ok((text, _)), Some(hook)) = (&frame, &self.on_received) {
+                    hook.call(text);
+                }
+                frame
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Response, ProtocolError> {
+        match self.read_text()? {
+            (_, Some(response)) => Ok(response),
+            (text, None) => text.parse(),
+        }
+    }
+
+    fn exchange(&mut self, command: &Command) -> Result<Response, ProtocolError> {
+        self.write(&[&command.to_string()], "command")?;
+        self.read()
+    }
+}
+
+/// The locked connection with a read timeout overridden for one exchange; the
+/// default comes back when the guard drops, whichever way the exchange ended.
+struct TimeoutOverride<'a, T: Stream> {
+    connection: MutexGuard<'a, Connection<T>>,
+    restore: Option<Duration>,
+}
+
+impl<'a, T: Stream> TimeoutOverride<'a, T> {
+    fn apply(
+        connection: MutexGuard<'a, Connection<T>>,
+        timeout: Option<Duration>,
+        default: Duration,
+    ) -> Result<Self, ProtocolError> {
+        if let Some(timeout) = timeout {
+            connection.stream.set_read_timeout(timeout)?;
+        }
+        Ok(Self {
+            connection,
+            restore: timeout.map(|_| default),
+        })
+    }
+
+    fn exchange(&mut self, command: &Command) -> Result<Response, ProtocolError> {
+        self.connection.exchange(command)
+    }
+
+    fn server_time(&self) -> Option<Duration> {
+        self.connection.server_time
+    }
+}
+
+impl<T: Stream> Drop for TimeoutOverride<'_, T> {
+    fn drop(&mut self) {
+        if let Some(default) = self.restore {
+            if let Err(e) = self.connection.stream.set_read_timeout(default) {
+                log(&format!("{}; marking the connection lost", e));
+                self.connection.connected = false;
+            }
+        }
+    }
+}
+
+/// Background thread pinging an idle connection; see [`ClientConfig::keepalive_interval`].
+struct Keepalive {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: JoinHandle<()>,
+}
+
+impl Keepalive {
+    fn spawn<T: Read + Write + Send + 'static>(
+        connection: Arc<Mutex<Connection<T>>>,
+        interval: Duration,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let handle = thread::spawn(move || loop {
+            let (stopped, wakeup) = &*signal;
+            let (stopped, _) = wakeup
+                .wait_timeout_while(lock(stopped), interval, |stopped| !*stopped)
+                .unwrap_or_else(PoisonError::into_inner);
+            if *stopped {
+                break;
+            }
+            drop(stopped);
+
+            let mut connection = lock(&connection);
+            if !connection.connected {
+                break;
+            }
+            if connection.last_used.elapsed() < interval {
+                continue;
+            }
+            match connection.exchange(&Command::Ping) {
+                Ok(Response::Pong) => {}
+                Ok(other) => {
+                    log(&format!("Unexpected reply to keepalive PING: {}", other));
+                    connection.connected = false;
+                }
+                Err(e) => log(&format!("Keepalive failed, connection lost: {}", e)),
+            }
+        });
+        Self { stop, handle }
+    }
+
+    /// Wakes the thread and waits for it; an idle thread exits immediately.
+    fn stop(self) {
+        let (stopped, wakeup) = &*self.stop;
+        *lock(stopped) = true;
+        wakeup.notify_all();
+        if self.handle.join().is_err() {
+            log("Keepalive thread panicked");
+        }
+    }
+}
+
+pub struct SmartSocketClient<T: Stream> {
+    connection: Arc<Mutex<Connection<T>>>,
+    strict: bool,
+    keepalive: Option<Keepalive>,
+    metrics: ClientMetrics,
+    last_server_time: Option<Duration>,
+    identity: Option<DeviceInfo>,
+    /// What [`with_config`](SmartSocketClient::with_config) connected with, for
+    /// opening further connections to the same server.
+    config: ClientConfig,
+}
+
+impl<T: Stream> SmartSocketClient<T> {
+    fn new(stream: T, strict: bool) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(Connection {
+                stream,
+                connected: true,
+                binary: false,
+                server_time: None,
+                last_used: Instant::now(),
+                on_sent: None,
+                on_received: None,
+            })),
+            strict,
+            keepalive: None,
+            metrics: ClientMetrics::default(),
+            last_server_time: None,
+            identity: None,
+            config: ClientConfig::default(),
+        }
+    }
+
+    /// Installs the frame hooks of `config` on the connection.
+    fn set_hooks(&mut self, config: &ClientConfig) {
+        let mut connection = lock(&self.connection);
+        connection.on_sent = config.on_frame_sent.clone();
+        connection.on_received = config.on_frame_received.clone();
+    }
+
+    /// Locks the connection, failing fast once it is known to be lost.
+    fn connection(&self) -> Result<MutexGuard<'_, Connection<T>>, ProtocolError> {
+        let connection = lock(&self.connection);
+        if connection.connected {
+            Ok(connection)
+        } else {
+            Err(ProtocolError::ConnectionClosed)
+        }
+    }
+
+    /// In strict mode, rejects a response that is not a valid reply to `command`.
+    fn check_response(
+        &self,
+        command: &Command,
+        response: Response,
+    ) -> Result<Response, ProtocolError> {
+        if self.strict && !command.accepts(&response) {
+            return Err(ProtocolError::InvalidResponse(format!(
+                "unexpected reply to {}: {}",
+                command, response
+            )));
+        }
+        Ok(response)
+    }
+}
+
+impl SmartSocketClient<ClientStream> {
+    pub fn with_config(config: ClientConfig) -> Result<Self, ProtocolError> {
+        let (mut stream, peer) = match &config.socks5_proxy {
+            Some(proxy) => connect(&proxy.address, config.connect_timeout)?,
+            None => connect(&config.address, config.connect_timeout)?,
+        };
+        set_timeouts(&stream, &config)?;
+
+        // After the timeouts, so a proxy that stops answering cannot hang the handshake.
+        let route = match &config.socks5_proxy {
+            Some(proxy) => {
+                socks5::connect(&mut stream, proxy, &config.address)?;
+                format!("via SOCKS5 proxy {}", peer)
+            }
+            None => peer.to_string(),
+        };
+        Self::establish(stream, &route, config)
+    }
+
+    /// Completes a connection over an open TCP stream: TLS, the banner,
+    /// authentication and keepalive. `route` describes the stream in the log.
+    pub(crate) fn establish(
+        stream: TcpStream,
+        route: &str,
+        config: ClientConfig,
+    ) -> Result<Self, ProtocolError> {
+        #[cfg(feature = "tls")]
+        let mut stream = match &config.tls {
+            Some(tls) => {
+                let tls_config = smart_socket_protocol::tls::client_config(&tls.ca_cert)?;
+                let stream =
+                    smart_socket_protocol::tls::connect(tls_config, &tls.server_name, stream)?;
+                ClientStream::Tls(Box::new(stream))
+            }
+            None => ClientStream::tcp(stream, config.psk.as_ref()),
+        };
+        #[cfg(not(feature = "tls"))]
+        let mut stream = ClientStream::tcp(stream, config.psk.as_ref());
+
+        let identity = match config.banner_timeout {
+            Some(wait) => {
+                stream.read_banner(wait, config.read_timeout, config.on_frame_received.as_ref())?
+            }
+            None => None,
+        };
+
+        let mut client = SmartSocketClient::new(stream, config.strict);
+        client.identity = identity;
+        client.set_hooks(&config);
+
+        match &client.identity {
+            Some(identity) => log(&format!(
+                "Connected to {} ({}): {}, protocol {}",
+                config.address, route, identity.name, identity.protocol
+            )),
+            None => log(&format!("Connected to {} ({})", config.address, route)),
+        }
+
+        if config.wire_format == WireFormat::Binary {
+            client.negotiate_binary()?;
+        }
+
+        if let Some(token) = &config.auth_token {
+            client.authenticate(token)?;
+        }
+
+        if let Some(interval) = config.keepalive_interval {
+            client.keepalive = Some(Keepalive::spawn(client.connection.clone(), interval));
+        }
+
+        client.config = config;
+        Ok(client)
+    }
+
+    /// Starts polling `STATUS` every `interval` on a separate connection opened with
+    /// this client's config, so the watcher never waits for this client's commands.
+    /// Changes and poll errors arrive on [`StatusWatcher::events`].
+    pub fn watch(&self, interval: Duration) -> StatusWatcher {
+        let config = ClientConfig {
+            // The polls themselves keep the connection alive.
+            keepalive_interval: None,
+            ..self.config.clone()
+        };
+        StatusWatcher::spawn(config, interval)
+    }
+}
+
+impl<T: Stream> SmartSocketClient<T> {
+    /// Sends `command` and waits for its response, up to the timeout configured
+    /// for its kind in [`ClientConfig::timeouts`] or else the read timeout.
+    pub fn send_command(&mut self, command: Command) -> Result<Response, ProtocolError> {
+        let timeout = self.config.timeouts.get(command.kind()).copied();
+        self.send(command, timeout)
+    }
+
+    /// Like [`send_command`](Self::send_command), but waits up to `timeout` for
+    /// this one response.
+    pub fn send_command_with_timeout(
+        &mut self,
+        command: Command,
+        timeout: Duration,
+    ) -> Result<Response, ProtocolError> {
+        self.send(command, Some(timeout))
+    }
+
+    fn send(
+        &mut self,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<Response, ProtocolError> {
+        match &command {
+            Command::Auth(_) => log("Sending command: Auth(***)"),
+            _ => log(&format!("Sending command: {:?}", command)),
+        }
+
+        let mut connection =
+            TimeoutOverride::apply(self.connection()?, timeout, self.config.read_timeout)?;
+        let started = Instant::now();
+        let response = connection.exchange(&command)?;
+        let latency = started.elapsed();
+        let server_time = connection.server_time();
+        drop(connection);
+        self.last_server_time = server_time;
+        self.metrics
+            .record_timed(command.kind(), latency, self.last_server_time);
+        log(&format!("Received response: {:?}", response));
+
+        self.check_response(&command, response)
+    }
+
+    /// Pipelines `commands`: all frames are written first, then the responses are
+    /// read back in order. The server handles commands strictly in order per
+    /// connection, so this costs a single round trip.
+    pub fn send_batch(&mut self, commands: &[Command]) -> Result<Vec<Response>, ProtocolError> {
+        log(&format!("Sending batch of {} commands", commands.len()));
+
+        let payloads: Vec<String> = commands.iter().map(ToString::to_string).collect();
+        let payloads: Vec<&str> = payloads.iter().map(String::as_str).collect();
+        let mut connection = self.connection()?;
+        connection.write(&payloads, "batch")?;
+
+        let mut responses = Vec::with_capacity(commands.len());
+        for command in commands {
+            let response = connection
+                .read()
+                .and_then(|response| self.check_response(command, response));
+            match response {
+                Ok(response) => responses.push(response),
+                Err(error) => {
+                    return Err(ProtocolError::PartialBatch {
+                        responses,
+                        error: Box::new(error),
+                    })
+                }
+            }
+        }
+        log(&format!("Received {} batch responses", responses.len()));
+
+        Ok(responses)
+    }
+
+    /// Asks for binary responses with the server time; only valid as the first
+    /// frames of a connection. Both are sent at once. A server that knows binary
+    /// responses but not the server time refuses the second and is used without it.
+    fn negotiate_binary(&mut self) -> Result<(), ProtocolError> {
+        let mut connection = self.connection()?;
+        connection.write(&[binfmt::NEGOTIATE, binfmt::NEGOTIATE_TIMED], "wire format")?;
+        match connection.read()? {
+            Response::Ok(_) => connection.binary = true,
+            Response::Error(err) => {
+                return Err(ProtocolError::ConnectionError(format!(
+                    "Server refused binary responses: {}",
+                    err
+                )))
+            }
+            other => return Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+        match connection.read()? {
+            Response::Ok(_) => Ok(()),
+            Response::Error(err) => {
+                log(&format!("Server does not report its own time: {}", err));
+                Ok(())
+            }
+            other => Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+    }
+
+    /// Sends `payload` as one frame exactly as given and returns the response
+    /// text, for commands this client does not know, such as a vendor
+    /// extension. The response is not checked against anything, and binary
+    /// responses are rendered as text.
+    pub fn send_raw(&mut self, payload: &str) -> Result<String, ProtocolError> {
+        log(&format!("Sending raw frame: {:?}", payload));
+        let mut connection = self.connection()?;
+        connection.write(&[payload], "raw frame")?;
+        let (text, _) = connection.read_text()?;
+        log(&format!("Received raw response: {:?}", text));
+        Ok(text)
+    }
+
+    pub fn authenticate(&mut self, token: &str) -> Result<(), ProtocolError> {
+        match self.send_command(Command::Auth(token.to_string()))? {
+            Response::Ok(_) => Ok(()),
+            Response::Error(err) => Err(ProtocolError::ConnectionError(format!(
+                "Authentication failed: {}",
+                err
+            ))),
+            other => Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+    }
+
+    pub fn turn_on(&mut self) -> Result<Response, ProtocolError> {
+        self.send_command(Command::TurnOn)
+    }
+
+    pub fn turn_off(&mut self) -> Result<Response, ProtocolError> {
+        self.send_command(Command::TurnOff)
+    }
+
+    pub fn get_status(&mut self) -> Result<Response, ProtocolError> {
+        self.send_command(Command::GetStatus)
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<Response, ProtocolError> {
+        self.send_command(Command::SetName(name.to_string()))
+    }
+
+    /// Sets a dimmer's level (0..=100); plain sockets answer `E_UNSUPPORTED`.
+    pub fn set_level(&mut self, level: u8) -> Result<Response, ProtocolError> {
+        self.send_command(Command::SetLevel(level))
+    }
+
+    /// Turns the socket on for `millis` milliseconds (10..=60000); the server turns it off.
+    pub fn pulse(&mut self, millis: u64) -> Result<Response, ProtocolError> {
+        self.send_command(Command::Pulse(millis))
+    }
+
+    /// Refuses switching until [`unlock`](Self::unlock), or for `minutes`
+    /// (1..=1440) if given. Queries are still answered.
+    pub fn lock(&mut self, minutes: Option<u32>) -> Result<Response, ProtocolError> {
+        self.send_command(Command::Lock(minutes))
+    }
+
+    /// Clears a child lock.
+    pub fn unlock(&mut self) -> Result<Response, ProtocolError> {
+        self.send_command(Command::Unlock)
+    }
+
+    /// Clears an overload trip; the socket stays off until turned on again.
+    pub fn reset_trip(&mut self) -> Result<Response, ProtocolError> {
+        self.send_command(Command::ResetTrip)
+    }
+
+    pub fn get_info(&mut self) -> Result<DeviceInfo, ProtocolError> {
+        match self.send_command(Command::GetInfo)? {
+            Response::Info(info) => Ok(info),
+            Response::Error(err) => Err(ProtocolError::InvalidResponse(err)),
+            other => Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+    }
+
+    pub fn get_stats(&mut self) -> Result<ServerStats, ProtocolError> {
+        match self.send_command(Command::GetStats)? {
+            Response::Stats(stats) => Ok(stats),
+            Response::Error(err) => Err(ProtocolError::InvalidResponse(err)),
+            other => Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+    }
+
+    /// What the server announced in its banner; `None` if it sent none.
+    pub fn server_identity(&self) -> Option<&DeviceInfo> {
+        self.identity.as_ref()
+    }
+
+    /// False once the connection was closed or failed at the transport level; the
+    /// stream may then be out of sync and should not be reused.
+    pub fn is_connected(&self) -> bool {
+        lock(&self.connection).connected
+    }
+
+    /// Latency of every [`send_command`](Self::send_command) that got a response,
+    /// `ERROR` replies included. Batches and keepalive pings are not counted.
+    pub fn metrics(&self) -> &ClientMetrics {
+        &self.metrics
+    }
+
+    /// How long the server says it spent on the last
+    /// [`send_command`](Self::send_command) that got a response. Only binary
+    /// responses carry it, and only from servers that report it.
+    pub fn last_server_time(&self) -> Option<Duration> {
+        self.last_server_time
+    }
+
+    /// Stops the keepalive thread and shuts the connection down, logging a latency summary.
+    pub fn close(&mut self) -> Result<(), ProtocolError> {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.stop();
+        }
+        let mut connection = lock(&self.connection);
+        if connection.connected {
+            if !self.metrics.is_empty() {
+                log(&format!("Command latency:\n{}", self.metrics));
+            }
+            log("Closing connection...");
+            connection.stream.shutdown(Shutdown::Both).map_err(|e| {
+                log(&format!("Failed to close connection: {}", e));
+                ProtocolError::ConnectionError(format!("Failed to close connection: {}", e))
+            })?;
+            connection.connected = false;
+            log("Connection closed successfully");
+        }
+        Ok(())
+    }
+}
+
+impl<T: Stream> Drop for SmartSocketClient<T> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::read_message;
+    use std::cell::RefCell;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn written(client: &SmartSocketClient<MockTcpStream>) -> Vec<u8> {
+        lock(&client.connection).stream.write_data.clone()
+    }
+
+    struct MockTcpStream {
+        read_data: io::Cursor<Vec<u8>>,
+        write_data: Vec<u8>,
+        read_timeouts: RefCell<Vec<Duration>>,
+    }
+
+    impl MockTcpStream {
+        fn with_responses(responses: &[&str]) -> Self {
+            let read_data = responses
+                .iter()
+                .flat_map(|response| serialize_message(response).unwrap())
+                .collect();
+            Self {
+                read_data: io::Cursor::new(read_data),
+                write_data: Vec::new(),
+                read_timeouts: RefCell::default(),
+            }
+        }
+    }
+
+    impl Stream for MockTcpStream {
+        fn set_read_timeout(&self, timeout: Duration) -> Result<(), ProtocolError> {
+            self.read_timeouts.borrow_mut().push(timeout);
+            Ok(())
+        }
+    }
+
+    fn read_timeouts(client: &SmartSocketClient<MockTcpStream>) -> Vec<Duration> {
+        lock(&client.connection)
+            .stream
+            .read_timeouts
+            .borrow()
+            .clone()
+    }
+
+    impl Read for MockTcpStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read_data.read(buf)
+        }
+    }
+
+    impl Write for MockTcpStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_turn_on() {
+        let mock_stream = MockTcpStream::with_responses(&["OK:Socket turned on"]);
+
+        let mut client = SmartSocketClient::new(mock_stream, true);
+
+        let response = client.turn_on().unwrap();
+        match response {
+            Response::Ok(msg) => assert_eq!(msg.as_deref(), Some("Socket turned on")),
+            _ => panic!("Unexpected response type"),
+        }
+    }
+
+    #[test]
+    fn test_turn_off() {
+        let mock_stream = MockTcpStream::with_responses(&["OK:Socket turned off"]);
+
+        let mut client = SmartSocketClient::new(mock_stream, true);
+
+        let response = client.turn_off().unwrap();
+        match response {
+            Response::Ok(msg) => assert_eq!(msg.as_deref(), Some("Socket turned off")),
+            _ => panic!("Unexpected response type"),
+        }
+    }
+
+    #[test]
+    fn test_get_status() {
+        let mock_stream = MockTcpStream::with_responses(&["STATUS:ON:100"]);
+
+        let mut client = SmartSocketClient::new(mock_stream, true);
+
+        let response = client.get_status().unwrap();
+        match response {
+            Response::Status {
+                is_on,
+                power,
+                tripped,
+                locked,
+            } => {
+                assert!(is_on);
+                assert_eq!(power, 100);
+                assert!(!tripped);
+                assert!(!locked);
+            }
+            _ => panic!("Unexpected response type"),
+        }
+    }
+
+    #[test]
+    fn test_get_info() {
+        let mock_stream = MockTcpStream::with_responses(&["INFO:Kitchen Socket, Power: 100W"]);
+
+        let mut client = SmartSocketClient::new(mock_stream, true);
+
+        let info = client.get_info().unwrap();
+        assert!(info.name.contains("Kitchen Socket"));
+    }
+
+    #[test]
+    fn test_get_info_structured() {
+        let mock_stream = MockTcpStream::with_responses(&[
+            "INFO:name=Kitchen Socket;power=3500;firmware=0.1.0;uptime=12",
+        ]);
+
+        let mut client = SmartSocketClient::new(mock_stream, true);
+
+        let info = client.get_info().unwrap();
+        assert_eq!(info.name, "Kitchen Socket");
+        assert_eq!(info.power, 3500);
+        assert_eq!(info.firmware, "0.1.0");
+        assert_eq!(info.uptime, 12);
+    }
+
+    #[test]
+    fn test_multi_frame_response_is_reassembled() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&[
+                "MORE:INFO:name=Kitch",
+                "MORE:en Socket;power=35",
+                "00",
+                "PONG",
+            ]),
+            true,
+        );
+
+        let info = client.get_info().unwrap();
+        assert_eq!(info.name, "Kitchen Socket");
+        assert_eq!(info.power, 3500);
+        assert!(matches!(
+            client.send_command(Command::Ping),
+            Ok(Response::Pong)
+        ));
+    }
+
+    #[test]
+    fn test_set_name() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["OK:Socket renamed"]), true);
+
+        assert!(matches!(client.set_name("Hall").unwrap(), Response::Ok(_)));
+        assert_eq!(
+            written(&client),
+            serialize_message("SET_NAME:Hall").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_stats() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&[
+                "STATS:uptime=5;connections=2;active=1;commands=9;errors=0",
+            ]),
+            true,
+        );
+
+        let stats = client.get_stats().unwrap();
+        assert_eq!(stats.connections, 2);
+        assert_eq!(stats.commands, 9);
+        assert_eq!(written(&client), serialize_message("STATS").unwrap());
+    }
+
+    #[test]
+    fn test_metrics_count_answered_commands() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:on", "STATUS:ON:100", "ERROR:E_X:boom"]),
+            true,
+        );
+        client.turn_on().unwrap();
+        client.get_status().unwrap();
+        client.get_status().unwrap();
+        assert!(client.get_status().is_err());
+
+        let metrics = client.metrics();
+        assert_eq!(metrics.get("ON").unwrap().count(), 1);
+        assert_eq!(metrics.get("STATUS").unwrap().count(), 2);
+        assert!(metrics.get("OFF").is_none());
+    }
+
+    #[test]
+    fn test_server_time_from_binary_responses() {
+        let mut frames = Vec::new();
+        binfmt::write_response_timed(&mut frames, &Response::Pong, Duration::from_micros(250))
+            .unwrap();
+        binfmt::write_response(&mut frames, &Response::Pong).unwrap();
+        let mut stream = MockTcpStream::with_responses(&[]);
+        stream.read_data = io::Cursor::new(frames);
+        let mut client = SmartSocketClient::new(stream, true);
+        lock(&client.connection).binary = true;
+        assert_eq!(client.last_server_time(), None);
+
+        client.send_command(Command::Ping).unwrap();
+        assert_eq!(client.last_server_time(), Some(Duration::from_micros(250)));
+        assert_eq!(client.metrics().server("PING").unwrap().count(), 1);
+        // An older server leaves the time out.
+        client.send_command(Command::Ping).unwrap();
+        assert_eq!(client.last_server_time(), None);
+        assert_eq!(client.metrics().get("PING").unwrap().count(), 2);
+        assert_eq!(client.metrics().network("PING").unwrap().count(), 1);
+
+        // Text responses never carry it.
+        let mut client = SmartSocketClient::new(MockTcpStream::with_responses(&["PONG"]), true);
+        client.send_command(Command::Ping).unwrap();
+        assert_eq!(client.last_server_time(), None);
+        assert!(client.metrics().server("PING").is_none());
+    }
+
+    #[test]
+    fn test_set_level() {
+        let mut client = SmartSocketClient::new(MockTcpStream::with_responses(&["LEVEL:30"]), true);
+
+        assert!(matches!(client.set_level(30).unwrap(), Response::Level(30)));
+        assert_eq!(written(&client), serialize_message("LEVEL:30").unwrap());
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["OK:Pulse started"]), true);
+        assert!(matches!(client.pulse(500).unwrap(), Response::Ok(_)));
+        assert_eq!(written(&client), serialize_message("PULSE:500").unwrap());
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["OK:Authenticated"]), true);
+
+        client.authenticate("secret").unwrap();
+        assert_eq!(written(&client), serialize_message("AUTH:secret").unwrap());
+    }
+
+    #[test]
+    fn test_authenticate_rejected() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["ERROR:E_UNAUTHORIZED:Invalid token"]),
+            true,
+        );
+
+        let err = client.authenticate("guess").unwrap_err();
+        assert!(err.to_string().contains("E_UNAUTHORIZED"));
+    }
+
+    #[test]
+    fn test_send_batch() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:Socket turned on", "STATUS:ON:100"]),
+            true,
+        );
+
+        let responses = client
+            .send_batch(&[Command::TurnOn, Command::GetStatus])
+            .unwrap();
+        assert!(matches!(responses[0], Response::Ok(_)));
+        assert!(matches!(
+            responses[1],
+            Response::Status {
+                is_on: true,
+                power: 100,
+                tripped: false,
+                locked: false,
+            }
+        ));
+
+        let mut expected = serialize_message("ON").unwrap();
+        expected.extend(serialize_message("STATUS").unwrap());
+        assert_eq!(written(&client), expected);
+    }
+
+    #[test]
+    fn test_send_batch_partial_failure() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:Socket turned on"]),
+            true,
+        );
+
+        match client.send_batch(&[Command::TurnOn, Command::GetStatus, Command::TurnOff]) {
+            Err(ProtocolError::PartialBatch { responses, error }) => {
+                assert_eq!(responses.len(), 1);
+                assert!(matches!(*error, ProtocolError::ConnectionClosed));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mismatched_response_is_rejected() {
+        let cases = [
+            (Command::TurnOn, "STATUS:ON:100"),
+            (Command::TurnOff, "INFO:name=Lamp"),
+            (Command::GetStatus, "INFO:name=Lamp"),
+            (Command::GetInfo, "OK:Socket turned on"),
+            (Command::Ping, "STATUS:OFF:0"),
+        ];
+        for (command, reply) in cases {
+            let mut client = SmartSocketClient::new(MockTcpStream::with_responses(&[reply]), true);
+            match client.send_command(command.clone()) {
+                Err(ProtocolError::InvalidResponse(msg)) => {
+                    assert!(msg.contains(&command.to_string()), "{}", msg);
+                    assert!(msg.contains(reply), "{}", msg);
+                }
+                other => panic!("{} / {}: unexpected result {:?}", command, reply, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_raw_round_trip() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:vendor ack", "ERROR:Invalid command: X"]),
+            true,
+        );
+        assert_eq!(client.send_raw("VENDOR:42").unwrap(), "OK:vendor ack");
+        assert_eq!(written(&client), serialize_message("VENDOR:42").unwrap());
+        // Not checked, and not counted as a command.
+        assert_eq!(client.send_raw("X").unwrap(), "ERROR:Invalid command: X");
+        assert!(client.metrics().is_empty());
+    }
+
+    #[test]
+    fn test_frame_hooks_see_traffic_in_order() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let (sent, received) = (trace.clone(), trace.clone());
+        let config = ClientConfig::builder()
+            .on_frame_sent(move |payload| lock(&sent).push(format!("> {}", payload)))
+            .on_frame_received(move |payload| lock(&received).push(format!("< {}", payload)))
+            .build()
+            .unwrap();
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:Socket turned on", "INFO:name=Lamp", "OK"]),
+            true,
+        );
+        client.set_hooks(&config);
+
+        client.turn_on().unwrap();
+        // A rejected reply was still received, and a batch is one write.
+        assert!(client.get_status().is_err());
+        assert!(client
+            .send_batch(&[Command::TurnOff, Command::GetStatus])
+            .is_err());
+        assert_eq!(
+            *lock(&trace),
+            [
+                "> ON",
+                "< OK:Socket turned on",
+                "> STATUS",
+                "< INFO:name=Lamp",
+                "> OFF",
+                "> STATUS",
+                "< OK",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_reply_is_accepted_for_any_command() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["ERROR:boom"]), true);
+        assert!(matches!(
+            client.get_status().unwrap(),
+            Response::Error(msg) if msg == "boom"
+        ));
+    }
+
+    #[test]
+    fn test_non_strict_passes_mismatch_through() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["INFO:name=Lamp"]), false);
+        assert!(matches!(client.get_status().unwrap(), Response::Info(_)));
+    }
+
+    fn dead_addr(ip: &str) -> SocketAddr {
+        // Bound and immediately released, so nothing is listening there.
+        std::net::TcpListener::bind((ip, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_connect_falls_back_to_next_resolved_address() {
+        let live = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_addr = live.local_addr().unwrap();
+        let resolved = [dead_addr("::1"), dead_addr("127.0.0.1"), live_addr];
+
+        let (_stream, connected) = connect(&resolved[..], CONNECT_TIMEOUT).unwrap();
+        assert_eq!(connected, live_addr);
+    }
+
+    #[test]
+    fn test_connect_prefers_ipv6() {
+        let v4 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let v6 = std::net::TcpListener::bind("[::1]:0").unwrap();
+        let resolved = [v4.local_addr().unwrap(), v6.local_addr().unwrap()];
+
+        let (_stream, connected) = connect(&resolved[..], CONNECT_TIMEOUT).unwrap();
+        assert_eq!(connected, resolved[1]);
+    }
+
+    #[test]
+    fn test_connect_reports_last_failure() {
+        let resolved = [dead_addr("127.0.0.1")];
+        assert!(matches!(
+            connect(&resolved[..], CONNECT_TIMEOUT),
+            Err(ProtocolError::ConnectionError(msg)) if msg.starts_with("Failed to connect")
+        ));
+    }
+
+    #[test]
+    fn test_connect_deadline_covers_every_address() {
+        let resolved: [SocketAddr; 3] = [
+            "[2001:db8::1]:8080".parse().unwrap(),
+            "10.0.0.9:8080".parse().unwrap(),
+            "10.0.0.10:8080".parse().unwrap(),
+        ];
+        let timeout = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut tried = Vec::new();
+        let result = connect_within(&resolved, timeout, |addr, remaining| {
+            tried.push(*addr);
+            if addr.is_ipv6() {
+                return Err::<(), _>(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "connection refused",
+                ));
+            }
+            // A blackholed host: the attempt uses up the rest of the deadline.
+            thread::sleep(remaining);
+            Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+        });
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= timeout && elapsed < timeout * 3, "{:?}", elapsed);
+        assert_eq!(tried, resolved[..2]);
+        assert!(matches!(
+            result,
+            Err(ProtocolError::ConnectionError(msg)) if msg == "Connection timed out after 100ms \
+                to 10.0.0.9:8080 (also tried [2001:db8::1]:8080: connection refused); \
+                1 more addresses not tried"
+        ));
+    }
+
+    #[test]
+    fn test_with_config_honours_connect_timeout() {
+        // TEST-NET-1 is never routed, so the connect hangs, or fails at once
+        // without a network; either way it is over well before the OS default.
+        let config = ClientConfig::builder()
+            .address("192.0.2.1:8080")
+            .connect_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            SmartSocketClient::with_config(config),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        assert!(matches!(
+            ClientConfig::builder()
+                .connect_timeout(Duration::ZERO)
+                .build(),
+            Err(ConfigError::ZeroDuration("connect_timeout"))
+        ));
+    }
+
+    #[test]
+    fn test_transport_failure_marks_client_disconnected() {
+        let mut client =
+            SmartSocketClient::new(MockTcpStream::with_responses(&["STATUS:ON:100"]), true);
+        client.get_status().unwrap();
+        assert!(client.is_connected());
+
+        assert!(client.get_status().is_err());
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_timeout_override_is_restored() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:Pulse started", "STATUS:ON:100"]),
+            true,
+        );
+        client
+            .config
+            .timeouts
+            .insert("PULSE", Duration::from_secs(30));
+
+        client.pulse(20_000).unwrap();
+        client.get_status().unwrap();
+        // STATUS has no entry, so only PULSE touched the timeout.
+        assert_eq!(
+            read_timeouts(&client),
+            [Duration::from_secs(30), client.config.read_timeout]
+        );
+    }
+
+    #[test]
+    fn test_timeout_override_is_restored_on_errors() {
+        let mut client = SmartSocketClient::new(MockTcpStream::with_responses(&["GARBAGE"]), true);
+
+        // An unparsable reply, then a closed connection: both return early.
+        assert!(client
+            .send_command_with_timeout(Command::GetStatus, Duration::from_secs(2))
+            .is_err());
+        assert!(client.is_connected());
+        assert!(client
+            .send_command_with_timeout(Command::GetStatus, Duration::from_secs(3))
+            .is_err());
+        assert!(!client.is_connected());
+
+        let default = client.config.read_timeout;
+        assert_eq!(
+            read_timeouts(&client),
+            [
+                Duration::from_secs(2),
+                default,
+                Duration::from_secs(3),
+                default
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slow_command_gets_its_own_timeout() {
+        let addr = spawn_fake_server(None, |stream| {
+            while let Ok(message) = read_message(stream) {
+                thread::sleep(Duration::from_millis(300));
+                let frame = match Command::from_str(&message) {
+                    Ok(Command::Pulse(_)) => "OK:Pulse started",
+                    _ => "STATUS:ON:100",
+                };
+                reply(stream, frame)?;
+            }
+            Ok(())
+        });
+        let mut client = SmartSocketClient::with_config(ClientConfig {
+            address: addr.into(),
+            read_timeout: Duration::from_millis(100),
+            timeouts: HashMap::from([("PULSE", Duration::from_secs(5))]),
+            banner_timeout: None,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(client.pulse(1000).unwrap(), Response::ok("Pulse started"));
+        assert!(client
+            .send_command_with_timeout(Command::GetStatus, Duration::from_secs(5))
+            .is_ok());
+        // Back to the 100 ms default, which the delayed reply misses.
+        assert!(client.get_status().is_err());
+    }
+
+    #[test]
+    fn test_config_checks_address() {
+        let config = ClientConfig::new("[::1]:9000").unwrap();
+        assert_eq!(config.address.to_string(), "[::1]:9000");
+        assert_eq!(
+            ClientConfig::new("localhost").unwrap_err(),
+            ConfigError::MissingPort("localhost".to_string())
+        );
+        assert_eq!(
+            ClientConfig::new("localhost:99999")
+                .unwrap_err()
+                .to_string(),
+            "Invalid address 'localhost:99999': port must be a number from 0 to 65535"
+        );
+    }
+
+    #[test]
+    fn test_builder_defaults_and_overrides() {
+        let config = ClientConfig::builder().build().unwrap();
+        let default = ClientConfig::default();
+        assert_eq!(config.address, default.address);
+        assert_eq!(config.read_timeout, default.read_timeout);
+        assert_eq!(config.banner_timeout, default.banner_timeout);
+        assert!(config.strict);
+
+        let config = ClientConfig::builder()
+            .address("10.0.0.5:8080")
+            .read_timeout(Duration::from_secs(3))
+            .timeout("PULSE", Duration::from_secs(30))
+            .auth_token("secret")
+            .strict(false)
+            .banner_timeout(None)
+            .build()
+            .unwrap();
+        assert_eq!(config.address.to_string(), "10.0.0.5:8080");
+        assert_eq!(config.read_timeout, Duration::from_secs(3));
+        assert_eq!(config.write_timeout, default.write_timeout);
+        assert_eq!(config.timeouts["PULSE"], Duration::from_secs(30));
+        assert_eq!(config.auth_token.as_deref(), Some("secret"));
+        assert!(!config.strict);
+        assert_eq!(config.banner_timeout, None);
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_values() {
+        assert_eq!(
+            ClientConfig::builder()
+                .address("localhost")
+                .read_timeout(Duration::ZERO)
+                .build()
+                .unwrap_err(),
+            ConfigError::MissingPort("localhost".to_string())
+        );
+        assert_eq!(
+            ClientConfig::builder()
+                .read_timeout(Duration::ZERO)
+                .build()
+                .unwrap_err(),
+            ConfigError::ZeroDuration("read_timeout")
+        );
+        assert_eq!(
+            ClientConfig::builder()
+                .keepalive_interval(Duration::ZERO)
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "keepalive_interval must be greater than zero"
+        );
+        assert_eq!(
+            ClientConfig::builder()
+                .timeout("PULSE", Duration::ZERO)
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "Invalid timeouts: the PULSE timeout must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn test_send_batch_rejects_mismatched_response() {
+        let mut client = SmartSocketClient::new(
+            MockTcpStream::with_responses(&["OK:Socket turned on", "OK:Socket turned on"]),
+            true,
+        );
+
+        match client.send_batch(&[Command::TurnOn, Command::GetStatus]) {
+            Err(ProtocolError::PartialBatch { responses, error }) => {
+                assert_eq!(responses.len(), 1);
+                assert!(matches!(*error, ProtocolError::InvalidResponse(_)));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    /// Serves one connection at a time on a local port with `handler`, sealing
+    /// every frame with `psk` if given. A handler error, such as the client
+    /// going away, only ends that connection.
+    fn spawn_fake_server(
+        psk: Option<[u8; 32]>,
+        mut handler: impl FnMut(&mut ClientStream) -> io::Result<()> + Send + 'static,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Batched replies go out back to back; don't let Nagle hold the second one.
+                stream.set_nodelay(true).unwrap();
+                let mut stream = match psk {
+                    Some(key) => {
+                        ClientStream::Psk(Box::new(CipherStream::new(stream, Psk::new(&key))))
+                    }
+                    None => ClientStream::Plain(stream),
+                };
+                let _ = handler(&mut stream);
+            }
+        });
+        addr
+    }
+
+    fn reply(stream: &mut ClientStream, frame: &str) -> io::Result<()> {
+        stream.write_all(&serialize_message(frame).unwrap())
+    }
+
+    /// Answers PING and STATUS. The returned counter tracks pings; any other
+    /// command is answered with a protocol error.
+    fn spawn_ping_server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let pings = Arc::new(AtomicUsize::new(0));
+        let counter = pings.clone();
+        let addr = spawn_fake_server(None, move |stream| {
+            while let Ok(message) = read_message(stream) {
+                let frame = match Command::from_str(&message) {
+                    Ok(Command::Ping) => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        "PONG"
+                    }
+                    Ok(Command::GetStatus) => "STATUS:OFF:0",
+                    _ => "ERROR:E_INVALID_ARGUMENT:garbled frame",
+                };
+                reply(stream, frame)?;
+            }
+            Ok(())
+        });
+        (addr, pings)
+    }
+
+    /// Answers STATUS after optionally greeting with an INFO banner, like old and
+    /// new servers, sealing every frame with `psk` if given.
+    fn spawn_status_server(banner: Option<&'static str>, psk: Option<[u8; 32]>) -> SocketAddr {
+        spawn_fake_server(psk, move |stream| {
+            if let Some(banner) = banner {
+                reply(stream, banner)?;
+            }
+            while read_message(stream).is_ok() {
+                reply(stream, "STATUS:ON:100")?;
+            }
+            Ok(())
+        })
+    }
+
+    const HALL_BANNER: &str =
+        "INFO:name=Hall;power=60;firmware=0.2.0;uptime=1;protocol=1;capabilities=ON,OFF";
+
+    fn connect_to(addr: SocketAddr) -> SmartSocketClient<ClientStream> {
+        SmartSocketClient::with_config(ClientConfig {
+            address: addr.into(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_banner_is_stored_before_first_command() {
+        let mut client = connect_to(spawn_status_server(Some(HALL_BANNER), None));
+        let identity = client.server_identity().unwrap();
+        assert_eq!(identity.name, "Hall");
+        assert_eq!(identity.protocol, 1);
+        assert_eq!(identity.capabilities, ["ON", "OFF"]);
+        assert!(matches!(
+            client.get_status().unwrap(),
+            Response::Status { is_on: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_server_without_banner_is_tolerated() {
+        let started = Instant::now();
+        let mut client = connect_to(spawn_status_server(None, None));
+        assert!(started.elapsed() < BANNER_TIMEOUT + Duration::from_secs(1));
+        assert!(client.server_identity().is_none());
+        assert!(matches!(
+            client.get_status().unwrap(),
+            Response::Status { is_on: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_banner_that_is_not_info_is_rejected() {
+        let addr = spawn_status_server(Some("STATUS:OFF:0"), None);
+        let result = SmartSocketClient::with_config(ClientConfig {
+            address: addr.into(),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(ProtocolError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn test_psk_connection() {
+        let key = [5u8; 32];
+        let addr = spawn_status_server(Some(HALL_BANNER), Some(key));
+        let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        let config = |psk: Option<&str>| {
+            let builder = ClientConfig::builder().address(addr);
+            match psk {
+                Some(psk) => builder.psk(psk),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+
+        let mut client = SmartSocketClient::with_config(config(Some(&hex))).unwrap();
+        assert_eq!(client.server_identity().unwrap().name, "Hall");
+        assert!(matches!(
+            client.get_status().unwrap(),
+            Response::Status { is_on: true, .. }
+        ));
+        drop(client);
+
+        let wrong = "07".repeat(32);
+        assert!(matches!(
+            SmartSocketClient::with_config(config(Some(&wrong))),
+            Err(ProtocolError::AuthFailed)
+        ));
+        // Without the key the sealed banner is just garbage.
+        assert!(SmartSocketClient::with_config(config(None)).is_err());
+        assert!(matches!(
+            ClientConfig::builder().psk("secret").build(),
+            Err(ConfigError::InvalidValue { field: "psk", .. })
+        ));
+    }
+
+    /// Answers STATUS in binary if the first frame asks for it, or refuses that
+    /// frame, like a server without binary responses. Either way it does not know
+    /// the server time and refuses the frame asking for it.
+    fn spawn_binary_server(supported: bool) -> SocketAddr {
+        spawn_fake_server(None, move |stream| {
+            assert_eq!(read_message(stream).unwrap(), binfmt::NEGOTIATE);
+            reply(
+                stream,
+                if supported {
+                    "OK:Binary responses"
+                } else {
+                    "ERROR:Unknown command: PROTO"
+                },
+            )?;
+            while let Ok(message) = read_message(stream) {
+                let response = if message == binfmt::NEGOTIATE_TIMED {
+                    Response::Error("Unknown command: PROTO".to_string())
+                } else {
+                    Response::Status {
+                        is_on: true,
+                        power: 100,
+                        tripped: false,
+                        locked: false,
+                    }
+                };
+                binfmt::write_response(stream, &response)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn binary_client(addr: SocketAddr) -> Result<SmartSocketClient<ClientStream>, ProtocolError> {
+        SmartSocketClient::with_config(ClientConfig {
+            address: addr.into(),
+            banner_timeout: None,
+            wire_format: WireFormat::Binary,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_binary_wire_format() {
+        let mut client = binary_client(spawn_binary_server(true)).unwrap();
+        let statuses = client
+            .send_batch(&[Command::GetStatus, Command::GetStatus])
+            .unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(matches!(
+            client.get_status().unwrap(),
+            Response::Status { power: 100, .. }
+        ));
+
+        assert!(matches!(
+            binary_client(spawn_binary_server(false)),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+    }
+
+    fn keepalive_client(addr: SocketAddr, interval: Duration) -> SmartSocketClient<ClientStream> {
+        SmartSocketClient::with_config(ClientConfig {
+            address: addr.into(),
+            keepalive_interval: Some(interval),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_keepalive_pings_idle_connection() {
+        let (addr, pings) = spawn_ping_server();
+        let mut client = keepalive_client(addr, Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(200));
+        assert!(pings.load(Ordering::SeqCst) > 0);
+        assert!(client.is_connected());
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn test_keepalive_detects_dead_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || drop(listener.accept().unwrap()));
+
+        let mut client = keepalive_client(addr, Duration::from_millis(20));
+        server.join().unwrap();
+        let started = Instant::now();
+        while client.is_connected() {
+            assert!(started.elapsed() < Duration::from_secs(5), "never noticed");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(
+            client.get_status(),
+            Err(ProtocolError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_close_stops_keepalive_promptly() {
+        let (addr, _) = spawn_ping_server();
+        let mut client = keepalive_client(addr, Duration::from_secs(3600));
+        let started = Instant::now();
+        client.close().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_drop_closes_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            // Returns 0 once the client shuts the connection down, not on timeout.
+            stream.read(&mut [0u8; 1]).unwrap()
+        });
+
+        let client = keepalive_client(addr, Duration::from_secs(3600));
+        drop(client);
+        assert_eq!(server.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_keepalive_and_commands_never_interleave() {
+        let (addr, pings) = spawn_ping_server();
+        let mut client = keepalive_client(addr, Duration::from_millis(1));
+        for i in 0..500 {
+            // Strict mode rejects any reply that belongs to someone else's frame.
+            assert!(matches!(
+                client.get_status().unwrap(),
+                Response::Status { is_on: false, .. }
+            ));
+            if i % 5 == 0 {
+                let responses = client
+                    .send_batch(&[Command::GetStatus, Command::GetStatus])
+                    .unwrap();
+                assert_eq!(responses.len(), 2);
+            }
+            if i % 10 == 0 {
+                thread::sleep(Duration::from_millis(2));
+            }
+        }
+        assert!(pings.load(Ordering::SeqCst) > 0);
+        assert!(client.is_connected());
+        client.close().unwrap();
+    }
+}
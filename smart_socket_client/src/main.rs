@@ -1,335 +1,1214 @@
-use smart_socket_server::{read_message, serialize_message, Command, ProtocolError, Response};
-use std::io::{self, Read, Write};
-use std::net::{Shutdown, TcpStream};
-use std::str::FromStr;
+mod dashboard;
+mod script;
+mod style;
+
+use dashboard::WatchState;
+use script::{Recorder, Step};
+use smart_socket_client::fleet::{FleetConfig, SmartSocketFleet};
+use smart_socket_client::messages::{self, Locale};
+use smart_socket_client::watch::SocketStatus;
+#[cfg(feature = "tls")]
+use smart_socket_client::TlsClientConfig;
+use smart_socket_client::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::beacon::Beacon;
+use smart_socket_protocol::shutdown::{Shutdown, EXIT_STARTUP_FAILED, GRACE_PERIOD};
+use smart_socket_protocol::{
+    address, validate_device_name, Command, DeviceInfo, ProtocolError, Response, LOCK_MINUTES,
+    PULSE_MILLIS,
+};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-
-fn get_timestamp() -> String {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs()
-        .to_string()
-}
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use style::{Color, Style};
 
-trait Stream: Read + Write {
-    fn shutdown(&self, _: Shutdown) -> std::io::Result<()> {
-        Ok(())
-    }
+/// Per-REPL presentation settings plus what we have learned about the device.
+struct Session {
+    locale: Locale,
+    style: Style,
+    /// Last name seen in an INFO response or set by `rename`.
+    device_name: Option<String>,
+    /// Script the executed commands are appended to, after `record <file>`.
+    recording: Option<(PathBuf, Recorder<File>)>,
+    /// Set while `watch` runs; Ctrl+C then clears it, ending the watch instead
+    /// of the client.
+    watching: Arc<AtomicBool>,
+    /// Started with `--expert`, which allows `raw`.
+    expert: bool,
 }
 
-impl Stream for TcpStream {}
+impl Session {
+    fn warn(&self, text: &str) {
+        eprintln!("{}", self.style.paint(Color::Yellow, text));
+    }
 
-#[derive(Debug)]
-struct ClientConfig {
-    read_timeout: Duration,
-    write_timeout: Duration,
-    address: String,
-}
+    fn error(&self, error: &dyn std::fmt::Display) {
+        let text = messages::format("error", self.locale, &[("error", error)]);
+        eprintln!("{}", self.style.paint(Color::Red, &text));
+    }
 
-impl Default for ClientConfig {
-    fn default() -> Self {
-        Self {
-            read_timeout: Duration::from_secs(5),
-            write_timeout: Duration::from_secs(5),
-            address: "127.0.0.1:8080".to_string(),
+    /// Prints `text` for `response`, sending error responses to stderr.
+    fn report(&self, response: &Response, text: &str) {
+        if matches!(response, Response::Error(_)) {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
         }
     }
 }
 
-struct SmartSocketClient<T: Stream> {
-    stream: T,
-    connected: bool,
+fn print_help(locale: Locale, expert: bool) {
+    for key in [
+        "help.title",
+        "help.on",
+        "help.off",
+        "help.status",
+        "help.info",
+        "help.rename",
+        "help.stats",
+        "help.level",
+        "help.pulse",
+        "help.reset",
+        "help.lock",
+        "help.unlock",
+        "help.metrics",
+        "help.help",
+        "help.batch",
+        "help.record",
+        "help.stop_record",
+        "help.replay",
+        "help.watch",
+        "help.exit",
+    ] {
+        println!("{}", messages::get(key, locale));
+    }
+    if expert {
+        println!("{}", messages::get("help.raw", locale));
+    }
 }
 
-impl<T: Stream> SmartSocketClient<T> {
-    fn log(&self, message: &str) {
-        println!("[{}] {}", get_timestamp(), message);
+/// Splits a console command into its lowercased first word and the rest, if any.
+/// Only the word ignores case; names keep theirs.
+fn split_command(cmd: &str) -> (String, Option<&str>) {
+    match cmd.split_once(' ') {
+        Some((word, rest)) => (word.to_ascii_lowercase(), Some(rest.trim())),
+        None => (cmd.to_ascii_lowercase(), None),
     }
 }
 
-impl SmartSocketClient<TcpStream> {
-    fn with_config(config: ClientConfig) -> Result<Self, ProtocolError> {
-        let stream = TcpStream::connect(&config.address)
-            .map_err(|e| ProtocolError::ConnectionError(format!("Failed to connect: {}", e)))?;
-
-        stream
-            .set_read_timeout(Some(config.read_timeout))
-            .map_err(|e| {
-                ProtocolError::ConnectionError(format!("Failed to set read timeout: {}", e))
-            })?;
+fn parse_protocol_command(cmd: &str) -> Option<Command> {
+    match cmd.to_ascii_lowercase().as_str() {
+        "on" => Some(Command::TurnOn),
+        "off" => Some(Command::TurnOff),
+        "status" => Some(Command::GetStatus),
+        "info" => Some(Command::GetInfo),
+        _ => None,
+    }
+}
 
-        stream
-            .set_write_timeout(Some(config.write_timeout))
-            .map_err(|e| {
-                ProtocolError::ConnectionError(format!("Failed to set write timeout: {}", e))
-            })?;
+fn handle_batch(client: &mut SmartSocketClient<ClientStream>, line: &str, session: &mut Session) {
+    let commands: Option<Vec<Command>> = line
+        .split(';')
+        .map(str::trim)
+        .filter(|cmd| !cmd.is_empty())
+        .map(parse_protocol_command)
+        .collect();
+    let Some(commands) = commands else {
+        session.warn(messages::get("batch_only", session.locale));
+        return;
+    };
 
-        Ok(SmartSocketClient {
-            stream,
-            connected: true,
-        })
+    let responses = match client.send_batch(&commands) {
+        Ok(responses) => responses,
+        Err(ProtocolError::PartialBatch { responses, error }) => {
+            session.error(&error);
+            responses
+        }
+        Err(e) => {
+            session.error(&e);
+            return;
+        }
+    };
+    for (command, response) in commands.iter().zip(&responses) {
+        remember_name(session, response);
+        let text = format!("{}: {}", command, format_response(response, session));
+        session.report(response, &text);
     }
 }
 
-impl<T: Stream> SmartSocketClient<T> {
-    fn send_command(&mut self, command: Command) -> Result<Response, ProtocolError> {
-        self.log(&format!("Sending command: {:?}", command));
-
-        let message = command.to_string();
-        let data = serialize_message(&message);
-        self.stream.write_all(&data).map_err(|e| {
-            self.log(&format!("Failed to send command: {}", e));
-            ProtocolError::ConnectionError(format!("Failed to send command: {}", e))
-        })?;
+/// Console commands that drive recording and replay rather than the socket.
+#[derive(Debug, PartialEq, Eq)]
+enum MetaCommand<'a> {
+    Record(&'a str),
+    StopRecord,
+    Replay {
+        path: &'a str,
+        fast: bool,
+    },
+    Watch(Option<&'a str>),
+    /// A frame sent exactly as typed, for vendor commands; needs `--expert`.
+    Raw(&'a str),
+}
 
-        let response_str = read_message(&mut self.stream)?;
-        let response = Response::from_str(&response_str)?;
-        self.log(&format!("Received response: {:?}", response));
+fn parse_meta_command(cmd: &str) -> Option<MetaCommand<'_>> {
+    match split_command(cmd) {
+        (word, Some(path)) if word == "record" => Some(MetaCommand::Record(path)),
+        (word, None) if word == "stop-record" => Some(MetaCommand::StopRecord),
+        (word, Some(args)) if word == "replay" => Some(match args.strip_prefix("--fast ") {
+            Some(path) => MetaCommand::Replay {
+                path: path.trim(),
+                fast: true,
+            },
+            None => MetaCommand::Replay {
+                path: args,
+                fast: false,
+            },
+        }),
+        (word, interval) if word == "watch" => Some(MetaCommand::Watch(interval)),
+        (word, Some(payload)) if word == "raw" => Some(MetaCommand::Raw(payload)),
+        _ => None,
+    }
+}
 
-        Ok(response)
+/// Handles a console line: meta-commands here, everything else through
+/// [`handle_command`], recording it first if a recording is running.
+fn handle_line(
+    client: &mut SmartSocketClient<ClientStream>,
+    cmd: &str,
+    session: &mut Session,
+    lines: &Receiver<String>,
+    shutdown: &Shutdown,
+) {
+    match parse_meta_command(cmd) {
+        Some(MetaCommand::Record(path)) => start_recording(session, path),
+        Some(MetaCommand::StopRecord) => stop_recording(session),
+        Some(MetaCommand::Replay { path, fast }) => replay(client, path, fast, session, shutdown),
+        Some(MetaCommand::Watch(interval)) => watch(client, interval, session, lines, shutdown),
+        Some(MetaCommand::Raw(payload)) => send_raw(client, payload, session),
+        None => {
+            if !cmd.is_empty() {
+                record(session, cmd);
+            }
+            handle_command(client, cmd, session);
+        }
     }
+}
 
-    fn turn_on(&mut self) -> Result<Response, ProtocolError> {
-        self.send_command(Command::TurnOn)
+/// Sends `payload` unchanged and prints the reply as it came, without parsing it.
+fn send_raw(client: &mut SmartSocketClient<ClientStream>, payload: &str, session: &Session) {
+    if !session.expert {
+        session.warn(messages::get("raw.expert_only", session.locale));
+        return;
     }
+    match client.send_raw(payload) {
+        Ok(reply) => println!("{}", reply),
+        Err(e) => session.error(&e),
+    }
+}
 
-    fn turn_off(&mut self) -> Result<Response, ProtocolError> {
-        self.send_command(Command::TurnOff)
+fn start_recording(session: &mut Session, path: &str) {
+    if let Some((recording, _)) = &session.recording {
+        session.warn(&messages::format(
+            "record.busy",
+            session.locale,
+            &[("path", &recording.display())],
+        ));
+        return;
+    }
+    match File::create(path).and_then(|file| Recorder::new(file, Instant::now())) {
+        Ok(recorder) => {
+            println!(
+                "{}",
+                messages::format("record.started", session.locale, &[("path", &path)])
+            );
+            session.recording = Some((PathBuf::from(path), recorder));
+        }
+        Err(e) => session.warn(&messages::format(
+            "record.failed",
+            session.locale,
+            &[("path", &path), ("error", &e)],
+        )),
     }
+}
 
-    fn get_status(&mut self) -> Result<Response, ProtocolError> {
-        self.send_command(Command::GetStatus)
+fn record(session: &mut Session, cmd: &str) {
+    let Some((path, recorder)) = &mut session.recording else {
+        return;
+    };
+    if let Err(e) = recorder.record(cmd, Instant::now()) {
+        let text = messages::format(
+            "record.failed",
+            session.locale,
+            &[("path", &path.display()), ("error", &e)],
+        );
+        session.recording = None;
+        session.warn(&text);
     }
+}
 
-    fn get_info(&mut self) -> Result<Response, ProtocolError> {
-        self.send_command(Command::GetInfo)
+fn stop_recording(session: &mut Session) {
+    let Some((path, recorder)) = session.recording.take() else {
+        session.warn(messages::get("record.idle", session.locale));
+        return;
+    };
+    match recorder.finish() {
+        Ok(_) => println!(
+            "{}",
+            messages::format("record.saved", session.locale, &[("path", &path.display())])
+        ),
+        Err(e) => session.warn(&messages::format(
+            "record.failed",
+            session.locale,
+            &[("path", &path.display()), ("error", &e)],
+        )),
     }
+}
 
-    pub fn close(&mut self) -> Result<(), ProtocolError> {
-        if self.connected {
-            self.log("Closing connection...");
-            self.stream.shutdown(Shutdown::Both).map_err(|e| {
-                self.log(&format!("Failed to close connection: {}", e));
-                ProtocolError::ConnectionError(format!("Failed to close connection: {}", e))
-            })?;
-            self.connected = false;
-            self.log("Connection closed successfully");
+/// Runs the script at `path`, pausing between commands as recorded unless
+/// `fast`; a shutdown request ends it early.
+fn replay(
+    client: &mut SmartSocketClient<ClientStream>,
+    path: &str,
+    fast: bool,
+    session: &mut Session,
+    shutdown: &Shutdown,
+) {
+    let steps = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| script::parse(&text))
+    {
+        Ok(steps) => steps,
+        Err(e) => {
+            session.warn(&messages::format(
+                "replay.failed",
+                session.locale,
+                &[("path", &path), ("error", &e)],
+            ));
+            return;
+        }
+    };
+    for step in steps {
+        if shutdown.is_requested() {
+            break;
+        }
+        match step {
+            Step::Wait(delay) if !fast => {
+                shutdown.wait_timeout(delay);
+            }
+            Step::Wait(_) => {}
+            Step::Command(cmd) => {
+                println!(
+                    "{}",
+                    session.style.paint(Color::Bold, &format!("> {}", cmd))
+                );
+                handle_command(client, &cmd, session);
+            }
         }
-        Ok(())
     }
 }
 
-impl<T: Stream> Drop for SmartSocketClient<T> {
-    fn drop(&mut self) {
-        let _ = self.close();
+/// Redraws the status dashboard every `interval` (by default every second)
+/// until a line is entered, Ctrl+C or the end of input.
+fn watch(
+    client: &mut SmartSocketClient<ClientStream>,
+    interval: Option<&str>,
+    session: &mut Session,
+    lines: &Receiver<String>,
+    shutdown: &Shutdown,
+) {
+    let interval = match interval.map(dashboard::parse_interval) {
+        None => dashboard::DEFAULT_INTERVAL,
+        Some(Some(interval)) => interval,
+        Some(None) => {
+            session.warn(&messages::format(
+                "watch.invalid_interval",
+                session.locale,
+                &[("min", &dashboard::MIN_INTERVAL.as_millis())],
+            ));
+            return;
+        }
+    };
+    let mut state = WatchState::new(interval, session.device_name.clone());
+    session.watching.store(true, Ordering::SeqCst);
+    let stopped = || !session.watching.load(Ordering::SeqCst) || shutdown.is_requested();
+    while !stopped() {
+        let poll = client.get_status().and_then(SocketStatus::from_response);
+        state.observe(poll, SystemTime::now());
+        println!(
+            "{}{}",
+            session.style.clear_screen(),
+            dashboard::render(&state, SystemTime::now(), session.locale, session.style)
+        );
+
+        let next_poll = Instant::now() + interval;
+        loop {
+            let left = next_poll.saturating_duration_since(Instant::now());
+            if left.is_zero() || stopped() {
+                break;
+            }
+            match lines.recv_timeout(left.min(INPUT_POLL)) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                // Any line, even an empty one, ends the watch.
+                Ok(_) | Err(RecvTimeoutError::Disconnected) => {
+                    session.watching.store(false, Ordering::SeqCst);
+                }
+            }
+        }
     }
+    session.watching.store(false, Ordering::SeqCst);
 }
 
-fn print_help() {
-    println!("\nAvailable commands:");
-    println!("on     - Turn the socket on");
-    println!("off    - Turn the socket off");
-    println!("status - Get socket status");
-    println!("info   - Get socket info");
-    println!("help   - Show this help");
-    println!("exit   - Close connection and exit");
-}
+fn handle_command(client: &mut SmartSocketClient<ClientStream>, cmd: &str, session: &mut Session) {
+    if cmd.contains(';') {
+        handle_batch(client, cmd, session);
+        return;
+    }
 
-fn handle_command(client: &mut SmartSocketClient<TcpStream>, cmd: &str) {
-    let result = match cmd {
-        "on" => client.turn_on(),
-        "off" => client.turn_off(),
-        "status" => client.get_status(),
-        "info" => client.get_info(),
-        "help" => {
-            print_help();
+    let (word, argument) = split_command(cmd);
+    let result = match (word.as_str(), argument) {
+        ("on", None) => client.turn_on(),
+        ("off", None) => client.turn_off(),
+        ("status", None) => client.get_status(),
+        ("info", None) => client.get_info().map(Response::Info),
+        ("stats", None) => client.get_stats().map(Response::Stats),
+        ("reset", None) => client.reset_trip(),
+        ("lock", None) => client.lock(None),
+        ("lock", Some(minutes)) => match minutes.parse::<u32>() {
+            Ok(minutes) if LOCK_MINUTES.contains(&minutes) => client.lock(Some(minutes)),
+            _ => {
+                session.warn(&messages::format(
+                    "invalid_lock",
+                    session.locale,
+                    &[("min", LOCK_MINUTES.start()), ("max", LOCK_MINUTES.end())],
+                ));
+                return;
+            }
+        },
+        ("unlock", None) => client.unlock(),
+        ("rename", Some(name)) => {
+            if let Err(e) = validate_device_name(name) {
+                session.warn(&messages::format(
+                    "invalid_name",
+                    session.locale,
+                    &[("error", &e)],
+                ));
+                return;
+            }
+            let result = client.set_name(name);
+            if let Ok(Response::Ok(_)) = result {
+                session.device_name = Some(name.to_string());
+            }
+            result
+        }
+        ("level", Some(level)) => match level.parse::<u8>() {
+            Ok(level) if level <= 100 => client.set_level(level),
+            _ => {
+                session.warn(messages::get("invalid_level", session.locale));
+                return;
+            }
+        },
+        ("pulse", Some(millis)) => match millis.parse::<u64>() {
+            Ok(millis) if PULSE_MILLIS.contains(&millis) => client.pulse(millis),
+            _ => {
+                session.warn(&messages::format(
+                    "invalid_pulse",
+                    session.locale,
+                    &[("min", PULSE_MILLIS.start()), ("max", PULSE_MILLIS.end())],
+                ));
+                return;
+            }
+        },
+        ("metrics", None) => {
+            if client.metrics().is_empty() {
+                println!("{}", messages::get("metrics.empty", session.locale));
+            } else {
+                println!("{}", client.metrics());
+            }
+            return;
+        }
+        ("help", None) => {
+            print_help(session.locale, session.expert);
             return;
         }
         _ => {
-            println!("Unknown command. Type 'help' for available commands.");
+            session.warn(messages::get("unknown_command", session.locale));
             return;
         }
     };
 
     match result {
-        Ok(response) => println!("Response: {}", format_response(&response)),
-        Err(e) => eprintln!("Error: {}", e),
+        Ok(response) => {
+            remember_name(session, &response);
+            let text = messages::format(
+                "response",
+                session.locale,
+                &[("response", &format_response(&response, session))],
+            );
+            session.report(&response, &text);
+        }
+        Err(e) => session.error(&e),
+    }
+}
+
+fn remember_name(session: &mut Session, response: &Response) {
+    if let Response::Info(info) = response {
+        session.device_name = Some(info.name.clone());
     }
 }
 
-fn format_response(response: &Response) -> String {
+fn format_response(response: &Response, session: &Session) -> String {
+    let locale = session.locale;
+    let style = session.style;
     match response {
-        Response::Ok(msg) => msg.clone(),
-        Response::Status { is_on, power } => {
-            format!(
-                "Socket is {}, power consumption: {}W",
-                if *is_on { "ON" } else { "OFF" },
-                power
-            )
-        }
-        Response::Info(info) => info.clone(),
-        Response::Error(err) => format!("Error: {}", err),
+        Response::Ok(msg) => style.paint(Color::Green, msg.as_deref().unwrap_or("OK")),
+        Response::Status { .. } => match SocketStatus::from_response(response.clone()) {
+            Ok(status) => format_status(&status, session.device_name.as_deref(), locale, style),
+            Err(e) => messages::format("response", locale, &[("response", &e)]),
+        },
+        Response::Info(info) => format_info(info, locale),
+        Response::Stats(stats) => messages::format(
+            "stats",
+            locale,
+            &[
+                ("uptime", &stats.uptime),
+                ("connections", &stats.connections),
+                ("active", &stats.active),
+                ("commands", &stats.commands),
+                ("errors", &stats.errors),
+                ("switches", &stats.switches),
+                ("on_secs", &stats.on_secs),
+            ],
+        ),
+        Response::Level(level) => messages::format("level", locale, &[("level", level)]),
+        Response::Pong => messages::get("pong", locale).to_string(),
+        Response::Error(err) => style.paint(
+            Color::Red,
+            &messages::format("error", locale, &[("error", err)]),
+        ),
+        other => messages::format("response", locale, &[("response", other)]),
     }
 }
 
-fn main() {
-    let config = ClientConfig {
-        read_timeout: Duration::from_secs(10),
-        write_timeout: Duration::from_secs(10),
-        address: "127.0.0.1:8080".to_string(),
+/// Renders STATUS as an aligned State / Power (/ Lock) (/ Name) block.
+fn format_status(
+    status: &SocketStatus,
+    name: Option<&str>,
+    locale: Locale,
+    style: Style,
+) -> String {
+    let rows = status_rows(status, name, locale, style);
+    format!("\n{}", style::columns(&rows))
+}
+
+/// The State / Power (/ Lock) (/ Name) rows of a status block, shared with the
+/// `watch` dashboard.
+fn status_rows(
+    status: &SocketStatus,
+    name: Option<&str>,
+    locale: Locale,
+    style: Style,
+) -> Vec<(&'static str, String)> {
+    let state = if status.is_on {
+        style.paint(Color::Green, messages::get("state.on", locale))
+    } else if status.tripped {
+        style.paint(Color::Red, messages::get("state.tripped", locale))
+    } else {
+        style.paint(Color::Red, messages::get("state.off", locale))
     };
+    let mut rows = vec![
+        (messages::get("label.state", locale), state),
+        (
+            messages::get("label.power", locale),
+            messages::format("power_value", locale, &[("power", &status.power)]),
+        ),
+    ];
+    if status.locked {
+        rows.push((
+            messages::get("label.lock", locale),
+            style.paint(Color::Yellow, messages::get("state.locked", locale)),
+        ));
+    }
+    if let Some(name) = name {
+        rows.push((messages::get("label.name", locale), name.to_string()));
+    }
+    rows
+}
 
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-
-    ctrlc::set_handler(move || {
-        println!("\nShutdown signal received, closing connection...");
-        r.store(false, Ordering::SeqCst);
-        std::process::exit(0);
-    })
-    .expect("Error setting Ctrl-C handler");
-
-    match SmartSocketClient::with_config(config) {
-        Ok(mut client) => {
-            println!("Connected to smart socket server");
-            print_help();
-
-            let mut input = String::new();
-            while running.load(Ordering::SeqCst) {
-                print!("\nEnter command > ");
-                io::stdout().flush().unwrap();
-                input.clear();
-
-                if io::stdin().read_line(&mut input).is_err() {
-                    continue;
-                }
+fn format_info(info: &DeviceInfo, locale: Locale) -> String {
+    let mut text = messages::format(
+        "info",
+        locale,
+        &[
+            ("name", &info.name),
+            ("power", &info.power),
+            ("firmware", &info.firmware),
+            ("uptime", &info.uptime),
+        ],
+    );
+    if !info.build.is_empty() {
+        text.push_str(&messages::format(
+            "info.build",
+            locale,
+            &[("build", &info.build)],
+        ));
+    }
+    if !info.location.is_empty() {
+        text.push_str(&messages::format(
+            "info.location",
+            locale,
+            &[("location", &info.location)],
+        ));
+    }
+    if !info.addresses.is_empty() {
+        text.push_str(&messages::format(
+            "info.addresses",
+            locale,
+            &[("addresses", &info.addresses.join(", "))],
+        ));
+    }
+    if info.read_only {
+        text.push_str(messages::get("info.read_only", locale));
+    }
+    text
+}
 
-                let cmd = input.trim();
-                if cmd == "exit" || !running.load(Ordering::SeqCst) {
-                    break;
-                }
+/// One line per beacon: name, sender, state, power, uptime and the last error.
+fn format_beacon(from: SocketAddr, beacon: &Beacon, locale: Locale) -> String {
+    let state = messages::get(
+        if beacon.is_on {
+            "state.on"
+        } else {
+            "state.off"
+        },
+        locale,
+    );
+    let mut text = messages::format(
+        "beacon",
+        locale,
+        &[
+            ("name", &beacon.name),
+            ("from", &from),
+            ("state", &state),
+            (
+                "power",
+                &messages::format("power_value", locale, &[("power", &beacon.power)]),
+            ),
+            ("uptime", &beacon.uptime_secs),
+        ],
+    );
+    if let Some(error) = &beacon.last_error {
+        text.push_str(&messages::format(
+            "beacon.last_error",
+            locale,
+            &[("error", error)],
+        ));
+    }
+    text
+}
 
-                handle_command(&mut client, cmd);
-            }
+/// Where `--listen-beacons` listens when given no address.
+const DEFAULT_BEACON_LISTEN: &str = "0.0.0.0:9999";
+
+/// Binds `address` for beacons, joining the group first if it is a multicast one.
+fn bind_beacon_socket(address: SocketAddr) -> io::Result<UdpSocket> {
+    match address {
+        SocketAddr::V4(v4) if v4.ip().is_multicast() => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, v4.port()))?;
+            socket.join_multicast_v4(v4.ip(), &Ipv4Addr::UNSPECIFIED)?;
+            Ok(socket)
+        }
+        SocketAddr::V6(v6) if v6.ip().is_multicast() => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, v6.port()))?;
+            socket.join_multicast_v6(v6.ip(), 0)?;
+            Ok(socket)
+        }
+        _ => UdpSocket::bind(address),
+    }
+}
 
-            println!("Closing connection...");
-            if let Err(e) = client.close() {
-                eprintln!("Error during shutdown: {}", e);
+/// Prints every beacon that arrives on `address` until Ctrl+C. Returns false
+/// if the address cannot be listened on.
+fn listen_beacons(address: &str, session: &Session, shutdown: &Shutdown) -> bool {
+    let socket = match address
+        .parse::<SocketAddr>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        .and_then(bind_beacon_socket)
+    {
+        Ok(socket) => socket,
+        Err(e) => {
+            session.error(&e);
+            return false;
+        }
+    };
+    println!(
+        "{}",
+        messages::format("beacon.listening", session.locale, &[("address", &address)])
+    );
+    while !shutdown.is_requested() {
+        match Beacon::listen(&socket, INPUT_POLL) {
+            Ok(Some((from, beacon))) => {
+                println!("{}", format_beacon(from, &beacon, session.locale))
+            }
+            Ok(None) => {}
+            Err(e) => {
+                session.error(&e);
+                return false;
             }
         }
-        Err(e) => eprintln!("Failed to connect: {}", e),
     }
+    true
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// How often the REPL checks for Ctrl+C while waiting for input.
+const INPUT_POLL: Duration = Duration::from_millis(100);
 
-    struct MockTcpStream {
-        read_data: Vec<u8>,
-        write_data: Vec<u8>,
+/// Reads `input` on its own thread, so a blocked read cannot keep the REPL from
+/// noticing Ctrl+C; on Windows a console read only returns once Enter is pressed.
+/// The channel closes at the end of input.
+/// Sends `command` to every socket in `addresses` at once and prints one line
+/// per device in the order given. Returns whether every device accepted it.
+fn broadcast(
+    client: ClientConfig,
+    addresses: &str,
+    command: Command,
+    parallelism: Option<usize>,
+    session: &Session,
+) -> bool {
+    let mut config = FleetConfig {
+        client,
+        ..Default::default()
+    };
+    if let Some(parallelism) = parallelism {
+        config.parallelism = parallelism;
     }
+    let fleet = match address::parse_list(addresses)
+        .and_then(|addresses| SmartSocketFleet::new(config, addresses))
+    {
+        Ok(fleet) => fleet,
+        Err(e) => {
+            session.error(&e);
+            return false;
+        }
+    };
 
-    impl Stream for MockTcpStream {}
-
-    impl Read for MockTcpStream {
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            let size = self.read_data.len();
-            buf[..size].copy_from_slice(&self.read_data);
-            Ok(size)
+    let outcomes = fleet.broadcast(command);
+    let mut failed = 0;
+    for (device, outcome) in &outcomes {
+        match outcome {
+            Ok(response) => {
+                if matches!(response, Response::Error(_)) {
+                    failed += 1;
+                }
+                let text = format!("{}: {}", device, format_response(response, session));
+                session.report(response, &text);
+            }
+            Err(e) => {
+                failed += 1;
+                let text = messages::format("error", session.locale, &[("error", e)]);
+                eprintln!("{}: {}", device, session.style.paint(Color::Red, &text));
+            }
         }
     }
+    if failed > 0 {
+        session.warn(&messages::format(
+            "all.failed",
+            session.locale,
+            &[("failed", &failed), ("total", &outcomes.len())],
+        ));
+    }
+    failed == 0
+}
 
-    impl Write for MockTcpStream {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            self.write_data.extend_from_slice(buf);
-            Ok(buf.len())
+fn spawn_line_reader<R: BufRead + Send + 'static>(input: R) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input.lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
         }
+    });
+    rx
+}
 
-        fn flush(&mut self) -> io::Result<()> {
-            Ok(())
+/// The next line typed, or `None` at the end of input or once shutdown is requested.
+fn next_line(lines: &Receiver<String>, shutdown: &Shutdown) -> Option<String> {
+    while !shutdown.is_requested() {
+        match lines.recv_timeout(INPUT_POLL) {
+            Ok(line) => return Some(line),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return None,
         }
     }
+    None
+}
 
-    #[test]
-    fn test_turn_on() {
-        let mock_stream = MockTcpStream {
-            read_data: b"OK:Socket turned on".to_vec(),
-            write_data: Vec::new(),
+/// Shows the prompt and passes each line to `handle` until `exit`, the end of
+/// input or a shutdown request.
+fn run_repl(
+    lines: &Receiver<String>,
+    shutdown: &Shutdown,
+    mut prompt: impl FnMut(),
+    mut handle: impl FnMut(&str),
+) {
+    loop {
+        prompt();
+        let Some(line) = next_line(lines, shutdown) else {
+            break;
         };
+        let cmd = line.trim();
+        if cmd.eq_ignore_ascii_case("exit") {
+            break;
+        }
+        handle(cmd);
+    }
+}
 
-        let mut client = SmartSocketClient {
-            stream: mock_stream,
-            connected: true,
+fn main() {
+    let mut builder = ClientConfig::builder()
+        .read_timeout(Duration::from_secs(10))
+        .write_timeout(Duration::from_secs(10))
+        .locale(
+            std::env::var("SMART_SOCKET_LOCALE")
+                .ok()
+                .and_then(|locale| locale.parse().ok())
+                .unwrap_or_default(),
+        );
+    if let Ok(token) = std::env::var("SMART_SOCKET_TOKEN") {
+        builder = builder.auth_token(token);
+    }
+    if let Ok(psk) = std::env::var("SMART_SOCKET_PSK") {
+        builder = builder.psk(&psk);
+    }
+    if let Some(secs) = std::env::var("SMART_SOCKET_KEEPALIVE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+    {
+        builder = builder.keepalive_interval(Duration::from_secs(secs));
+    }
+    if let Ok(proxy) = std::env::var("SMART_SOCKET_SOCKS5_PROXY") {
+        match proxy.parse() {
+            Ok(proxy) => builder = builder.socks5_proxy(proxy),
+            Err(e) => {
+                eprintln!("SMART_SOCKET_SOCKS5_PROXY: {}", e);
+                std::process::exit(EXIT_STARTUP_FAILED);
+            }
+        }
+    }
+    if let Ok(format) = std::env::var("SMART_SOCKET_WIRE_FORMAT") {
+        match format.parse() {
+            Ok(format) => builder = builder.wire_format(format),
+            Err(e) => {
+                eprintln!("SMART_SOCKET_WIRE_FORMAT: {}", e);
+                std::process::exit(EXIT_STARTUP_FAILED);
+            }
+        }
+    }
+    #[cfg(feature = "tls")]
+    if let Some(ca_cert) = std::env::var_os("SMART_SOCKET_TLS_CA") {
+        builder = builder.tls(TlsClientConfig {
+            ca_cert: ca_cert.into(),
+            server_name: std::env::var("SMART_SOCKET_TLS_NAME")
+                .unwrap_or_else(|_| "localhost".to_string()),
+        });
+    }
+    let config = match builder.build() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_STARTUP_FAILED);
+        }
+    };
+
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
+    let watching = Arc::new(AtomicBool::new(false));
+    let w = watching.clone();
+
+    if let Err(e) = ctrlc::set_handler(move || {
+        // During `watch`, Ctrl+C only returns to the prompt.
+        if w.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        println!("\nShutdown signal received, stopping client...");
+        s.interrupt(GRACE_PERIOD);
+    }) {
+        eprintln!("Error setting Ctrl-C handler: {}", e);
+        std::process::exit(EXIT_STARTUP_FAILED);
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    let mut session = Session {
+        locale: config.locale,
+        style: Style::detect(args.iter().any(|arg| arg == "--no-color")),
+        device_name: None,
+        recording: None,
+        watching,
+        expert: args.iter().any(|arg| arg == "--expert"),
+    };
+
+    // `--all <addresses> <command>` sends one command to many sockets and exits.
+    if let Some(i) = args.iter().position(|arg| arg == "--all") {
+        let addresses = args.get(i + 1);
+        let command = args.get(i + 2).and_then(|cmd| parse_protocol_command(cmd));
+        let parallelism = args
+            .iter()
+            .position(|arg| arg == "--parallelism")
+            .map(|i| args.get(i + 1).and_then(|n| n.parse().ok()));
+        let (Some(addresses), Some(command), None | Some(Some(_))) =
+            (addresses, command, parallelism)
+        else {
+            session.warn(messages::get("all.usage", session.locale));
+            std::process::exit(EXIT_STARTUP_FAILED);
         };
+        let succeeded = broadcast(config, addresses, command, parallelism.flatten(), &session);
+        std::process::exit(if succeeded { 0 } else { 1 });
+    }
 
-        let response = client.turn_on().unwrap();
-        match response {
-            Response::Ok(msg) => assert_eq!(msg, "Socket turned on"),
-            _ => panic!("Unexpected response type"),
+    // `--listen-beacons [<address>]` prints the status beacons servers send and
+    // exits on Ctrl+C; see `SMART_SOCKET_BEACON_ADDRESS` on the server.
+    if let Some(i) = args.iter().position(|arg| arg == "--listen-beacons") {
+        let address = args
+            .get(i + 1)
+            .filter(|arg| !arg.starts_with('-'))
+            .cloned()
+            .or_else(|| std::env::var("SMART_SOCKET_BEACON_ADDRESS").ok())
+            .unwrap_or_else(|| DEFAULT_BEACON_LISTEN.to_string());
+        let succeeded = listen_beacons(&address, &session, &shutdown);
+        std::process::exit(if succeeded { 0 } else { EXIT_STARTUP_FAILED });
+    }
+
+    let mut client = match SmartSocketClient::with_config(config) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                session
+                    .style
+                    .paint(Color::Red, &format!("Failed to connect: {}", e))
+            );
+            std::process::exit(EXIT_STARTUP_FAILED);
         }
+    };
+    println!(
+        "{}",
+        session
+            .style
+            .paint(Color::Green, "Connected to smart socket server")
+    );
+    if let Some(identity) = client.server_identity() {
+        println!("{}", format_info(identity, session.locale));
+        session.device_name = Some(identity.name.clone());
     }
 
+    // `--replay <file>` runs a script instead of the console.
+    if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--replay")
+        .and_then(|i| args.get(i + 1))
+    {
+        let fast = args.iter().any(|arg| arg == "--fast");
+        replay(&mut client, path, fast, &mut session, &shutdown);
+    } else {
+        print_help(session.locale, session.expert);
+        let lines = spawn_line_reader(io::BufReader::new(io::stdin()));
+        let prompt = session.style.paint(Color::Bold, "Enter command > ");
+        run_repl(
+            &lines,
+            &shutdown,
+            || {
+                print!("\n{}", prompt);
+                let _ = io::stdout().flush();
+            },
+            |cmd| handle_line(&mut client, cmd, &mut session, &lines, &shutdown),
+        );
+    }
+    // Also after Ctrl+C, which ends the console like `exit`.
+    if session.recording.is_some() {
+        stop_recording(&mut session);
+    }
+
+    println!("Closing connection...");
+    if let Err(e) = client.close() {
+        eprintln!("Error during shutdown: {}", e);
+    }
+    println!("Client shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
     #[test]
-    fn test_turn_off() {
-        let mock_stream = MockTcpStream {
-            read_data: b"OK:Socket turned off".to_vec(),
-            write_data: Vec::new(),
+    fn test_format_info() {
+        let info = DeviceInfo {
+            name: "Kitchen Socket".to_string(),
+            power: 3500,
+            firmware: "0.1.0".to_string(),
+            uptime: 12,
+            ..Default::default()
         };
+        let text = format_info(&info, Locale::En);
+        assert!(text.contains("Name:     Kitchen Socket"));
+        assert!(text.contains("Power:    3500W"));
+        assert!(!text.contains("Address"));
 
-        let mut client = SmartSocketClient {
-            stream: mock_stream,
-            connected: true,
+        let info = DeviceInfo {
+            build: "1a2b3c4".to_string(),
+            location: "Hall".to_string(),
+            addresses: vec!["127.0.0.1:8081".to_string()],
+            ..info
         };
+        assert!(format_info(&info, Locale::En)
+            .ends_with("\n  Build:    1a2b3c4\n  Location: Hall\n  Address:  127.0.0.1:8081"));
 
-        let response = client.turn_off().unwrap();
-        match response {
-            Response::Ok(msg) => assert_eq!(msg, "Socket turned off"),
-            _ => panic!("Unexpected response type"),
+        let info = DeviceInfo {
+            read_only: true,
+            ..info
+        };
+        assert!(
+            format_info(&info, Locale::En).ends_with("\n  Mode:     read-only, switching refused")
+        );
+    }
+
+    fn session(locale: Locale, device_name: Option<&str>) -> Session {
+        Session {
+            locale,
+            style: Style::new(true),
+            device_name: device_name.map(str::to_string),
+            recording: None,
+            watching: Arc::default(),
+            expert: false,
         }
     }
 
     #[test]
-    fn test_get_status() {
-        let mock_stream = MockTcpStream {
-            read_data: b"STATUS:ON:100".to_vec(),
-            write_data: Vec::new(),
+    fn test_format_beacon() {
+        let from = SocketAddr::from(([192, 168, 1, 20], 40000));
+        let mut beacon = Beacon {
+            name: "Kitchen".to_string(),
+            is_on: true,
+            power: 1500,
+            uptime_secs: 90,
+            last_error: None,
         };
+        assert_eq!(
+            format_beacon(from, &beacon, Locale::En),
+            "Kitchen (192.168.1.20:40000): ON, 1500W, up 90s"
+        );
+        beacon.last_error = Some("E_TRIPPED:Socket is tripped".to_string());
+        assert!(format_beacon(from, &beacon, Locale::En)
+            .ends_with("; last error: E_TRIPPED:Socket is tripped"));
+    }
 
-        let mut client = SmartSocketClient {
-            stream: mock_stream,
-            connected: true,
+    #[test]
+    fn test_format_status_block() {
+        let status = Response::Status {
+            is_on: true,
+            power: 100,
+            tripped: false,
+            locked: false,
         };
+        assert_eq!(
+            style::strip(&format_response(&status, &session(Locale::En, None))),
+            "\n  State: ON\n  Power: 100W"
+        );
+        assert_eq!(
+            style::strip(&format_response(
+                &status,
+                &session(Locale::En, Some("Kitchen"))
+            )),
+            "\n  State: ON\n  Power: 100W\n  Name:  Kitchen"
+        );
+        assert_eq!(
+            style::strip(&format_response(&status, &session(Locale::Ru, None))),
+            "\n  Состояние: ВКЛЮЧЕНА\n  Мощность:  100 Вт"
+        );
 
-        let response = client.get_status().unwrap();
-        match response {
-            Response::Status { is_on, power } => {
-                assert!(is_on);
-                assert_eq!(power, 100);
-            }
-            _ => panic!("Unexpected response type"),
-        }
+        let tripped = Response::Status {
+            is_on: false,
+            power: 0,
+            tripped: true,
+            locked: false,
+        };
+        assert_eq!(
+            style::strip(&format_response(&tripped, &session(Locale::En, None))),
+            "\n  State: OFF (tripped on overload)\n  Power: 0W"
+        );
+
+        let locked = Response::Status {
+            is_on: true,
+            power: 100,
+            tripped: false,
+            locked: true,
+        };
+        assert_eq!(
+            style::strip(&format_response(&locked, &session(Locale::En, None))),
+            "\n  State: ON\n  Power: 100W\n  Lock:  ON, switching refused"
+        );
     }
 
     #[test]
-    fn test_get_info() {
-        let mock_stream = MockTcpStream {
-            read_data: b"INFO:Kitchen Socket, Power: 100W".to_vec(),
-            write_data: Vec::new(),
-        };
+    fn test_format_response_colors() {
+        let session = session(Locale::Ru, None);
+        let error = format_response(&Response::Error("E_X:boom".to_string()), &session);
+        assert!(error.starts_with("\x1b[31m"));
+        assert_eq!(style::strip(&error), "Ошибка: E_X:boom");
+
+        let ok = format_response(&Response::ok("Socket turned on"), &session);
+        assert!(ok.starts_with("\x1b[32m"));
 
-        let mut client = SmartSocketClient {
-            stream: mock_stream,
-            connected: true,
+        let plain = Session {
+            style: Style::new(false),
+            ..session
         };
+        assert_eq!(
+            format_response(&Response::ok("Socket turned on"), &plain),
+            "Socket turned on"
+        );
+        assert_eq!(format_response(&Response::Ok(None), &plain), "OK");
+    }
+
+    #[test]
+    fn test_next_line_stops_on_shutdown() {
+        let (tx, lines) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        tx.send("status".to_string()).unwrap();
+        assert_eq!(next_line(&lines, &shutdown).as_deref(), Some("status"));
+
+        let s = shutdown.clone();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            s.request();
+        });
+        let started = std::time::Instant::now();
+        assert_eq!(next_line(&lines, &shutdown), None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        interrupter.join().unwrap();
+
+        // Lines still queued are ignored once shutdown is requested.
+        tx.send("on".to_string()).unwrap();
+        assert_eq!(next_line(&lines, &shutdown), None);
+    }
+
+    /// Runs the REPL over `input`, returning the lines handled and how often the
+    /// prompt was shown. Handling `on` requests shutdown.
+    fn repl(input: &'static str, shutdown: &Shutdown) -> (Vec<String>, usize) {
+        let lines = spawn_line_reader(io::Cursor::new(input));
+        let mut handled = Vec::new();
+        let mut prompts = 0;
+        run_repl(
+            &lines,
+            shutdown,
+            || prompts += 1,
+            |cmd| {
+                handled.push(cmd.to_string());
+                if cmd == "on" {
+                    shutdown.request();
+                }
+            },
+        );
+        (handled, prompts)
+    }
+
+    #[test]
+    fn test_repl_handles_lines_until_exit() {
+        let (handled, prompts) = repl("status\n  info  \nEXIT\noff\n", &Shutdown::new());
+        assert_eq!(handled, ["status", "info"]);
+        assert_eq!(prompts, 3);
+
+        let (handled, prompts) = repl("status\nhelp", &Shutdown::new());
+        assert_eq!(handled, ["status", "help"]);
+        assert_eq!(prompts, 3);
+    }
+
+    #[test]
+    fn test_repl_stops_on_shutdown() {
+        let (handled, _) = repl("status\non\noff\n", &Shutdown::new());
+        assert_eq!(handled, ["status", "on"]);
+
+        // Requested before the first line arrives: nothing is handled.
+        let shutdown = Shutdown::new();
+        shutdown.request();
+        assert_eq!(repl("status\n", &shutdown), (vec![], 1));
+    }
+
+    #[test]
+    fn test_next_line_ends_with_input() {
+        let (tx, lines) = mpsc::channel::<String>();
+        drop(tx);
+        assert_eq!(next_line(&lines, &Shutdown::new()), None);
+    }
 
-        let response = client.get_info().unwrap();
-        match response {
-            Response::Info(info) => assert!(info.contains("Kitchen Socket")),
-            _ => panic!("Unexpected response type"),
+    #[test]
+    fn test_parse_protocol_command() {
+        assert!(matches!(
+            parse_protocol_command("on"),
+            Some(Command::TurnOn)
+        ));
+        assert!(matches!(
+            parse_protocol_command("status"),
+            Some(Command::GetStatus)
+        ));
+        assert!(matches!(
+            parse_protocol_command("Status"),
+            Some(Command::GetStatus)
+        ));
+        assert!(parse_protocol_command("help").is_none());
+    }
+
+    #[test]
+    fn test_parse_meta_command() {
+        assert_eq!(
+            parse_meta_command("Record  session.txt"),
+            Some(MetaCommand::Record("session.txt"))
+        );
+        assert_eq!(
+            parse_meta_command("stop-record"),
+            Some(MetaCommand::StopRecord)
+        );
+        assert_eq!(
+            parse_meta_command("replay bug 42.txt"),
+            Some(MetaCommand::Replay {
+                path: "bug 42.txt",
+                fast: false
+            })
+        );
+        assert_eq!(
+            parse_meta_command("replay --fast  bug.txt"),
+            Some(MetaCommand::Replay {
+                path: "bug.txt",
+                fast: true
+            })
+        );
+        assert_eq!(parse_meta_command("watch"), Some(MetaCommand::Watch(None)));
+        assert_eq!(
+            parse_meta_command("RAW VENDOR:x:1"),
+            Some(MetaCommand::Raw("VENDOR:x:1"))
+        );
+        assert_eq!(parse_meta_command("raw"), None);
+        assert_eq!(
+            parse_meta_command("WATCH 500ms"),
+            Some(MetaCommand::Watch(Some("500ms")))
+        );
+        // Everything else is a command to execute, and to record.
+        for cmd in ["record", "stop-record now", "on; status", "status"] {
+            assert_eq!(parse_meta_command(cmd), None, "{}", cmd);
         }
     }
+
+    #[test]
+    fn test_split_command_ignores_case_of_the_word_only() {
+        assert_eq!(split_command("STATUS"), ("status".to_string(), None));
+        assert_eq!(
+            split_command("Rename  Hall Lamp"),
+            ("rename".to_string(), Some("Hall Lamp"))
+        );
+        assert_eq!(split_command("LEVEL 40"), ("level".to_string(), Some("40")));
+    }
 }
@@ -0,0 +1,349 @@
+//! Catalog of human-readable client messages.
+//!
+//! Only text shown to people lives here; the wire protocol never depends on the locale.
+//! Placeholders are written as `{name}` and filled in by [`format`].
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Accepts bare language codes as well as POSIX-style values such as `ru_RU.UTF-8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s.split(['_', '-', '.']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Ok(Locale::En),
+            "ru" => Ok(Locale::Ru),
+            _ => Err(format!("Unsupported locale: {}", s)),
+        }
+    }
+}
+
+type Catalog = &'static [(&'static str, &'static str)];
+
+const EN: Catalog = &[
+    ("help.title", "\nAvailable commands:"),
+    ("help.on", "on     - Turn the socket on"),
+    ("help.off", "off    - Turn the socket off"),
+    ("help.status", "status - Get socket status"),
+    ("help.info", "info   - Get socket info"),
+    ("help.rename", "rename <name> - Rename the socket"),
+    ("help.stats", "stats  - Get server statistics"),
+    ("help.level", "level <0-100> - Set the dimmer level"),
+    (
+        "help.pulse",
+        "pulse <ms> - Turn the socket on for <ms> milliseconds",
+    ),
+    ("help.reset", "reset  - Clear an overload trip"),
+    (
+        "help.lock",
+        "lock [minutes] - Refuse switching until unlock, or for [minutes]",
+    ),
+    ("help.unlock", "unlock - Allow switching again"),
+    (
+        "help.metrics",
+        "metrics - Show command latency measured by this client",
+    ),
+    ("help.help", "help   - Show this help"),
+    (
+        "help.batch",
+        "a; b   - Send several of on/off/status/info in one pipelined batch",
+    ),
+    (
+        "help.record",
+        "record <file> - Save the commands that follow as a script",
+    ),
+    ("help.stop_record", "stop-record - Stop recording"),
+    (
+        "help.replay",
+        "replay [--fast] <file> - Run a script, keeping its pauses unless --fast",
+    ),
+    (
+        "help.watch",
+        "watch [interval] - Redraw the status every interval (default 1s) until Enter or Ctrl+C",
+    ),
+    ("help.exit", "exit   - Close connection and exit"),
+    (
+        "help.raw",
+        "raw <payload> - Send a frame exactly as typed and show the reply as received",
+    ),
+    ("raw.expert_only", "raw is only available with --expert"),
+    ("label.state", "State"),
+    ("label.power", "Power"),
+    ("label.name", "Name"),
+    ("label.lock", "Lock"),
+    ("power_value", "{power}W"),
+    ("level", "Level: {level}%"),
+    ("invalid_level", "Level must be a number from 0 to 100"),
+    (
+        "invalid_pulse",
+        "Pulse must be a number of milliseconds from {min} to {max}",
+    ),
+    (
+        "invalid_lock",
+        "Lock must be a number of minutes from {min} to {max}",
+    ),
+    ("state.on", "ON"),
+    ("state.off", "OFF"),
+    ("state.tripped", "OFF (tripped on overload)"),
+    ("state.locked", "ON, switching refused"),
+    (
+        "info",
+        "\n  Name:     {name}\n  Power:    {power}W\n  Firmware: {firmware}\n  Uptime:   {uptime}s",
+    ),
+    ("info.build", "\n  Build:    {build}"),
+    ("info.location", "\n  Location: {location}"),
+    ("info.addresses", "\n  Address:  {addresses}"),
+    ("info.read_only", "\n  Mode:     read-only, switching refused"),
+    (
+        "stats",
+        "\n  Uptime:      {uptime}s\n  Connections: {connections} ({active} active)\n  Commands:    {commands}\n  Errors:      {errors}\n  Switches:    {switches} ({on_secs}s on)",
+    ),
+    ("pong", "Pong"),
+    ("metrics.empty", "No commands sent yet"),
+    ("error", "Error: {error}"),
+    ("response", "Response: {response}"),
+    (
+        "unknown_command",
+        "Unknown command. Type 'help' for available commands.",
+    ),
+    ("invalid_name", "Invalid name: {error}"),
+    (
+        "batch_only",
+        "Only on, off, status and info can be batched.",
+    ),
+    (
+        "all.usage",
+        "Usage: --all <address>,<address>... on|off|status|info [--parallelism <n>]",
+    ),
+    ("all.failed", "{failed} of {total} devices failed"),
+    ("beacon.listening", "Listening for beacons on {address}"),
+    ("beacon", "{name} ({from}): {state}, {power}, up {uptime}s"),
+    ("beacon.last_error", "; last error: {error}"),
+    ("record.started", "Recording to {path}"),
+    ("record.saved", "Recording saved to {path}"),
+    (
+        "record.busy",
+        "Already recording to {path}; type stop-record first",
+    ),
+    ("record.idle", "Not recording"),
+    ("record.failed", "Cannot record to {path}: {error}"),
+    ("replay.failed", "Cannot replay {path}: {error}"),
+    ("watch.title", "Watching every {interval}; press Enter to stop"),
+    ("watch.waiting", "waiting for the first reply"),
+    ("watch.no_change", "none seen"),
+    ("watch.ago", "{secs}s ago"),
+    (
+        "watch.invalid_interval",
+        "Interval must look like 2, 2s or 500ms and be at least {min}ms",
+    ),
+    ("label.last_change", "Last change"),
+    ("label.errors", "Errors"),
+    ("label.last_error", "Last error"),
+];
+
+const RU: Catalog = &[
+    ("help.title", "\nДоступные команды:"),
+    ("help.on", "on     - Включить розетку"),
+    ("help.off", "off    - Выключить розетку"),
+    ("help.status", "status - Показать состояние розетки"),
+    ("help.info", "info   - Показать информацию о розетке"),
+    ("help.rename", "rename <name> - Переименовать розетку"),
+    ("help.stats", "stats  - Показать статистику сервера"),
+    ("help.level", "level <0-100> - Установить уровень диммера"),
+    (
+        "help.pulse",
+        "pulse <ms> - Включить розетку на <ms> миллисекунд",
+    ),
+    ("help.reset", "reset  - Сбросить срабатывание защиты от перегрузки"),
+    (
+        "help.lock",
+        "lock [minutes] - Запретить переключение до unlock или на [minutes] минут",
+    ),
+    ("help.unlock", "unlock - Снова разрешить переключение"),
+    (
+        "help.metrics",
+        "metrics - Показать задержку команд, измеренную клиентом",
+    ),
+    ("help.help", "help   - Показать эту справку"),
+    (
+        "help.batch",
+        "a; b   - Отправить несколько команд on/off/status/info одним пакетом",
+    ),
+    (
+        "help.record",
+        "record <file> - Сохранить следующие команды как сценарий",
+    ),
+    ("help.stop_record", "stop-record - Остановить запись"),
+    (
+        "help.replay",
+        "replay [--fast] <file> - Выполнить сценарий, соблюдая паузы, если не указан --fast",
+    ),
+    (
+        "help.watch",
+        "watch [interval] - Обновлять состояние с этим интервалом (по умолчанию 1s) до Enter или Ctrl+C",
+    ),
+    ("help.exit", "exit   - Закрыть соединение и выйти"),
+    (
+        "help.raw",
+        "raw <payload> - Отправить кадр как есть и показать ответ в том виде, в каком он пришёл",
+    ),
+    ("raw.expert_only", "raw доступна только с --expert"),
+    ("label.state", "Состояние"),
+    ("label.power", "Мощность"),
+    ("label.name", "Имя"),
+    ("label.lock", "Блокировка"),
+    ("power_value", "{power} Вт"),
+    ("level", "Уровень: {level}%"),
+    ("invalid_level", "Уровень должен быть числом от 0 до 100"),
+    (
+        "invalid_pulse",
+        "Длительность импульса должна быть от {min} до {max} мс",
+    ),
+    (
+        "invalid_lock",
+        "Блокировка задаётся в минутах, от {min} до {max}",
+    ),
+    ("state.on", "ВКЛЮЧЕНА"),
+    ("state.off", "ВЫКЛЮЧЕНА"),
+    ("state.tripped", "ВЫКЛЮЧЕНА (сработала защита от перегрузки)"),
+    ("state.locked", "ВКЛЮЧЕНА, переключение запрещено"),
+    (
+        "info",
+        "\n  Имя:      {name}\n  Мощность: {power} Вт\n  Прошивка: {firmware}\n  Аптайм:   {uptime} с",
+    ),
+    ("info.build", "\n  Сборка:   {build}"),
+    ("info.location", "\n  Место:    {location}"),
+    ("info.addresses", "\n  Адрес:    {addresses}"),
+    (
+        "info.read_only",
+        "\n  Режим:    только чтение, переключение запрещено",
+    ),
+    (
+        "stats",
+        "\n  Аптайм:      {uptime} с\n  Подключения: {connections} (активных: {active})\n  Команды:     {commands}\n  Ошибки:      {errors}\n  Циклы:       {switches} (включена {on_secs} с)",
+    ),
+    ("pong", "Понг"),
+    ("metrics.empty", "Команды ещё не отправлялись"),
+    ("error", "Ошибка: {error}"),
+    ("response", "Ответ: {response}"),
+    (
+        "unknown_command",
+        "Неизвестная команда. Введите 'help' для списка команд.",
+    ),
+    ("invalid_name", "Недопустимое имя: {error}"),
+    (
+        "batch_only",
+        "Пакетом можно отправлять только on, off, status и info.",
+    ),
+    (
+        "all.usage",
+        "Использование: --all <адрес>,<адрес>... on|off|status|info [--parallelism <n>]",
+    ),
+    ("all.failed", "Ошибка на {failed} из {total} устройств"),
+    ("beacon.listening", "Ожидание маяков на {address}"),
+    ("beacon", "{name} ({from}): {state}, {power}, работает {uptime} с"),
+    ("beacon.last_error", "; последняя ошибка: {error}"),
+    ("record.started", "Запись в {path}"),
+    ("record.saved", "Запись сохранена в {path}"),
+    (
+        "record.busy",
+        "Уже идёт запись в {path}; сначала введите stop-record",
+    ),
+    ("record.idle", "Запись не ведётся"),
+    ("record.failed", "Не удалось записать в {path}: {error}"),
+    ("replay.failed", "Не удалось выполнить {path}: {error}"),
+    ("watch.title", "Обновление каждые {interval}; нажмите Enter для выхода"),
+    ("watch.waiting", "ожидание первого ответа"),
+    ("watch.no_change", "не было"),
+    ("watch.ago", "{secs} с назад"),
+    (
+        "watch.invalid_interval",
+        "Интервал задаётся как 2, 2s или 500ms и должен быть не меньше {min} мс",
+    ),
+    ("label.last_change", "Последнее изменение"),
+    ("label.errors", "Ошибки"),
+    ("label.last_error", "Последняя ошибка"),
+];
+
+fn catalog(locale: Locale) -> Catalog {
+    match locale {
+        Locale::En => EN,
+        Locale::Ru => RU,
+    }
+}
+
+/// Looks `key` up in `primary`, then in the English catalog; unknown keys are returned as is.
+fn resolve(key: &'static str, primary: Catalog) -> &'static str {
+    [primary, EN]
+        .iter()
+        .find_map(|catalog| {
+            catalog
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, text)| *text)
+        })
+        .unwrap_or(key)
+}
+
+pub fn get(key: &'static str, locale: Locale) -> &'static str {
+    resolve(key, catalog(locale))
+}
+
+/// Looks up `key` and substitutes every `{name}` placeholder from `args`.
+pub fn format(key: &'static str, locale: Locale, args: &[(&str, &dyn fmt::Display)]) -> String {
+    interpolate(get(key, locale), args)
+}
+
+fn interpolate(template: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    args.iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_have_the_same_keys() {
+        let keys = |catalog: Catalog| catalog.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(EN), keys(RU));
+    }
+
+    #[test]
+    fn test_missing_key_falls_back_to_english() {
+        const PARTIAL: Catalog = &[("pong", "Понг")];
+        assert_eq!(resolve("pong", PARTIAL), "Понг");
+        assert_eq!(resolve("state.on", PARTIAL), "ON");
+        assert_eq!(resolve("no.such.key", PARTIAL), "no.such.key");
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let text = format("power_value", Locale::Ru, &[("power", &1500)]);
+        assert_eq!(text, "1500 Вт");
+
+        // Placeholders without a matching argument are left in place.
+        let text = format("info", Locale::En, &[("power", &0)]);
+        assert!(text.contains("Name:     {name}"));
+        assert!(text.contains("Power:    0W"));
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!("ru".parse(), Ok(Locale::Ru));
+        assert_eq!("ru_RU.UTF-8".parse(), Ok(Locale::Ru));
+        assert_eq!("EN-us".parse(), Ok(Locale::En));
+        assert!("de".parse::<Locale>().is_err());
+    }
+}
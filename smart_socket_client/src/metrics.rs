@@ -0,0 +1,271 @@
+//! Per-command latency, measured from the first byte written to the parsed response.
+//! Servers that report their own processing time also get it split into server
+//! and network time.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Upper bounds (exclusive) of all buckets but the last, which holds everything slower.
+pub const BUCKET_BOUNDS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(20),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+pub const BUCKET_LABELS: [&str; BUCKET_BOUNDS.len() + 1] =
+    ["<1ms", "<5ms", "<20ms", "<100ms", "<1s", ">=1s"];
+
+/// Index of the bucket `latency` falls into.
+pub fn bucket(latency: Duration) -> usize {
+    BUCKET_BOUNDS
+        .iter()
+        .position(|bound| latency < *bound)
+        .unwrap_or(BUCKET_BOUNDS.len())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKET_LABELS.len()],
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.counts[bucket(latency)] += 1;
+        self.total = self.total.saturating_add(latency);
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    /// Samples per bucket, in the order of [`BUCKET_LABELS`].
+    pub fn counts(&self) -> &[u64; BUCKET_LABELS.len()] {
+        &self.counts
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.total.as_nanos() / u128::from(count)) as u64,
+            )),
+        }
+    }
+}
+
+fn millis(duration: Option<Duration>) -> String {
+    duration.map_or("-".to_string(), |d| {
+        format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+    })
+}
+
+/// `n=3 min=0.40ms mean=2.10ms max=5.00ms <1ms:1 <5ms:1 ...`; empty buckets are left out.
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "n={} min={} mean={} max={}",
+            self.count(),
+            millis(self.min),
+            millis(self.mean()),
+            millis(self.max)
+        )?;
+        for (label, count) in BUCKET_LABELS.iter().zip(self.counts) {
+            if count > 0 {
+                write!(f, " {}:{}", label, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What is left of `total` once the server's share is taken out. The two are
+/// measured on different clocks, so this never goes below zero.
+pub fn network_time(total: Duration, server_time: Duration) -> Duration {
+    total.saturating_sub(server_time)
+}
+
+/// Latency histograms keyed by command kind (`ON`, `STATUS`, ...).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMetrics {
+    commands: BTreeMap<&'static str, LatencyHistogram>,
+    /// Only for responses that carried the server's processing time.
+    server: BTreeMap<&'static str, LatencyHistogram>,
+    network: BTreeMap<&'static str, LatencyHistogram>,
+}
+
+impl ClientMetrics {
+    pub fn record(&mut self, kind: &'static str, latency: Duration) {
+        self.commands.entry(kind).or_default().record(latency);
+    }
+
+    /// Records `latency` and, when the server reported its `server_time`, how
+    /// it splits into server and network time.
+    pub fn record_timed(
+        &mut self,
+        kind: &'static str,
+        latency: Duration,
+        server_time: Option<Duration>,
+    ) {
+        self.record(kind, latency);
+        if let Some(server_time) = server_time {
+            self.server.entry(kind).or_default().record(server_time);
+            self.network
+                .entry(kind)
+                .or_default()
+                .record(network_time(latency, server_time));
+        }
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&LatencyHistogram> {
+        self.commands.get(kind)
+    }
+
+    /// Time the server spent on `kind`, by its own account.
+    pub fn server(&self, kind: &str) -> Option<&LatencyHistogram> {
+        self.server.get(kind)
+    }
+
+    /// Latency of `kind` minus the server's time.
+    pub fn network(&self, kind: &str) -> Option<&LatencyHistogram> {
+        self.network.get(kind)
+    }
+
+    /// Histograms in alphabetical order of the command kind.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &LatencyHistogram)> {
+        self.commands
+            .iter()
+            .map(|(kind, histogram)| (*kind, histogram))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// One `KIND: histogram` line per command kind, followed by indented `server:`
+/// and `network:` lines where the server reported its time.
+impl fmt::Display for ClientMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (kind, histogram)) in self.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", kind, histogram)?;
+            if let (Some(server), Some(network)) = (self.server(kind), self.network(kind)) {
+                write!(f, "\n  server: {}\n  network: {}", server, network)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us(micros: u64) -> Duration {
+        Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn test_bucket_boundaries() {
+        for (latency, expected) in [
+            (us(0), 0),
+            (us(999), 0),
+            (us(1000), 1),
+            (us(4900), 1),
+            (us(5000), 2),
+            (us(19000), 2),
+            (us(20000), 3),
+            (us(99000), 3),
+            (us(100000), 4),
+            (us(999000), 4),
+            (us(1000000), 5),
+            (Duration::MAX, 5),
+        ] {
+            assert_eq!(bucket(latency), expected, "{:?}", latency);
+        }
+    }
+
+    #[test]
+    fn test_histogram_statistics() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.to_string(), "n=0 min=- mean=- max=-");
+
+        for latency in [us(500), us(3000), us(3500), us(1500000)] {
+            histogram.record(latency);
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.counts(), &[1, 2, 0, 0, 0, 1]);
+        assert_eq!(histogram.min(), Some(us(500)));
+        assert_eq!(histogram.max(), Some(us(1500000)));
+        assert_eq!(histogram.mean(), Some(us(376750)));
+        assert_eq!(
+            histogram.to_string(),
+            "n=4 min=0.50ms mean=376.75ms max=1500.00ms <1ms:1 <5ms:2 >=1s:1"
+        );
+    }
+
+    #[test]
+    fn test_client_metrics_by_command() {
+        let mut metrics = ClientMetrics::default();
+        assert!(metrics.is_empty());
+        metrics.record("STATUS", us(2000));
+        metrics.record("ON", us(30000));
+        metrics.record("STATUS", us(4000));
+
+        assert_eq!(metrics.get("STATUS").unwrap().mean(), Some(us(3000)));
+        assert_eq!(metrics.get("OFF"), None);
+        assert_eq!(
+            metrics.to_string(),
+            "ON: n=1 min=30.00ms mean=30.00ms max=30.00ms <100ms:1\n\
+             STATUS: n=2 min=2.00ms mean=3.00ms max=4.00ms <5ms:2"
+        );
+    }
+
+    #[test]
+    fn test_server_time_is_split_out() {
+        assert_eq!(network_time(us(5000), us(1200)), us(3800));
+        // The server's clock ran a little faster than ours.
+        assert_eq!(network_time(us(1000), us(1100)), Duration::ZERO);
+
+        let mut metrics = ClientMetrics::default();
+        metrics.record_timed("STATUS", us(3000), Some(us(500)));
+        metrics.record_timed("STATUS", us(5000), Some(us(1500)));
+        // An older server: counted in the total only.
+        metrics.record_timed("STATUS", us(7000), None);
+        metrics.record_timed("ON", us(2000), None);
+
+        assert_eq!(metrics.get("STATUS").unwrap().count(), 3);
+        assert_eq!(metrics.server("STATUS").unwrap().mean(), Some(us(1000)));
+        assert_eq!(metrics.network("STATUS").unwrap().count(), 2);
+        assert_eq!(metrics.network("STATUS").unwrap().min(), Some(us(2500)));
+        assert_eq!(metrics.network("STATUS").unwrap().max(), Some(us(3500)));
+        assert_eq!(metrics.server("ON"), None);
+        assert_eq!(
+            metrics.to_string(),
+            "ON: n=1 min=2.00ms mean=2.00ms max=2.00ms <5ms:1\n\
+             STATUS: n=3 min=3.00ms mean=5.00ms max=7.00ms <5ms:1 <20ms:2\n  \
+             server: n=2 min=0.50ms mean=1.00ms max=1.50ms <1ms:1 <5ms:1\n  \
+             network: n=2 min=2.50ms mean=3.00ms max=3.50ms <5ms:2"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! A client that keeps state-changing commands while the socket is unreachable
+//! and replays them once it is back, for automations where a late `OFF` is
+//! better than a lost one.
+//!
+//! Only transport failures queue a command: a socket that answers `ERROR` has
+//! seen the command and rejected it. Queries (`STATUS`, `INFO`, ...) are never
+//! queued, since a late answer is of no use to the caller.
+
+use crate::{log, ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Command, ProtocolError, Response};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+type Client = SmartSocketClient<ClientStream>;
+
+#[derive(Debug, Clone)]
+pub struct OfflineQueueConfig {
+    pub client: ClientConfig,
+    /// Most commands kept while offline; further ones fail instead of queuing.
+    pub capacity: usize,
+    /// Commands older than this are dropped instead of replayed; `None` keeps
+    /// them until they can be sent.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for OfflineQueueConfig {
+    fn default() -> Self {
+        Self {
+            client: ClientConfig::default(),
+            capacity: 32,
+            max_age: None,
+        }
+    }
+}
+
+/// What became of a command given to [`OfflineQueue::send_command`].
+// Returned once per command and matched right away, so boxing the reply buys nothing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    Sent(Response),
+    /// The socket was unreachable; the command waits for the next flush and
+    /// nothing confirms it was applied yet.
+    Queued,
+}
+
+/// What a flush did with the waiting commands.
+#[derive(Debug, Default, Clone)]
+pub struct FlushReport {
+    /// Commands sent, in queue order, with the socket's replies.
+    pub replayed: Vec<(Command, Response)>,
+    /// Commands dropped for being older than the maximum age.
+    pub expired: Vec<Command>,
+}
+
+struct Queued {
+    command: Command,
+    queued_at: Instant,
+}
+
+/// A reconnecting client with a bounded FIFO of commands that could not be sent.
+///
+/// Waiting commands are flushed before every new command, so they are applied
+/// in the order they were issued; [`flush`](Self::flush) does the same on
+/// demand, e.g. from a timer.
+pub struct OfflineQueue {
+    config: OfflineQueueConfig,
+    client: Option<Client>,
+    queue: VecDeque<Queued>,
+}
+
+/// Failures after which the socket may not have seen the command.
+fn is_transport_error(e: &ProtocolError) -> bool {
+    matches!(
+        e,
+        ProtocolError::ConnectionError(_)
+            | ProtocolError::ConnectionClosed
+            | ProtocolError::PeerUnreachable(_)
+    )
+}
+
+impl OfflineQueue {
+    /// Connects lazily, on the first command.
+    pub fn new(config: OfflineQueueConfig) -> Self {
+        Self {
+            config,
+            client: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Number of commands waiting to be replayed.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The open connection, reconnecting if there is none or it was lost.
+    fn client(&mut self) -> Result<&mut Client, ProtocolError> {
+        if !self.client.as_ref().is_some_and(Client::is_connected) {
+            self.client = None;
+            self.client = Some(SmartSocketClient::with_config(self.config.client.clone())?);
+        }
+        Ok(self.client.as_mut().expect("client was just connected"))
+    }
+
+    fn exchange(&mut self, command: &Command) -> Result<Response, ProtocolError> {
+        self.client()?.send_command(command.clone())
+    }
+
+    /// Drops expired commands, then replays the rest in order until the queue is
+    /// empty or the socket is unreachable again; a command that fails to send
+    /// stays at the front. The error is that of the failed send.
+    pub fn flush(&mut self) -> Result<FlushReport, ProtocolError> {
+        let mut report = FlushReport::default();
+        if let Some(max_age) = self.config.max_age {
+            let now = Instant::now();
+            while let Some(queued) = self.queue.front() {
+                if now.saturating_duration_since(queued.queued_at) <= max_age {
+                    break;
+                }
+                let queued = self.queue.pop_front().expect("front was just checked");
+                log(&format!(
+                    "Dropping expired queued command: {}",
+                    queued.command
+                ));
+                report.expired.push(queued.command);
+            }
+        }
+        while let Some(queued) = self.queue.front() {
+            let command = queued.command.clone();
+            let response = self.exchange(&command)?;
+            self.queue.pop_front();
+            log(&format!(
+                "Replayed queued command {}: {}",
+                command, response
+            ));
+            report.replayed.push((command, response));
+        }
+        Ok(report)
+    }
+
+    /// Sends `command` after any waiting ones. If the socket is unreachable, a
+    /// state-changing command is queued and [`Delivery::Queued`] returned; a
+    /// query, or any command once the queue is full, fails with the error.
+    pub fn send_command(&mut self, command: Command) -> Result<Delivery, ProtocolError> {
+        let sent = match self.flush() {
+            Ok(_) => self.exchange(&command),
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(response) => Ok(Delivery::Sent(response)),
+            Err(e) if is_transport_error(&e) && command.is_state_changing() => {
+                if self.queue.len() >= self.config.capacity {
+                    return Err(ProtocolError::ConnectionError(format!(
+                        "{}; offline queue is full ({} commands)",
+                        e, self.config.capacity
+                    )));
+                }
+                log(&format!("Socket unreachable ({}), queued {}", e, command));
+                self.queue.push_back(Queued {
+                    command,
+                    queued_at: Instant::now(),
+                });
+                Ok(Delivery::Queued)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn turn_on(&mut self) -> Result<Delivery, ProtocolError> {
+        self.send_command(Command::TurnOn)
+    }
+
+    pub fn turn_off(&mut self) -> Result<Delivery, ProtocolError> {
+        self.send_command(Command::TurnOff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, write_message};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Acknowledges every command and records it, until stopped.
+    struct RecordingServer {
+        running: Arc<AtomicBool>,
+        handle: thread::JoinHandle<()>,
+    }
+
+    impl RecordingServer {
+        fn start(addr: &str, received: Arc<Mutex<Vec<String>>>) -> (Self, SocketAddr) {
+            let listener = TcpListener::bind(addr).unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let local_addr = listener.local_addr().unwrap();
+            let running = Arc::new(AtomicBool::new(true));
+            let r = running.clone();
+            let handle = thread::spawn(move || {
+                let mut handlers = Vec::new();
+                while r.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((mut stream, _)) => {
+                            stream.set_nonblocking(false).unwrap();
+                            stream
+                                .set_read_timeout(Some(Duration::from_millis(20)))
+                                .unwrap();
+                            let (r, received) = (r.clone(), received.clone());
+                            handlers.push(thread::spawn(move || {
+                                while r.load(Ordering::SeqCst) {
+                                    let command = match read_message(&mut stream) {
+                                        Ok(command) => command,
+                                        Err(ProtocolError::ConnectionClosed) => break,
+                                        Err(_) => continue,
+                                    };
+                                    let reply = if command == "STATUS" {
+                                        "STATUS:OFF:0".to_string()
+                                    } else {
+                                        format!("OK:{}", command)
+                                    };
+                                    received.lock().unwrap().push(command);
+                                    if write_message(&mut stream, &reply).is_err() {
+                                        break;
+                                    }
+                                }
+                            }));
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+                for handler in handlers {
+                    handler.join().unwrap();
+                }
+            });
+            (Self { running, handle }, local_addr)
+        }
+
+        fn stop(self) {
+            self.running.store(false, Ordering::SeqCst);
+            self.handle.join().unwrap();
+        }
+    }
+
+    fn queue(addr: SocketAddr, max_age: Option<Duration>) -> OfflineQueue {
+        OfflineQueue::new(OfflineQueueConfig {
+            client: ClientConfig {
+                address: addr.into(),
+                ..Default::default()
+            },
+            capacity: 2,
+            max_age,
+        })
+    }
+
+    #[test]
+    fn test_commands_are_replayed_in_order_after_an_outage() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (server, addr) = RecordingServer::start("127.0.0.1:0", received.clone());
+        let mut queue = queue(addr, None);
+        assert!(matches!(queue.turn_on().unwrap(), Delivery::Sent(_)));
+
+        server.stop();
+        assert_eq!(queue.turn_off().unwrap(), Delivery::Queued);
+        assert_eq!(
+            queue
+                .send_command(Command::SetName("Hall".to_string()))
+                .unwrap(),
+            Delivery::Queued
+        );
+        // Queries are not queued, nor is anything beyond the capacity.
+        assert!(queue.send_command(Command::GetStatus).is_err());
+        assert!(matches!(
+            queue.turn_on(),
+            Err(ProtocolError::ConnectionError(msg)) if msg.ends_with("offline queue is full (2 commands)")
+        ));
+        assert_eq!(queue.pending(), 2);
+
+        let (server, _) = RecordingServer::start(&addr.to_string(), received.clone());
+        assert!(matches!(
+            queue.send_command(Command::GetStatus).unwrap(),
+            Delivery::Sent(Response::Status { .. })
+        ));
+        assert_eq!(queue.pending(), 0);
+        assert_eq!(
+            *received.lock().unwrap(),
+            ["ON", "OFF", "SET_NAME:Hall", "STATUS"]
+        );
+
+        drop(queue);
+        server.stop();
+    }
+
+    #[test]
+    fn test_expired_commands_are_dropped() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (server, addr) = RecordingServer::start("127.0.0.1:0", received.clone());
+        server.stop();
+
+        let mut queue = queue(addr, Some(Duration::from_millis(150)));
+        assert_eq!(queue.turn_on().unwrap(), Delivery::Queued);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(queue.turn_off().unwrap(), Delivery::Queued);
+        thread::sleep(Duration::from_millis(100));
+
+        let (server, _) = RecordingServer::start(&addr.to_string(), received.clone());
+        let report = queue.flush().unwrap();
+        let expired: Vec<String> = report.expired.iter().map(Command::to_string).collect();
+        assert_eq!(expired, ["ON"]);
+        let [(command, response)] = &report.replayed[..] else {
+            panic!("Unexpected replay: {:?}", report.replayed);
+        };
+        assert_eq!(command.to_string(), "OFF");
+        assert_eq!(*response, Response::Ok(Some("OFF".to_string())));
+        assert_eq!(*received.lock().unwrap(), ["OFF"]);
+
+        drop(queue);
+        server.stop();
+    }
+}
@@ -0,0 +1,307 @@
+//! A fixed-size pool of connected clients for programs that talk to the socket
+//! from several threads.
+
+use crate::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::ProtocolError;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+type Client = SmartSocketClient<ClientStream>;
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub client: ClientConfig,
+    /// Most connections open at once.
+    pub size: usize,
+    /// How long [`SocketClientPool::checkout`] waits for a free client.
+    pub checkout_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            client: ClientConfig::default(),
+            size: 4,
+            checkout_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+struct PoolState {
+    idle: Vec<Client>,
+    checked_out: usize,
+}
+
+/// Hands out connected clients, one thread at a time each.
+///
+/// Connections are opened lazily on checkout. A client whose command failed at
+/// the transport level is discarded when it is returned, so the next checkout
+/// opens a fresh connection.
+pub struct SocketClientPool {
+    config: PoolConfig,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+impl SocketClientPool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                checked_out: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes an idle client, or connects a new one while the pool is below its size.
+    /// Blocks up to `checkout_timeout` when every client is in use.
+    pub fn checkout(&self) -> Result<PooledClient<'_>, ProtocolError> {
+        let deadline = Instant::now() + self.config.checkout_timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(client) = state.idle.pop() {
+                state.checked_out += 1;
+                return Ok(PooledClient {
+                    pool: self,
+                    client: Some(client),
+                });
+            }
+            if state.checked_out < self.config.size {
+                // Reserve the slot, then connect without holding the lock.
+                state.checked_out += 1;
+                drop(state);
+                return match SmartSocketClient::with_config(self.config.client.clone()) {
+                    Ok(client) => Ok(PooledClient {
+                        pool: self,
+                        client: Some(client),
+                    }),
+                    Err(e) => {
+                        self.checkin(None);
+                        Err(e)
+                    }
+                };
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ProtocolError::ConnectionError(format!(
+                    "Pool exhausted: no client available within {:?}",
+                    self.config.checkout_timeout
+                )));
+            }
+            state = self
+                .returned
+                .wait_timeout(state, left)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Frees a slot, keeping `client` for reuse if its connection is still healthy.
+    fn checkin(&self, client: Option<Client>) {
+        let mut state = self.lock();
+        state.checked_out -= 1;
+        if let Some(client) = client.filter(Client::is_connected) {
+            state.idle.push(client);
+        }
+        drop(state);
+        self.returned.notify_one();
+    }
+
+    /// Number of open connections waiting to be checked out.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+}
+
+/// A checked-out client; it goes back to the pool when dropped.
+pub struct PooledClient<'a> {
+    pool: &'a SocketClientPool,
+    client: Option<Client>,
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("client is present until drop")
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("client is present until drop")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        self.pool.checkin(self.client.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, serialize_message, Response};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Minimal socket server: answers STATUS and acknowledges everything else.
+    struct TestServer {
+        running: Arc<AtomicBool>,
+        connections: Arc<AtomicUsize>,
+        handle: thread::JoinHandle<()>,
+    }
+
+    impl TestServer {
+        fn start(addr: &str) -> (Self, SocketAddr) {
+            let listener = TcpListener::bind(addr).unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let local_addr = listener.local_addr().unwrap();
+            let running = Arc::new(AtomicBool::new(true));
+            let connections = Arc::new(AtomicUsize::new(0));
+            let (r, c) = (running.clone(), connections.clone());
+            let handle = thread::spawn(move || {
+                let mut handlers = Vec::new();
+                while r.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((mut stream, _)) => {
+                            c.fetch_add(1, Ordering::SeqCst);
+                            stream.set_nonblocking(false).unwrap();
+                            stream
+                                .set_read_timeout(Some(Duration::from_millis(20)))
+                                .unwrap();
+                            let r = r.clone();
+                            handlers.push(thread::spawn(move || {
+                                while r.load(Ordering::SeqCst) {
+                                    let reply = match read_message(&mut stream) {
+                                        Ok(command) if command == "STATUS" => "STATUS:OFF:0",
+                                        Ok(_) => "OK:done",
+                                        Err(ProtocolError::ConnectionClosed) => break,
+                                        Err(_) => continue,
+                                    };
+                                    if stream
+                                        .write_all(&serialize_message(reply).unwrap())
+                                        .is_err()
+                                    {
+                                        break;
+                                    }
+                                }
+                            }));
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+                for handler in handlers {
+                    handler.join().unwrap();
+                }
+            });
+            (
+                Self {
+                    running,
+                    connections,
+                    handle,
+                },
+                local_addr,
+            )
+        }
+
+        /// Stops accepting and closes every open connection.
+        fn stop(self) -> usize {
+            self.running.store(false, Ordering::SeqCst);
+            self.handle.join().unwrap();
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    fn pool(addr: SocketAddr, size: usize, checkout_timeout: Duration) -> SocketClientPool {
+        SocketClientPool::new(PoolConfig {
+            client: ClientConfig {
+                address: addr.into(),
+                ..Default::default()
+            },
+            size,
+            checkout_timeout,
+        })
+    }
+
+    #[test]
+    fn test_concurrent_checkouts_share_connections() {
+        let (server, addr) = TestServer::start("127.0.0.1:0");
+        let pool = pool(addr, 2, Duration::from_secs(5));
+
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    for _ in 0..20 {
+                        let mut client = pool.checkout().unwrap();
+                        assert!(matches!(
+                            client.get_status().unwrap(),
+                            Response::Status { is_on: false, .. }
+                        ));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(pool.idle(), 2);
+        drop(pool);
+        assert_eq!(server.stop(), 2);
+    }
+
+    #[test]
+    fn test_exhausted_pool_times_out() {
+        let (server, addr) = TestServer::start("127.0.0.1:0");
+        let pool = pool(addr, 1, Duration::from_millis(100));
+
+        let held = pool.checkout().unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            pool.checkout(),
+            Err(ProtocolError::ConnectionError(msg)) if msg.starts_with("Pool exhausted")
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // A returned client wakes up a waiting checkout.
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| pool.checkout().map(|_| ()));
+            thread::sleep(Duration::from_millis(20));
+            drop(held);
+            assert!(waiter.join().unwrap().is_ok());
+        });
+
+        drop(pool);
+        server.stop();
+    }
+
+    #[test]
+    fn test_recovers_after_server_restart() {
+        let (server, addr) = TestServer::start("127.0.0.1:0");
+        let pool = pool(addr, 1, Duration::from_secs(1));
+        pool.checkout().unwrap().turn_on().unwrap();
+        assert_eq!(pool.idle(), 1);
+
+        server.stop();
+        let (server, _) = TestServer::start(&addr.to_string());
+
+        // The idle connection is stale: its command fails and it is discarded...
+        assert!(pool.checkout().unwrap().turn_on().is_err());
+        assert_eq!(pool.idle(), 0);
+        // ...and the next checkout reconnects.
+        pool.checkout().unwrap().turn_on().unwrap();
+        assert_eq!(pool.idle(), 1);
+
+        drop(pool);
+        assert_eq!(server.stop(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+//! The controller's side of reverse mode. A socket behind NAT cannot be reached,
+//! so its server dials out to the controller instead; the controller accepts
+//! those connections here and drives each device with an ordinary client.
+
+use crate::{lock, log, set_timeouts, ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Address, ProtocolError};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Devices that have dialled in, keyed by the name in their `INFO`.
+#[derive(Default)]
+struct Devices {
+    connected: Mutex<HashMap<String, SmartSocketClient<ClientStream>>>,
+    arrived: Condvar,
+}
+
+/// Accepts connections from devices in reverse mode. Each one is greeted like a
+/// normal connection (TLS, banner, authentication from the [`ClientConfig`])
+/// and then waits to be [taken](ReverseListener::take) by name. A device that
+/// dials in again replaces its previous connection. Dropping the listener stops
+/// accepting.
+pub struct ReverseListener {
+    local_addr: SocketAddr,
+    devices: Arc<Devices>,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReverseListener {
+    /// Listens on `address`; `config` supplies the timeouts, TLS settings and
+    /// token used with every device. Its `address` is ignored.
+    pub fn bind(address: &Address, config: ClientConfig) -> Result<Self, ProtocolError> {
+        let bind_error =
+            |e: std::io::Error| ProtocolError::ConnectionError(format!("Failed to listen: {}", e));
+        let listener = TcpListener::bind(address).map_err(bind_error)?;
+        let local_addr = listener.local_addr().map_err(bind_error)?;
+        log(&format!("Waiting for devices to dial in on {}", local_addr));
+
+        let devices = Arc::new(Devices::default());
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let devices = devices.clone();
+            let running = running.clone();
+            thread::spawn(move || accept_loop(listener, config, &devices, &running))
+        };
+        Ok(Self {
+            local_addr,
+            devices,
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Names of the devices waiting to be taken, sorted.
+    pub fn devices(&self) -> Vec<String> {
+        let mut names: Vec<String> = lock(&self.devices.connected).keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Takes the connection of the device named `name`, waiting up to `timeout`
+    /// for it to dial in. The device is forgotten until it dials in again.
+    pub fn take(&self, name: &str, timeout: Duration) -> Option<SmartSocketClient<ClientStream>> {
+        let connected = lock(&self.devices.connected);
+        let (mut connected, _) = self
+            .devices
+            .arrived
+            .wait_timeout_while(connected, timeout, |devices| !devices.contains_key(name))
+            .unwrap_or_else(PoisonError::into_inner);
+        connected.remove(name)
+    }
+}
+
+impl Drop for ReverseListener {
+    /// The accept loop blocks in `accept()`, so it is woken with a throwaway connection.
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake_addr, Duration::from_secs(1));
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log("Reverse listener thread panicked");
+            }
+        }
+    }
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    config: ClientConfig,
+    devices: &Arc<Devices>,
+    running: &AtomicBool,
+) {
+    for stream in listener.incoming() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            // Greeting takes a round trip or two; a slow device must not hold up the next.
+            Ok(stream) => {
+                let config = config.clone();
+                let devices = devices.clone();
+                thread::spawn(move || greet(stream, config, &devices));
+            }
+            Err(e) => log(&format!("Failed to accept a device: {}", e)),
+        }
+    }
+}
+
+/// Sets up the client for a device that dialled in and files it under its name,
+/// asking for `INFO` if the device sent no banner.
+fn greet(stream: TcpStream, config: ClientConfig, devices: &Devices) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer.to_string(),
+        Err(_) => "unknown".to_string(),
+    };
+    let result = set_timeouts(&stream, &config).and_then(|()| {
+        let route = format!("dialled in from {}", peer);
+        let mut client = SmartSocketClient::establish(stream, &route, config)?;
+        if client.identity.is_none() {
+            client.identity = Some(client.get_info()?);
+        }
+        Ok(client)
+    });
+    match result {
+        Ok(client) => {
+            let name = client
+                .server_identity()
+                .map(|info| info.name.clone())
+                .unwrap_or_default();
+            log(&format!("Device {:?} dialled in from {}", name, peer));
+            if lock(&devices.connected)
+                .insert(name.clone(), client)
+                .is_some()
+            {
+                log(&format!(
+                    "Device {:?} replaced its earlier connection",
+                    name
+                ));
+            }
+            devices.arrived.notify_all();
+        }
+        Err(e) => log(&format!("Device at {} failed to connect: {}", peer, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, serialize_message, Command, DeviceInfo, Response};
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn info(name: &str) -> Response {
+        Response::Info(DeviceInfo {
+            name: name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// A device that dials `controller`, optionally greets it with a banner,
+    /// then answers INFO with its name and everything else with `OK`.
+    fn dial_in(
+        controller: SocketAddr,
+        name: &'static str,
+        banner: bool,
+    ) -> JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(controller).unwrap();
+            if banner {
+                stream
+                    .write_all(&serialize_message(&info(name).to_string()).unwrap())
+                    .unwrap();
+            }
+            let mut received = Vec::new();
+            while let Ok(message) = read_message(&mut stream) {
+                let reply = match Command::from_str(&message) {
+                    Ok(Command::GetInfo) => info(name),
+                    _ => Response::ok("done"),
+                };
+                received.push(message);
+                if stream
+                    .write_all(&serialize_message(&reply.to_string()).unwrap())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            received
+        })
+    }
+
+    fn listener() -> ReverseListener {
+        let address = SocketAddr::from(([127, 0, 0, 1], 0)).into();
+        ReverseListener::bind(
+            &address,
+            ClientConfig {
+                banner_timeout: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_devices_are_found_by_name() {
+        let listener = listener();
+        let hall = dial_in(listener.local_addr(), "Hall", true);
+        let porch = dial_in(listener.local_addr(), "Porch", false);
+
+        let mut client = listener.take("Porch", Duration::from_secs(5)).unwrap();
+        assert_eq!(client.server_identity().unwrap().name, "Porch");
+        assert_eq!(client.turn_on().unwrap(), Response::ok("done"));
+        client.close().unwrap();
+        // Without a banner the listener asked for INFO first.
+        assert_eq!(porch.join().unwrap(), ["INFO", "ON"]);
+
+        let client = listener.take("Hall", Duration::from_secs(5)).unwrap();
+        assert!(listener.devices().is_empty());
+        drop(client);
+        assert!(hall.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_take_times_out_for_unknown_device() {
+        let listener = listener();
+        let device = dial_in(listener.local_addr(), "Hall", true);
+        assert!(listener
+            .take("Cellar", Duration::from_millis(300))
+            .is_none());
+        assert_eq!(listener.devices(), ["Hall"]);
+        drop(listener);
+        device.join().unwrap();
+    }
+}
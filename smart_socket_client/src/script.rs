@@ -0,0 +1,195 @@
+//! Scripts of console commands: what `record` writes and `replay` runs.
+//!
+//! A script has one console line per line, exactly as it would be typed, so a
+//! batch such as `on; status` stays one pipelined batch when replayed. Blank
+//! lines and lines starting with `#` are ignored, and `wait <ms>` pauses for the
+//! time that passed between two recorded commands. In a command, `\\` stands for
+//! a backslash and `\n` for a line break; a command that would read as a comment
+//! or a wait is written with a leading `\`.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Wait(Duration),
+    Command(String),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Wait(delay) => write!(f, "wait {}", delay.as_millis()),
+            Step::Command(command) => f.write_str(&escape(command)),
+        }
+    }
+}
+
+/// Whether `line` starts with the `wait` keyword, in any case.
+fn is_wait(line: &str) -> bool {
+    let word = line.split_whitespace().next().unwrap_or_default();
+    word.eq_ignore_ascii_case("wait")
+}
+
+fn escape(command: &str) -> String {
+    let escaped = command.replace('\\', "\\\\").replace('\n', "\\n");
+    if escaped.starts_with('#') || is_wait(&escaped) {
+        format!("\\{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn unescape(line: &str) -> String {
+    let mut command = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            command.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => command.push('\n'),
+            Some(other) => command.push(other),
+            None => command.push('\\'),
+        }
+    }
+    command
+}
+
+/// Parses a script; errors name the 1-based line.
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if is_wait(line) {
+            let millis = line.split_whitespace().nth(1).unwrap_or_default();
+            match (millis.parse(), line.split_whitespace().count()) {
+                (Ok(millis), 2) => steps.push(Step::Wait(Duration::from_millis(millis))),
+                _ => {
+                    return Err(format!(
+                        "line {}: expected 'wait <milliseconds>', got '{}'",
+                        index + 1,
+                        line
+                    ))
+                }
+            }
+        } else {
+            steps.push(Step::Command(unescape(line)));
+        }
+    }
+    Ok(steps)
+}
+
+/// Writes commands as they are executed, each followed by a flush so that a
+/// session that ends abruptly still leaves every command up to then on disk.
+pub struct Recorder<W: Write> {
+    out: W,
+    last: Instant,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a script at `now`; the first command waits for the time since then.
+    pub fn new(mut out: W, now: Instant) -> io::Result<Self> {
+        writeln!(
+            out,
+            "# Recorded by smart_socket_client; replay with 'replay <file>'"
+        )?;
+        out.flush()?;
+        Ok(Self { out, last: now })
+    }
+
+    /// Appends `command`, executed at `now`, after a wait for the time since the
+    /// previous one. Pauses shorter than a millisecond are not written.
+    pub fn record(&mut self, command: &str, now: Instant) -> io::Result<()> {
+        let delay = now.saturating_duration_since(self.last);
+        self.last = now;
+        if delay.as_millis() > 0 {
+            writeln!(self.out, "{}", Step::Wait(delay))?;
+        }
+        writeln!(self.out, "{}", Step::Command(command.to_string()))?;
+        self.out.flush()
+    }
+
+    /// Flushes and hands back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> Step {
+        Step::Command(text.to_string())
+    }
+
+    #[test]
+    fn test_escaping_round_trips() {
+        for text in [
+            "rename Hall\\Lamp",
+            "# not a comment",
+            "wait for it",
+            "WAIT 5",
+            "line\nbreak",
+            "trailing\\",
+            "on; status",
+        ] {
+            let line = command(text).to_string();
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(parse(&line), Ok(vec![command(text)]), "{:?}", line);
+        }
+        assert_eq!(command("# x").to_string(), "\\# x");
+        assert_eq!(command("a\\b").to_string(), "a\\\\b");
+    }
+
+    #[test]
+    fn test_comments_blank_lines_and_waits() {
+        let script = "# header\n\n  on  \nwait 1500\n   # indented comment\nWait 0\nstatus\n";
+        assert_eq!(
+            parse(script),
+            Ok(vec![
+                command("on"),
+                Step::Wait(Duration::from_millis(1500)),
+                Step::Wait(Duration::ZERO),
+                command("status"),
+            ])
+        );
+        assert_eq!(
+            parse("on\nwait soon\n"),
+            Err("line 2: expected 'wait <milliseconds>', got 'wait soon'".to_string())
+        );
+        assert!(parse("wait 10 20").is_err());
+        assert!(parse("wait").is_err());
+    }
+
+    #[test]
+    fn test_recorder_encodes_delays() {
+        let start = Instant::now();
+        let mut recorder = Recorder::new(Vec::new(), start).unwrap();
+        recorder.record("on", start).unwrap();
+        recorder
+            .record("status", start + Duration::from_micros(2_750_400))
+            .unwrap();
+        recorder
+            .record("off", start + Duration::from_micros(2_750_900))
+            .unwrap();
+        let text = String::from_utf8(recorder.finish().unwrap()).unwrap();
+        assert!(text.starts_with('#'));
+        assert_eq!(
+            parse(&text),
+            Ok(vec![
+                command("on"),
+                Step::Wait(Duration::from_millis(2750)),
+                command("status"),
+                command("off"),
+            ])
+        );
+    }
+}
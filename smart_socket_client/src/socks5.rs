@@ -0,0 +1,417 @@
+//! Connecting through a SOCKS5 proxy (RFC 1928), for sockets that are only
+//! reachable via a jump host. Supports no authentication and username/password
+//! (RFC 1929). Host names are sent to the proxy unresolved, so they only have to
+//! resolve on the proxy's side.
+
+use smart_socket_protocol::{Address, ConfigError, ProtocolError};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A proxy written as `host:port` or `user:password@host:port`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub address: Address,
+    pub credentials: Option<(String, String)>,
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((userinfo, address)) = s.rsplit_once('@') else {
+            return Ok(Self {
+                address: s.parse()?,
+                credentials: None,
+            });
+        };
+        let (user, password) = userinfo
+            .split_once(':')
+            .filter(|(user, password)| {
+                (1..=255).contains(&user.len()) && (1..=255).contains(&password.len())
+            })
+            .ok_or(ConfigError::InvalidCredentials)?;
+        Ok(Self {
+            address: address.parse()?,
+            credentials: Some((user.to_string(), password.to_string())),
+        })
+    }
+}
+
+/// Shows the user name but never the password, so configs can be logged.
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("address", &self.address)
+            .field("user", &self.credentials.as_ref().map(|(user, _)| user))
+            .finish()
+    }
+}
+
+fn reply_reason(code: u8) -> &'static str {
+    match code {
+        1 => "general proxy server failure",
+        2 => "connection not allowed by the proxy's rules",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused by the target",
+        6 => "TTL expired",
+        7 => "command not supported by the proxy",
+        8 => "address type not supported by the proxy",
+        _ => "unknown error",
+    }
+}
+
+fn error(proxy: &Socks5Proxy, reason: &str) -> ProtocolError {
+    ProtocolError::ConnectionError(format!("SOCKS5 proxy {}: {}", proxy.address, reason))
+}
+
+fn exchange<S: Read + Write>(
+    stream: &mut S,
+    request: &[u8],
+    reply: &mut [u8],
+    proxy: &Socks5Proxy,
+) -> Result<(), ProtocolError> {
+    stream
+        .write_all(request)
+        .and_then(|()| stream.read_exact(reply))
+        .map_err(|e| error(proxy, &format!("handshake failed: {}", e)))
+}
+
+/// Performs the handshake on `stream`, already connected to `proxy`, and asks the
+/// proxy to connect to `target`. Afterwards the stream carries the target's traffic.
+pub fn connect<S: Read + Write>(
+    stream: &mut S,
+    proxy: &Socks5Proxy,
+    target: &Address,
+) -> Result<(), ProtocolError> {
+    let greeting: &[u8] = match proxy.credentials {
+        Some(_) => &[VERSION, 2, METHOD_NONE, METHOD_PASSWORD],
+        None => &[VERSION, 1, METHOD_NONE],
+    };
+    let mut choice = [0u8; 2];
+    exchange(stream, greeting, &mut choice, proxy)?;
+    match (choice, &proxy.credentials) {
+        ([VERSION, METHOD_NONE], _) => {}
+        ([VERSION, METHOD_PASSWORD], Some((user, password))) => {
+            let mut request = vec![AUTH_VERSION, user.len() as u8];
+            request.extend_from_slice(user.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            let mut status = [0u8; 2];
+            exchange(stream, &request, &mut status, proxy)?;
+            if status[1] != 0 {
+                return Err(error(proxy, "username or password rejected"));
+            }
+        }
+        ([VERSION, METHOD_UNACCEPTABLE], None) => {
+            return Err(error(proxy, "authentication required"));
+        }
+        ([VERSION, METHOD_UNACCEPTABLE], Some(_)) => {
+            return Err(error(proxy, "no offered authentication method accepted"));
+        }
+        _ => return Err(error(proxy, "not a SOCKS5 server")),
+    }
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    match target {
+        Address::Ip(SocketAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        Address::Ip(SocketAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        // Address validation limits host names to 253 bytes.
+        Address::Host { host, .. } => {
+            request.extend_from_slice(&[ATYP_DOMAIN, host.len() as u8]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+
+    let mut reply = [0u8; 4];
+    exchange(stream, &request, &mut reply, proxy)?;
+    if reply[0] != VERSION {
+        return Err(error(proxy, "not a SOCKS5 server"));
+    }
+    if reply[1] != 0 {
+        return Err(error(
+            proxy,
+            &format!(
+                "CONNECT to {} failed with reply {}: {}",
+                target,
+                reply[1],
+                reply_reason(reply[1])
+            ),
+        ));
+    }
+    // The address the proxy bound for us; nothing needs it, but it has to be read.
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            exchange(stream, &[], &mut len, proxy)?;
+            usize::from(len[0])
+        }
+        other => {
+            return Err(error(
+                proxy,
+                &format!("unknown address type {} in reply", other),
+            ))
+        }
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    exchange(stream, &[], &mut bound, proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientConfig, SmartSocketClient};
+    use smart_socket_protocol::{read_message, serialize_message, Response};
+    use std::io::{self, Cursor};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Replays canned proxy replies and records what the client sent.
+    struct Scripted {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&[u8]]) -> Self {
+            Self {
+                replies: Cursor::new(replies.concat()),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SUCCESS: &[u8] = &[5, 0, 0, 1, 10, 0, 0, 1, 0x1f, 0x90];
+
+    #[derive(Clone, Copy)]
+    enum Stub {
+        Open,
+        /// Requires `alice` / `secret`.
+        Password,
+        /// Accepts the handshake but reports the target as refusing.
+        Refuse,
+    }
+
+    fn read_bytes(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// A SOCKS5 proxy for one connection. After a successful CONNECT it acts as the
+    /// target itself and answers every command with `STATUS:ON:100`.
+    fn spawn_proxy(stub: Stub) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let header = read_bytes(&mut stream, 2);
+            let methods = read_bytes(&mut stream, usize::from(header[1]));
+            match stub {
+                Stub::Password if methods.contains(&METHOD_PASSWORD) => {
+                    stream.write_all(&[VERSION, METHOD_PASSWORD]).unwrap();
+                    let user_len = read_bytes(&mut stream, 2)[1];
+                    let user = read_bytes(&mut stream, usize::from(user_len));
+                    let password_len = read_bytes(&mut stream, 1)[0];
+                    let password = read_bytes(&mut stream, usize::from(password_len));
+                    let ok = user == b"alice" && password == b"secret";
+                    stream.write_all(&[AUTH_VERSION, u8::from(!ok)]).unwrap();
+                    if !ok {
+                        return;
+                    }
+                }
+                Stub::Password => {
+                    stream.write_all(&[VERSION, METHOD_UNACCEPTABLE]).unwrap();
+                    return;
+                }
+                Stub::Open | Stub::Refuse => stream.write_all(&[VERSION, METHOD_NONE]).unwrap(),
+            }
+
+            let request = read_bytes(&mut stream, 4);
+            let address_len = match request[3] {
+                ATYP_IPV4 => 4,
+                ATYP_IPV6 => 16,
+                _ => usize::from(read_bytes(&mut stream, 1)[0]),
+            };
+            read_bytes(&mut stream, address_len + 2);
+            if let Stub::Refuse = stub {
+                stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+                return;
+            }
+            stream.write_all(SUCCESS).unwrap();
+            while read_message(&mut stream).is_ok() {
+                if stream
+                    .write_all(&serialize_message("STATUS:ON:100").unwrap())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        addr
+    }
+
+    fn via(proxy: &str) -> ClientConfig {
+        ClientConfig {
+            address: "socket-1.lan:8080".parse().unwrap(),
+            socks5_proxy: Some(proxy.parse().unwrap()),
+            banner_timeout: None,
+            ..Default::default()
+        }
+    }
+
+    fn connection_error(config: ClientConfig) -> String {
+        match SmartSocketClient::with_config(config) {
+            Err(ProtocolError::ConnectionError(message)) => message,
+            Err(other) => panic!("Expected a connection error, got {:?}", other),
+            Ok(_) => panic!("Expected a connection error"),
+        }
+    }
+
+    #[test]
+    fn test_client_connects_through_proxy() {
+        let proxy = spawn_proxy(Stub::Password);
+        let mut client =
+            SmartSocketClient::with_config(via(&format!("alice:secret@{}", proxy))).unwrap();
+        assert!(matches!(
+            client.get_status(),
+            Ok(Response::Status { is_on: true, .. })
+        ));
+
+        let proxy = spawn_proxy(Stub::Password);
+        assert_eq!(
+            connection_error(via(&format!("alice:wrong@{}", proxy))),
+            format!("SOCKS5 proxy {}: username or password rejected", proxy)
+        );
+    }
+
+    #[test]
+    fn test_proxy_requiring_authentication() {
+        let proxy = spawn_proxy(Stub::Password);
+        assert_eq!(
+            connection_error(via(&proxy.to_string())),
+            format!("SOCKS5 proxy {}: authentication required", proxy)
+        );
+    }
+
+    #[test]
+    fn test_refused_connect_is_explained() {
+        let proxy = spawn_proxy(Stub::Refuse);
+        assert_eq!(
+            connection_error(via(&proxy.to_string())),
+            format!(
+                "SOCKS5 proxy {}: CONNECT to socket-1.lan:8080 failed with reply 5: \
+                 connection refused by the target",
+                proxy
+            )
+        );
+
+        let proxy = spawn_proxy(Stub::Open);
+        assert!(SmartSocketClient::with_config(via(&proxy.to_string())).is_ok());
+    }
+
+    #[test]
+    fn test_parse_proxy() {
+        let proxy: Socks5Proxy = "jump.lan:1080".parse().unwrap();
+        assert_eq!(proxy.address.to_string(), "jump.lan:1080");
+        assert_eq!(proxy.credentials, None);
+
+        let proxy: Socks5Proxy = "alice:p@ss@[::1]:1080".parse().unwrap();
+        assert_eq!(proxy.address.to_string(), "[::1]:1080");
+        assert_eq!(
+            proxy.credentials,
+            Some(("alice".to_string(), "p@ss".to_string()))
+        );
+        assert!(!format!("{:?}", proxy).contains("p@ss"));
+
+        for invalid in [
+            "alice@jump.lan:1080",
+            ":secret@jump.lan:1080",
+            "alice:@jump.lan:1080",
+        ] {
+            assert_eq!(
+                invalid.parse::<Socks5Proxy>(),
+                Err(ConfigError::InvalidCredentials)
+            );
+        }
+        assert_eq!(
+            "jump.lan".parse::<Socks5Proxy>(),
+            Err(ConfigError::MissingPort("jump.lan".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_connect_sends_domain_names_unresolved() {
+        let proxy = "127.0.0.1:1080".parse().unwrap();
+        let mut stream = Scripted::new(&[&[5, 0], &[5, 0, 0, 3, 3], b"hub", &[0, 80]]);
+        connect(&mut stream, &proxy, &"socket-1.lan:8080".parse().unwrap()).unwrap();
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 12];
+        expected.extend_from_slice(b"socket-1.lan");
+        expected.extend_from_slice(&[0x1f, 0x90]);
+        assert_eq!(stream.sent, expected);
+        assert_eq!(stream.replies.position(), 12);
+    }
+
+    #[test]
+    fn test_unexpected_replies() {
+        let proxy: Socks5Proxy = "127.0.0.1:1080".parse().unwrap();
+        let target: Address = "[::1]:8080".parse().unwrap();
+        let cases: [(&[&[u8]], &str); 4] = [
+            (&[b"HTTP/1.1"], "not a SOCKS5 server"),
+            (&[&[5, 2]], "not a SOCKS5 server"),
+            (&[&[5, 0], &[5, 0, 0, 9]], "unknown address type 9"),
+            (&[&[5, 0], &[5, 0]], "handshake failed"),
+        ];
+        for (replies, reason) in cases {
+            let mut stream = Scripted::new(replies);
+            match connect(&mut stream, &proxy, &target) {
+                Err(ProtocolError::ConnectionError(message)) => {
+                    assert!(message.contains(reason), "{}", message)
+                }
+                other => panic!("Expected a connection error, got {:?}", other),
+            }
+        }
+
+        let mut stream = Scripted::new(&[&[5, 0], SUCCESS]);
+        connect(&mut stream, &proxy, &target).unwrap();
+        assert_eq!(&stream.sent[3..8], &[5, 1, 0, 4, 0]);
+    }
+}
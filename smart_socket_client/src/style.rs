@@ -0,0 +1,127 @@
+//! ANSI colors and column layout for the interactive client.
+
+use std::io::{self, IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Green,
+    Red,
+    Yellow,
+    Bold,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "\x1b[32m",
+            Color::Red => "\x1b[31m",
+            Color::Yellow => "\x1b[33m",
+            Color::Bold => "\x1b[1m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    /// Colors are used only when stdout is a terminal, and never with `--no-color`
+    /// or a non-empty `NO_COLOR` (see <https://no-color.org>).
+    pub fn detect(no_color_flag: bool) -> Self {
+        let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self::new(!no_color_flag && !no_color_env && io::stdout().is_terminal())
+    }
+
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Moves the cursor home and clears the screen; nothing when styling is off.
+    pub fn clear_screen(&self) -> &'static str {
+        if self.enabled {
+            "\x1b[H\x1b[2J"
+        } else {
+            ""
+        }
+    }
+
+    pub fn paint(&self, color: Color, text: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", color.code(), text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Renders `rows` as an indented two-column block with the labels padded to a common width.
+pub fn columns(rows: &[(&str, String)]) -> String {
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    rows.iter()
+        .map(|(label, value)| {
+            let padding = " ".repeat(width - label.chars().count());
+            format!("  {}:{} {}", label, padding, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+pub fn strip(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(
+            Style::new(true).paint(Color::Green, "ON"),
+            "\x1b[32mON\x1b[0m"
+        );
+        assert_eq!(Style::new(false).paint(Color::Green, "ON"), "ON");
+        assert_eq!(strip(&Style::new(true).paint(Color::Red, "OFF")), "OFF");
+    }
+
+    #[test]
+    fn test_columns_are_aligned() {
+        let style = Style::new(true);
+        let block = columns(&[
+            ("State", style.paint(Color::Green, "ON")),
+            ("Power", "100W".to_string()),
+            ("Name", "Kitchen".to_string()),
+        ]);
+        assert_eq!(
+            strip(&block),
+            "  State: ON\n  Power: 100W\n  Name:  Kitchen"
+        );
+    }
+
+    #[test]
+    fn test_columns_count_characters_not_bytes() {
+        let block = columns(&[
+            ("Состояние", "ВКЛ".to_string()),
+            ("Имя", "Кухня".to_string()),
+        ]);
+        assert_eq!(block, "  Состояние: ВКЛ\n  Имя:       Кухня");
+    }
+}
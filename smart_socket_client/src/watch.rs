@@ -0,0 +1,260 @@
+//! Background STATUS polling that reports only changes, for dashboards and
+//! automations that do not want to poll themselves.
+
+use crate::{lock, ClientConfig, SmartSocketClient};
+use smart_socket_protocol::{ProtocolError, Response};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// The state reported by one `STATUS` reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketStatus {
+    pub is_on: bool,
+    pub power: u32,
+    pub tripped: bool,
+    pub locked: bool,
+}
+
+impl SocketStatus {
+    /// The status in a `STATUS` reply; anything else is an error.
+    pub fn from_response(response: Response) -> Result<Self, ProtocolError> {
+        match response {
+            Response::Status {
+                is_on,
+                power,
+                tripped,
+                locked,
+            } => Ok(Self {
+                is_on,
+                power,
+                tripped,
+                locked,
+            }),
+            Response::Error(err) => Err(ProtocolError::InvalidResponse(err)),
+            other => Err(ProtocolError::InvalidResponse(format!("{:?}", other))),
+        }
+    }
+}
+
+/// Two consecutive polls that disagreed, and when the second one was answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusChange {
+    pub from: SocketStatus,
+    pub to: SocketStatus,
+    pub at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum WatchEvent {
+    Changed(StatusChange),
+    /// A poll failed. The watcher reconnects and tries again on the next tick; the
+    /// last known status is kept, so a change during the outage is still reported.
+    Error(ProtocolError),
+}
+
+/// Polls `STATUS` on its own connection; see [`SmartSocketClient::watch`].
+/// Dropping the watcher stops the thread.
+pub struct StatusWatcher {
+    events: Receiver<WatchEvent>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatusWatcher {
+    pub(crate) fn spawn(config: ClientConfig, interval: Duration) -> Self {
+        let (sender, events) = mpsc::channel();
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let handle = thread::spawn(move || poll(config, interval, &signal, &sender));
+        Self {
+            events,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Changes and poll errors, in the order they happened.
+    pub fn events(&self) -> &Receiver<WatchEvent> {
+        &self.events
+    }
+}
+
+impl Drop for StatusWatcher {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stop;
+        *lock(stopped) = true;
+        wakeup.notify_all();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                crate::log("Status watcher thread panicked");
+            }
+        }
+    }
+}
+
+fn poll_once(
+    client: &mut Option<SmartSocketClient<crate::ClientStream>>,
+    config: &ClientConfig,
+) -> Result<SocketStatus, ProtocolError> {
+    let connected = match client {
+        Some(client) if client.is_connected() => client,
+        _ => client.insert(SmartSocketClient::with_config(config.clone())?),
+    };
+    SocketStatus::from_response(connected.get_status()?)
+}
+
+/// The watcher thread: polls right away, then every `interval` until stopped or
+/// until the watcher is dropped.
+fn poll(
+    config: ClientConfig,
+    interval: Duration,
+    stop: &(Mutex<bool>, Condvar),
+    events: &Sender<WatchEvent>,
+) {
+    let mut client = None;
+    let mut last: Option<SocketStatus> = None;
+    loop {
+        let event = match poll_once(&mut client, &config) {
+            Ok(status) => {
+                let previous = last.replace(status);
+                match previous {
+                    Some(from) if from != status => Some(WatchEvent::Changed(StatusChange {
+                        from,
+                        to: status,
+                        at: SystemTime::now(),
+                    })),
+                    _ => None,
+                }
+            }
+            Err(e) => Some(WatchEvent::Error(e)),
+        };
+        if let Some(event) = event {
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+
+        let (stopped, wakeup) = stop;
+        let (stopped, _) = wakeup
+            .wait_timeout_while(lock(stopped), interval, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        if *stopped {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::{read_message, serialize_message, Command};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    /// A switch shared by every connection, answering ON, OFF and STATUS.
+    fn spawn_switch_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let on = Arc::new(AtomicBool::new(false));
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let on = on.clone();
+                thread::spawn(move || {
+                    while let Ok(message) = read_message(&mut stream) {
+                        let reply = match Command::from_str(&message) {
+                            Ok(Command::TurnOn) => {
+                                on.store(true, Ordering::SeqCst);
+                                "OK:Socket turned on".to_string()
+                            }
+                            Ok(Command::TurnOff) => {
+                                on.store(false, Ordering::SeqCst);
+                                "OK:Socket turned off".to_string()
+                            }
+                            Ok(Command::GetStatus) if on.load(Ordering::SeqCst) => {
+                                "STATUS:ON:100".to_string()
+                            }
+                            Ok(Command::GetStatus) => "STATUS:OFF:0".to_string(),
+                            _ => "ERROR:Unsupported".to_string(),
+                        };
+                        if stream
+                            .write_all(&serialize_message(&reply).unwrap())
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn config(addr: SocketAddr) -> ClientConfig {
+        ClientConfig {
+            address: addr.into(),
+            banner_timeout: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_one_change_is_reported_once() {
+        let addr = spawn_switch_server();
+        let mut client = SmartSocketClient::with_config(config(addr)).unwrap();
+        let watcher = client.watch(Duration::from_millis(20));
+        // Let the watcher take its baseline before flipping the socket.
+        thread::sleep(Duration::from_millis(100));
+
+        let before = SystemTime::now();
+        client.turn_on().unwrap();
+        match watcher.events().recv_timeout(Duration::from_secs(2)) {
+            Ok(WatchEvent::Changed(change)) => {
+                assert!(!change.from.is_on);
+                assert_eq!(
+                    change.to,
+                    SocketStatus {
+                        is_on: true,
+                        power: 100,
+                        tripped: false,
+                        locked: false,
+                    }
+                );
+                assert!(change.at >= before);
+            }
+            other => panic!("Expected a change, got {:?}", other),
+        }
+        // Further polls see the same status and stay quiet.
+        assert!(watcher
+            .events()
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn test_poll_errors_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let watcher = StatusWatcher::spawn(config(addr), Duration::from_millis(20));
+        assert!(matches!(
+            watcher.events().recv_timeout(Duration::from_secs(5)),
+            Ok(WatchEvent::Error(ProtocolError::ConnectionError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_drop_stops_the_watcher_promptly() {
+        let addr = spawn_switch_server();
+        let watcher = StatusWatcher::spawn(config(addr), Duration::from_secs(3600));
+        thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        drop(watcher);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,505 @@
+use serde_json::{json, Value};
+use smart_socket_client::pool::{PoolConfig, SocketClientPool};
+use smart_socket_protocol::logging::Logger;
+use smart_socket_protocol::{Address, Command, ErrorCode, ProtocolError, Response};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+static LOGGER: Logger = Logger::new();
+
+pub fn log(message: &str) {
+    LOGGER.log(message);
+}
+
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    pub address: Address,
+    /// Connections to the socket server, shared by the HTTP requests.
+    pub upstream: PoolConfig,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            address: Address::Ip(SocketAddr::from(([127, 0, 0, 1], 8090))),
+            upstream: PoolConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum GatewayError {
+    /// The socket server could not be reached (HTTP 502).
+    Upstream(ProtocolError),
+    /// The socket server replied with something we could not use (HTTP 500).
+    Protocol(ProtocolError),
+}
+
+/// Pooled upstream connections, opened on demand and replaced after connection failures.
+struct Upstream {
+    pool: SocketClientPool,
+    size: usize,
+}
+
+impl Upstream {
+    fn new(config: PoolConfig) -> Self {
+        Self {
+            size: config.size,
+            pool: SocketClientPool::new(config),
+        }
+    }
+
+    fn execute(&self, command: Command) -> Result<Response, GatewayError> {
+        // Idle connections may have gone stale, e.g. because the server restarted.
+        // Each failure discards one of them, so after `size` failures the next
+        // checkout connects afresh, and failing to connect ends the retries.
+        let mut attempts = 0;
+        loop {
+            let mut client = self.pool.checkout().map_err(GatewayError::Upstream)?;
+            match client.send_command(command.clone()) {
+                Ok(response) => return Ok(response),
+                Err(
+                    e @ (ProtocolError::ConnectionError(_)
+                    | ProtocolError::ConnectionClosed
+                    | ProtocolError::PeerUnreachable(_)),
+                ) => {
+                    log(&format!("Upstream connection failed: {}", e));
+                    attempts += 1;
+                    if attempts > self.size {
+                        return Err(GatewayError::Upstream(e));
+                    }
+                }
+                Err(e) => return Err(GatewayError::Protocol(e)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<HttpRequest> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Malformed request line",
+        ));
+    };
+
+    let mut content_length = 0usize;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    // Request bodies are not used by any route; drain them.
+    io::copy(&mut reader.take(content_length as u64), &mut io::sink())?;
+
+    Ok(HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+    })
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        409 => "Conflict",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        _ => "Unknown",
+    }
+}
+
+fn write_response<W: Write>(writer: &mut W, status: u16, body: &Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason_phrase(status),
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+/// Maps a socket protocol response to an HTTP status and JSON body.
+fn response_to_json(response: &Response) -> (u16, Value) {
+    match response {
+        Response::Ok(Some(message)) => (200, json!({ "ok": true, "message": message })),
+        Response::Ok(None) => (200, json!({ "ok": true })),
+        Response::Status {
+            is_on,
+            power,
+            tripped,
+            locked,
+        } => (
+            200,
+            json!({ "is_on": is_on, "power": power, "tripped": tripped, "locked": locked }),
+        ),
+        Response::Info(info) => (
+            200,
+            json!({
+                "name": info.name,
+                "power": info.power,
+                "firmware": info.firmware,
+                "uptime": info.uptime,
+                "build": info.build,
+                "location": info.location,
+                "addresses": info.addresses,
+                "protocol": info.protocol,
+                "capabilities": info.capabilities,
+                "read_only": info.read_only,
+            }),
+        ),
+        Response::Stats(stats) => (
+            200,
+            json!({
+                "uptime": stats.uptime,
+                "connections": stats.connections,
+                "active": stats.active,
+                "commands": stats.commands,
+                "errors": stats.errors,
+                "switches": stats.switches,
+                "on_secs": stats.on_secs,
+            }),
+        ),
+        Response::Level(level) => (200, json!({ "level": level })),
+        Response::Pong => (200, json!({ "pong": true })),
+        Response::Error(error) if error.starts_with(&ErrorCode::Unauthorized.to_string()) => {
+            (403, json!({ "error": error }))
+        }
+        Response::Error(error)
+            if error.starts_with(&ErrorCode::Tripped.to_string())
+                || error.starts_with(&ErrorCode::Locked.to_string())
+                || error.starts_with(&ErrorCode::Overload.to_string())
+                || error.starts_with(&ErrorCode::ReadOnly.to_string()) =>
+        {
+            (409, json!({ "error": error }))
+        }
+        Response::Error(error) => (500, json!({ "error": error })),
+        other => (
+            502,
+            json!({ "error": format!("Unsupported upstream response: {}", other) }),
+        ),
+    }
+}
+
+fn route(upstream: &Upstream, request: &HttpRequest) -> (u16, Value) {
+    let command = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => Command::GetStatus,
+        ("GET", "/info") => Command::GetInfo,
+        ("GET", "/stats") => Command::GetStats,
+        ("POST", "/on") => Command::TurnOn,
+        ("POST", "/off") => Command::TurnOff,
+        ("POST", "/reset") => Command::ResetTrip,
+        (_, "/status" | "/info" | "/stats" | "/on" | "/off" | "/reset") => {
+            return (405, json!({ "error": "Method not allowed" }))
+        }
+        _ => return (404, json!({ "error": "Not found" })),
+    };
+
+    match upstream.execute(command) {
+        Ok(response) => response_to_json(&response),
+        Err(GatewayError::Upstream(e)) => (502, json!({ "error": e.to_string() })),
+        Err(GatewayError::Protocol(e)) => (500, json!({ "error": e.to_string() })),
+    }
+}
+
+fn handle_connection(stream: TcpStream, upstream: &Upstream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let (status, body) = match read_request(&mut reader) {
+        Ok(request) => {
+            let (status, body) = route(upstream, &request);
+            log(&format!(
+                "{} {} -> {}",
+                request.method, request.path, status
+            ));
+            (status, body)
+        }
+        Err(e) => (400, json!({ "error": e.to_string() })),
+    };
+    write_response(&mut writer, status, &body)
+}
+
+/// Handle to a running gateway.
+pub struct GatewayHandle {
+    local_addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl GatewayHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Waits for the accept loop to exit once `running` is cleared.
+    pub fn join(self) -> thread::Result<()> {
+        self.handle.join()
+    }
+}
+
+/// Binds the HTTP listener and spawns the accept loop.
+pub fn run_gateway(config: GatewayConfig, running: Arc<AtomicBool>) -> io::Result<GatewayHandle> {
+    let listener = TcpListener::bind(&config.address)?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+    let upstream = Arc::new(Upstream::new(config.upstream));
+
+    log(&format!("HTTP gateway is running on {}", local_addr));
+
+    let handle = thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let upstream = Arc::clone(&upstream);
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &upstream) {
+                            log(&format!("HTTP connection error: {}", e));
+                        }
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => log(&format!("Connection failed: {}", e)),
+            }
+        }
+        log("HTTP gateway stopped");
+    });
+
+    Ok(GatewayHandle { local_addr, handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_client::ClientConfig;
+    use smart_socket_server::server::{run_server, ConnectionContext, ServerConfig, ServerHandle};
+
+    /// The real socket server on an ephemeral loopback port.
+    fn start_upstream() -> ServerHandle {
+        let config = ServerConfig::builder()
+            .address(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .socket_name("Gateway Socket")
+            .socket_power(2000)
+            .build()
+            .unwrap();
+        let context = ConnectionContext::from_config(&config).unwrap();
+        run_server(&config.addresses, false, context).unwrap()
+    }
+
+    fn start_gateway(upstream: SocketAddr, size: usize) -> (GatewayHandle, Arc<AtomicBool>) {
+        let config = GatewayConfig {
+            address: SocketAddr::from(([127, 0, 0, 1], 0)).into(),
+            upstream: PoolConfig {
+                client: ClientConfig::builder()
+                    .address(upstream)
+                    .read_timeout(Duration::from_secs(2))
+                    .write_timeout(Duration::from_secs(2))
+                    .build()
+                    .unwrap(),
+                size,
+                ..PoolConfig::default()
+            },
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let gateway = run_gateway(config, running.clone()).unwrap();
+        (gateway, running)
+    }
+
+    fn http(addr: SocketAddr, method: &str, path: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+            method, path
+        )
+        .unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).unwrap();
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn stop(gateway: GatewayHandle, running: Arc<AtomicBool>) {
+        running.store(false, Ordering::SeqCst);
+        gateway.join().unwrap();
+    }
+
+    #[test]
+    fn test_all_routes() {
+        let server = start_upstream();
+        let (gateway, running) = start_gateway(server.local_addr(), 2);
+        let addr = gateway.local_addr();
+
+        let (status, body) = http(addr, "POST", "/on");
+        assert_eq!(status, 200);
+        assert_eq!(body["message"], "Socket turned on");
+
+        let (status, body) = http(addr, "GET", "/status");
+        assert_eq!(status, 200);
+        assert_eq!(body["is_on"], true);
+
+        let (status, body) = http(addr, "GET", "/info");
+        assert_eq!(status, 200);
+        assert_eq!(body["name"], "Gateway Socket");
+        assert_eq!(body["power"], 2000);
+
+        let (status, _) = http(addr, "POST", "/off");
+        assert_eq!(status, 200);
+        let (_, body) = http(addr, "GET", "/status");
+        assert_eq!(body["is_on"], false);
+
+        let (status, body) = http(addr, "POST", "/reset");
+        assert_eq!(status, 200);
+        assert_eq!(body["message"], "Socket was not tripped");
+
+        let (status, body) = http(addr, "GET", "/stats");
+        assert_eq!(status, 200);
+        assert_eq!(body["switches"], 2);
+        assert!(body["commands"].as_u64().unwrap() >= 7);
+
+        assert_eq!(http(addr, "GET", "/on").0, 405);
+        assert_eq!(http(addr, "GET", "/nope").0, 404);
+
+        stop(gateway, running);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_requests_share_the_pool() {
+        let server = start_upstream();
+        let (gateway, running) = start_gateway(server.local_addr(), 2);
+        let addr = gateway.local_addr();
+
+        let requests: Vec<_> = (0..8)
+            .map(|_| thread::spawn(move || http(addr, "GET", "/status").0))
+            .collect();
+        for request in requests {
+            assert_eq!(request.join().unwrap(), 200);
+        }
+        // Never more upstream connections than the pool holds.
+        let (_, body) = http(addr, "GET", "/stats");
+        assert!(body["connections"].as_u64().unwrap() <= 2, "{}", body);
+
+        stop(gateway, running);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_upstream_down_returns_bad_gateway() {
+        let server = start_upstream();
+        let upstream = server.local_addr();
+        let (gateway, running) = start_gateway(upstream, 2);
+        assert_eq!(http(gateway.local_addr(), "POST", "/on").0, 200);
+
+        // The server waits for its clients to leave, so the pooled connection
+        // has to go with the first gateway before it can stop.
+        stop(gateway, running);
+        server.shutdown().unwrap();
+
+        let (gateway, running) = start_gateway(upstream, 2);
+        let addr = gateway.local_addr();
+        for path in ["/status", "/info", "/stats"] {
+            let (status, body) = http(addr, "GET", path);
+            assert_eq!(status, 502, "{}", path);
+            assert!(
+                body["error"]
+                    .as_str()
+                    .unwrap()
+                    .contains("Failed to connect"),
+                "{}",
+                body
+            );
+        }
+        for path in ["/on", "/off", "/reset"] {
+            assert_eq!(http(addr, "POST", path).0, 502, "{}", path);
+        }
+
+        stop(gateway, running);
+    }
+
+    #[test]
+    fn test_response_mapping() {
+        assert_eq!(
+            response_to_json(&Response::Error(
+                "E_UNAUTHORIZED:Authentication required".into()
+            ))
+            .0,
+            403
+        );
+        assert_eq!(response_to_json(&Response::Error("boom".into())).0, 500);
+        assert_eq!(
+            response_to_json(&Response::Status {
+                is_on: true,
+                power: 5,
+                tripped: false,
+                locked: true,
+            }),
+            (
+                200,
+                json!({ "is_on": true, "power": 5, "tripped": false, "locked": true })
+            )
+        );
+        assert_eq!(
+            response_to_json(&Response::Ok(None)),
+            (200, json!({ "ok": true }))
+        );
+        assert_eq!(
+            response_to_json(&Response::Error(
+                "E_TRIPPED:Socket tripped on overload".into()
+            ))
+            .0,
+            409
+        );
+        assert_eq!(
+            response_to_json(&Response::Error("E_LOCKED:Socket is locked".into())).0,
+            409
+        );
+        assert_eq!(
+            response_to_json(&Response::Error("E_OVERLOAD:Load too high".into())).0,
+            409
+        );
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /on HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(
+            request,
+            HttpRequest {
+                method: "POST".to_string(),
+                path: "/on".to_string()
+            }
+        );
+        assert!(read_request(&mut &b"\r\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use smart_socket_http_gateway::{log, run_gateway, GatewayConfig};
+use smart_socket_protocol::shutdown::{Shutdown, GRACE_PERIOD};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = GatewayConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                config.address = args.next().ok_or("--listen needs an address")?.parse()?
+            }
+            "--upstream" => {
+                config.upstream.client.address =
+                    args.next().ok_or("--upstream needs an address")?.parse()?
+            }
+            "--pool-size" => {
+                config.upstream.size = args.next().ok_or("--pool-size needs a number")?.parse()?
+            }
+            other => return Err(format!("Unknown argument: {}", other).into()),
+        }
+    }
+    config.upstream.client.auth_token = std::env::var("SMART_SOCKET_TOKEN").ok();
+
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
+
+    ctrlc::set_handler(move || {
+        log("Shutdown signal received, stopping gateway...");
+        s.interrupt(GRACE_PERIOD);
+    })?;
+
+    let gateway = run_gateway(config, shutdown.running())?;
+    log("Press Ctrl+C to stop the gateway");
+
+    gateway
+        .join()
+        .unwrap_or_else(|e| log(&format!("Gateway thread join error: {:?}", e)));
+    log("Gateway shutdown complete");
+    Ok(())
+}
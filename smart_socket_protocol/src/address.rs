@@ -0,0 +1,342 @@
+//! Network addresses as they appear in configuration, checked when the config is
+//! built rather than when a connect or bind fails.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::vec;
+
+/// Longest host name DNS allows.
+const MAX_HOST_LEN: usize = 253;
+/// Longest label (the parts between dots) of a host name.
+const MAX_LABEL_LEN: usize = 63;
+
+/// What is wrong with a configured address or setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Empty,
+    /// An address list that needs at least one entry has none.
+    NoAddresses,
+    MissingPort(String),
+    InvalidPort(String),
+    /// Unparsable or unbracketed IPv6 literal, such as `[::1` or `::1:8080`.
+    InvalidIpv6(String),
+    /// Dotted numbers that are not an IPv4 address, such as `256.0.0.1`.
+    InvalidIpv4(String),
+    InvalidHost(String),
+    /// `user:password@` in front of a proxy address with an empty or over-long
+    /// part. The value is not kept, so the password never ends up in a log.
+    InvalidCredentials,
+    /// A timeout or interval of zero; names the setting.
+    ZeroDuration(&'static str),
+    /// A setting outside the values it allows; names the setting.
+    InvalidValue {
+        field: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Empty => write!(f, "Address is empty"),
+            ConfigError::NoAddresses => write!(f, "No address configured"),
+            ConfigError::MissingPort(address) => {
+                write!(f, "Invalid address '{}': missing port", address)
+            }
+            ConfigError::InvalidPort(address) => write!(
+                f,
+                "Invalid address '{}': port must be a number from 0 to 65535",
+                address
+            ),
+            ConfigError::InvalidIpv6(address) => write!(
+                f,
+                "Invalid address '{}': invalid IPv6 literal, expected e.g. [::1]:8080",
+                address
+            ),
+            ConfigError::InvalidIpv4(address) => {
+                write!(f, "Invalid address '{}': invalid IPv4 address", address)
+            }
+            ConfigError::InvalidHost(address) => {
+                write!(f, "Invalid address '{}': invalid host name", address)
+            }
+            ConfigError::InvalidCredentials => write!(
+                f,
+                "Invalid proxy credentials, expected user:password@host:port \
+                 with 1 to 255 bytes for each"
+            ),
+            ConfigError::ZeroDuration(field) => write!(f, "{} must be greater than zero", field),
+            ConfigError::InvalidValue { field, reason } => {
+                write!(f, "Invalid {}: {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// `<ip>:<port>` or `<host>:<port>`; IPv6 literals are written in brackets.
+/// Host names are resolved on every connect or bind, so a changed DNS record is
+/// picked up without a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Ip(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl Address {
+    pub fn port(&self) -> u16 {
+        match self {
+            Address::Ip(addr) => addr.port(),
+            Address::Host { port, .. } => *port,
+        }
+    }
+
+    /// The same host with another port, e.g. for trying the next free one.
+    pub fn with_port(&self, port: u16) -> Self {
+        match self {
+            Address::Ip(addr) => Address::Ip(SocketAddr::new(addr.ip(), port)),
+            Address::Host { host, .. } => Address::Host {
+                host: host.clone(),
+                port,
+            },
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        Address::Ip(addr)
+    }
+}
+
+fn valid_host(host: &str) -> bool {
+    host.len() <= MAX_HOST_LEN
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Parses a port, telling a missing one apart from a malformed one.
+fn parse_port(port: &str, address: &str) -> Result<u16, ConfigError> {
+    if port.is_empty() {
+        return Err(ConfigError::MissingPort(address.to_string()));
+    }
+    // `u16::from_str` accepts a leading `+`, which no one means in an address.
+    if !port.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ConfigError::InvalidPort(address.to_string()));
+    }
+    port.parse()
+        .map_err(|_| ConfigError::InvalidPort(address.to_string()))
+}
+
+impl FromStr for Address {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ConfigError::Empty);
+        }
+        if let Some(rest) = s.strip_prefix('[') {
+            let (ip, after) = rest
+                .split_once(']')
+                .ok_or_else(|| ConfigError::InvalidIpv6(s.to_string()))?;
+            let ip: Ipv6Addr = ip
+                .parse()
+                .map_err(|_| ConfigError::InvalidIpv6(s.to_string()))?;
+            let port = match after.strip_prefix(':') {
+                Some(port) => parse_port(port, s)?,
+                None if after.is_empty() => return Err(ConfigError::MissingPort(s.to_string())),
+                None => return Err(ConfigError::InvalidIpv6(s.to_string())),
+            };
+            return Ok(Address::Ip(SocketAddr::new(IpAddr::V6(ip), port)));
+        }
+
+        let Some((host, port)) = s.rsplit_once(':') else {
+            return Err(ConfigError::MissingPort(s.to_string()));
+        };
+        if host.contains(':') {
+            return Err(ConfigError::InvalidIpv6(s.to_string()));
+        }
+        let port = parse_port(port, s)?;
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            return Ok(Address::Ip(SocketAddr::new(IpAddr::V4(ip), port)));
+        }
+        if !host.is_empty() && host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return Err(ConfigError::InvalidIpv4(s.to_string()));
+        }
+        if !valid_host(host) {
+            return Err(ConfigError::InvalidHost(s.to_string()));
+        }
+        Ok(Address::Host {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Host { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl ToSocketAddrs for Address {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        match self {
+            Address::Ip(addr) => Ok(vec![*addr].into_iter()),
+            Address::Host { host, port } => (host.as_str(), *port).to_socket_addrs(),
+        }
+    }
+}
+
+/// What config builders accept as an address: text, checked when the config is
+/// built, or an address that is already known to be valid.
+pub trait IntoAddress {
+    fn into_address(self) -> Result<Address, ConfigError>;
+}
+
+impl IntoAddress for &str {
+    fn into_address(self) -> Result<Address, ConfigError> {
+        self.parse()
+    }
+}
+
+impl IntoAddress for String {
+    fn into_address(self) -> Result<Address, ConfigError> {
+        self.parse()
+    }
+}
+
+impl IntoAddress for Address {
+    fn into_address(self) -> Result<Address, ConfigError> {
+        Ok(self)
+    }
+}
+
+impl IntoAddress for SocketAddr {
+    fn into_address(self) -> Result<Address, ConfigError> {
+        Ok(self.into())
+    }
+}
+
+/// Parses a `,`-separated list such as `127.0.0.1:8080,[::1]:8080`, ignoring
+/// whitespace around entries. At least one address is required.
+pub fn parse_list(s: &str) -> Result<Vec<Address>, ConfigError> {
+    let addresses = s
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if addresses.is_empty() {
+        return Err(ConfigError::NoAddresses);
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_addresses() {
+        let ip = |s: &str| Address::Ip(s.parse().unwrap());
+        let host = |host: &str, port| Address::Host {
+            host: host.to_string(),
+            port,
+        };
+        let cases = [
+            ("127.0.0.1:8080", ip("127.0.0.1:8080")),
+            ("0.0.0.0:0", ip("0.0.0.0:0")),
+            ("[::1]:8080", ip("[::1]:8080")),
+            ("[::]:0", ip("[::]:0")),
+            ("[fe80::1:2]:65535", ip("[fe80::1:2]:65535")),
+            ("localhost:8080", host("localhost", 8080)),
+            (
+                "Collector.Example.com:8081",
+                host("collector.example.com", 8081),
+            ),
+            ("socket-1:1", host("socket-1", 1)),
+        ];
+        for (input, expected) in cases {
+            let address: Address = input.parse().expect(input);
+            assert_eq!(address, expected, "{:?}", input);
+            assert_eq!(address.to_string().parse::<Address>().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_invalid_addresses() {
+        use ConfigError::*;
+        let cases = [
+            ("", Empty),
+            ("127.0.0.1", MissingPort("127.0.0.1".into())),
+            ("127.0.0.1:", MissingPort("127.0.0.1:".into())),
+            ("localhost", MissingPort("localhost".into())),
+            ("[::1]", MissingPort("[::1]".into())),
+            ("127.0.0.1:65536", InvalidPort("127.0.0.1:65536".into())),
+            ("127.0.0.1:http", InvalidPort("127.0.0.1:http".into())),
+            ("127.0.0.1:+80", InvalidPort("127.0.0.1:+80".into())),
+            ("[::1]:-1", InvalidPort("[::1]:-1".into())),
+            ("::1", InvalidIpv6("::1".into())),
+            ("::1:8080", InvalidIpv6("::1:8080".into())),
+            ("[::1:8080", InvalidIpv6("[::1:8080".into())),
+            ("[zz::1]:8080", InvalidIpv6("[zz::1]:8080".into())),
+            ("[127.0.0.1]:8080", InvalidIpv6("[127.0.0.1]:8080".into())),
+            ("[::1]8080", InvalidIpv6("[::1]8080".into())),
+            ("256.0.0.1:80", InvalidIpv4("256.0.0.1:80".into())),
+            ("1.2.3:80", InvalidIpv4("1.2.3:80".into())),
+            (":8080", InvalidHost(":8080".into())),
+            ("my host:80", InvalidHost("my host:80".into())),
+            ("-host:80", InvalidHost("-host:80".into())),
+            ("host..lan:80", InvalidHost("host..lan:80".into())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<Address>(), Err(expected), "{:?}", input);
+        }
+        assert_eq!(
+            "127.0.0.1".parse::<Address>().unwrap_err().to_string(),
+            "Invalid address '127.0.0.1': missing port"
+        );
+    }
+
+    #[test]
+    fn test_port_helpers_and_resolution() {
+        let address: Address = "[::1]:8080".parse().unwrap();
+        assert_eq!(address.port(), 8080);
+        assert_eq!(address.with_port(0).to_string(), "[::1]:0");
+        assert_eq!(
+            address.to_socket_addrs().unwrap().collect::<Vec<_>>(),
+            ["[::1]:8080".parse::<SocketAddr>().unwrap()]
+        );
+        let host: Address = "localhost:80".parse().unwrap();
+        assert_eq!(host.with_port(81).to_string(), "localhost:81");
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list(" 127.0.0.1:8080 , [::1]:8080,").unwrap(),
+            [
+                "127.0.0.1:8080".parse::<Address>().unwrap(),
+                "[::1]:8080".parse().unwrap()
+            ]
+        );
+        assert_eq!(parse_list(" , "), Err(ConfigError::NoAddresses));
+        assert_eq!(
+            parse_list("127.0.0.1:8080,127.0.0.1"),
+            Err(ConfigError::MissingPort("127.0.0.1".into()))
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Status beacons: a UDP datagram a server sends at a fixed interval, so that
+//! monitoring can listen passively instead of polling.
+//!
+//! A beacon is one line of text,
+//! `BEACON:name=<...>;state=<ON|OFF>;power=<u32>;uptime_secs=<secs>`, followed by
+//! `;last_error=<...>` once the server has answered a command with an error.
+//! Values are escaped as in `INFO`, and unknown keys are ignored.
+
+use crate::info::{escape, split_fields};
+use crate::ProtocolError;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const PREFIX: &str = "BEACON:";
+
+/// Largest beacon a listener reads; longer datagrams are cut off and fail to parse.
+pub const MAX_BEACON_LEN: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Beacon {
+    pub name: String,
+    pub is_on: bool,
+    pub power: u32,
+    pub uptime_secs: u64,
+    /// The last `ERROR` response the server sent, as `<code>:<message>`.
+    pub last_error: Option<String>,
+}
+
+impl Beacon {
+    /// Waits up to `timeout` for a beacon on `socket` and returns it with its
+    /// sender. Datagrams that are not beacons are skipped; `None` means none came.
+    pub fn listen(
+        socket: &UdpSocket,
+        timeout: Duration,
+    ) -> io::Result<Option<(SocketAddr, Beacon)>> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0; MAX_BEACON_LEN];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let (len, sender) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            let beacon = std::str::from_utf8(&buffer[..len])
+                .ok()
+                .and_then(|text| text.parse().ok());
+            if let Some(beacon) = beacon {
+                return Ok(Some((sender, beacon)));
+            }
+        }
+    }
+}
+
+impl fmt::Display for Beacon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}name={};state={};power={};uptime_secs={}",
+            PREFIX,
+            escape(&self.name),
+            if self.is_on { "ON" } else { "OFF" },
+            self.power,
+            self.uptime_secs
+        )?;
+        if let Some(error) = &self.last_error {
+            write!(f, ";last_error={}", escape(error))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Beacon {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let payload = s
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| ProtocolError::ParseError("Not a beacon".to_string()))?;
+        let invalid = |key: &str, value: &str| {
+            ProtocolError::ParseError(format!("Invalid beacon {}: {}", key, value))
+        };
+        let (mut name, mut is_on, mut power, mut uptime_secs, mut last_error) =
+            (None, None, None, None, None);
+        for field in split_fields(payload) {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "state" => {
+                    is_on = Some(match value {
+                        "ON" => true,
+                        "OFF" => false,
+                        _ => return Err(invalid(key, value)),
+                    })
+                }
+                "power" => power = Some(value.parse().map_err(|_| invalid(key, value))?),
+                "uptime_secs" => {
+                    uptime_secs = Some(value.parse().map_err(|_| invalid(key, value))?)
+                }
+                "last_error" => last_error = Some(value.to_string()),
+                _ => continue,
+            }
+        }
+        let missing = |key: &str| ProtocolError::ParseError(format!("Beacon without {}", key));
+        Ok(Beacon {
+            name: name.ok_or_else(|| missing("name"))?,
+            is_on: is_on.ok_or_else(|| missing("state"))?,
+            power: power.ok_or_else(|| missing("power"))?,
+            uptime_secs: uptime_secs.ok_or_else(|| missing("uptime_secs"))?,
+            last_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Beacon {
+        Beacon {
+            name: "Hall; Lamp".to_string(),
+            is_on: true,
+            power: 1500,
+            uptime_secs: 3600,
+            last_error: Some("E_TRIPPED:Socket is tripped".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let beacon = sample();
+        assert_eq!(
+            beacon.to_string(),
+            "BEACON:name=Hall\\; Lamp;state=ON;power=1500;uptime_secs=3600;\
+             last_error=E_TRIPPED:Socket is tripped"
+        );
+        assert_eq!(beacon.to_string().parse::<Beacon>().unwrap(), beacon);
+
+        let quiet = Beacon {
+            is_on: false,
+            last_error: None,
+            ..sample()
+        };
+        assert_eq!(quiet.to_string().parse::<Beacon>().unwrap(), quiet);
+        // Newer servers may add keys.
+        let extended = format!("{};temperature=40", quiet);
+        assert_eq!(extended.parse::<Beacon>().unwrap(), quiet);
+    }
+
+    #[test]
+    fn test_invalid_beacons() {
+        for invalid in [
+            "",
+            "STATUS:ON:1500",
+            "BEACON:",
+            "BEACON:name=Lamp;state=ON;power=1500",
+            "BEACON:name=Lamp;state=DIM;power=1500;uptime_secs=1",
+            "BEACON:name=Lamp;state=ON;power=-1;uptime_secs=1",
+        ] {
+            assert!(invalid.parse::<Beacon>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_listen_skips_other_datagrams() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to = listener.local_addr().unwrap();
+        sender.send_to(b"hello", to).unwrap();
+        sender.send_to(&[0xff, 0xfe], to).unwrap();
+        sender.send_to(sample().to_string().as_bytes(), to).unwrap();
+
+        let (from, beacon) = Beacon::listen(&listener, Duration::from_secs(2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(from, sender.local_addr().unwrap());
+        assert_eq!(beacon, sample());
+
+        let started = Instant::now();
+        let timeout = Duration::from_millis(100);
+        assert_eq!(Beacon::listen(&listener, timeout).unwrap(), None);
+        assert!(started.elapsed() >= timeout);
+    }
+}
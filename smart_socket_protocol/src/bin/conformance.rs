@@ -0,0 +1,63 @@
+//! Runs the protocol conformance suite against a server.
+//!
+//! Usage: `conformance <address> [check...]`, or `conformance --list` for the check
+//! names. All checks run when none are named. `SMART_SOCKET_TOKEN` is sent with
+//! `AUTH` on every connection.
+
+use smart_socket_protocol::conformance::{self, Check, ConformanceConfig};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let address = match args.next().as_deref() {
+        Some("--list") => {
+            for check in Check::ALL {
+                println!("{}", check);
+            }
+            return ExitCode::SUCCESS;
+        }
+        Some(address) => match address.parse() {
+            Ok(address) => address,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            eprintln!("Usage: conformance <address> [check...]");
+            return ExitCode::FAILURE;
+        }
+    };
+    let checks = match args
+        .map(|name| name.parse())
+        .collect::<Result<Vec<Check>, _>>()
+    {
+        Ok(checks) if checks.is_empty() => Check::ALL.to_vec(),
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = ConformanceConfig {
+        address,
+        auth_token: std::env::var("SMART_SOCKET_TOKEN").ok(),
+        checks,
+        ..Default::default()
+    };
+    match conformance::run(&config) {
+        Ok(report) => {
+            print!("{}", report);
+            if report.passed() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("Cannot run the suite against {}: {}", config.address, e);
+            ExitCode::FAILURE
+        }
+    }
+}
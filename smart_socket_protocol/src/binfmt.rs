@@ -0,0 +1,428 @@
+//! Compact binary responses, for clients that poll `STATUS` often enough for the
+//! bytes to matter.
+//!
+//! A client asks for them by sending [`NEGOTIATE`] as its first frame. The server
+//! confirms with a text `OK`, or rejects it with a text `ERROR` if it predates
+//! this mode, and from then on writes every response of that connection
+//! with [`write_response`]. Commands stay text in both modes.
+//!
+//! A binary response is one frame, length-prefixed as usual, whose first byte is
+//! a tag:
+//!
+//! | Tag    | Response      | Rest of the frame                                   |
+//! |--------|---------------|-----------------------------------------------------|
+//! | `0x00` | any other     | the response in its text form                       |
+//! | `0x01` | `OK`          | the message as UTF-8, empty for a bare `OK`         |
+//! | `0x02` | `STATUS`      | state byte (bits: 0 on, 1 tripped, 2 locked), power as `u32` |
+//! | `0x03` | `TEMP` answer | celsius as `f64`, age in seconds as `u64`           |
+//! | `0x04` | `LEVEL`       | the level byte                                      |
+//! | `0x05` | `PONG`        | nothing                                             |
+//! | `0x06` | `ERROR`       | `<code>:<message>` as UTF-8                         |
+//!
+//! Numbers are big-endian, like the length prefix.
+//!
+//! A client that also sends [`NEGOTIATE_TIMED`] as its second frame asks for
+//! the field `t`: the server then sets the [`TIMED`] bit of the tag and appends
+//! the microseconds from receiving the command to encoding the response, as
+//! `u64`. It tells server time from network time. The server confirms with a
+//! binary `OK`; an older one refuses it with a binary `ERROR` and leaves `t`
+//! out, as it does for every client that did not ask, so a decoder without
+//! [`decode_timed`] never sees the bit.
+
+use crate::{
+    read_frame_limited, serialize_frame, ProtocolError, Response, MAX_MESSAGE_LEN, MAX_RESPONSE_LEN,
+};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// First frame a client sends to receive binary responses.
+pub const NEGOTIATE: &str = "PROTO:BIN";
+
+/// Second frame, right after [`NEGOTIATE`], of a client that wants the server
+/// time in every response; see the [module docs](self).
+pub const NEGOTIATE_TIMED: &str = "PROTO:BIN:T";
+
+const TAG_TEXT: u8 = 0x00;
+const TAG_OK: u8 = 0x01;
+const TAG_STATUS: u8 = 0x02;
+const TAG_TEMPERATURE: u8 = 0x03;
+const TAG_LEVEL: u8 = 0x04;
+const TAG_PONG: u8 = 0x05;
+const TAG_ERROR: u8 = 0x06;
+
+/// Tag bit marking a frame that ends with the server's processing time.
+pub const TIMED: u8 = 0x80;
+
+const STATE_ON: u8 = 0b01;
+const STATE_TRIPPED: u8 = 0b10;
+const STATE_LOCKED: u8 = 0b100;
+
+/// How a connection's responses are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    /// The text responses every server sends.
+    #[default]
+    Text,
+    /// Responses encoded by this module, negotiated with [`NEGOTIATE`].
+    Binary,
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(WireFormat::Text),
+            "binary" => Ok(WireFormat::Binary),
+            _ => Err(format!(
+                "Unknown wire format '{}', expected text or binary",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Text => write!(f, "text"),
+            WireFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+/// Encodes `response` without the length prefix.
+pub fn encode(response: &Response) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(17);
+    match response {
+        Response::Ok(message) => {
+            bytes.push(TAG_OK);
+            bytes.extend_from_slice(message.as_deref().unwrap_or_default().as_bytes());
+        }
+        Response::Status {
+            is_on,
+            power,
+            tripped,
+            locked,
+        } => {
+            let mut state = 0;
+            if *is_on {
+                state |= STATE_ON;
+            }
+            if *tripped {
+                state |= STATE_TRIPPED;
+            }
+            if *locked {
+                state |= STATE_LOCKED;
+            }
+            bytes.extend_from_slice(&[TAG_STATUS, state]);
+            bytes.extend_from_slice(&power.to_be_bytes());
+        }
+        Response::Temperature { celsius, age_secs } => {
+            bytes.push(TAG_TEMPERATURE);
+            bytes.extend_from_slice(&celsius.to_be_bytes());
+            bytes.extend_from_slice(&age_secs.to_be_bytes());
+        }
+        Response::Level(level) => bytes.extend_from_slice(&[TAG_LEVEL, *level]),
+        Response::Pong => bytes.push(TAG_PONG),
+        Response::Error(error) => {
+            bytes.push(TAG_ERROR);
+            bytes.extend_from_slice(error.as_bytes());
+        }
+        Response::Reading { .. } | Response::Info(_) | Response::Stats(_) => {
+            bytes.push(TAG_TEXT);
+            bytes.extend_from_slice(response.to_string().as_bytes());
+        }
+    }
+    bytes
+}
+
+fn text(bytes: &[u8]) -> Result<&str, ProtocolError> {
+    std::str::from_utf8(bytes)
+        .map_err(|e| ProtocolError::ParseError(format!("Invalid UTF-8: {}", e)))
+}
+
+/// The `N` bytes after the tag, which must be all there is.
+fn fixed<const N: usize>(what: &str, rest: &[u8]) -> Result<[u8; N], ProtocolError> {
+    rest.try_into().map_err(|_| {
+        ProtocolError::ParseError(format!(
+            "Binary {} response has {} bytes after the tag, expected {}",
+            what,
+            rest.len(),
+            N
+        ))
+    })
+}
+
+/// Like [`encode`], followed by the time the server spent on the command.
+pub fn encode_timed(response: &Response, server_time: Duration) -> Vec<u8> {
+    let mut bytes = encode(response);
+    bytes[0] |= TIMED;
+    let micros = u64::try_from(server_time.as_micros()).unwrap_or(u64::MAX);
+    bytes.extend_from_slice(&micros.to_be_bytes());
+    bytes
+}
+
+/// Decodes a frame written by [`encode`] or [`encode_timed`], returning the
+/// server time if the frame has one.
+pub fn decode_timed(bytes: &[u8]) -> Result<(Response, Option<Duration>), ProtocolError> {
+    match bytes.first() {
+        Some(tag) if tag & TIMED != 0 => {
+            let Some(split) = bytes.len().checked_sub(8).filter(|split| *split > 0) else {
+                return Err(ProtocolError::ParseError(
+                    "Timed binary response is too short for its server time".to_string(),
+                ));
+            };
+            let (body, micros) = bytes.split_at(split);
+            let mut body = body.to_vec();
+            body[0] &= !TIMED;
+            let micros = u64::from_be_bytes(micros.try_into().expect("8 bytes"));
+            Ok((decode(&body)?, Some(Duration::from_micros(micros))))
+        }
+        _ => Ok((decode(bytes)?, None)),
+    }
+}
+
+/// Decodes a frame written by [`encode`]. Anything else, including an unknown
+/// tag, is a [`ProtocolError::ParseError`]; a text frame fails as its text would.
+/// A timed frame has to go through [`decode_timed`].
+pub fn decode(bytes: &[u8]) -> Result<Response, ProtocolError> {
+    let Some((&tag, rest)) = bytes.split_first() else {
+        return Err(ProtocolError::ParseError(
+            "Empty binary response".to_string(),
+        ));
+    };
+    match tag {
+        TAG_TEXT => Response::from_str(text(rest)?),
+        TAG_OK if rest.is_empty() => Ok(Response::Ok(None)),
+        TAG_OK => Ok(Response::ok(text(rest)?)),
+        TAG_STATUS => {
+            let [state, power @ ..] = fixed::<5>("STATUS", rest)?;
+            if state & !(STATE_ON | STATE_TRIPPED | STATE_LOCKED) != 0 {
+                return Err(ProtocolError::ParseError(format!(
+                    "Invalid status state byte: {:#04x}",
+                    state
+                )));
+            }
+            Ok(Response::Status {
+                is_on: state & STATE_ON != 0,
+                power: u32::from_be_bytes(power),
+                tripped: state & STATE_TRIPPED != 0,
+                locked: state & STATE_LOCKED != 0,
+            })
+        }
+        TAG_TEMPERATURE => {
+            let fields = fixed::<16>("TEMP", rest)?;
+            let (celsius, age_secs) = fields.split_at(8);
+            let celsius = f64::from_be_bytes(celsius.try_into().expect("8 bytes"));
+            if !celsius.is_finite() {
+                return Err(ProtocolError::ParseError(format!(
+                    "Invalid temperature: {}",
+                    celsius
+                )));
+            }
+            Ok(Response::Temperature {
+                celsius,
+                age_secs: u64::from_be_bytes(age_secs.try_into().expect("8 bytes")),
+            })
+        }
+        TAG_LEVEL => match fixed::<1>("LEVEL", rest)? {
+            [level] if level <= 100 => Ok(Response::Level(level)),
+            [level] => Err(ProtocolError::ParseError(format!(
+                "Invalid level: {}",
+                level
+            ))),
+        },
+        TAG_PONG => {
+            fixed::<0>("PONG", rest)?;
+            Ok(Response::Pong)
+        }
+        TAG_ERROR => Ok(Response::Error(text(rest)?.to_string())),
+        tag => Err(ProtocolError::ParseError(format!(
+            "Unknown binary response tag: {:#04x}",
+            tag
+        ))),
+    }
+}
+
+/// Writes `response` as one binary frame.
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    let frame = serialize_frame(&encode(response), MAX_MESSAGE_LEN)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    writer.write_all(&frame)
+}
+
+/// Writes `response` as one binary frame carrying `server_time`.
+pub fn write_response_timed<W: Write>(
+    writer: &mut W,
+    response: &Response,
+    server_time: Duration,
+) -> io::Result<()> {
+    let frame = serialize_frame(&encode_timed(response, server_time), MAX_MESSAGE_LEN)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    writer.write_all(&frame)
+}
+
+/// Reads one binary response, the counterpart of [`crate::read_response`].
+pub fn read_response<R: Read>(reader: &mut R) -> Result<Response, ProtocolError> {
+    read_response_timed(reader).map(|(response, _)| response)
+}
+
+/// Reads one binary response and the server time, if the server sent one.
+pub fn read_response_timed<R: Read>(
+    reader: &mut R,
+) -> Result<(Response, Option<Duration>), ProtocolError> {
+    decode_timed(&read_frame_limited(reader, MAX_RESPONSE_LEN)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serialize_message, DeviceInfo, ServerStats};
+
+    fn round_trip(response: Response) {
+        assert_eq!(decode(&encode(&response)).unwrap(), response);
+        let mut buffer = Vec::new();
+        write_response(&mut buffer, &response).unwrap();
+        assert_eq!(read_response(&mut &buffer[..]).unwrap(), response);
+    }
+
+    #[test]
+    fn test_every_response_round_trips() {
+        for is_on in [false, true] {
+            for tripped in [false, true] {
+                for locked in [false, true] {
+                    for power in [0, 1500, u32::MAX] {
+                        round_trip(Response::Status {
+                            is_on,
+                            power,
+                            tripped,
+                            locked,
+                        });
+                    }
+                }
+            }
+        }
+        round_trip(Response::Ok(None));
+        round_trip(Response::ok("Socket turned on"));
+        round_trip(Response::Temperature {
+            celsius: -12.25,
+            age_secs: u64::MAX,
+        });
+        round_trip(Response::Reading {
+            device_id: "hall".to_string(),
+            celsius: 21.5,
+            epoch_secs: 1_700_000_000,
+        });
+        round_trip(Response::Info(DeviceInfo {
+            name: "Лампа".to_string(),
+            ..DeviceInfo::default()
+        }));
+        round_trip(Response::Stats(ServerStats::default()));
+        round_trip(Response::Level(0));
+        round_trip(Response::Level(100));
+        round_trip(Response::Pong);
+        round_trip(Response::Error("E_TRIPPED:Socket is tripped".to_string()));
+    }
+
+    #[test]
+    fn test_malformed_frames_are_parse_errors() {
+        let parse_error = |bytes: &[u8]| matches!(decode(bytes), Err(ProtocolError::ParseError(_)));
+        assert!(parse_error(&[]));
+        for tag in 0x07..=0xff {
+            let Err(ProtocolError::ParseError(message)) = decode(&[tag]) else {
+                panic!("Expected a parse error for tag {:#04x}", tag);
+            };
+            assert_eq!(
+                message,
+                format!("Unknown binary response tag: {:#04x}", tag)
+            );
+        }
+        assert!(parse_error(&[TAG_STATUS, STATE_ON, 0, 0, 5]));
+        assert!(parse_error(&[TAG_STATUS, STATE_ON, 0, 0, 0, 5, 0]));
+        assert!(parse_error(&[TAG_STATUS, 0b1000, 0, 0, 0, 5]));
+        assert!(parse_error(&[TAG_LEVEL, 101]));
+        assert!(parse_error(&[TAG_LEVEL]));
+        assert!(parse_error(&[TAG_PONG, 0]));
+        assert!(parse_error(&[TAG_OK, 0xff]));
+        assert!(parse_error(b"\0STATUS:DIM:5"));
+        let mut nan = vec![TAG_TEMPERATURE];
+        nan.extend_from_slice(&f64::NAN.to_be_bytes());
+        nan.extend_from_slice(&0u64.to_be_bytes());
+        assert!(parse_error(&nan));
+    }
+
+    #[test]
+    fn test_server_time_is_optional() {
+        let status = Response::Status {
+            is_on: true,
+            power: 1500,
+            tripped: false,
+            locked: true,
+        };
+        let untimed = encode(&status);
+        assert_eq!(decode_timed(&untimed).unwrap(), (status.clone(), None));
+
+        let timed = encode_timed(&status, Duration::from_micros(1234));
+        assert_eq!(timed.len(), untimed.len() + 8);
+        assert_eq!(timed[0], TAG_STATUS | TIMED);
+        assert_eq!(
+            decode_timed(&timed).unwrap(),
+            (status.clone(), Some(Duration::from_micros(1234)))
+        );
+        let pong = encode_timed(&Response::Pong, Duration::ZERO);
+        assert_eq!(
+            decode_timed(&pong).unwrap(),
+            (Response::Pong, Some(Duration::ZERO))
+        );
+
+        let mut buffer = Vec::new();
+        write_response_timed(&mut buffer, &status, Duration::from_millis(3)).unwrap();
+        write_response(&mut buffer, &status).unwrap();
+        let mut reader = &buffer[..];
+        assert_eq!(
+            read_response_timed(&mut reader).unwrap().1,
+            Some(Duration::from_millis(3))
+        );
+        assert_eq!(read_response_timed(&mut reader).unwrap().1, None);
+        // Readers that ignore the time still get the response.
+        assert_eq!(read_response(&mut &buffer[..]).unwrap(), status);
+
+        let parse_error =
+            |bytes: &[u8]| matches!(decode_timed(bytes), Err(ProtocolError::ParseError(_)));
+        assert!(parse_error(&[TAG_PONG | TIMED, 0, 0, 0, 0, 0, 0, 0]));
+        assert!(parse_error(&[TAG_PONG | TIMED, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        assert!(parse_error(&[0x07 | TIMED, 0, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_status_poll_is_smaller() {
+        let status = Response::Status {
+            is_on: true,
+            power: 1500,
+            tripped: false,
+            locked: false,
+        };
+        let text = serialize_message(&status.to_string()).unwrap().len();
+        let mut binary = Vec::new();
+        write_response(&mut binary, &status).unwrap();
+        // STATUS:ON:1500 is 4 + 14 bytes as text, 4 + 6 as binary.
+        println!(
+            "STATUS poll response: {} bytes as text, {} as binary ({} saved)",
+            text,
+            binary.len(),
+            text - binary.len()
+        );
+        assert_eq!((text, binary.len()), (18, 10));
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!("Binary".parse(), Ok(WireFormat::Binary));
+        assert_eq!("text".parse(), Ok(WireFormat::Text));
+        assert!("json".parse::<WireFormat>().is_err());
+        assert_eq!(WireFormat::default().to_string(), "text");
+    }
+}
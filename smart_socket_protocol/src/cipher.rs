@@ -0,0 +1,399 @@
+//! Optional encryption of frame payloads with a pre-shared key, for peers that
+//! cannot run TLS but should not send ON and OFF in the clear.
+//!
+//! With a [`Psk`], every frame carries a random 12-byte nonce followed by the
+//! ChaCha20-Poly1305 ciphertext and tag of the payload, inside the usual length
+//! prefix. A frame that was altered, or sealed with another key, fails with
+//! [`ProtocolError::AuthFailed`]. [`Plain`] leaves frames as they are.
+//!
+//! There is no replay protection: the nonce is random rather than a counter and
+//! nothing records the nonces already seen, so a sealed frame captured on the
+//! link, such as an `ON`, opens just as well when an attacker sends it again.
+
+use crate::{read_frame_limited, read_full, serialize_frame, ProtocolError, MAX_MESSAGE_LEN};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+/// Bytes a [`Psk`] adds to every payload.
+pub const PSK_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// Turns payloads into what goes inside a frame and back.
+pub trait FrameCipher {
+    fn seal(&self, payload: &[u8]) -> Vec<u8>;
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, ProtocolError>;
+    /// How much longer a sealed payload is than the plain one.
+    fn overhead(&self) -> usize;
+}
+
+/// Frames as the protocol always sent them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plain;
+
+impl FrameCipher for Plain {
+    fn seal(&self, payload: &[u8]) -> Vec<u8> {
+        payload.to_vec()
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        Ok(sealed.to_vec())
+    }
+
+    fn overhead(&self) -> usize {
+        0
+    }
+}
+
+/// ChaCha20-Poly1305 with a 32-byte key both peers were configured with.
+#[derive(Clone)]
+pub struct Psk {
+    cipher: ChaCha20Poly1305,
+}
+
+impl Psk {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Reads the key from 64 hex digits, as it is written in settings.
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let hex = hex.trim();
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("the key must consist of hex digits".to_string());
+        }
+        if hex.len() != KEY_LEN * 2 {
+            return Err(format!(
+                "the key must be {} hex digits, got {}",
+                KEY_LEN * 2,
+                hex.len()
+            ));
+        }
+        let mut key = [0u8; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|e| format!("invalid key: {}", e))?;
+        }
+        Ok(Self::new(&key))
+    }
+}
+
+/// Never shows the key.
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Psk(***)")
+    }
+}
+
+impl FrameCipher for Psk {
+    fn seal(&self, payload: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .expect("ChaCha20-Poly1305 encrypts any frame-sized payload");
+        [nonce.as_slice(), &ciphertext].concat()
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        if sealed.len() < PSK_OVERHEAD {
+            return Err(ProtocolError::AuthFailed);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| ProtocolError::AuthFailed)
+    }
+
+    fn overhead(&self) -> usize {
+        PSK_OVERHEAD
+    }
+}
+
+/// Like [`crate::write_message`], with the payload sealed by `cipher`.
+pub fn write_message_with<W: Write>(
+    writer: &mut W,
+    message: &str,
+    cipher: &impl FrameCipher,
+) -> Result<(), ProtocolError> {
+    let frame = seal_frame(message.as_bytes(), cipher)?;
+    writer
+        .write_all(&frame)
+        .map_err(|e| ProtocolError::ConnectionError(format!("Failed to write message: {}", e)))
+}
+
+/// Like [`crate::read_message`], with the payload opened by `cipher`.
+pub fn read_message_with<R: Read>(
+    reader: &mut R,
+    cipher: &impl FrameCipher,
+) -> Result<String, ProtocolError> {
+    let sealed = read_frame_limited(reader, MAX_MESSAGE_LEN + cipher.overhead())?;
+    String::from_utf8(cipher.open(&sealed)?)
+        .map_err(|e| ProtocolError::ParseError(format!("Invalid UTF-8: {}", e)))
+}
+
+/// Checks the payload against the frame limit before sealing, so the limit
+/// means the same with and without a cipher.
+fn seal_frame(payload: &[u8], cipher: &impl FrameCipher) -> Result<Vec<u8>, ProtocolError> {
+    if payload.len() > MAX_MESSAGE_LEN {
+        return Err(ProtocolError::MessageTooLarge {
+            len: payload.len(),
+            max: MAX_MESSAGE_LEN,
+        });
+    }
+    serialize_frame(&cipher.seal(payload), MAX_MESSAGE_LEN + cipher.overhead())
+}
+
+/// Applies a [`FrameCipher`] to every frame passing through `inner`, so code
+/// that frames its own messages, such as chunked and binary responses, works
+/// unchanged over it.
+///
+/// Writes are collected until a whole frame is there, then sealed and sent.
+/// Reads open one frame at a time and hand out its plain form. A frame that
+/// fails to open is reported as an [`io::ErrorKind::InvalidData`] error
+/// carrying [`ProtocolError::AuthFailed`], which [`crate::read_message`]
+/// passes on as it is.
+pub struct CipherStream<S, C> {
+    inner: S,
+    cipher: C,
+    /// Plain bytes written that do not make up a whole frame yet.
+    outgoing: Vec<u8>,
+    /// The last frame opened, length prefix included, and how much of it was read.
+    incoming: Vec<u8>,
+    read_pos: usize,
+}
+
+impl<S, C: FrameCipher> CipherStream<S, C> {
+    pub fn new(inner: S, cipher: C) -> Self {
+        Self {
+            inner,
+            cipher,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            read_pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Read, C: FrameCipher> CipherStream<S, C> {
+    /// Reads one sealed frame; `None` on a clean close between frames. I/O
+    /// errors keep their kind, so a read timeout is still recognised as one.
+    fn read_sealed(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 4];
+        match read_full(&mut self.inner, &mut header)? {
+            0 => return Ok(None),
+            4 => {}
+            _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+        let len = u32::from_be_bytes(header) as usize;
+        let max = MAX_MESSAGE_LEN + self.cipher.overhead();
+        if len > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame of {} bytes exceeds the limit of {}", len, max),
+            ));
+        }
+        let mut sealed = vec![0u8; len];
+        self.inner.read_exact(&mut sealed)?;
+        Ok(Some(sealed))
+    }
+}
+
+/// Whether `e` is a [`CipherStream`] reporting a frame that failed to open.
+pub fn is_auth_failure(e: &io::Error) -> bool {
+    matches!(
+        e.get_ref().and_then(|inner| inner.downcast_ref()),
+        Some(ProtocolError::AuthFailed)
+    )
+}
+
+fn to_io_error(e: ProtocolError) -> io::Error {
+    match e {
+        ProtocolError::AuthFailed => io::Error::new(io::ErrorKind::InvalidData, e),
+        e => io::Error::other(e),
+    }
+}
+
+impl<S: Read, C: FrameCipher> Read for CipherStream<S, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_pos == self.incoming.len() {
+            let Some(sealed) = self.read_sealed()? else {
+                return Ok(0);
+            };
+            let payload = self.cipher.open(&sealed).map_err(to_io_error)?;
+            self.incoming = serialize_frame(&payload, MAX_MESSAGE_LEN).map_err(to_io_error)?;
+            self.read_pos = 0;
+        }
+        let available = &self.incoming[self.read_pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+impl<S: Write, C: FrameCipher> Write for CipherStream<S, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        while let Some(header) = self.outgoing.first_chunk::<4>() {
+            let end = 4 + u32::from_be_bytes(*header) as usize;
+            if self.outgoing.len() < end {
+                break;
+            }
+            let sent = seal_frame(&self.outgoing[4..end], &self.cipher)
+                .map_err(to_io_error)
+                .and_then(|frame| self.inner.write_all(&frame));
+            if let Err(e) = sent {
+                // The stream is out of step now; nothing buffered can be sent.
+                self.outgoing.clear();
+                return Err(e);
+            }
+            self.outgoing.drain(..end);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{read_message, serialize_message, write_message};
+    use std::io::Cursor;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn psk() -> Psk {
+        Psk::from_hex(KEY).unwrap()
+    }
+
+    #[test]
+    fn test_psk_round_trip() {
+        let mut wire = Vec::new();
+        write_message_with(&mut wire, "ON", &psk()).unwrap();
+        write_message_with(&mut wire, "OFF", &psk()).unwrap();
+        // Length prefix, nonce, ciphertext and tag: no trace of the command.
+        assert_eq!(wire.len(), 2 * 4 + 2 * PSK_OVERHEAD + "ONOFF".len());
+        assert!(!wire.windows(2).any(|w| w == b"ON"));
+
+        let mut reader = Cursor::new(wire);
+        assert_eq!(read_message_with(&mut reader, &psk()).unwrap(), "ON");
+        assert_eq!(read_message_with(&mut reader, &psk()).unwrap(), "OFF");
+        assert!(matches!(
+            read_message_with(&mut reader, &psk()),
+            Err(ProtocolError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_nonces_differ_per_frame() {
+        let (first, second) = (psk().seal(b"ON"), psk().seal(b"ON"));
+        assert_ne!(first[..NONCE_LEN], second[..NONCE_LEN]);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_tampered_frame_is_rejected() {
+        let mut wire = Vec::new();
+        write_message_with(&mut wire, "ON", &psk()).unwrap();
+        for at in [4, 4 + NONCE_LEN, wire.len() - 1] {
+            let mut tampered = wire.clone();
+            tampered[at] ^= 0x01;
+            assert!(
+                matches!(
+                    read_message_with(&mut Cursor::new(tampered), &psk()),
+                    Err(ProtocolError::AuthFailed)
+                ),
+                "byte {}",
+                at
+            );
+        }
+        // Too short to even hold a nonce and tag.
+        let short = serialize_message("ON").unwrap();
+        assert!(matches!(
+            read_message_with(&mut Cursor::new(short), &psk()),
+            Err(ProtocolError::AuthFailed)
+        ));
+    }
+
+    #[test]
+    fn test_wrong_key_is_rejected() {
+        let mut wire = Vec::new();
+        write_message_with(&mut wire, "OFF", &psk()).unwrap();
+        let other = Psk::new(&[7; KEY_LEN]);
+        assert!(matches!(
+            read_message_with(&mut Cursor::new(wire), &other),
+            Err(ProtocolError::AuthFailed)
+        ));
+    }
+
+    #[test]
+    fn test_plain_is_the_usual_framing() {
+        let mut wire = Vec::new();
+        write_message_with(&mut wire, "STATUS", &Plain).unwrap();
+        assert_eq!(wire, serialize_message("STATUS").unwrap());
+        assert_eq!(
+            read_message_with(&mut Cursor::new(wire), &Plain).unwrap(),
+            "STATUS"
+        );
+    }
+
+    #[test]
+    fn test_cipher_stream_is_transparent_to_framing() {
+        let mut sender = CipherStream::new(Vec::new(), psk());
+        // Written in pieces that split frames, as a caller may.
+        let frames = [
+            serialize_message("ON").unwrap(),
+            serialize_message("LEVEL:40").unwrap(),
+        ]
+        .concat();
+        for piece in frames.chunks(3) {
+            sender.write_all(piece).unwrap();
+        }
+        let wire = sender.inner;
+        assert_eq!(wire.len(), frames.len() + 2 * PSK_OVERHEAD);
+
+        let mut receiver = CipherStream::new(Cursor::new(wire.clone()), psk());
+        assert_eq!(read_message(&mut receiver).unwrap(), "ON");
+        assert_eq!(read_message(&mut receiver).unwrap(), "LEVEL:40");
+        assert!(matches!(
+            read_message(&mut receiver),
+            Err(ProtocolError::ConnectionClosed)
+        ));
+
+        let mut wrong = CipherStream::new(Cursor::new(wire), Psk::new(&[7; KEY_LEN]));
+        assert!(matches!(
+            read_message(&mut wrong),
+            Err(ProtocolError::AuthFailed)
+        ));
+    }
+
+    #[test]
+    fn test_plain_cipher_stream_interoperates_with_plain_peers() {
+        let mut sender = CipherStream::new(Vec::new(), Plain);
+        write_message(&mut sender, "INFO").unwrap();
+        assert_eq!(sender.inner, serialize_message("INFO").unwrap());
+    }
+
+    #[test]
+    fn test_key_from_hex() {
+        assert!(Psk::from_hex(&KEY.to_uppercase()).is_ok());
+        assert!(Psk::from_hex(&KEY[2..]).is_err());
+        assert!(Psk::from_hex(&format!("zz{}", &KEY[2..])).is_err());
+        assert!(Psk::from_hex(&format!("+f{}", &KEY[2..])).is_err());
+        assert!(Psk::from_hex(&format!("é{}", &KEY[2..])).is_err());
+        assert_eq!(format!("{:?}", psk()), "Psk(***)");
+    }
+}
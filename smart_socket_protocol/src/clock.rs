@@ -0,0 +1,158 @@
+//! Time as the binaries read it, behind a trait so time-dependent code can be
+//! driven by a [`MockClock`] in tests, and the UTC formatting of log lines and
+//! file names.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock {
+    /// Monotonic time, for timeouts, intervals and ages.
+    fn now(&self) -> Instant;
+    /// Wall-clock time, for timestamps that leave the process.
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real clocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test
+/// can keep one and hand another to the code under test.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    time: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl MockClock {
+    /// Wall time of a clock made by [`MockClock::new`]: 2024-05-01T12:00:00Z.
+    pub const START: u64 = 1_714_564_800;
+
+    pub fn new() -> Self {
+        Self::at(SystemTime::UNIX_EPOCH + Duration::from_secs(Self::START))
+    }
+
+    /// A clock whose wall time starts at `system_time`.
+    pub fn at(system_time: SystemTime) -> Self {
+        Self {
+            time: Arc::new(Mutex::new((Instant::now(), system_time))),
+        }
+    }
+
+    /// Moves both clocks forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock().unwrap_or_else(PoisonError::into_inner);
+        time.0 += by;
+        time.1 += by;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap_or_else(PoisonError::into_inner).0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().unwrap_or_else(PoisonError::into_inner).1
+    }
+}
+
+/// Whole seconds since the Unix epoch; 0 for times before it.
+pub fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) UTC date.
+pub fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's days-to-civil algorithm, restricted to dates after the epoch.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `time` in UTC to the second, e.g. `2024-05-01T12:00:00Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let secs = epoch_secs(time);
+    let (year, month, day) = civil_date(secs / 86_400);
+    let of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(at(MockClock::START)), "2024-05-01T12:00:00Z");
+        // The leap day of a leap year, one second before midnight.
+        assert_eq!(rfc3339(at(1_709_251_199)), "2024-02-29T23:59:59Z");
+        assert_eq!(rfc3339(at(4_102_444_800)), "2100-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let shared = clock.clone();
+        let (start, wall) = (clock.now(), clock.system_time());
+        assert_eq!(shared.now(), start);
+
+        shared.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert_eq!(epoch_secs(clock.system_time()), epoch_secs(wall) + 90);
+
+        let dynamic: Arc<dyn Clock + Send + Sync> = Arc::new(clock);
+        assert_eq!(dynamic.now() - start, Duration::from_secs(90));
+    }
+}
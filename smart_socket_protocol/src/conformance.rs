@@ -0,0 +1,554 @@
+//! Wire compatibility checks runnable against any server implementation, e.g. a
+//! firmware port. Each [`Check`] talks to the server over fresh connections and
+//! reports pass or fail, with the bytes the server sent when it fails.
+//!
+//! The suite switches the socket on and off, so it reads the on/off state first
+//! and puts it back at the end.
+
+use crate::{
+    read_message_limited, serialize_message, Address, Command, ProtocolError, Response,
+    MAX_FRAME_LEN, MAX_RESPONSE_LEN, MORE_PREFIX,
+};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Commands sent back to back by [`Check::Pipelining`].
+pub const PIPELINE_DEPTH: usize = 50;
+/// At most this many observed bytes are kept in a [`Failure`].
+pub const MAX_OBSERVED: usize = 512;
+
+/// One check of the suite, selectable by its [name](Check::name).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// `ON`, `STATUS`, `OFF`, `STATUS` and `INFO` get the matching replies.
+    BaseCommands,
+    /// Unknown and empty commands, and frames that are not UTF-8, are answered
+    /// with `ERROR` or end the connection, and the server keeps serving.
+    MalformedFrames,
+    /// A frame announcing 4 GiB is refused instead of waited for.
+    OversizedFrame,
+    /// Commands written in one go are answered in order.
+    Pipelining,
+    /// Connections dropped mid-frame or before their reply leave the server serving.
+    UnexpectedDisconnects,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::BaseCommands,
+        Check::MalformedFrames,
+        Check::OversizedFrame,
+        Check::Pipelining,
+        Check::UnexpectedDisconnects,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::BaseCommands => "base-commands",
+            Check::MalformedFrames => "malformed-frames",
+            Check::OversizedFrame => "oversized-frame",
+            Check::Pipelining => "pipelining",
+            Check::UnexpectedDisconnects => "unexpected-disconnects",
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Check {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Check::ALL
+            .into_iter()
+            .find(|check| check.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Check::ALL.iter().map(Check::name).collect();
+                format!(
+                    "Unknown check '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConformanceConfig {
+    pub address: Address,
+    /// Sent with `AUTH` on every connection, for servers that require a token.
+    pub auth_token: Option<String>,
+    /// How long any reply may take.
+    pub timeout: Duration,
+    /// The checks to run, in order.
+    pub checks: Vec<Check>,
+}
+
+impl Default for ConformanceConfig {
+    fn default() -> Self {
+        Self {
+            address: Address::Ip(([127, 0, 0, 1], 8080).into()),
+            auth_token: None,
+            timeout: Duration::from_secs(5),
+            checks: Check::ALL.to_vec(),
+        }
+    }
+}
+
+/// Why a check failed, with the bytes the server sent during the failing exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub reason: String,
+    pub observed: Vec<u8>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if !self.observed.is_empty() {
+            write!(f, "\n    observed: {}", escape_bytes(&self.observed))?;
+        }
+        Ok(())
+    }
+}
+
+/// `bytes` with printable ASCII as is and everything else as `\xNN`.
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'\\' => "\\\\".to_string(),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\x{:02x}", byte),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: Result<(), Failure>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+    /// Whether the socket was put back in the on/off state it started in.
+    pub restored: Result<(), Failure>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.restored.is_ok() && self.results.iter().all(|result| result.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {}", result.check)?,
+                Err(failure) => writeln!(f, "FAIL {}: {}", result.check, failure)?,
+            }
+        }
+        match &self.restored {
+            Ok(()) => Ok(()),
+            Err(failure) => writeln!(f, "FAIL restoring the on/off state: {}", failure),
+        }
+    }
+}
+
+/// Runs the configured checks. Fails without running any when the server cannot
+/// be reached or its initial state cannot be read.
+pub fn run(config: &ConformanceConfig) -> Result<Report, ProtocolError> {
+    let initial = Session::open(config)
+        .map(|(_, is_on)| is_on)
+        .map_err(|failure| ProtocolError::ConnectionError(failure.to_string()))?;
+
+    let results = config
+        .checks
+        .iter()
+        .map(|&check| CheckResult {
+            check,
+            outcome: run_check(check, config),
+        })
+        .collect();
+    Ok(Report {
+        results,
+        restored: restore(config, initial),
+    })
+}
+
+fn run_check(check: Check, config: &ConformanceConfig) -> Result<(), Failure> {
+    match check {
+        Check::BaseCommands => base_commands(config),
+        Check::MalformedFrames => malformed_frames(config),
+        Check::OversizedFrame => oversized_frame(config),
+        Check::Pipelining => pipelining(config),
+        Check::UnexpectedDisconnects => unexpected_disconnects(config),
+    }
+}
+
+fn failure(reason: impl Into<String>) -> Failure {
+    Failure {
+        reason: reason.into(),
+        observed: Vec::new(),
+    }
+}
+
+fn is_on(response: &Response) -> Option<bool> {
+    match response {
+        Response::Status { is_on, .. } => Some(*is_on),
+        _ => None,
+    }
+}
+
+/// Sends `command` and fails unless the reply is of the `expected` kind.
+fn expect_reply(session: &mut Session, command: Command, expected: &str) -> Result<(), Failure> {
+    let response = session.request(&command.to_string())?;
+    if response.kind() != expected {
+        return Err(session.failure(format!(
+            "{} was answered with {}, expected {}",
+            command,
+            response.kind(),
+            expected
+        )));
+    }
+    Ok(())
+}
+
+fn expect_status(session: &mut Session, expected: bool) -> Result<(), Failure> {
+    let response = session.request("STATUS")?;
+    if is_on(&response) != Some(expected) {
+        let state = if expected { "on" } else { "off" };
+        return Err(session.failure(format!("STATUS does not report the socket {}", state)));
+    }
+    Ok(())
+}
+
+fn base_commands(config: &ConformanceConfig) -> Result<(), Failure> {
+    let (mut session, _) = Session::open(config)?;
+    expect_reply(&mut session, Command::TurnOn, "OK")?;
+    expect_status(&mut session, true)?;
+    expect_reply(&mut session, Command::TurnOff, "OK")?;
+    expect_status(&mut session, false)?;
+    expect_reply(&mut session, Command::GetInfo, "INFO")
+}
+
+/// Fails unless the next reply is `ERROR`; when `may_close`, the server may
+/// instead close the connection, after which the check continues on a new one.
+fn expect_rejection(
+    session: &mut Session,
+    frame: &[u8],
+    what: &str,
+    may_close: bool,
+) -> Result<bool, Failure> {
+    session.send_raw(frame)?;
+    match session.next_response()? {
+        Some(Response::Error(_)) => Ok(true),
+        Some(other) => Err(session.failure(format!(
+            "{} was answered with {} instead of ERROR",
+            what,
+            other.kind()
+        ))),
+        None if may_close => Ok(false),
+        None => Err(session.failure(format!("the server closed the connection after {}", what))),
+    }
+}
+
+fn malformed_frames(config: &ConformanceConfig) -> Result<(), Failure> {
+    let (mut session, is_on) = Session::open(config)?;
+    // Well-formed commands in between keep servers that close connections after
+    // several malformed commands in a row from doing so.
+    for (frame, what) in [
+        ("NOT_A_COMMAND", "an unknown command"),
+        ("", "an empty frame"),
+    ] {
+        let frame = serialize_message(frame).map_err(|e| failure(e.to_string()))?;
+        expect_rejection(&mut session, &frame, what, false)?;
+        expect_status(&mut session, is_on)?;
+    }
+    let mut not_utf8 = 2u32.to_be_bytes().to_vec();
+    not_utf8.extend([0xff, 0xfe]);
+    if !expect_rejection(&mut session, &not_utf8, "a frame that is not UTF-8", true)? {
+        session = Session::open(config)?.0;
+    }
+    expect_status(&mut session, is_on)
+}
+
+fn oversized_frame(config: &ConformanceConfig) -> Result<(), Failure> {
+    let (mut session, is_on) = Session::open(config)?;
+    let mut frame = u32::MAX.to_be_bytes().to_vec();
+    frame.extend_from_slice(b"STATUS");
+    expect_rejection(&mut session, &frame, "a 4 GiB frame", true)?;
+    let (mut session, _) = Session::open(config)?;
+    expect_status(&mut session, is_on)
+}
+
+fn pipelining(config: &ConformanceConfig) -> Result<(), Failure> {
+    let (mut session, _) = Session::open(config)?;
+    let commands: Vec<Command> = (0..PIPELINE_DEPTH)
+        .map(|i| {
+            if i % 2 == 0 {
+                Command::GetStatus
+            } else {
+                Command::GetInfo
+            }
+        })
+        .collect();
+    let batch = commands
+        .iter()
+        .map(|command| serialize_message(&command.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| failure(e.to_string()))?
+        .concat();
+    session.send_raw(&batch)?;
+    for (i, command) in commands.iter().enumerate() {
+        let response = session.next_response()?.ok_or_else(|| {
+            session.failure(format!(
+                "the connection closed after {} of {} replies",
+                i,
+                commands.len()
+            ))
+        })?;
+        if matches!(response, Response::Error(_)) || !command.accepts(&response) {
+            return Err(session.failure(format!(
+                "reply {} to {} was {}",
+                i + 1,
+                command,
+                response.kind()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn unexpected_disconnects(config: &ConformanceConfig) -> Result<(), Failure> {
+    let (mut session, _) = Session::open(config)?;
+    let status = serialize_message("STATUS").map_err(|e| failure(e.to_string()))?;
+    // Gone after half a length prefix.
+    session.send_raw(&status[..2])?;
+    drop(session);
+
+    let (mut session, _) = Session::open(config)?;
+    // Gone after the length prefix and half the payload.
+    session.send_raw(&status[..7])?;
+    drop(session);
+
+    let (mut session, _) = Session::open(config)?;
+    // Gone before reading the reply.
+    session.send_raw(&status)?;
+    drop(session);
+
+    let (mut session, is_on) = Session::open(config)?;
+    expect_status(&mut session, is_on)
+}
+
+/// Switches the socket back to `on` if a check left it otherwise.
+fn restore(config: &ConformanceConfig, on: bool) -> Result<(), Failure> {
+    let (mut session, is_on) = Session::open(config)?;
+    if is_on != on {
+        let command = if on {
+            Command::TurnOn
+        } else {
+            Command::TurnOff
+        };
+        expect_reply(&mut session, command, "OK")?;
+    }
+    expect_status(&mut session, on)
+}
+
+/// One connection, remembering what the server sent since the last command.
+struct Session {
+    stream: TcpStream,
+    observed: Vec<u8>,
+    timeout: Duration,
+}
+
+/// Reads from the stream and keeps a copy of the bytes for [`Failure::observed`].
+struct Recording<'a> {
+    stream: &'a mut TcpStream,
+    observed: &'a mut Vec<u8>,
+}
+
+impl Read for Recording<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        let room = MAX_OBSERVED.saturating_sub(self.observed.len());
+        self.observed.extend_from_slice(&buf[..read.min(room)]);
+        Ok(read)
+    }
+}
+
+impl Session {
+    /// Connects, authenticates if configured, and reads the on/off state. A
+    /// banner the server may send first is skipped.
+    fn open(config: &ConformanceConfig) -> Result<(Self, bool), Failure> {
+        let stream = connect(&config.address, config.timeout)
+            .map_err(|e| failure(format!("cannot connect to {}: {}", config.address, e)))?;
+        stream
+            .set_read_timeout(Some(config.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(config.timeout)))
+            .map_err(|e| failure(format!("cannot set timeouts: {}", e)))?;
+        let mut session = Session {
+            stream,
+            observed: Vec::new(),
+            timeout: config.timeout,
+        };
+
+        let mut response = session.request("STATUS")?;
+        if let Response::Info(_) = response {
+            response = session
+                .next_response()?
+                .ok_or_else(|| session.failure("the connection closed after the banner"))?;
+        }
+        let is_on = is_on(&response).ok_or_else(|| {
+            session.failure(format!("STATUS was answered with {}", response.kind()))
+        })?;
+        if let Some(token) = &config.auth_token {
+            let response = session.request(&Command::Auth(token.clone()).to_string())?;
+            if let Response::Error(e) = response {
+                return Err(session.failure(format!("AUTH was refused: {}", e)));
+            }
+        }
+        Ok((session, is_on))
+    }
+
+    fn failure(&self, reason: impl Into<String>) -> Failure {
+        Failure {
+            reason: reason.into(),
+            observed: self.observed.clone(),
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> Result<(), Failure> {
+        self.observed.clear();
+        self.stream
+            .write_all(data)
+            .map_err(|e| failure(format!("cannot send: {}", e)))
+    }
+
+    fn request(&mut self, command: &str) -> Result<Response, Failure> {
+        let frame = serialize_message(command).map_err(|e| failure(e.to_string()))?;
+        self.send_raw(&frame)?;
+        self.next_response()?.ok_or_else(|| {
+            self.failure(format!(
+                "the connection closed instead of answering {}",
+                command
+            ))
+        })
+    }
+
+    /// The next reply, or `None` when the server closed the connection instead.
+    fn next_response(&mut self) -> Result<Option<Response>, Failure> {
+        let mut first = [0u8; 1];
+        let read = loop {
+            match self.stream.read(&mut first) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                other => break other,
+            }
+        };
+        match read {
+            Ok(0) => return Ok(None),
+            Ok(_) => self.observed.push(first[0]),
+            Err(e) if is_timeout(&e) => {
+                return Err(self.failure(format!("no reply within {:?}", self.timeout)))
+            }
+            Err(_) => return Ok(None),
+        }
+
+        let mut reader = Recording {
+            stream: &mut self.stream,
+            observed: &mut self.observed,
+        };
+        let read = read_reply(&mut (&first[..]).chain(&mut reader));
+        read.map(Some)
+            .map_err(|e| self.failure(format!("unreadable reply: {}", e)))
+    }
+}
+
+/// Like [`read_response`](crate::read_response), but refuses frames longer than
+/// any server should send, so a broken one cannot make the suite allocate gigabytes.
+fn read_reply<R: Read>(reader: &mut R) -> Result<Response, ProtocolError> {
+    let mut text = String::new();
+    loop {
+        let frame = read_message_limited(reader, MORE_PREFIX.len() + MAX_FRAME_LEN)?;
+        match frame.strip_prefix(MORE_PREFIX) {
+            Some(chunk) if text.len() + chunk.len() <= MAX_RESPONSE_LEN => text.push_str(chunk),
+            Some(_) => {
+                return Err(ProtocolError::InvalidResponse(format!(
+                    "longer than {} bytes",
+                    MAX_RESPONSE_LEN
+                )))
+            }
+            None => {
+                text.push_str(&frame);
+                return text.parse();
+            }
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn connect(address: &Address, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses resolved");
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_are_selected_by_name() {
+        for check in Check::ALL {
+            assert_eq!(check.name().parse::<Check>(), Ok(check));
+        }
+        assert!("pipeline"
+            .parse::<Check>()
+            .unwrap_err()
+            .starts_with("Unknown check 'pipeline', expected one of base-commands, "));
+    }
+
+    #[test]
+    fn test_observed_bytes_are_escaped() {
+        let failure = Failure {
+            reason: "STATUS was answered with OK".to_string(),
+            observed: [&[0, 0, 0, 3][..], b"OK\\"].concat(),
+        };
+        assert_eq!(
+            failure.to_string(),
+            "STATUS was answered with OK\n    observed: \\x00\\x00\\x00\\x03OK\\\\"
+        );
+    }
+
+    #[test]
+    fn test_oversized_replies_are_refused() {
+        let mut data = u32::MAX.to_be_bytes().to_vec();
+        data.extend_from_slice(b"PONG");
+        assert!(read_reply(&mut io::Cursor::new(data)).is_err());
+        assert_eq!(
+            read_reply(&mut io::Cursor::new(serialize_message("PONG").unwrap())).unwrap(),
+            Response::Pong
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::ProtocolError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Structured payload of an `INFO` response:
+/// `name=<...>;power=<u32>;firmware=<...>;uptime=<secs>`, followed by the optional
+/// `;build=<git hash>`, `;location=<...>`, `;addresses=<addr>,<addr>`,
+/// `;protocol=<version>`, `;capabilities=<COMMAND>,<COMMAND>`, `;last_change=<epoch secs>`,
+/// `;last_actor=<peer>` and `;read_only=true` fields, each sent only when the server knows a
+/// value.
+///
+/// Values may contain spaces; `;` and `\` inside values are escaped with a backslash.
+/// Unknown keys are ignored and missing keys keep their default, so older and newer
+/// peers can talk to each other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub power: u32,
+    pub firmware: String,
+    pub uptime: u64,
+    /// Git commit the server was built from; empty when unknown.
+    pub build: String,
+    /// Where the device is installed, as configured by the operator.
+    pub location: String,
+    /// Addresses the server is bound to; useful when it fell back to another port.
+    pub addresses: Vec<String>,
+    /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) spoken by the server; 0 when not reported.
+    pub protocol: u32,
+    /// Command keywords the device supports, e.g. `LEVEL` only on dimmers.
+    pub capabilities: Vec<String>,
+    /// When a command last changed the device, in seconds since the Unix epoch.
+    pub last_change: Option<u64>,
+    /// Address of the client that made the last change; empty when unknown.
+    pub last_actor: String,
+    /// The server refuses every command that would change the device; its
+    /// capabilities then list only the others.
+    pub read_only: bool,
+}
+
+/// Splits a `,`-separated list value, dropping empty items.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(';', "\\;")
+}
+
+/// Splits `payload` on unescaped `;` and unescapes each segment.
+pub(crate) fn split_fields(payload: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut chars = payload.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.push(chars.next().unwrap_or('\\')),
+            ';' => fields.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+impl FromStr for DeviceInfo {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut info = DeviceInfo::default();
+        let mut structured = false;
+
+        for field in split_fields(s) {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "name" => info.name = value.to_string(),
+                "power" => {
+                    info.power = value.trim().parse().map_err(|_| {
+                        ProtocolError::ParseError(format!("Invalid power value: {}", value))
+                    })?
+                }
+                "firmware" => info.firmware = value.to_string(),
+                "build" => info.build = value.to_string(),
+                "location" => info.location = value.to_string(),
+                "addresses" => info.addresses = split_list(value),
+                "capabilities" => info.capabilities = split_list(value),
+                "protocol" => {
+                    info.protocol = value.trim().parse().map_err(|_| {
+                        ProtocolError::ParseError(format!("Invalid protocol version: {}", value))
+                    })?
+                }
+                "last_change" => {
+                    info.last_change = Some(value.trim().parse().map_err(|_| {
+                        ProtocolError::ParseError(format!("Invalid last change time: {}", value))
+                    })?)
+                }
+                "last_actor" => info.last_actor = value.to_string(),
+                "read_only" => {
+                    info.read_only = value.trim().parse().map_err(|_| {
+                        ProtocolError::ParseError(format!("Invalid read-only flag: {}", value))
+                    })?
+                }
+                "uptime" => {
+                    info.uptime = value.trim().parse().map_err(|_| {
+                        ProtocolError::ParseError(format!("Invalid uptime value: {}", value))
+                    })?
+                }
+                _ => continue,
+            }
+            structured = true;
+        }
+
+        if !structured {
+            // Legacy servers send a free-form description.
+            return Ok(DeviceInfo {
+                name: s.to_string(),
+                ..Default::default()
+            });
+        }
+        Ok(info)
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "name={};power={};firmware={};uptime={}",
+            escape(&self.name),
+            self.power,
+            escape(&self.firmware),
+            self.uptime
+        )?;
+        if !self.build.is_empty() {
+            write!(f, ";build={}", escape(&self.build))?;
+        }
+        if !self.location.is_empty() {
+            write!(f, ";location={}", escape(&self.location))?;
+        }
+        if !self.addresses.is_empty() {
+            write!(f, ";addresses={}", escape(&self.addresses.join(",")))?;
+        }
+        if self.protocol != 0 {
+            write!(f, ";protocol={}", self.protocol)?;
+        }
+        if !self.capabilities.is_empty() {
+            write!(f, ";capabilities={}", escape(&self.capabilities.join(",")))?;
+        }
+        if let Some(last_change) = self.last_change {
+            write!(f, ";last_change={}", last_change)?;
+        }
+        if !self.last_actor.is_empty() {
+            write!(f, ";last_actor={}", escape(&self.last_actor))?;
+        }
+        if self.read_only {
+            write!(f, ";read_only=true")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> DeviceInfo {
+        DeviceInfo {
+            name: "Kitchen Socket".to_string(),
+            power: 3500,
+            firmware: "0.1.0".to_string(),
+            uptime: 42,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let info = sample();
+        let wire = info.to_string();
+        assert_eq!(
+            wire,
+            "name=Kitchen Socket;power=3500;firmware=0.1.0;uptime=42"
+        );
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+    }
+
+    #[test]
+    fn test_build_metadata_round_trip() {
+        let info = DeviceInfo {
+            build: "1a2b3c4".to_string(),
+            location: "Kitchen; by the window".to_string(),
+            ..sample()
+        };
+        let wire = info.to_string();
+        assert_eq!(
+            wire,
+            "name=Kitchen Socket;power=3500;firmware=0.1.0;uptime=42;build=1a2b3c4;\
+             location=Kitchen\\; by the window"
+        );
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+    }
+
+    #[test]
+    fn test_addresses_round_trip() {
+        let info = DeviceInfo {
+            addresses: vec!["127.0.0.1:8081".to_string(), "[::1]:8081".to_string()],
+            ..sample()
+        };
+        let wire = info.to_string();
+        assert!(wire.ends_with(";addresses=127.0.0.1:8081,[::1]:8081"));
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+    }
+
+    #[test]
+    fn test_identity_round_trip() {
+        let info = DeviceInfo {
+            protocol: 1,
+            capabilities: vec!["ON".to_string(), "LEVEL".to_string()],
+            ..sample()
+        };
+        let wire = info.to_string();
+        assert!(wire.ends_with(";protocol=1;capabilities=ON,LEVEL"));
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+        assert!(DeviceInfo::from_str("name=Lamp;protocol=v2").is_err());
+    }
+
+    #[test]
+    fn test_last_change_round_trip() {
+        let info = DeviceInfo {
+            last_change: Some(1_700_000_000),
+            last_actor: "[::1]:50412".to_string(),
+            ..sample()
+        };
+        let wire = info.to_string();
+        assert!(wire.ends_with(";last_change=1700000000;last_actor=[::1]:50412"));
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+        assert!(DeviceInfo::from_str("name=Lamp;last_change=yesterday").is_err());
+    }
+
+    #[test]
+    fn test_read_only_round_trip() {
+        let info = DeviceInfo {
+            read_only: true,
+            ..sample()
+        };
+        let wire = info.to_string();
+        assert!(wire.ends_with(";uptime=42;read_only=true"));
+        assert_eq!(DeviceInfo::from_str(&wire).unwrap(), info);
+        assert!(
+            !DeviceInfo::from_str("name=Lamp;read_only=false")
+                .unwrap()
+                .read_only
+        );
+        assert!(DeviceInfo::from_str("name=Lamp;read_only=yes").is_err());
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let info = DeviceInfo::from_str("name=Lamp;power=60").unwrap();
+        assert_eq!(info.name, "Lamp");
+        assert_eq!(info.power, 60);
+        assert_eq!(info.firmware, "");
+        assert_eq!(info.uptime, 0);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_ignored() {
+        let info = DeviceInfo::from_str("color=red;name=Desk Lamp;x=;power=40").unwrap();
+        assert_eq!(info.name, "Desk Lamp");
+        assert_eq!(info.power, 40);
+    }
+
+    #[test]
+    fn test_values_with_separators() {
+        let info = DeviceInfo {
+            name: "Garage; left = door \\ main".to_string(),
+            ..sample()
+        };
+        assert_eq!(DeviceInfo::from_str(&info.to_string()).unwrap(), info);
+    }
+
+    #[test]
+    fn test_legacy_description_fallback() {
+        let info = DeviceInfo::from_str("Socket: Kitchen Socket, Power: 100W").unwrap();
+        assert_eq!(info.name, "Socket: Kitchen Socket, Power: 100W");
+        assert_eq!(info.power, 0);
+    }
+
+    #[test]
+    fn test_invalid_numbers_are_rejected() {
+        assert!(DeviceInfo::from_str("name=Lamp;power=lots").is_err());
+        assert!(DeviceInfo::from_str("name=Lamp;uptime=-1").is_err());
+    }
+}
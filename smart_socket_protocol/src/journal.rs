@@ -0,0 +1,338 @@
+//! Append-only request journal with size-based rotation.
+//!
+//! Each accepted command is one line: `timestamp<TAB>peer<TAB>command<TAB>response_kind`.
+//! Backslashes, tabs and line breaks inside fields are escaped so a line always has
+//! exactly four fields.
+
+use crate::{Command, ProtocolError};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Longest time a recorded entry may sit in the write buffer.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    pub path: PathBuf,
+    /// The journal is rotated before a write would take it past this size.
+    pub max_bytes: u64,
+    /// Number of rotated files (`<path>.1` .. `<path>.<keep>`) to keep.
+    pub keep: usize,
+}
+
+impl JournalConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: 10 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub peer: String,
+    pub command: String,
+    pub response_kind: String,
+}
+
+impl JournalEntry {
+    /// The journaled command, or `None` if it no longer parses.
+    pub fn parsed_command(&self) -> Option<Command> {
+        self.command.parse().ok()
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.timestamp,
+            escape(&self.peer),
+            escape(&self.command),
+            escape(&self.response_kind)
+        )
+    }
+}
+
+impl FromStr for JournalEntry {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split('\t').collect();
+        let [timestamp, peer, command, response_kind] = fields[..] else {
+            return Err(ProtocolError::ParseError(format!(
+                "Expected 4 journal fields, got {}",
+                fields.len()
+            )));
+        };
+        Ok(JournalEntry {
+            timestamp: timestamp.parse().map_err(|_| {
+                ProtocolError::ParseError(format!("Invalid journal timestamp: {}", timestamp))
+            })?,
+            peer: unescape(peer),
+            command: unescape(command),
+            response_kind: unescape(response_kind),
+        })
+    }
+}
+
+/// How a command is written to the journal; AUTH tokens are never recorded.
+pub fn command_label(command: &Command) -> String {
+    match command {
+        Command::Auth(_) => "AUTH:***".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Buffered journal writer. Call [`Journal::flush_if_due`] periodically so entries
+/// reach the disk within [`FLUSH_INTERVAL`]; the buffer is also flushed on drop.
+pub struct Journal {
+    config: JournalConfig,
+    writer: BufWriter<File>,
+    size: u64,
+    last_flush: Instant,
+}
+
+impl Journal {
+    pub fn open(config: JournalConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            config,
+            writer: BufWriter::new(file),
+            size,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let line = format!("{}\n", entry);
+        let len = line.len() as u64;
+        if self.size > 0 && self.size + len > self.config.max_bytes {
+            self.rotate()?;
+        }
+        self.writer.write_all(line.as_bytes())?;
+        self.size += len;
+        self.flush_if_due()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1` (dropping the oldest) and starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(path, self.config.keep));
+            for n in (1..self.config.keep).rev() {
+                let from = rotated_path(path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(path, n + 1))?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Path of the `n`-th rotated journal: `<path>.<n>`.
+pub(crate) fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Reads every entry of a journal, failing on the first malformed line.
+pub fn read_entries<R: BufRead>(reader: R) -> Result<Vec<JournalEntry>, ProtocolError> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| ProtocolError::ParseError(e.to_string()))?;
+        if line.is_empty() {
+            continue;
+        }
+        entries.push(line.parse()?);
+    }
+    Ok(entries)
+}
+
+/// The commands worth re-issuing: state-changing commands the server accepted.
+pub fn replayable(entries: &[JournalEntry]) -> Vec<Command> {
+    entries
+        .iter()
+        .filter(|entry| entry.response_kind != "ERROR")
+        .filter_map(JournalEntry::parsed_command)
+        .filter(Command::is_state_changing)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, response_kind: &str) -> JournalEntry {
+        JournalEntry {
+            timestamp: 1_700_000_000,
+            peer: "127.0.0.1:5000".to_string(),
+            command: command.to_string(),
+            response_kind: response_kind.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let mut tricky = entry("SET_NAME:Tab\there\\", "OK");
+        tricky.peer = "line\nbreak".to_string();
+        for original in [entry("ON", "OK"), tricky] {
+            let line = original.to_string();
+            assert_eq!(line.split('\t').count(), 4);
+            assert!(!line.contains('\n'));
+            assert_eq!(line.parse::<JournalEntry>().unwrap(), original);
+        }
+        assert!("1\tpeer\tON".parse::<JournalEntry>().is_err());
+    }
+
+    #[test]
+    fn test_rotation_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+        let line_len = (entry("ON", "OK").to_string().len() + 1) as u64;
+        let config = JournalConfig {
+            path: path.clone(),
+            max_bytes: line_len * 2,
+            keep: 2,
+        };
+
+        let mut journal = Journal::open(config).unwrap();
+        let count_lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+
+        // Two entries fit exactly; the third starts a new file.
+        journal.record(&entry("ON", "OK")).unwrap();
+        journal.record(&entry("ON", "OK")).unwrap();
+        journal.flush().unwrap();
+        assert_eq!(count_lines(&path), 2);
+        assert!(!rotated_path(&path, 1).exists());
+
+        journal.record(&entry("OFF", "OK")).unwrap();
+        journal.flush().unwrap();
+        assert_eq!(count_lines(&path), 1);
+        assert_eq!(count_lines(&rotated_path(&path, 1)), 2);
+
+        // Fill enough files to push the oldest one past `keep`.
+        for _ in 0..5 {
+            journal.record(&entry("OFF", "OK")).unwrap();
+        }
+        journal.flush().unwrap();
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_entries_are_flushed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+
+        let mut journal = Journal::open(JournalConfig::new(path.clone())).unwrap();
+        journal.record(&entry("ON", "OK")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        drop(journal);
+        let entries = read_entries(fs::read_to_string(&path).unwrap().as_bytes()).unwrap();
+        assert_eq!(entries, [entry("ON", "OK")]);
+    }
+
+    #[test]
+    fn test_replay_skips_read_only_and_rejected_commands() {
+        let entries = [
+            entry("STATUS", "STATUS"),
+            entry("ON", "OK"),
+            entry("INFO", "INFO"),
+            entry("AUTH:***", "OK"),
+            entry("OFF", "ERROR"),
+            entry("SET_NAME:Hall", "OK"),
+            entry("LEVEL:40", "LEVEL"),
+            entry("PING", "PONG"),
+        ];
+        let commands: Vec<String> = replayable(&entries)
+            .iter()
+            .map(Command::to_string)
+            .collect();
+        assert_eq!(commands, ["ON", "SET_NAME:Hall", "LEVEL:40"]);
+    }
+
+    #[test]
+    fn test_auth_tokens_are_masked() {
+        assert_eq!(
+            command_label(&Command::Auth("secret".to_string())),
+            "AUTH:***"
+        );
+        assert_eq!(command_label(&Command::TurnOn), "ON");
+    }
+}
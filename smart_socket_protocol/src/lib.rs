@@ -0,0 +1,1693 @@
+//! Wire protocol shared by the smart socket server and its clients: commands,
+//! responses, length-prefixed framing and the request journal format, plus the
+//! datagrams of the thermometer client and server.
+//!
+//! It also holds the process-level helpers every binary in the workspace
+//! shares: the [`clock`], the [`logging`] format, [`shutdown`] handling and the
+//! [`systemd`] integration.
+
+pub mod address;
+pub mod beacon;
+pub mod binfmt;
+pub mod cipher;
+pub mod clock;
+pub mod conformance;
+mod info;
+pub mod journal;
+pub mod logging;
+pub mod schema;
+pub mod shutdown;
+mod stats;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+/// Without the `systemd` feature, or off Unix, there is nothing to notify.
+#[cfg(not(all(unix, feature = "systemd")))]
+pub mod systemd {
+    pub fn notify_or_log(_state: &str, _log: impl Fn(&str)) {}
+}
+pub mod thermometer;
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub use address::{Address, ConfigError, IntoAddress};
+pub use info::DeviceInfo;
+pub use stats::ServerStats;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Version of this protocol, announced in the server's `INFO` banner. Bumped when a
+/// change would break existing peers; new optional fields and commands do not count.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Keywords of every [`Command`], as returned by [`Command::kind`].
+pub const COMMAND_KINDS: [&str; 14] = [
+    "ON", "OFF", "STATUS", "INFO", "PING", "AUTH", "SET_NAME", "STATS", "LEVEL", "PULSE", "RESET",
+    "RELOAD", "LOCK", "UNLOCK",
+];
+
+/// Other keywords [`Command::from_str`] accepts, each with the keyword it stands for.
+/// Commands are always written with the keyword from [`COMMAND_KINDS`].
+pub const COMMAND_ALIASES: [(&str, &str); 6] = [
+    ("TURN_ON", "ON"),
+    ("TURN_OFF", "OFF"),
+    ("STATE", "STATUS"),
+    ("GET_STATUS", "STATUS"),
+    ("RENAME", "SET_NAME"),
+    ("DIM", "LEVEL"),
+];
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Command {
+    TurnOn,
+    TurnOff,
+    GetStatus,
+    GetInfo,
+    Ping,
+    Auth(String),
+    SetName(String),
+    GetStats,
+    /// Dimmer level in percent, 0..=100.
+    SetLevel(u8),
+    /// Turn on, then off again after this many milliseconds (see [`PULSE_MILLIS`]).
+    Pulse(u64),
+    /// Clears an overload trip so the socket may be turned on again.
+    ResetTrip,
+    /// Asks the server to re-read its config file.
+    Reload,
+    /// Child lock: refuse switching until `UNLOCK`, or for this many minutes
+    /// (see [`LOCK_MINUTES`]). Queries are still answered.
+    Lock(Option<u32>),
+    Unlock,
+}
+
+impl Command {
+    /// The command keyword without its argument, e.g. `ON` or `SET_NAME`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::TurnOn => "ON",
+            Command::TurnOff => "OFF",
+            Command::GetStatus => "STATUS",
+            Command::GetInfo => "INFO",
+            Command::Ping => "PING",
+            Command::Auth(_) => "AUTH",
+            Command::SetName(_) => "SET_NAME",
+            Command::GetStats => "STATS",
+            Command::SetLevel(_) => "LEVEL",
+            Command::Pulse(_) => "PULSE",
+            Command::ResetTrip => "RESET",
+            Command::Reload => "RELOAD",
+            Command::Lock(_) => "LOCK",
+            Command::Unlock => "UNLOCK",
+        }
+    }
+
+    /// Commands that change device state and therefore require authentication.
+    pub fn is_state_changing(&self) -> bool {
+        matches!(
+            self,
+            Command::TurnOn
+                | Command::TurnOff
+                | Command::SetName(_)
+                | Command::SetLevel(_)
+                | Command::Pulse(_)
+                | Command::ResetTrip
+                | Command::Reload
+                | Command::Lock(_)
+                | Command::Unlock
+        )
+    }
+
+    /// Commands that change the device itself, which a read-only server refuses:
+    /// every state-changing one but `RELOAD`, which only re-reads the server's
+    /// own settings.
+    pub fn changes_device(&self) -> bool {
+        self.is_state_changing() && !matches!(self, Command::Reload)
+    }
+
+    /// One command of every kind, in [`COMMAND_KINDS`] order, for questions
+    /// about a kind rather than a particular command.
+    pub fn examples() -> [Command; COMMAND_KINDS.len()] {
+        [
+            Command::TurnOn,
+            Command::TurnOff,
+            Command::GetStatus,
+            Command::GetInfo,
+            Command::Ping,
+            Command::Auth(String::new()),
+            Command::SetName(String::new()),
+            Command::GetStats,
+            Command::SetLevel(0),
+            Command::Pulse(*PULSE_MILLIS.start()),
+            Command::ResetTrip,
+            Command::Reload,
+            Command::Lock(None),
+            Command::Unlock,
+        ]
+    }
+
+    /// Whether `response` is a valid reply to this command. `ERROR` is accepted for every command.
+    pub fn accepts(&self, response: &Response) -> bool {
+        matches!(
+            (self, response),
+            (_, Response::Error(_))
+                | (
+                    Command::TurnOn
+                        | Command::TurnOff
+                        | Command::Auth(_)
+                        | Command::SetName(_)
+                        | Command::Pulse(_)
+                        | Command::ResetTrip
+                        | Command::Reload
+                        | Command::Lock(_)
+                        | Command::Unlock,
+                    Response::Ok(_)
+                )
+                | (
+                    Command::GetStatus,
+                    Response::Status { .. } | Response::Temperature { .. }
+                )
+                | (Command::GetInfo, Response::Info(_))
+                | (Command::GetStats, Response::Stats(_))
+                | (Command::SetLevel(_), Response::Level(_))
+                | (Command::Ping, Response::Pong)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Response {
+    /// Acknowledgement with an optional human-readable message; a bare `OK` has none.
+    Ok(Option<String>),
+    /// `tripped` is set while an overload trip keeps the socket off, `locked`
+    /// while a child lock refuses switching.
+    Status {
+        is_on: bool,
+        power: u32,
+        tripped: bool,
+        locked: bool,
+    },
+    /// `STATUS` answer of a thermometer: the latest reading and its age in seconds.
+    Temperature {
+        celsius: f64,
+        age_secs: u64,
+    },
+    /// A reading pushed by a thermometer server as it arrives, never an answer
+    /// to a command: `TEMP:<device id>:<celsius>:<Unix seconds>`.
+    Reading {
+        device_id: String,
+        celsius: f64,
+        epoch_secs: u64,
+    },
+    Info(DeviceInfo),
+    Stats(ServerStats),
+    Level(u8),
+    Pong,
+    Error(String),
+}
+
+impl Response {
+    /// The response keyword without its payload, e.g. `STATUS` or `ERROR`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Response::Ok(_) => "OK",
+            Response::Status { .. } => "STATUS",
+            Response::Temperature { .. } | Response::Reading { .. } => "TEMP",
+            Response::Info(_) => "INFO",
+            Response::Stats(_) => "STATS",
+            Response::Level(_) => "LEVEL",
+            Response::Pong => "PONG",
+            Response::Error(_) => "ERROR",
+        }
+    }
+
+    /// Builds an `OK:<message>` response.
+    pub fn ok(message: &str) -> Self {
+        Response::Ok(Some(message.to_string()))
+    }
+
+    /// Builds an `ERROR:<code>:<message>` response.
+    pub fn error(code: ErrorCode, message: &str) -> Self {
+        Response::Error(format!("{}:{}", code, message))
+    }
+}
+
+/// Machine-readable codes carried at the start of `ERROR` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Unauthorized,
+    /// The peer's address is not permitted to send the command.
+    Forbidden,
+    InvalidArgument,
+    Unsupported,
+    Internal,
+    /// Sent before the server closes a connection that kept sending malformed commands.
+    TooManyErrors,
+    /// The socket tripped on overload and stays off until `RESET`.
+    Tripped,
+    /// A child lock refuses switching until `UNLOCK` or until it expires.
+    Locked,
+    /// The device itself refused to switch on because of the load it would draw.
+    Overload,
+    /// The device failed to carry out a switch, e.g. a relay that did not close.
+    DeviceFault,
+    /// The server is configured read-only and refuses every command that would change the device.
+    ReadOnly,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::Unauthorized => write!(f, "E_UNAUTHORIZED"),
+            ErrorCode::Forbidden => write!(f, "E_FORBIDDEN"),
+            ErrorCode::InvalidArgument => write!(f, "E_INVALID_ARGUMENT"),
+            ErrorCode::Unsupported => write!(f, "E_UNSUPPORTED"),
+            ErrorCode::Internal => write!(f, "E_INTERNAL"),
+            ErrorCode::TooManyErrors => write!(f, "E_TOO_MANY_ERRORS"),
+            ErrorCode::Tripped => write!(f, "E_TRIPPED"),
+            ErrorCode::Locked => write!(f, "E_LOCKED"),
+            ErrorCode::Overload => write!(f, "E_OVERLOAD"),
+            ErrorCode::DeviceFault => write!(f, "E_DEVICE_FAULT"),
+            ErrorCode::ReadOnly => write!(f, "E_READ_ONLY"),
+        }
+    }
+}
+
+/// `value` if it is nothing but ASCII digits, so that `+5`, ` 5` or `5 OFF`
+/// are not taken for a number.
+fn digits(value: &str) -> Option<&str> {
+    Some(value).filter(|value| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Parses a `LEVEL` argument: an integer percentage in 0..=100.
+fn parse_level(value: &str) -> Option<u8> {
+    digits(value)?.parse().ok().filter(|level| *level <= 100)
+}
+
+/// Accepted `PULSE` durations, in milliseconds.
+pub const PULSE_MILLIS: RangeInclusive<u64> = 10..=60_000;
+
+/// Parses a `PULSE` argument: a duration in milliseconds within [`PULSE_MILLIS`].
+fn parse_pulse(value: &str) -> Option<u64> {
+    digits(value)?
+        .parse()
+        .ok()
+        .filter(|millis| PULSE_MILLIS.contains(millis))
+}
+
+/// Accepted `LOCK` durations, in minutes: up to a day.
+pub const LOCK_MINUTES: RangeInclusive<u32> = 1..=1440;
+
+/// Parses a `LOCK` argument: a duration in minutes within [`LOCK_MINUTES`].
+fn parse_lock(value: &str) -> Option<u32> {
+    digits(value)?
+        .parse()
+        .ok()
+        .filter(|minutes| LOCK_MINUTES.contains(minutes))
+}
+
+/// Longest device name accepted by `SET_NAME`, in characters.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Checks a device name before it is put on the wire: 1..=64 characters, no `:`
+/// (the field separator) and no control characters.
+pub fn validate_device_name(name: &str) -> Result<(), String> {
+    let len = name.chars().count();
+    if len == 0 || len > MAX_NAME_LEN {
+        return Err(format!(
+            "Name must be 1 to {} characters long",
+            MAX_NAME_LEN
+        ));
+    }
+    if name.contains(':') {
+        return Err("Name must not contain ':'".to_string());
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Name must not contain control characters".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ProtocolError {
+    InvalidCommand(String),
+    InvalidResponse(String),
+    ConnectionError(String),
+    /// The peer closed the connection cleanly between frames.
+    ConnectionClosed,
+    ParseError(String),
+    /// A pipelined batch failed part-way; carries the responses received before the failure.
+    PartialBatch {
+        responses: Vec<Response>,
+        error: Box<ProtocolError>,
+    },
+    /// A message longer than a frame may carry; nothing was sent.
+    MessageTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// A frame sealed with a pre-shared key failed to open: it was altered, or
+    /// the peer uses another key or none; see [`cipher`].
+    AuthFailed,
+    /// The transport gave up on the peer, as it does when TCP keepalive probes
+    /// go unanswered after the peer lost power or network.
+    PeerUnreachable(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::InvalidCommand(msg) => write!(f, "Invalid command: {}", msg),
+            ProtocolError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
+            ProtocolError::ConnectionError(msg) => write!(f, "Connection error: {}", msg),
+            ProtocolError::ConnectionClosed => write!(f, "Connection closed by peer"),
+            ProtocolError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            ProtocolError::PartialBatch { responses, error } => write!(
+                f,
+                "Batch interrupted after {} responses: {}",
+                responses.len(),
+                error
+            ),
+            ProtocolError::MessageTooLarge { len, max } => {
+                write!(f, "Message of {} bytes exceeds the limit of {}", len, max)
+            }
+            ProtocolError::AuthFailed => {
+                write!(f, "Frame failed authentication: wrong or missing key")
+            }
+            ProtocolError::PeerUnreachable(msg) => write!(f, "Peer unreachable: {}", msg),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+/// Splits a frame into its keyword and the payload after the first `:`, if any.
+fn split_keyword(s: &str) -> (&str, Option<&str>) {
+    match s.split_once(':') {
+        Some((keyword, payload)) => (keyword, Some(payload)),
+        None => (s, None),
+    }
+}
+
+/// The keyword from [`COMMAND_KINDS`] that `keyword` stands for, ignoring case;
+/// unknown keywords are returned uppercased.
+fn canonical_keyword(keyword: &str) -> String {
+    let keyword = keyword.to_ascii_uppercase();
+    COMMAND_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == keyword)
+        .map_or(keyword, |(_, canonical)| canonical.to_string())
+}
+
+/// Number of single-character insertions, deletions and substitutions that
+/// turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The known keyword closest to an unknown, uppercased one, if it is within two
+/// edits and not so short that any keyword would do.
+fn suggest_keyword(keyword: &str) -> Option<&'static str> {
+    let aliases = COMMAND_ALIASES.iter().map(|(alias, _)| *alias);
+    COMMAND_KINDS
+        .into_iter()
+        .chain(aliases)
+        .map(|known| (edit_distance(keyword, known), known))
+        .filter(|(distance, _)| *distance <= 2 && *distance < keyword.chars().count())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+/// The payload of a frame that requires one; missing and empty payloads are
+/// reported as `Missing <what>`.
+fn required<'a>(payload: Option<&'a str>, what: &str) -> Result<&'a str, ProtocolError> {
+    payload
+        .filter(|payload| !payload.is_empty())
+        .ok_or_else(|| ProtocolError::ParseError(format!("Missing {}", what)))
+}
+
+/// Whitespace a command frame may start and end with. Anywhere else only a
+/// name or token may contain whitespace, and only spaces.
+pub const COMMAND_PADDING: [char; 4] = [' ', '\t', '\r', '\n'];
+
+/// Keywords are case-insensitive and may be one of the [`COMMAND_ALIASES`]; payloads
+/// are taken as they are. A frame with a control character other than
+/// [`COMMAND_PADDING`] at either end is invalid, so a second command smuggled in
+/// after a line break or NUL is never acted on. Numeric arguments are digits only.
+/// Commands without an argument reject any payload, including
+/// an empty one (`ON:` is invalid). An unknown keyword's error suggests the nearest
+/// known one, e.g. `STAUS (did you mean STATUS?)`. Missing or empty arguments are a [`ProtocolError::ParseError`]
+/// (except `SET_NAME:`, see below); unknown keywords and out-of-range arguments are
+/// [`ProtocolError::InvalidCommand`].
+impl FromStr for Command {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_matches(COMMAND_PADDING);
+        if s.chars().any(char::is_control) {
+            return Err(ProtocolError::InvalidCommand(format!(
+                "{} (contains control characters)",
+                s.escape_debug()
+            )));
+        }
+        let invalid = || ProtocolError::InvalidCommand(s.to_string());
+        let (keyword, payload) = split_keyword(s);
+        let keyword = canonical_keyword(keyword);
+        match (keyword.as_str(), payload) {
+            ("ON", None) => Ok(Command::TurnOn),
+            ("OFF", None) => Ok(Command::TurnOff),
+            ("STATUS", None) => Ok(Command::GetStatus),
+            ("INFO", None) => Ok(Command::GetInfo),
+            ("PING", None) => Ok(Command::Ping),
+            ("STATS", None) => Ok(Command::GetStats),
+            ("RESET", None) => Ok(Command::ResetTrip),
+            ("RELOAD", None) => Ok(Command::Reload),
+            ("UNLOCK", None) => Ok(Command::Unlock),
+            ("LOCK", None) => Ok(Command::Lock(None)),
+            ("LOCK", Some(minutes)) => parse_lock(minutes)
+                .map(|minutes| Command::Lock(Some(minutes)))
+                .ok_or_else(invalid),
+            ("AUTH", token) => Ok(Command::Auth(required(token, "AUTH token")?.to_string())),
+            // An empty name parses, so that the server can reject it with
+            // E_INVALID_ARGUMENT like any other name that fails validation.
+            ("SET_NAME", Some(name)) => Ok(Command::SetName(name.to_string())),
+            ("SET_NAME", None) => Err(ProtocolError::ParseError("Missing device name".to_string())),
+            ("LEVEL", level) => parse_level(required(level, "level value")?)
+                .map(Command::SetLevel)
+                .ok_or_else(invalid),
+            ("PULSE", millis) => parse_pulse(required(millis, "pulse duration")?)
+                .map(Command::Pulse)
+                .ok_or_else(invalid),
+            ("", _) => Err(ProtocolError::ParseError("Empty command".to_string())),
+            (keyword, _) if !COMMAND_KINDS.contains(&keyword) => match suggest_keyword(keyword) {
+                Some(known) => Err(ProtocolError::InvalidCommand(format!(
+                    "{} (did you mean {}?)",
+                    s, known
+                ))),
+                None => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::TurnOn => write!(f, "ON"),
+            Command::TurnOff => write!(f, "OFF"),
+            Command::GetStatus => write!(f, "STATUS"),
+            Command::GetInfo => write!(f, "INFO"),
+            Command::Ping => write!(f, "PING"),
+            Command::Auth(token) => write!(f, "AUTH:{}", token),
+            Command::SetName(name) => write!(f, "SET_NAME:{}", name),
+            Command::GetStats => write!(f, "STATS"),
+            Command::SetLevel(level) => write!(f, "LEVEL:{}", level),
+            Command::Pulse(millis) => write!(f, "PULSE:{}", millis),
+            Command::ResetTrip => write!(f, "RESET"),
+            Command::Reload => write!(f, "RELOAD"),
+            Command::Lock(None) => write!(f, "LOCK"),
+            Command::Lock(Some(minutes)) => write!(f, "LOCK:{}", minutes),
+            Command::Unlock => write!(f, "UNLOCK"),
+        }
+    }
+}
+
+/// Parses the `<ON|OFF>:<power>[:TRIPPED][:LOCKED]` payload of a `STATUS` response.
+fn parse_status(payload: &str) -> Result<Response, ProtocolError> {
+    let parse_error = |message: String| Err(ProtocolError::ParseError(message));
+    let mut fields = payload.split(':');
+    let is_on = match fields.next() {
+        Some("ON") => true,
+        Some("OFF") => false,
+        None | Some("") => return parse_error("Missing status state".to_string()),
+        Some(state) => return parse_error(format!("Invalid status state: {}", state)),
+    };
+    let power = match fields.next() {
+        None | Some("") => return parse_error("Missing power value".to_string()),
+        Some(power) => power
+            .parse()
+            .map_err(|_| ProtocolError::ParseError(format!("Invalid power value: {}", power)))?,
+    };
+    // The flags follow in this order, each only when set.
+    let mut flags = fields.peekable();
+    let tripped = flags.next_if_eq(&"TRIPPED").is_some();
+    let locked = flags.next_if_eq(&"LOCKED").is_some();
+    if flags.next().is_some() {
+        let last = match (tripped, locked) {
+            (_, true) => "LOCKED",
+            (true, false) => "TRIPPED",
+            (false, false) => "power value",
+        };
+        return parse_error(format!("Unexpected data after {}", last));
+    }
+    Ok(Response::Status {
+        is_on,
+        power,
+        tripped,
+        locked,
+    })
+}
+
+fn parse_celsius(celsius: &str) -> Result<f64, ProtocolError> {
+    match celsius.parse::<f64>() {
+        Ok(celsius) if celsius.is_finite() => Ok(celsius),
+        _ => Err(ProtocolError::ParseError(format!(
+            "Invalid temperature value: {}",
+            celsius
+        ))),
+    }
+}
+
+/// Parses the payload of a `TEMP` response: `<celsius>:<age_secs>` for a
+/// `STATUS` answer, or `<device id>:<celsius>:<epoch_secs>` for a pushed
+/// reading. Fields are taken from the right, so the device id may contain `:`.
+fn parse_temperature(payload: &str) -> Result<Response, ProtocolError> {
+    let parse_error = |message: String| Err(ProtocolError::ParseError(message));
+    let mut fields = payload.rsplitn(3, ':');
+    let (Some(last), Some(celsius)) = (fields.next(), fields.next()) else {
+        return parse_error("Missing reading age".to_string());
+    };
+    let Some(device_id) = fields.next() else {
+        let celsius = parse_celsius(celsius)?;
+        let age_secs = last
+            .parse()
+            .map_err(|_| ProtocolError::ParseError(format!("Invalid reading age: {}", last)))?;
+        return Ok(Response::Temperature { celsius, age_secs });
+    };
+    if device_id.is_empty() {
+        return parse_error("Missing device id".to_string());
+    }
+    let celsius = parse_celsius(celsius)?;
+    let epoch_secs = last
+        .parse()
+        .map_err(|_| ProtocolError::ParseError(format!("Invalid reading time: {}", last)))?;
+    Ok(Response::Reading {
+        device_id: device_id.to_string(),
+        celsius,
+        epoch_secs,
+    })
+}
+
+/// Fixed-arity responses (`STATUS`, `LEVEL`, `PONG`) reject trailing fields;
+/// `OK` and `ERROR` messages are free-form and may contain `:`. The `OK` message
+/// is optional (`OK` and `OK:` carry none); every other payload must be non-empty,
+/// since an `INFO` without fields identifies nothing and an `ERROR` should say
+/// what went wrong.
+impl FromStr for Response {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, payload) = split_keyword(s);
+        match kind {
+            "OK" => Ok(Response::Ok(
+                payload
+                    .filter(|message| !message.is_empty())
+                    .map(str::to_string),
+            )),
+            "STATUS" => parse_status(required(payload, "status data")?),
+            "TEMP" => parse_temperature(required(payload, "temperature data")?),
+            "INFO" => Ok(Response::Info(required(payload, "info message")?.parse()?)),
+            "STATS" => Ok(Response::Stats(required(payload, "stats data")?.parse()?)),
+            "LEVEL" => parse_level(required(payload, "level value")?)
+                .map(Response::Level)
+                .ok_or_else(|| ProtocolError::ParseError("Invalid level value".to_string())),
+            "PONG" => match payload {
+                None => Ok(Response::Pong),
+                Some(_) => Err(ProtocolError::ParseError(
+                    "Unexpected data after PONG".to_string(),
+                )),
+            },
+            "ERROR" => Ok(Response::Error(
+                required(payload, "error message")?.to_string(),
+            )),
+            "" if payload.is_none() => Err(ProtocolError::ParseError("Empty response".to_string())),
+            "" => Err(ProtocolError::ParseError(
+                "Missing response kind".to_string(),
+            )),
+            unknown => Err(ProtocolError::InvalidResponse(unknown.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ok(Some(msg)) => write!(f, "OK:{}", msg),
+            Response::Ok(None) => write!(f, "OK"),
+            Response::Status {
+                is_on,
+                power,
+                tripped,
+                locked,
+            } => {
+                write!(f, "STATUS:{}:{}", if *is_on { "ON" } else { "OFF" }, power)?;
+                if *tripped {
+                    write!(f, ":TRIPPED")?;
+                }
+                if *locked {
+                    write!(f, ":LOCKED")?;
+                }
+                Ok(())
+            }
+            Response::Temperature { celsius, age_secs } => {
+                write!(f, "TEMP:{}:{}", celsius, age_secs)
+            }
+            Response::Reading {
+                device_id,
+                celsius,
+                epoch_secs,
+            } => write!(f, "TEMP:{}:{}:{}", device_id, celsius, epoch_secs),
+            Response::Info(info) => write!(f, "INFO:{}", info),
+            Response::Stats(stats) => write!(f, "STATS:{}", stats),
+            Response::Level(level) => write!(f, "LEVEL:{}", level),
+            Response::Pong => write!(f, "PONG"),
+            Response::Error(err) => write!(f, "ERROR:{}", err),
+        }
+    }
+}
+
+/// Longest message a frame can carry: its length prefix is a `u32`.
+pub const MAX_MESSAGE_LEN: usize = u32::MAX as usize;
+
+/// Frames `message` with its length in bytes, which for non-ASCII text is more
+/// than its number of characters.
+pub fn serialize_message(message: &str) -> Result<Vec<u8>, ProtocolError> {
+    serialize_message_limited(message, MAX_MESSAGE_LEN)
+}
+
+/// Like [`serialize_message`], but a message longer than `max_len` bytes is a
+/// [`ProtocolError::MessageTooLarge`]; the counterpart of [`read_message_limited`].
+pub fn serialize_message_limited(message: &str, max_len: usize) -> Result<Vec<u8>, ProtocolError> {
+    serialize_frame(message.as_bytes(), max_len)
+}
+
+/// Frames raw bytes, text or [`binfmt`].
+pub(crate) fn serialize_frame(data: &[u8], max_len: usize) -> Result<Vec<u8>, ProtocolError> {
+    let max = max_len.min(MAX_MESSAGE_LEN);
+    if data.len() > max {
+        return Err(ProtocolError::MessageTooLarge {
+            len: data.len(),
+            max,
+        });
+    }
+    let mut buffer = Vec::with_capacity(4 + data.len());
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(data);
+    Ok(buffer)
+}
+
+/// Frames `message` and writes it in one call. Too long a message is refused
+/// before anything is written, so the stream stays usable.
+pub fn write_message<W: Write>(writer: &mut W, message: &str) -> Result<(), ProtocolError> {
+    let frame = serialize_message(message)?;
+    writer
+        .write_all(&frame)
+        .map_err(|e| ProtocolError::ConnectionError(format!("Failed to write message: {}", e)))
+}
+
+/// Fills `buf` from `reader`, retrying on `Interrupted`. Returns the number of
+/// bytes read, which is less than `buf.len()` only if EOF was reached.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Whether a read failed because the transport gave up on the peer. Only on
+/// Unix, where a read timeout fails with `WouldBlock` instead; elsewhere the two
+/// cannot be told apart.
+fn is_peer_unreachable(e: &io::Error) -> bool {
+    cfg!(unix)
+        && matches!(
+            e.kind(),
+            io::ErrorKind::TimedOut
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkUnreachable
+        )
+}
+
+/// Describes a failed read, passing on the [`ProtocolError::AuthFailed`] of a
+/// [`cipher::CipherStream`] underneath as it is.
+fn read_error(e: io::Error, what: &str) -> ProtocolError {
+    if cipher::is_auth_failure(&e) {
+        return ProtocolError::AuthFailed;
+    }
+    if is_peer_unreachable(&e) {
+        return ProtocolError::PeerUnreachable(format!("{}: {}", what, e));
+    }
+    ProtocolError::ConnectionError(format!("{}: {}", what, e))
+}
+
+/// Reads one length-prefixed frame.
+///
+/// EOF before the first byte of a frame is a clean close
+/// ([`ProtocolError::ConnectionClosed`]); EOF anywhere inside a frame is a
+/// [`ProtocolError::ConnectionError`].
+pub fn read_message<R: Read>(reader: &mut R) -> Result<String, ProtocolError> {
+    read_message_limited(reader, MAX_MESSAGE_LEN)
+}
+
+/// Like [`read_message`], but a frame announcing more than `max_len` bytes is a
+/// [`ProtocolError::ConnectionError`] before anything is allocated for it. The
+/// rest of that frame is left unread, so the stream cannot be used any more.
+pub fn read_message_limited<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<String, ProtocolError> {
+    String::from_utf8(read_frame_limited(reader, max_len)?)
+        .map_err(|e| ProtocolError::ParseError(format!("Invalid UTF-8: {}", e)))
+}
+
+/// Reads the raw bytes of one frame, as [`read_message_limited`] does.
+pub(crate) fn read_frame_limited<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut length_bytes = [0u8; 4];
+    let read = read_full(reader, &mut length_bytes)
+        .map_err(|e| read_error(e, "Failed to read message length"))?;
+    match read {
+        0 => return Err(ProtocolError::ConnectionClosed),
+        4 => {}
+        n => {
+            return Err(ProtocolError::ConnectionError(format!(
+                "Connection closed mid-frame: got {} of 4 length bytes",
+                n
+            )))
+        }
+    }
+
+    let length = u32::from_be_bytes(length_bytes) as usize;
+    if length > max_len {
+        return Err(ProtocolError::ConnectionError(format!(
+            "Frame of {} bytes exceeds the limit of {}",
+            length, max_len
+        )));
+    }
+    let mut buffer = vec![0u8; length];
+
+    let read =
+        read_full(reader, &mut buffer).map_err(|e| read_error(e, "Failed to read message"))?;
+    if read < length {
+        return Err(ProtocolError::ConnectionError(format!(
+            "Connection closed mid-frame: got {} of {} bytes",
+            read, length
+        )));
+    }
+    Ok(buffer)
+}
+
+/// Prefix of every frame of a multi-frame response except the last.
+pub const MORE_PREFIX: &str = "MORE:";
+
+/// Longest response text the server puts in a single frame; longer responses are
+/// split with [`write_response_chunked`].
+pub const MAX_FRAME_LEN: usize = 16 * 1024;
+
+/// Longest response [`read_response`] reassembles, so a peer that never sends
+/// the final frame cannot make the reader buffer without bound.
+pub const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+
+/// Reads one response, joining `MORE:` continuation frames with the final frame.
+///
+/// A connection that ends or times out before the final frame is an error, never
+/// a truncated response.
+pub fn read_response<R: Read>(reader: &mut R) -> Result<Response, ProtocolError> {
+    read_response_text(reader).and_then(|text| Response::from_str(&text))
+}
+
+/// Like [`read_response`], but returns the reassembled text without parsing it,
+/// for responses this crate may not know.
+pub fn read_response_text<R: Read>(reader: &mut R) -> Result<String, ProtocolError> {
+    let mut text = String::new();
+    loop {
+        let frame = match read_message(reader) {
+            Err(ProtocolError::ConnectionClosed) if !text.is_empty() => {
+                return Err(ProtocolError::ConnectionError(
+                    "Connection closed before the final frame of a response".to_string(),
+                ))
+            }
+            frame => frame?,
+        };
+        match frame.strip_prefix(MORE_PREFIX) {
+            Some(chunk) => text.push_str(chunk),
+            None => {
+                text.push_str(&frame);
+                return Ok(text);
+            }
+        }
+        // The rest of the response is still unread, so the stream is out of sync.
+        if text.len() > MAX_RESPONSE_LEN {
+            return Err(ProtocolError::ConnectionError(format!(
+                "Response longer than {} bytes",
+                MAX_RESPONSE_LEN
+            )));
+        }
+    }
+}
+
+/// Writes `response` as one frame, or split into frames of at most `max_chunk`
+/// bytes of text (plus the `MORE:` prefix) when it is longer.
+///
+/// Splits fall on character boundaries, and never right before text that starts
+/// with `MORE:`, so the final frame cannot be mistaken for a continuation.
+pub fn write_response_chunked<W: Write>(
+    writer: &mut W,
+    response: &Response,
+    max_chunk: usize,
+) -> io::Result<()> {
+    // Backing off to a character boundary and past a `MORE:` costs at most 7 bytes,
+    // so every chunk keeps some text.
+    let max_chunk = max_chunk.max(16);
+    let frame = |message: &str| {
+        serialize_message(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+    };
+    let text = response.to_string();
+    let mut data = Vec::with_capacity(text.len() + 16);
+    let mut rest = text.as_str();
+    while rest.len() > max_chunk {
+        let mut split = max_chunk;
+        while !rest.is_char_boundary(split) || rest[split..].starts_with(MORE_PREFIX) {
+            split -= 1;
+        }
+        data.extend(frame(&format!("{}{}", MORE_PREFIX, &rest[..split]))?);
+        rest = &rest[split..];
+    }
+    data.extend(frame(rest)?);
+    writer.write_all(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    enum Step {
+        Data(Vec<u8>),
+        Interrupted,
+        /// A read timeout, which Unix reports as `WouldBlock`.
+        TimedOut,
+        /// What a read fails with once TCP keepalive gives up on the peer.
+        #[cfg_attr(not(unix), allow(dead_code))]
+        Unreachable,
+    }
+
+    /// Reader that hands out data in the given chunks, then EOF.
+    struct ScriptedReader(VecDeque<Step>);
+
+    impl ScriptedReader {
+        fn new(steps: Vec<Step>) -> Self {
+            Self(steps.into())
+        }
+    }
+
+    impl Read for ScriptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                None => Ok(0),
+                Some(Step::Interrupted) => Err(io::ErrorKind::Interrupted.into()),
+                Some(Step::TimedOut) => Err(io::ErrorKind::WouldBlock.into()),
+                Some(Step::Unreachable) => Err(io::ErrorKind::TimedOut.into()),
+                Some(Step::Data(mut data)) => {
+                    let n = data.len().min(buf.len());
+                    buf[..n].copy_from_slice(&data[..n]);
+                    if n < data.len() {
+                        self.0.push_front(Step::Data(data.split_off(n)));
+                    }
+                    Ok(n)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_message_clean_eof() {
+        let mut reader = ScriptedReader::new(vec![Step::Data(serialize_message("PING").unwrap())]);
+        assert_eq!(read_message(&mut reader).unwrap(), "PING");
+        assert!(matches!(
+            read_message(&mut reader),
+            Err(ProtocolError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_read_message_limited_rejects_long_frames() {
+        let mut data = serialize_message("STATUS").unwrap();
+        data.extend(u32::MAX.to_be_bytes());
+        let mut reader = io::Cursor::new(data);
+        assert_eq!(read_message_limited(&mut reader, 6).unwrap(), "STATUS");
+        match read_message_limited(&mut reader, 6) {
+            Err(ProtocolError::ConnectionError(msg)) => {
+                assert_eq!(msg, "Frame of 4294967295 bytes exceeds the limit of 6")
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serialize_message_limit_boundary() {
+        let frame = serialize_message_limited("STATUS", 6).unwrap();
+        assert_eq!(
+            read_message_limited(&mut io::Cursor::new(frame), 6).unwrap(),
+            "STATUS"
+        );
+        match serialize_message_limited("STATUS", 5) {
+            Err(e @ ProtocolError::MessageTooLarge { len: 6, max: 5 }) => {
+                assert_eq!(e.to_string(), "Message of 6 bytes exceeds the limit of 5")
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serialize_message_counts_utf8_bytes() {
+        let mut frame = Vec::new();
+        write_message(&mut frame, "SET_NAME:Гостиная").unwrap();
+        assert_eq!(frame[..4], 25u32.to_be_bytes());
+        assert_eq!(
+            read_message(&mut io::Cursor::new(frame)).unwrap(),
+            "SET_NAME:Гостиная"
+        );
+        // Eight characters, but sixteen bytes.
+        assert!(serialize_message_limited("Гостиная", 8).is_err());
+    }
+
+    #[test]
+    fn test_read_message_eof_mid_frame() {
+        let mut reader = ScriptedReader::new(vec![Step::Data(vec![0, 0])]);
+        assert!(matches!(
+            read_message(&mut reader),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+
+        let mut truncated = serialize_message("STATUS").unwrap();
+        truncated.truncate(7);
+        let mut reader = ScriptedReader::new(vec![Step::Data(truncated)]);
+        assert!(matches!(
+            read_message(&mut reader),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+    }
+
+    fn long_info(name: &str) -> Response {
+        Response::Info(DeviceInfo {
+            name: name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn frames(mut data: &[u8]) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Ok(frame) = read_message(&mut data) {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_short_response_is_a_single_frame() {
+        let mut data = Vec::new();
+        write_response_chunked(&mut data, &Response::Pong, MAX_FRAME_LEN).unwrap();
+        assert_eq!(data, serialize_message("PONG").unwrap());
+        assert!(matches!(
+            read_response(&mut data.as_slice()).unwrap(),
+            Response::Pong
+        ));
+    }
+
+    #[test]
+    fn test_long_response_is_reassembled() {
+        let response = long_info(&"Привет ".repeat(5));
+        let mut data = Vec::new();
+        write_response_chunked(&mut data, &response, 40).unwrap();
+
+        let frames = frames(&data);
+        assert_eq!(frames.len(), 3);
+        assert!(frames[..2]
+            .iter()
+            .all(|frame| frame.starts_with(MORE_PREFIX)));
+        assert!(!frames[2].starts_with(MORE_PREFIX));
+
+        let mut reader = data.as_slice();
+        assert_eq!(
+            read_response(&mut reader).unwrap().to_string(),
+            response.to_string()
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_final_frame_never_looks_like_a_continuation() {
+        for len in 0..40 {
+            let response = long_info(&format!("{}MORE:MORE:", "x".repeat(len)));
+            let mut data = Vec::new();
+            write_response_chunked(&mut data, &response, 16).unwrap();
+            assert_eq!(
+                read_response(&mut data.as_slice()).unwrap().to_string(),
+                response.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_final_frame_is_an_error() {
+        let mut data = Vec::new();
+        write_response_chunked(&mut data, &long_info(&"x".repeat(100)), 40).unwrap();
+        let last = frames(&data).pop().unwrap();
+        data.truncate(data.len() - serialize_message(&last).unwrap().len());
+
+        assert!(matches!(
+            read_response(&mut data.as_slice()),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+
+        let mut reader = ScriptedReader::new(vec![Step::Data(data), Step::TimedOut]);
+        assert!(matches!(
+            read_response(&mut reader),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keepalive_failure_is_peer_unreachable() {
+        let mut reader = ScriptedReader::new(vec![Step::Unreachable]);
+        assert!(matches!(
+            read_message(&mut reader),
+            Err(ProtocolError::PeerUnreachable(_))
+        ));
+        let mut reader = ScriptedReader::new(vec![Step::TimedOut]);
+        assert!(matches!(
+            read_message(&mut reader),
+            Err(ProtocolError::ConnectionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_message_retries_interrupted() {
+        let mut reader = ScriptedReader::new(vec![
+            Step::Interrupted,
+            Step::Data(serialize_message("STATUS").unwrap()),
+        ]);
+        assert_eq!(read_message(&mut reader).unwrap(), "STATUS");
+    }
+
+    #[test]
+    fn test_read_message_split_length_prefix() {
+        let frame = serialize_message("INFO").unwrap();
+        let mut reader = ScriptedReader::new(vec![
+            Step::Data(frame[..1].to_vec()),
+            Step::Interrupted,
+            Step::Data(frame[1..3].to_vec()),
+            Step::Data(frame[3..].to_vec()),
+        ]);
+        assert_eq!(read_message(&mut reader).unwrap(), "INFO");
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        for command in [
+            Command::TurnOn,
+            Command::TurnOff,
+            Command::GetStatus,
+            Command::GetInfo,
+            Command::Ping,
+            Command::Auth("s3cr:et".to_string()),
+            Command::SetName("Living Room".to_string()),
+            Command::GetStats,
+            Command::SetLevel(0),
+            Command::SetLevel(100),
+            Command::Pulse(500),
+            Command::ResetTrip,
+            Command::Reload,
+            Command::Lock(None),
+            Command::Lock(Some(30)),
+            Command::Unlock,
+        ] {
+            let parsed = Command::from_str(&command.to_string()).unwrap();
+            assert_eq!(parsed.to_string(), command.to_string());
+            assert!(command.to_string().starts_with(command.kind()));
+            assert!(COMMAND_KINDS.contains(&command.kind()));
+        }
+    }
+
+    #[test]
+    fn test_auth_command_parse() {
+        match Command::from_str("AUTH:token").unwrap() {
+            Command::Auth(token) => assert_eq!(token, "token"),
+            other => panic!("Unexpected command: {:?}", other),
+        }
+        assert!(Command::from_str("AUTHX:token").is_err());
+    }
+
+    #[test]
+    fn test_level_command_range() {
+        assert!(matches!(
+            Command::from_str("LEVEL:42").unwrap(),
+            Command::SetLevel(42)
+        ));
+        for invalid in ["LEVEL:101", "LEVEL:-1", "LEVEL:255", "LEVEL:half"] {
+            assert!(
+                matches!(
+                    Command::from_str(invalid),
+                    Err(ProtocolError::InvalidCommand(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_pulse_command_range() {
+        assert!(matches!(
+            Command::from_str("PULSE:500").unwrap(),
+            Command::Pulse(500)
+        ));
+        assert!(matches!(
+            Command::from_str("PULSE:10").unwrap(),
+            Command::Pulse(10)
+        ));
+        assert!(matches!(
+            Command::from_str("PULSE:60000").unwrap(),
+            Command::Pulse(60_000)
+        ));
+        for invalid in ["PULSE:9", "PULSE:60001", "PULSE:-5", "PULSE:1s"] {
+            assert!(
+                matches!(
+                    Command::from_str(invalid),
+                    Err(ProtocolError::InvalidCommand(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_tripped_status_round_trip() {
+        let tripped = Response::Status {
+            is_on: false,
+            power: 0,
+            tripped: true,
+            locked: false,
+        };
+        assert_eq!(tripped.to_string(), "STATUS:OFF:0:TRIPPED");
+        assert!(matches!(
+            Response::from_str("STATUS:OFF:0:TRIPPED").unwrap(),
+            Response::Status { tripped: true, .. }
+        ));
+        // Servers without a power limit never send the flag.
+        assert!(matches!(
+            Response::from_str("STATUS:ON:100").unwrap(),
+            Response::Status { tripped: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_locked_status_round_trip() {
+        for (is_on, tripped, wire) in [
+            (true, false, "STATUS:ON:100:LOCKED"),
+            (false, true, "STATUS:OFF:100:TRIPPED:LOCKED"),
+        ] {
+            let locked = Response::Status {
+                is_on,
+                power: 100,
+                tripped,
+                locked: true,
+            };
+            assert_eq!(locked.to_string(), wire);
+            assert_eq!(Response::from_str(wire).unwrap(), locked);
+        }
+        assert!(matches!(
+            Response::from_str("STATUS:ON:100").unwrap(),
+            Response::Status { locked: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_lock_commands() {
+        assert!(matches!(Command::from_str("LOCK"), Ok(Command::Lock(None))));
+        assert!(matches!(
+            Command::from_str("lock:30"),
+            Ok(Command::Lock(Some(30)))
+        ));
+        assert!(matches!(Command::from_str("UNLOCK"), Ok(Command::Unlock)));
+        for invalid in ["LOCK:", "LOCK:0", "LOCK:1441", "LOCK:soon", "UNLOCK:5"] {
+            assert!(
+                matches!(
+                    Command::from_str(invalid),
+                    Err(ProtocolError::InvalidCommand(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+        assert!(Command::Lock(Some(5)).is_state_changing());
+        assert!(Command::Unlock.accepts(&Response::ok("Unlocked")));
+    }
+
+    #[test]
+    fn test_examples_cover_every_kind() {
+        assert_eq!(
+            Command::examples().map(|command| command.kind()),
+            COMMAND_KINDS
+        );
+        let changing: Vec<_> = Command::examples()
+            .into_iter()
+            .filter(Command::changes_device)
+            .map(|command| command.kind())
+            .collect();
+        assert_eq!(
+            changing,
+            ["ON", "OFF", "SET_NAME", "LEVEL", "PULSE", "RESET", "LOCK", "UNLOCK"]
+        );
+        assert!(Command::Reload.is_state_changing());
+    }
+
+    #[test]
+    fn test_ok_message_is_optional() {
+        for (input, message) in [
+            ("OK", None),
+            ("OK:", None),
+            ("OK:Socket turned on", Some("Socket turned on")),
+            ("OK:a:b", Some("a:b")),
+        ] {
+            match Response::from_str(input).unwrap() {
+                Response::Ok(parsed) => assert_eq!(parsed.as_deref(), message, "{:?}", input),
+                other => panic!("Unexpected response for {:?}: {:?}", input, other),
+            }
+        }
+        assert_eq!(Response::Ok(None).to_string(), "OK");
+        assert_eq!(Response::ok("done").to_string(), "OK:done");
+        assert!(Command::TurnOn.accepts(&Response::Ok(None)));
+    }
+
+    #[test]
+    fn test_temperature_round_trip() {
+        let reading = Response::Temperature {
+            celsius: 21.5,
+            age_secs: 3,
+        };
+        assert_eq!(reading.to_string(), "TEMP:21.5:3");
+        assert!(matches!(
+            Response::from_str("TEMP:-4:0").unwrap(),
+            Response::Temperature { celsius, age_secs: 0 } if celsius == -4.0
+        ));
+        assert!(Command::GetStatus.accepts(&reading));
+
+        for malformed in [
+            "TEMP",
+            "TEMP:21.5",
+            "TEMP:warm:3",
+            "TEMP:NaN:3",
+            "TEMP:21.5:-1",
+            "TEMP:21.5:3:x",
+        ] {
+            assert!(Response::from_str(malformed).is_err(), "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_pushed_reading_round_trip() {
+        let reading = Response::Reading {
+            device_id: "bedroom".to_string(),
+            celsius: 19.25,
+            epoch_secs: 1_700_000_000,
+        };
+        assert_eq!(reading.to_string(), "TEMP:bedroom:19.25:1700000000");
+        assert_eq!(Response::from_str(&reading.to_string()).unwrap(), reading);
+        assert_eq!(reading.kind(), "TEMP");
+        assert!(!Command::GetStatus.accepts(&reading));
+
+        // Fields are split from the right, so an id with `:` survives.
+        assert!(matches!(
+            Response::from_str("TEMP:hall:2:-0.5:60").unwrap(),
+            Response::Reading { device_id, celsius, epoch_secs: 60 }
+                if device_id == "hall:2" && celsius == -0.5
+        ));
+
+        for malformed in [
+            "TEMP::19.25:1700000000",
+            "TEMP:bedroom:warm:1700000000",
+            "TEMP:bedroom:inf:1700000000",
+            "TEMP:bedroom:19.25:-1",
+            "TEMP:bedroom:19.25:",
+        ] {
+            assert!(Response::from_str(malformed).is_err(), "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_level_response_round_trip() {
+        let response = Response::from_str(&Response::Level(75).to_string()).unwrap();
+        assert!(matches!(response, Response::Level(75)));
+        assert!(Response::from_str("LEVEL:101").is_err());
+    }
+
+    #[test]
+    fn test_validate_device_name() {
+        assert!(validate_device_name("Living Room").is_ok());
+        assert!(validate_device_name(&"я".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(validate_device_name("").is_err());
+        assert!(validate_device_name(&"x".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert!(validate_device_name("Room:1").is_err());
+        assert!(validate_device_name("Room\n1").is_err());
+        assert!(validate_device_name("Room\t1").is_err());
+    }
+
+    #[test]
+    fn test_command_accepts_response() {
+        let status = Response::Status {
+            is_on: true,
+            power: 100,
+            tripped: false,
+            locked: false,
+        };
+        assert!(Command::GetStatus.accepts(&status));
+        assert!(!Command::GetInfo.accepts(&status));
+        assert!(!Command::TurnOn.accepts(&status));
+        assert!(Command::TurnOff.accepts(&Response::ok("Socket turned off")));
+        assert!(Command::Ping.accepts(&Response::Pong));
+        assert!(Command::ResetTrip.accepts(&Response::ok("Trip reset")));
+        assert!(Command::GetInfo.accepts(&Response::Error("boom".to_string())));
+    }
+
+    #[test]
+    fn test_error_code_response() {
+        let response = Response::error(ErrorCode::Unauthorized, "Authentication required");
+        assert_eq!(
+            response.to_string(),
+            "ERROR:E_UNAUTHORIZED:Authentication required"
+        );
+        assert!(matches!(
+            Response::from_str("PONG").unwrap(),
+            Response::Pong
+        ));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Expected<'a> {
+        Parse(&'a str),
+        InvalidCommand,
+        InvalidResponse,
+    }
+
+    /// The kind of `error`, borrowing its message to compare with a case.
+    fn classify(error: &ProtocolError) -> Expected<'_> {
+        match error {
+            ProtocolError::ParseError(message) => Expected::Parse(message),
+            ProtocolError::InvalidCommand(_) => Expected::InvalidCommand,
+            ProtocolError::InvalidResponse(_) => Expected::InvalidResponse,
+            other => panic!("Unexpected error kind: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_malformed_responses() {
+        use Expected::*;
+        let cases = [
+            ("", Parse("Empty response")),
+            (":", Parse("Missing response kind")),
+            (":OK", Parse("Missing response kind")),
+            ("STATUS", Parse("Missing status data")),
+            ("STATUS:", Parse("Missing status data")),
+            ("STATUS::", Parse("Missing status state")),
+            ("STATUS::100", Parse("Missing status state")),
+            ("STATUS:ON", Parse("Missing power value")),
+            ("STATUS:ON:", Parse("Missing power value")),
+            ("STATUS:MAYBE:100", Parse("Invalid status state: MAYBE")),
+            ("STATUS:on:100", Parse("Invalid status state: on")),
+            ("STATUS:ON:lots", Parse("Invalid power value: lots")),
+            ("STATUS:ON:-1", Parse("Invalid power value: -1")),
+            (
+                "STATUS:ON:100:extra",
+                Parse("Unexpected data after power value"),
+            ),
+            ("STATUS:ON:100:", Parse("Unexpected data after power value")),
+            (
+                "STATUS:OFF:0:tripped",
+                Parse("Unexpected data after power value"),
+            ),
+            (
+                "STATUS:OFF:0:TRIPPED:1",
+                Parse("Unexpected data after TRIPPED"),
+            ),
+            (
+                "STATUS:OFF:0:LOCKED:TRIPPED",
+                Parse("Unexpected data after LOCKED"),
+            ),
+            ("INFO", Parse("Missing info message")),
+            ("INFO:", Parse("Missing info message")),
+            ("STATS:", Parse("Missing stats data")),
+            ("STATS:uptime", Parse("Invalid stats field: uptime")),
+            ("LEVEL", Parse("Missing level value")),
+            ("LEVEL:101", Parse("Invalid level value")),
+            ("LEVEL:50:extra", Parse("Invalid level value")),
+            ("PONG:", Parse("Unexpected data after PONG")),
+            ("PONG:x", Parse("Unexpected data after PONG")),
+            ("ERROR", Parse("Missing error message")),
+            ("ERROR:", Parse("Missing error message")),
+            ("WHAT:ever", InvalidResponse),
+            ("ok:fine", InvalidResponse),
+        ];
+        for (input, expected) in cases {
+            let error = Response::from_str(input).expect_err(input);
+            assert_eq!(classify(&error), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_malformed_commands() {
+        use Expected::*;
+        let cases = [
+            ("", Parse("Empty command")),
+            ("   ", Parse("Empty command")),
+            (":", Parse("Empty command")),
+            ("ON:", InvalidCommand),
+            ("ON:now", InvalidCommand),
+            ("STATUS:x", InvalidCommand),
+            ("RESET:now", InvalidCommand),
+            ("PING:", InvalidCommand),
+            ("on:now", InvalidCommand),
+            ("AUTH", Parse("Missing AUTH token")),
+            ("AUTH:", Parse("Missing AUTH token")),
+            ("SET_NAME", Parse("Missing device name")),
+            ("LEVEL:", Parse("Missing level value")),
+            ("LEVEL:50:1", InvalidCommand),
+            ("PULSE", Parse("Missing pulse duration")),
+            ("PULSE:", Parse("Missing pulse duration")),
+            ("FLY:away", InvalidCommand),
+        ];
+        for (input, expected) in cases {
+            let error = Command::from_str(input).expect_err(input);
+            assert_eq!(classify(&error), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_tricky_frames() {
+        // Padding at either end is all that is tolerated around a command.
+        for (input, canonical) in [
+            (" ON", "ON"),
+            ("ON\r\n", "ON"),
+            ("\tSTATUS \n", "STATUS"),
+            ("LEVEL:40\n", "LEVEL:40"),
+            ("SET_NAME:Hall Lamp ", "SET_NAME:Hall Lamp"),
+        ] {
+            let command = Command::from_str(input).expect(input);
+            assert_eq!(command.to_string(), canonical, "{:?}", input);
+        }
+
+        let rejected = [
+            // Two commands in one frame.
+            "ON\nOFF",
+            "ON\r\nOFF",
+            "ON OFF",
+            "ON;OFF",
+            "STATUS\u{0}ON",
+            "AUTH:secret\nON",
+            "SET_NAME:Lamp\nON",
+            "LEVEL:50\nOFF",
+            "LEVEL:50 OFF",
+            // Control characters and lookalike whitespace.
+            "ON\0",
+            "\0ON",
+            "O\u{7f}N",
+            "ON\u{85}",
+            "ON\u{a0}",
+            "\u{feff}ON",
+            "ON\u{200b}",
+            // Lookalike letters and separators.
+            "\u{41e}N",
+            "\u{ff2f}\u{ff2e}",
+            "LEVEL\u{a789}50",
+            "LEVEL\u{ff1a}50",
+            "LEVEL:\u{665}\u{660}",
+            "LEVEL:\u{ff15}\u{ff10}",
+            // Numbers are digits only.
+            "LEVEL:+50",
+            "LEVEL: 50",
+            "PULSE:500 0",
+            "LOCK:\t30",
+            "LOCK:0x1e",
+        ];
+        for input in rejected {
+            let Err(error) = Command::from_str(input) else {
+                panic!("{:?} must be rejected", input);
+            };
+            assert!(
+                matches!(error, ProtocolError::InvalidCommand(_)),
+                "{:?}: {:?}",
+                input,
+                error
+            );
+            // The error is safe to echo back and to log.
+            assert!(
+                !error.to_string().chars().any(char::is_control),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_aliases_and_case_are_accepted() {
+        let cases = [
+            ("on", "ON"),
+            ("Off", "OFF"),
+            ("status", "STATUS"),
+            ("pInG", "PING"),
+            ("TURN_ON", "ON"),
+            ("turn_on", "ON"),
+            ("TURN_OFF", "OFF"),
+            ("Turn_Off", "OFF"),
+            ("STATE", "STATUS"),
+            ("state", "STATUS"),
+            ("GET_STATUS", "STATUS"),
+            ("RENAME:Hall Lamp", "SET_NAME:Hall Lamp"),
+            ("set_name:lower case", "SET_NAME:lower case"),
+            ("DIM:40", "LEVEL:40"),
+            ("dim:40", "LEVEL:40"),
+            ("pulse:500", "PULSE:500"),
+            // Tokens and names keep their case.
+            ("auth:SeCrEt", "AUTH:SeCrEt"),
+        ];
+        for (input, canonical) in cases {
+            let command = Command::from_str(input).expect(input);
+            assert_eq!(command.to_string(), canonical, "{:?}", input);
+        }
+        for (alias, canonical) in COMMAND_ALIASES {
+            assert!(COMMAND_KINDS.contains(&canonical), "{}", alias);
+            assert!(!COMMAND_KINDS.contains(&alias), "{}", alias);
+        }
+    }
+
+    #[test]
+    fn test_unknown_command_suggestions() {
+        let cases = [
+            ("STAUS", "STAUS (did you mean STATUS?)"),
+            ("staus", "staus (did you mean STATUS?)"),
+            ("RESTE", "RESTE (did you mean RESET?)"),
+            ("TRUN_ON", "TRUN_ON (did you mean TURN_ON?)"),
+            ("SETNAME:Lamp", "SETNAME:Lamp (did you mean SET_NAME?)"),
+            ("LEVL:50", "LEVL:50 (did you mean LEVEL?)"),
+            ("FLY:away", "FLY:away"),
+            ("X", "X"),
+            ("SHUTDOWN", "SHUTDOWN"),
+        ];
+        for (input, message) in cases {
+            match Command::from_str(input) {
+                Err(ProtocolError::InvalidCommand(actual)) => {
+                    assert_eq!(actual, message, "{:?}", input)
+                }
+                other => panic!("Expected InvalidCommand for {:?}, got {:?}", input, other),
+            }
+        }
+        assert_eq!(edit_distance("STAUS", "STATUS"), 1);
+        assert_eq!(edit_distance("", "ON"), 2);
+        assert_eq!(edit_distance("OFF", "OFF"), 0);
+    }
+
+    /// xorshift64*, so the property tests are reproducible without a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        const PIECES: [&str; 16] = [
+            "OK", "STATUS", "INFO", "STATS", "LEVEL", "PONG", "ERROR", "ON", "OFF", "AUTH",
+            "PULSE", ":", ";", "=", "100", "\\",
+        ];
+        let mut rng = Rng(0x5eed_1234_abcd_0001);
+        for _ in 0..5000 {
+            let input = if rng.below(2) == 0 {
+                let bytes: Vec<u8> = (0..rng.below(32)).map(|_| rng.next() as u8).collect();
+                String::from_utf8_lossy(&bytes).into_owned()
+            } else {
+                (0..rng.below(8))
+                    .map(|_| PIECES[rng.below(PIECES.len())])
+                    .collect()
+            };
+            // Only the absence of panics matters here.
+            let _ = Response::from_str(&input);
+            let _ = Command::from_str(&input);
+            if let Ok(response) = Response::from_str(&input) {
+                let _ = Response::from_str(&response.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_inputs_parse_in_linear_time() {
+        let started = std::time::Instant::now();
+        for input in [
+            format!("STATUS:ON:{}", "9".repeat(1 << 20)),
+            format!("STATUS:ON:1{}", ":".repeat(1 << 20)),
+            format!("INFO:{}", "\\;".repeat(1 << 19)),
+            format!("STATS:{}", ";uptime=1".repeat(1 << 17)),
+            format!("SET_NAME:{}", "x".repeat(1 << 20)),
+            ":".repeat(1 << 20),
+        ] {
+            let _ = Response::from_str(&input);
+            let _ = Command::from_str(&input);
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+}
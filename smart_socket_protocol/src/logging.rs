@@ -0,0 +1,76 @@
+//! The log output of every binary: one `[<UTC time>] <message>` line on stdout.
+//!
+//! Each crate keeps its own [`Logger`] in a static, so a tool embedding both the
+//! server and the client can silence one of them and keep the other.
+
+use crate::clock::{rfc3339, Clock, SystemClock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// `message` as a log line stamped with `time`.
+pub fn format_line(time: SystemTime, message: &str) -> String {
+    format!("[{}] {}", rfc3339(time), message)
+}
+
+#[derive(Debug)]
+pub struct Logger {
+    enabled: AtomicBool,
+}
+
+impl Logger {
+    /// An enabled logger.
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn log(&self, message: &str) {
+        self.log_at(&SystemClock, message);
+    }
+
+    /// Logs `message` stamped with the wall time of `clock`.
+    pub fn log_at(&self, clock: &impl Clock, message: &str) {
+        if self.enabled() {
+            println!("{}", format_line(clock.system_time(), message));
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_format_line() {
+        let clock = MockClock::new();
+        assert_eq!(
+            format_line(clock.system_time(), "Server started"),
+            "[2024-05-01T12:00:00Z] Server started"
+        );
+    }
+
+    #[test]
+    fn test_logger_can_be_silenced() {
+        let logger = Logger::new();
+        assert!(logger.enabled());
+        logger.set_enabled(false);
+        assert!(!logger.enabled());
+        logger.log("not printed");
+    }
+}
@@ -0,0 +1,774 @@
+//! A machine-readable description of the text protocol: every command and
+//! response with the payload it carries, and the error codes, for generating
+//! bindings in other languages. [`ProtocolSchema::to_json`] exports it and
+//! [`validate`] checks frames against it.
+//!
+//! The schema is written out by hand next to the parsers; the tests keep the
+//! two in agreement.
+
+use crate::{
+    ErrorCode, ProtocolError, COMMAND_ALIASES, LOCK_MINUTES, PROTOCOL_VERSION, PULSE_MILLIS,
+};
+use std::fmt::Write;
+
+/// One `:`-separated field of a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// One of these words, in this case.
+    Word(&'static [&'static str]),
+    /// A decimal integer in `min..=max`.
+    Integer { min: u64, max: u64 },
+    /// A finite decimal number, e.g. `21.5`.
+    Decimal,
+    /// Non-empty text; only ever the first field, where it may contain `:`.
+    Text,
+}
+
+/// Whether a free-text payload must be there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    /// `OK`, `OK:` and `OK:<text>` are all valid.
+    Optional,
+    /// The `:` is required, the text may be empty.
+    Present,
+    NonEmpty,
+}
+
+/// What follows the keyword.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// Nothing, not even a `:`.
+    None,
+    /// Free text that may contain `:`.
+    Text(Presence),
+    /// `:`-separated fields; the last `optional` of them may be left out.
+    Fields { fields: Vec<Field>, optional: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// The keyword commands are written with; parsing ignores its case.
+    pub keyword: &'static str,
+    /// Other keywords parsed as this command.
+    pub aliases: Vec<&'static str>,
+    pub payload: Payload,
+    /// Requires authentication when the server has a token.
+    pub state_changing: bool,
+    /// Keywords of the responses that answer it; `ERROR` answers every command.
+    pub responses: Vec<&'static str>,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseSpec {
+    /// The keyword, which is case-sensitive in responses.
+    pub keyword: &'static str,
+    /// The payload forms the response comes in; the first that matches applies.
+    pub payloads: Vec<Payload>,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolSchema {
+    pub version: u32,
+    pub commands: Vec<CommandSpec>,
+    pub responses: Vec<ResponseSpec>,
+    /// Codes at the start of `ERROR` payloads, e.g. `E_UNAUTHORIZED`.
+    pub error_codes: Vec<String>,
+}
+
+/// Every [`ErrorCode`].
+pub const ERROR_CODES: [ErrorCode; 11] = [
+    ErrorCode::Unauthorized,
+    ErrorCode::Forbidden,
+    ErrorCode::InvalidArgument,
+    ErrorCode::Unsupported,
+    ErrorCode::Internal,
+    ErrorCode::TooManyErrors,
+    ErrorCode::Tripped,
+    ErrorCode::Locked,
+    ErrorCode::Overload,
+    ErrorCode::DeviceFault,
+    ErrorCode::ReadOnly,
+];
+
+fn command(
+    keyword: &'static str,
+    payload: Payload,
+    state_changing: bool,
+    responses: &[&'static str],
+    description: &'static str,
+) -> CommandSpec {
+    CommandSpec {
+        keyword,
+        aliases: COMMAND_ALIASES
+            .iter()
+            .filter(|(_, canonical)| *canonical == keyword)
+            .map(|(alias, _)| *alias)
+            .collect(),
+        payload,
+        state_changing,
+        responses: responses.iter().copied().chain(["ERROR"]).collect(),
+        description,
+    }
+}
+
+fn integer(min: u64, max: u64) -> Payload {
+    Payload::Fields {
+        fields: vec![Field::Integer { min, max }],
+        optional: 0,
+    }
+}
+
+impl ProtocolSchema {
+    /// The protocol this crate speaks.
+    pub fn current() -> Self {
+        let level = || integer(0, 100);
+        Self {
+            version: PROTOCOL_VERSION,
+            commands: vec![
+                command("ON", Payload::None, true, &["OK"], "Turn the socket on"),
+                command("OFF", Payload::None, true, &["OK"], "Turn the socket off"),
+                command(
+                    "STATUS",
+                    Payload::None,
+                    false,
+                    &["STATUS", "TEMP"],
+                    "Current state: STATUS from a socket, TEMP from a thermometer",
+                ),
+                command("INFO", Payload::None, false, &["INFO"], "Device identity"),
+                command("PING", Payload::None, false, &["PONG"], "Liveness check"),
+                command(
+                    "AUTH",
+                    Payload::Text(Presence::NonEmpty),
+                    false,
+                    &["OK"],
+                    "Authenticate with the shared token",
+                ),
+                command(
+                    "SET_NAME",
+                    Payload::Text(Presence::Present),
+                    true,
+                    &["OK"],
+                    "Rename the device",
+                ),
+                command("STATS", Payload::None, false, &["STATS"], "Server counters"),
+                command(
+                    "LEVEL",
+                    level(),
+                    true,
+                    &["LEVEL"],
+                    "Set the dimmer level in percent",
+                ),
+                command(
+                    "PULSE",
+                    integer(*PULSE_MILLIS.start(), *PULSE_MILLIS.end()),
+                    true,
+                    &["OK"],
+                    "Turn on, then off after this many milliseconds",
+                ),
+                command(
+                    "RESET",
+                    Payload::None,
+                    true,
+                    &["OK"],
+                    "Clear an overload trip",
+                ),
+                command(
+                    "RELOAD",
+                    Payload::None,
+                    true,
+                    &["OK"],
+                    "Re-read the server's config file",
+                ),
+                command(
+                    "LOCK",
+                    Payload::Fields {
+                        fields: vec![Field::Integer {
+                            min: (*LOCK_MINUTES.start()).into(),
+                            max: (*LOCK_MINUTES.end()).into(),
+                        }],
+                        optional: 1,
+                    },
+                    true,
+                    &["OK"],
+                    "Refuse switching until UNLOCK, or for this many minutes",
+                ),
+                command("UNLOCK", Payload::None, true, &["OK"], "Clear a lock"),
+            ],
+            responses: vec![
+                ResponseSpec {
+                    keyword: "OK",
+                    payloads: vec![Payload::Text(Presence::Optional)],
+                    description: "Acknowledgement with an optional message",
+                },
+                ResponseSpec {
+                    keyword: "STATUS",
+                    payloads: vec![
+                        Payload::Fields {
+                            fields: vec![
+                                Field::Word(&["ON", "OFF"]),
+                                Field::Integer {
+                                    min: 0,
+                                    max: u32::MAX.into(),
+                                },
+                                Field::Word(&["TRIPPED"]),
+                                Field::Word(&["LOCKED"]),
+                            ],
+                            optional: 2,
+                        },
+                        Payload::Fields {
+                            fields: vec![
+                                Field::Word(&["ON", "OFF"]),
+                                Field::Integer {
+                                    min: 0,
+                                    max: u32::MAX.into(),
+                                },
+                                Field::Word(&["LOCKED"]),
+                            ],
+                            optional: 0,
+                        },
+                    ],
+                    description: "State, power in watts, TRIPPED after an overload trip and \
+                                  LOCKED while switching is refused",
+                },
+                ResponseSpec {
+                    keyword: "TEMP",
+                    payloads: vec![
+                        Payload::Fields {
+                            fields: vec![
+                                Field::Decimal,
+                                Field::Integer {
+                                    min: 0,
+                                    max: u64::MAX,
+                                },
+                            ],
+                            optional: 0,
+                        },
+                        Payload::Fields {
+                            fields: vec![
+                                Field::Text,
+                                Field::Decimal,
+                                Field::Integer {
+                                    min: 0,
+                                    max: u64::MAX,
+                                },
+                            ],
+                            optional: 0,
+                        },
+                    ],
+                    description: "Celsius and the reading's age in seconds; pushed readings \
+                                  carry the device id first and Unix seconds last",
+                },
+                ResponseSpec {
+                    keyword: "INFO",
+                    payloads: vec![Payload::Text(Presence::NonEmpty)],
+                    description: "Device identity as key=value pairs separated by ;",
+                },
+                ResponseSpec {
+                    keyword: "STATS",
+                    payloads: vec![Payload::Text(Presence::NonEmpty)],
+                    description: "Server counters as key=value pairs separated by ;",
+                },
+                ResponseSpec {
+                    keyword: "LEVEL",
+                    payloads: vec![level()],
+                    description: "The dimmer level in percent",
+                },
+                ResponseSpec {
+                    keyword: "PONG",
+                    payloads: vec![Payload::None],
+                    description: "Answer to PING",
+                },
+                ResponseSpec {
+                    keyword: "ERROR",
+                    payloads: vec![Payload::Text(Presence::NonEmpty)],
+                    description: "<code>:<message>, the code being one of error_codes",
+                },
+            ],
+            error_codes: ERROR_CODES.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// The schema as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let commands: Vec<String> = self
+            .commands
+            .iter()
+            .map(|spec| {
+                format!(
+                    "{{\"keyword\":{},\"aliases\":{},\"payload\":{},\"state_changing\":{},\
+                     \"responses\":{},\"description\":{}}}",
+                    string(spec.keyword),
+                    strings(&spec.aliases),
+                    spec.payload.to_json(),
+                    spec.state_changing,
+                    strings(&spec.responses),
+                    string(spec.description)
+                )
+            })
+            .collect();
+        let responses: Vec<String> = self
+            .responses
+            .iter()
+            .map(|spec| {
+                let payloads: Vec<String> = spec.payloads.iter().map(Payload::to_json).collect();
+                format!(
+                    "{{\"keyword\":{},\"payloads\":[{}],\"description\":{}}}",
+                    string(spec.keyword),
+                    payloads.join(","),
+                    string(spec.description)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"version\":{},\"commands\":[{}],\"responses\":[{}],\"error_codes\":{}}}",
+            self.version,
+            commands.join(","),
+            responses.join(","),
+            strings(&self.error_codes)
+        )
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn strings<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<String> = items.iter().map(|item| string(item.as_ref())).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl Field {
+    fn to_json(&self) -> String {
+        match self {
+            Field::Word(words) => format!("{{\"kind\":\"word\",\"words\":{}}}", strings(words)),
+            Field::Integer { min, max } => {
+                format!("{{\"kind\":\"integer\",\"min\":{},\"max\":{}}}", min, max)
+            }
+            Field::Decimal => "{\"kind\":\"decimal\"}".to_string(),
+            Field::Text => "{\"kind\":\"text\"}".to_string(),
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        let valid = match self {
+            Field::Word(words) => words.contains(&value),
+            Field::Integer { min, max } => value
+                .trim()
+                .parse::<u64>()
+                .is_ok_and(|n| (*min..=*max).contains(&n)),
+            Field::Decimal => value.parse::<f64>().is_ok_and(f64::is_finite),
+            Field::Text => !value.is_empty(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("Invalid {}: '{}'", self.name(), value))
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Word(_) => "word",
+            Field::Integer { .. } => "integer",
+            Field::Decimal => "number",
+            Field::Text => "text",
+        }
+    }
+}
+
+impl Payload {
+    fn to_json(&self) -> String {
+        match self {
+            Payload::None => "{\"kind\":\"none\"}".to_string(),
+            Payload::Text(presence) => {
+                let presence = match presence {
+                    Presence::Optional => "optional",
+                    Presence::Present => "present",
+                    Presence::NonEmpty => "non_empty",
+                };
+                format!("{{\"kind\":\"text\",\"presence\":\"{}\"}}", presence)
+            }
+            Payload::Fields { fields, optional } => {
+                let fields: Vec<String> = fields.iter().map(Field::to_json).collect();
+                format!(
+                    "{{\"kind\":\"fields\",\"fields\":[{}],\"optional\":{}}}",
+                    fields.join(","),
+                    optional
+                )
+            }
+        }
+    }
+
+    /// Checks what follows the keyword, `None` when there is no `:`.
+    fn check(&self, payload: Option<&str>) -> Result<(), String> {
+        match (self, payload) {
+            (Payload::None, None) => Ok(()),
+            (Payload::None, Some(_)) => Err("Unexpected payload".to_string()),
+            (Payload::Text(Presence::Optional), _)
+            | (Payload::Text(Presence::Present), Some(_)) => Ok(()),
+            (Payload::Text(_), Some(text)) if !text.is_empty() => Ok(()),
+            (Payload::Fields { fields, optional }, None) if *optional == fields.len() => Ok(()),
+            (Payload::Text(_), _) | (Payload::Fields { .. }, None) => {
+                Err("Missing payload".to_string())
+            }
+            (Payload::Fields { fields, optional }, Some(payload)) => {
+                // Split from the right, so that a leading text field keeps its `:`s.
+                let mut values: Vec<&str> = payload.rsplitn(fields.len(), ':').collect();
+                values.reverse();
+                if values.len() < fields.len() - optional {
+                    return Err(format!(
+                        "Expected at least {} fields, got {}",
+                        fields.len() - optional,
+                        values.len()
+                    ));
+                }
+                fields
+                    .iter()
+                    .zip(values)
+                    .try_for_each(|(field, value)| field.check(value))
+            }
+        }
+    }
+}
+
+/// What a valid frame is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A command, named by its keyword from [`crate::COMMAND_KINDS`].
+    Command(&'static str),
+    Response(&'static str),
+    /// Valid both ways, e.g. `LEVEL:50`.
+    CommandOrResponse(&'static str),
+}
+
+impl ProtocolSchema {
+    /// The keyword of the command `frame` is, as [`crate::Command::from_str`] reads it.
+    fn check_command(&self, frame: &str) -> Result<&'static str, ProtocolError> {
+        let (keyword, payload) = match frame.trim().split_once(':') {
+            Some((keyword, payload)) => (keyword, Some(payload)),
+            None => (frame.trim(), None),
+        };
+        let spec = self
+            .commands
+            .iter()
+            .find(|spec| {
+                spec.keyword.eq_ignore_ascii_case(keyword)
+                    || spec
+                        .aliases
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(keyword))
+            })
+            .ok_or_else(|| ProtocolError::InvalidCommand(frame.to_string()))?;
+        spec.payload
+            .check(payload)
+            .map(|()| spec.keyword)
+            .map_err(|e| ProtocolError::ParseError(format!("{}: {}", spec.keyword, e)))
+    }
+
+    fn check_response(&self, frame: &str) -> Result<&'static str, ProtocolError> {
+        let (keyword, payload) = match frame.split_once(':') {
+            Some((keyword, payload)) => (keyword, Some(payload)),
+            None => (frame, None),
+        };
+        let spec = self
+            .responses
+            .iter()
+            .find(|spec| spec.keyword == keyword)
+            .ok_or_else(|| ProtocolError::InvalidResponse(keyword.to_string()))?;
+        // Reports why the first form did not match.
+        let mut first_error = None;
+        for form in &spec.payloads {
+            match form.check(payload) {
+                Ok(()) => return Ok(spec.keyword),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(ProtocolError::ParseError(format!(
+            "{}: {}",
+            spec.keyword,
+            first_error.unwrap_or_default()
+        )))
+    }
+}
+
+/// Classifies `frame` by the schema alone, without parsing it into a
+/// [`crate::Command`] or [`crate::Response`]. A frame that is neither fails
+/// with the reason it is not a command, unless its keyword is a response's.
+pub fn validate(frame: &str) -> Result<FrameKind, ProtocolError> {
+    let schema = ProtocolSchema::current();
+    match (schema.check_command(frame), schema.check_response(frame)) {
+        (Ok(keyword), Ok(_)) => Ok(FrameKind::CommandOrResponse(keyword)),
+        (Ok(keyword), Err(_)) => Ok(FrameKind::Command(keyword)),
+        (Err(_), Ok(keyword)) => Ok(FrameKind::Response(keyword)),
+        (Err(e), Err(ProtocolError::InvalidResponse(_))) => Err(e),
+        (Err(_), Err(e)) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, DeviceInfo, Response, ServerStats, COMMAND_KINDS};
+    use std::str::FromStr;
+
+    /// A valid frame for `spec`, using the smallest argument it takes.
+    fn sample_frame(spec: &CommandSpec) -> String {
+        match &spec.payload {
+            Payload::None => spec.keyword.to_string(),
+            Payload::Text(_) => format!("{}:Hall", spec.keyword),
+            Payload::Fields { fields, .. } => match fields[..] {
+                [Field::Integer { min, .. }] => format!("{}:{}", spec.keyword, min),
+                _ => panic!("no sample for {}", spec.keyword),
+            },
+        }
+    }
+
+    fn sample_responses() -> Vec<Response> {
+        vec![
+            Response::Ok(None),
+            Response::ok("Done"),
+            Response::Status {
+                is_on: true,
+                power: 60,
+                tripped: false,
+                locked: false,
+            },
+            Response::Status {
+                is_on: false,
+                power: 0,
+                tripped: true,
+                locked: false,
+            },
+            Response::Status {
+                is_on: true,
+                power: 60,
+                tripped: false,
+                locked: true,
+            },
+            Response::Status {
+                is_on: false,
+                power: 0,
+                tripped: true,
+                locked: true,
+            },
+            Response::Temperature {
+                celsius: -3.5,
+                age_secs: 2,
+            },
+            Response::Reading {
+                device_id: "attic:north".to_string(),
+                celsius: 21.0,
+                epoch_secs: 1_700_000_000,
+            },
+            Response::Info(DeviceInfo {
+                name: "Hall".to_string(),
+                addresses: vec!["127.0.0.1:8080".to_string()],
+                ..DeviceInfo::default()
+            }),
+            Response::Stats(ServerStats::default()),
+            Response::Level(40),
+            Response::Pong,
+            Response::error(ErrorCode::Tripped, "Socket is tripped"),
+        ]
+    }
+
+    #[test]
+    fn test_commands_match_the_parser() {
+        let schema = ProtocolSchema::current();
+        let keywords: Vec<&str> = schema.commands.iter().map(|spec| spec.keyword).collect();
+        assert_eq!(keywords, COMMAND_KINDS);
+        let aliases: usize = schema.commands.iter().map(|spec| spec.aliases.len()).sum();
+        assert_eq!(aliases, COMMAND_ALIASES.len());
+
+        for spec in &schema.commands {
+            let frame = sample_frame(spec);
+            let command = Command::from_str(&frame).unwrap();
+            assert_eq!(command.kind(), spec.keyword);
+            assert_eq!(command.to_string(), frame);
+            assert_eq!(
+                command.is_state_changing(),
+                spec.state_changing,
+                "{}",
+                frame
+            );
+            assert_eq!(validate(&frame).unwrap(), {
+                if spec.keyword == "LEVEL" {
+                    FrameKind::CommandOrResponse("LEVEL")
+                } else {
+                    FrameKind::Command(spec.keyword)
+                }
+            });
+            for alias in &spec.aliases {
+                let frame = frame.replacen(spec.keyword, alias, 1);
+                assert_eq!(Command::from_str(&frame).unwrap().kind(), spec.keyword);
+            }
+
+            let responses = sample_responses();
+            for response in &responses {
+                if command.accepts(response) {
+                    assert!(spec.responses.contains(&response.kind()), "{}", response);
+                }
+            }
+            for kind in &spec.responses {
+                assert!(
+                    responses
+                        .iter()
+                        .any(|response| response.kind() == *kind && command.accepts(response)),
+                    "{} does not accept {}",
+                    frame,
+                    kind
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_responses_match_the_parser() {
+        let schema = ProtocolSchema::current();
+        for response in sample_responses() {
+            let frame = response.to_string();
+            assert_eq!(frame.parse::<Response>().unwrap(), response);
+            let kind = validate(&frame).unwrap();
+            assert!(
+                matches!(kind, FrameKind::Response(k) | FrameKind::CommandOrResponse(k) if k == response.kind()),
+                "{} is {:?}",
+                frame,
+                kind
+            );
+        }
+        let keywords: Vec<&str> = schema.responses.iter().map(|spec| spec.keyword).collect();
+        let mut kinds: Vec<&str> = sample_responses().iter().map(Response::kind).collect();
+        kinds.dedup();
+        assert_eq!(keywords, kinds);
+    }
+
+    #[test]
+    fn test_error_codes_are_complete() {
+        // Fails to compile when a code is added, as a reminder to list it.
+        fn listed(code: ErrorCode) -> bool {
+            match code {
+                ErrorCode::Unauthorized
+                | ErrorCode::Forbidden
+                | ErrorCode::InvalidArgument
+                | ErrorCode::Unsupported
+                | ErrorCode::Internal
+                | ErrorCode::TooManyErrors
+                | ErrorCode::Tripped
+                | ErrorCode::Locked
+                | ErrorCode::Overload
+                | ErrorCode::DeviceFault
+                | ErrorCode::ReadOnly => ERROR_CODES.contains(&code),
+            }
+        }
+        assert!(ERROR_CODES.into_iter().all(listed));
+        assert_eq!(ProtocolSchema::current().error_codes[0], "E_UNAUTHORIZED");
+    }
+
+    #[test]
+    fn test_validate_agrees_with_parsers() {
+        for frame in [
+            "on",
+            " Turn_On ",
+            "ON:",
+            "ON:1",
+            "",
+            "STAUS",
+            "AUTH",
+            "AUTH:",
+            "SET_NAME",
+            "SET_NAME:",
+            "LEVEL:101",
+            "dim:7",
+            "PULSE:9",
+            "PULSE:60000",
+            "STATUS:ON:5",
+            "STATUS:ON:5:TRIPPED",
+            "STATUS:DIM:5",
+            "STATUS:ON:5:BROKEN",
+            "STATUS:ON:5:LOCKED",
+            "STATUS:ON:5:TRIPPED:LOCKED",
+            "STATUS:ON:5:LOCKED:TRIPPED",
+            "STATUS:ON:5:LOCKED:LOCKED",
+            "LOCK",
+            "LOCK:",
+            "LOCK:30",
+            "LOCK:0",
+            "LOCK:1441",
+            "UNLOCK:5",
+            "STATUS:ON:-5",
+            "TEMP:21.5:3",
+            "TEMP:NaN:3",
+            "TEMP:hall:21.5:1700000000",
+            "TEMP::21.5:1",
+            "OK",
+            "OK:",
+            "ok",
+            "PONG:1",
+            "ERROR:",
+            "NOPE:1",
+        ] {
+            let kind = validate(frame);
+            let command = Command::from_str(frame).is_ok();
+            let response = Response::from_str(frame).is_ok();
+            let expected = match (command, response) {
+                (true, true) => "both",
+                (true, false) => "command",
+                (false, true) => "response",
+                (false, false) => "invalid",
+            };
+            let got = match kind {
+                Ok(FrameKind::CommandOrResponse(_)) => "both",
+                Ok(FrameKind::Command(_)) => "command",
+                Ok(FrameKind::Response(_)) => "response",
+                Err(_) => "invalid",
+            };
+            assert_eq!(got, expected, "{:?}", frame);
+        }
+        assert!(matches!(
+            validate("STATUS:ON"),
+            Err(ProtocolError::ParseError(_))
+        ));
+        assert!(matches!(
+            validate("FLY"),
+            Err(ProtocolError::InvalidCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = ProtocolSchema::current().to_json();
+        assert!(json.starts_with(&format!(
+            "{{\"version\":{},\"commands\":[{{\"keyword\":\"ON\",\"aliases\":[\"TURN_ON\"],\
+             \"payload\":{{\"kind\":\"none\"}},\"state_changing\":true,\
+             \"responses\":[\"OK\",\"ERROR\"]",
+            PROTOCOL_VERSION
+        )));
+        assert!(json.contains(
+            "{\"kind\":\"fields\",\"fields\":[{\"kind\":\"integer\",\"min\":10,\"max\":60000}],\"optional\":0}"
+        ));
+        assert!(json.ends_with("\"E_READ_ONLY\"]}"));
+        assert_eq!(string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+        // Brackets and braces balance, outside of strings too.
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+    }
+}
@@ -0,0 +1,144 @@
+//! Ctrl+C handling and exit codes shared by the binaries.
+//!
+//! The first signal clears the `running` flag that their loops poll, so threads
+//! wind down and the binary runs its cleanup before exiting with 0. If cleanup
+//! takes longer than the grace period, or a second signal arrives, the process
+//! is terminated with [`EXIT_INTERRUPTED`].
+
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// Configuration or startup failed; `main` returning `Err` exits with this too.
+pub const EXIT_STARTUP_FAILED: i32 = 1;
+/// 128 + SIGINT, what shells report for a process killed by Ctrl+C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+/// How long cleanup may take after Ctrl+C before the process is terminated.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// A shutdown request, shared between the signal handler and the threads that
+/// have to stop.
+#[derive(Clone)]
+pub struct Shutdown {
+    running: Arc<AtomicBool>,
+    requested: Arc<(Mutex<bool>, Condvar)>,
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(true)),
+            requested: Arc::default(),
+            interrupted: Arc::default(),
+        }
+    }
+
+    /// The flag polled by server and client loops; cleared once shutdown is requested.
+    pub fn running(&self) -> Arc<AtomicBool> {
+        self.running.clone()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        !self.running.load(Ordering::SeqCst)
+    }
+
+    /// Clears the running flag and wakes everyone in [`wait`](Self::wait).
+    pub fn request(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        let (requested, condvar) = &*self.requested;
+        *requested.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_all();
+    }
+
+    /// Blocks until shutdown is requested.
+    pub fn wait(&self) {
+        let (requested, condvar) = &*self.requested;
+        let guard = requested.lock().unwrap_or_else(PoisonError::into_inner);
+        let _guard = condvar
+            .wait_while(guard, |requested| !*requested)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Like [`wait`](Self::wait), but gives up after `timeout`. Returns true if
+    /// shutdown was requested.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (requested, condvar) = &*self.requested;
+        let guard = requested.lock().unwrap_or_else(PoisonError::into_inner);
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, timeout, |requested| !*requested)
+            .unwrap_or_else(PoisonError::into_inner);
+        *guard
+    }
+
+    /// The body of a Ctrl+C handler. The first call requests shutdown and starts
+    /// a watchdog that exits with [`EXIT_INTERRUPTED`] if the process is still
+    /// running after `grace`; a second call exits right away.
+    pub fn interrupt(&self, grace: Duration) {
+        if self.interrupted.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting immediately");
+            process::exit(EXIT_INTERRUPTED);
+        }
+        self.request();
+        thread::spawn(move || {
+            thread::sleep(grace);
+            eprintln!(
+                "Shutdown did not finish within {}s, exiting",
+                grace.as_secs_f64()
+            );
+            process::exit(EXIT_INTERRUPTED);
+        });
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_request_clears_running_flag() {
+        let shutdown = Shutdown::new();
+        let running = shutdown.running();
+        assert!(running.load(Ordering::SeqCst));
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+
+        shutdown.clone().request();
+        assert!(!running.load(Ordering::SeqCst));
+        assert!(shutdown.is_requested());
+        assert!(shutdown.wait_timeout(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_wait_returns_once_requested() {
+        let shutdown = Shutdown::new();
+        let waiter = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                shutdown.wait();
+                Instant::now()
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        let requested_at = Instant::now();
+        shutdown.request();
+        assert!(waiter.join().unwrap() >= requested_at);
+    }
+
+    #[test]
+    fn test_first_interrupt_only_requests_shutdown() {
+        let shutdown = Shutdown::new();
+        // The watchdog outlives the test process, so it never fires here.
+        shutdown.interrupt(Duration::from_secs(3600));
+        assert!(shutdown.is_requested());
+        assert!(shutdown.wait_timeout(Duration::from_secs(1)));
+    }
+}
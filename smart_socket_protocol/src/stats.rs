@@ -0,0 +1,109 @@
+use crate::ProtocolError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Payload of a `STATS` response:
+/// `uptime=<s>;connections=<n>;active=<n>;commands=<n>;errors=<n>;switches=<n>;on_secs=<s>`.
+///
+/// Unknown keys are ignored and missing keys default to zero, so servers can add counters
+/// without breaking older clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Seconds since the server started.
+    pub uptime: u64,
+    /// Connections accepted since start.
+    pub connections: u64,
+    /// Connections currently open.
+    pub active: u64,
+    /// Commands received on all connections.
+    pub commands: u64,
+    /// `ERROR` responses sent.
+    pub errors: u64,
+    /// On/off transitions over the device's lifetime, including earlier runs.
+    pub switches: u64,
+    /// Seconds the device has been on over its lifetime.
+    pub on_secs: u64,
+}
+
+impl FromStr for ServerStats {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stats = ServerStats::default();
+        for field in s.split(';') {
+            let Some((key, value)) = field.split_once('=') else {
+                return Err(ProtocolError::ParseError(format!(
+                    "Invalid stats field: {}",
+                    field
+                )));
+            };
+            let slot = match key.trim() {
+                "uptime" => &mut stats.uptime,
+                "connections" => &mut stats.connections,
+                "active" => &mut stats.active,
+                "commands" => &mut stats.commands,
+                "errors" => &mut stats.errors,
+                "switches" => &mut stats.switches,
+                "on_secs" => &mut stats.on_secs,
+                _ => continue,
+            };
+            *slot = value.trim().parse().map_err(|_| {
+                ProtocolError::ParseError(format!("Invalid {} value: {}", key, value))
+            })?;
+        }
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for ServerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "uptime={};connections={};active={};commands={};errors={};switches={};on_secs={}",
+            self.uptime,
+            self.connections,
+            self.active,
+            self.commands,
+            self.errors,
+            self.switches,
+            self.on_secs
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let stats = ServerStats {
+            uptime: 3600,
+            connections: 12,
+            active: 2,
+            commands: 340,
+            errors: 3,
+            switches: 42,
+            on_secs: 7200,
+        };
+        let wire = stats.to_string();
+        assert_eq!(
+            wire,
+            "uptime=3600;connections=12;active=2;commands=340;errors=3;switches=42;on_secs=7200"
+        );
+        assert_eq!(ServerStats::from_str(&wire).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_unknown_and_missing_keys() {
+        let stats = ServerStats::from_str("commands=5;threads=8").unwrap();
+        assert_eq!(stats.commands, 5);
+        assert_eq!(stats.uptime, 0);
+    }
+
+    #[test]
+    fn test_malformed_fields_are_rejected() {
+        assert!(ServerStats::from_str("commands").is_err());
+        assert!(ServerStats::from_str("errors=-1").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! The two systemd protocols the servers speak, implemented by hand rather than
+//! through libsystemd:
+//!
+//! - socket activation: systemd binds the listening sockets itself and passes
+//!   them as file descriptors from 3 on, announced in `LISTEN_PID` and
+//!   `LISTEN_FDS`;
+//! - readiness notification: a `Type=notify` service sends lines such as
+//!   `READY=1` or `STOPPING=1` as datagrams to the Unix socket named in
+//!   `NOTIFY_SOCKET`, where a leading `@` stands for the abstract namespace.
+//!
+//! Without those variables both do nothing, so a server started by hand
+//! behaves as usual.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+
+/// The first descriptor systemd passes; the others follow without gaps.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// The descriptors passed to the process with id `pid`, as `var` reports the
+/// variables. Variables left over for another process, such as the parent of
+/// a fork, yield none.
+fn listen_fds(var: impl Fn(&str) -> Option<String>, pid: u32) -> Result<Vec<RawFd>, String> {
+    let Some(count) = var("LISTEN_FDS") else {
+        return Ok(Vec::new());
+    };
+    if var("LISTEN_PID").and_then(|listen_pid| listen_pid.trim().parse().ok()) != Some(pid) {
+        return Ok(Vec::new());
+    }
+    let end = count
+        .trim()
+        .parse::<RawFd>()
+        .ok()
+        .filter(|count| *count >= 0)
+        .and_then(|count| LISTEN_FDS_START.checked_add(count))
+        .ok_or_else(|| format!("LISTEN_FDS must be a number of sockets, got '{}'", count))?;
+    Ok((LISTEN_FDS_START..end).collect())
+}
+
+/// Takes over the listening sockets systemd passed, in the order of the
+/// `ListenStream=` lines, and clears the variables so that child processes do
+/// not claim them as well. Empty unless the process was socket-activated.
+///
+/// Changing the environment is unsound while other threads may read it, so
+/// call this at the start of `main`, before any thread is spawned.
+pub fn take_listeners() -> io::Result<Vec<TcpListener>> {
+    let fds = listen_fds(|name| env::var(name).ok(), std::process::id())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    fds.into_iter()
+        // SAFETY: systemd passed these descriptors to this very process, as
+        // `LISTEN_PID` confirms, and nothing else in it owns the range.
+        .map(|fd| adopt(unsafe { OwnedFd::from_raw_fd(fd) }))
+        .collect()
+}
+
+/// Turns a passed descriptor into a listener, refusing one that is not a socket.
+fn adopt(fd: OwnedFd) -> io::Result<TcpListener> {
+    let listener = TcpListener::from(fd);
+    listener.local_addr().map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("passed descriptor is not a listening socket: {}", e),
+        )
+    })?;
+    Ok(listener)
+}
+
+/// Sends `state`, e.g. `READY=1`, to systemd. Returns `false` without sending
+/// when `NOTIFY_SOCKET` is not set, i.e. the service is not `Type=notify`.
+pub fn notify(state: &str) -> io::Result<bool> {
+    notify_socket(env::var_os("NOTIFY_SOCKET").as_deref(), state)
+}
+
+/// Sends `state` like [`notify`], reporting a failure through the caller's
+/// `log` instead of returning it: a service manager that cannot be told is no
+/// reason to stop serving.
+pub fn notify_or_log(state: &str, log: impl Fn(&str)) {
+    if let Err(e) = notify(state) {
+        log(&format!("Failed to notify systemd of {}: {}", state, e));
+    }
+}
+
+fn notify_socket(socket: Option<&OsStr>, state: &str) -> io::Result<bool> {
+    let Some(socket) = socket.filter(|socket| !socket.is_empty()) else {
+        return Ok(false);
+    };
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract notification sockets need Linux",
+            ))
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    fn fake_env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn received(socket: &UnixDatagram) -> String {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(fake_env(&[]), 42), Ok(vec![]));
+        assert_eq!(
+            listen_fds(fake_env(&[("LISTEN_PID", "42"), ("LISTEN_FDS", "2")]), 42),
+            Ok(vec![3, 4])
+        );
+        // Meant for another process.
+        assert_eq!(
+            listen_fds(fake_env(&[("LISTEN_PID", "41"), ("LISTEN_FDS", "2")]), 42),
+            Ok(vec![])
+        );
+        assert_eq!(listen_fds(fake_env(&[("LISTEN_FDS", "2")]), 42), Ok(vec![]));
+        for count in ["two", "-1", "2147483647"] {
+            assert!(
+                listen_fds(fake_env(&[("LISTEN_PID", "42"), ("LISTEN_FDS", count)]), 42).is_err(),
+                "{}",
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn test_adopt_listener() {
+        let bound = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = bound.local_addr().unwrap();
+        let listener = adopt(OwnedFd::from(bound)).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), address);
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut greeting = [0; 2];
+        accepted.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting, b"hi");
+    }
+
+    #[test]
+    fn test_adopt_rejects_files() {
+        let file = tempfile::tempfile().unwrap();
+        let error = adopt(OwnedFd::from(file)).unwrap_err();
+        assert!(error.to_string().contains("not a listening socket"));
+    }
+
+    #[test]
+    fn test_notify_path_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        assert!(notify_socket(Some(path.as_os_str()), "READY=1").unwrap());
+        assert!(notify_socket(Some(path.as_os_str()), "STOPPING=1").unwrap());
+        assert_eq!(received(&systemd), "READY=1");
+        assert_eq!(received(&systemd), "STOPPING=1");
+
+        assert!(!notify_socket(None, "READY=1").unwrap());
+        assert!(!notify_socket(Some(OsStr::new("")), "READY=1").unwrap());
+        let missing = dir.path().join("missing");
+        assert!(notify_socket(Some(missing.as_os_str()), "READY=1").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notify_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("smart-socket-notify-test-{}", std::process::id());
+        let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let systemd = UnixDatagram::bind_addr(&address).unwrap();
+        let socket = format!("@{}", name);
+        assert!(notify_socket(Some(OsStr::new(&socket)), "READY=1").unwrap());
+        assert_eq!(received(&systemd), "READY=1");
+    }
+}
@@ -0,0 +1,361 @@
+//! Datagram formats spoken between the thermometer client and server.
+//!
+//! * plain reading: 8 bytes, big-endian `f64` (fire-and-forget)
+//! * reliable reading: `0x01`, big-endian `u64` sequence, big-endian `f64` (17 bytes)
+//! * acknowledgement: `0x06`, big-endian `u64` sequence (9 bytes)
+//! * device reading: `0x02`, id length `N` (1 byte), `N` bytes UTF-8 device id,
+//!   big-endian `f64` (`10 + N` bytes), for many thermometers sharing one port
+//! * history query: ASCII `HIST:<n>`, answered with the `n` most recent readings
+//!   as big-endian `u64` Unix seconds and `f64` pairs, oldest first, or with an
+//!   ASCII `ERR:<reason>` datagram
+//!
+//! Datagrams of any other size or content are counted and dropped.
+
+pub const RELIABLE_FLAG: u8 = 0x01;
+pub const DEVICE_VERSION: u8 = 0x02;
+pub const ACK_FLAG: u8 = 0x06;
+
+pub const PLAIN_LEN: usize = 8;
+pub const RELIABLE_LEN: usize = 17;
+pub const ACK_LEN: usize = 9;
+/// The id length is a single byte.
+pub const MAX_DEVICE_ID_LEN: usize = u8::MAX as usize;
+/// Longest datagram of any format, for sizing receive buffers.
+pub const MAX_PACKET_LEN: usize = 2 + MAX_DEVICE_ID_LEN + PLAIN_LEN;
+/// Size of the server's receive buffer: a full Ethernet MTU, well above
+/// [`MAX_PACKET_LEN`], so an oversized datagram arrives whole and is rejected by
+/// its real length instead of being truncated to one that looks valid.
+pub const RECV_BUFFER_LEN: usize = 1500;
+
+/// Checked before any other format: `HIST:123` is also 8 bytes long, but no
+/// real reading starts with these bytes (as an `f64` it is about 2e40).
+pub const HISTORY_PREFIX: &[u8] = b"HIST:";
+pub const ERROR_PREFIX: &[u8] = b"ERR:";
+pub const TOO_MANY: &str = "too_many";
+pub const HISTORY_ENTRY_LEN: usize = 16;
+/// Largest history reply; stays below the usual 1500-byte MTU.
+pub const MAX_HISTORY_REPLY_LEN: usize = 1400;
+pub const MAX_HISTORY_ENTRIES: usize = MAX_HISTORY_REPLY_LEN / HISTORY_ENTRY_LEN;
+
+/// One reading in a history reply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub epoch_secs: u64,
+    pub celsius: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Reading(f64),
+    Reliable {
+        seq: u64,
+        temperature: f64,
+    },
+    DeviceReading {
+        device_id: String,
+        temperature: f64,
+    },
+    /// Asks for up to `count` of the most recent readings.
+    HistoryRequest {
+        count: usize,
+    },
+}
+
+/// Checks that `device_id` fits the device reading header.
+pub fn check_device_id(device_id: &str) -> Result<(), String> {
+    if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LEN {
+        return Err(format!(
+            "Device id must be 1 to {} bytes long",
+            MAX_DEVICE_ID_LEN
+        ));
+    }
+    Ok(())
+}
+
+pub fn encode_reading(temperature: f64) -> [u8; PLAIN_LEN] {
+    temperature.to_be_bytes()
+}
+
+pub fn encode_reliable(seq: u64, temperature: f64) -> [u8; RELIABLE_LEN] {
+    let mut buf = [0u8; RELIABLE_LEN];
+    buf[0] = RELIABLE_FLAG;
+    buf[1..9].copy_from_slice(&seq.to_be_bytes());
+    buf[9..].copy_from_slice(&temperature.to_be_bytes());
+    buf
+}
+
+pub fn encode_device_reading(device_id: &str, temperature: f64) -> Result<Vec<u8>, String> {
+    check_device_id(device_id)?;
+    let mut buf = Vec::with_capacity(2 + device_id.len() + PLAIN_LEN);
+    buf.push(DEVICE_VERSION);
+    buf.push(device_id.len() as u8);
+    buf.extend_from_slice(device_id.as_bytes());
+    buf.extend_from_slice(&temperature.to_be_bytes());
+    Ok(buf)
+}
+
+pub fn encode_ack(seq: u64) -> [u8; ACK_LEN] {
+    let mut buf = [0u8; ACK_LEN];
+    buf[0] = ACK_FLAG;
+    buf[1..].copy_from_slice(&seq.to_be_bytes());
+    buf
+}
+
+pub fn encode_history_request(count: usize) -> Vec<u8> {
+    [HISTORY_PREFIX, count.to_string().as_bytes()].concat()
+}
+
+/// Encodes a history reply; fails if the entries would not fit in one datagram.
+pub fn encode_history_reply(entries: &[HistoryEntry]) -> Result<Vec<u8>, String> {
+    if entries.len() > MAX_HISTORY_ENTRIES {
+        return Err(format!(
+            "A history reply holds at most {} readings",
+            MAX_HISTORY_ENTRIES
+        ));
+    }
+    let mut buf = Vec::with_capacity(entries.len() * HISTORY_ENTRY_LEN);
+    for entry in entries {
+        buf.extend_from_slice(&entry.epoch_secs.to_be_bytes());
+        buf.extend_from_slice(&entry.celsius.to_be_bytes());
+    }
+    Ok(buf)
+}
+
+pub fn encode_error(reason: &str) -> Vec<u8> {
+    [ERROR_PREFIX, reason.as_bytes()].concat()
+}
+
+/// Decodes the answer to a history query. An `ERR:` datagram gives its reason
+/// as the error.
+pub fn decode_history_reply(buf: &[u8]) -> Result<Vec<HistoryEntry>, String> {
+    if let Some(reason) = buf.strip_prefix(ERROR_PREFIX) {
+        return Err(String::from_utf8_lossy(reason).into_owned());
+    }
+    if !buf.len().is_multiple_of(HISTORY_ENTRY_LEN) {
+        return Err(format!(
+            "History reply of {} bytes is not a whole number of readings",
+            buf.len()
+        ));
+    }
+    Ok(buf
+        .chunks_exact(HISTORY_ENTRY_LEN)
+        .map(|chunk| {
+            let (secs, celsius) = chunk.split_at(8);
+            HistoryEntry {
+                epoch_secs: u64::from_be_bytes(secs.try_into().expect("8-byte half")),
+                celsius: f64::from_be_bytes(celsius.try_into().expect("8-byte half")),
+            }
+        })
+        .collect())
+}
+
+/// Decodes a reading datagram or history query; returns `None` for unknown
+/// formats. Apart from a history query, an 8-byte datagram is always a plain
+/// reading, whatever its first byte.
+pub fn decode(buf: &[u8]) -> Option<Packet> {
+    if let Some(count) = buf.strip_prefix(HISTORY_PREFIX) {
+        let count = std::str::from_utf8(count).ok()?;
+        if !count.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        return Some(Packet::HistoryRequest {
+            count: count.parse().ok()?,
+        });
+    }
+    match buf.len() {
+        PLAIN_LEN => Some(Packet::Reading(f64::from_be_bytes(buf.try_into().ok()?))),
+        RELIABLE_LEN if buf[0] == RELIABLE_FLAG => Some(Packet::Reliable {
+            seq: u64::from_be_bytes(buf[1..9].try_into().ok()?),
+            temperature: f64::from_be_bytes(buf[9..].try_into().ok()?),
+        }),
+        _ => decode_device_reading(buf),
+    }
+}
+
+/// The id length must match the datagram exactly, so a truncated or padded
+/// packet is dropped rather than read with a shifted temperature.
+fn decode_device_reading(buf: &[u8]) -> Option<Packet> {
+    let [DEVICE_VERSION, id_len, rest @ ..] = buf else {
+        return None;
+    };
+    let id_len = usize::from(*id_len);
+    if id_len == 0 || rest.len() != id_len + PLAIN_LEN {
+        return None;
+    }
+    let (id, temperature) = rest.split_at(id_len);
+    Some(Packet::DeviceReading {
+        device_id: std::str::from_utf8(id).ok()?.to_string(),
+        temperature: f64::from_be_bytes(temperature.try_into().ok()?),
+    })
+}
+
+/// Decodes an acknowledgement datagram into its sequence number.
+pub fn decode_ack(buf: &[u8]) -> Option<u64> {
+    match buf {
+        [ACK_FLAG, seq @ ..] if seq.len() == 8 => Some(u64::from_be_bytes(seq.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_round_trip() {
+        assert_eq!(decode(&encode_reading(21.5)), Some(Packet::Reading(21.5)));
+    }
+
+    #[test]
+    fn test_reliable_round_trip() {
+        assert_eq!(
+            decode(&encode_reliable(7, -3.25)),
+            Some(Packet::Reliable {
+                seq: 7,
+                temperature: -3.25
+            })
+        );
+    }
+
+    #[test]
+    fn test_ack_round_trip() {
+        assert_eq!(decode_ack(&encode_ack(u64::MAX)), Some(u64::MAX));
+        assert_eq!(decode_ack(&[ACK_FLAG, 1, 2]), None);
+        assert_eq!(decode_ack(&encode_reliable(1, 1.0)), None);
+    }
+
+    #[test]
+    fn test_device_reading_round_trip() {
+        let buf = encode_device_reading("bedroom", 19.25).unwrap();
+        assert_eq!(buf.len(), 10 + "bedroom".len());
+        assert_eq!(&buf[..2], &[DEVICE_VERSION, 7]);
+        assert_eq!(
+            decode(&buf),
+            Some(Packet::DeviceReading {
+                device_id: "bedroom".to_string(),
+                temperature: 19.25
+            })
+        );
+
+        // Byte length, not characters, goes into the header.
+        let buf = encode_device_reading("спальня", 18.0).unwrap();
+        assert_eq!(usize::from(buf[1]), "спальня".len());
+        assert!(matches!(
+            decode(&buf),
+            Some(Packet::DeviceReading { device_id, .. }) if device_id == "спальня"
+        ));
+
+        // A 7-byte id makes a 17-byte datagram, which must not pass for a reliable one.
+        let buf = encode_device_reading("kitchen", 21.0).unwrap();
+        assert_eq!(buf.len(), RELIABLE_LEN);
+        assert!(matches!(decode(&buf), Some(Packet::DeviceReading { .. })));
+
+        let longest = "x".repeat(MAX_DEVICE_ID_LEN);
+        assert_eq!(
+            encode_device_reading(&longest, 1.0).unwrap().len(),
+            MAX_PACKET_LEN
+        );
+    }
+
+    #[test]
+    fn test_history_request_round_trip() {
+        assert_eq!(encode_history_request(5), b"HIST:5");
+        for count in [0, 5, MAX_HISTORY_ENTRIES, 100_000] {
+            assert_eq!(
+                decode(&encode_history_request(count)),
+                Some(Packet::HistoryRequest { count })
+            );
+        }
+        // Eight bytes, yet a query rather than a plain reading.
+        assert_eq!(
+            decode(b"HIST:123"),
+            Some(Packet::HistoryRequest { count: 123 })
+        );
+        for malformed in [
+            &b"HIST:"[..],
+            b"HIST:-1",
+            b"HIST:+1",
+            b"HIST: 5",
+            b"HIST:5x",
+        ] {
+            assert_eq!(decode(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_history_reply_round_trip() {
+        let entries: Vec<HistoryEntry> = (0..MAX_HISTORY_ENTRIES as u64)
+            .map(|i| HistoryEntry {
+                epoch_secs: 1_700_000_000 + i,
+                celsius: -10.0 + i as f64 * 0.25,
+            })
+            .collect();
+        let buf = encode_history_reply(&entries).unwrap();
+        assert_eq!(buf.len(), 1392);
+        assert!(buf.len() <= MAX_HISTORY_REPLY_LEN);
+        assert_eq!(decode_history_reply(&buf).unwrap(), entries);
+        assert_eq!(decode_history_reply(&[]).unwrap(), []);
+
+        let mut too_many = entries;
+        too_many.push(too_many[0]);
+        assert!(encode_history_reply(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_history_reply_errors() {
+        assert_eq!(encode_error(TOO_MANY), b"ERR:too_many");
+        assert_eq!(
+            decode_history_reply(&encode_error(TOO_MANY)),
+            Err(TOO_MANY.to_string())
+        );
+        assert!(decode_history_reply(&[0; 17]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_device_ids_are_not_encoded() {
+        assert!(encode_device_reading("", 20.0).is_err());
+        assert!(encode_device_reading(&"x".repeat(MAX_DEVICE_ID_LEN + 1), 20.0).is_err());
+    }
+
+    #[test]
+    fn test_malformed_device_readings() {
+        let valid = encode_device_reading("hall", 20.0).unwrap();
+        // Truncated anywhere: in the header, the id or the temperature.
+        for len in 1..valid.len() {
+            if len != PLAIN_LEN {
+                assert_eq!(decode(&valid[..len]), None, "truncated to {}", len);
+            }
+        }
+        // Trailing garbage.
+        let mut padded = valid.clone();
+        padded.push(0);
+        assert_eq!(decode(&padded), None);
+        // Id length larger or smaller than what follows.
+        let mut long = valid.clone();
+        long[1] = 5;
+        assert_eq!(decode(&long), None);
+        let mut short = valid.clone();
+        short[1] = 3;
+        assert_eq!(decode(&short), None);
+        // Empty id.
+        let mut empty = vec![DEVICE_VERSION, 0];
+        empty.extend_from_slice(&20.0f64.to_be_bytes());
+        assert_eq!(decode(&empty), None);
+        // Not UTF-8.
+        let mut latin1 = valid.clone();
+        latin1[2] = 0xff;
+        assert_eq!(decode(&latin1), None);
+        // Unknown version.
+        let mut version = valid;
+        version[0] = 0x03;
+        assert_eq!(decode(&version), None);
+    }
+
+    #[test]
+    fn test_unknown_formats() {
+        assert_eq!(decode(&[1, 2, 3]), None);
+        let mut bad_flag = encode_reliable(1, 20.0);
+        bad_flag[0] = 0x02;
+        assert_eq!(decode(&bad_flag), None);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::ProtocolError;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, StreamOwned};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+pub type TlsServerStream = StreamOwned<rustls::ServerConnection, TcpStream>;
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+fn tls_error(context: &str, e: impl std::fmt::Display) -> ProtocolError {
+    ProtocolError::ConnectionError(format!("{}: {}", context, e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, ProtocolError> {
+    let file = File::open(path).map_err(|e| tls_error(&format!("Failed to open {:?}", path), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| tls_error(&format!("Failed to parse {:?}", path), e))?;
+    if certs.is_empty() {
+        return Err(ProtocolError::ConnectionError(format!(
+            "No certificates found in {:?}",
+            path
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, ProtocolError> {
+    let file = File::open(path).map_err(|e| tls_error(&format!("Failed to open {:?}", path), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| tls_error(&format!("Failed to parse {:?}", path), e))?
+        .ok_or_else(|| {
+            ProtocolError::ConnectionError(format!("No private key found in {:?}", path))
+        })
+}
+
+/// Builds a server-side TLS configuration from PEM certificate chain and key files.
+pub fn server_config(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<Arc<ServerConfig>, ProtocolError> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| tls_error("Invalid TLS certificate or key", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Builds a client-side TLS configuration trusting the CA certificates in `ca_cert_path`.
+pub fn client_config(ca_cert_path: &Path) -> Result<Arc<ClientConfig>, ProtocolError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_cert_path)? {
+        roots
+            .add(cert)
+            .map_err(|e| tls_error("Invalid CA certificate", e))?;
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Wraps an accepted TCP stream and completes the server side of the handshake.
+pub fn accept(
+    config: Arc<ServerConfig>,
+    mut stream: TcpStream,
+) -> Result<TlsServerStream, ProtocolError> {
+    let mut conn = rustls::ServerConnection::new(config)
+        .map_err(|e| tls_error("Failed to create TLS session", e))?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)
+            .map_err(|e| tls_error("TLS handshake failed", e))?;
+    }
+    Ok(StreamOwned::new(conn, stream))
+}
+
+/// Wraps a connected TCP stream and completes the client side of the handshake.
+pub fn connect(
+    config: Arc<ClientConfig>,
+    server_name: &str,
+    mut stream: TcpStream,
+) -> Result<TlsClientStream, ProtocolError> {
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| tls_error("Invalid TLS server name", e))?;
+    let mut conn = ClientConnection::new(config, server_name)
+        .map_err(|e| tls_error("Failed to create TLS session", e))?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)
+            .map_err(|e| tls_error("TLS handshake failed", e))?;
+    }
+    Ok(StreamOwned::new(conn, stream))
+}
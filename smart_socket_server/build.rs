@@ -0,0 +1,17 @@
+//! Embeds the git commit the server is built from, when git and the repository are available.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(hash) = hash {
+        println!("cargo:rustc-env=SMART_SOCKET_GIT_HASH={}", hash.trim());
+    }
+}
@@ -0,0 +1,234 @@
+//! Temporary bans for peers that keep sending garbage.
+//!
+//! A connection that sends too many malformed commands in a row, or too many in
+//! total between valid ones, is closed; an address whose connections are closed that way often enough within a window
+//! is refused for a while.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbuseConfig {
+    /// Consecutive malformed commands after which a connection is closed; 0 disables the limit.
+    pub max_errors: u32,
+    /// Malformed commands in all after which a connection is closed, however many
+    /// valid ones came in between; 0 disables the limit.
+    pub max_errors_total: u32,
+    /// Closures within `window` that ban the address; 0 disables bans.
+    pub strikes: u32,
+    pub window: Duration,
+    /// How long a banned address is refused.
+    pub ban: Duration,
+    /// Most addresses tracked at once; the least recently seen one is forgotten first.
+    pub max_entries: usize,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> Self {
+        Self {
+            max_errors: 5,
+            max_errors_total: 20,
+            strikes: 3,
+            window: Duration::from_secs(60),
+            ban: Duration::from_secs(300),
+            max_entries: 1024,
+        }
+    }
+}
+
+struct Entry {
+    /// Closures still inside the window, oldest first.
+    strikes: Vec<Instant>,
+    banned_until: Option<Instant>,
+    last_seen: Instant,
+}
+
+impl Entry {
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Strikes and bans per peer address, kept in memory only.
+pub struct BanTable {
+    config: RwLock<AbuseConfig>,
+    entries: Mutex<HashMap<IpAddr, Entry>>,
+}
+
+impl BanTable {
+    pub fn new(config: AbuseConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> AbuseConfig {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Applies `config` to later strikes; bans and strikes already recorded stay.
+    pub fn reconfigure(&self, config: AbuseConfig) {
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = config;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<IpAddr, Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records that a connection from `addr` was closed for abuse. Returns true if
+    /// this strike got the address banned.
+    pub fn strike(&self, addr: IpAddr, now: Instant) -> bool {
+        let config = self.config();
+        if config.strikes == 0 {
+            return false;
+        }
+        let window = config.window;
+        let mut entries = self.lock();
+        entries.retain(|_, entry| {
+            entry.is_banned(now)
+                || entry
+                    .strikes
+                    .iter()
+                    .any(|strike| now.duration_since(*strike) < window)
+        });
+        if !entries.contains_key(&addr) && entries.len() >= config.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_seen)
+                .map(|(addr, _)| *addr);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        let entry = entries.entry(addr).or_insert_with(|| Entry {
+            strikes: Vec::new(),
+            banned_until: None,
+            last_seen: now,
+        });
+        entry.last_seen = now;
+        entry
+            .strikes
+            .retain(|strike| now.duration_since(*strike) < window);
+        entry.strikes.push(now);
+        if entry.strikes.len() < config.strikes as usize {
+            return false;
+        }
+        entry.strikes.clear();
+        entry.banned_until = Some(now.checked_add(config.ban).unwrap_or(now));
+        true
+    }
+
+    pub fn is_banned(&self, addr: IpAddr, now: Instant) -> bool {
+        self.lock()
+            .get(&addr)
+            .is_some_and(|entry| entry.is_banned(now))
+    }
+
+    /// Number of addresses currently tracked.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BanTable {
+    fn default() -> Self {
+        Self::new(AbuseConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 168, 1, last])
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_strikes_within_window_ban_until_expiry() {
+        let table = BanTable::default();
+        let start = Instant::now();
+
+        assert!(!table.strike(ip(1), start));
+        assert!(!table.strike(ip(1), start + secs(10)));
+        assert!(!table.is_banned(ip(1), start + secs(10)));
+        assert!(table.strike(ip(1), start + secs(20)));
+
+        assert!(table.is_banned(ip(1), start + secs(20)));
+        assert!(!table.is_banned(ip(2), start + secs(20)));
+        assert!(table.is_banned(ip(1), start + secs(319)));
+        assert!(!table.is_banned(ip(1), start + secs(320)));
+    }
+
+    #[test]
+    fn test_strikes_outside_window_are_forgotten() {
+        let table = BanTable::default();
+        let start = Instant::now();
+
+        table.strike(ip(1), start);
+        table.strike(ip(1), start + secs(30));
+        assert!(!table.strike(ip(1), start + secs(61)));
+        assert!(!table.is_banned(ip(1), start + secs(61)));
+
+        // Only the strikes at 30s and 61s count; a third one in time bans.
+        assert!(table.strike(ip(1), start + secs(62)));
+    }
+
+    #[test]
+    fn test_expired_entries_are_pruned() {
+        let table = BanTable::default();
+        let start = Instant::now();
+        table.strike(ip(1), start);
+        table.strike(ip(2), start);
+        assert_eq!(table.len(), 2);
+
+        table.strike(ip(3), start + secs(61));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_table_size_is_bounded() {
+        let table = BanTable::new(AbuseConfig {
+            strikes: 1,
+            max_entries: 2,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        table.strike(ip(1), start);
+        table.strike(ip(2), start + secs(1));
+        table.strike(ip(3), start + secs(2));
+
+        assert_eq!(table.len(), 2);
+        assert!(!table.is_banned(ip(1), start + secs(2)));
+        assert!(table.is_banned(ip(2), start + secs(2)));
+        assert!(table.is_banned(ip(3), start + secs(2)));
+    }
+
+    #[test]
+    fn test_zero_strikes_disables_bans() {
+        let table = BanTable::new(AbuseConfig {
+            strikes: 0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(!table.strike(ip(1), now));
+        }
+        assert!(table.is_empty());
+    }
+}
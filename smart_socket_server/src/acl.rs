@@ -0,0 +1,301 @@
+//! Which commands each peer address may send.
+//!
+//! Rules are checked in order and the first one whose range contains the peer
+//! decides: commands in its set are allowed, all others are denied. Peers that
+//! no rule matches get the default policy.
+
+use smart_socket_protocol::{Command, COMMAND_KINDS};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address range such as `192.168.1.0/24` or `fd00::/8`. A bare address is a
+/// range of one; host bits below the prefix are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as `::ffff:a.b.c.d`.
+        match (self.network, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid address '{}' in range '{}'", address, s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("Invalid prefix length in '{}', expected 0-{}", s, max))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// What happens to peers that no rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "allow" => Ok(Policy::Allow),
+            "deny" => Ok(Policy::Deny),
+            other => Err(format!(
+                "Unknown ACL policy '{}', expected allow or deny",
+                other
+            )),
+        }
+    }
+}
+
+/// A range and the command keywords it may send; `*` permits every command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    pub range: Cidr,
+    pub commands: Vec<String>,
+}
+
+impl AclRule {
+    fn permits(&self, command: &Command) -> bool {
+        self.commands
+            .iter()
+            .any(|permitted| permitted == "*" || permitted == command.kind())
+    }
+}
+
+/// Parses `<range>=<COMMAND>,<COMMAND>,...`, e.g. `192.168.1.10=ON,OFF,STATUS`.
+impl FromStr for AclRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, commands) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid ACL rule '{}', expected <range>=<commands>", s))?;
+        let commands = commands
+            .split(',')
+            .map(|command| command.trim().to_ascii_uppercase())
+            .filter(|command| !command.is_empty())
+            .map(|command| {
+                if command == "*" || COMMAND_KINDS.contains(&command.as_str()) {
+                    Ok(command)
+                } else {
+                    Err(format!("Unknown command '{}' in ACL rule '{}'", command, s))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            range: range.parse()?,
+            commands,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub rules: Vec<AclRule>,
+    /// Applies to peers outside every rule's range; allowing keeps a server without
+    /// rules open to everyone.
+    pub default: Policy,
+}
+
+impl Acl {
+    /// Parses `;`-separated rules, e.g. `192.168.1.10=*;192.168.1.0/24=STATUS,INFO`.
+    pub fn parse(rules: &str, default: Policy) -> Result<Self, String> {
+        let rules = rules
+            .split(';')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules, default })
+    }
+
+    /// Resolves what `peer` may do. Done once per connection, since the peer
+    /// address cannot change.
+    pub fn permissions(&self, peer: IpAddr) -> Permissions<'_> {
+        match self.rules.iter().find(|rule| rule.range.contains(peer)) {
+            Some(rule) => Permissions::Rule(rule),
+            None => Permissions::Default(self.default),
+        }
+    }
+}
+
+/// The rule that applies to one peer, or the default policy if none does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permissions<'a> {
+    Rule(&'a AclRule),
+    Default(Policy),
+}
+
+impl Permissions<'_> {
+    /// `Err` carries the reason sent back in `E_FORBIDDEN`.
+    pub fn check(&self, command: &Command) -> Result<(), Denial> {
+        match self {
+            Permissions::Rule(rule) if rule.permits(command) => Ok(()),
+            Permissions::Rule(rule) => Err(Denial {
+                command: command.kind(),
+                range: Some(rule.range),
+            }),
+            Permissions::Default(Policy::Allow) => Ok(()),
+            Permissions::Default(Policy::Deny) => Err(Denial {
+                command: command.kind(),
+                range: None,
+            }),
+        }
+    }
+}
+
+/// Why a command was refused: the rule whose range the peer is in, or `None`
+/// when the default policy denied it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denial {
+    pub command: &'static str,
+    pub range: Option<Cidr>,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.range {
+            Some(range) => write!(f, "{} is not permitted from {}", self.command, range),
+            None => write!(f, "{} is not permitted from this address", self.command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn cidr(range: &str) -> Cidr {
+        range.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_ranges() {
+        assert!(cidr("192.168.1.0/24").contains(ip("192.168.1.200")));
+        assert!(!cidr("192.168.1.0/24").contains(ip("192.168.2.1")));
+        assert!(cidr("192.168.1.10").contains(ip("192.168.1.10")));
+        assert!(!cidr("192.168.1.10").contains(ip("192.168.1.11")));
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(cidr("10.1.2.3/8").contains(ip("10.200.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::1")));
+    }
+
+    #[test]
+    fn test_ipv6_ranges() {
+        assert!(cidr("fd00::/8").contains(ip("fd12:3456::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+        assert!(cidr("::1").contains(ip("::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(!cidr("::/0").contains(ip("127.0.0.1")));
+        assert!(cidr("192.168.1.0/24").contains(ip("::ffff:192.168.1.7")));
+    }
+
+    #[test]
+    fn test_invalid_ranges() {
+        for range in ["", "localhost", "10.0.0.0/33", "::/129", "10.0.0.0/x", "/8"] {
+            assert!(range.parse::<Cidr>().is_err(), "{:?}", range);
+        }
+        assert_eq!(cidr("fd00::/8").to_string(), "fd00::/8");
+    }
+
+    #[test]
+    fn test_rule_parsing() {
+        let rule: AclRule = "192.168.1.10 = on, off,STATUS".parse().unwrap();
+        assert_eq!(rule.range, cidr("192.168.1.10/32"));
+        assert_eq!(rule.commands, ["ON", "OFF", "STATUS"]);
+
+        assert!("192.168.1.10".parse::<AclRule>().is_err());
+        assert!("192.168.1.10=ON,REBOOT".parse::<AclRule>().is_err());
+        assert!(Acl::parse("::1=*;", Policy::Deny).is_ok());
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let acl = Acl::parse("192.168.1.10=*;192.168.1.0/24=STATUS,INFO", Policy::Deny).unwrap();
+
+        let hub = acl.permissions(ip("192.168.1.10"));
+        assert!(hub.check(&Command::TurnOn).is_ok());
+
+        let neighbour = acl.permissions(ip("192.168.1.20"));
+        assert!(neighbour.check(&Command::GetStatus).is_ok());
+        assert_eq!(
+            neighbour.check(&Command::TurnOn).unwrap_err().to_string(),
+            "ON is not permitted from 192.168.1.0/24"
+        );
+
+        // The narrower rule never applies when a broader one comes first.
+        let acl = Acl::parse("192.168.1.0/24=STATUS;192.168.1.10=*", Policy::Allow).unwrap();
+        assert!(acl
+            .permissions(ip("192.168.1.10"))
+            .check(&Command::TurnOn)
+            .is_err());
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let allow = Acl::parse("192.168.1.10=STATUS", Policy::Allow).unwrap();
+        assert!(allow
+            .permissions(ip("10.0.0.1"))
+            .check(&Command::TurnOn)
+            .is_ok());
+
+        let deny = Acl::parse("192.168.1.10=STATUS", Policy::Deny).unwrap();
+        assert!(deny
+            .permissions(ip("10.0.0.1"))
+            .check(&Command::GetStatus)
+            .is_err());
+
+        assert!(Acl::default()
+            .permissions(ip("::1"))
+            .check(&Command::Pulse(100))
+            .is_ok());
+        assert!("block".parse::<Policy>().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Sending status beacons (see [`smart_socket_protocol::beacon`]) to a unicast
+//! or multicast address. Sends never block: a destination that is down only
+//! costs a logged error, never the server's time.
+
+use crate::server::log;
+use smart_socket_protocol::beacon::Beacon;
+use smart_socket_protocol::Address;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+pub const DEFAULT_BEACON_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BeaconConfig {
+    /// Where beacons go; a multicast group works as well as a single host.
+    pub address: Address,
+    pub interval: Duration,
+}
+
+impl BeaconConfig {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            interval: DEFAULT_BEACON_INTERVAL,
+        }
+    }
+}
+
+pub struct BeaconSender {
+    socket: UdpSocket,
+    address: Address,
+    /// The error of the last failed send, so a destination that stays down is
+    /// logged once rather than on every beacon.
+    failure: Option<String>,
+}
+
+impl BeaconSender {
+    /// Binds a non-blocking socket of the family `address` needs.
+    pub fn bind(address: Address) -> io::Result<Self> {
+        let local = match &address {
+            Address::Ip(SocketAddr::V6(_)) => "[::]:0",
+            _ => "0.0.0.0:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            address,
+            failure: None,
+        })
+    }
+
+    /// Sends `beacon`, resolving the address again so a changed DNS entry is
+    /// followed. Returns whether it went out.
+    pub fn send(&mut self, beacon: &Beacon) -> bool {
+        let sent = self
+            .address
+            .to_socket_addrs()
+            .and_then(|mut addrs| {
+                addrs.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+                })
+            })
+            .and_then(|to| self.socket.send_to(beacon.to_string().as_bytes(), to));
+        match sent {
+            Ok(_) => {
+                if self.failure.take().is_some() {
+                    log(&format!("Beacons to {} are going out again", self.address));
+                }
+                true
+            }
+            Err(e) => {
+                let failure = e.to_string();
+                if self.failure.as_ref() != Some(&failure) {
+                    log(&format!(
+                        "Failed to send beacon to {}: {}",
+                        self.address, failure
+                    ));
+                    self.failure = Some(failure);
+                }
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon() -> Beacon {
+        Beacon {
+            name: "Lamp".to_string(),
+            is_on: false,
+            power: 0,
+            uptime_secs: 5,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_send_to_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender = BeaconSender::bind(listener.local_addr().unwrap().into()).unwrap();
+        assert!(sender.send(&beacon()));
+        let (_, received) = Beacon::listen(&listener, Duration::from_secs(2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, beacon());
+    }
+
+    #[test]
+    fn test_unresolvable_destination_does_not_block() {
+        let address = Address::Host {
+            host: "beacon.invalid".to_string(),
+            port: 9999,
+        };
+        let mut sender = BeaconSender::bind(address).unwrap();
+        assert!(!sender.send(&beacon()));
+        assert!(sender.failure.is_some());
+    }
+}
@@ -0,0 +1,120 @@
+//! Child lock: while set, the server refuses to switch the device but still
+//! answers queries. A lock can be indefinite or end after a number of minutes,
+//! and is kept across restarts through the state file.
+
+use smart_socket_protocol::clock;
+use std::time::{Duration, SystemTime};
+
+/// Lock state for one device. A timed lock expires lazily: every check takes
+/// the current time and clears a lock whose deadline has passed, so no thread
+/// has to wake up for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChildLock {
+    state: Option<Lock>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lock {
+    /// `None` keeps the lock until `UNLOCK`.
+    until: Option<SystemTime>,
+}
+
+impl ChildLock {
+    /// Locks until `UNLOCK`, or for `duration`, replacing any current lock.
+    pub fn lock(&mut self, duration: Option<Duration>, now: SystemTime) {
+        self.state = Some(Lock {
+            until: duration.map(|duration| now + duration),
+        });
+    }
+
+    /// Clears the lock; returns whether one was in force.
+    pub fn unlock(&mut self, now: SystemTime) -> bool {
+        let was_locked = self.is_locked(now);
+        self.state = None;
+        was_locked
+    }
+
+    /// Whether switching is refused at `now`. An expired lock is cleared.
+    pub fn is_locked(&mut self, now: SystemTime) -> bool {
+        if self
+            .state
+            .is_some_and(|lock| lock.until.is_some_and(|until| until <= now))
+        {
+            self.state = None;
+        }
+        self.state.is_some()
+    }
+
+    /// When a timed lock ends; `None` for no lock or an indefinite one.
+    pub fn until(&self) -> Option<SystemTime> {
+        self.state.and_then(|lock| lock.until)
+    }
+
+    /// The form written to the state file: `None` when unlocked, `Some(0)` for
+    /// an indefinite lock, or the expiry in seconds since the Unix epoch.
+    pub fn to_saved(&self) -> Option<u64> {
+        let lock = self.state?;
+        Some(lock.until.map(clock::epoch_secs).unwrap_or(0))
+    }
+
+    /// Reads back a saved lock; one that expired while the server was down is dropped.
+    pub fn from_saved(saved: Option<u64>, now: SystemTime) -> Self {
+        let state = saved.map(|secs| Lock {
+            until: (secs != 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        });
+        let mut lock = Self { state };
+        lock.is_locked(now);
+        lock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_timed_lock_expires() {
+        let mut lock = ChildLock::default();
+        assert!(!lock.is_locked(at(1_000)));
+        lock.lock(Some(Duration::from_secs(60)), at(1_000));
+        assert!(lock.is_locked(at(1_059)));
+        assert_eq!(lock.until(), Some(at(1_060)));
+        assert!(!lock.is_locked(at(1_060)));
+        assert_eq!(lock, ChildLock::default());
+        assert!(!lock.unlock(at(1_060)));
+    }
+
+    #[test]
+    fn test_indefinite_lock_lasts_until_unlock() {
+        let mut lock = ChildLock::default();
+        lock.lock(Some(Duration::from_secs(60)), at(1_000));
+        lock.lock(None, at(1_010));
+        assert!(lock.is_locked(at(u32::MAX as u64)));
+        assert_eq!(lock.until(), None);
+        assert!(lock.unlock(at(2_000)));
+        assert!(!lock.is_locked(at(2_000)));
+    }
+
+    #[test]
+    fn test_saved_round_trip() {
+        let mut lock = ChildLock::default();
+        assert_eq!(lock.to_saved(), None);
+        lock.lock(None, at(1_000));
+        assert_eq!(lock.to_saved(), Some(0));
+        assert_eq!(ChildLock::from_saved(Some(0), at(5_000)), lock);
+
+        lock.lock(Some(Duration::from_secs(600)), at(1_000));
+        assert_eq!(lock.to_saved(), Some(1_600));
+        assert_eq!(ChildLock::from_saved(Some(1_600), at(1_100)), lock);
+        // Expired while the server was down.
+        assert_eq!(
+            ChildLock::from_saved(Some(1_600), at(1_600)),
+            ChildLock::default()
+        );
+        assert_eq!(ChildLock::from_saved(None, at(0)), ChildLock::default());
+    }
+}
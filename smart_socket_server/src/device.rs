@@ -0,0 +1,210 @@
+use smart_home::devices::socket::Socket;
+use smart_socket_protocol::{Command, Response};
+use std::fmt;
+use std::str::FromStr;
+
+/// Why a device did not switch on. The device is left as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwitchError {
+    /// Switching on would draw `power` watts, more than the device's own `limit`.
+    Overload { power: u32, limit: u32 },
+    /// The device is locked at the hardware, independently of the server's child lock.
+    Locked,
+    /// The switch itself failed, e.g. a relay that did not close.
+    Fault(String),
+}
+
+impl fmt::Display for SwitchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwitchError::Overload { power, limit } => {
+                write!(f, "{}W would exceed the device's {}W limit", power, limit)
+            }
+            SwitchError::Locked => write!(f, "Device is locked"),
+            SwitchError::Fault(reason) => write!(f, "Device fault: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SwitchError {}
+
+/// A switchable device served over the socket protocol.
+pub trait Device: Send {
+    fn turn_on(&mut self) -> Result<(), SwitchError>;
+    fn turn_off(&mut self);
+    fn is_on(&self) -> bool;
+    fn get_power(&self) -> u32;
+
+    /// Level control, for devices that have it.
+    fn as_dimmable(&mut self) -> Option<&mut dyn Dimmable> {
+        None
+    }
+
+    /// Current level, for devices that have one.
+    fn level(&self) -> Option<u8> {
+        None
+    }
+
+    /// A reply that replaces the normal handling of `command`. Only consulted
+    /// when the server runs a simulated device.
+    fn intercept(&mut self, _command: &Command) -> Option<Response> {
+        None
+    }
+}
+
+/// Devices whose output can be set between 0 and 100 percent.
+pub trait Dimmable {
+    /// Sets the level; values above 100 are clamped.
+    fn set_level(&mut self, level: u8);
+    fn get_level(&self) -> u8;
+}
+
+impl Device for Socket {
+    fn turn_on(&mut self) -> Result<(), SwitchError> {
+        Socket::turn_on(self);
+        Ok(())
+    }
+
+    fn turn_off(&mut self) {
+        Socket::turn_off(self)
+    }
+
+    fn is_on(&self) -> bool {
+        Socket::is_on(self)
+    }
+
+    fn get_power(&self) -> u32 {
+        Socket::get_power(self)
+    }
+}
+
+/// A socket with a dimmer: power draw scales with the level.
+pub struct Dimmer {
+    socket: Socket,
+    level: u8,
+}
+
+impl Dimmer {
+    /// Wraps `socket`, starting at full level.
+    pub fn new(socket: Socket) -> Self {
+        Self { socket, level: 100 }
+    }
+}
+
+impl Device for Dimmer {
+    fn turn_on(&mut self) -> Result<(), SwitchError> {
+        Device::turn_on(&mut self.socket)
+    }
+
+    fn turn_off(&mut self) {
+        self.socket.turn_off()
+    }
+
+    fn is_on(&self) -> bool {
+        self.socket.is_on()
+    }
+
+    fn get_power(&self) -> u32 {
+        (u64::from(self.socket.get_power()) * u64::from(self.level) / 100) as u32
+    }
+
+    fn as_dimmable(&mut self) -> Option<&mut dyn Dimmable> {
+        Some(self)
+    }
+
+    fn level(&self) -> Option<u8> {
+        Some(self.level)
+    }
+}
+
+impl Dimmable for Dimmer {
+    fn set_level(&mut self, level: u8) {
+        self.level = level.min(100);
+    }
+
+    fn get_level(&self) -> u8 {
+        self.level
+    }
+}
+
+/// Which device the server drives, chosen by configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceType {
+    #[default]
+    Socket,
+    Dimmer,
+}
+
+impl DeviceType {
+    pub fn build(self, socket: Socket) -> Box<dyn Device> {
+        match self {
+            DeviceType::Socket => Box::new(socket),
+            DeviceType::Dimmer => Box::new(Dimmer::new(socket)),
+        }
+    }
+}
+
+impl FromStr for DeviceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "socket" => Ok(DeviceType::Socket),
+            "dimmer" => Ok(DeviceType::Dimmer),
+            other => Err(format!(
+                "Unknown device type '{}', expected socket or dimmer",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceType::Socket => write!(f, "socket"),
+            DeviceType::Dimmer => write!(f, "dimmer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket() -> Socket {
+        Socket::new("Lamp", 200).unwrap()
+    }
+
+    #[test]
+    fn test_socket_has_no_level() {
+        let mut device = DeviceType::Socket.build(socket());
+        assert!(device.as_dimmable().is_none());
+        assert_eq!(device.level(), None);
+    }
+
+    #[test]
+    fn test_dimmer_scales_power() {
+        let mut reference = socket();
+        reference.turn_on();
+        let full = reference.get_power();
+
+        let mut dimmer = Dimmer::new(socket());
+        dimmer.turn_on().unwrap();
+        assert_eq!(dimmer.get_power(), full);
+
+        dimmer.set_level(50);
+        assert_eq!(dimmer.get_level(), 50);
+        assert_eq!(dimmer.get_power(), full / 2);
+
+        dimmer.set_level(250);
+        assert_eq!(dimmer.get_level(), 100);
+    }
+
+    #[test]
+    fn test_parse_device_type() {
+        assert_eq!("dimmer".parse(), Ok(DeviceType::Dimmer));
+        assert_eq!("socket".parse(), Ok(DeviceType::Socket));
+        assert!("toaster".parse::<DeviceType>().is_err());
+    }
+}
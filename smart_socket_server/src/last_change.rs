@@ -0,0 +1,67 @@
+//! Who last changed the device and how: answers "why did it turn off?" after
+//! the fact. Reported in INFO and kept across restarts through the state file.
+
+use crate::persistence::SavedChange;
+use smart_socket_protocol::{clock, Command};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// The last command that actually changed the device. Commands that leave it
+/// as it was, such as ON while it is already on, do not replace it.
+#[derive(Debug, Clone)]
+pub struct LastChange {
+    pub at: SystemTime,
+    /// The client's address, as in log lines and the journal.
+    pub peer: String,
+    pub command: Command,
+}
+
+impl LastChange {
+    pub fn epoch_secs(&self) -> u64 {
+        clock::epoch_secs(self.at)
+    }
+
+    /// The form written to the state file, with the command in its wire form.
+    pub fn to_saved(&self) -> SavedChange {
+        SavedChange {
+            at: self.epoch_secs(),
+            peer: self.peer.clone(),
+            command: self.command.to_string(),
+        }
+    }
+
+    /// Reads back a saved change; `None` if its command no longer parses.
+    pub fn from_saved(saved: &SavedChange) -> Option<Self> {
+        Some(Self {
+            at: SystemTime::UNIX_EPOCH + Duration::from_secs(saved.at),
+            peer: saved.peer.clone(),
+            command: Command::from_str(&saved.command).ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_round_trip() {
+        let change = LastChange {
+            at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            peer: "127.0.0.1:50412".to_string(),
+            command: Command::SetName("Hall lamp".to_string()),
+        };
+        let saved = change.to_saved();
+        assert_eq!(saved.command, "SET_NAME:Hall lamp");
+        let restored = LastChange::from_saved(&saved).unwrap();
+        assert_eq!(restored.at, change.at);
+        assert_eq!(restored.peer, change.peer);
+        assert_eq!(restored.command.to_string(), "SET_NAME:Hall lamp");
+
+        let unknown = SavedChange {
+            command: "SELF_DESTRUCT".to_string(),
+            ..saved
+        };
+        assert!(LastChange::from_saved(&unknown).is_none());
+    }
+}
@@ -1,152 +1,282 @@
-use smart_home::devices::socket::Socket;
-use smart_socket_server::{read_message, serialize_message, Command, ProtocolError, Response};
-use std::io::{self, Write};
-use std::net::{TcpListener, TcpStream};
-use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+//! Runs the smart socket server configured from `SMART_SOCKET_*` environment variables,
+//! or from the config file named by `SMART_SOCKET_CONFIG`.
+
+use smart_socket_protocol::address;
+use smart_socket_protocol::journal::JournalConfig;
+use smart_socket_protocol::schema::ProtocolSchema;
+use smart_socket_protocol::shutdown::{Shutdown, EXIT_STARTUP_FAILED, GRACE_PERIOD};
+use smart_socket_protocol::systemd;
+use smart_socket_server::beacon::BeaconConfig;
+use smart_socket_server::net_opts::KeepaliveConfig;
+use smart_socket_server::runtime::{RuntimeConfig, Settings};
+#[cfg(feature = "tls")]
+use smart_socket_server::server::TlsServerConfig;
+use smart_socket_server::server::{
+    log, preflight, run_reverse, run_server, serve, validate_device, version_line,
+    ConnectionContext, PreflightError, ServerConfig,
+};
+use smart_socket_server::simulation::SimulationConfig;
+use smart_socket_server::startup::DeviceInit;
+use smart_socket_server::watchdog::WatchdogConfig;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+#[cfg(unix)]
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 
-fn get_timestamp() -> String {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs()
-        .to_string()
+/// Listening sockets handed over by systemd socket activation; empty when the
+/// server should bind its addresses itself.
+#[cfg(all(unix, feature = "systemd"))]
+fn activated_listeners() -> io::Result<Vec<TcpListener>> {
+    systemd::take_listeners()
 }
 
-fn log(message: &str) {
-    println!("[{}] {}", get_timestamp(), message);
+#[cfg(not(all(unix, feature = "systemd")))]
+fn activated_listeners() -> io::Result<Vec<TcpListener>> {
+    Ok(Vec::new())
 }
 
-fn handle_client(mut stream: TcpStream, socket: Arc<Mutex<Socket>>) -> Result<(), ProtocolError> {
-    stream.set_nonblocking(false).map_err(|e| {
-        ProtocolError::ConnectionError(format!("Failed to set blocking mode: {}", e))
-    })?;
-
-    let peer_addr = stream
-        .peer_addr()
-        .unwrap_or_else(|_| "unknown".parse().unwrap());
-    log(&format!("New client connected: {}", peer_addr));
-
-    while let Ok(command_str) = read_message(&mut stream) {
-        log(&format!(
-            "Received command from {}: {}",
-            peer_addr, command_str
-        ));
-
-        let response = match Command::from_str(&command_str) {
-            Ok(command) => {
-                let mut smart_socket = socket.lock().unwrap();
-                match command {
-                    Command::TurnOn => {
-                        smart_socket.turn_on();
-                        log("Socket turned ON");
-                        Response::Ok("Socket turned on".to_string())
-                    }
-                    Command::TurnOff => {
-                        smart_socket.turn_off();
-                        log("Socket turned OFF");
-                        Response::Ok("Socket turned off".to_string())
-                    }
-                    Command::GetStatus => {
-                        let status = Response::Status {
-                            is_on: smart_socket.is_on(),
-                            power: smart_socket.get_power(),
-                        };
-                        log(&format!("Status requested: {:?}", status));
-                        status
-                    }
-                    Command::GetInfo => {
-                        let info = smart_socket.description();
-                        log(&format!("Info requested: {}", info));
-                        Response::Info(info)
-                    }
-                }
-            }
-            Err(e) => {
-                log(&format!("Error processing command: {}", e));
-                Response::Error(e.to_string())
+fn run_check(result: Result<(), Vec<PreflightError>>, ok: &str) -> ! {
+    match result {
+        Ok(()) => {
+            println!("{}", ok);
+            std::process::exit(0);
+        }
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error);
             }
-        };
-
-        let response_data = serialize_message(&response.to_string());
-        if let Err(e) = stream.write_all(&response_data) {
-            log(&format!("Failed to send response to {}: {}", peer_addr, e));
-            break;
+            std::process::exit(EXIT_STARTUP_FAILED);
         }
     }
-
-    Ok(())
-}
-#[derive(Debug)]
-struct ServerConfig {
-    address: String,
-    socket_name: String,
-    socket_power: u32,
 }
 
-impl Default for ServerConfig {
-    fn default() -> Self {
-        Self {
-            address: "127.0.0.1:8080".to_string(),
-            socket_name: "Kitchen Socket".to_string(),
-            socket_power: 3500,
+/// Reloads the config file whenever the process gets SIGHUP.
+#[cfg(unix)]
+fn reload_on_sighup(context: ConnectionContext) -> std::io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            log("SIGHUP received, reloading configuration");
+            if let Err(e) = context.reload() {
+                log(&format!(
+                    "Reload failed, keeping the running settings: {}",
+                    e
+                ));
+            }
         }
-    }
+    });
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = ServerConfig {
-        address: "127.0.0.1:8080".to_string(),
-        socket_name: "Kitchen Socket".to_string(),
-        socket_power: 3500,
+    // Clears the activation variables, which is only sound while this is the
+    // only thread, so it comes before the device, signal and server threads.
+    let activated = activated_listeners()?;
+    // Needs no configuration, so it comes before any is read.
+    if std::env::args().any(|arg| arg == "--dump-schema") {
+        println!("{}", ProtocolSchema::current().to_json());
+        return Ok(());
+    }
+    let config_file = std::env::var_os("SMART_SOCKET_CONFIG").map(PathBuf::from);
+    let settings = match &config_file {
+        Some(path) => Settings::load(path)?,
+        None => Settings::default(),
     };
+    let mut builder = ServerConfig::builder()
+        .port_fallback(
+            settings
+                .get("SMART_SOCKET_PORT_FALLBACK")
+                .is_some_and(|value| matches!(value.as_str(), "1" | "true")),
+        )
+        .read_only(
+            settings
+                .get("SMART_SOCKET_READ_ONLY")
+                .is_some_and(|value| matches!(value.as_str(), "1" | "true")),
+        )
+        .runtime(RuntimeConfig::from_settings(&settings)?)
+        .banner(
+            settings
+                .get("SMART_SOCKET_BANNER")
+                .is_none_or(|value| !matches!(value.as_str(), "0" | "false")),
+        );
+    if let Some(path) = config_file {
+        builder = builder.config_file(path);
+    }
+    if let Some(addresses) = settings.get("SMART_SOCKET_ADDRESS") {
+        builder = builder.addresses(address::parse_list(&addresses)?);
+    }
+    if let Some(device_type) = settings.get("SMART_SOCKET_DEVICE_TYPE") {
+        builder = builder.device_type(device_type.parse()?);
+    }
+    if let Some(token) = settings.get("SMART_SOCKET_TOKEN") {
+        builder = builder.auth_token(token);
+    }
+    if let Some(psk) = settings.get("SMART_SOCKET_PSK") {
+        builder = builder.psk(&psk);
+    }
+    if let Some(path) = settings.get("SMART_SOCKET_STATE_FILE") {
+        builder = builder.state_file(path);
+    }
+    if let Some(path) = settings.get("SMART_SOCKET_JOURNAL") {
+        let mut journal = JournalConfig::new(path.into());
+        if let Some(max_bytes) = settings.get("SMART_SOCKET_JOURNAL_MAX_BYTES") {
+            journal.max_bytes = max_bytes.parse()?;
+        }
+        if let Some(keep) = settings.get("SMART_SOCKET_JOURNAL_KEEP") {
+            journal.keep = keep.parse()?;
+        }
+        builder = builder.journal(journal);
+    }
+    if let Some(millis) = settings.get("SMART_SOCKET_WRITE_TIMEOUT_MS") {
+        builder = builder.write_timeout(Duration::from_millis(millis.parse()?));
+    }
+    if let Some(millis) = settings.get("SMART_SOCKET_CACHE_TTL_MS") {
+        builder = builder.cache_ttl(Duration::from_millis(millis.parse()?));
+    }
+    if let Some(location) = settings.get("SMART_SOCKET_LOCATION") {
+        builder = builder.location(location);
+    }
+    if let Some(millis) = settings.get("SMART_SOCKET_DEVICE_TIMEOUT_MS") {
+        builder = builder.device_timeout(Duration::from_millis(millis.parse()?));
+    }
+    let mut watchdog = WatchdogConfig::default();
+    if let Some(secs) = settings.get("SMART_SOCKET_WATCHDOG_SECS") {
+        watchdog.stall_timeout = Duration::from_secs(secs.parse()?);
+    }
+    if let Some(action) = settings.get("SMART_SOCKET_WATCHDOG_ACTION") {
+        watchdog.action = action.parse()?;
+    }
+    let mut device_init = DeviceInit::default();
+    if let Some(retries) = settings.get("SMART_SOCKET_DEVICE_INIT_RETRIES") {
+        device_init.retries = retries.parse()?;
+    }
+    if let Some(millis) = settings.get("SMART_SOCKET_DEVICE_INIT_DELAY_MS") {
+        device_init.delay = Duration::from_millis(millis.parse()?);
+    }
+    builder = builder.device_init(device_init);
+    let mut keepalive = KeepaliveConfig::default();
+    if let Some(secs) = settings.get("SMART_SOCKET_KEEPALIVE_IDLE_SECS") {
+        keepalive.idle = Duration::from_secs(secs.parse()?);
+    }
+    if let Some(secs) = settings.get("SMART_SOCKET_KEEPALIVE_INTERVAL_SECS") {
+        keepalive.interval = Duration::from_secs(secs.parse()?);
+    }
+    if let Some(count) = settings.get("SMART_SOCKET_KEEPALIVE_COUNT") {
+        keepalive.count = count.parse()?;
+    }
+    // An idle time of 0 turns keepalive off.
+    builder = builder.keepalive(Some(keepalive).filter(|keepalive| !keepalive.idle.is_zero()));
+    if let Some(locale) = settings.get("SMART_SOCKET_LOCALE") {
+        builder = builder.locale(locale.parse()?);
+    }
+    if let Some(address) = settings.get("SMART_SOCKET_BEACON_ADDRESS") {
+        let mut beacon = BeaconConfig::new(address.parse()?);
+        if let Some(secs) = settings.get("SMART_SOCKET_BEACON_INTERVAL_SECS") {
+            beacon.interval = Duration::from_secs(secs.parse()?);
+        }
+        builder = builder.beacon(Some(beacon));
+    }
+    // A timeout of 0 disables the watchdog.
+    builder = builder.watchdog(Some(watchdog).filter(|watchdog| !watchdog.stall_timeout.is_zero()));
+    if std::env::args().any(|arg| arg == "--simulate") {
+        let mut simulation = SimulationConfig::default();
+        if let Some(millis) = settings.get("SMART_SOCKET_SIM_LATENCY_MS") {
+            simulation.latency = Duration::from_millis(millis.parse()?);
+        }
+        if let Some(rate) = settings.get("SMART_SOCKET_SIM_FAILURE_RATE") {
+            simulation.failure_rate = rate.parse()?;
+        }
+        simulation.script = settings.get("SMART_SOCKET_SIM_SCRIPT").map(PathBuf::from);
+        if let Some(seed) = settings.get("SMART_SOCKET_SIM_SEED") {
+            simulation.seed = Some(seed.parse()?);
+        }
+        builder = builder.simulation(simulation);
+    }
+    if let Some(controller) = settings.get("SMART_SOCKET_CONTROLLER") {
+        builder = builder.controller_address(controller);
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (
+        settings.get("SMART_SOCKET_TLS_CERT"),
+        settings.get("SMART_SOCKET_TLS_KEY"),
+    ) {
+        builder = builder.tls(TlsServerConfig {
+            cert_path: cert.into(),
+            key_path: key.into(),
+        });
+    }
+    let config = builder.build()?;
 
-    let smart_socket = Socket::new(&config.socket_name, config.socket_power)?;
-    let smart_socket = Arc::new(Mutex::new(smart_socket));
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    if std::env::args().any(|arg| arg == "--version") {
+        println!("{}", version_line(config.location.as_deref()));
+        return Ok(());
+    }
+    if std::env::args().any(|arg| arg == "--check") {
+        run_check(preflight(&config), "Configuration OK");
+    }
+    if std::env::args().any(|arg| arg == "--validate-device") {
+        run_check(validate_device(&config), "Device OK");
+    }
+
+    // Reported in full rather than as `main`'s debug print, since a rejected
+    // device setting is the most common reason to end up here.
+    let context = ConnectionContext::from_config(&config).unwrap_or_else(|e| {
+        eprintln!("Startup failed: {}", e);
+        std::process::exit(EXIT_STARTUP_FAILED);
+    });
+    #[cfg(unix)]
+    reload_on_sighup(context.clone())?;
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
 
     ctrlc::set_handler(move || {
         log("Shutdown signal received, stopping server...");
-        r.store(false, Ordering::SeqCst);
+        s.interrupt(GRACE_PERIOD);
     })?;
 
-    let listener = TcpListener::bind(&config.address)?;
-    listener.set_nonblocking(true)?;
-
-    log("Smart socket server is running on port 8080");
-    log("Press Ctrl+C to stop the server");
+    if let Some(controller) = config.controller_address {
+        log(&format!("Dialling the controller at {}", controller));
+        log("Press Ctrl+C to stop the server");
+        let device = run_reverse(controller, context);
+        systemd::notify_or_log("READY=1", log);
+        shutdown.wait();
+        systemd::notify_or_log("STOPPING=1", log);
+        device
+            .shutdown()
+            .unwrap_or_else(|e| log(&format!("Server thread join error: {:?}", e)));
+        log("Server shutdown complete");
+        return Ok(());
+    }
 
-    let mut handles = vec![];
+    let server = if activated.is_empty() {
+        run_server(&config.addresses, config.port_fallback, context)?
+    } else {
+        log(&format!(
+            "Serving {} socket(s) passed by systemd instead of binding",
+            activated.len()
+        ));
+        serve(activated, context)?
+    };
 
-    while running.load(Ordering::SeqCst) {
-        match listener.accept() {
-            Ok((stream, _)) => {
-                let smart_socket_clone = Arc::clone(&smart_socket);
-                let handle = thread::spawn(move || {
-                    if let Err(e) = handle_client(stream, smart_socket_clone) {
-                        log(&format!("Client handler error: {}", e));
-                    }
-                });
-                handles.push(handle);
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                thread::sleep(Duration::from_millis(100));
-                continue;
-            }
-            Err(e) => log(&format!("Connection failed: {}", e)),
-        }
-    }
+    let bound: Vec<_> = server
+        .local_addrs()
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect();
+    log(&format!(
+        "Smart socket server is running on {}",
+        bound.join(", ")
+    ));
+    log("Press Ctrl+C to stop the server");
+    systemd::notify_or_log("READY=1", log);
 
-    log("Waiting for all client connections to close...");
-    for handle in handles {
-        handle
-            .join()
-            .unwrap_or_else(|e| log(&format!("Thread join error: {:?}", e)));
-    }
+    shutdown.wait();
+    systemd::notify_or_log("STOPPING=1", log);
+    server
+        .shutdown()
+        .unwrap_or_else(|e| log(&format!("Server thread join error: {:?}", e)));
     log("Server shutdown complete");
 
     Ok(())
@@ -0,0 +1,253 @@
+//! Catalog of the human-readable text in `OK` and `ERROR` responses.
+//!
+//! Only the message after the keyword and error code is translated: codes,
+//! `STATUS`, `INFO` and every other structured field read the same in every
+//! locale, so machines are not affected. Details quoted from elsewhere, such as
+//! the part of a command that failed to parse, are passed through as they are.
+//! Placeholders are written as `{name}` and filled in by [`format`].
+
+use smart_socket_protocol::ProtocolError;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Accepts bare language codes as well as POSIX-style values such as `ru_RU.UTF-8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s.split(['_', '-', '.']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Ok(Locale::En),
+            "ru" => Ok(Locale::Ru),
+            _ => Err(format!("Unsupported locale: {}", s)),
+        }
+    }
+}
+
+type Catalog = &'static [(&'static str, &'static str)];
+
+const EN: Catalog = &[
+    ("ok.reloaded", "Configuration reloaded"),
+    ("ok.turned_on", "Socket turned on"),
+    ("ok.already_on", "Already on"),
+    ("ok.turned_off", "Socket turned off"),
+    ("ok.renamed", "Socket renamed"),
+    ("ok.pulse_started", "Pulse started"),
+    ("ok.trip_reset", "Trip reset"),
+    ("ok.not_tripped", "Socket was not tripped"),
+    ("ok.locked_for", "Socket locked for {minutes} min"),
+    ("ok.locked", "Socket locked"),
+    ("ok.unlocked", "Socket unlocked"),
+    ("ok.not_locked", "Socket was not locked"),
+    ("ok.auth_not_required", "Authentication not required"),
+    ("ok.authenticated", "Authenticated"),
+    ("error.internal", "Internal device error"),
+    (
+        "error.device_timeout",
+        "Device did not answer within {timeout}",
+    ),
+    ("error.shutting_down", "Server is shutting down"),
+    ("error.no_levels", "Device does not support levels"),
+    ("error.unsupported", "Command not supported: {command}"),
+    ("error.invalid_token", "Invalid token"),
+    ("error.auth_required", "Authentication required"),
+    ("error.too_many_errors", "Too many malformed commands"),
+    ("error.invalid_command", "Invalid command: {detail}"),
+    ("error.parse", "Parse error: {detail}"),
+    (
+        "error.tripped",
+        "Socket tripped at {power}W over the {limit}W limit; send RESET first",
+    ),
+    (
+        "error.overload",
+        "{power}W exceeds the {limit}W limit, socket turned off",
+    ),
+    (
+        "error.locked_for",
+        "Socket is locked for {minutes} more min; send UNLOCK to switch it now",
+    ),
+    ("error.locked", "Socket is locked; send UNLOCK first"),
+    (
+        "error.forbidden_from",
+        "{command} is not permitted from {range}",
+    ),
+    (
+        "error.forbidden",
+        "{command} is not permitted from this address",
+    ),
+    ("error.simulated", "Simulated device failure"),
+    (
+        "error.device_overload",
+        "{power}W exceeds the device's {limit}W limit, socket left off",
+    ),
+    (
+        "error.device_locked",
+        "Device is locked and refused to switch on",
+    ),
+    ("error.device_fault", "Device failed to switch on: {reason}"),
+    (
+        "error.read_only",
+        "{command} is refused: the socket is read-only",
+    ),
+];
+
+const RU: Catalog = &[
+    ("ok.reloaded", "Конфигурация перечитана"),
+    ("ok.turned_on", "Розетка включена"),
+    ("ok.already_on", "Уже включена"),
+    ("ok.turned_off", "Розетка выключена"),
+    ("ok.renamed", "Розетка переименована"),
+    ("ok.pulse_started", "Импульс запущен"),
+    ("ok.trip_reset", "Защита сброшена"),
+    ("ok.not_tripped", "Защита не срабатывала"),
+    ("ok.locked_for", "Розетка заблокирована на {minutes} мин"),
+    ("ok.locked", "Розетка заблокирована"),
+    ("ok.unlocked", "Розетка разблокирована"),
+    ("ok.not_locked", "Розетка не была заблокирована"),
+    ("ok.auth_not_required", "Аутентификация не требуется"),
+    ("ok.authenticated", "Аутентификация пройдена"),
+    ("error.internal", "Внутренняя ошибка устройства"),
+    (
+        "error.device_timeout",
+        "Устройство не ответило за {timeout}",
+    ),
+    ("error.shutting_down", "Сервер завершает работу"),
+    ("error.no_levels", "Устройство не поддерживает уровни"),
+    ("error.unsupported", "Команда не поддерживается: {command}"),
+    ("error.invalid_token", "Неверный токен"),
+    ("error.auth_required", "Требуется аутентификация"),
+    ("error.too_many_errors", "Слишком много некорректных команд"),
+    ("error.invalid_command", "Некорректная команда: {detail}"),
+    ("error.parse", "Ошибка разбора: {detail}"),
+    (
+        "error.tripped",
+        "Защита сработала при {power} Вт сверх предела {limit} Вт; сначала отправьте RESET",
+    ),
+    (
+        "error.overload",
+        "{power} Вт превышает предел {limit} Вт, розетка выключена",
+    ),
+    (
+        "error.locked_for",
+        "Розетка заблокирована ещё на {minutes} мин; отправьте UNLOCK, чтобы переключить её",
+    ),
+    (
+        "error.locked",
+        "Розетка заблокирована; сначала отправьте UNLOCK",
+    ),
+    (
+        "error.forbidden_from",
+        "{command} запрещена с адресов {range}",
+    ),
+    ("error.forbidden", "{command} запрещена с этого адреса"),
+    ("error.simulated", "Имитация отказа устройства"),
+    (
+        "error.device_overload",
+        "{power} Вт превышает предел устройства {limit} Вт, розетка не включена",
+    ),
+    (
+        "error.device_locked",
+        "Устройство заблокировано и не включилось",
+    ),
+    ("error.device_fault", "Устройство не включилось: {reason}"),
+    (
+        "error.read_only",
+        "{command} отклонена: розетка только для чтения",
+    ),
+];
+
+fn catalog(locale: Locale) -> Catalog {
+    match locale {
+        Locale::En => EN,
+        Locale::Ru => RU,
+    }
+}
+
+/// Looks `key` up in `primary`, then in the English catalog; unknown keys are returned as is.
+fn resolve(key: &'static str, primary: Catalog) -> &'static str {
+    [primary, EN]
+        .iter()
+        .find_map(|catalog| {
+            catalog
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, text)| *text)
+        })
+        .unwrap_or(key)
+}
+
+pub fn get(key: &'static str, locale: Locale) -> &'static str {
+    resolve(key, catalog(locale))
+}
+
+/// Looks up `key` and substitutes every `{name}` placeholder from `args`.
+pub fn format(key: &'static str, locale: Locale, args: &[(&str, &dyn fmt::Display)]) -> String {
+    interpolate(get(key, locale), args)
+}
+
+fn interpolate(template: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    args.iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+}
+
+/// The message for a command that failed to parse. The part quoted from the
+/// command stays as the parser wrote it.
+pub fn protocol_error(e: &ProtocolError, locale: Locale) -> String {
+    match e {
+        ProtocolError::InvalidCommand(detail) => {
+            format("error.invalid_command", locale, &[("detail", detail)])
+        }
+        ProtocolError::ParseError(detail) => format("error.parse", locale, &[("detail", detail)]),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_have_the_same_keys() {
+        let keys = |catalog: Catalog| catalog.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(EN), keys(RU));
+    }
+
+    #[test]
+    fn test_english_matches_protocol_errors() {
+        for e in [
+            ProtocolError::InvalidCommand("FOO".to_string()),
+            ProtocolError::ParseError("bad".to_string()),
+            ProtocolError::ConnectionClosed,
+        ] {
+            assert_eq!(protocol_error(&e, Locale::En), e.to_string());
+        }
+        assert_eq!(
+            protocol_error(
+                &ProtocolError::InvalidCommand("FOO".to_string()),
+                Locale::Ru
+            ),
+            "Некорректная команда: FOO"
+        );
+    }
+
+    #[test]
+    fn test_interpolation_and_fallback() {
+        assert_eq!(
+            format("ok.locked_for", Locale::Ru, &[("minutes", &5)]),
+            "Розетка заблокирована на 5 мин"
+        );
+        assert_eq!(resolve("ok.turned_on", &[]), "Socket turned on");
+        assert_eq!(get("no.such.key", Locale::Ru), "no.such.key");
+        assert_eq!("ru_RU.UTF-8".parse(), Ok(Locale::Ru));
+        assert!("de".parse::<Locale>().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! TCP keepalive on accepted connections, so a client whose machine lost power
+//! is noticed within minutes instead of whenever the OS gives up on its own.
+//!
+//! How much of the configuration applies depends on the platform: Windows
+//! always sends 10 probes, and some systems only let the idle time be set.
+
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// Silence on the connection before the first probe.
+    pub idle: Duration,
+    /// Wait between unanswered probes.
+    pub interval: Duration,
+    /// Unanswered probes after which the peer is given up on.
+    pub count: u32,
+}
+
+impl Default for KeepaliveConfig {
+    /// A dead peer is detected about two minutes after it went silent.
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            count: 5,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// How long after the last sign of life a dead peer is given up on.
+    pub fn detection_time(&self) -> Duration {
+        self.idle + self.interval * self.count
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+))]
+fn params(config: &KeepaliveConfig) -> TcpKeepalive {
+    TcpKeepalive::new()
+        .with_time(config.idle)
+        .with_interval(config.interval)
+        .with_retries(config.count)
+}
+
+#[cfg(windows)]
+fn params(config: &KeepaliveConfig) -> TcpKeepalive {
+    TcpKeepalive::new()
+        .with_time(config.idle)
+        .with_interval(config.interval)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    windows,
+)))]
+fn params(config: &KeepaliveConfig) -> TcpKeepalive {
+    TcpKeepalive::new().with_time(config.idle)
+}
+
+/// Turns keepalive on for `stream` with as much of `config` as the platform supports.
+pub fn set_keepalive(stream: &TcpStream, config: &KeepaliveConfig) -> io::Result<()> {
+    SockRef::from(stream).set_tcp_keepalive(&params(config))
+}
+
+/// The keepalive settings in force on `stream`, `None` if keepalive is off.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+))]
+pub fn keepalive(stream: &TcpStream) -> io::Result<Option<KeepaliveConfig>> {
+    let socket = SockRef::from(stream);
+    if !socket.keepalive()? {
+        return Ok(None);
+    }
+    Ok(Some(KeepaliveConfig {
+        idle: socket.keepalive_time()?,
+        interval: socket.keepalive_interval()?,
+        count: socket.keepalive_retries()?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        (client, accepted)
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+    ))]
+    #[test]
+    fn test_options_are_applied() {
+        let (_client, accepted) = connected_pair();
+        assert_eq!(keepalive(&accepted).unwrap(), None);
+        let config = KeepaliveConfig {
+            idle: Duration::from_secs(42),
+            interval: Duration::from_secs(7),
+            count: 3,
+        };
+        set_keepalive(&accepted, &config).unwrap();
+        assert_eq!(keepalive(&accepted).unwrap(), Some(config));
+    }
+
+    #[test]
+    fn test_set_keepalive_succeeds() {
+        let (_client, accepted) = connected_pair();
+        set_keepalive(&accepted, &KeepaliveConfig::default()).unwrap();
+        assert!(SockRef::from(&accepted).keepalive().unwrap());
+        assert_eq!(
+            KeepaliveConfig::default().detection_time(),
+            Duration::from_secs(110)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Overload protection: like a real smart socket, the server cuts power when the
+//! draw exceeds a configured limit and keeps it off until the trip is reset.
+
+use crate::device::Device;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// How many past trips are kept for inspection.
+pub const MAX_EVENTS: usize = 32;
+
+/// A trip: when it happened and the draw that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverloadEvent {
+    pub at: SystemTime,
+    pub power: u32,
+}
+
+/// Trip state for one device. Every method that takes the device expects to
+/// run on the device thread, so a check never races a state change.
+#[derive(Debug, Default)]
+pub struct Breaker {
+    limit: Option<u32>,
+    tripped: Option<OverloadEvent>,
+    events: VecDeque<OverloadEvent>,
+}
+
+impl Breaker {
+    /// `None` disables the limit; the breaker then never trips.
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    /// Changes the limit for the checks that follow; a current trip stays until reset.
+    pub fn set_limit(&mut self, limit: Option<u32>) {
+        self.limit = limit;
+    }
+
+    /// Turns `device` off if it draws more than the limit, recording the trip.
+    /// Returns the new event, or `None` if the draw is within the limit.
+    pub fn check(&mut self, device: &mut dyn Device, at: SystemTime) -> Option<OverloadEvent> {
+        let limit = self.limit?;
+        let power = device.get_power();
+        if power <= limit {
+            return None;
+        }
+        device.turn_off();
+        let event = OverloadEvent { at, power };
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+        self.tripped = Some(event.clone());
+        Some(event)
+    }
+
+    /// The trip keeping the device off, if any.
+    pub fn tripped(&self) -> Option<&OverloadEvent> {
+        self.tripped.as_ref()
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.is_some()
+    }
+
+    /// Clears the trip; the device stays off until it is turned on again.
+    /// Returns the trip that was cleared.
+    pub fn reset(&mut self) -> Option<OverloadEvent> {
+        self.tripped.take()
+    }
+
+    /// Past trips, oldest first, up to [`MAX_EVENTS`].
+    pub fn events(&self) -> impl Iterator<Item = &OverloadEvent> {
+        self.events.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::SwitchError;
+    use std::time::Duration;
+
+    /// Draws a fixed load while on.
+    struct Load {
+        on: bool,
+        power: u32,
+    }
+
+    impl Device for Load {
+        fn turn_on(&mut self) -> Result<(), SwitchError> {
+            self.on = true;
+            Ok(())
+        }
+
+        fn turn_off(&mut self) {
+            self.on = false;
+        }
+
+        fn is_on(&self) -> bool {
+            self.on
+        }
+
+        fn get_power(&self) -> u32 {
+            if self.on {
+                self.power
+            } else {
+                0
+            }
+        }
+    }
+
+    fn load(power: u32) -> Load {
+        Load { on: true, power }
+    }
+
+    #[test]
+    fn test_draw_above_limit_trips() {
+        let mut breaker = Breaker::new(Some(2000));
+        let mut device = load(2500);
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+
+        let event = breaker.check(&mut device, at).unwrap();
+        assert_eq!(event, OverloadEvent { at, power: 2500 });
+        assert!(!device.is_on());
+        assert_eq!(breaker.tripped(), Some(&event));
+        assert_eq!(breaker.events().count(), 1);
+
+        assert_eq!(breaker.reset(), Some(event));
+        assert!(!breaker.is_tripped());
+        assert!(!device.is_on());
+        assert_eq!(breaker.reset(), None);
+    }
+
+    #[test]
+    fn test_draw_within_limit_does_not_trip() {
+        let mut breaker = Breaker::new(Some(2000));
+        let mut device = load(2000);
+        assert_eq!(breaker.check(&mut device, SystemTime::now()), None);
+        assert!(device.is_on());
+        assert!(!breaker.is_tripped());
+
+        let mut unlimited = Breaker::new(None);
+        let mut device = load(u32::MAX);
+        assert_eq!(unlimited.check(&mut device, SystemTime::now()), None);
+        assert!(device.is_on());
+    }
+
+    #[test]
+    fn test_event_history_is_bounded() {
+        let mut breaker = Breaker::new(Some(0));
+        for power in 1..=(MAX_EVENTS as u32 + 5) {
+            breaker.check(&mut load(power), SystemTime::now());
+        }
+        assert_eq!(breaker.events().count(), MAX_EVENTS);
+        assert_eq!(breaker.events().next().unwrap().power, 6);
+        assert_eq!(breaker.tripped().unwrap().power, MAX_EVENTS as u32 + 5);
+    }
+}
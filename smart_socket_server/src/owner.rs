@@ -0,0 +1,306 @@
+//! The thread that owns the device. Everything that touches the device is sent
+//! to it as a job over a channel and runs there, one job at a time in arrival
+//! order, so no lock guards the device and a panicking job cannot poison it.
+//!
+//! Callers wait for a job's result with a timeout; a job that times out still
+//! runs once the thread gets to it. A thread wedged in a job can be abandoned
+//! for a new one with [`DeviceOwner::replace`].
+
+use crate::device::Device;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Default [`crate::server::ServerConfig::device_timeout`].
+pub const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a job gave no result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The device thread did not finish the job in time.
+    Timeout(Duration),
+    /// The device thread has stopped, or is stopping, and no longer runs jobs.
+    Stopped,
+    /// The job panicked; the device thread carries on with the next one.
+    Panicked,
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Timeout(timeout) => {
+                write!(f, "Device did not answer within {:?}", timeout)
+            }
+            DeviceError::Stopped => write!(f, "Server is shutting down"),
+            DeviceError::Panicked => write!(f, "Internal device error"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+/// Gets the device while the thread runs, or `None` once it is stopping.
+type Job = Box<dyn FnOnce(Option<&mut dyn Device>) + Send>;
+
+/// Counters the watchdog reads to tell a busy device thread from a wedged one.
+#[derive(Debug, Default)]
+struct Progress {
+    busy: AtomicBool,
+    completed: AtomicU64,
+}
+
+/// One device thread and the means to reach it.
+struct Worker {
+    sender: mpsc::Sender<Job>,
+    stopping: Arc<AtomicBool>,
+    progress: Arc<Progress>,
+    thread: JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(mut device: Box<dyn Device>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(Progress::default());
+        let thread = {
+            let (stopping, progress) = (stopping.clone(), progress.clone());
+            thread::spawn(move || {
+                // Ends once the sender is dropped and every queued job was handled.
+                for job in receiver {
+                    if stopping.load(Ordering::SeqCst) {
+                        job(None);
+                        continue;
+                    }
+                    progress.busy.store(true, Ordering::SeqCst);
+                    job(Some(device.as_mut()));
+                    progress.busy.store(false, Ordering::SeqCst);
+                    progress.completed.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        Self {
+            sender,
+            stopping,
+            progress,
+            thread,
+        }
+    }
+
+    /// Closes the queue: jobs still in it and sent later get no device. Returns
+    /// the thread, which ends once its current job does.
+    fn stop(self) -> JoinHandle<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.thread
+    }
+}
+
+/// Handle to the device thread, shared by every connection.
+pub struct DeviceOwner {
+    /// `None` once shut down.
+    worker: Mutex<Option<Worker>>,
+}
+
+impl DeviceOwner {
+    /// Moves `device` onto a new thread that runs jobs until [`shutdown`](Self::shutdown).
+    pub fn spawn(device: Box<dyn Device>) -> Self {
+        Self {
+            worker: Mutex::new(Some(Worker::spawn(device))),
+        }
+    }
+
+    fn worker(&self) -> MutexGuard<'_, Option<Worker>> {
+        self.worker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sender(&self) -> Option<mpsc::Sender<Job>> {
+        self.worker().as_ref().map(|worker| worker.sender.clone())
+    }
+
+    /// Runs `f` on the device thread and waits up to `timeout` for its result.
+    /// A panic in `f` is caught there and reported as [`DeviceError::Panicked`].
+    pub fn call<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        f: impl FnOnce(&mut dyn Device) -> T + Send + 'static,
+    ) -> Result<T, DeviceError> {
+        let (reply, result) = mpsc::channel();
+        let job: Job = Box::new(move |device| {
+            let outcome = match device {
+                Some(device) => panic::catch_unwind(AssertUnwindSafe(|| f(device)))
+                    .map_err(|_| DeviceError::Panicked),
+                None => Err(DeviceError::Stopped),
+            };
+            // The caller may have given up waiting.
+            let _ = reply.send(outcome);
+        });
+        match self.sender() {
+            Some(sender) => sender.send(job).map_err(|_| DeviceError::Stopped)?,
+            None => return Err(DeviceError::Stopped),
+        }
+        match result.recv_timeout(timeout) {
+            Ok(outcome) => outcome,
+            Err(RecvTimeoutError::Timeout) => Err(DeviceError::Timeout(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(DeviceError::Stopped),
+        }
+    }
+
+    /// Whether a job is running, and how many have finished, on the current
+    /// device thread. A shut down owner reports itself idle.
+    pub fn progress(&self) -> (bool, u64) {
+        self.worker().as_ref().map_or((false, 0), |worker| {
+            (
+                worker.progress.busy.load(Ordering::SeqCst),
+                worker.progress.completed.load(Ordering::SeqCst),
+            )
+        })
+    }
+
+    /// Moves `device` onto a new thread that takes every job from now on. The
+    /// old thread is not waited for: it is left to finish its current job, if
+    /// it ever does, and the jobs queued behind it are answered with
+    /// [`DeviceError::Stopped`]. Does nothing once shut down.
+    pub fn replace(&self, device: Box<dyn Device>) {
+        let mut worker = self.worker();
+        if let Some(old) = worker.as_mut() {
+            drop(std::mem::replace(old, Worker::spawn(device)).stop());
+        }
+    }
+
+    /// Stops the device thread once the job it is running, if any, finishes.
+    /// Jobs still queued are answered with [`DeviceError::Stopped`], as are
+    /// any sent later. Waits for the thread to exit.
+    pub fn shutdown(&self) {
+        let worker = self.worker().take();
+        if let Some(worker) = worker {
+            let _ = worker.stop().join();
+        }
+    }
+}
+
+impl Drop for DeviceOwner {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceType;
+    use smart_home::devices::socket::Socket;
+    use std::sync::mpsc::Receiver;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn owner() -> DeviceOwner {
+        DeviceOwner::spawn(DeviceType::Socket.build(Socket::new("Test", 1000).unwrap()))
+    }
+
+    /// Occupies the device thread until the returned sender is used or dropped.
+    fn block(owner: &DeviceOwner) -> mpsc::Sender<()> {
+        let (release, wait): (_, Receiver<()>) = mpsc::channel();
+        let (started, running) = mpsc::channel();
+        let blocker = owner.sender().unwrap();
+        blocker
+            .send(Box::new(move |_| {
+                started.send(()).unwrap();
+                let _ = wait.recv();
+            }))
+            .unwrap();
+        running.recv().unwrap();
+        release
+    }
+
+    #[test]
+    fn test_jobs_run_in_order_on_one_device() {
+        let owner = owner();
+        owner
+            .call(TIMEOUT, |device| device.turn_on())
+            .unwrap()
+            .unwrap();
+        assert_eq!(owner.call(TIMEOUT, |device| device.is_on()), Ok(true));
+    }
+
+    #[test]
+    fn test_panicking_job_leaves_the_device_usable() {
+        let owner = owner();
+        assert_eq!(
+            owner.call(TIMEOUT, |_| -> () { panic!("driver fault") }),
+            Err(DeviceError::Panicked)
+        );
+        assert_eq!(owner.call(TIMEOUT, |device| device.is_on()), Ok(false));
+    }
+
+    #[test]
+    fn test_slow_device_times_out() {
+        let owner = owner();
+        let release = block(&owner);
+        let timeout = Duration::from_millis(50);
+        assert_eq!(
+            owner.call(timeout, |device| device.is_on()),
+            Err(DeviceError::Timeout(timeout))
+        );
+        assert_eq!(owner.progress(), (true, 0));
+        drop(release);
+        assert_eq!(owner.call(TIMEOUT, |device| device.is_on()), Ok(false));
+    }
+
+    #[test]
+    fn test_replace_abandons_a_wedged_thread() {
+        let owner = owner();
+        owner
+            .call(TIMEOUT, |device| device.turn_on())
+            .unwrap()
+            .unwrap();
+        let release = block(&owner);
+        let (ran, device_given) = mpsc::channel();
+        owner
+            .sender()
+            .unwrap()
+            .send(Box::new(move |device| ran.send(device.is_some()).unwrap()))
+            .unwrap();
+
+        owner.replace(DeviceType::Socket.build(Socket::new("Fresh", 1000).unwrap()));
+        assert_eq!(owner.progress(), (false, 0));
+        assert_eq!(owner.call(TIMEOUT, |device| device.is_on()), Ok(false));
+
+        // Once free, the old thread runs nothing more on the old device.
+        drop(release);
+        assert_eq!(device_given.recv(), Ok(false));
+        owner.shutdown();
+        assert_eq!(owner.progress(), (false, 0));
+    }
+
+    #[test]
+    fn test_shutdown_answers_queued_jobs() {
+        let owner = Arc::new(owner());
+        let release = block(&owner);
+        let waiting: Vec<_> = (0..3)
+            .map(|_| {
+                let owner = owner.clone();
+                thread::spawn(move || owner.call(TIMEOUT, |device| device.turn_on()))
+            })
+            .collect();
+        // Let the calls queue up behind the blocked job.
+        thread::sleep(Duration::from_millis(100));
+
+        let stopper = {
+            let owner = owner.clone();
+            thread::spawn(move || owner.shutdown())
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(release);
+        stopper.join().unwrap();
+        for call in waiting {
+            assert_eq!(call.join().unwrap(), Err(DeviceError::Stopped));
+        }
+        assert_eq!(
+            owner.call(TIMEOUT, |device| device.is_on()),
+            Err(DeviceError::Stopped)
+        );
+    }
+}
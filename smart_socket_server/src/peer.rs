@@ -0,0 +1,100 @@
+//! Who is on the other end of a connection, for log lines, ACLs and the journal.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+
+/// The remote address, if the OS can report one. Some proxied or abstract
+/// sockets cannot, and their clients are served all the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Addr(SocketAddr),
+    Unknown,
+}
+
+impl Peer {
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Addr(addr) => Some(addr.ip()),
+            Peer::Unknown => None,
+        }
+    }
+}
+
+impl From<io::Result<SocketAddr>> for Peer {
+    fn from(addr: io::Result<SocketAddr>) -> Self {
+        addr.map_or(Peer::Unknown, Peer::Addr)
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Addr(addr) => write!(f, "{}", addr),
+            Peer::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Streams that may be able to report the remote address.
+pub trait RemotePeer {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl RemotePeer for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+/// One connection as it appears in log lines: `#<id> <peer>`. The id tells apart
+/// connections from the same address, and connections whose address is unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientLabel {
+    pub id: u64,
+    pub peer: Peer,
+}
+
+impl ClientLabel {
+    pub fn of<S: RemotePeer>(id: u64, stream: &S) -> Self {
+        Self {
+            id,
+            peer: stream.peer_addr().into(),
+        }
+    }
+}
+
+impl fmt::Display for ClientLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}", self.id, self.peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Detached;
+
+    impl RemotePeer for Detached {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::Error::from(io::ErrorKind::NotConnected))
+        }
+    }
+
+    #[test]
+    fn test_label_formatting() {
+        let addr: SocketAddr = "[::1]:50312".parse().unwrap();
+        let label = ClientLabel {
+            id: 12,
+            peer: Peer::Addr(addr),
+        };
+        assert_eq!(label.to_string(), "#12 [::1]:50312");
+        assert_eq!(label.peer.ip(), Some(addr.ip()));
+
+        let label = ClientLabel::of(3, &Detached);
+        assert_eq!(label.peer, Peer::Unknown);
+        assert_eq!(label.peer.ip(), None);
+        assert_eq!(label.to_string(), "#3 unknown");
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Device state saved across server restarts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedState {
+    pub is_on: bool,
+    /// Name set over the protocol; `None` keeps the configured name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Dimmer level; absent for devices without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+    /// Lifetime on/off transitions, for relay wear.
+    pub switch_count: u64,
+    /// Lifetime on-time in milliseconds.
+    pub total_on_ms: u64,
+    /// The last command that changed the device; absent until one does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_change: Option<SavedChange>,
+    /// Child lock in force: 0 until `UNLOCK`, otherwise when it expires in
+    /// seconds since the Unix epoch. Absent when unlocked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock: Option<u64>,
+}
+
+/// A [`LastChange`](crate::last_change::LastChange) as stored in the state file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedChange {
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+    pub peer: String,
+    /// The command in its wire form, e.g. `ON`.
+    pub command: String,
+}
+
+/// Loads the saved state, returning `None` when no state file exists yet.
+pub fn load(path: &Path) -> io::Result<Option<PersistedState>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&data)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes the state atomically: a temporary file next to `path` is renamed over it.
+pub fn save(path: &Path, state: &PersistedState) -> io::Result<()> {
+    let data = serde_json::to_string_pretty(state)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        assert_eq!(load(&path).unwrap(), None);
+        let state = PersistedState {
+            is_on: true,
+            name: Some("Hallway".to_string()),
+            level: Some(40),
+            switch_count: 17,
+            total_on_ms: 3_600_250,
+            last_change: Some(SavedChange {
+                at: 1_700_000_000,
+                peer: "127.0.0.1:50412".to_string(),
+                command: "OFF".to_string(),
+            }),
+            lock: Some(1_700_000_600),
+        };
+        save(&path, &state).unwrap();
+        assert_eq!(load(&path).unwrap(), Some(state));
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "{}").unwrap();
+
+        assert_eq!(load(&path).unwrap(), Some(PersistedState::default()));
+    }
+
+    #[test]
+    fn test_corrupt_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "not json").unwrap();
+
+        assert_eq!(load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
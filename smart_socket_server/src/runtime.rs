@@ -0,0 +1,210 @@
+//! The config file and the settings that can be reloaded from it while the
+//! server runs.
+//!
+//! The file, named by `SMART_SOCKET_CONFIG`, holds `NAME=value` lines using the
+//! names of the environment variables; a value in the file wins over the
+//! environment. Blank lines and lines starting with `#` are ignored.
+//!
+//! A reload re-reads the file and applies only [`RELOADABLE`] settings: the ACL,
+//! the abuse limits, the overload limit and debug logging. Everything else,
+//! such as the listen address or the device identity, needs a restart.
+
+use crate::abuse::AbuseConfig;
+use crate::acl::{Acl, Policy};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Settings a reload applies; changes to any other setting are logged and ignored.
+pub const RELOADABLE: [&str; 9] = [
+    "SMART_SOCKET_ACL",
+    "SMART_SOCKET_ACL_DEFAULT",
+    "SMART_SOCKET_MAX_ERRORS",
+    "SMART_SOCKET_MAX_ERRORS_TOTAL",
+    "SMART_SOCKET_BAN_STRIKES",
+    "SMART_SOCKET_BAN_WINDOW_SECS",
+    "SMART_SOCKET_BAN_SECS",
+    "SMART_SOCKET_OVERLOAD_LIMIT",
+    "SMART_SOCKET_DEBUG",
+];
+
+/// Setting values from the config file, falling back to the environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    file: BTreeMap<String, String>,
+}
+
+impl Settings {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut file = BTreeMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, value)) if !name.trim().is_empty() => {
+                    file.insert(name.trim().to_string(), value.trim().to_string());
+                }
+                _ => {
+                    return Err(format!(
+                        "line {}: expected NAME=value, got '{}'",
+                        index + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(Self { file })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The value from the file, or else from the environment.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.file
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    /// Names whose value in the file differs between `self` and `other`,
+    /// including ones added or removed.
+    pub fn changed(&self, other: &Settings) -> Vec<String> {
+        let names: BTreeSet<&String> = self.file.keys().chain(other.file.keys()).collect();
+        names
+            .into_iter()
+            .filter(|name| self.file.get(*name) != other.file.get(*name))
+            .cloned()
+            .collect()
+    }
+}
+
+fn parse<T: std::str::FromStr>(settings: &Settings, name: &str) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    settings
+        .get(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| format!("Invalid {} '{}': {}", name, value, e))
+        })
+        .transpose()
+}
+
+/// The settings handlers consult on every command, swapped as a whole on reload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub acl: Acl,
+    pub abuse: AbuseConfig,
+    pub overload_limit: Option<u32>,
+    /// Also log clean client disconnects.
+    pub debug: bool,
+}
+
+impl RuntimeConfig {
+    /// Reads the [`RELOADABLE`] settings; unset ones keep their defaults.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let default = parse::<Policy>(settings, "SMART_SOCKET_ACL_DEFAULT")?.unwrap_or_default();
+        let acl = Acl::parse(
+            &settings.get("SMART_SOCKET_ACL").unwrap_or_default(),
+            default,
+        )?;
+        let mut abuse = AbuseConfig::default();
+        if let Some(max_errors) = parse(settings, "SMART_SOCKET_MAX_ERRORS")? {
+            abuse.max_errors = max_errors;
+        }
+        if let Some(max_errors) = parse(settings, "SMART_SOCKET_MAX_ERRORS_TOTAL")? {
+            abuse.max_errors_total = max_errors;
+        }
+        if let Some(strikes) = parse(settings, "SMART_SOCKET_BAN_STRIKES")? {
+            abuse.strikes = strikes;
+        }
+        if let Some(secs) = parse(settings, "SMART_SOCKET_BAN_WINDOW_SECS")? {
+            abuse.window = Duration::from_secs(secs);
+        }
+        if let Some(secs) = parse(settings, "SMART_SOCKET_BAN_SECS")? {
+            abuse.ban = Duration::from_secs(secs);
+        }
+        let overload_limit = parse(settings, "SMART_SOCKET_OVERLOAD_LIMIT")?;
+        if overload_limit == Some(0) {
+            return Err("SMART_SOCKET_OVERLOAD_LIMIT must be greater than zero".to_string());
+        }
+        Ok(Self {
+            acl,
+            abuse,
+            overload_limit,
+            debug: settings.get("SMART_SOCKET_DEBUG").is_some(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let settings = Settings::parse(
+            "# socket\n\nSMART_SOCKET_ACL = 127.0.0.1=STATUS\nSMART_SOCKET_BAN_SECS=60\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings.get("SMART_SOCKET_ACL").as_deref(),
+            Some("127.0.0.1=STATUS")
+        );
+        assert_eq!(
+            Settings::parse("SMART_SOCKET_ACL\n"),
+            Err("line 1: expected NAME=value, got 'SMART_SOCKET_ACL'".to_string())
+        );
+        assert!(Settings::parse("=5").is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_from_settings() {
+        let settings = Settings::parse(
+            "SMART_SOCKET_ACL=127.0.0.1=STATUS\nSMART_SOCKET_ACL_DEFAULT=deny\n\
+             SMART_SOCKET_MAX_ERRORS=2\nSMART_SOCKET_MAX_ERRORS_TOTAL=7\n\
+             SMART_SOCKET_OVERLOAD_LIMIT=1500\nSMART_SOCKET_DEBUG=1",
+        )
+        .unwrap();
+        let runtime = RuntimeConfig::from_settings(&settings).unwrap();
+        assert_eq!(runtime.acl.rules.len(), 1);
+        assert_eq!(runtime.acl.default, Policy::Deny);
+        assert_eq!(runtime.abuse.max_errors, 2);
+        assert_eq!(runtime.abuse.max_errors_total, 7);
+        assert_eq!(runtime.abuse.ban, AbuseConfig::default().ban);
+        assert_eq!(runtime.overload_limit, Some(1500));
+        assert!(runtime.debug);
+
+        let invalid = Settings::parse("SMART_SOCKET_MAX_ERRORS=many").unwrap();
+        assert!(RuntimeConfig::from_settings(&invalid)
+            .unwrap_err()
+            .starts_with("Invalid SMART_SOCKET_MAX_ERRORS 'many'"));
+        let zero = Settings::parse("SMART_SOCKET_OVERLOAD_LIMIT=0").unwrap();
+        assert!(RuntimeConfig::from_settings(&zero).is_err());
+    }
+
+    #[test]
+    fn test_changed_settings() {
+        let old =
+            Settings::parse("SMART_SOCKET_ADDRESS=127.0.0.1:8080\nSMART_SOCKET_ACL=").unwrap();
+        let new =
+            Settings::parse("SMART_SOCKET_ADDRESS=0.0.0.0:8080\nSMART_SOCKET_BAN_SECS=5").unwrap();
+        assert_eq!(
+            old.changed(&new),
+            [
+                "SMART_SOCKET_ACL",
+                "SMART_SOCKET_ADDRESS",
+                "SMART_SOCKET_BAN_SECS"
+            ]
+        );
+        assert!(new.changed(&new).is_empty());
+    }
+}
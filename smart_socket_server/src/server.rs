@@ -0,0 +1,4343 @@
+//! The socket server itself: connection handling, the device it serves and the
+//! listeners, callable in-process by the binary, tests and load tests.
+
+use crate::abuse::{AbuseConfig, BanTable};
+use crate::acl::{Acl, Denial, Permissions};
+use crate::auth::constant_time_eq;
+use crate::beacon::{BeaconConfig, BeaconSender};
+use crate::child_lock::ChildLock;
+use crate::device::{Device, DeviceType, SwitchError};
+use crate::last_change::LastChange;
+use crate::messages::{self, Locale};
+use crate::net_opts::{self, KeepaliveConfig};
+use crate::overload::Breaker;
+use crate::owner::{DeviceError, DeviceOwner, DEFAULT_DEVICE_TIMEOUT};
+use crate::peer::ClientLabel;
+use crate::persistence::{self, PersistedState};
+use crate::runtime::{RuntimeConfig, Settings, RELOADABLE};
+use crate::simulation::{SimulatedSocket, SimulationConfig};
+use crate::startup::{self, DeviceInit, DeviceInitError};
+use crate::usage::Usage;
+use crate::watchdog::{InFlight, StallDetector, WatchdogAction, WatchdogConfig};
+use smart_home::devices::socket::Socket;
+use smart_socket_protocol::beacon::Beacon;
+use smart_socket_protocol::binfmt::{self, WireFormat};
+use smart_socket_protocol::cipher::{CipherStream, Psk};
+use smart_socket_protocol::clock::{epoch_secs, Clock, SystemClock};
+use smart_socket_protocol::journal::{self, Journal, JournalConfig, JournalEntry};
+use smart_socket_protocol::logging::Logger;
+use smart_socket_protocol::shutdown::Shutdown;
+use smart_socket_protocol::{
+    read_message_limited, validate_device_name, write_response_chunked, Address, Command,
+    ConfigError, DeviceInfo, ErrorCode, IntoAddress, ProtocolError, Response, ServerStats,
+    COMMAND_PADDING, MAX_FRAME_LEN, PROTOCOL_VERSION,
+};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Set by the build script when the server is built from a git checkout.
+const GIT_HASH: Option<&str> = option_env!("SMART_SOCKET_GIT_HASH");
+
+/// Disabled by [`set_logging`]; tools embedding the server may not want its chatter.
+static LOGGER: Logger = Logger::new();
+
+/// Turns the server's log output on or off for the whole process.
+pub fn set_logging(enabled: bool) {
+    LOGGER.set_enabled(enabled);
+}
+
+pub fn log(message: &str) {
+    #[cfg(test)]
+    tests::capture_log(message);
+    LOGGER.log(message);
+}
+
+/// `frame` as the log shows it. The token of an `AUTH` frame, well-formed or
+/// not, is masked the way [`journal::command_label`] masks it.
+fn logged_frame(frame: &str) -> &str {
+    let keyword = frame.trim_matches(COMMAND_PADDING).split(':').next();
+    if keyword.is_some_and(|keyword| keyword.eq_ignore_ascii_case("AUTH")) {
+        "AUTH:***"
+    } else {
+        frame
+    }
+}
+
+/// Runs an authorized command from `client`. Reads are served from the status
+/// cache; only commands that change the device are sent to the device thread.
+fn execute(command: Command, context: &ConnectionContext, client: ClientLabel) -> Response {
+    // Refused before the device thread is involved, so nothing can slip through.
+    if context.read_only && command.changes_device() {
+        log(&format!(
+            "Refused {} from {}: the socket is read-only",
+            command.kind(),
+            client
+        ));
+        return context.error(
+            ErrorCode::ReadOnly,
+            "error.read_only",
+            &[("command", &command.kind())],
+        );
+    }
+    if context.simulated {
+        let intercept = command.clone();
+        match context.with_device(move |device, _| device.intercept(&intercept)) {
+            Ok(Some(response)) => return response,
+            Ok(None) => {}
+            Err(e) => return device_error(e, context.locale),
+        }
+    }
+    match command {
+        Command::GetStatus => {
+            let snapshot = match context.cached_status() {
+                Ok(snapshot) => snapshot,
+                Err(e) => return device_error(e, context.locale),
+            };
+            let status = Response::Status {
+                is_on: snapshot.is_on,
+                power: snapshot.power,
+                tripped: snapshot.tripped,
+                locked: context.is_locked(),
+            };
+            log(&format!("Status requested: {:?}", status));
+            status
+        }
+        Command::GetInfo => {
+            let info = context.device_info();
+            log(&format!("Info requested: {:?}", info));
+            Response::Info(info)
+        }
+        Command::GetStats => {
+            let usage = lock_or_recover(&context.usage, "usage");
+            Response::Stats(ServerStats {
+                switches: usage.switch_count(),
+                on_secs: usage.total_on(context.clock.now()).as_secs(),
+                ..context.stats.snapshot(context.started_at)
+            })
+        }
+        Command::Ping => Response::Pong,
+        Command::Reload => match context.reload() {
+            Ok(()) => context.ok("ok.reloaded", &[]),
+            Err(e) => {
+                log(&format!(
+                    "Reload failed, keeping the running settings: {}",
+                    e
+                ));
+                Response::error(ErrorCode::Internal, &e)
+            }
+        },
+        command => context
+            .with_device(move |device, context| execute_on_device(command, device, context, client))
+            .unwrap_or_else(|e| device_error(e, context.locale)),
+    }
+}
+
+/// The answer to a command the device thread did not carry out.
+fn device_error(e: DeviceError, locale: Locale) -> Response {
+    let message = match e {
+        DeviceError::Timeout(timeout) => {
+            log(&format!("Device unavailable: {}", e));
+            let timeout = format!("{:?}", timeout);
+            messages::format("error.device_timeout", locale, &[("timeout", &timeout)])
+        }
+        DeviceError::Stopped => {
+            log(&format!("Device unavailable: {}", e));
+            messages::get("error.shutting_down", locale).to_string()
+        }
+        DeviceError::Panicked => {
+            log("Device code panicked while handling a command");
+            messages::get("error.internal", locale).to_string()
+        }
+    };
+    Response::error(ErrorCode::Internal, &message)
+}
+
+/// Applies a state-changing command on the device thread and records it as the
+/// last change if it succeeded and the device is no longer as it was.
+fn execute_on_device(
+    command: Command,
+    smart_socket: &mut dyn Device,
+    context: &ConnectionContext,
+    client: ClientLabel,
+) -> Response {
+    let before = context.observable_state(smart_socket);
+    let response = apply_on_device(command.clone(), smart_socket, context);
+    let changed = context.observable_state(smart_socket) != before;
+    let failed = matches!(response, Response::Error(_));
+    if changed && !failed {
+        *lock_or_recover(&context.last_change, "last change") = Some(LastChange {
+            at: context.clock.system_time(),
+            peer: client.peer.to_string(),
+            command,
+        });
+    }
+    // A refused command leaves nothing to save, unless it tripped the breaker.
+    if changed || !failed {
+        context.persist(smart_socket);
+    }
+    response
+}
+
+/// Carries out a state-changing command. The status cache is updated before the
+/// next job runs, so the client's next STATUS sees the change.
+fn apply_on_device(
+    command: Command,
+    smart_socket: &mut dyn Device,
+    context: &ConnectionContext,
+) -> Response {
+    if matches!(
+        command,
+        Command::TurnOn | Command::TurnOff | Command::Pulse(_)
+    ) {
+        if let Some(refusal) = context.refuse_if_locked() {
+            return refusal;
+        }
+    }
+    match command {
+        Command::TurnOn => {
+            if let Some(refusal) = context.refuse_if_tripped() {
+                return refusal;
+            }
+            if smart_socket.is_on() {
+                return context.ok("ok.already_on", &[]);
+            }
+            if let Err(e) = smart_socket.turn_on() {
+                return context.switch_failed(&e);
+            }
+            log("Socket turned ON");
+            let tripped = context.enforce_limit(smart_socket);
+            context.state_changed(smart_socket);
+            tripped.unwrap_or_else(|| context.ok("ok.turned_on", &[]))
+        }
+        Command::TurnOff => {
+            if context.pulse.cancel() {
+                log("Pending pulse cancelled");
+            }
+            smart_socket.turn_off();
+            log("Socket turned OFF");
+            context.state_changed(smart_socket);
+            context.ok("ok.turned_off", &[])
+        }
+        Command::SetName(name) => match validate_device_name(&name) {
+            Ok(()) => {
+                log(&format!("Socket renamed to {:?}", name));
+                *lock_or_recover(&context.device_name, "device name") = name;
+                context.state_changed(smart_socket);
+                context.ok("ok.renamed", &[])
+            }
+            Err(e) => Response::error(ErrorCode::InvalidArgument, &e),
+        },
+        Command::SetLevel(level) => match smart_socket.as_dimmable() {
+            Some(dimmer) => {
+                dimmer.set_level(level);
+                log(&format!("Level set to {}%", level));
+                let tripped = context.enforce_limit(smart_socket);
+                context.state_changed(smart_socket);
+                tripped.unwrap_or(Response::Level(level))
+            }
+            None => context.error(ErrorCode::Unsupported, "error.no_levels", &[]),
+        },
+        Command::Pulse(millis) => {
+            if let Some(refusal) = context.refuse_if_tripped() {
+                return refusal;
+            }
+            if let Err(e) = smart_socket.turn_on() {
+                return context.switch_failed(&e);
+            }
+            log(&format!("Socket turned ON for a {}ms pulse", millis));
+            let tripped = context.enforce_limit(smart_socket);
+            context.state_changed(smart_socket);
+            if let Some(tripped) = tripped {
+                return tripped;
+            }
+            context
+                .pulse
+                .schedule(Duration::from_millis(millis), context);
+            context.ok("ok.pulse_started", &[])
+        }
+        Command::ResetTrip => {
+            let cleared = lock_or_recover(&context.breaker, "overload breaker").reset();
+            context.state_changed(smart_socket);
+            match cleared {
+                Some(event) => {
+                    log(&format!("Overload trip at {}W reset", event.power));
+                    context.ok("ok.trip_reset", &[])
+                }
+                None => context.ok("ok.not_tripped", &[]),
+            }
+        }
+        Command::Lock(minutes) => {
+            let duration = minutes.map(|minutes| Duration::from_secs(u64::from(minutes) * 60));
+            lock_or_recover(&context.child_lock, "child lock")
+                .lock(duration, context.clock.system_time());
+            match minutes {
+                Some(minutes) => {
+                    log(&format!("Child lock set for {} min", minutes));
+                    context.ok("ok.locked_for", &[("minutes", &minutes)])
+                }
+                None => {
+                    log("Child lock set");
+                    context.ok("ok.locked", &[])
+                }
+            }
+        }
+        Command::Unlock => {
+            if lock_or_recover(&context.child_lock, "child lock")
+                .unlock(context.clock.system_time())
+            {
+                log("Child lock cleared");
+                context.ok("ok.unlocked", &[])
+            } else {
+                context.ok("ok.not_locked", &[])
+            }
+        }
+        Command::Auth(_) => unreachable!("AUTH is handled by the connection loop"),
+        other => context.error(
+            ErrorCode::Unsupported,
+            "error.unsupported",
+            &[("command", &other)],
+        ),
+    }
+}
+
+/// Runs device code, turning a panic into an error response so that neither the
+/// connection nor the handler thread is lost.
+fn run_guarded(locale: Locale, f: impl FnOnce() -> Response) -> Response {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        log("Device code panicked while handling a command");
+        Response::error(ErrorCode::Internal, messages::get("error.internal", locale))
+    })
+}
+
+/// Locks `mutex`, recovering it if a panicking thread poisoned it. The state may
+/// be half-updated, so a warning is logged and the poison flag cleared to warn once.
+fn lock_or_recover<'a, T: ?Sized>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        log(&format!(
+            "Warning: {} lock was poisoned by a panicked thread; state may be inconsistent",
+            name
+        ));
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Why [`handle_client`] stopped serving a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Disconnect {
+    /// The peer went away or the connection failed.
+    Ended,
+    /// The peer sent [`AbuseConfig::max_errors`] malformed commands in a row,
+    /// or [`AbuseConfig::max_errors_total`] in all.
+    TooManyErrors,
+}
+
+/// Serves framed commands from `stream` until the peer disconnects.
+///
+/// Only `Read + Write` is required so the same loop serves plain TCP, TLS and
+/// in-memory test streams; `client` labels the connection in logs. The ACL and
+/// the malformed-command limit are looked up for every command, so a reload
+/// applies to open connections too.
+fn handle_client<S: Read + Write>(
+    mut stream: S,
+    client: ClientLabel,
+    context: &ConnectionContext,
+) -> Result<Disconnect, ProtocolError> {
+    let auth_token = &context.auth_token;
+    log(&format!("New client connected: {}", client));
+    let mut authenticated = auth_token.is_none();
+    let mut malformed = 0;
+    let mut malformed_total = 0;
+    let peer_ip = client.peer.ip();
+    let mut format = WireFormat::Text;
+    // Binary responses carry the server time once the client asked for it.
+    let mut timed = false;
+    let mut frames = 0u64;
+
+    // Peers that may not ask for INFO do not get it unasked either.
+    if context.banner && context.permit(peer_ip, &Command::GetInfo).is_ok() {
+        let banner = Response::Info(context.device_info());
+        if let Err(e) = write_response_chunked(&mut stream, &banner, MAX_FRAME_LEN) {
+            log(&format!("Failed to send banner to {}: {}", client, e));
+            return Ok(Disconnect::Ended);
+        }
+    }
+
+    loop {
+        // Commands are short; a longer frame is garbage, not a reason to allocate.
+        let command_str = match read_message_limited(&mut stream, MAX_FRAME_LEN) {
+            Ok(command_str) => command_str,
+            Err(ProtocolError::ConnectionClosed) => {
+                context.debug(&format!("Client {} disconnected", client));
+                break;
+            }
+            Err(ProtocolError::PeerUnreachable(_)) => {
+                log(&format!(
+                    "Dropping client {}: peer unreachable (keepalive)",
+                    client
+                ));
+                break;
+            }
+            Err(e) => {
+                log(&format!("Dropping client {}: {}", client, e));
+                break;
+            }
+        };
+        let received = Instant::now();
+        log(&format!(
+            "Received command from {}: {}",
+            client,
+            logged_frame(&command_str)
+        ));
+        frames += 1;
+        if frames == 1 && command_str == binfmt::NEGOTIATE {
+            format = WireFormat::Binary;
+            // Confirmed in text, which is what an older server's rejection is in too.
+            let confirmed = Response::ok("Binary responses");
+            if let Err(e) = write_response_chunked(&mut stream, &confirmed, MAX_FRAME_LEN) {
+                log(&format!("Failed to send response to {}: {}", client, e));
+                break;
+            }
+            continue;
+        }
+        if frames == 2 && format == WireFormat::Binary && command_str == binfmt::NEGOTIATE_TIMED {
+            timed = true;
+            if let Err(e) = binfmt::write_response(&mut stream, &Response::ok("Server time")) {
+                log(&format!("Failed to send response to {}: {}", client, e));
+                break;
+            }
+            continue;
+        }
+        context.stats.commands.fetch_add(1, Ordering::Relaxed);
+
+        let parsed = Command::from_str(&command_str);
+        malformed = if parsed.is_ok() { 0 } else { malformed + 1 };
+        malformed_total += u32::from(parsed.is_err());
+        let abuse = context.abuse.config();
+        let too_many = (abuse.max_errors > 0 && malformed >= abuse.max_errors)
+            || (abuse.max_errors_total > 0 && malformed_total >= abuse.max_errors_total);
+        let journal_label = parsed.as_ref().ok().map(journal::command_label);
+        let denial = parsed
+            .as_ref()
+            .ok()
+            .and_then(|command| context.permit(peer_ip, command).err());
+
+        let response = match parsed {
+            Ok(_) if denial.is_some() => {
+                let denial = denial.expect("checked by the guard");
+                log(&format!("Denied command from {}: {}", client, denial));
+                context.denied(&denial)
+            }
+            Ok(Command::Auth(token)) => match auth_token {
+                None => context.ok("ok.auth_not_required", &[]),
+                Some(expected) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+                    authenticated = true;
+                    log(&format!("Client {} authenticated", client));
+                    context.ok("ok.authenticated", &[])
+                }
+                // A failed attempt drops an earlier success, so a connection
+                // cannot keep its access while probing for other tokens.
+                Some(_) => {
+                    authenticated = false;
+                    log(&format!("Client {} failed authentication", client));
+                    context.error(ErrorCode::Unauthorized, "error.invalid_token", &[])
+                }
+            },
+            Ok(command) if command.is_state_changing() && !authenticated => {
+                log(&format!(
+                    "Rejected unauthenticated command from {}: {}",
+                    client, command
+                ));
+                context.error(ErrorCode::Unauthorized, "error.auth_required", &[])
+            }
+            // The device thread only computes the response: it is an owned value by the
+            // time it is written, so a slow client never holds up other connections.
+            Ok(command) => {
+                let _in_flight =
+                    context
+                        .in_flight
+                        .begin(client.id, command.kind(), context.clock.now());
+                run_guarded(context.locale, || execute(command, context, client))
+            }
+            Err(_) if too_many => {
+                log(&format!(
+                    "Closing connection from {}: {} malformed commands in a row, {} in all",
+                    client, malformed, malformed_total
+                ));
+                context.error(ErrorCode::TooManyErrors, "error.too_many_errors", &[])
+            }
+            Err(e) => {
+                // The error quotes the frame, token and all.
+                match logged_frame(&command_str) {
+                    shown if shown != command_str => {
+                        log(&format!("Error processing command: malformed {}", shown))
+                    }
+                    _ => log(&format!("Error processing command: {}", e)),
+                }
+                Response::Error(messages::protocol_error(&e, context.locale))
+            }
+        };
+
+        if let Response::Error(error) = &response {
+            context.stats.errors.fetch_add(1, Ordering::Relaxed);
+            *lock_or_recover(&context.last_error, "last error") = Some(error.clone());
+        }
+        if let Some(command) = journal_label {
+            context.journal_record(&client.peer.to_string(), command, &response);
+        }
+
+        let written = match format {
+            WireFormat::Text => write_response_chunked(&mut stream, &response, MAX_FRAME_LEN),
+            WireFormat::Binary if timed => {
+                binfmt::write_response_timed(&mut stream, &response, received.elapsed())
+            }
+            WireFormat::Binary => binfmt::write_response(&mut stream, &response),
+        };
+        if too_many {
+            return Ok(Disconnect::TooManyErrors);
+        }
+        if let Err(e) = written {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) {
+                log(&format!(
+                    "Dropping slow client {}: write timed out, lost response {}",
+                    client, response
+                ));
+            } else {
+                log(&format!("Failed to send response to {}: {}", client, e));
+            }
+            break;
+        }
+    }
+
+    Ok(Disconnect::Ended)
+}
+
+/// Server-wide counters reported by `STATS`.
+#[derive(Default)]
+struct StatsCounters {
+    connections: AtomicU64,
+    active: AtomicU64,
+    commands: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl StatsCounters {
+    /// Counts a new connection and returns its id, starting at 1.
+    fn connection_opened(&self) -> u64 {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn connection_closed(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, started_at: Instant) -> ServerStats {
+        ServerStats {
+            uptime: started_at.elapsed().as_secs(),
+            connections: self.connections.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            commands: self.commands.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+/// What STATUS and INFO report about the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StatusSnapshot {
+    is_on: bool,
+    power: u32,
+    name: String,
+    tripped: bool,
+}
+
+struct CachedStatus {
+    snapshot: StatusSnapshot,
+    updated_at: Instant,
+}
+
+/// Read cache in front of the device, so polling clients share a read lock
+/// instead of queueing on the device thread.
+///
+/// Every write happens on the device thread, so a refresh can never overwrite
+/// a newer snapshot stored by a state-changing command.
+struct StatusCache {
+    ttl: Duration,
+    state: RwLock<Option<CachedStatus>>,
+}
+
+impl StatusCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: RwLock::new(None),
+        }
+    }
+
+    /// The cached snapshot, if it is younger than the TTL at `now`.
+    fn fresh(&self, now: Instant) -> Option<StatusSnapshot> {
+        let state = self.state.read().unwrap_or_else(PoisonError::into_inner);
+        state
+            .as_ref()
+            .filter(|cached| now.saturating_duration_since(cached.updated_at) < self.ttl)
+            .map(|cached| cached.snapshot.clone())
+    }
+
+    /// Stores `snapshot`, taken at `now`; the caller must run on the device thread.
+    fn store(&self, snapshot: StatusSnapshot, now: Instant) {
+        *self.state.write().unwrap_or_else(PoisonError::into_inner) = Some(CachedStatus {
+            snapshot,
+            updated_at: now,
+        });
+    }
+}
+
+#[derive(Default)]
+struct PulseState {
+    /// When the socket is due to be turned off again.
+    deadline: Option<Instant>,
+    shutting_down: bool,
+}
+
+/// Turns the socket off when a PULSE ends, from one timer thread per server
+/// that is started by the first pulse.
+///
+/// A pulse that arrives while another is running extends it: the socket goes
+/// off at the later of the two deadlines, once. The deadline is only changed
+/// on the device thread, so the timer cannot turn off a pulse that was
+/// extended or cancelled while it was waking up.
+///
+/// The time comes from the context's clock; the timer thread still sleeps in
+/// real time, so a test that moves a mock clock ends the pulse with
+/// [`end_due_pulse`].
+#[derive(Default)]
+struct PulseTimer {
+    state: Mutex<PulseState>,
+    wakeup: Condvar,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PulseTimer {
+    fn lock(&self) -> MutexGuard<'_, PulseState> {
+        lock_or_recover(&self.state, "pulse timer")
+    }
+
+    /// Arranges for the socket to be turned off `duration` from now, or later if
+    /// a running pulse already ends later. The caller must run on the device thread.
+    fn schedule(&self, duration: Duration, context: &ConnectionContext) {
+        let deadline = context.clock.now() + duration;
+        {
+            let mut state = self.lock();
+            if state.shutting_down {
+                return;
+            }
+            state.deadline = state.deadline.max(Some(deadline));
+        }
+        self.wakeup.notify_all();
+        lock_or_recover(&self.thread, "pulse timer thread").get_or_insert_with(|| {
+            let context = context.clone();
+            thread::spawn(move || run_pulse_timer(&context))
+        });
+    }
+
+    /// Drops the pending turn-off, if any; the caller must run on the device thread.
+    fn cancel(&self) -> bool {
+        self.lock().deadline.take().is_some()
+    }
+
+    /// Ends a running pulse right away and stops the timer thread.
+    fn shutdown(&self) {
+        self.lock().shutting_down = true;
+        self.wakeup.notify_all();
+        if let Some(handle) = lock_or_recover(&self.thread, "pulse timer thread").take() {
+            handle
+                .join()
+                .unwrap_or_else(|e| log(&format!("Pulse timer join error: {:?}", e)));
+        }
+    }
+}
+
+fn run_pulse_timer(context: &ConnectionContext) {
+    let timer = &context.pulse;
+    let mut state = timer.lock();
+    loop {
+        match state.deadline {
+            Some(deadline) if !state.shutting_down && deadline > context.clock.now() => {
+                let timeout = deadline - context.clock.now();
+                state = timer
+                    .wakeup
+                    .wait_timeout(state, timeout)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+            Some(_) => {
+                drop(state);
+                let ended = end_due_pulse(context);
+                state = timer.lock();
+                if let Err(e) = ended {
+                    // Retrying at once would spin; the device cannot be switched anyway.
+                    log(&format!("Failed to end pulse: {}", e));
+                    state.deadline = None;
+                }
+            }
+            None if state.shutting_down => break,
+            None => {
+                state = timer
+                    .wakeup
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+    }
+}
+
+/// Turns the socket off on the device thread, as command handlers do, if the
+/// pulse is still due: it may have been extended or cancelled in the meantime.
+fn end_due_pulse(context: &ConnectionContext) -> Result<(), DeviceError> {
+    context.with_device(|smart_socket, context| {
+        let mut state = context.pulse.lock();
+        let due = state.shutting_down
+            || state
+                .deadline
+                .is_some_and(|deadline| deadline <= context.clock.now());
+        if due && state.deadline.take().is_some() {
+            drop(state);
+            smart_socket.turn_off();
+            log("Pulse finished, socket turned OFF");
+            context.state_changed(smart_socket);
+            context.persist(smart_socket);
+        }
+    })
+}
+
+/// Builds the configured device as it is before any state is restored.
+type DeviceFactory = Arc<dyn Fn() -> Result<Box<dyn Device>, BoxError> + Send + Sync>;
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The factory for the device `config` describes.
+fn device_factory(config: &ServerConfig) -> DeviceFactory {
+    let simulation = config.simulation.clone();
+    let device_type = config.device_type;
+    let init = config.device_init.clone();
+    let name = config.socket_name.clone();
+    let (power, locale) = (config.socket_power, config.locale);
+    Arc::new(move || match &simulation {
+        Some(simulation) => Ok(Box::new(
+            SimulatedSocket::from_config(power, simulation)?.locale(locale),
+        )),
+        None => Ok(device_type.build(startup::build_device(
+            &init,
+            &name,
+            power,
+            Socket::new,
+            thread::sleep,
+        )?)),
+    })
+}
+
+/// Per-connection settings shared by all handler threads.
+#[derive(Clone)]
+pub struct ConnectionContext {
+    device: Arc<DeviceOwner>,
+    /// Builds a replacement when the watchdog gives up on a wedged device thread.
+    device_factory: DeviceFactory,
+    /// How long a connection waits for the device thread to answer a command.
+    device_timeout: Duration,
+    /// Kept beside the device because `Socket` has no setter for its name.
+    device_name: Arc<Mutex<String>>,
+    rated_power: u32,
+    started_at: Instant,
+    auth_token: Option<String>,
+    state_file: Option<PathBuf>,
+    /// Clients that stop reading are dropped once a response write blocks this long.
+    write_timeout: Duration,
+    stats: Arc<StatsCounters>,
+    status_cache: Arc<StatusCache>,
+    pulse: Arc<PulseTimer>,
+    /// What the child lock, breaker, usage counters, bans, pulses and status
+    /// cache read the time from.
+    clock: Arc<dyn Clock + Send + Sync>,
+    journal: Option<Arc<Mutex<Journal>>>,
+    /// Filled in by [`run_server`] once the listeners are bound; reported in INFO.
+    local_addrs: Arc<OnceLock<Vec<SocketAddr>>>,
+    location: Option<String>,
+    /// The settings a reload replaces. The ACL and debug flag are read from here;
+    /// the abuse and overload limits are also handed to `abuse` and `breaker`.
+    runtime: Arc<RwLock<RuntimeConfig>>,
+    /// Re-read by [`ConnectionContext::reload`].
+    config_file: Option<PathBuf>,
+    /// The config file as last applied, to tell which settings a reload changes.
+    settings: Arc<Mutex<Settings>>,
+    /// Send an `INFO` frame to every client as soon as it connects.
+    banner: bool,
+    /// Whether the device supports `LEVEL`, reported among the INFO capabilities.
+    dimmable: bool,
+    /// Refuse every command that would change the device; see [`ServerConfig::read_only`].
+    read_only: bool,
+    abuse: Arc<BanTable>,
+    /// Overload trip state; only changed on the device thread.
+    breaker: Arc<Mutex<Breaker>>,
+    /// Switch count and on-time; only changed on the device thread.
+    usage: Arc<Mutex<Usage>>,
+    /// Reported in INFO; only changed on the device thread.
+    last_change: Arc<Mutex<Option<LastChange>>>,
+    /// Refuses switching while set; only changed on the device thread.
+    child_lock: Arc<Mutex<ChildLock>>,
+    /// Every command is offered to [`Device::intercept`] first, on the device
+    /// thread, even reads that the cache would otherwise answer.
+    simulated: bool,
+    /// Commands being executed, named by the watchdog when the device thread wedges.
+    in_flight: Arc<InFlight>,
+    watchdog: Option<WatchdogConfig>,
+    keepalive: Option<KeepaliveConfig>,
+    beacon: Option<BeaconConfig>,
+    /// Language of the messages in `OK` and `ERROR` responses.
+    locale: Locale,
+    /// The last `ERROR` response sent to any client, reported in beacons.
+    last_error: Arc<Mutex<Option<String>>>,
+    /// Frames are sealed with this key when set.
+    psk: Option<Psk>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl ConnectionContext {
+    /// Builds the device described by `config`, restoring its saved state, and
+    /// opens the journal and TLS configuration.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let device_factory = device_factory(config);
+        let mut smart_socket = device_factory().map_err(|e| e as Box<dyn std::error::Error>)?;
+        let restored = match &config.state_file {
+            Some(path) => restore_state(smart_socket.as_mut(), path),
+            None => None,
+        };
+        let restored = restored.unwrap_or_default();
+        let clock: Arc<dyn Clock + Send + Sync> = Arc::new(SystemClock);
+        let mut usage = Usage::new(
+            restored.switch_count,
+            Duration::from_millis(restored.total_on_ms),
+            smart_socket.is_on(),
+            clock.now(),
+        );
+        let dimmable = smart_socket.level().is_some();
+        let mut breaker = Breaker::new(config.overload_limit);
+        if let Some(event) = breaker.check(smart_socket.as_mut(), clock.system_time()) {
+            log(&format!(
+                "Restored state draws {}W, over the {}W limit; socket tripped OFF",
+                event.power,
+                config.overload_limit.unwrap_or_default()
+            ));
+            usage.observe(smart_socket.is_on(), clock.now());
+        }
+        Ok(Self {
+            device: Arc::new(DeviceOwner::spawn(smart_socket)),
+            device_factory,
+            device_timeout: config.device_timeout,
+            device_name: Arc::new(Mutex::new(
+                restored.name.unwrap_or_else(|| config.socket_name.clone()),
+            )),
+            rated_power: config.socket_power,
+            started_at: Instant::now(),
+            auth_token: config.auth_token.clone(),
+            state_file: config.state_file.clone(),
+            write_timeout: config.write_timeout,
+            stats: Arc::default(),
+            status_cache: Arc::new(StatusCache::new(config.cache_ttl)),
+            pulse: Arc::default(),
+            clock: clock.clone(),
+            journal: match &config.journal {
+                Some(journal) => Some(Arc::new(Mutex::new(Journal::open(journal.clone())?))),
+                None => None,
+            },
+            local_addrs: Arc::default(),
+            location: config.location.clone(),
+            runtime: Arc::new(RwLock::new(RuntimeConfig {
+                acl: config.acl.clone(),
+                abuse: config.abuse.clone(),
+                overload_limit: config.overload_limit,
+                debug: config.debug,
+            })),
+            config_file: config.config_file.clone(),
+            settings: Arc::new(Mutex::new(match &config.config_file {
+                Some(path) => Settings::load(path)?,
+                None => Settings::default(),
+            })),
+            banner: config.banner,
+            dimmable,
+            read_only: config.read_only,
+            abuse: Arc::new(BanTable::new(config.abuse.clone())),
+            breaker: Arc::new(Mutex::new(breaker)),
+            usage: Arc::new(Mutex::new(usage)),
+            last_change: Arc::new(Mutex::new(
+                restored
+                    .last_change
+                    .as_ref()
+                    .and_then(LastChange::from_saved),
+            )),
+            child_lock: Arc::new(Mutex::new(ChildLock::from_saved(
+                restored.lock,
+                clock.system_time(),
+            ))),
+            simulated: config.simulation.is_some(),
+            in_flight: Arc::default(),
+            watchdog: config.watchdog.clone(),
+            keepalive: config.keepalive.clone(),
+            beacon: config.beacon.clone(),
+            locale: config.locale,
+            last_error: Arc::default(),
+            psk: config.psk.clone(),
+            #[cfg(feature = "tls")]
+            tls: match &config.tls {
+                Some(tls) => Some(smart_socket_protocol::tls::server_config(
+                    &tls.cert_path,
+                    &tls.key_path,
+                )?),
+                None => None,
+            },
+        })
+    }
+
+    fn runtime(&self) -> RwLockReadGuard<'_, RuntimeConfig> {
+        self.runtime.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Checks `command` against the ACL rule for `peer`; a peer whose address is
+    /// unknown gets the default policy.
+    fn permit(&self, peer: Option<IpAddr>, command: &Command) -> Result<(), Denial> {
+        let runtime = self.runtime();
+        let permissions = match peer {
+            Some(ip) => runtime.acl.permissions(ip),
+            None => Permissions::Default(runtime.acl.default),
+        };
+        permissions.check(command)
+    }
+
+    /// `OK` with the catalog message `key` in the configured locale.
+    fn ok(&self, key: &'static str, args: &[(&str, &dyn fmt::Display)]) -> Response {
+        Response::ok(&messages::format(key, self.locale, args))
+    }
+
+    /// `ERROR` with `code` and the catalog message `key` in the configured locale.
+    fn error(
+        &self,
+        code: ErrorCode,
+        key: &'static str,
+        args: &[(&str, &dyn fmt::Display)],
+    ) -> Response {
+        Response::error(code, &messages::format(key, self.locale, args))
+    }
+
+    /// The `E_FORBIDDEN` refusal for a command the ACL denied.
+    fn denied(&self, denial: &Denial) -> Response {
+        match &denial.range {
+            Some(range) => self.error(
+                ErrorCode::Forbidden,
+                "error.forbidden_from",
+                &[("command", &denial.command), ("range", range)],
+            ),
+            None => self.error(
+                ErrorCode::Forbidden,
+                "error.forbidden",
+                &[("command", &denial.command)],
+            ),
+        }
+    }
+
+    /// Verbose logging, enabled by `SMART_SOCKET_DEBUG`.
+    fn debug(&self, message: &str) {
+        if self.runtime().debug {
+            log(message);
+        }
+    }
+
+    /// Re-reads the config file and applies the [`RELOADABLE`] settings, which
+    /// open connections use from their next command. Changes to other settings
+    /// are logged and ignored. On error nothing changes.
+    pub fn reload(&self) -> Result<(), String> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or("No config file to reload; set SMART_SOCKET_CONFIG")?;
+        let settings = Settings::load(path)?;
+        let runtime = RuntimeConfig::from_settings(&settings)?;
+        let mut applied = lock_or_recover(&self.settings, "settings");
+        for name in applied.changed(&settings) {
+            if !RELOADABLE.contains(&name.as_str()) {
+                log(&format!(
+                    "{} changed in {}; restart the server to apply it",
+                    name,
+                    path.display()
+                ));
+            }
+        }
+        self.abuse.reconfigure(runtime.abuse.clone());
+        lock_or_recover(&self.breaker, "overload breaker").set_limit(runtime.overload_limit);
+        *self.runtime.write().unwrap_or_else(PoisonError::into_inner) = runtime;
+        *applied = settings;
+        log(&format!("Reloaded configuration from {}", path.display()));
+        Ok(())
+    }
+
+    /// Gives up on the device thread: a fresh device, restored from the state
+    /// file like at startup, takes over on a new thread. Usage and the status
+    /// cache then follow the new device, whatever the old one was left in.
+    fn rebuild_device(&self) -> Result<(), BoxError> {
+        let mut device = (self.device_factory)()?;
+        if let Some(path) = &self.state_file {
+            restore_state(device.as_mut(), path);
+        }
+        self.device.replace(device);
+        self.with_device(|device, context| context.state_changed(device))?;
+        Ok(())
+    }
+
+    /// Runs `f` on the device thread with this context, waiting up to the device timeout.
+    fn with_device<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Device, &ConnectionContext) -> T + Send + 'static,
+    ) -> Result<T, DeviceError> {
+        let context = self.clone();
+        self.device
+            .call(self.device_timeout, move |device| f(device, &context))
+    }
+
+    fn snapshot(&self, socket: &dyn Device) -> StatusSnapshot {
+        StatusSnapshot {
+            is_on: socket.is_on(),
+            power: socket.get_power(),
+            name: lock_or_recover(&self.device_name, "device name").clone(),
+            tripped: lock_or_recover(&self.breaker, "overload breaker").is_tripped(),
+        }
+    }
+
+    /// The `E_TRIPPED` refusal for commands that would switch a tripped socket on.
+    fn refuse_if_tripped(&self) -> Option<Response> {
+        let breaker = lock_or_recover(&self.breaker, "overload breaker");
+        let event = breaker.tripped()?;
+        Some(self.error(
+            ErrorCode::Tripped,
+            "error.tripped",
+            &[
+                ("power", &event.power),
+                ("limit", &breaker.limit().unwrap_or_default()),
+            ],
+        ))
+    }
+
+    fn is_locked(&self) -> bool {
+        lock_or_recover(&self.child_lock, "child lock").is_locked(self.clock.system_time())
+    }
+
+    /// The answer when the device itself did not switch on.
+    fn switch_failed(&self, e: &SwitchError) -> Response {
+        log(&format!("Device did not switch on: {}", e));
+        match e {
+            SwitchError::Overload { power, limit } => self.error(
+                ErrorCode::Overload,
+                "error.device_overload",
+                &[("power", power), ("limit", limit)],
+            ),
+            SwitchError::Locked => self.error(ErrorCode::Locked, "error.device_locked", &[]),
+            SwitchError::Fault(reason) => self.error(
+                ErrorCode::DeviceFault,
+                "error.device_fault",
+                &[("reason", reason)],
+            ),
+        }
+    }
+
+    /// The `E_LOCKED` refusal for commands that would switch a locked socket.
+    fn refuse_if_locked(&self) -> Option<Response> {
+        let mut lock = lock_or_recover(&self.child_lock, "child lock");
+        if !lock.is_locked(self.clock.system_time()) {
+            return None;
+        }
+        Some(match lock.until() {
+            Some(until) => {
+                let minutes = until
+                    .duration_since(self.clock.system_time())
+                    .unwrap_or_default()
+                    .as_secs()
+                    .div_ceil(60);
+                self.error(
+                    ErrorCode::Locked,
+                    "error.locked_for",
+                    &[("minutes", &minutes)],
+                )
+            }
+            None => self.error(ErrorCode::Locked, "error.locked", &[]),
+        })
+    }
+
+    /// Trips the socket if the change just applied pushed its draw over the limit.
+    /// The caller must run on the device thread.
+    fn enforce_limit(&self, socket: &mut dyn Device) -> Option<Response> {
+        // The relay closed before the breaker can open it again; that is a switch too.
+        lock_or_recover(&self.usage, "usage").observe(socket.is_on(), self.clock.now());
+        let mut breaker = lock_or_recover(&self.breaker, "overload breaker");
+        let event = breaker.check(socket, self.clock.system_time())?;
+        let args: [(&str, &dyn fmt::Display); 2] = [
+            ("power", &event.power),
+            ("limit", &breaker.limit().unwrap_or_default()),
+        ];
+        log(&format!(
+            "Overload: {}",
+            messages::format("error.overload", Locale::En, &args)
+        ));
+        Some(self.error(ErrorCode::Tripped, "error.overload", &args))
+    }
+
+    /// Serves from the cache, refreshing it from the device once it is older than the TTL.
+    fn cached_status(&self) -> Result<StatusSnapshot, DeviceError> {
+        if let Some(snapshot) = self.status_cache.fresh(self.clock.now()) {
+            return Ok(snapshot);
+        }
+        self.with_device(|smart_socket, context| {
+            let snapshot = context.snapshot(smart_socket);
+            context
+                .status_cache
+                .store(snapshot.clone(), context.clock.now());
+            snapshot
+        })
+    }
+
+    /// The beacon to send now; `None` if the device did not answer in time.
+    fn beacon(&self) -> Option<Beacon> {
+        let status = self.cached_status().ok()?;
+        Some(Beacon {
+            name: status.name,
+            is_on: status.is_on,
+            power: status.power,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            last_error: lock_or_recover(&self.last_error, "last error").clone(),
+        })
+    }
+
+    /// Records a change made on the device thread: counts a switch and refreshes
+    /// the cache. The caller persists it once the change is complete.
+    fn state_changed(&self, socket: &dyn Device) {
+        lock_or_recover(&self.usage, "usage").observe(socket.is_on(), self.clock.now());
+        self.status_cache
+            .store(self.snapshot(socket), self.clock.now());
+    }
+
+    /// Everything a client can see change: STATUS, the name and the level.
+    fn observable_state(&self, socket: &dyn Device) -> (StatusSnapshot, Option<u8>) {
+        (self.snapshot(socket), socket.level())
+    }
+
+    /// Saves the device state if persistence is enabled. Called on the device
+    /// thread so concurrent changes are written in the order they were applied.
+    fn persist(&self, socket: &dyn Device) {
+        if let Some(path) = &self.state_file {
+            let usage = lock_or_recover(&self.usage, "usage");
+            let state = PersistedState {
+                is_on: socket.is_on(),
+                name: Some(lock_or_recover(&self.device_name, "device name").clone()),
+                level: socket.level(),
+                switch_count: usage.switch_count(),
+                total_on_ms: usage.total_on(self.clock.now()).as_millis() as u64,
+                last_change: lock_or_recover(&self.last_change, "last change")
+                    .as_ref()
+                    .map(LastChange::to_saved),
+                lock: lock_or_recover(&self.child_lock, "child lock").to_saved(),
+            };
+            if let Err(e) = persistence::save(path, &state) {
+                log(&format!("Failed to save state to {:?}: {}", path, e));
+            }
+        }
+    }
+
+    /// Saves the on-time accumulated since the last change, at shutdown.
+    fn persist_usage(&self) {
+        let (switches, on_time) = {
+            let usage = lock_or_recover(&self.usage, "usage");
+            (usage.switch_count(), usage.total_on(self.clock.now()))
+        };
+        log(&format!(
+            "Lifetime usage: {} switches, {}s on",
+            switches,
+            on_time.as_secs()
+        ));
+        if let Err(e) = self.with_device(|smart_socket, context| context.persist(smart_socket)) {
+            log(&format!("Failed to save usage: {}", e));
+        }
+    }
+
+    fn journal_record(&self, peer: &str, command: String, response: &Response) {
+        if let Some(journal) = &self.journal {
+            let entry = JournalEntry {
+                timestamp: epoch_secs(self.clock.system_time()),
+                peer: peer.to_string(),
+                command,
+                response_kind: response.kind().to_string(),
+            };
+            if let Err(e) = lock_or_recover(journal, "journal").record(&entry) {
+                log(&format!("Failed to write journal entry: {}", e));
+            }
+        }
+    }
+
+    fn flush_journal(&self) {
+        if let Some(journal) = &self.journal {
+            if let Err(e) = lock_or_recover(journal, "journal").flush() {
+                log(&format!("Failed to flush journal: {}", e));
+            }
+        }
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        let last_change = lock_or_recover(&self.last_change, "last change").clone();
+        DeviceInfo {
+            name: lock_or_recover(&self.device_name, "device name").clone(),
+            power: self.rated_power,
+            firmware: VERSION.to_string(),
+            uptime: self.started_at.elapsed().as_secs(),
+            build: GIT_HASH.unwrap_or_default().to_string(),
+            location: self.location.clone().unwrap_or_default(),
+            addresses: self
+                .local_addrs
+                .get()
+                .map(|addrs| addrs.iter().map(SocketAddr::to_string).collect())
+                .unwrap_or_default(),
+            protocol: PROTOCOL_VERSION,
+            capabilities: Command::examples()
+                .iter()
+                .filter(|command| self.dimmable || !matches!(command, Command::SetLevel(_)))
+                .filter(|command| !self.read_only || !command.changes_device())
+                .map(|command| command.kind().to_string())
+                .collect(),
+            last_change: last_change.as_ref().map(LastChange::epoch_secs),
+            last_actor: last_change.map(|change| change.peer).unwrap_or_default(),
+            read_only: self.read_only,
+        }
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    id: u64,
+    context: ConnectionContext,
+) -> Result<(), ProtocolError> {
+    stream.set_nonblocking(false).map_err(|e| {
+        ProtocolError::ConnectionError(format!("Failed to set blocking mode: {}", e))
+    })?;
+    stream
+        .set_write_timeout(Some(context.write_timeout))
+        .map_err(|e| {
+            ProtocolError::ConnectionError(format!("Failed to set write timeout: {}", e))
+        })?;
+    // Without keepalive a half-open connection is only noticed on the next
+    // write, which an idle client never triggers; serve the client anyway.
+    if let Some(keepalive) = &context.keepalive {
+        if let Err(e) = net_opts::set_keepalive(&stream, keepalive) {
+            log(&format!(
+                "Failed to enable keepalive on connection {}: {}",
+                id, e
+            ));
+        }
+    }
+
+    // Looked up before TLS wraps the stream; a failed lookup is not fatal.
+    let client = ClientLabel::of(id, &stream);
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = &context.tls {
+        let stream = smart_socket_protocol::tls::accept(tls_config.clone(), stream)?;
+        return serve_client(stream, client, &context);
+    }
+
+    if let Some(psk) = &context.psk {
+        return serve_client(CipherStream::new(stream, psk.clone()), client, &context);
+    }
+    serve_client(stream, client, &context)
+}
+
+/// Serves one labelled connection and strikes the address if the connection is
+/// closed for abuse. A peer whose address is unknown cannot be banned.
+fn serve_client<S: Read + Write>(
+    stream: S,
+    client: ClientLabel,
+    context: &ConnectionContext,
+) -> Result<(), ProtocolError> {
+    let disconnect = handle_client(stream, client, context)?;
+    if let (Disconnect::TooManyErrors, Some(ip)) = (disconnect, client.peer.ip()) {
+        if context.abuse.strike(ip, context.clock.now()) {
+            log(&format!(
+                "Banning {} for {}s after repeated malformed commands",
+                ip,
+                context.abuse.config().ban.as_secs()
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The first bound address; enough when the server listens on a single one.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Stops accepting connections and waits for the open ones to close.
+    ///
+    /// Each accept loop blocks in `accept()`, so after clearing the flag a
+    /// throwaway loopback connection is made to every listener to wake it up.
+    pub fn shutdown(self) -> thread::Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        for &local_addr in &self.local_addrs {
+            let mut wake_addr = local_addr;
+            if wake_addr.ip().is_unspecified() {
+                wake_addr.set_ip(match wake_addr {
+                    SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            if let Err(e) = TcpStream::connect_timeout(&wake_addr, Duration::from_secs(1)) {
+                log(&format!(
+                    "Failed to wake accept loop on {}: {}",
+                    local_addr, e
+                ));
+            }
+        }
+        self.handle.join()
+    }
+}
+
+/// Serves every accepted connection on its own thread until `running` is cleared,
+/// then waits for those connections to close.
+fn accept_loop(listener: TcpListener, context: ConnectionContext, running: Arc<AtomicBool>) {
+    let mut handles = vec![];
+
+    for stream in listener.incoming() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                if let Ok(peer) = stream.peer_addr() {
+                    if context.abuse.is_banned(peer.ip(), context.clock.now()) {
+                        context.debug(&format!("Refused connection from banned {}", peer));
+                        continue;
+                    }
+                }
+                let context = context.clone();
+                let id = context.stats.connection_opened();
+                handles.push(thread::spawn(move || {
+                    if let Err(e) = serve_connection(stream, id, context.clone()) {
+                        log(&format!("Client #{} handler error: {}", id, e));
+                    }
+                    context.stats.connection_closed();
+                }));
+            }
+            Err(e) => log(&format!("Connection failed: {}", e)),
+        }
+    }
+
+    log(&format!(
+        "Waiting for client connections on {} to close...",
+        listener
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    ));
+    for handle in handles {
+        handle
+            .join()
+            .unwrap_or_else(|e| log(&format!("Thread join error: {:?}", e)));
+    }
+}
+
+/// How many successive ports are tried when port fallback is enabled.
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+/// Binds `address`. When the port is taken and `port_fallback` is set, the next
+/// [`PORT_FALLBACK_ATTEMPTS`] ports on the same host are tried in turn.
+fn bind_listener(address: &Address, port_fallback: bool) -> io::Result<TcpListener> {
+    let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    let error = match TcpListener::bind(&addrs[..]) {
+        Ok(listener) => return Ok(listener),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => e,
+        Err(e) => return Err(e),
+    };
+    log(&format!(
+        "Address {} is already in use, is another server instance running?",
+        address
+    ));
+
+    if port_fallback {
+        for offset in 1..=PORT_FALLBACK_ATTEMPTS {
+            let candidates: Vec<SocketAddr> = addrs
+                .iter()
+                .filter_map(|addr| {
+                    let port = addr.port().checked_add(offset)?;
+                    Some(SocketAddr::new(addr.ip(), port))
+                })
+                .collect();
+            if candidates.is_empty() {
+                break;
+            }
+            match TcpListener::bind(&candidates[..]) {
+                Ok(listener) => {
+                    log(&format!(
+                        "Fell back from {} to {}",
+                        address,
+                        listener.local_addr()?
+                    ));
+                    return Ok(listener);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "{} and the next {} ports are in use",
+                address, PORT_FALLBACK_ATTEMPTS
+            ),
+        ));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!(
+            "{} is already in use ({}); stop the other instance, pick another port \
+             or set SMART_SOCKET_PORT_FALLBACK=1",
+            address, error
+        ),
+    ))
+}
+
+/// Binds every address in `addresses` and runs one accept loop per listener,
+/// all serving the same device.
+pub fn run_server(
+    addresses: &[Address],
+    port_fallback: bool,
+    context: ConnectionContext,
+) -> io::Result<ServerHandle> {
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no listen address configured",
+        ));
+    }
+    let listeners = addresses
+        .iter()
+        .map(|address| bind_listener(address, port_fallback))
+        .collect::<io::Result<Vec<_>>>()?;
+    serve(listeners, context)
+}
+
+/// Runs one accept loop per listener, all serving the same device. The
+/// listeners are used as they are, e.g. sockets handed over by systemd.
+pub fn serve(listeners: Vec<TcpListener>, context: ConnectionContext) -> io::Result<ServerHandle> {
+    if listeners.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no listener to serve",
+        ));
+    }
+    let local_addrs = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<io::Result<Vec<_>>>()?;
+    for local_addr in &local_addrs {
+        log(&format!("Listening on {}", local_addr));
+    }
+    let _ = context.local_addrs.set(local_addrs.clone());
+    let running = Arc::new(AtomicBool::new(true));
+    let flusher = spawn_journal_flusher(&context, running.clone());
+    let watchdog = spawn_watchdog(&context, running.clone());
+    let beacon = spawn_beacon(&context, running.clone());
+
+    let accept_loops: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let context = context.clone();
+            let r = running.clone();
+            thread::spawn(move || accept_loop(listener, context, r))
+        })
+        .collect();
+
+    let handle = thread::spawn(move || {
+        for handle in accept_loops
+            .into_iter()
+            .chain(flusher)
+            .chain(watchdog)
+            .chain(beacon)
+        {
+            handle
+                .join()
+                .unwrap_or_else(|e| log(&format!("Thread join error: {:?}", e)));
+        }
+        wind_down(&context);
+    });
+
+    Ok(ServerHandle {
+        local_addrs,
+        running,
+        handle,
+    })
+}
+
+/// Idle connections never write, so a separate thread keeps the journal's
+/// flush-within-a-second promise until `running` is cleared.
+fn spawn_journal_flusher(
+    context: &ConnectionContext,
+    running: Arc<AtomicBool>,
+) -> Option<JoinHandle<()>> {
+    let journal = context.journal.clone()?;
+    Some(thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+            if let Err(e) = lock_or_recover(&journal, "journal").flush_if_due() {
+                log(&format!("Failed to flush journal: {}", e));
+            }
+        }
+    }))
+}
+
+/// Sends a beacon every interval until `running` is cleared, the first one at
+/// startup; see [`crate::beacon`].
+fn spawn_beacon(context: &ConnectionContext, running: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+    let config = context.beacon.clone()?;
+    let mut sender = match BeaconSender::bind(config.address.clone()) {
+        Ok(sender) => sender,
+        Err(e) => {
+            log(&format!(
+                "Beacons disabled, cannot open a UDP socket: {}",
+                e
+            ));
+            return None;
+        }
+    };
+    log(&format!(
+        "Sending beacons to {} every {}s",
+        config.address,
+        config.interval.as_secs_f64()
+    ));
+    let context = context.clone();
+    Some(thread::spawn(move || {
+        let mut next = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            if Instant::now() >= next {
+                if let Some(beacon) = context.beacon() {
+                    sender.send(&beacon);
+                }
+                next = Instant::now() + config.interval;
+            }
+            thread::sleep(Duration::from_millis(100).min(config.interval));
+        }
+    }))
+}
+
+/// Checks that the device thread makes progress until `running` is cleared and
+/// acts on a stall as configured; see [`crate::watchdog`].
+fn spawn_watchdog(context: &ConnectionContext, running: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+    let config = context.watchdog.clone()?;
+    let context = context.clone();
+    Some(thread::spawn(move || {
+        let mut detector = StallDetector::new(config.stall_timeout);
+        let mut last_completed = None;
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(config.probe_interval());
+            // An idle thread is fine, and so is a busy one that finished a job since the last probe.
+            let (busy, completed) = context.device.progress();
+            let progressed = !busy || last_completed != Some(completed);
+            last_completed = Some(completed);
+            let now = context.clock.now();
+            let Some(stalled) = detector.probe(progressed, now) else {
+                continue;
+            };
+            let commands: Vec<String> = context
+                .in_flight
+                .snapshot(now)
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            log(&format!(
+                "CRITICAL: device thread stuck for {:.1}s; commands in flight: {}",
+                stalled.as_secs_f64(),
+                if commands.is_empty() {
+                    "none".to_string()
+                } else {
+                    commands.join(", ")
+                }
+            ));
+            match config.action {
+                WatchdogAction::Abort => {
+                    log("Aborting so that the service manager restarts the server");
+                    std::process::abort();
+                }
+                WatchdogAction::Rebuild => match context.rebuild_device() {
+                    Ok(()) => log("Device rebuilt from its saved state on a new thread"),
+                    Err(e) => log(&format!("Failed to rebuild the device: {}", e)),
+                },
+                WatchdogAction::Log => {}
+            }
+        }
+    }))
+}
+
+/// Last duties once no connection is served any more.
+fn wind_down(context: &ConnectionContext) {
+    // Leave no socket switched on by a pulse that would never end.
+    context.pulse.shutdown();
+    context.persist_usage();
+    context.device.shutdown();
+    context.flush_journal();
+}
+
+/// First wait before the controller is dialled again; doubled after every
+/// failed attempt up to [`MAX_REDIAL_BACKOFF`].
+pub const REDIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_REDIAL_BACKOFF: Duration = Duration::from_secs(60);
+/// How long each resolved controller address is tried.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server in reverse mode, see [`run_reverse`].
+pub struct ReverseHandle {
+    shutdown: Shutdown,
+    /// The connection to the controller while there is one, kept to close it on shutdown.
+    active: Arc<Mutex<Option<TcpStream>>>,
+    handle: JoinHandle<()>,
+}
+
+impl ReverseHandle {
+    /// Stops dialling, closes the connection to the controller and waits for
+    /// the server thread.
+    pub fn shutdown(self) -> thread::Result<()> {
+        self.shutdown.request();
+        if let Some(stream) = lock_or_recover(&self.active, "controller connection").take() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        self.handle.join()
+    }
+}
+
+/// Reverse mode, for devices behind NAT that the controller cannot reach:
+/// instead of listening, the server dials `controller` and serves the protocol
+/// over that connection exactly as over an accepted one. When the controller
+/// cannot be reached or the connection drops, it is dialled again with
+/// exponential backoff.
+pub fn run_reverse(controller: Address, context: ConnectionContext) -> ReverseHandle {
+    let shutdown = Shutdown::new();
+    let active = Arc::new(Mutex::new(None));
+    let flusher = spawn_journal_flusher(&context, shutdown.running());
+    let watchdog = spawn_watchdog(&context, shutdown.running());
+    let beacon = spawn_beacon(&context, shutdown.running());
+    let handle = {
+        let shutdown = shutdown.clone();
+        let active = active.clone();
+        thread::spawn(move || {
+            dial_loop(&controller, &context, &shutdown, &active);
+            for handle in flusher.into_iter().chain(watchdog).chain(beacon) {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| log(&format!("Thread join error: {:?}", e)));
+            }
+            wind_down(&context);
+        })
+    };
+    ReverseHandle {
+        shutdown,
+        active,
+        handle,
+    }
+}
+
+fn dial(address: &Address) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses resolved");
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, DIAL_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn dial_loop(
+    controller: &Address,
+    context: &ConnectionContext,
+    shutdown: &Shutdown,
+    active: &Mutex<Option<TcpStream>>,
+) {
+    let mut backoff = REDIAL_BACKOFF;
+    while !shutdown.is_requested() {
+        match dial(controller) {
+            Ok(stream) => {
+                backoff = REDIAL_BACKOFF;
+                *lock_or_recover(active, "controller connection") = stream.try_clone().ok();
+                // Shutdown closes the stored stream; one stored after it looked must not be served.
+                if shutdown.is_requested() {
+                    break;
+                }
+                let id = context.stats.connection_opened();
+                log(&format!(
+                    "Connected to controller {} as client #{}",
+                    controller, id
+                ));
+                if let Err(e) = serve_connection(stream, id, context.clone()) {
+                    log(&format!("Controller connection error: {}", e));
+                }
+                context.stats.connection_closed();
+                lock_or_recover(active, "controller connection").take();
+                log(&format!("Connection to controller {} closed", controller));
+            }
+            Err(e) => log(&format!("Failed to reach controller {}: {}", controller, e)),
+        }
+        log(&format!("Dialling the controller again in {:?}", backoff));
+        if shutdown.wait_timeout(backoff) {
+            break;
+        }
+        backoff = (backoff * 2).min(MAX_REDIAL_BACKOFF);
+    }
+}
+
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+pub struct TlsServerConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Built with [`ServerConfig::builder`], which checks the values; the fields stay
+/// public for reading and for adjusting a config that has been built.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ServerConfig {
+    /// Every address is bound; IPv6 literals are written as `[::1]:8080`.
+    pub addresses: Vec<Address>,
+    pub socket_name: String,
+    pub socket_power: u32,
+    pub device_type: DeviceType,
+    pub auth_token: Option<String>,
+    pub state_file: Option<PathBuf>,
+    pub journal: Option<JournalConfig>,
+    pub write_timeout: Duration,
+    /// How long STATUS and INFO may be served from the cache without reading the device.
+    pub cache_ttl: Duration,
+    /// Try the next few ports when a configured port is already taken.
+    pub port_fallback: bool,
+    /// Only let the network observe the device: every command that would
+    /// change it is answered with `E_READ_ONLY`, whoever sends it.
+    pub read_only: bool,
+    /// Free-form installation site reported in INFO, e.g. `Kitchen`.
+    pub location: Option<String>,
+    /// Commands each peer address range may send.
+    pub acl: Acl,
+    /// Greet clients with an unsolicited `INFO` frame; disable for clients that
+    /// expect the first frame to answer their first command.
+    pub banner: bool,
+    /// When connections that send garbage are closed, and their addresses banned.
+    pub abuse: AbuseConfig,
+    /// Draw in watts above which the socket trips off until `RESET`; `None` disables it.
+    pub overload_limit: Option<u32>,
+    /// Also log clean client disconnects and refused banned peers.
+    pub debug: bool,
+    /// Settings file that `RELOAD` and SIGHUP re-read; see [`crate::runtime`].
+    pub config_file: Option<PathBuf>,
+    /// Serve a [`SimulatedSocket`] instead of `device_type`, for protocol development.
+    pub simulation: Option<SimulationConfig>,
+    /// Dial this controller and serve it instead of listening on `addresses`;
+    /// see [`run_reverse`].
+    pub controller_address: Option<Address>,
+    /// How long a connection waits for the device thread to carry out a command
+    /// before answering `E_INTERNAL`; the command may still run later.
+    pub device_timeout: Duration,
+    /// Detects a device thread that stops making progress; `None` disables the watchdog.
+    pub watchdog: Option<WatchdogConfig>,
+    /// Retries for a device that fails to start, e.g. hardware not ready at boot.
+    pub device_init: DeviceInit,
+    /// TCP keepalive on accepted connections, so clients that vanished without
+    /// closing are dropped; `None` leaves the OS default, usually off.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Where to send a UDP status beacon at a fixed interval, for monitoring
+    /// that listens instead of polling; `None` sends none.
+    pub beacon: Option<BeaconConfig>,
+    /// Language of the human-readable messages in `OK` and `ERROR` responses;
+    /// error codes and structured responses do not change with it.
+    pub locale: Locale,
+    /// Pre-shared key that every frame is encrypted with, for clients that
+    /// cannot do TLS; clients without the same key cannot talk to the server.
+    pub psk: Option<Psk>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsServerConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addresses: vec![Address::Ip(SocketAddr::from(([127, 0, 0, 1], 8080)))],
+            socket_name: "Kitchen Socket".to_string(),
+            socket_power: 3500,
+            device_type: DeviceType::default(),
+            auth_token: None,
+            state_file: None,
+            journal: None,
+            write_timeout: Duration::from_secs(10),
+            cache_ttl: Duration::from_millis(250),
+            port_fallback: false,
+            read_only: false,
+            location: None,
+            acl: Acl::default(),
+            banner: true,
+            abuse: AbuseConfig::default(),
+            overload_limit: None,
+            debug: false,
+            config_file: None,
+            simulation: None,
+            controller_address: None,
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            watchdog: Some(WatchdogConfig::default()),
+            device_init: DeviceInit::default(),
+            keepalive: Some(KeepaliveConfig::default()),
+            beacon: None,
+            locale: Locale::default(),
+            psk: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Starts from the defaults.
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder {
+            config: Self::default(),
+            error: None,
+        }
+    }
+}
+
+/// Sets up a [`ServerConfig`]; nothing is checked until [`build`](Self::build).
+#[derive(Debug)]
+#[must_use]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+    /// The first address that failed to parse, reported by `build`.
+    error: Option<ConfigError>,
+}
+
+impl ServerConfigBuilder {
+    /// Listens on `address` only.
+    pub fn address(mut self, address: impl IntoAddress) -> Self {
+        match address.into_address() {
+            Ok(address) => self.config.addresses = vec![address],
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    pub fn addresses(mut self, addresses: Vec<Address>) -> Self {
+        self.config.addresses = addresses;
+        self
+    }
+
+    pub fn socket_name(mut self, name: impl Into<String>) -> Self {
+        self.config.socket_name = name.into();
+        self
+    }
+
+    pub fn socket_power(mut self, watts: u32) -> Self {
+        self.config.socket_power = watts;
+        self
+    }
+
+    pub fn device_type(mut self, device_type: DeviceType) -> Self {
+        self.config.device_type = device_type;
+        self
+    }
+
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.config.auth_token = Some(token.into());
+        self
+    }
+
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.state_file = Some(path.into());
+        self
+    }
+
+    pub fn journal(mut self, journal: JournalConfig) -> Self {
+        self.config.journal = Some(journal);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.config.cache_ttl = ttl;
+        self
+    }
+
+    pub fn port_fallback(mut self, enabled: bool) -> Self {
+        self.config.port_fallback = enabled;
+        self
+    }
+
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.config.read_only = enabled;
+        self
+    }
+
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.config.location = Some(location.into());
+        self
+    }
+
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.config.acl = acl;
+        self
+    }
+
+    pub fn banner(mut self, enabled: bool) -> Self {
+        self.config.banner = enabled;
+        self
+    }
+
+    pub fn abuse(mut self, abuse: AbuseConfig) -> Self {
+        self.config.abuse = abuse;
+        self
+    }
+
+    pub fn overload_limit(mut self, watts: u32) -> Self {
+        self.config.overload_limit = Some(watts);
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    /// Sets every setting a reload can change at once.
+    pub fn runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.config.acl = runtime.acl;
+        self.config.abuse = runtime.abuse;
+        self.config.overload_limit = runtime.overload_limit;
+        self.config.debug = runtime.debug;
+        self
+    }
+
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.config_file = Some(path.into());
+        self
+    }
+
+    pub fn simulation(mut self, simulation: SimulationConfig) -> Self {
+        self.config.simulation = Some(simulation);
+        self
+    }
+
+    pub fn controller_address(mut self, address: impl IntoAddress) -> Self {
+        match address.into_address() {
+            Ok(address) => self.config.controller_address = Some(address),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    pub fn device_timeout(mut self, timeout: Duration) -> Self {
+        self.config.device_timeout = timeout;
+        self
+    }
+
+    /// `None` disables the watchdog.
+    pub fn watchdog(mut self, watchdog: Option<WatchdogConfig>) -> Self {
+        self.config.watchdog = watchdog;
+        self
+    }
+
+    pub fn device_init(mut self, init: DeviceInit) -> Self {
+        self.config.device_init = init;
+        self
+    }
+
+    /// `None` turns keepalive off.
+    pub fn keepalive(mut self, keepalive: Option<KeepaliveConfig>) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// `None` sends no beacons.
+    pub fn beacon(mut self, beacon: Option<BeaconConfig>) -> Self {
+        self.config.beacon = beacon;
+        self
+    }
+
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.config.locale = locale;
+        self
+    }
+
+    /// Encrypts frames with the 32-byte key given as 64 hex digits.
+    pub fn psk(mut self, hex: &str) -> Self {
+        match Psk::from_hex(hex) {
+            Ok(psk) => self.config.psk = Some(psk),
+            Err(reason) => {
+                self.error.get_or_insert(ConfigError::InvalidValue {
+                    field: "psk",
+                    reason,
+                });
+            }
+        }
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsServerConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Checks what can be checked without touching the network or the disk;
+    /// [`preflight`] covers the rest.
+    pub fn build(self) -> Result<ServerConfig, ConfigError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let config = self.config;
+        if config.addresses.is_empty() && config.controller_address.is_none() {
+            return Err(ConfigError::NoAddresses);
+        }
+        validate_device_name(&config.socket_name).map_err(|reason| ConfigError::InvalidValue {
+            field: "socket_name",
+            reason,
+        })?;
+        if config.write_timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("write_timeout"));
+        }
+        if config.overload_limit == Some(0) {
+            return Err(ConfigError::InvalidValue {
+                field: "overload_limit",
+                reason: "must be greater than zero".to_string(),
+            });
+        }
+        if let Some(simulation) = &config.simulation {
+            if !(0.0..=1.0).contains(&simulation.failure_rate) {
+                return Err(ConfigError::InvalidValue {
+                    field: "failure_rate",
+                    reason: format!("{} is not between 0 and 1", simulation.failure_rate),
+                });
+            }
+        }
+        if config.device_timeout.is_zero() {
+            return Err(ConfigError::ZeroDuration("device_timeout"));
+        }
+        if config
+            .watchdog
+            .as_ref()
+            .is_some_and(|watchdog| watchdog.stall_timeout.is_zero())
+        {
+            return Err(ConfigError::ZeroDuration("stall_timeout"));
+        }
+        if let Some(keepalive) = &config.keepalive {
+            if keepalive.idle.is_zero() {
+                return Err(ConfigError::ZeroDuration("keepalive_idle"));
+            }
+            if keepalive.interval.is_zero() {
+                return Err(ConfigError::ZeroDuration("keepalive_interval"));
+            }
+            if keepalive.count == 0 {
+                return Err(ConfigError::InvalidValue {
+                    field: "keepalive_count",
+                    reason: "at least one probe must be sent".to_string(),
+                });
+            }
+        }
+        if config
+            .beacon
+            .as_ref()
+            .is_some_and(|beacon| beacon.interval.is_zero())
+        {
+            return Err(ConfigError::ZeroDuration("beacon_interval"));
+        }
+        #[cfg(feature = "tls")]
+        if config.tls.is_some() && config.psk.is_some() {
+            return Err(ConfigError::InvalidValue {
+                field: "psk",
+                reason: "a pre-shared key cannot be combined with TLS".to_string(),
+            });
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum PreflightError {
+    InvalidAddress { address: String, reason: String },
+    BindFailed { address: String, reason: String },
+    PathNotWritable { path: PathBuf, reason: String },
+    FileUnreadable { path: PathBuf, reason: String },
+    Device(DeviceInitError),
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::InvalidAddress { address, reason } => {
+                write!(f, "Invalid address '{}': {}", address, reason)
+            }
+            PreflightError::BindFailed { address, reason } => {
+                write!(f, "Cannot bind {}: {}", address, reason)
+            }
+            PreflightError::PathNotWritable { path, reason } => {
+                write!(f, "Path {:?} is not writable: {}", path, reason)
+            }
+            PreflightError::FileUnreadable { path, reason } => {
+                write!(f, "File {:?} is not readable: {}", path, reason)
+            }
+            PreflightError::Device(e) => e.fmt(f),
+        }
+    }
+}
+
+fn check_writable(path: &Path) -> Result<(), PreflightError> {
+    let not_writable = |reason: String| PreflightError::PathNotWritable {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(not_writable(format!("directory {:?} does not exist", dir)));
+    }
+    let probe = dir.join(format!(".preflight-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| not_writable(e.to_string()))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+fn check_readable(path: &Path) -> Result<(), PreflightError> {
+    fs::File::open(path)
+        .map(|_| ())
+        .map_err(|e| PreflightError::FileUnreadable {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+}
+
+/// Builds the configured device once and drops it, so a setting the device
+/// rejects is reported before the server is started with it. Failures are
+/// not retried.
+pub fn validate_device(config: &ServerConfig) -> Result<(), Vec<PreflightError>> {
+    let built = match &config.simulation {
+        Some(simulation) => SimulatedSocket::from_config(config.socket_power, simulation)
+            .map(drop)
+            .map_err(|reason| DeviceInitError {
+                rejected: None,
+                reason,
+                attempts: 1,
+            }),
+        None => startup::build_device(
+            &DeviceInit {
+                retries: 0,
+                ..config.device_init.clone()
+            },
+            &config.socket_name,
+            config.socket_power,
+            Socket::new,
+            thread::sleep,
+        )
+        .map(drop),
+    };
+    built.map_err(|e| vec![PreflightError::Device(e)])
+}
+
+/// Validates the configuration without starting the server, reporting every
+/// problem found. The listener is bound and released immediately.
+pub fn preflight(config: &ServerConfig) -> Result<(), Vec<PreflightError>> {
+    let mut errors = Vec::new();
+
+    // A device in reverse mode listens nowhere, but its controller must resolve.
+    let listen_addresses = match &config.controller_address {
+        Some(controller) => {
+            if let Err(e) = controller.to_socket_addrs() {
+                errors.push(PreflightError::InvalidAddress {
+                    address: controller.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+            &[][..]
+        }
+        None => &config.addresses[..],
+    };
+    if config.controller_address.is_none() && config.addresses.is_empty() {
+        errors.push(PreflightError::InvalidAddress {
+            address: String::new(),
+            reason: "no listen address configured".to_string(),
+        });
+    }
+    // Listeners stay bound until every address is checked, so duplicates are caught.
+    let mut listeners = Vec::new();
+    for address in listen_addresses {
+        match address.to_socket_addrs() {
+            Ok(addrs) => {
+                let addrs: Vec<_> = addrs.collect();
+                let bound = match TcpListener::bind(&addrs[..]) {
+                    Err(e) if e.kind() == io::ErrorKind::AddrInUse && config.port_fallback => {
+                        bind_listener(address, true)
+                    }
+                    bound => bound,
+                };
+                match bound {
+                    Ok(listener) => listeners.push(listener),
+                    Err(e) => errors.push(PreflightError::BindFailed {
+                        address: address.to_string(),
+                        reason: e.to_string(),
+                    }),
+                }
+            }
+            Err(e) => errors.push(PreflightError::InvalidAddress {
+                address: address.to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+    drop(listeners);
+
+    let journal_path = config.journal.as_ref().map(|journal| &journal.path);
+    for path in config.state_file.iter().chain(journal_path) {
+        if let Err(e) = check_writable(path) {
+            errors.push(e);
+        }
+    }
+
+    let script = config
+        .simulation
+        .as_ref()
+        .and_then(|sim| sim.script.as_ref());
+    if let Some(path) = script {
+        if let Err(e) = check_readable(path) {
+            errors.push(e);
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &config.tls {
+        for path in [&tls.cert_path, &tls.key_path] {
+            if let Err(e) = check_readable(path) {
+                errors.push(e);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// What `--version` prints: the same version, build and location as INFO reports.
+pub fn version_line(location: Option<&str>) -> String {
+    let mut line = format!("smart_socket_server {}", VERSION);
+    if let Some(hash) = GIT_HASH {
+        line.push_str(&format!(" ({})", hash));
+    }
+    if let Some(location) = location {
+        line.push_str(&format!(", location: {}", location));
+    }
+    line
+}
+
+/// Applies the saved power state (and level) to `socket` and returns the saved
+/// state for the name and usage counters.
+fn restore_state(socket: &mut dyn Device, path: &Path) -> Option<PersistedState> {
+    match persistence::load(path) {
+        Ok(Some(state)) => {
+            if state.is_on {
+                if let Err(e) = socket.turn_on() {
+                    log(&format!("Could not switch the socket back on: {}", e));
+                }
+            }
+            if let (Some(level), Some(dimmer)) = (state.level, socket.as_dimmable()) {
+                dimmer.set_level(level);
+            }
+            log(&format!(
+                "Restored state from {:?}: {}",
+                path,
+                if state.is_on { "ON" } else { "OFF" }
+            ));
+            Some(state)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            log(&format!("Failed to load state from {:?}: {}", path, e));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::Policy;
+    use crate::peer::{Peer, RemotePeer};
+    use smart_socket_protocol::clock::MockClock;
+    use smart_socket_protocol::{read_message, serialize_message};
+    use std::cell::RefCell;
+    use std::net::UdpSocket;
+    use std::time::SystemTime;
+
+    thread_local! {
+        static CAPTURED_LOG: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    }
+
+    /// Keeps `message` if this thread is inside [`logged_by`].
+    pub(super) fn capture_log(message: &str) {
+        CAPTURED_LOG.with(|captured| {
+            if let Some(lines) = captured.borrow_mut().as_mut() {
+                lines.push(message.to_string());
+            }
+        });
+    }
+
+    /// The lines `f` logs on the calling thread.
+    fn logged_by(f: impl FnOnce()) -> Vec<String> {
+        CAPTURED_LOG.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+        f();
+        CAPTURED_LOG
+            .with(|captured| captured.borrow_mut().take())
+            .unwrap_or_default()
+    }
+
+    /// The replies `context` gives to `commands` over one in-memory connection.
+    fn replies_to(context: &ConnectionContext, commands: &[&str]) -> Vec<String> {
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(commands)),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, context).unwrap();
+        let mut output = io::Cursor::new(duplex.output);
+        let mut replies = Vec::new();
+        while let Ok(reply) = read_message(&mut output) {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    /// In-memory stream: reads come from a fixed script, writes are captured.
+    struct Duplex {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Like a proxied or abstract socket, an in-memory stream has no peer address.
+    impl RemotePeer for Duplex {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::Error::from(io::ErrorKind::NotConnected))
+        }
+    }
+
+    const TEST_CLIENT: ClientLabel = ClientLabel {
+        id: 1,
+        peer: Peer::Unknown,
+    };
+
+    fn run_script(input: Vec<u8>) -> Vec<u8> {
+        let mut duplex = Duplex {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &test_context(None)).unwrap();
+        duplex.output
+    }
+
+    /// STATUS payload the test socket reports in the given state.
+    fn expected_status(on: bool) -> String {
+        let mut socket = Socket::new("Test Socket", 1000).unwrap();
+        if on {
+            socket.turn_on();
+        }
+        Response::Status {
+            is_on: socket.is_on(),
+            power: socket.get_power(),
+            tripped: false,
+            locked: false,
+        }
+        .to_string()
+    }
+
+    fn framed<S: AsRef<str>>(messages: &[S]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|message| serialize_message(message.as_ref()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_handle_client_in_memory() {
+        let output = run_script(framed(&["ON", "STATUS", "xyz", "OFF"]));
+        assert_eq!(
+            output,
+            framed(&[
+                "OK:Socket turned on".to_string(),
+                expected_status(true),
+                "ERROR:Invalid command: xyz".to_string(),
+                "OK:Socket turned off".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_handle_client_invalid_utf8_closes_connection() {
+        let mut input = framed(&["PING"]);
+        input.extend_from_slice(&2u32.to_be_bytes());
+        input.extend_from_slice(&[0xff, 0xfe]);
+        input.extend(framed(&["PING"]));
+
+        assert_eq!(run_script(input), framed(&["PONG"]));
+    }
+
+    #[test]
+    fn test_malformed_commands_close_connection() {
+        let garbage = ["GET / HTTP/1.1", "\u{1}\u{2}", "SSH-2.0", "ON:x", ""];
+        let mut input = framed(&["PING"]);
+        input.extend(framed(&garbage[..2]));
+        // A valid command in between resets the count.
+        input.extend(framed(&["PING"]));
+        input.extend(framed(&garbage));
+        input.extend(framed(&["PING"]));
+
+        let mut output = io::Cursor::new(run_script(input));
+        let mut replies = Vec::new();
+        while let Ok(reply) = read_message(&mut output) {
+            replies.push(reply);
+        }
+        assert_eq!(replies.len(), 9);
+        assert_eq!(replies[3], "PONG");
+        assert!(replies[7].starts_with("ERROR:Invalid command"));
+        assert_eq!(
+            replies[8],
+            "ERROR:E_TOO_MANY_ERRORS:Too many malformed commands"
+        );
+    }
+
+    #[test]
+    fn test_responses_follow_the_locale() {
+        let replies = |locale| {
+            let mut context = test_context(None);
+            context.locale = locale;
+            let mut duplex = Duplex {
+                input: io::Cursor::new(framed(&["ON", "STATUS", "LOCK", "OFF", "BOGUS"])),
+                output: Vec::new(),
+            };
+            handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+            let mut output = io::Cursor::new(duplex.output);
+            let mut replies = Vec::new();
+            while let Ok(reply) = read_message(&mut output) {
+                replies.push(reply.parse::<Response>().unwrap());
+            }
+            replies
+        };
+        let english = replies(Locale::En);
+        let russian = replies(Locale::Ru);
+
+        assert_eq!(
+            russian,
+            [
+                Response::ok("Розетка включена"),
+                expected_status(true).parse().unwrap(),
+                Response::ok("Розетка заблокирована"),
+                Response::error(
+                    ErrorCode::Locked,
+                    "Розетка заблокирована; сначала отправьте UNLOCK"
+                ),
+                Response::Error("Некорректная команда: BOGUS".to_string()),
+            ]
+        );
+        assert_eq!(english[0], Response::ok("Socket turned on"));
+        // Keywords, codes and structured fields do not depend on the locale.
+        for (english, russian) in english.iter().zip(&russian) {
+            assert_eq!(english.kind(), russian.kind());
+            if let Response::Error(_) | Response::Ok(_) = english {
+                let code = |response: &Response| {
+                    response.to_string().split(':').nth(1).map(str::to_string)
+                };
+                assert_eq!(
+                    code(english).filter(|code| code.starts_with("E_")),
+                    code(russian).filter(|code| code.starts_with("E_"))
+                );
+            } else {
+                assert_eq!(english, russian);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interleaved_garbage_is_capped_per_connection() {
+        let max = AbuseConfig::default().max_errors_total as usize;
+        let mut input = Vec::new();
+        for _ in 0..max + 1 {
+            // Never enough in a row to trip max_errors.
+            input.extend(framed(&["ON\nOFF", "PING"]));
+        }
+
+        let mut output = io::Cursor::new(run_script(input));
+        let mut replies = Vec::new();
+        while let Ok(reply) = read_message(&mut output) {
+            replies.push(reply);
+        }
+        assert_eq!(replies.len(), 2 * max - 1);
+        assert!(replies[0].starts_with("ERROR:Invalid command: ON\\nOFF"));
+        assert_eq!(replies[1], "PONG");
+        assert_eq!(
+            replies.last().unwrap(),
+            "ERROR:E_TOO_MANY_ERRORS:Too many malformed commands"
+        );
+    }
+
+    /// A context that reads the time from the returned clock. Moving it does
+    /// not wake the pulse timer thread, so pulse tests end due pulses themselves.
+    fn mock_clock_context() -> (ConnectionContext, MockClock) {
+        let clock = MockClock::new();
+        let context = ConnectionContext {
+            clock: Arc::new(clock.clone()),
+            ..test_context(None)
+        };
+        (context, clock)
+    }
+
+    #[test]
+    fn test_repeated_abuse_bans_address_until_expiry() {
+        let (context, clock) = mock_clock_context();
+        let context = ConnectionContext {
+            abuse: Arc::new(BanTable::new(AbuseConfig {
+                max_errors: 1,
+                strikes: 2,
+                ban: Duration::from_secs(300),
+                ..Default::default()
+            })),
+            ..context
+        };
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, context).unwrap();
+        let addr = server.local_addr();
+
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            assert!(request(&mut stream, "garbage").starts_with("ERROR:E_TOO_MANY_ERRORS:"));
+            assert!(matches!(
+                read_message(&mut stream),
+                Err(ProtocolError::ConnectionClosed)
+            ));
+        }
+
+        // The ban is recorded once the second connection's thread finishes.
+        let started = Instant::now();
+        loop {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(&serialize_message("PING").unwrap())
+                .unwrap();
+            match read_message(&mut stream) {
+                Err(_) => break,
+                Ok(_) => assert!(started.elapsed() < Duration::from_secs(1), "never banned"),
+            }
+        }
+
+        clock.advance(Duration::from_secs(299));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        // The server may already have closed the connection, failing the write.
+        let _ = stream.write_all(&serialize_message("PING").unwrap());
+        assert!(read_message(&mut stream).is_err(), "ban ended early");
+        clock.advance(Duration::from_secs(1));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_handle_client_truncated_frame() {
+        let mut input = framed(&["STATUS"]);
+        input.extend_from_slice(&10u32.to_be_bytes());
+        input.extend_from_slice(b"ON");
+
+        assert_eq!(run_script(input), framed(&[expected_status(false)]));
+    }
+
+    fn test_context(auth_token: Option<&str>) -> ConnectionContext {
+        ConnectionContext {
+            device: Arc::new(DeviceOwner::spawn(
+                DeviceType::Socket.build(Socket::new("Test Socket", 1000).unwrap()),
+            )),
+            device_factory: Arc::new(|| {
+                Ok(DeviceType::Socket.build(Socket::new("Test Socket", 1000).unwrap()))
+            }),
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            device_name: Arc::new(Mutex::new("Test Socket".to_string())),
+            rated_power: 1000,
+            started_at: Instant::now(),
+            auth_token: auth_token.map(str::to_string),
+            state_file: None,
+            write_timeout: Duration::from_secs(10),
+            stats: Arc::default(),
+            status_cache: Arc::new(StatusCache::new(Duration::from_millis(250))),
+            pulse: Arc::default(),
+            clock: Arc::new(SystemClock),
+            journal: None,
+            local_addrs: Arc::default(),
+            location: None,
+            runtime: Arc::default(),
+            config_file: None,
+            settings: Arc::default(),
+            banner: false,
+            dimmable: false,
+            read_only: false,
+            abuse: Arc::default(),
+            breaker: Arc::default(),
+            usage: Arc::default(),
+            last_change: Arc::default(),
+            child_lock: Arc::default(),
+            simulated: false,
+            in_flight: Arc::default(),
+            watchdog: None,
+            keepalive: None,
+            beacon: None,
+            locale: Locale::default(),
+            last_error: Arc::default(),
+            psk: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    fn runtime_with_acl(rules: &str, default: Policy) -> Arc<RwLock<RuntimeConfig>> {
+        Arc::new(RwLock::new(RuntimeConfig {
+            acl: Acl::parse(rules, default).unwrap(),
+            ..Default::default()
+        }))
+    }
+
+    fn listen(addresses: &[&str]) -> Vec<Address> {
+        addresses
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect()
+    }
+
+    fn spawn_with_context(context: ConnectionContext) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for (id, stream) in (1..).zip(listener.incoming().flatten()) {
+                let context = context.clone();
+                thread::spawn(move || serve_connection(stream, id, context));
+            }
+        });
+        addr
+    }
+
+    fn spawn_server(auth_token: Option<&str>) -> SocketAddr {
+        spawn_with_context(test_context(auth_token))
+    }
+
+    fn request<S: Read + Write>(stream: &mut S, command: &str) -> String {
+        stream
+            .write_all(&serialize_message(command).unwrap())
+            .unwrap();
+        read_message(stream).unwrap()
+    }
+
+    #[test]
+    fn test_oversized_frame_closes_the_connection() {
+        let addr = spawn_server(None);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        assert!(read_message(&mut stream).is_err());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+    }
+
+    #[test]
+    fn test_psk_server_serves_only_clients_with_the_key() {
+        let key = [9u8; 32];
+        let addr = spawn_with_context(ConnectionContext {
+            psk: Some(Psk::new(&key)),
+            ..test_context(None)
+        });
+        let tcp = TcpStream::connect(addr).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut stream = CipherStream::new(tcp, Psk::new(&key));
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+
+        // A plain client, or one with another key, is dropped on its first frame.
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        plain.write_all(&serialize_message("OFF").unwrap()).unwrap();
+        assert!(read_message(&mut plain).is_err());
+        let tcp = TcpStream::connect(addr).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut wrong = CipherStream::new(tcp, Psk::new(&[1; 32]));
+        wrong.write_all(&serialize_message("OFF").unwrap()).unwrap();
+        assert!(read_message(&mut wrong).is_err());
+
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+    }
+
+    #[test]
+    fn test_pipelined_commands() {
+        let mut stream = TcpStream::connect(spawn_server(None)).unwrap();
+        let batch = framed(&["STATUS"; 50]);
+        stream.write_all(&batch).unwrap();
+
+        for _ in 0..50 {
+            assert_eq!(read_message(&mut stream).unwrap(), expected_status(false));
+        }
+    }
+
+    #[test]
+    fn test_all_listeners_share_one_device() {
+        let server = run_server(
+            &listen(&["127.0.0.1:0", "[::1]:0"]),
+            false,
+            test_context(None),
+        )
+        .unwrap();
+        let [v4, v6] = server.local_addrs() else {
+            panic!("Expected two listeners");
+        };
+        assert!(v4.is_ipv4() && v6.is_ipv6());
+
+        let mut v4_stream = TcpStream::connect(v4).unwrap();
+        assert_eq!(request(&mut v4_stream, "ON"), "OK:Socket turned on");
+        let mut v6_stream = TcpStream::connect(v6).unwrap();
+        assert_eq!(request(&mut v6_stream, "STATUS"), expected_status(true));
+
+        drop((v4_stream, v6_stream));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_serve_prebound_listener() {
+        assert!(serve(Vec::new(), test_context(None)).is_err());
+
+        // Bound elsewhere, as systemd does for socket activation.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = serve(vec![listener], test_context(None)).unwrap();
+        assert_eq!(server.local_addrs(), [address]);
+        let mut stream = TcpStream::connect(address).unwrap();
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_status_is_cached_until_ttl_expires() {
+        let (mut context, clock) = mock_clock_context();
+        context.status_cache = Arc::new(StatusCache::new(Duration::from_millis(100)));
+        let device = context.device.clone();
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+
+        // Changed behind the server's back, so only the TTL can reveal it.
+        device
+            .call(TIMEOUT, |device| device.turn_on())
+            .unwrap()
+            .unwrap();
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+    }
+
+    #[test]
+    fn test_status_cache_reads_your_writes() {
+        let mut context = test_context(None);
+        // Long enough that only the write path can refresh the cache during the test.
+        context.status_cache = Arc::new(StatusCache::new(Duration::from_secs(60)));
+        let addr = spawn_with_context(context);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    let valid = [expected_status(false), expected_status(true)];
+                    while !done.load(Ordering::SeqCst) {
+                        assert!(valid.contains(&request(&mut stream, "STATUS")));
+                    }
+                })
+            })
+            .collect();
+
+        let mut writer = TcpStream::connect(addr).unwrap();
+        for i in 0..200 {
+            let on = i % 2 == 0;
+            request(&mut writer, if on { "ON" } else { "OFF" });
+            assert_eq!(request(&mut writer, "STATUS"), expected_status(on));
+        }
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn is_on(context: &ConnectionContext) -> bool {
+        context
+            .device
+            .call(TIMEOUT, |device| device.is_on())
+            .unwrap()
+    }
+
+    /// Moves `clock` by `millis` and ends the pulse if it is due by then.
+    fn after(context: &ConnectionContext, clock: &MockClock, millis: u64) -> bool {
+        clock.advance(Duration::from_millis(millis));
+        end_due_pulse(context).unwrap();
+        is_on(context)
+    }
+
+    #[test]
+    fn test_pulse_turns_socket_off_again() {
+        let (context, clock) = mock_clock_context();
+        let response = execute(Command::Pulse(50), &context, TEST_CLIENT);
+        assert_eq!(response.to_string(), "OK:Pulse started");
+        assert!(is_on(&context));
+
+        assert!(after(&context, &clock, 49));
+        assert!(!after(&context, &clock, 1));
+        assert!(!context.cached_status().unwrap().is_on);
+    }
+
+    #[test]
+    fn test_overlapping_pulses_extend() {
+        let (context, clock) = mock_clock_context();
+        // A longer pulse pushes the end out...
+        execute(Command::Pulse(100), &context, TEST_CLIENT);
+        execute(Command::Pulse(400), &context, TEST_CLIENT);
+        assert!(after(&context, &clock, 250));
+        assert!(!after(&context, &clock, 150));
+
+        // ...and a shorter one never cuts a running pulse short.
+        execute(Command::Pulse(400), &context, TEST_CLIENT);
+        execute(Command::Pulse(20), &context, TEST_CLIENT);
+        assert!(after(&context, &clock, 200));
+        assert!(!after(&context, &clock, 200));
+    }
+
+    #[test]
+    fn test_turn_off_cancels_pulse() {
+        let (context, clock) = mock_clock_context();
+        execute(Command::Pulse(100), &context, TEST_CLIENT);
+        execute(Command::TurnOff, &context, TEST_CLIENT);
+        assert!(!is_on(&context));
+
+        // The cancelled pulse must not switch off a later, plain ON.
+        execute(Command::TurnOn, &context, TEST_CLIENT);
+        assert!(after(&context, &clock, 300));
+    }
+
+    #[test]
+    fn test_shutdown_ends_running_pulse() {
+        let context = test_context(None);
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, context.clone()).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        assert_eq!(request(&mut stream, "PULSE:60000"), "OK:Pulse started");
+        assert!(is_on(&context));
+        drop(stream);
+
+        let started = Instant::now();
+        server.shutdown().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // The device thread is gone by now; the last change it made was the turn-off.
+        let cached = context.status_cache.state.read().unwrap();
+        assert!(!cached.as_ref().unwrap().snapshot.is_on);
+        assert_eq!(
+            context.device.call(TIMEOUT, |device| device.is_on()),
+            Err(DeviceError::Stopped)
+        );
+    }
+
+    #[test]
+    fn test_commands_after_a_panicking_device_call() {
+        let context = test_context(None);
+        assert_eq!(
+            context.with_device(|_, _| -> () { panic!("driver fault") }),
+            Err(DeviceError::Panicked)
+        );
+
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+    }
+
+    /// Device whose switch panics, standing in for a driver bug.
+    struct FaultyDevice;
+
+    impl Device for FaultyDevice {
+        fn turn_on(&mut self) -> Result<(), SwitchError> {
+            panic!("relay driver fault");
+        }
+
+        fn turn_off(&mut self) {}
+
+        fn is_on(&self) -> bool {
+            false
+        }
+
+        fn get_power(&self) -> u32 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_device_panic_becomes_error_response() {
+        let mut context = test_context(None);
+        context.device = Arc::new(DeviceOwner::spawn(Box::new(FaultyDevice)));
+
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(
+            request(&mut stream, "ON"),
+            "ERROR:E_INTERNAL:Internal device error"
+        );
+        // The same connection keeps working, and so does the device thread.
+        assert_eq!(request(&mut stream, "STATUS"), "STATUS:OFF:0");
+    }
+
+    /// Device that never switches on, for the given reason.
+    struct RefusingDevice(SwitchError);
+
+    impl Device for RefusingDevice {
+        fn turn_on(&mut self) -> Result<(), SwitchError> {
+            Err(self.0.clone())
+        }
+
+        fn turn_off(&mut self) {}
+
+        fn is_on(&self) -> bool {
+            false
+        }
+
+        fn get_power(&self) -> u32 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_turn_on_outcomes() {
+        let context = test_context(None);
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert_eq!(request(&mut stream, "ON"), "OK:Already on");
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+
+        for (refusal, expected) in [
+            (
+                SwitchError::Overload {
+                    power: 2500,
+                    limit: 2000,
+                },
+                "ERROR:E_OVERLOAD:2500W exceeds the device's 2000W limit, socket left off",
+            ),
+            (
+                SwitchError::Locked,
+                "ERROR:E_LOCKED:Device is locked and refused to switch on",
+            ),
+            (
+                SwitchError::Fault("relay stuck open".to_string()),
+                "ERROR:E_DEVICE_FAULT:Device failed to switch on: relay stuck open",
+            ),
+        ] {
+            let mut context = test_context(None);
+            context.device = Arc::new(DeviceOwner::spawn(Box::new(RefusingDevice(refusal))));
+            let mut stream = TcpStream::connect(spawn_with_context(context.clone())).unwrap();
+            for command in ["ON", "PULSE:50"] {
+                assert_eq!(request(&mut stream, command), expected, "{}", command);
+            }
+            assert_eq!(request(&mut stream, "STATUS"), "STATUS:OFF:0");
+            assert!(lock_or_recover(&context.last_change, "last change").is_none());
+        }
+    }
+
+    #[test]
+    fn test_new_connection_is_served_promptly() {
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, test_context(None)).unwrap();
+
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+        let elapsed = started.elapsed();
+        assert!(elapsed < Duration::from_millis(10), "took {:?}", elapsed);
+
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_beacon_reports_uptime_and_last_error() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut context = test_context(None);
+        context.beacon = Some(BeaconConfig {
+            address: listener.local_addr().unwrap().into(),
+            interval: Duration::from_millis(100),
+        });
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, context).unwrap();
+        let next_beacon = || {
+            Beacon::listen(&listener, Duration::from_secs(2))
+                .unwrap()
+                .unwrap()
+                .1
+        };
+
+        let first = next_beacon();
+        assert_eq!(first.name, "Test Socket");
+        assert!(!first.is_on);
+        assert_eq!(first.last_error, None);
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        let error = request(&mut stream, "BOGUS");
+        assert!(error.starts_with("ERROR:"), "{}", error);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let reported = loop {
+            let beacon = next_beacon();
+            if beacon.last_error.is_some() || Instant::now() > deadline {
+                break beacon;
+            }
+        };
+        assert_eq!(
+            reported.last_error.map(|e| format!("ERROR:{}", e)),
+            Some(error)
+        );
+        assert!(reported.uptime_secs >= first.uptime_secs);
+
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_client_that_stops_reading_is_dropped() {
+        let write_timeout = Duration::from_millis(200);
+        let mut context = test_context(None);
+        context.write_timeout = write_timeout;
+        let stats = context.stats.clone();
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, context).unwrap();
+
+        // Pipeline INFO requests without ever reading the responses. Once the socket
+        // buffers fill up the server blocks on write, stops reading, and our writes stall.
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .set_write_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let batch = framed(&["INFO"; 1000]);
+        while stream.write_all(&batch).is_ok() {}
+        let stalled_at = Instant::now();
+
+        while stats.active.load(Ordering::Relaxed) > 0 {
+            assert!(
+                stalled_at.elapsed() < write_timeout + Duration::from_secs(1),
+                "handler thread still blocked on write"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_stats_under_concurrent_clients() {
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, test_context(None)).unwrap();
+        let addr = server.local_addr();
+
+        let clients: Vec<_> = (0..10)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    for i in 0..100 {
+                        let command = if i % 10 == 0 { "BOGUS" } else { "STATUS" };
+                        request(&mut stream, command);
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        // Handler threads finish asynchronously; poll until only this connection is left.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut stats_requests = 0;
+        let stats = loop {
+            stats_requests += 1;
+            let stats = match Response::from_str(&request(&mut stream, "STATS")).unwrap() {
+                Response::Stats(stats) => stats,
+                other => panic!("Unexpected response: {:?}", other),
+            };
+            if stats.active == 1 || Instant::now() > deadline {
+                break stats;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        assert_eq!(stats.connections, 11);
+        assert_eq!(stats.active, 1);
+        assert_eq!(stats.commands, 1000 + stats_requests);
+        assert_eq!(stats.errors, 100);
+
+        drop(stream);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_journal_is_flushed_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+        let mut context = test_context(Some("secret"));
+        context.journal = Some(Arc::new(Mutex::new(
+            Journal::open(JournalConfig::new(path.clone())).unwrap(),
+        )));
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, context).unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        for command in ["AUTH:secret", "ON", "STATUS", "BOGUS"] {
+            request(&mut stream, command);
+        }
+        drop(stream);
+        server.shutdown().unwrap();
+
+        let entries = journal::read_entries(fs::read_to_string(&path).unwrap().as_bytes()).unwrap();
+        let recorded: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.command.as_str(), entry.response_kind.as_str()))
+            .collect();
+        assert_eq!(
+            recorded,
+            [("AUTH:***", "OK"), ("ON", "OK"), ("STATUS", "STATUS")]
+        );
+    }
+
+    fn info_addresses(addr: SocketAddr) -> Vec<String> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let response = request(&mut stream, "INFO").parse::<Response>().unwrap();
+        let Response::Info(info) = response else {
+            panic!("Expected INFO, got {:?}", response);
+        };
+        info.addresses
+    }
+
+    #[test]
+    fn test_info_reports_version_and_location() {
+        let context = ConnectionContext {
+            location: Some("Kitchen; by the window".to_string()),
+            ..test_context(None)
+        };
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        let response = request(&mut stream, "INFO").parse::<Response>().unwrap();
+        let Response::Info(info) = response else {
+            panic!("Expected INFO, got {:?}", response);
+        };
+        assert_eq!(info.firmware, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.build, GIT_HASH.unwrap_or_default());
+        assert_eq!(info.location, "Kitchen; by the window");
+
+        let line = version_line(Some("Kitchen"));
+        assert!(line.starts_with(&format!("smart_socket_server {}", info.firmware)));
+        assert!(line.ends_with(", location: Kitchen"));
+    }
+
+    #[test]
+    fn test_banner_is_sent_on_connect() {
+        let context = ConnectionContext {
+            banner: true,
+            ..test_context(None)
+        };
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        let banner = read_message(&mut stream)
+            .unwrap()
+            .parse::<Response>()
+            .unwrap();
+        let Response::Info(info) = banner else {
+            panic!("Expected INFO banner, got {:?}", banner);
+        };
+        assert_eq!(info.name, "Test Socket");
+        assert_eq!(info.protocol, PROTOCOL_VERSION);
+        assert!(info.capabilities.contains(&"PULSE".to_string()));
+        assert!(!info.capabilities.contains(&"LEVEL".to_string()));
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+    }
+
+    #[test]
+    fn test_banner_respects_acl() {
+        let context = ConnectionContext {
+            banner: true,
+            runtime: runtime_with_acl("127.0.0.1=STATUS", Policy::Allow),
+            ..test_context(None)
+        };
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+    }
+
+    #[test]
+    fn test_binary_responses_after_negotiation() {
+        let mut stream = TcpStream::connect(spawn_with_context(test_context(None))).unwrap();
+        assert_eq!(
+            request(&mut stream, binfmt::NEGOTIATE),
+            "OK:Binary responses"
+        );
+        let send = |stream: &mut TcpStream, command: &str| {
+            stream
+                .write_all(&serialize_message(command).unwrap())
+                .unwrap()
+        };
+        send(&mut stream, binfmt::NEGOTIATE_TIMED);
+        assert_eq!(
+            binfmt::read_response(&mut stream).unwrap(),
+            Response::ok("Server time")
+        );
+        send(&mut stream, "STATUS");
+        let (status, server_time) = binfmt::read_response_timed(&mut stream).unwrap();
+        assert_eq!(status, expected_status(false).parse().unwrap());
+        // Every binary response now says how long the server spent on it.
+        assert!(server_time.is_some_and(|time| time < Duration::from_secs(5)));
+        send(&mut stream, binfmt::NEGOTIATE);
+        assert!(matches!(
+            binfmt::read_response(&mut stream),
+            Ok(Response::Error(_))
+        ));
+
+        // Only the first frame negotiates.
+        let mut stream = TcpStream::connect(spawn_with_context(test_context(None))).unwrap();
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+        assert!(request(&mut stream, binfmt::NEGOTIATE).starts_with("ERROR:"));
+        assert!(request(&mut stream, binfmt::NEGOTIATE_TIMED).starts_with("ERROR:"));
+    }
+
+    #[test]
+    fn test_binary_client_without_server_time() {
+        // A client from before the server time only knows the untimed tags.
+        let mut stream = TcpStream::connect(spawn_with_context(test_context(None))).unwrap();
+        assert_eq!(
+            request(&mut stream, binfmt::NEGOTIATE),
+            "OK:Binary responses"
+        );
+        for (command, expected) in [
+            ("STATUS", expected_status(false).parse().unwrap()),
+            ("PING", Response::Pong),
+            ("ON", Response::ok("Socket turned on")),
+        ] {
+            stream
+                .write_all(&serialize_message(command).unwrap())
+                .unwrap();
+            let mut len = [0; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut frame).unwrap();
+            assert_eq!(frame[0] & binfmt::TIMED, 0, "{}", command);
+            assert_eq!(binfmt::decode(&frame).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_port_zero_reports_actual_port() {
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, test_context(None)).unwrap();
+        let addr = server.local_addr();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(info_addresses(addr), [addr.to_string()]);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_port_in_use_without_fallback_fails() {
+        let blocker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = blocker.local_addr().unwrap();
+        let Err(error) = run_server(&[address.into()], false, test_context(None)) else {
+            panic!("Expected the bind to fail");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert!(error.to_string().contains(&address.to_string()));
+    }
+
+    #[test]
+    fn test_port_fallback_selects_next_free_port() {
+        let blocker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let blocked = blocker.local_addr().unwrap();
+        let expected = (1..=PORT_FALLBACK_ATTEMPTS)
+            .map(|offset| SocketAddr::new(blocked.ip(), blocked.port() + offset))
+            .find(|addr| TcpListener::bind(addr).is_ok())
+            .unwrap();
+
+        let server = run_server(&[blocked.into()], true, test_context(None)).unwrap();
+        assert_eq!(server.local_addr(), expected);
+        assert_eq!(info_addresses(expected), [expected.to_string()]);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_idle_server_shuts_down_promptly() {
+        let server = run_server(&listen(&["127.0.0.1:0"]), false, test_context(None)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let started = Instant::now();
+        server.shutdown().unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed < Duration::from_secs(1), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_read_only_refuses_every_change() {
+        let context = ConnectionContext {
+            read_only: true,
+            ..test_context(None)
+        };
+        // Set up behind the server's back: on, and locked so UNLOCK has something to clear.
+        context
+            .device
+            .call(TIMEOUT, |device| device.turn_on())
+            .unwrap()
+            .unwrap();
+        context
+            .child_lock
+            .lock()
+            .unwrap()
+            .lock(None, context.clock.system_time());
+        let before = execute(Command::GetStatus, &context, TEST_CLIENT);
+
+        for command in [
+            Command::TurnOn,
+            Command::TurnOff,
+            Command::SetName("Hall".to_string()),
+            Command::SetLevel(50),
+            Command::Pulse(50),
+            Command::ResetTrip,
+            Command::Lock(None),
+            Command::Unlock,
+        ] {
+            let kind = command.kind();
+            assert_eq!(
+                execute(command, &context, TEST_CLIENT),
+                Response::error(
+                    ErrorCode::ReadOnly,
+                    &format!("{} is refused: the socket is read-only", kind)
+                )
+            );
+        }
+        assert_eq!(execute(Command::GetStatus, &context, TEST_CLIENT), before);
+        assert!(is_on(&context));
+        assert!(context.is_locked());
+        assert!(lock_or_recover(&context.last_change, "last change").is_none());
+        assert!(matches!(
+            execute(Command::GetStats, &context, TEST_CLIENT),
+            Response::Stats(_)
+        ));
+
+        let Response::Info(info) = execute(Command::GetInfo, &context, TEST_CLIENT) else {
+            panic!("INFO must be answered");
+        };
+        assert_eq!(info.name, "Test Socket");
+        assert!(info.read_only);
+        assert_eq!(
+            info.capabilities,
+            ["STATUS", "INFO", "PING", "AUTH", "STATS", "RELOAD"]
+        );
+
+        let writable = test_context(None);
+        let Response::Info(info) = execute(Command::GetInfo, &writable, TEST_CLIENT) else {
+            panic!("INFO must be answered");
+        };
+        assert!(!info.read_only);
+        assert!(info.capabilities.contains(&"ON".to_string()));
+    }
+
+    fn usage_stats(context: &ConnectionContext) -> (u64, u64) {
+        match execute(Command::GetStats, context, TEST_CLIENT) {
+            Response::Stats(stats) => (stats.switches, stats.on_secs),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_on_time_follows_the_clock() {
+        let (context, clock) = mock_clock_context();
+        execute(Command::TurnOn, &context, TEST_CLIENT);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(usage_stats(&context), (1, 90));
+        execute(Command::TurnOff, &context, TEST_CLIENT);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(usage_stats(&context), (2, 90));
+    }
+
+    #[test]
+    fn test_usage_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            state_file: Some(dir.path().join("state.json")),
+            ..Default::default()
+        };
+        let context = ConnectionContext::from_config(&config).unwrap();
+        for command in [Command::TurnOn, Command::TurnOn, Command::TurnOff] {
+            execute(command, &context, TEST_CLIENT);
+        }
+        // Repeating ON is not a transition.
+        assert_eq!(usage_stats(&context), (2, 0));
+        execute(Command::TurnOn, &context, TEST_CLIENT);
+        context.persist_usage();
+
+        let restarted = ConnectionContext::from_config(&config).unwrap();
+        assert!(is_on(&restarted));
+        assert_eq!(usage_stats(&restarted), (3, 0));
+        execute(Command::TurnOff, &restarted, TEST_CLIENT);
+        assert_eq!(usage_stats(&restarted).0, 4);
+    }
+
+    fn last_change(context: &ConnectionContext) -> (Option<u64>, String) {
+        match execute(Command::GetInfo, context, TEST_CLIENT) {
+            Response::Info(info) => (info.last_change, info.last_actor),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_last_change_follows_transitions_and_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            state_file: Some(dir.path().join("state.json")),
+            ..Default::default()
+        };
+        let client = |port| ClientLabel {
+            id: u64::from(port),
+            peer: Peer::Addr(SocketAddr::from(([192, 168, 1, 20], port))),
+        };
+        let context = ConnectionContext::from_config(&config).unwrap();
+        assert_eq!(last_change(&context), (None, String::new()));
+
+        let started = epoch_secs(SystemTime::now());
+        execute(Command::TurnOn, &context, client(4001));
+        let (at, actor) = last_change(&context);
+        assert!(at.unwrap() >= started);
+        assert_eq!(actor, "192.168.1.20:4001");
+
+        // Neither a repeated ON nor a refused command is a change.
+        execute(Command::TurnOn, &context, client(4002));
+        execute(Command::SetName(String::new()), &context, client(4002));
+        execute(Command::SetLevel(50), &context, client(4002));
+        assert_eq!(last_change(&context).1, "192.168.1.20:4001");
+
+        execute(Command::SetName("Hall".to_string()), &context, client(4003));
+        assert_eq!(last_change(&context).1, "192.168.1.20:4003");
+        execute(Command::TurnOff, &context, client(4004));
+        let before_restart = last_change(&context);
+        assert_eq!(before_restart.1, "192.168.1.20:4004");
+
+        let restarted = ConnectionContext::from_config(&config).unwrap();
+        assert_eq!(last_change(&restarted), before_restart);
+        let saved = lock_or_recover(&restarted.last_change, "last change").clone();
+        assert_eq!(saved.unwrap().command.to_string(), "OFF");
+    }
+
+    #[test]
+    fn test_simulated_device_follows_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script.txt");
+        fs::write(&script, "ERROR:E_INTERNAL:Relay stuck\n-\nSTATUS:OFF:0\n").unwrap();
+        let config = ServerConfig {
+            simulation: Some(SimulationConfig {
+                script: Some(script),
+                seed: Some(1),
+                ..Default::default()
+            }),
+            banner: false,
+            ..Default::default()
+        };
+        let context = ConnectionContext::from_config(&config).unwrap();
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&["ON", "ON", "STATUS", "STATUS"])),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+        // The forced STATUS contradicts the device; the script wins, then the device answers.
+        assert_eq!(
+            duplex.output,
+            framed(&[
+                "ERROR:E_INTERNAL:Relay stuck",
+                "OK:Socket turned on",
+                "STATUS:OFF:0",
+                "STATUS:ON:3500",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_preflight_reports_missing_simulation_script() {
+        let config = ServerConfig {
+            addresses: listen(&["127.0.0.1:0"]),
+            simulation: Some(SimulationConfig {
+                script: Some(PathBuf::from("/nonexistent/script.txt")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], PreflightError::FileUnreadable { .. }));
+    }
+
+    #[test]
+    fn test_preflight_in_reverse_mode_skips_listen_addresses() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ServerConfig {
+            addresses: vec![taken.local_addr().unwrap().into()],
+            controller_address: Some(taken.local_addr().unwrap().into()),
+            ..Default::default()
+        };
+        assert!(preflight(&config).is_ok());
+
+        let config = ServerConfig {
+            controller_address: Some(Address::Host {
+                host: "nonexistent.invalid".to_string(),
+                port: 9000,
+            }),
+            ..config
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], PreflightError::InvalidAddress { .. }));
+    }
+
+    #[test]
+    fn test_reverse_mode_serves_the_controller_and_redials() {
+        let controller = TcpListener::bind("127.0.0.1:0").unwrap();
+        let device = run_reverse(controller.local_addr().unwrap().into(), test_context(None));
+
+        let (mut stream, _) = controller.accept().unwrap();
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        drop(stream);
+
+        // The dropped connection is dialled again after the first backoff.
+        let (mut stream, _) = controller.accept().unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+
+        let started = Instant::now();
+        device.shutdown().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(read_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_reverse_mode_stops_promptly_while_backing_off() {
+        let unreachable = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let device = run_reverse(unreachable.into(), test_context(None));
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        device.shutdown().unwrap();
+        assert!(started.elapsed() < REDIAL_BACKOFF);
+    }
+
+    #[test]
+    fn test_client_without_peer_address_is_served() {
+        let mut context = test_context(None);
+        // No rule can match a peer without an address, so the default policy applies.
+        context.runtime = runtime_with_acl("127.0.0.1=STATUS", Policy::Allow);
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&["STATUS", "ON"])),
+            output: Vec::new(),
+        };
+        let client = ClientLabel::of(42, &duplex);
+        assert_eq!(client.to_string(), "#42 unknown");
+
+        serve_client(&mut duplex, client, &context).unwrap();
+        assert_eq!(
+            duplex.output,
+            framed(&[&expected_status(false), "OK:Socket turned on"])
+        );
+    }
+
+    #[test]
+    fn test_state_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut context = test_context(None);
+        context.state_file = Some(path.clone());
+
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&["ON"])),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+        let mut saved = persistence::load(&path).unwrap().unwrap();
+        let last_change = saved.last_change.take().unwrap();
+        assert_eq!(
+            (last_change.peer.as_str(), last_change.command.as_str()),
+            ("unknown", "ON")
+        );
+        assert_eq!(
+            saved,
+            PersistedState {
+                is_on: true,
+                name: Some("Test Socket".to_string()),
+                level: None,
+                switch_count: 1,
+                total_on_ms: 0,
+                last_change: None,
+                lock: None,
+            }
+        );
+
+        let mut restored = Socket::new("Test Socket", 1000).unwrap();
+        restore_state(&mut restored, &path);
+        assert!(restored.is_on());
+    }
+
+    #[test]
+    fn test_rebuild_replaces_a_wedged_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut context = test_context(None);
+        context.state_file = Some(dir.path().join("state.json"));
+        assert_eq!(replies_to(&context, &["ON"]), ["OK:Socket turned on"]);
+
+        let (release, wedged) = std::sync::mpsc::channel::<()>();
+        let stuck = context.device.call(Duration::from_millis(10), move |_| {
+            let _ = wedged.recv();
+        });
+        assert!(stuck.is_err());
+
+        // The fresh device comes back on from the state file and takes commands.
+        context.rebuild_device().unwrap();
+        assert_eq!(
+            replies_to(&context, &["STATUS", "OFF", "STATUS"]),
+            [
+                expected_status(true),
+                "OK:Socket turned off".to_string(),
+                expected_status(false)
+            ]
+        );
+        drop(release);
+    }
+
+    #[test]
+    fn test_level_on_plain_socket_is_unsupported() {
+        assert_eq!(
+            run_script(framed(&["LEVEL:50"])),
+            framed(&["ERROR:E_UNSUPPORTED:Device does not support levels"])
+        );
+    }
+
+    #[test]
+    fn test_dimmer_level_is_applied_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut context = test_context(None);
+        context.device = Arc::new(DeviceOwner::spawn(
+            DeviceType::Dimmer.build(Socket::new("Test Socket", 1000).unwrap()),
+        ));
+        context.state_file = Some(path.clone());
+
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&["ON", "LEVEL:50", "STATUS", "LEVEL:101"])),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+
+        let mut reference = Socket::new("Test Socket", 1000).unwrap();
+        reference.turn_on();
+        let mut output = io::Cursor::new(duplex.output);
+        assert_eq!(read_message(&mut output).unwrap(), "OK:Socket turned on");
+        assert_eq!(read_message(&mut output).unwrap(), "LEVEL:50");
+        assert_eq!(
+            read_message(&mut output).unwrap(),
+            format!("STATUS:ON:{}", reference.get_power() / 2)
+        );
+        assert!(read_message(&mut output)
+            .unwrap()
+            .starts_with("ERROR:Invalid command"));
+
+        let mut restored = DeviceType::Dimmer.build(Socket::new("Test Socket", 1000).unwrap());
+        restore_state(restored.as_mut(), &path);
+        assert_eq!(restored.level(), Some(50));
+    }
+
+    fn on_power() -> u32 {
+        let mut reference = Socket::new("Test Socket", 1000).unwrap();
+        reference.turn_on();
+        reference.get_power()
+    }
+
+    #[test]
+    fn test_overload_trips_socket_until_reset() {
+        let power = on_power();
+        let mut context = test_context(None);
+        context.breaker = Arc::new(Mutex::new(Breaker::new(Some(power - 1))));
+
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&[
+                "ON", "STATUS", "ON", "PULSE:50", "RESET", "RESET",
+            ])),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+
+        let mut output = io::Cursor::new(duplex.output);
+        assert_eq!(
+            read_message(&mut output).unwrap(),
+            format!(
+                "ERROR:E_TRIPPED:{}W exceeds the {}W limit, socket turned off",
+                power,
+                power - 1
+            )
+        );
+        assert_eq!(read_message(&mut output).unwrap(), "STATUS:OFF:0:TRIPPED");
+        for _ in 0..2 {
+            assert!(read_message(&mut output)
+                .unwrap()
+                .ends_with("limit; send RESET first"));
+        }
+        assert_eq!(read_message(&mut output).unwrap(), "OK:Trip reset");
+        assert_eq!(
+            read_message(&mut output).unwrap(),
+            "OK:Socket was not tripped"
+        );
+        assert!(!is_on(&context));
+        assert_eq!(
+            context.breaker.lock().unwrap().events().count(),
+            1,
+            "Refused commands must not record new trips"
+        );
+    }
+
+    #[test]
+    fn test_draw_at_limit_does_not_trip() {
+        let mut context = test_context(None);
+        context.breaker = Arc::new(Mutex::new(Breaker::new(Some(on_power()))));
+        let mut stream = TcpStream::connect(spawn_with_context(context)).unwrap();
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(true));
+    }
+
+    #[test]
+    fn test_lock_refuses_switching_but_not_queries() {
+        let context = test_context(None);
+        let mut stream = TcpStream::connect(spawn_with_context(context.clone())).unwrap();
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert_eq!(request(&mut stream, "LOCK"), "OK:Socket locked");
+        for command in ["OFF", "ON", "PULSE:50"] {
+            assert_eq!(
+                request(&mut stream, command),
+                "ERROR:E_LOCKED:Socket is locked; send UNLOCK first"
+            );
+        }
+        assert_eq!(
+            request(&mut stream, "STATUS"),
+            format!("{}:LOCKED", expected_status(true))
+        );
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+        assert_eq!(request(&mut stream, "SET_NAME:Hall"), "OK:Socket renamed");
+        assert!(is_on(&context));
+
+        assert_eq!(request(&mut stream, "UNLOCK"), "OK:Socket unlocked");
+        assert_eq!(request(&mut stream, "UNLOCK"), "OK:Socket was not locked");
+        assert_eq!(request(&mut stream, "OFF"), "OK:Socket turned off");
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+    }
+
+    #[test]
+    fn test_timed_lock_expires() {
+        let (context, clock) = mock_clock_context();
+        assert_eq!(
+            execute(Command::Lock(Some(30)), &context, TEST_CLIENT),
+            Response::ok("Socket locked for 30 min")
+        );
+        let Response::Error(refusal) = execute(Command::TurnOn, &context, TEST_CLIENT) else {
+            panic!("ON must be refused while locked");
+        };
+        assert_eq!(
+            refusal,
+            "E_LOCKED:Socket is locked for 30 more min; send UNLOCK to switch it now"
+        );
+
+        clock.advance(Duration::from_secs(29 * 60 + 1));
+        assert_eq!(
+            execute(Command::TurnOn, &context, TEST_CLIENT),
+            Response::Error(
+                "E_LOCKED:Socket is locked for 1 more min; send UNLOCK to switch it now"
+                    .to_string()
+            )
+        );
+        clock.advance(Duration::from_secs(59));
+        assert!(!context.is_locked());
+        assert_eq!(
+            execute(Command::TurnOn, &context, TEST_CLIENT),
+            Response::ok("Socket turned on")
+        );
+    }
+
+    #[test]
+    fn test_lock_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            state_file: Some(dir.path().join("state.json")),
+            ..Default::default()
+        };
+        let context = ConnectionContext::from_config(&config).unwrap();
+        execute(Command::Lock(Some(30)), &context, TEST_CLIENT);
+        let saved = persistence::load(config.state_file.as_ref().unwrap())
+            .unwrap()
+            .unwrap();
+        let expected = epoch_secs(SystemTime::now()) + 30 * 60;
+        assert!((expected - 1..=expected).contains(&saved.lock.unwrap()));
+
+        let restarted = ConnectionContext::from_config(&config).unwrap();
+        assert!(matches!(
+            execute(Command::TurnOn, &restarted, TEST_CLIENT),
+            Response::Error(e) if e.starts_with("E_LOCKED:")
+        ));
+        execute(Command::Unlock, &restarted, TEST_CLIENT);
+
+        let unlocked = ConnectionContext::from_config(&config).unwrap();
+        assert!(!unlocked.is_locked());
+        assert_eq!(
+            execute(Command::TurnOn, &unlocked, TEST_CLIENT),
+            Response::ok("Socket turned on")
+        );
+    }
+
+    #[test]
+    fn test_rename_is_reported_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut context = test_context(None);
+        context.state_file = Some(path.clone());
+
+        let mut duplex = Duplex {
+            input: io::Cursor::new(framed(&["SET_NAME:Living Room", "INFO"])),
+            output: Vec::new(),
+        };
+        handle_client(&mut duplex, TEST_CLIENT, &context).unwrap();
+
+        let mut output = io::Cursor::new(duplex.output);
+        assert_eq!(read_message(&mut output).unwrap(), "OK:Socket renamed");
+        assert!(read_message(&mut output)
+            .unwrap()
+            .starts_with("INFO:name=Living Room;"));
+
+        let mut restored = Socket::new("Test Socket", 1000).unwrap();
+        assert_eq!(
+            restore_state(&mut restored, &path).and_then(|state| state.name),
+            Some("Living Room".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_names_are_rejected() {
+        let long_name = format!("SET_NAME:{}", "x".repeat(65));
+        let commands = [
+            "SET_NAME:",
+            "SET_NAME:Room:1",
+            &long_name,
+            // Control characters fail the frame before the name is looked at.
+            "SET_NAME:Room\u{7}",
+            "INFO",
+        ];
+
+        let mut output = io::Cursor::new(run_script(framed(&commands)));
+        for _ in 0..3 {
+            assert!(read_message(&mut output)
+                .unwrap()
+                .starts_with("ERROR:E_INVALID_ARGUMENT:"));
+        }
+        assert_eq!(
+            read_message(&mut output).unwrap(),
+            "ERROR:Invalid command: SET_NAME:Room\\u{7} (contains control characters)"
+        );
+        assert!(read_message(&mut output)
+            .unwrap()
+            .starts_with("INFO:name=Test Socket;"));
+    }
+
+    #[test]
+    fn test_acl_restricts_commands_by_peer_address() {
+        let mut context = test_context(None);
+        context.runtime = runtime_with_acl("127.0.0.1=STATUS,INFO", Policy::Allow);
+        let addr = spawn_with_context(context.clone());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            request(&mut stream, "ON"),
+            "ERROR:E_FORBIDDEN:ON is not permitted from 127.0.0.1/32"
+        );
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+        assert!(!is_on(&context));
+        assert_eq!(context.stats.errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_reload_applies_to_open_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.conf");
+        fs::write(&path, "SMART_SOCKET_ACL=127.0.0.1=STATUS,RELOAD\n").unwrap();
+        let config = ServerConfig::builder()
+            .runtime(RuntimeConfig::from_settings(&Settings::load(&path).unwrap()).unwrap())
+            .config_file(&path)
+            .build()
+            .unwrap();
+        let context = ConnectionContext::from_config(&config).unwrap();
+        let mut stream = TcpStream::connect(spawn_with_context(context.clone())).unwrap();
+        assert!(request(&mut stream, "ON").starts_with("ERROR:E_FORBIDDEN:"));
+
+        // A file that fails to parse leaves the running settings alone.
+        fs::write(
+            &path,
+            "SMART_SOCKET_ACL=127.0.0.1=*\nSMART_SOCKET_MAX_ERRORS=lots\n",
+        )
+        .unwrap();
+        assert!(request(&mut stream, "RELOAD")
+            .starts_with("ERROR:E_INTERNAL:Invalid SMART_SOCKET_MAX_ERRORS 'lots'"));
+        assert!(request(&mut stream, "ON").starts_with("ERROR:E_FORBIDDEN:"));
+
+        // The address only changes on restart; the ACL and limit apply right away.
+        fs::write(
+            &path,
+            "SMART_SOCKET_ACL=127.0.0.1=STATUS,ON\nSMART_SOCKET_OVERLOAD_LIMIT=100\n\
+             SMART_SOCKET_ADDRESS=0.0.0.0:9090\n",
+        )
+        .unwrap();
+        assert_eq!(request(&mut stream, "RELOAD"), "OK:Configuration reloaded");
+        assert_eq!(
+            request(&mut stream, "ON"),
+            "ERROR:E_TRIPPED:3500W exceeds the 100W limit, socket turned off"
+        );
+        assert!(request(&mut stream, "RELOAD").starts_with("ERROR:E_FORBIDDEN:"));
+
+        // SIGHUP takes the same path without a connection.
+        fs::write(&path, "").unwrap();
+        context.reload().unwrap();
+        assert_eq!(request(&mut stream, "RELOAD"), "OK:Configuration reloaded");
+        assert!(test_context(None).reload().is_err());
+    }
+
+    #[test]
+    fn test_acl_default_policy_applies_to_unmatched_peers() {
+        let mut context = test_context(None);
+        context.runtime = runtime_with_acl("192.168.1.10=*", Policy::Deny);
+        let addr = spawn_with_context(context);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert!(request(&mut stream, "STATUS").starts_with("ERROR:E_FORBIDDEN:"));
+        assert!(request(&mut stream, "PING").starts_with("ERROR:E_FORBIDDEN:"));
+    }
+
+    #[test]
+    fn test_builder_defaults_and_overrides() {
+        let config = ServerConfig::builder().build().unwrap();
+        assert_eq!(config.addresses, ServerConfig::default().addresses);
+        assert_eq!(config.socket_name, "Kitchen Socket");
+        assert!(config.banner);
+
+        let config = ServerConfig::builder()
+            .address("0.0.0.0:9000")
+            .socket_name("Hall Socket")
+            .auth_token("secret")
+            .write_timeout(Duration::from_secs(2))
+            .banner(false)
+            .build()
+            .unwrap();
+        assert_eq!(config.addresses, listen(&["0.0.0.0:9000"]));
+        assert_eq!(config.socket_name, "Hall Socket");
+        assert_eq!(config.auth_token.as_deref(), Some("secret"));
+        assert_eq!(config.write_timeout, Duration::from_secs(2));
+        assert!(!config.banner);
+
+        // In reverse mode there is nothing to listen on.
+        let config = ServerConfig::builder()
+            .addresses(Vec::new())
+            .controller_address("controller.lan:7000")
+            .build()
+            .unwrap();
+        assert!(config.addresses.is_empty());
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_values() {
+        let error = |builder: ServerConfigBuilder| builder.build().unwrap_err();
+        assert_eq!(
+            error(ServerConfig::builder().addresses(Vec::new())),
+            ConfigError::NoAddresses
+        );
+        assert_eq!(
+            error(ServerConfig::builder().address("[::1]")),
+            ConfigError::MissingPort("[::1]".to_string())
+        );
+        assert!(matches!(
+            error(ServerConfig::builder().socket_name("")),
+            ConfigError::InvalidValue {
+                field: "socket_name",
+                ..
+            }
+        ));
+        assert_eq!(
+            error(ServerConfig::builder().write_timeout(Duration::ZERO)),
+            ConfigError::ZeroDuration("write_timeout")
+        );
+        let simulation = SimulationConfig {
+            failure_rate: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(
+            error(ServerConfig::builder().simulation(simulation)).to_string(),
+            "Invalid failure_rate: 1.5 is not between 0 and 1"
+        );
+        assert_eq!(
+            error(ServerConfig::builder().device_timeout(Duration::ZERO)),
+            ConfigError::ZeroDuration("device_timeout")
+        );
+        let watchdog = WatchdogConfig {
+            stall_timeout: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            error(ServerConfig::builder().watchdog(Some(watchdog))),
+            ConfigError::ZeroDuration("stall_timeout")
+        );
+        let keepalive = KeepaliveConfig {
+            interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            error(ServerConfig::builder().keepalive(Some(keepalive))),
+            ConfigError::ZeroDuration("keepalive_interval")
+        );
+        let keepalive = KeepaliveConfig {
+            count: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            error(ServerConfig::builder().keepalive(Some(keepalive))),
+            ConfigError::InvalidValue {
+                field: "keepalive_count",
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(ServerConfig::builder().psk("00ff")),
+            ConfigError::InvalidValue { field: "psk", .. }
+        ));
+    }
+
+    #[test]
+    fn test_preflight_accepts_valid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            addresses: listen(&["127.0.0.1:0"]),
+            state_file: Some(dir.path().join("state.json")),
+            ..Default::default()
+        };
+        assert!(preflight(&config).is_ok());
+    }
+
+    #[test]
+    fn test_device_rejection_names_the_setting() {
+        let config = ServerConfig {
+            socket_power: 0,
+            ..Default::default()
+        };
+        let errors = validate_device(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("Device rejected socket_power 0: "));
+        let Err(e) = ConnectionContext::from_config(&config) else {
+            panic!("a socket without power must not start");
+        };
+        assert_eq!(e.to_string(), errors[0].to_string());
+
+        assert!(validate_device(&ServerConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_preflight_reports_occupied_port() {
+        let blocker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ServerConfig {
+            addresses: vec![blocker.local_addr().unwrap().into()],
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], PreflightError::BindFailed { .. }));
+    }
+
+    #[test]
+    fn test_preflight_reports_duplicate_addresses() {
+        let free_port: Address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .into();
+        let config = ServerConfig {
+            addresses: vec![free_port.clone(), free_port],
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], PreflightError::BindFailed { .. }));
+    }
+
+    #[test]
+    fn test_preflight_reports_all_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            addresses: Vec::new(),
+            state_file: Some(dir.path().join("missing").join("state.json")),
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], PreflightError::InvalidAddress { .. }));
+        assert!(matches!(errors[1], PreflightError::PathNotWritable { .. }));
+    }
+
+    #[test]
+    fn test_preflight_rejects_file_as_directory() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = ServerConfig {
+            addresses: listen(&["127.0.0.1:0"]),
+            state_file: Some(file.path().join("state.json")),
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert!(matches!(errors[0], PreflightError::PathNotWritable { .. }));
+    }
+
+    #[test]
+    fn test_no_token_configured_allows_everything() {
+        let mut stream = TcpStream::connect(spawn_server(None)).unwrap();
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert_eq!(
+            request(&mut stream, "AUTH:anything"),
+            "OK:Authentication not required"
+        );
+    }
+
+    #[test]
+    fn test_missing_token_allows_read_only_commands() {
+        let mut stream = TcpStream::connect(spawn_server(Some("secret"))).unwrap();
+        assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+        let info = request(&mut stream, "INFO");
+        match Response::from_str(&info).unwrap() {
+            Response::Info(info) => {
+                assert_eq!(info.name, "Test Socket");
+                assert_eq!(info.power, 1000);
+                assert_eq!(info.firmware, env!("CARGO_PKG_VERSION"));
+            }
+            other => panic!("Unexpected response: {:?}", other),
+        }
+        assert_eq!(request(&mut stream, "PING"), "PONG");
+        assert_eq!(
+            request(&mut stream, "ON"),
+            "ERROR:E_UNAUTHORIZED:Authentication required"
+        );
+        assert_eq!(
+            request(&mut stream, "OFF"),
+            "ERROR:E_UNAUTHORIZED:Authentication required"
+        );
+        assert_eq!(
+            request(&mut stream, "SET_NAME:Hall"),
+            "ERROR:E_UNAUTHORIZED:Authentication required"
+        );
+        for command in ["LOCK", "UNLOCK"] {
+            assert_eq!(
+                request(&mut stream, command),
+                "ERROR:E_UNAUTHORIZED:Authentication required"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrong_token_is_rejected() {
+        let mut stream = TcpStream::connect(spawn_server(Some("secret"))).unwrap();
+        assert_eq!(
+            request(&mut stream, "AUTH:guess"),
+            "ERROR:E_UNAUTHORIZED:Invalid token"
+        );
+        assert_eq!(
+            request(&mut stream, "ON"),
+            "ERROR:E_UNAUTHORIZED:Authentication required"
+        );
+    }
+
+    #[test]
+    fn test_full_access_after_auth() {
+        let mut stream = TcpStream::connect(spawn_server(Some("secret"))).unwrap();
+        assert_eq!(request(&mut stream, "AUTH:secret"), "OK:Authenticated");
+        assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+        assert!(request(&mut stream, "STATUS").starts_with("STATUS:ON:"));
+        assert_eq!(request(&mut stream, "OFF"), "OK:Socket turned off");
+    }
+
+    #[test]
+    fn test_failed_auth_revokes_access() {
+        let context = test_context(Some("secret"));
+        let replies = replies_to(&context, &["AUTH:secret", "ON", "AUTH:guess", "OFF"]);
+        assert_eq!(
+            replies,
+            [
+                "OK:Authenticated",
+                "OK:Socket turned on",
+                "ERROR:E_UNAUTHORIZED:Invalid token",
+                "ERROR:E_UNAUTHORIZED:Authentication required",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_auth_token_is_not_logged() {
+        let context = test_context(Some("secret"));
+        let lines = logged_by(|| {
+            replies_to(
+                &context,
+                &["AUTH:secret", "auth:wrong-secret", "AUTH:sec\u{1}ret"],
+            );
+        });
+        assert!(
+            lines.contains(&"Received command from #1 unknown: AUTH:***".to_string()),
+            "{:?}",
+            lines
+        );
+        assert!(lines.contains(&"Error processing command: malformed AUTH:***".to_string()));
+        assert!(
+            lines.iter().all(|line| !line.contains("secret")),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    mod tls {
+        use super::*;
+        use smart_socket_protocol::tls::{client_config, connect, server_config};
+        use std::fs;
+
+        struct TestCerts {
+            _dir: tempfile::TempDir,
+            cert: PathBuf,
+            key: PathBuf,
+        }
+
+        fn generate_certs() -> TestCerts {
+            let certified =
+                rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            let dir = tempfile::tempdir().unwrap();
+            let cert = dir.path().join("cert.pem");
+            let key = dir.path().join("key.pem");
+            fs::write(&cert, certified.cert.pem()).unwrap();
+            fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+            TestCerts {
+                _dir: dir,
+                cert,
+                key,
+            }
+        }
+
+        #[test]
+        fn test_command_matrix_over_tls() {
+            let certs = generate_certs();
+            let mut context = test_context(Some("secret"));
+            context.tls = Some(server_config(&certs.cert, &certs.key).unwrap());
+            let addr = spawn_with_context(context);
+
+            let tcp = TcpStream::connect(addr).unwrap();
+            let mut stream =
+                connect(client_config(&certs.cert).unwrap(), "localhost", tcp).unwrap();
+
+            assert_eq!(request(&mut stream, "PING"), "PONG");
+            assert_eq!(request(&mut stream, "AUTH:secret"), "OK:Authenticated");
+            assert_eq!(request(&mut stream, "ON"), "OK:Socket turned on");
+            assert!(request(&mut stream, "STATUS").starts_with("STATUS:ON:"));
+            assert!(request(&mut stream, "INFO").starts_with("INFO:"));
+            assert_eq!(request(&mut stream, "OFF"), "OK:Socket turned off");
+            assert_eq!(request(&mut stream, "STATUS"), expected_status(false));
+            assert!(request(&mut stream, "BOGUS").starts_with("ERROR:"));
+        }
+
+        #[test]
+        fn test_untrusted_certificate_fails_handshake() {
+            let server_certs = generate_certs();
+            let other_certs = generate_certs();
+            let mut context = test_context(None);
+            context.tls = Some(server_config(&server_certs.cert, &server_certs.key).unwrap());
+            let addr = spawn_with_context(context);
+
+            let tcp = TcpStream::connect(addr).unwrap();
+            let err = connect(client_config(&other_certs.cert).unwrap(), "localhost", tcp)
+                .err()
+                .unwrap();
+            match err {
+                ProtocolError::ConnectionError(msg) => {
+                    assert!(msg.starts_with("TLS handshake failed"), "{}", msg)
+                }
+                other => panic!("Unexpected error: {}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! A stand-in device for protocol development: no `smart_home` socket behind
+//! it, but configurable latency, injected failures and scripted replies, so
+//! client timeouts and retries can be exercised end to end.
+
+use crate::device::{Device, SwitchError};
+use crate::messages::{self, Locale};
+use crate::server::log;
+use smart_socket_protocol::{Command, ErrorCode, Response};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How the simulated device misbehaves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationConfig {
+    /// Added to every command before it is answered.
+    pub latency: Duration,
+    /// Probability in `0.0..=1.0` that a command fails with `E_INTERNAL`.
+    pub failure_rate: f64,
+    /// File of forced replies, one per command; see [`Script`].
+    pub script: Option<PathBuf>,
+    /// Seed for the failure schedule; a random one is picked (and logged) when unset.
+    pub seed: Option<u64>,
+}
+
+/// Decides which commands fail. A xorshift generator is plenty here, and the
+/// same seed always yields the same schedule.
+#[derive(Debug, Clone)]
+pub struct FailureSchedule {
+    rate: f64,
+    state: u64,
+}
+
+impl FailureSchedule {
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            // Xorshift never leaves zero, so that seed is remapped.
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// Whether the next command fails.
+    pub fn next_fails(&mut self) -> bool {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        // The top 53 bits give a uniform value in [0, 1).
+        let sample = (self.state >> 11) as f64 / (1u64 << 53) as f64;
+        sample < self.rate
+    }
+}
+
+/// One line of a [`Script`].
+// Scripts are a handful of lines, so the unboxed reply costs next to nothing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scripted {
+    /// Sent instead of whatever the device would have answered.
+    Reply(Response),
+    /// Let the command through to the device as usual.
+    PassThrough,
+}
+
+/// Forced replies, consumed one per command. Each line is a response in wire
+/// syntax, e.g. `ERROR:E_INTERNAL:Relay stuck` or `STATUS:ON:120`, or `-` to
+/// let that command through. Blank lines and lines starting with `#` are
+/// skipped. Once the script runs out, the failure rate takes over.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    steps: VecDeque<Scripted>,
+}
+
+impl Script {
+    pub fn next_step(&mut self) -> Option<Scripted> {
+        self.steps.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = VecDeque::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "-" {
+                steps.push_back(Scripted::PassThrough);
+                continue;
+            }
+            let reply = line
+                .parse()
+                .map_err(|e| format!("Script line {}: {}", number + 1, e))?;
+            steps.push_back(Scripted::Reply(reply));
+        }
+        Ok(Self { steps })
+    }
+}
+
+/// A plain on/off socket drawing its full rated power while on, answering
+/// through the script and failure schedule first.
+pub struct SimulatedSocket {
+    on: bool,
+    rated_power: u32,
+    latency: Duration,
+    failures: FailureSchedule,
+    script: Script,
+    locale: Locale,
+}
+
+impl SimulatedSocket {
+    pub fn new(
+        rated_power: u32,
+        latency: Duration,
+        failures: FailureSchedule,
+        script: Script,
+    ) -> Self {
+        Self {
+            on: false,
+            rated_power,
+            latency,
+            failures,
+            script,
+            locale: Locale::default(),
+        }
+    }
+
+    /// Language of the injected failures' messages.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Builds the device described by `config`, reading its script file.
+    pub fn from_config(rated_power: u32, config: &SimulationConfig) -> Result<Self, String> {
+        let script = match &config.script {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read simulation script {:?}: {}", path, e))?
+                .parse()
+                .map_err(|e| format!("Invalid simulation script {:?}: {}", path, e))?,
+            None => Script::default(),
+        };
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        });
+        log(&format!(
+            "Simulating the device: {}ms latency, failure rate {}, {} scripted replies, seed {}",
+            config.latency.as_millis(),
+            config.failure_rate,
+            script.len(),
+            seed
+        ));
+        Ok(Self::new(
+            rated_power,
+            config.latency,
+            FailureSchedule::new(config.failure_rate, seed),
+            script,
+        ))
+    }
+}
+
+impl Device for SimulatedSocket {
+    fn turn_on(&mut self) -> Result<(), SwitchError> {
+        self.on = true;
+        Ok(())
+    }
+
+    fn turn_off(&mut self) {
+        self.on = false;
+    }
+
+    fn is_on(&self) -> bool {
+        self.on
+    }
+
+    fn get_power(&self) -> u32 {
+        if self.on {
+            self.rated_power
+        } else {
+            0
+        }
+    }
+
+    fn intercept(&mut self, command: &Command) -> Option<Response> {
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+        match self.script.next_step() {
+            Some(Scripted::Reply(reply)) => {
+                log(&format!("Scripted reply to {}: {}", command, reply));
+                Some(reply)
+            }
+            Some(Scripted::PassThrough) => None,
+            None if self.failures.next_fails() => {
+                log(&format!("Injected failure for {}", command));
+                Some(Response::error(
+                    ErrorCode::Internal,
+                    messages::get("error.simulated", self.locale),
+                ))
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(rate: f64, seed: u64, count: usize) -> Vec<bool> {
+        let mut failures = FailureSchedule::new(rate, seed);
+        (0..count).map(|_| failures.next_fails()).collect()
+    }
+
+    #[test]
+    fn test_failure_schedule_is_reproducible() {
+        assert_eq!(schedule(0.3, 42, 200), schedule(0.3, 42, 200));
+        assert_ne!(schedule(0.3, 42, 200), schedule(0.3, 43, 200));
+        // Seed 0 still produces a schedule instead of getting stuck.
+        assert!(schedule(0.5, 0, 100).contains(&true));
+        assert!(schedule(0.5, 0, 100).contains(&false));
+    }
+
+    #[test]
+    fn test_failure_rate_bounds_and_proportion() {
+        assert!(!schedule(0.0, 7, 1000).contains(&true));
+        assert!(!schedule(1.0, 7, 1000).contains(&false));
+        assert!(!schedule(-1.0, 7, 100).contains(&true));
+        assert!(!schedule(5.0, 7, 100).contains(&false));
+
+        let failed = schedule(0.25, 7, 10_000).iter().filter(|f| **f).count();
+        assert!((2_200..2_800).contains(&failed), "{} failures", failed);
+    }
+
+    #[test]
+    fn test_script_is_consumed_before_failures() {
+        let script: Script = "# warm-up\nSTATUS:ON:120\n\n-\nERROR:E_INTERNAL:Relay stuck\n"
+            .parse()
+            .unwrap();
+        assert_eq!(script.len(), 3);
+        let mut socket =
+            SimulatedSocket::new(100, Duration::ZERO, FailureSchedule::new(1.0, 1), script);
+
+        assert_eq!(
+            socket.intercept(&Command::GetStatus),
+            Some(Response::Status {
+                is_on: true,
+                power: 120,
+                tripped: false,
+                locked: false,
+            })
+        );
+        assert_eq!(socket.intercept(&Command::TurnOn), None);
+        assert_eq!(
+            socket.intercept(&Command::TurnOff),
+            Some(Response::Error("E_INTERNAL:Relay stuck".to_string()))
+        );
+        // With the script used up, the failure rate of 1.0 applies.
+        assert_eq!(
+            socket.intercept(&Command::Ping),
+            Some(Response::error(
+                ErrorCode::Internal,
+                "Simulated device failure"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_script_line_is_reported() {
+        let err = "OK\nNOPE:1\n".parse::<Script>().unwrap_err();
+        assert!(err.starts_with("Script line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn test_latency_delays_commands() {
+        let mut socket = SimulatedSocket::new(
+            100,
+            Duration::from_millis(50),
+            FailureSchedule::new(0.0, 1),
+            Script::default(),
+        );
+        let started = std::time::Instant::now();
+        assert_eq!(socket.intercept(&Command::Ping), None);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        socket.turn_on().unwrap();
+        assert_eq!(socket.get_power(), 100);
+        socket.turn_off();
+        assert_eq!(socket.get_power(), 0);
+    }
+}
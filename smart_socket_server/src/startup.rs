@@ -0,0 +1,236 @@
+//! Building the device at startup: errors that name the setting the device
+//! rejected, and retries for backends that are not ready yet at boot.
+
+use crate::server::log;
+use smart_socket_protocol::validate_device_name;
+use std::fmt;
+use std::time::Duration;
+
+/// How often building the device is retried when it fails for a reason other
+/// than a rejected setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInit {
+    /// Attempts after the first; 0 gives up at once.
+    pub retries: u32,
+    /// Wait between attempts.
+    pub delay: Duration,
+}
+
+impl Default for DeviceInit {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The device could not be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInitError {
+    /// The setting the device rejected and its value; `None` when the device
+    /// itself failed, which is the case retries are for.
+    pub rejected: Option<(&'static str, String)>,
+    /// The device's own error message.
+    pub reason: String,
+    pub attempts: u32,
+}
+
+impl fmt::Display for DeviceInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rejected {
+            Some((field, value)) => {
+                write!(f, "Device rejected {} {}: {}", field, value, self.reason)
+            }
+            None if self.attempts > 1 => write!(
+                f,
+                "Device failed to start after {} attempts: {}",
+                self.attempts, self.reason
+            ),
+            None => write!(f, "Device failed to start: {}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for DeviceInitError {}
+
+/// The setting a device error is about: a value the protocol itself would
+/// refuse first, otherwise the one the message names. `None` if neither tells.
+pub fn rejected_field(name: &str, power: u32, reason: &str) -> Option<(&'static str, String)> {
+    let name_field = || ("socket_name", format!("{:?}", name));
+    let power_field = || ("socket_power", power.to_string());
+    if power == 0 {
+        return Some(power_field());
+    }
+    if validate_device_name(name).is_err() {
+        return Some(name_field());
+    }
+    let reason = reason.to_ascii_lowercase();
+    if reason.contains("power") {
+        Some(power_field())
+    } else if reason.contains("name") {
+        Some(name_field())
+    } else {
+        None
+    }
+}
+
+/// Builds the device with `construct`, the way `Socket::new` is called.
+/// A rejected setting fails at once, since retrying cannot fix it; any other
+/// error is retried as `init` allows, waiting with `sleep` in between.
+pub fn build_device<T, E: fmt::Display>(
+    init: &DeviceInit,
+    name: &str,
+    power: u32,
+    mut construct: impl FnMut(&str, u32) -> Result<T, E>,
+    mut sleep: impl FnMut(Duration),
+) -> Result<T, DeviceInitError> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let reason = match construct(name, power) {
+            Ok(device) => return Ok(device),
+            Err(e) => e.to_string(),
+        };
+        let rejected = rejected_field(name, power, &reason);
+        if rejected.is_some() || attempts > init.retries {
+            return Err(DeviceInitError {
+                rejected,
+                reason,
+                attempts,
+            });
+        }
+        log(&format!(
+            "Device not ready ({}), retrying in {}ms ({}/{})",
+            reason,
+            init.delay.as_millis(),
+            attempts,
+            init.retries
+        ));
+        sleep(init.delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_sleep(_: Duration) {
+        panic!("must not wait");
+    }
+
+    #[test]
+    fn test_rejected_field_mapping() {
+        let field = |name, power, reason| rejected_field(name, power, reason).map(|(f, _)| f);
+        assert_eq!(
+            rejected_field("Lamp", 0, "Power must be positive"),
+            Some(("socket_power", "0".to_string()))
+        );
+        assert_eq!(
+            rejected_field("", 100, "whatever"),
+            Some(("socket_name", "\"\"".to_string()))
+        );
+        // Values the protocol accepts fall back to the message.
+        assert_eq!(
+            field("Lamp", 5000, "power exceeds relay rating"),
+            Some("socket_power")
+        );
+        assert_eq!(field("Lamp", 100, "Name is reserved"), Some("socket_name"));
+        assert_eq!(field("Lamp", 100, "GPIO 17 busy"), None);
+    }
+
+    #[test]
+    fn test_rejected_setting_is_not_retried() {
+        let init = DeviceInit {
+            retries: 5,
+            ..Default::default()
+        };
+        let mut calls = 0;
+        let error = build_device(
+            &init,
+            "Lamp",
+            0,
+            |_, _| {
+                calls += 1;
+                Err::<(), _>("Power must be positive")
+            },
+            no_sleep,
+        )
+        .unwrap_err();
+        assert_eq!(calls, 1);
+        assert_eq!(
+            error.to_string(),
+            "Device rejected socket_power 0: Power must be positive"
+        );
+    }
+
+    #[test]
+    fn test_device_that_becomes_ready_is_retried() {
+        let init = DeviceInit {
+            retries: 3,
+            delay: Duration::from_millis(250),
+        };
+        let mut waits = Vec::new();
+        let mut calls = 0;
+        let device = build_device(
+            &init,
+            "Lamp",
+            100,
+            |name, power| {
+                calls += 1;
+                match calls {
+                    1 | 2 => Err("GPIO not exported yet"),
+                    _ => Ok((name.to_string(), power)),
+                }
+            },
+            |delay| waits.push(delay),
+        );
+        assert_eq!(device.unwrap(), ("Lamp".to_string(), 100));
+        assert_eq!(waits, [Duration::from_millis(250); 2]);
+    }
+
+    #[test]
+    fn test_retries_run_out() {
+        let init = DeviceInit {
+            retries: 2,
+            delay: Duration::ZERO,
+        };
+        let mut calls = 0;
+        let error = build_device(
+            &init,
+            "Lamp",
+            100,
+            |_, _| {
+                calls += 1;
+                Err::<(), _>("GPIO not exported yet")
+            },
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(calls, 3);
+        assert_eq!(
+            error,
+            DeviceInitError {
+                rejected: None,
+                reason: "GPIO not exported yet".to_string(),
+                attempts: 3,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Device failed to start after 3 attempts: GPIO not exported yet"
+        );
+
+        let once = build_device(
+            &DeviceInit::default(),
+            "Lamp",
+            100,
+            |_, _| Err::<(), _>("busy"),
+            no_sleep,
+        );
+        assert_eq!(
+            once.unwrap_err().to_string(),
+            "Device failed to start: busy"
+        );
+    }
+}
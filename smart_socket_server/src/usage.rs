@@ -0,0 +1,106 @@
+//! Relay wear counters: how often the socket has switched and how long it has
+//! been on in total. Both survive restarts through the state file.
+
+use std::time::{Duration, Instant};
+
+/// Lifetime usage of one device. The caller passes the time of every
+/// observation, so on-time can be tested without waiting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    switch_count: u64,
+    /// On-time of the periods that have ended.
+    total_on: Duration,
+    /// When the current on period started; `None` while off.
+    on_since: Option<Instant>,
+}
+
+impl Usage {
+    /// Counters restored from a previous run; `is_on` is the device state at `now`.
+    pub fn new(switch_count: u64, total_on: Duration, is_on: bool, now: Instant) -> Self {
+        Self {
+            switch_count,
+            total_on,
+            on_since: is_on.then_some(now),
+        }
+    }
+
+    /// Records the device state at `now`. Only a change counts as a switch, so
+    /// turning on a socket that is already on leaves the counters alone.
+    pub fn observe(&mut self, is_on: bool, now: Instant) {
+        match (self.on_since, is_on) {
+            (None, true) => self.on_since = Some(now),
+            (Some(since), false) => {
+                self.total_on += now.saturating_duration_since(since);
+                self.on_since = None;
+            }
+            _ => return,
+        }
+        self.switch_count += 1;
+    }
+
+    pub fn switch_count(&self) -> u64 {
+        self.switch_count
+    }
+
+    /// On-time up to `now`, including the current on period.
+    pub fn total_on(&self, now: Instant) -> Duration {
+        self.total_on
+            + self
+                .on_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self::new(0, Duration::ZERO, false, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_state_is_not_a_switch() {
+        let start = Instant::now();
+        let mut usage = Usage::new(0, Duration::ZERO, false, start);
+        usage.observe(false, start);
+        assert_eq!(usage.switch_count(), 0);
+
+        usage.observe(true, start);
+        usage.observe(true, start + Duration::from_secs(5));
+        assert_eq!(usage.switch_count(), 1);
+        assert_eq!(
+            usage.total_on(start + Duration::from_secs(10)),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_on_time_accumulates_across_cycles() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut usage = Usage::new(6, Duration::from_secs(100), false, start);
+        for (on, off) in [(10, 40), (60, 70), (100, 160)] {
+            usage.observe(true, at(on));
+            usage.observe(false, at(off));
+        }
+        assert_eq!(usage.switch_count(), 12);
+        assert_eq!(usage.total_on(at(500)), Duration::from_secs(200));
+
+        usage.observe(true, at(600));
+        assert_eq!(usage.total_on(at(615)), Duration::from_secs(215));
+    }
+
+    #[test]
+    fn test_restored_on_state_counts_from_restart() {
+        let start = Instant::now();
+        let usage = Usage::new(3, Duration::from_secs(60), true, start);
+        assert_eq!(usage.switch_count(), 3);
+        assert_eq!(
+            usage.total_on(start + Duration::from_secs(30)),
+            Duration::from_secs(90)
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Watchdog for a wedged device thread. A bug that leaves a device call hanging
+//! does not stop the server from accepting connections, it only makes every
+//! command time out; the watchdog notices, names the connections stuck in a
+//! command and, by default, aborts so that the service manager restarts the server.
+//! It can instead rebuild the device on a new thread and leave the stuck one behind.
+//!
+//! The device thread's progress counters are read without waiting on it, so the
+//! watchdog never blocks itself.
+//! Callers pass the time of every probe and command, which keeps the logic testable.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the watchdog does once the device thread has been stuck for the stall timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Abort the process, for a service manager to restart it.
+    #[default]
+    Abort,
+    /// Build a fresh device, restored from the state file, on a new device
+    /// thread, and abandon the stuck one; the server keeps running.
+    Rebuild,
+    /// Only log, e.g. to attach a debugger to the stuck server.
+    Log,
+}
+
+impl FromStr for WatchdogAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "abort" => Ok(WatchdogAction::Abort),
+            "rebuild" => Ok(WatchdogAction::Rebuild),
+            "log" => Ok(WatchdogAction::Log),
+            _ => Err(format!(
+                "Unknown watchdog action '{}', expected abort, rebuild or log",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// How long the device thread may stay on one job before it counts as wedged.
+    pub stall_timeout: Duration,
+    pub action: WatchdogAction,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            action: WatchdogAction::default(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// How often the device thread is probed: ten times per timeout, but no more often
+    /// than every 10ms and no less often than every second.
+    pub fn probe_interval(&self) -> Duration {
+        (self.stall_timeout / 10).clamp(Duration::from_millis(10), Duration::from_secs(1))
+    }
+}
+
+/// A command a connection has been executing for `elapsed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InFlightCommand {
+    pub connection: u64,
+    pub command: &'static str,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for InFlightCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} for {:.1}s",
+            self.connection,
+            self.command,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// The command each connection is executing, and since when.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    commands: Mutex<BTreeMap<u64, (&'static str, Instant)>>,
+}
+
+impl InFlight {
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, (&'static str, Instant)>> {
+        self.commands.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Marks `connection` as executing `command` until the guard drops, also
+    /// when the command panics.
+    pub fn begin(&self, connection: u64, command: &'static str, now: Instant) -> InFlightGuard<'_> {
+        self.lock().insert(connection, (command, now));
+        InFlightGuard {
+            in_flight: self,
+            connection,
+        }
+    }
+
+    /// Commands in flight at `now`, the longest running first.
+    pub fn snapshot(&self, now: Instant) -> Vec<InFlightCommand> {
+        let mut commands: Vec<InFlightCommand> = self
+            .lock()
+            .iter()
+            .map(|(&connection, &(command, since))| InFlightCommand {
+                connection,
+                command,
+                elapsed: now.saturating_duration_since(since),
+            })
+            .collect();
+        commands.sort_by_key(|command| std::cmp::Reverse(command.elapsed));
+        commands
+    }
+}
+
+pub struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+    connection: u64,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().remove(&self.connection);
+    }
+}
+
+/// Turns periodic probes into a verdict. The device counts as wedged once no
+/// probe for `timeout` saw progress; one that does starts over.
+#[derive(Debug)]
+pub struct StallDetector {
+    timeout: Duration,
+    blocked_since: Option<Instant>,
+    reported: bool,
+}
+
+impl StallDetector {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            blocked_since: None,
+            reported: false,
+        }
+    }
+
+    /// Feeds the result of one probe. Returns how long the device has made no
+    /// progress when that first reaches the timeout, once per stall.
+    pub fn probe(&mut self, progressed: bool, now: Instant) -> Option<Duration> {
+        if progressed {
+            self.blocked_since = None;
+            self.reported = false;
+            return None;
+        }
+        let blocked_for = now.saturating_duration_since(*self.blocked_since.get_or_insert(now));
+        if blocked_for < self.timeout || self.reported {
+            return None;
+        }
+        self.reported = true;
+        Some(blocked_for)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_in_flight_commands() {
+        let start = Instant::now();
+        let in_flight = InFlight::default();
+        let first = in_flight.begin(3, "ON", start);
+        {
+            let _second = in_flight.begin(7, "STATUS", start + SECOND * 5);
+            assert_eq!(
+                in_flight.snapshot(start + SECOND * 12),
+                [
+                    InFlightCommand {
+                        connection: 3,
+                        command: "ON",
+                        elapsed: SECOND * 12,
+                    },
+                    InFlightCommand {
+                        connection: 7,
+                        command: "STATUS",
+                        elapsed: SECOND * 7,
+                    },
+                ]
+            );
+        }
+        let snapshot = in_flight.snapshot(start + SECOND * 31);
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].to_string(), "#3 ON for 31.0s");
+        drop(first);
+        assert!(in_flight.snapshot(start).is_empty());
+    }
+
+    #[test]
+    fn test_stall_is_reported_once_after_the_timeout() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(SECOND * 30);
+        assert_eq!(detector.probe(true, start), None);
+        for secs in 0..30 {
+            assert_eq!(detector.probe(false, start + SECOND * secs), None);
+        }
+        assert_eq!(
+            detector.probe(false, start + SECOND * 30),
+            Some(SECOND * 30)
+        );
+        assert_eq!(detector.probe(false, start + SECOND * 31), None);
+
+        // Once the device makes progress again, a new stall is reported anew.
+        assert_eq!(detector.probe(true, start + SECOND * 32), None);
+        assert_eq!(detector.probe(false, start + SECOND * 40), None);
+        assert_eq!(
+            detector.probe(false, start + SECOND * 75),
+            Some(SECOND * 35)
+        );
+    }
+
+    #[test]
+    fn test_a_free_probe_resets_the_stall() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(SECOND * 30);
+        assert_eq!(detector.probe(false, start), None);
+        assert_eq!(detector.probe(true, start + SECOND * 29), None);
+        assert_eq!(detector.probe(false, start + SECOND * 30), None);
+        assert_eq!(detector.probe(false, start + SECOND * 59), None);
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!("Abort".parse(), Ok(WatchdogAction::Abort));
+        assert_eq!("log".parse(), Ok(WatchdogAction::Log));
+        assert_eq!("REBUILD".parse(), Ok(WatchdogAction::Rebuild));
+        assert!("restart".parse::<WatchdogAction>().is_err());
+
+        let config = WatchdogConfig::default();
+        assert_eq!(config.probe_interval(), SECOND);
+        let quick = WatchdogConfig {
+            stall_timeout: Duration::from_millis(50),
+            ..config
+        };
+        assert_eq!(quick.probe_interval(), Duration::from_millis(10));
+    }
+}
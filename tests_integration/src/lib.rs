@@ -0,0 +1,124 @@
+//! Helpers for the integration tests in `tests/`, which run the socket and
+//! thermometer components together in one process, each on an ephemeral
+//! loopback port.
+
+use smart_socket_client::watch::{StatusWatcher, WatchEvent};
+use smart_socket_client::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Address, Response};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long any single step of a scenario may take before the test fails.
+pub const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Port 0 on the loopback interface; the OS picks a free port.
+pub fn loopback() -> Address {
+    SocketAddr::from(([127, 0, 0, 1], 0)).into()
+}
+
+/// Starts a socket server built from `config` on an ephemeral loopback port.
+pub fn start_socket_server(
+    config: smart_socket_server::server::ServerConfigBuilder,
+) -> smart_socket_server::server::ServerHandle {
+    use smart_socket_server::server::{run_server, ConnectionContext};
+
+    let config = config
+        .address(loopback())
+        .build()
+        .expect("socket server config");
+    let context = ConnectionContext::from_config(&config).expect("socket server config");
+    run_server(&config.addresses, false, context).expect("socket server start")
+}
+
+/// A client for `addr`; `banner` says whether the server greets clients with `INFO`.
+pub fn connect(addr: SocketAddr, banner: bool) -> SmartSocketClient<ClientStream> {
+    let config = ClientConfig::builder()
+        .address(addr)
+        .banner_timeout(banner.then_some(STEP_TIMEOUT))
+        .build()
+        .expect("client config");
+    SmartSocketClient::with_config(config).expect("connect")
+}
+
+/// A thermometer server started with its own `running` flag.
+pub struct ThermometerServer {
+    handle: thermometer_server::ServerHandle,
+    running: Arc<AtomicBool>,
+}
+
+impl ThermometerServer {
+    /// Starts a server built from `config` on an ephemeral UDP port.
+    pub fn start(config: thermometer_server::ServerConfigBuilder) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let config = config
+            .address(loopback())
+            .build()
+            .expect("thermometer server config");
+        let handle =
+            thermometer_server::run_server(config, running.clone()).expect("thermometer server");
+        Self { handle, running }
+    }
+
+    pub fn handle(&self) -> &thermometer_server::ServerHandle {
+        &self.handle
+    }
+
+    /// Clears the flag and waits for every server thread to finish.
+    pub fn stop(self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.handle.join().expect("thermometer server thread");
+    }
+}
+
+/// A thermometer client sending readings from its own thread.
+pub struct ThermometerClient {
+    handle: JoinHandle<Vec<thermometer_client::DestinationStats>>,
+    running: Arc<AtomicBool>,
+}
+
+impl ThermometerClient {
+    pub fn start(config: thermometer_client::ClientConfig) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let handle = thread::spawn(move || {
+            thermometer_client::run_client(config, flag).expect("thermometer client")
+        });
+        Self { handle, running }
+    }
+
+    /// Stops the client after the reading in flight and returns its delivery counters.
+    pub fn stop(self) -> Vec<thermometer_client::DestinationStats> {
+        self.running.store(false, Ordering::SeqCst);
+        self.handle.join().expect("thermometer client thread")
+    }
+}
+
+/// Waits until `client` sees the socket switched on. `watcher` must have been
+/// started before the change could happen: every change it reports triggers a
+/// fresh `STATUS`, and one is also sent whenever it stays quiet for a second, in
+/// case the change came before its first poll.
+pub fn wait_until_on(
+    client: &mut SmartSocketClient<ClientStream>,
+    watcher: &StatusWatcher,
+) -> bool {
+    let deadline = Instant::now() + STEP_TIMEOUT;
+    loop {
+        if let Ok(Response::Status { is_on: true, .. }) = client.get_status() {
+            return true;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        match watcher
+            .events()
+            .recv_timeout(left.min(Duration::from_secs(1)))
+        {
+            Ok(WatchEvent::Changed(change)) if change.to.is_on => return true,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! The protocol conformance suite against the reference socket server, as a
+//! self-test of both.
+
+use smart_socket_protocol::conformance::{self, Check, ConformanceConfig};
+use smart_socket_protocol::Response;
+use smart_socket_server::server::ServerConfig;
+use tests_integration::{connect, start_socket_server, STEP_TIMEOUT};
+
+#[test]
+fn test_reference_server_passes_and_keeps_its_state() {
+    let server = start_socket_server(ServerConfig::builder().auth_token("conformance"));
+    let mut client = connect(server.local_addr(), true);
+    client.authenticate("conformance").unwrap();
+    client.turn_on().unwrap();
+
+    let report = conformance::run(&ConformanceConfig {
+        address: server.local_addr().into(),
+        auth_token: Some("conformance".to_string()),
+        timeout: STEP_TIMEOUT,
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.results.len(), Check::ALL.len());
+    assert!(matches!(
+        client.get_status().unwrap(),
+        Response::Status { is_on: true, .. }
+    ));
+
+    client.close().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_selected_checks_report_failures() {
+    // Without the token, switching the socket is refused.
+    let server = start_socket_server(ServerConfig::builder().auth_token("conformance"));
+    let report = conformance::run(&ConformanceConfig {
+        address: server.local_addr().into(),
+        timeout: STEP_TIMEOUT,
+        checks: vec![Check::BaseCommands, Check::Pipelining],
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(!report.passed());
+    let failure = report.results[0].outcome.as_ref().unwrap_err();
+    assert_eq!(failure.reason, "ON was answered with ERROR, expected OK");
+    assert!(String::from_utf8_lossy(&failure.observed).contains("E_UNAUTHORIZED"));
+    assert!(report.results[1].outcome.is_ok());
+    assert!(report.restored.is_ok());
+    assert!(report.to_string().starts_with("FAIL base-commands: "));
+
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,80 @@
+//! The controller façade driving a socket server and a thermometer server.
+
+use smart_home_controller::{ControllerConfig, ControllerError, SmartHomeController};
+use smart_socket_client::ClientConfig;
+use smart_socket_server::server::ServerConfig;
+use std::net::{SocketAddr, UdpSocket};
+use tests_integration::{loopback, start_socket_server, ThermometerServer, STEP_TIMEOUT};
+
+fn client(addr: SocketAddr) -> ClientConfig {
+    ClientConfig::builder()
+        .address(addr)
+        .read_timeout(STEP_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_thermostat_loop_on_the_facade() {
+    let heater = start_socket_server(ServerConfig::builder().socket_name("Heater"));
+    let thermometer = ThermometerServer::start(
+        thermometer_server::ServerConfig::builder()
+            .initial_temperature(21.0)
+            .query_address(loopback()),
+    );
+    let controller = SmartHomeController::new(
+        ControllerConfig::default()
+            .socket("kitchen", client(heater.local_addr()))
+            .thermometer(
+                "kitchen",
+                client(thermometer.handle().query_addr().unwrap()),
+            ),
+    );
+    assert!(!controller.socket("kitchen").status().unwrap().is_on);
+
+    let sensor = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sensor
+        .send_to(&16.5f64.to_be_bytes(), thermometer.handle().local_addr())
+        .unwrap();
+    assert_eq!(
+        thermometer
+            .handle()
+            .state()
+            .wait_for_accepted(1, STEP_TIMEOUT),
+        1
+    );
+    let reading = controller.thermometer("kitchen").reading().unwrap();
+    assert_eq!(reading.celsius, 16.5);
+
+    // Too cold: the heater goes on.
+    if reading.celsius < 20.0 {
+        controller.socket("kitchen").turn_on().unwrap();
+    }
+    assert!(controller.socket("kitchen").status().unwrap().is_on);
+
+    controller.shutdown();
+    assert!(matches!(
+        controller.thermometer("kitchen").reading(),
+        Err(ControllerError::ShutDown)
+    ));
+    // With the connections closed, both servers stop promptly.
+    heater.shutdown().unwrap();
+    thermometer.stop();
+}
+
+#[test]
+fn test_devices_refusing_commands_are_reported() {
+    let socket = start_socket_server(ServerConfig::builder().auth_token("secret"));
+    let controller = SmartHomeController::new(
+        ControllerConfig::default().socket("hall", client(socket.local_addr())),
+    );
+    assert!(matches!(
+        controller.socket("hall").turn_on(),
+        Err(ControllerError::Rejected(message)) if message.contains("E_UNAUTHORIZED")
+    ));
+    // The connection survives a refusal.
+    assert!(!controller.socket("hall").status().unwrap().is_on);
+
+    drop(controller);
+    socket.shutdown().unwrap();
+}
@@ -0,0 +1,53 @@
+//! A socket server in reverse mode dialling a controller's `ReverseListener`.
+
+use smart_socket_client::reverse::ReverseListener;
+use smart_socket_client::ClientConfig;
+use smart_socket_protocol::Response;
+use smart_socket_server::server::{run_reverse, ConnectionContext, ServerConfig};
+use std::time::Instant;
+use tests_integration::{loopback, STEP_TIMEOUT};
+
+#[test]
+fn test_device_dials_in_and_is_driven_by_name() {
+    let controller = ReverseListener::bind(
+        &loopback(),
+        ClientConfig::builder()
+            .banner_timeout(Some(STEP_TIMEOUT))
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    let config = ServerConfig::builder()
+        .socket_name("Garden Socket")
+        .socket_power(800)
+        .controller_address(controller.local_addr())
+        .build()
+        .unwrap();
+    let device = run_reverse(
+        controller.local_addr().into(),
+        ConnectionContext::from_config(&config).unwrap(),
+    );
+
+    let mut client = controller
+        .take("Garden Socket", STEP_TIMEOUT)
+        .expect("device never dialled in");
+    assert_eq!(client.turn_on().unwrap(), Response::ok("Socket turned on"));
+    assert_eq!(
+        client.get_status().unwrap(),
+        Response::Status {
+            is_on: true,
+            power: 800,
+            tripped: false,
+            locked: false,
+        }
+    );
+    assert_eq!(
+        client.turn_off().unwrap(),
+        Response::ok("Socket turned off")
+    );
+    client.close().unwrap();
+
+    let started = Instant::now();
+    device.shutdown().unwrap();
+    assert!(started.elapsed() < STEP_TIMEOUT);
+}
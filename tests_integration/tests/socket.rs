@@ -0,0 +1,155 @@
+//! The socket client against a real socket server, for every command.
+
+use smart_socket_protocol::binfmt::WireFormat;
+use smart_socket_protocol::{Command, ErrorCode, Response};
+use smart_socket_server::device::DeviceType;
+use smart_socket_server::server::ServerConfig;
+use tests_integration::{connect, start_socket_server};
+
+fn status(is_on: bool, power: u32) -> Response {
+    Response::Status {
+        is_on,
+        power,
+        tripped: false,
+        locked: false,
+    }
+}
+
+#[test]
+fn test_socket_command_matrix() {
+    let server = start_socket_server(
+        ServerConfig::builder()
+            .socket_name("Integration Socket")
+            .socket_power(1200),
+    );
+    let mut client = connect(server.local_addr(), true);
+    assert_eq!(
+        client.server_identity().map(|info| info.name.as_str()),
+        Some("Integration Socket")
+    );
+
+    let unsupported = Response::error(ErrorCode::Unsupported, "Device does not support levels");
+    let cases = [
+        (Command::Ping, Response::Pong),
+        (Command::GetStatus, status(false, 0)),
+        (Command::TurnOn, Response::ok("Socket turned on")),
+        (Command::GetStatus, status(true, 1200)),
+        (Command::SetLevel(40), unsupported),
+        (
+            Command::SetName("Hall Socket".to_string()),
+            Response::ok("Socket renamed"),
+        ),
+        (Command::ResetTrip, Response::ok("Socket was not tripped")),
+        (
+            Command::Auth("unused".to_string()),
+            Response::ok("Authentication not required"),
+        ),
+        (Command::TurnOff, Response::ok("Socket turned off")),
+        (Command::GetStatus, status(false, 0)),
+        (
+            Command::Lock(Some(5)),
+            Response::ok("Socket locked for 5 min"),
+        ),
+        (
+            Command::TurnOn,
+            Response::error(
+                ErrorCode::Locked,
+                "Socket is locked for 5 more min; send UNLOCK to switch it now",
+            ),
+        ),
+        (Command::Unlock, Response::ok("Socket unlocked")),
+    ];
+    for (command, expected) in cases {
+        let description = command.to_string();
+        assert_eq!(
+            client.send_command(command).unwrap(),
+            expected,
+            "{}",
+            description
+        );
+    }
+
+    let info = client.get_info().unwrap();
+    assert_eq!(info.name, "Hall Socket");
+    assert_eq!(info.power, 1200);
+    let stats = client.get_stats().unwrap();
+    assert_eq!(stats.switches, 2);
+    assert!(stats.commands >= 10, "{:?}", stats);
+
+    // The pulse ends on the server's timer; only its start is checked here.
+    assert_eq!(client.pulse(50).unwrap(), Response::ok("Pulse started"));
+    client.close().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_dimmer_levels_scale_power() {
+    let server = start_socket_server(
+        ServerConfig::builder()
+            .socket_power(2000)
+            .device_type(DeviceType::Dimmer),
+    );
+    let mut client = connect(server.local_addr(), true);
+    client.turn_on().unwrap();
+    assert_eq!(client.get_status().unwrap(), status(true, 2000));
+    assert_eq!(client.set_level(25).unwrap(), Response::Level(25));
+    assert_eq!(client.get_status().unwrap(), status(true, 500));
+    assert!(client
+        .get_info()
+        .unwrap()
+        .capabilities
+        .iter()
+        .any(|kind| kind == "LEVEL"));
+    client.close().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_binary_responses_carry_the_server_time() {
+    let server = start_socket_server(ServerConfig::builder().socket_power(1200));
+    let mut client = smart_socket_client::SmartSocketClient::with_config(
+        smart_socket_client::ClientConfig::builder()
+            .address(server.local_addr())
+            .wire_format(WireFormat::Binary)
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(client.turn_on().unwrap(), Response::ok("Socket turned on"));
+    assert_eq!(client.get_status().unwrap(), status(true, 1200));
+    assert!(client.last_server_time().is_some());
+    client.close().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_state_changes_require_the_token() {
+    let server = start_socket_server(ServerConfig::builder().auth_token("integration"));
+    let mut anonymous = connect(server.local_addr(), true);
+    assert_eq!(
+        anonymous.turn_on().unwrap(),
+        Response::error(ErrorCode::Unauthorized, "Authentication required")
+    );
+    assert!(matches!(
+        anonymous.get_status().unwrap(),
+        Response::Status { is_on: false, .. }
+    ));
+
+    let mut client = smart_socket_client::SmartSocketClient::with_config(
+        smart_socket_client::ClientConfig::builder()
+            .address(server.local_addr())
+            .auth_token("integration")
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(client.turn_on().unwrap(), Response::ok("Socket turned on"));
+    assert!(matches!(
+        anonymous.get_status().unwrap(),
+        Response::Status { is_on: true, .. }
+    ));
+
+    anonymous.close().unwrap();
+    client.close().unwrap();
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,139 @@
+//! Thermometer client readings arriving at thermometer servers.
+
+use smart_socket_protocol::thermometer;
+use smart_socket_protocol::Response;
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime};
+use tests_integration::{connect, loopback, ThermometerClient, ThermometerServer, STEP_TIMEOUT};
+use thermometer_client::generator::GenerationMode;
+use thermometer_client::ClientConfig;
+
+const SEED: u64 = 0x5eed;
+
+/// A fast, reproducible client sending to `servers`.
+fn client_config(servers: &[ThermometerServer]) -> ClientConfig {
+    ClientConfig {
+        server_addresses: servers
+            .iter()
+            .map(|server| server.handle().local_addr().into())
+            .collect(),
+        update_interval: Duration::from_millis(5),
+        min_temp: 16.0,
+        max_temp: 24.0,
+        reliable: true,
+        mode: GenerationMode::Uniform,
+        seed: Some(SEED),
+        ..Default::default()
+    }
+}
+
+/// The first `count` readings a client with `config` sends.
+fn expected_readings(config: &ClientConfig, count: u64) -> Vec<f64> {
+    let mut generator = config.mode.build_seeded(
+        config.min_temp,
+        config.max_temp,
+        config.update_interval,
+        SEED,
+    );
+    (0..count).map(|_| generator.next_reading()).collect()
+}
+
+#[test]
+fn test_reliable_readings_reach_every_server() {
+    let servers = [
+        ThermometerServer::start(thermometer_server::ServerConfig::builder()),
+        ThermometerServer::start(
+            thermometer_server::ServerConfig::builder().query_address(loopback()),
+        ),
+    ];
+    let client = ThermometerClient::start(client_config(&servers));
+    for server in &servers {
+        let accepted = server.handle().state().wait_for_accepted(4, STEP_TIMEOUT);
+        assert!(accepted >= 4, "only {} readings arrived", accepted);
+    }
+    let stats = client.stop();
+
+    // Every reading was acknowledged, so each server has seen all of them by now.
+    let config = client_config(&servers);
+    for (server, destination) in servers.iter().zip(&stats) {
+        assert_eq!(destination.failed, 0);
+        let sent = expected_readings(&config, destination.delivered);
+        let today = server.handle().today().expect("readings today");
+        let min = sent.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = sent.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        assert_eq!(today.min.unwrap().celsius, min);
+        assert_eq!(today.max.unwrap().celsius, max);
+        assert_eq!(server.handle().state().temperature(), *sent.last().unwrap());
+    }
+
+    // The query socket serves the same reading to socket protocol clients.
+    let query = servers[1].handle().query_addr().unwrap();
+    let mut reader = connect(query, false);
+    match reader.get_status().unwrap() {
+        Response::Temperature { celsius, .. } => {
+            assert_eq!(celsius, servers[1].handle().state().temperature())
+        }
+        other => panic!("Expected a temperature, got {:?}", other),
+    }
+    reader.close().unwrap();
+
+    for server in servers {
+        server.stop();
+    }
+}
+
+fn epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn test_history_query_returns_the_latest_readings() {
+    let server = ThermometerServer::start(thermometer_server::ServerConfig::builder());
+    let addr = server.handle().local_addr();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.set_read_timeout(Some(STEP_TIMEOUT)).unwrap();
+
+    let before = epoch_secs();
+    for i in 0..10 {
+        let temperature = 15.0 + f64::from(i) * 0.5;
+        sender
+            .send_to(&thermometer::encode_reading(temperature), addr)
+            .unwrap();
+    }
+    let accepted = server.handle().state().wait_for_accepted(10, STEP_TIMEOUT);
+    assert_eq!(accepted, 10);
+    let after = epoch_secs();
+
+    let mut buf = [0u8; thermometer::MAX_HISTORY_REPLY_LEN];
+    sender
+        .send_to(&thermometer::encode_history_request(5), addr)
+        .unwrap();
+    let (size, _) = sender.recv_from(&mut buf).unwrap();
+    let history = thermometer::decode_history_reply(&buf[..size]).unwrap();
+    let celsius: Vec<f64> = history.iter().map(|entry| entry.celsius).collect();
+    assert_eq!(celsius, [17.5, 18.0, 18.5, 19.0, 19.5]);
+    assert!(history
+        .windows(2)
+        .all(|pair| pair[0].epoch_secs <= pair[1].epoch_secs));
+    assert!(history
+        .iter()
+        .all(|entry| (before..=after).contains(&entry.epoch_secs)));
+
+    // More than one datagram could hold is refused outright.
+    sender
+        .send_to(
+            &thermometer::encode_history_request(thermometer::MAX_HISTORY_ENTRIES + 1),
+            addr,
+        )
+        .unwrap();
+    let (size, _) = sender.recv_from(&mut buf).unwrap();
+    assert_eq!(
+        thermometer::decode_history_reply(&buf[..size]),
+        Err(thermometer::TOO_MANY.to_string())
+    );
+
+    server.stop();
+}
@@ -0,0 +1,65 @@
+//! The whole chain: thermometer client, thermometer server with its thermostat,
+//! and the heater's socket server, started and stopped together.
+
+use smart_socket_server::server::ServerConfig;
+use std::time::{Duration, Instant};
+use tests_integration::{
+    connect, start_socket_server, wait_until_on, ThermometerClient, ThermometerServer, STEP_TIMEOUT,
+};
+use thermometer_server::thermostat::ThermostatConfig;
+
+#[test]
+fn test_cold_readings_switch_the_heater_on_and_everything_stops() {
+    let heater = start_socket_server(ServerConfig::builder().socket_name("Heater"));
+    let mut client = connect(heater.local_addr(), true);
+    let watcher = client.watch(Duration::from_millis(20));
+
+    // 20°C sits inside the dead band, so the heater is left alone until readings arrive.
+    let thermometer = ThermometerServer::start(
+        thermometer_server::ServerConfig::builder()
+            .initial_temperature(20.0)
+            .thermostat(ThermostatConfig {
+                socket_address: heater.local_addr().into(),
+                setpoint: 20.0,
+                hysteresis: 1.0,
+                poll_interval: Duration::from_millis(20),
+            }),
+    );
+    let sensor = ThermometerClient::start(thermometer_client::ClientConfig {
+        server_addresses: vec![thermometer.handle().local_addr().into()],
+        update_interval: Duration::from_millis(10),
+        min_temp: 5.0,
+        max_temp: 10.0,
+        reliable: true,
+        seed: Some(1),
+        ..Default::default()
+    });
+
+    assert!(
+        thermometer
+            .handle()
+            .state()
+            .wait_for_accepted(1, STEP_TIMEOUT)
+            >= 1
+    );
+    assert!(
+        wait_until_on(&mut client, &watcher),
+        "heater never turned on"
+    );
+    drop(watcher);
+
+    // Upstream first: the sensor, then the thermometer server, whose thermostat
+    // closes its connection, and only then the socket server, which waits for
+    // its connections to close.
+    let started = Instant::now();
+    let stats = sensor.stop();
+    assert!(stats[0].delivered >= 1);
+    thermometer.stop();
+    client.close().unwrap();
+    heater.shutdown().unwrap();
+    assert!(
+        started.elapsed() < STEP_TIMEOUT,
+        "shutdown took {:?}",
+        started.elapsed()
+    );
+}
@@ -0,0 +1,297 @@
+//! Models for the simulated temperature readings.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use smart_socket_protocol::clock::{Clock, SystemClock};
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+
+/// Produces one reading per call.
+pub trait TemperatureGenerator: Send {
+    fn next_reading(&mut self) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GenerationMode {
+    /// Independent uniformly random readings between the bounds.
+    #[default]
+    Uniform,
+    /// Each reading moves from the previous one by at most `step`.
+    RandomWalk { step: f64 },
+    /// A smooth cycle around `midpoint`, e.g. a daily temperature swing.
+    Sine {
+        period: Duration,
+        amplitude: f64,
+        midpoint: f64,
+    },
+    /// Moves linearly from `start` to `target` over `duration` of wall-clock time
+    /// from the first reading, then holds within `noise` of `target`.
+    Ramp {
+        start: f64,
+        target: f64,
+        duration: Duration,
+        noise: f64,
+    },
+}
+
+impl GenerationMode {
+    /// Builds a generator for readings clamped to `min..=max`. `interval` is the simulated
+    /// time between readings, which drives the [`GenerationMode::Sine`] phase.
+    ///
+    /// Every random draw comes from an RNG seeded with `seed`, so the same seed
+    /// always yields the same sequence.
+    pub fn build_seeded(
+        &self,
+        min: f64,
+        max: f64,
+        interval: Duration,
+        seed: u64,
+    ) -> Box<dyn TemperatureGenerator> {
+        self.build_with_clock(min, max, interval, seed, SystemClock)
+    }
+
+    /// Like [`build_seeded`](Self::build_seeded), with `clock` timing the
+    /// [`GenerationMode::Ramp`] instead of the system clock.
+    pub fn build_with_clock<C: Clock + Send + 'static>(
+        &self,
+        min: f64,
+        max: f64,
+        interval: Duration,
+        seed: u64,
+        clock: C,
+    ) -> Box<dyn TemperatureGenerator> {
+        let rng = StdRng::seed_from_u64(seed);
+        match *self {
+            GenerationMode::Uniform => Box::new(Uniform { rng, min, max }),
+            GenerationMode::RandomWalk { step } => Box::new(RandomWalk {
+                rng,
+                min,
+                max,
+                step,
+                current: None,
+            }),
+            GenerationMode::Sine {
+                period,
+                amplitude,
+                midpoint,
+            } => Box::new(Sine {
+                min,
+                max,
+                period,
+                amplitude,
+                midpoint,
+                interval,
+                elapsed: Duration::ZERO,
+            }),
+            GenerationMode::Ramp {
+                start,
+                target,
+                duration,
+                noise,
+            } => Box::new(Ramp {
+                rng,
+                clock,
+                min,
+                max,
+                start,
+                target,
+                duration,
+                noise,
+                started: None,
+            }),
+        }
+    }
+}
+
+struct Uniform {
+    rng: StdRng,
+    min: f64,
+    max: f64,
+}
+
+impl TemperatureGenerator for Uniform {
+    fn next_reading(&mut self) -> f64 {
+        self.rng.gen_range(self.min..self.max)
+    }
+}
+
+struct RandomWalk {
+    rng: StdRng,
+    min: f64,
+    max: f64,
+    step: f64,
+    current: Option<f64>,
+}
+
+impl TemperatureGenerator for RandomWalk {
+    fn next_reading(&mut self) -> f64 {
+        let next = match self.current {
+            None => self.rng.gen_range(self.min..self.max),
+            Some(current) => {
+                (current + self.rng.gen_range(-self.step..=self.step)).clamp(self.min, self.max)
+            }
+        };
+        self.current = Some(next);
+        next
+    }
+}
+
+struct Sine {
+    min: f64,
+    max: f64,
+    period: Duration,
+    amplitude: f64,
+    midpoint: f64,
+    interval: Duration,
+    elapsed: Duration,
+}
+
+impl TemperatureGenerator for Sine {
+    fn next_reading(&mut self) -> f64 {
+        let phase = self.elapsed.as_secs_f64() / self.period.as_secs_f64();
+        self.elapsed += self.interval;
+        (self.midpoint + self.amplitude * (TAU * phase).sin()).clamp(self.min, self.max)
+    }
+}
+
+struct Ramp<C> {
+    rng: StdRng,
+    clock: C,
+    min: f64,
+    max: f64,
+    start: f64,
+    target: f64,
+    duration: Duration,
+    noise: f64,
+    started: Option<Instant>,
+}
+
+impl<C: Clock + Send> TemperatureGenerator for Ramp<C> {
+    fn next_reading(&mut self) -> f64 {
+        let now = self.clock.now();
+        let elapsed = now.duration_since(*self.started.get_or_insert(now));
+        let value = if elapsed < self.duration {
+            let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+            self.start + (self.target - self.start) * progress
+        } else {
+            self.target + self.rng.gen_range(-self.noise..=self.noise)
+        };
+        value.clamp(self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::MockClock;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn samples(mode: GenerationMode, seed: u64, count: usize) -> Vec<f64> {
+        let mut generator = mode.build_seeded(15.0, 30.0, INTERVAL, seed);
+        (0..count).map(|_| generator.next_reading()).collect()
+    }
+
+    #[test]
+    fn test_seeded_generators_are_deterministic() {
+        for mode in [
+            GenerationMode::Uniform,
+            GenerationMode::RandomWalk { step: 0.5 },
+        ] {
+            assert_eq!(samples(mode, 7, 50), samples(mode, 7, 50));
+            assert_ne!(samples(mode, 7, 50), samples(mode, 8, 50));
+        }
+    }
+
+    #[test]
+    fn test_uniform_stays_in_bounds() {
+        for value in samples(GenerationMode::Uniform, 1, 10_000) {
+            assert!((15.0..30.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_random_walk_is_bounded_and_continuous() {
+        let step = 0.5;
+        let values = samples(GenerationMode::RandomWalk { step }, 42, 10_000);
+        for value in &values {
+            assert!((15.0..=30.0).contains(value));
+        }
+        for pair in values.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= step, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_sine_follows_the_cycle() {
+        let mode = GenerationMode::Sine {
+            period: INTERVAL * 4,
+            amplitude: 5.0,
+            midpoint: 20.0,
+        };
+        let values = samples(mode, 0, 5);
+        let expected = [20.0, 25.0, 20.0, 15.0, 20.0];
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-9, "{:?}", values);
+        }
+    }
+
+    /// Readings of a ramp with 0.2° of noise, taken `offsets` seconds after it starts.
+    fn ramp(start: f64, target: f64, duration_secs: u64, offsets: &[u64]) -> Vec<f64> {
+        let clock = MockClock::new();
+        let mode = GenerationMode::Ramp {
+            start,
+            target,
+            duration: Duration::from_secs(duration_secs),
+            noise: 0.2,
+        };
+        let mut generator = mode.build_with_clock(15.0, 30.0, INTERVAL, 3, clock.clone());
+        let mut elapsed = 0;
+        offsets
+            .iter()
+            .map(|&offset| {
+                clock.advance(Duration::from_secs(offset - elapsed));
+                elapsed = offset;
+                generator.next_reading()
+            })
+            .collect()
+    }
+
+    fn assert_close(values: &[f64], expected: &[f64]) {
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-9, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn test_ramp_moves_toward_target_then_holds() {
+        let values = ramp(16.0, 24.0, 100, &[0, 25, 50, 75, 100, 150, 1000]);
+        assert_close(&values[..4], &[16.0, 18.0, 20.0, 22.0]);
+        for value in &values[4..] {
+            assert!((value - 24.0).abs() <= 0.2, "{:?}", values);
+        }
+        assert_eq!(
+            values,
+            ramp(16.0, 24.0, 100, &[0, 25, 50, 75, 100, 150, 1000])
+        );
+    }
+
+    #[test]
+    fn test_ramp_downward_and_without_duration() {
+        assert_close(&ramp(24.0, 18.0, 60, &[0, 30]), &[24.0, 21.0]);
+        for value in ramp(16.0, 24.0, 0, &[0, 1, 2]) {
+            assert!((value - 24.0).abs() <= 0.2);
+        }
+    }
+
+    #[test]
+    fn test_sine_is_clamped_to_bounds() {
+        let mode = GenerationMode::Sine {
+            period: INTERVAL * 4,
+            amplitude: 50.0,
+            midpoint: 20.0,
+        };
+        let values = samples(mode, 0, 4);
+        assert_eq!((values[1], values[3]), (30.0, 15.0));
+    }
+}
@@ -0,0 +1,493 @@
+pub mod generator;
+pub mod reliable;
+pub mod schedule;
+pub mod source;
+pub mod ticker;
+
+use generator::GenerationMode;
+use reliable::{Delivery, UdpTransport};
+use schedule::Schedule;
+use smart_socket_protocol::clock::SystemClock;
+use smart_socket_protocol::logging::Logger;
+use smart_socket_protocol::thermometer;
+use smart_socket_protocol::Address;
+#[cfg(target_os = "linux")]
+use source::SysfsSensor;
+use source::{ReadingSource, Source};
+use std::error::Error;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use ticker::Ticker;
+
+static LOGGER: Logger = Logger::new();
+
+pub fn log(message: &str) {
+    LOGGER.log(message);
+}
+
+/// How often the per-destination delivery summary is logged.
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct ClientConfig {
+    /// Every reading is sent to each of these servers.
+    pub server_addresses: Vec<Address>,
+    pub update_interval: Duration,
+    pub min_temp: f64,
+    pub max_temp: f64,
+    /// Request an ACK for every reading and retransmit until it arrives.
+    pub reliable: bool,
+    pub mode: GenerationMode,
+    /// Where readings come from; `mode` only applies to [`Source::Simulated`].
+    pub source: Source,
+    /// Seed for simulated readings; a random one is picked (and logged) when unset.
+    pub seed: Option<u64>,
+    /// Sent with every reading so several thermometers can share one server
+    /// port; plain readings are credited to the server's default id.
+    pub device_id: Option<String>,
+    /// When readings go out; every `update_interval` by default.
+    pub schedule: Schedule,
+    /// Stop after this many readings, counting ones the source failed to produce.
+    pub count: Option<u64>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addresses: vec![Address::Ip(SocketAddr::from(([127, 0, 0, 1], 8081)))],
+            update_interval: Duration::from_secs(1),
+            min_temp: 15.0,
+            max_temp: 30.0,
+            reliable: false,
+            mode: GenerationMode::default(),
+            source: Source::default(),
+            seed: None,
+            device_id: None,
+            schedule: Schedule::default(),
+            count: None,
+        }
+    }
+}
+
+/// Delivery counters for one server address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationStats {
+    pub address: Address,
+    pub delivered: u64,
+    pub failed: u64,
+}
+
+/// One server a reading fans out to. Each destination has its own socket, so
+/// ACKs from one server are never mistaken for another's.
+struct Destination {
+    socket: UdpSocket,
+    stats: DestinationStats,
+}
+
+impl Destination {
+    fn new(address: &Address) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            socket: UdpSocket::bind("0.0.0.0:0")?,
+            stats: DestinationStats {
+                address: address.clone(),
+                delivered: 0,
+                failed: 0,
+            },
+        })
+    }
+
+    fn send(&mut self, reliable: bool, seq: u64, temperature: f64, device_id: Option<&str>) {
+        let address = &self.stats.address;
+        let delivered = if reliable {
+            let transport = UdpTransport {
+                socket: &self.socket,
+                target: address,
+            };
+            match reliable::send_reliable(&transport, &SystemClock, seq, temperature) {
+                Ok(Delivery::Acked { attempts }) => {
+                    log(&format!(
+                        "Sent temperature to {}: {:.1}°C (acknowledged after {} attempt(s))",
+                        address, temperature, attempts
+                    ));
+                    true
+                }
+                Ok(Delivery::Lost { attempts }) => {
+                    log(&format!(
+                        "Reading {} ({:.1}°C) to {} lost: no acknowledgement after {} attempts",
+                        seq, temperature, address, attempts
+                    ));
+                    false
+                }
+                Err(e) => {
+                    log(&format!("Error sending temperature to {}: {}", address, e));
+                    false
+                }
+            }
+        } else {
+            let sent = match device_id {
+                Some(device_id) => thermometer::encode_device_reading(device_id, temperature)
+                    .map_err(std::io::Error::other)
+                    .and_then(|packet| self.socket.send_to(&packet, address)),
+                None => self
+                    .socket
+                    .send_to(&thermometer::encode_reading(temperature), address),
+            };
+            match sent {
+                Ok(_) => {
+                    log(&format!(
+                        "Sent temperature to {}: {:.1}°C",
+                        address, temperature
+                    ));
+                    true
+                }
+                Err(e) => {
+                    log(&format!("Error sending temperature to {}: {}", address, e));
+                    false
+                }
+            }
+        };
+        if delivered {
+            self.stats.delivered += 1;
+        } else {
+            self.stats.failed += 1;
+        }
+    }
+}
+
+fn log_summary(destinations: &[Destination]) {
+    for destination in destinations {
+        let stats = &destination.stats;
+        log(&format!(
+            "Delivery to {}: {} delivered, {} failed",
+            stats.address, stats.delivered, stats.failed
+        ));
+    }
+}
+
+/// Sleeps for `duration`, waking early once `running` is cleared so a long
+/// update interval does not hold up shutdown.
+fn sleep_while_running(duration: Duration, running: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while running.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+/// Sends a reading to every server as `config.schedule` dictates until
+/// `running` is cleared or `config.count` readings were sent, and returns the
+/// per-destination delivery counters.
+///
+/// Reliable deliveries run concurrently, so a server that never acknowledges
+/// does not hold back the others.
+pub fn run_client(
+    config: ClientConfig,
+    running: Arc<AtomicBool>,
+) -> Result<Vec<DestinationStats>, Box<dyn Error>> {
+    if let Some(device_id) = &config.device_id {
+        if config.reliable {
+            return Err("Reliable readings cannot carry a device id".into());
+        }
+        thermometer::check_device_id(device_id)?;
+    }
+    config.schedule.validate()?;
+    let mut destinations = config
+        .server_addresses
+        .iter()
+        .map(|address| Destination::new(address))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    log(&format!(
+        "Thermometer client started, sending data to {}",
+        config
+            .server_addresses
+            .iter()
+            .map(Address::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+
+    let mut source: Box<dyn ReadingSource> = match &config.source {
+        Source::Simulated => {
+            let seed = config.seed.unwrap_or_else(rand::random);
+            log(&format!(
+                "Simulating readings with seed {} (pass --seed {} to reproduce them)",
+                seed, seed
+            ));
+            Box::new(config.mode.build_seeded(
+                config.min_temp,
+                config.max_temp,
+                config.update_interval,
+                seed,
+            ))
+        }
+        #[cfg(target_os = "linux")]
+        Source::Sysfs(path) => Box::new(SysfsSensor::open(path.clone())?),
+    };
+    let mut seq = 0u64;
+    let mut last_summary = Instant::now();
+    let mut ticker = Ticker::new(
+        config.schedule,
+        config.update_interval,
+        config.count,
+        SystemClock,
+    );
+    while running.load(Ordering::SeqCst)
+        && ticker.wait(|duration| {
+            sleep_while_running(duration, &running);
+            running.load(Ordering::SeqCst)
+        })
+    {
+        match source.read() {
+            Ok(temperature) => {
+                seq += 1;
+                if config.reliable {
+                    thread::scope(|scope| {
+                        for destination in &mut destinations {
+                            scope.spawn(move || destination.send(true, seq, temperature, None));
+                        }
+                    });
+                } else {
+                    for destination in &mut destinations {
+                        destination.send(false, seq, temperature, config.device_id.as_deref());
+                    }
+                }
+            }
+            Err(e) => log(&format!("Skipping reading: {}", e)),
+        }
+
+        if last_summary.elapsed() >= SUMMARY_INTERVAL {
+            log_summary(&destinations);
+            last_summary = Instant::now();
+        }
+    }
+
+    log_summary(&destinations);
+    log(&format!(
+        "Schedule: {} ticks taken, {} missed while falling behind",
+        ticker.sent(),
+        ticker.missed()
+    ));
+    Ok(destinations
+        .into_iter()
+        .map(|destination| destination.stats)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_config_default() {
+        let config = ClientConfig::default();
+        assert_eq!(config.server_addresses, ["127.0.0.1:8081".parse().unwrap()]);
+        assert_eq!(config.update_interval, Duration::from_secs(1));
+        assert_eq!(config.min_temp, 15.0);
+        assert_eq!(config.max_temp, 30.0);
+        assert!(!config.reliable);
+        assert_eq!(config.mode, GenerationMode::Uniform);
+        assert_eq!(config.source, Source::Simulated);
+        assert_eq!(config.seed, None);
+        assert_eq!(config.schedule, Schedule::Steady);
+        assert_eq!(config.count, None);
+    }
+
+    #[test]
+    fn test_run_client_sends_readings() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let config = ClientConfig {
+            server_addresses: vec![receiver.local_addr().unwrap().into()],
+            update_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let handle = thread::spawn(move || run_client(config, r).unwrap());
+
+        let mut buf = [0u8; 8];
+        let (size, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(size, 8);
+        let temperature = f64::from_be_bytes(buf);
+        assert!((15.0..30.0).contains(&temperature));
+
+        running.store(false, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_does_not_wait_for_the_next_interval() {
+        let receiver = receiver();
+        let config = ClientConfig {
+            server_addresses: vec![receiver.local_addr().unwrap().into()],
+            update_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let handle = thread::spawn(move || run_client(config, r).unwrap());
+
+        receiver.recv_from(&mut [0u8; 8]).unwrap();
+        let stopped = Instant::now();
+        running.store(false, Ordering::SeqCst);
+        let stats = handle.join().unwrap();
+        assert!(stopped.elapsed() < Duration::from_secs(1));
+        assert_eq!(stats[0].delivered, 1);
+    }
+
+    #[test]
+    fn test_device_id_is_sent_in_the_header() {
+        let receiver = receiver();
+        let config = ClientConfig {
+            server_addresses: vec![receiver.local_addr().unwrap().into()],
+            update_interval: Duration::from_millis(10),
+            device_id: Some("bedroom".to_string()),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let handle = thread::spawn(move || run_client(config, r).unwrap());
+
+        let mut buf = [0u8; thermometer::MAX_PACKET_LEN];
+        let (size, _) = receiver.recv_from(&mut buf).unwrap();
+        assert!(matches!(
+            thermometer::decode(&buf[..size]),
+            Some(thermometer::Packet::DeviceReading { device_id, temperature })
+                if device_id == "bedroom" && (15.0..30.0).contains(&temperature)
+        ));
+
+        running.store(false, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_count_ends_a_burst_run() {
+        let receiver = receiver();
+        let config = ClientConfig {
+            server_addresses: vec![receiver.local_addr().unwrap().into()],
+            schedule: Schedule::Burst {
+                size: 4,
+                interval: Duration::from_secs(3600),
+            },
+            count: Some(3),
+            ..Default::default()
+        };
+        // The three readings go out back-to-back and the run ends on its own.
+        let stats = run_client(config, Arc::new(AtomicBool::new(true))).unwrap();
+        assert_eq!(stats[0].delivered, 3);
+        for _ in 0..3 {
+            receiver.recv_from(&mut [0u8; 8]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_device_id_is_checked_before_sending() {
+        let running = Arc::new(AtomicBool::new(true));
+        let config = ClientConfig {
+            device_id: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(run_client(config, running.clone()).is_err());
+        let config = ClientConfig {
+            device_id: Some("bedroom".to_string()),
+            reliable: true,
+            ..Default::default()
+        };
+        assert!(run_client(config, running).is_err());
+    }
+
+    fn receiver() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn test_readings_fan_out_to_every_server() {
+        let receivers = [receiver(), receiver()];
+        let config = ClientConfig {
+            server_addresses: receivers
+                .iter()
+                .map(|r| r.local_addr().unwrap().into())
+                .collect(),
+            update_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let handle = thread::spawn(move || run_client(config, r).unwrap());
+
+        let mut first = Vec::new();
+        for receiver in &receivers {
+            let mut buf = [0u8; 8];
+            let (size, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(size, 8);
+            first.push(f64::from_be_bytes(buf));
+        }
+        // Both servers receive the same reading first.
+        assert_eq!(first[0], first[1]);
+
+        running.store(false, Ordering::SeqCst);
+        let stats = handle.join().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert!(stats.iter().all(|s| s.delivered > 0 && s.failed == 0));
+    }
+
+    #[test]
+    fn test_unreachable_server_does_not_block_the_others() {
+        // Nobody listens on the dead address, so every reliable delivery there is lost.
+        let dead: Address = receiver().local_addr().unwrap().into();
+        let live = receiver();
+        let live_addr: Address = live.local_addr().unwrap().into();
+
+        let config = ClientConfig {
+            server_addresses: vec![dead.clone(), live_addr.clone()],
+            update_interval: Duration::from_millis(10),
+            reliable: true,
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let handle = thread::spawn(move || run_client(config, r).unwrap());
+
+        let started = Instant::now();
+        let mut buf = [0u8; 64];
+        let (size, from) = live.recv_from(&mut buf).unwrap();
+        assert!(started.elapsed() < reliable::ACK_TIMEOUT);
+        let Some(thermometer::Packet::Reliable { seq, .. }) = thermometer::decode(&buf[..size])
+        else {
+            panic!("Expected a reliable reading");
+        };
+        live.send_to(&thermometer::encode_ack(seq), from).unwrap();
+
+        running.store(false, Ordering::SeqCst);
+        let stats = handle.join().unwrap();
+        assert_eq!(
+            stats,
+            [
+                DestinationStats {
+                    address: dead,
+                    delivered: 0,
+                    failed: 1,
+                },
+                DestinationStats {
+                    address: live_addr,
+                    delivered: 1,
+                    failed: 0,
+                },
+            ]
+        );
+    }
+}
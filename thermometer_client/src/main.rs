@@ -1,102 +1,120 @@
-use rand::Rng;
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
-use std::time::{Duration, SystemTime};
-
-fn get_timestamp() -> String {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs()
-        .to_string()
-}
-
-fn log(message: &str) {
-    println!("[{}] {}", get_timestamp(), message);
+use smart_socket_protocol::shutdown::{Shutdown, GRACE_PERIOD};
+use std::error::Error;
+use std::time::Duration;
+use thermometer_client::generator::GenerationMode;
+use thermometer_client::schedule::{self, Schedule, DEFAULT_DUTY_PERIOD};
+use thermometer_client::{log, run_client, ClientConfig};
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, Box<dyn Error>>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value for {}: {}", flag, e).into())
 }
 
-#[derive(Debug)]
-struct ClientConfig {
-    server_address: String,
-    update_interval: Duration,
-    min_temp: f64,
-    max_temp: f64,
+fn parse_duration(flag: &str, value: Option<String>) -> Result<Duration, Box<dyn Error>> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    schedule::parse_duration(&value)
+        .map_err(|e| format!("Invalid value for {}: {}", flag, e).into())
 }
 
-impl Default for ClientConfig {
-    fn default() -> Self {
-        Self {
-            server_address: "127.0.0.1:8081".to_string(),
-            update_interval: Duration::from_secs(1),
-            min_temp: 15.0,
-            max_temp: 30.0,
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut config = ClientConfig::default();
+    let mut servers = Vec::new();
+    let mut mode = "uniform".to_string();
+    let mut step = 0.5;
+    let mut period = None;
+    let mut amplitude = 5.0;
+    let mut midpoint = 22.5;
+    let mut start = 15.0;
+    let mut target = 22.5;
+    let mut ramp_secs = 600.0;
+    let mut noise = 0.1;
+    let mut burst = None;
+    let mut burst_interval = None;
+    let mut duty_cycle = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--reliable" => config.reliable = true,
+            "--server" => servers.push(parse_value(&arg, args.next())?),
+            "--mode" => mode = parse_value(&arg, args.next())?,
+            "--step" => step = parse_value(&arg, args.next())?,
+            "--period" => period = Some(parse_duration(&arg, args.next())?),
+            "--amplitude" => amplitude = parse_value(&arg, args.next())?,
+            "--midpoint" => midpoint = parse_value(&arg, args.next())?,
+            "--start" => start = parse_value(&arg, args.next())?,
+            "--target" => target = parse_value(&arg, args.next())?,
+            "--ramp-secs" => ramp_secs = parse_value(&arg, args.next())?,
+            "--noise" => noise = parse_value(&arg, args.next())?,
+            "--source" => config.source = parse_value(&arg, args.next())?,
+            "--seed" => config.seed = Some(parse_value(&arg, args.next())?),
+            "--device-id" => config.device_id = Some(parse_value(&arg, args.next())?),
+            "--burst" => burst = Some(parse_value(&arg, args.next())?),
+            "--burst-interval" => burst_interval = Some(parse_duration(&arg, args.next())?),
+            "--duty-cycle" => duty_cycle = Some(parse_value(&arg, args.next())?),
+            "--count" => config.count = Some(parse_value(&arg, args.next())?),
+            other => return Err(format!("Unknown argument: {}", other).into()),
         }
     }
-}
-
-fn generate_temperature(min_temp: f64, max_temp: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min_temp..max_temp)
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = ClientConfig::default();
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    if !servers.is_empty() {
+        config.server_addresses = servers;
+    }
+    config.mode = match mode.as_str() {
+        "uniform" => GenerationMode::Uniform,
+        "random-walk" => GenerationMode::RandomWalk { step },
+        "sine" => GenerationMode::Sine {
+            period: period.unwrap_or(Duration::from_secs(86_400)),
+            amplitude,
+            midpoint,
+        },
+        "ramp" if noise < 0.0 => {
+            return Err(format!("Invalid value for --noise: {} is negative", noise).into())
+        }
+        "ramp" => GenerationMode::Ramp {
+            start,
+            target,
+            duration: Duration::try_from_secs_f64(ramp_secs)?,
+            noise,
+        },
+        other => {
+            return Err(format!(
+                "Unknown mode '{}', expected uniform, random-walk, sine or ramp",
+                other
+            )
+            .into())
+        }
+    };
+
+    config.schedule = match (burst, burst_interval, duty_cycle) {
+        (None, None, None) => Schedule::Steady,
+        (Some(size), interval, None) => Schedule::Burst {
+            size,
+            interval: interval.ok_or("--burst requires --burst-interval")?,
+        },
+        (None, Some(_), _) => return Err("--burst-interval requires --burst".into()),
+        (None, None, Some(fraction)) => Schedule::DutyCycle {
+            fraction,
+            period: period.unwrap_or(DEFAULT_DUTY_PERIOD),
+        },
+        (Some(_), _, Some(_)) => return Err("--burst and --duty-cycle cannot be combined".into()),
+    };
+
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
 
     ctrlc::set_handler(move || {
         log("Shutdown signal received, stopping client...");
-        r.store(false, Ordering::SeqCst);
+        s.interrupt(GRACE_PERIOD);
     })?;
 
-    log(&format!(
-        "Thermometer client started, sending data to {}",
-        config.server_address
-    ));
     log("Press Ctrl+C to stop the client");
-
-    while running.load(Ordering::SeqCst) {
-        let temperature = generate_temperature(config.min_temp, config.max_temp);
-        let bytes = temperature.to_be_bytes();
-
-        if let Err(e) = socket.send_to(&bytes, &config.server_address) {
-            log(&format!("Error sending temperature: {}", e));
-        } else {
-            log(&format!("Sent temperature: {:.1}°C", temperature));
-        }
-
-        thread::sleep(config.update_interval);
-    }
+    run_client(config, shutdown.running())?;
 
     log("Client shutdown complete");
     Ok(())
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_generate_temperature() {
-        let min_temp = 15.0;
-        let max_temp = 30.0;
-
-        for _ in 0..100 {
-            let temp = generate_temperature(min_temp, max_temp);
-            assert!(temp >= min_temp && temp <= max_temp);
-        }
-    }
-
-    #[test]
-    fn test_client_config_default() {
-        let config = ClientConfig::default();
-        assert_eq!(config.server_address, "127.0.0.1:8081");
-        assert_eq!(config.update_interval, Duration::from_secs(1));
-        assert_eq!(config.min_temp, 15.0);
-        assert_eq!(config.max_temp, 30.0);
-    }
-}
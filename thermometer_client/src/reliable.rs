@@ -0,0 +1,227 @@
+//! Acknowledged delivery of readings: each reliable datagram is retransmitted
+//! until the server echoes its sequence number back or the retries run out.
+
+use smart_socket_protocol::clock::Clock;
+use smart_socket_protocol::thermometer;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(200);
+pub const MAX_RETRANSMITS: u32 = 3;
+
+/// Datagram transport used by [`send_reliable`].
+pub trait Transport {
+    fn send(&self, buf: &[u8]) -> io::Result<()>;
+    /// Waits up to `timeout` for a datagram; a timeout is reported as
+    /// `WouldBlock` or `TimedOut`.
+    fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+}
+
+pub struct UdpTransport<'a, A: ToSocketAddrs> {
+    pub socket: &'a UdpSocket,
+    pub target: A,
+}
+
+impl<A: ToSocketAddrs> Transport for UdpTransport<'_, A> {
+    fn send(&self, buf: &[u8]) -> io::Result<()> {
+        self.socket.send_to(buf, &self.target).map(|_| ())
+    }
+
+    fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // A zero read timeout means "block forever" for std sockets.
+        self.socket
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        self.socket.recv_from(buf).map(|(size, _)| size)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Acked { attempts: u32 },
+    Lost { attempts: u32 },
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Sends `temperature` with sequence number `seq`, retransmitting up to
+/// [`MAX_RETRANSMITS`] times when no matching ACK arrives within [`ACK_TIMEOUT`].
+/// ACKs for other sequence numbers (late replies to earlier readings) are ignored.
+pub fn send_reliable<T: Transport, C: Clock>(
+    transport: &T,
+    clock: &C,
+    seq: u64,
+    temperature: f64,
+) -> io::Result<Delivery> {
+    let datagram = thermometer::encode_reliable(seq, temperature);
+    let mut buf = [0u8; 64];
+
+    for attempt in 1..=MAX_RETRANSMITS + 1 {
+        transport.send(&datagram)?;
+        let deadline = clock.now() + ACK_TIMEOUT;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if remaining.is_zero() {
+                break;
+            }
+            match transport.recv(&mut buf, remaining) {
+                Ok(size) if thermometer::decode_ack(&buf[..size]) == Some(seq) => {
+                    return Ok(Delivery::Acked { attempts: attempt });
+                }
+                Ok(_) => continue,
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(Delivery::Lost {
+        attempts: MAX_RETRANSMITS + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::{MockClock, SystemClock};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    enum Event {
+        Datagram(Vec<u8>),
+        Timeout,
+    }
+
+    /// Replays scripted receive events; a timeout advances the fake clock by
+    /// the full wait so no real time passes.
+    struct FakeTransport<'a> {
+        clock: &'a MockClock,
+        sent: RefCell<Vec<Vec<u8>>>,
+        events: RefCell<VecDeque<Event>>,
+    }
+
+    impl<'a> FakeTransport<'a> {
+        fn new(clock: &'a MockClock, events: Vec<Event>) -> Self {
+            Self {
+                clock,
+                sent: RefCell::new(Vec::new()),
+                events: RefCell::new(events.into()),
+            }
+        }
+    }
+
+    impl Transport for FakeTransport<'_> {
+        fn send(&self, buf: &[u8]) -> io::Result<()> {
+            self.sent.borrow_mut().push(buf.to_vec());
+            Ok(())
+        }
+
+        fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+            match self.events.borrow_mut().pop_front() {
+                Some(Event::Datagram(data)) => {
+                    self.clock.advance(Duration::from_millis(5));
+                    buf[..data.len()].copy_from_slice(&data);
+                    Ok(data.len())
+                }
+                Some(Event::Timeout) | None => {
+                    self.clock.advance(timeout);
+                    Err(io::ErrorKind::WouldBlock.into())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_acked_first_try() {
+        let clock = MockClock::new();
+        let transport = FakeTransport::new(
+            &clock,
+            vec![Event::Datagram(thermometer::encode_ack(1).to_vec())],
+        );
+
+        let delivery = send_reliable(&transport, &clock, 1, 21.0).unwrap();
+        assert_eq!(delivery, Delivery::Acked { attempts: 1 });
+        assert_eq!(
+            transport.sent.borrow().as_slice(),
+            &[thermometer::encode_reliable(1, 21.0).to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_retransmit_after_timeout() {
+        let clock = MockClock::new();
+        let transport = FakeTransport::new(
+            &clock,
+            vec![
+                Event::Timeout,
+                Event::Datagram(thermometer::encode_ack(5).to_vec()),
+            ],
+        );
+
+        let delivery = send_reliable(&transport, &clock, 5, 21.0).unwrap();
+        assert_eq!(delivery, Delivery::Acked { attempts: 2 });
+        assert_eq!(transport.sent.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_stale_acks_are_ignored() {
+        let clock = MockClock::new();
+        let transport = FakeTransport::new(
+            &clock,
+            vec![
+                Event::Datagram(thermometer::encode_ack(2).to_vec()),
+                Event::Datagram(vec![1, 2, 3]),
+                Event::Datagram(thermometer::encode_ack(3).to_vec()),
+            ],
+        );
+
+        let delivery = send_reliable(&transport, &clock, 3, 21.0).unwrap();
+        assert_eq!(delivery, Delivery::Acked { attempts: 1 });
+    }
+
+    #[test]
+    fn test_lost_after_max_retransmits() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let transport = FakeTransport::new(&clock, Vec::new());
+
+        let delivery = send_reliable(&transport, &clock, 9, 21.0).unwrap();
+        assert_eq!(delivery, Delivery::Lost { attempts: 4 });
+        assert_eq!(transport.sent.borrow().len(), 4);
+        assert_eq!(clock.now() - start, ACK_TIMEOUT * 4);
+    }
+
+    #[test]
+    fn test_recovery_after_dropped_packet_over_loopback() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            // Ignore the first datagram to simulate loss, acknowledge the retransmit.
+            server.recv_from(&mut buf).unwrap();
+            let (size, addr) = server.recv_from(&mut buf).unwrap();
+            match thermometer::decode(&buf[..size]) {
+                Some(thermometer::Packet::Reliable { seq, temperature }) => {
+                    server.send_to(&thermometer::encode_ack(seq), addr).unwrap();
+                    temperature
+                }
+                other => panic!("Unexpected packet: {:?}", other),
+            }
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = UdpTransport {
+            socket: &socket,
+            target: server_addr,
+        };
+        let delivery = send_reliable(&transport, &SystemClock, 11, 22.5).unwrap();
+        assert_eq!(delivery, Delivery::Acked { attempts: 2 });
+        assert_eq!(handle.join().unwrap(), 22.5);
+    }
+}
@@ -0,0 +1,280 @@
+//! When readings are sent: a steady stream, bursts or a duty cycle, for
+//! exercising the server's batching, rate summaries and filters.
+//!
+//! Send times are offsets from the start of the run computed from the index of
+//! the slot, so they do not drift. Slots that passed unused, e.g. while the
+//! process was suspended, are skipped by the [`Ticker`](crate::ticker::Ticker).
+
+use smart_socket_protocol::clock::Clock;
+use std::time::{Duration, Instant};
+
+/// Duty-cycle period used when `--period` is not given.
+pub const DEFAULT_DUTY_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Schedule {
+    /// One reading every update interval.
+    #[default]
+    Steady,
+    /// `size` readings back-to-back at the start of every `interval`.
+    Burst { size: u64, interval: Duration },
+    /// One reading every update interval during the first `fraction` of each
+    /// `period`, and none for the rest of it.
+    DutyCycle { fraction: f64, period: Duration },
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Schedule::Steady => Ok(()),
+            Schedule::Burst { size: 0, .. } => Err("Burst size must be at least 1".to_string()),
+            Schedule::Burst { interval, .. } if interval.is_zero() => {
+                Err("Burst interval must be greater than zero".to_string())
+            }
+            Schedule::DutyCycle { fraction, .. } if !(fraction > 0.0 && fraction <= 1.0) => Err(
+                format!("Duty cycle must be above 0 and at most 1, got {}", fraction),
+            ),
+            Schedule::DutyCycle { period, .. } if period.is_zero() => {
+                Err("Duty cycle period must be greater than zero".to_string())
+            }
+            Schedule::Burst { .. } | Schedule::DutyCycle { .. } => Ok(()),
+        }
+    }
+
+    /// When the reading with 0-based index `n` is due, after the start of the run.
+    pub fn offset(&self, n: u64, update_interval: Duration) -> Duration {
+        match *self {
+            Schedule::Steady => times(update_interval, n),
+            Schedule::Burst { size, interval } => times(interval, n / size),
+            Schedule::DutyCycle { fraction, period } => {
+                // Readings fit in the on-window at 0, 1, ... intervals into the
+                // period; the first one always does.
+                let window = period.mul_f64(fraction).as_nanos();
+                let interval = update_interval.as_nanos();
+                let per_period = match interval {
+                    0 => 1,
+                    _ => window.div_ceil(interval).max(1) as u64,
+                };
+                times(period, n / per_period) + times(update_interval, n % per_period)
+            }
+        }
+    }
+}
+
+/// `duration * n`, saturating rather than overflowing on absurdly long runs.
+fn times(duration: Duration, n: u64) -> Duration {
+    u32::try_from(n)
+        .ok()
+        .and_then(|n| duration.checked_mul(n))
+        .unwrap_or(Duration::MAX)
+}
+
+/// Hands out send times for a run that started when it was created, until
+/// `count` readings have been sent.
+pub struct Scheduler {
+    schedule: Schedule,
+    update_interval: Duration,
+    count: Option<u64>,
+    start: Instant,
+    /// Index of the next slot, sent or skipped.
+    slot: u64,
+    sent: u64,
+}
+
+impl Scheduler {
+    pub fn new(
+        schedule: Schedule,
+        update_interval: Duration,
+        count: Option<u64>,
+        clock: &impl Clock,
+    ) -> Self {
+        Self {
+            schedule,
+            update_interval,
+            count,
+            start: clock.now(),
+            slot: 0,
+            sent: 0,
+        }
+    }
+
+    /// When the next reading is due; `None` once `count` readings were sent.
+    pub fn next_due(&self) -> Option<Instant> {
+        if self.count.is_some_and(|count| self.sent >= count) {
+            return None;
+        }
+        self.due(self.slot)
+    }
+
+    /// The first due time after that of the next slot, whatever `count` says.
+    /// Slots of a burst share a due time, so this may be several slots on.
+    pub fn following_due(&self) -> Option<Instant> {
+        let next = self.due(self.slot)?;
+        (self.slot + 1..)
+            .map(|slot| self.due(slot))
+            .find(|due| due.is_none_or(|due| due > next))
+            .flatten()
+    }
+
+    fn due(&self, slot: u64) -> Option<Instant> {
+        self.start
+            .checked_add(self.schedule.offset(slot, self.update_interval))
+    }
+
+    /// Records that the reading due at [`next_due`](Self::next_due) went out,
+    /// or was skipped because the source failed.
+    pub fn advance(&mut self) {
+        self.slot += 1;
+        self.sent += 1;
+    }
+
+    /// Passes over the next slot without sending; it does not count towards `count`.
+    pub fn skip(&mut self) {
+        self.slot += 1;
+    }
+}
+
+/// Parses a duration in seconds, optionally with an `s` suffix, or in
+/// milliseconds with an `ms` suffix, e.g. `10`, `1.5s` or `250ms`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("'{}' is not a duration such as 10, 1.5s or 250ms", text);
+    let trimmed = text.trim();
+    match trimmed.strip_suffix("ms") {
+        Some(millis) => millis
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| invalid()),
+        None => trimmed
+            .strip_suffix('s')
+            .unwrap_or(trimmed)
+            .parse()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::MockClock;
+
+    /// Runs `schedule` on a fake clock that jumps to every due time, and returns
+    /// the send times in milliseconds from the start.
+    fn send_times(schedule: Schedule, count: Option<u64>, limit: usize) -> Vec<u64> {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut scheduler = Scheduler::new(schedule, Duration::from_secs(1), count, &clock);
+        let mut times = Vec::new();
+        while let Some(due) = scheduler.next_due() {
+            if times.len() == limit {
+                break;
+            }
+            assert!(due >= clock.now(), "send times must not go backwards");
+            clock.advance(due - clock.now());
+            times.push((clock.now() - start).as_millis() as u64);
+            scheduler.advance();
+        }
+        times
+    }
+
+    #[test]
+    fn test_steady_schedule() {
+        assert_eq!(
+            send_times(Schedule::Steady, None, 4),
+            [0, 1_000, 2_000, 3_000]
+        );
+    }
+
+    #[test]
+    fn test_bursts_are_back_to_back() {
+        let burst = Schedule::Burst {
+            size: 3,
+            interval: Duration::from_secs(10),
+        };
+        assert_eq!(
+            send_times(burst, None, 8),
+            [0, 0, 0, 10_000, 10_000, 10_000, 20_000, 20_000]
+        );
+    }
+
+    #[test]
+    fn test_duty_cycle_sends_only_in_the_on_window() {
+        // 20% of 10s is a 2s window: readings at 0s and 1s of every period.
+        let duty = Schedule::DutyCycle {
+            fraction: 0.2,
+            period: Duration::from_secs(10),
+        };
+        assert_eq!(
+            send_times(duty, None, 6),
+            [0, 1_000, 10_000, 11_000, 20_000, 21_000]
+        );
+        // A 2.5s window also fits a reading at 2s.
+        let duty = Schedule::DutyCycle {
+            fraction: 0.25,
+            period: Duration::from_secs(10),
+        };
+        assert_eq!(send_times(duty, None, 4), [0, 1_000, 2_000, 10_000]);
+        // A window shorter than the update interval still sends once per period.
+        let duty = Schedule::DutyCycle {
+            fraction: 0.01,
+            period: Duration::from_secs(10),
+        };
+        assert_eq!(send_times(duty, None, 3), [0, 10_000, 20_000]);
+        let always = Schedule::DutyCycle {
+            fraction: 1.0,
+            period: Duration::from_secs(3),
+        };
+        assert_eq!(send_times(always, None, 4), [0, 1_000, 2_000, 3_000]);
+    }
+
+    #[test]
+    fn test_count_ends_the_run() {
+        let burst = Schedule::Burst {
+            size: 50,
+            interval: Duration::from_secs(10),
+        };
+        // The count can end a burst part-way.
+        let times = send_times(burst, Some(120), usize::MAX);
+        assert_eq!(times.len(), 120);
+        assert_eq!(times.iter().filter(|&&t| t == 20_000).count(), 20);
+        let duty = Schedule::DutyCycle {
+            fraction: 0.2,
+            period: Duration::from_secs(10),
+        };
+        assert_eq!(send_times(duty, Some(3), usize::MAX), [0, 1_000, 10_000]);
+        assert!(send_times(Schedule::Steady, Some(0), usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Schedule::Steady.validate().is_ok());
+        let burst = |size, secs| Schedule::Burst {
+            size,
+            interval: Duration::from_secs(secs),
+        };
+        assert!(burst(50, 10).validate().is_ok());
+        assert!(burst(0, 10).validate().is_err());
+        assert!(burst(50, 0).validate().is_err());
+        let duty = |fraction, secs| Schedule::DutyCycle {
+            fraction,
+            period: Duration::from_secs(secs),
+        };
+        assert!(duty(1.0, 60).validate().is_ok());
+        for fraction in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(duty(fraction, 60).validate().is_err(), "{}", fraction);
+        }
+        assert!(duty(0.2, 0).validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1_500)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        for invalid in ["", "soon", "-1s", "1.5ms", "10m"] {
+            assert!(parse_duration(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Where readings come from: a simulated generator or, on Linux, a real sensor.
+
+use crate::generator::TemperatureGenerator;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Produces the reading for one send interval. An error skips that interval;
+/// the client keeps running and tries again on the next one.
+pub trait ReadingSource: Send {
+    fn read(&mut self) -> io::Result<f64>;
+}
+
+impl ReadingSource for Box<dyn TemperatureGenerator> {
+    fn read(&mut self) -> io::Result<f64> {
+        Ok(self.next_reading())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Source {
+    /// Readings from the configured [`GenerationMode`](crate::generator::GenerationMode).
+    #[default]
+    Simulated,
+    /// A sysfs file holding millidegrees Celsius, such as
+    /// `/sys/class/thermal/thermal_zone0/temp`.
+    #[cfg(target_os = "linux")]
+    Sysfs(PathBuf),
+}
+
+/// Parses `simulated` or `sysfs:<path>`.
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "simulated" => Ok(Source::Simulated),
+            #[cfg(target_os = "linux")]
+            Some(("sysfs", path)) if !path.is_empty() => Ok(Source::Sysfs(path.into())),
+            #[cfg(not(target_os = "linux"))]
+            Some(("sysfs", _)) => Err("sysfs sources are only available on Linux".to_string()),
+            _ => Err(format!(
+                "Unknown source '{}', expected simulated or sysfs:<path>",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads a thermal zone (or any file in the same format) on every interval.
+#[cfg(target_os = "linux")]
+pub struct SysfsSensor {
+    path: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+impl SysfsSensor {
+    /// Fails if `path` cannot be read right now, so a mistyped path is reported at
+    /// startup rather than as a warning every interval.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let mut sensor = Self { path };
+        sensor.read()?;
+        Ok(sensor)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ReadingSource for SysfsSensor {
+    fn read(&mut self) -> io::Result<f64> {
+        let context = |e: io::Error| io::Error::new(e.kind(), format!("{:?}: {}", self.path, e));
+        let text = std::fs::read_to_string(&self.path).map_err(context)?;
+        parse_millidegrees(&text).map_err(context)
+    }
+}
+
+/// Parses a millidegree value such as `45500\n` into °C.
+#[cfg(target_os = "linux")]
+fn parse_millidegrees(text: &str) -> io::Result<f64> {
+    text.trim()
+        .parse::<i64>()
+        .map(|millidegrees| millidegrees as f64 / 1000.0)
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected millidegrees, got {:?}", text.trim()),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::GenerationMode;
+    use std::time::Duration;
+
+    #[test]
+    fn test_generator_is_a_source() {
+        let mut source =
+            GenerationMode::Uniform.build_seeded(15.0, 30.0, Duration::from_secs(1), 7);
+        let reading = ReadingSource::read(&mut source).unwrap();
+        assert!((15.0..30.0).contains(&reading));
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!("simulated".parse::<Source>().unwrap(), Source::Simulated);
+        assert!("random".parse::<Source>().is_err());
+        assert!("sysfs:".parse::<Source>().is_err());
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            "sysfs:/sys/class/thermal/thermal_zone0/temp"
+                .parse::<Source>()
+                .unwrap(),
+            Source::Sysfs("/sys/class/thermal/thermal_zone0/temp".into())
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sysfs_readings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("temp");
+        std::fs::write(&path, "45500\n").unwrap();
+
+        let mut sensor = SysfsSensor::open(path.clone()).unwrap();
+        assert_eq!(sensor.read().unwrap(), 45.5);
+
+        std::fs::write(&path, "-1250").unwrap();
+        assert_eq!(sensor.read().unwrap(), -1.25);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sysfs_errors_skip_a_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("temp");
+        assert_eq!(
+            SysfsSensor::open(path.clone()).err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        std::fs::write(&path, "42000").unwrap();
+        let mut sensor = SysfsSensor::open(path.clone()).unwrap();
+        for malformed in ["", "warm", "42.0", "42000 43000"] {
+            std::fs::write(&path, malformed).unwrap();
+            assert_eq!(
+                sensor.read().unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                malformed
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(sensor.read().is_err());
+
+        std::fs::write(&path, "43000").unwrap();
+        assert_eq!(sensor.read().unwrap(), 43.0);
+    }
+}
@@ -0,0 +1,181 @@
+//! A fixed-schedule ticker: every tick is due at the start of the run plus its
+//! offset from the [`Schedule`], never at the end of the previous send plus an
+//! interval, so the time spent sending does not add up over a long run.
+//!
+//! When the process falls behind, e.g. because it was suspended, the ticks
+//! that passed meanwhile are skipped and counted instead of being sent in a
+//! burst: the latest overdue tick fires at once and the rest follow on the
+//! original grid.
+
+use crate::schedule::{Schedule, Scheduler};
+use smart_socket_protocol::clock::Clock;
+use std::time::Duration;
+
+pub struct Ticker<C: Clock> {
+    scheduler: Scheduler,
+    clock: C,
+    sent: u64,
+    missed: u64,
+}
+
+impl<C: Clock> Ticker<C> {
+    /// Starts the run now; ticks end after `count` if given.
+    pub fn new(
+        schedule: Schedule,
+        update_interval: Duration,
+        count: Option<u64>,
+        clock: C,
+    ) -> Self {
+        Self {
+            scheduler: Scheduler::new(schedule, update_interval, count, &clock),
+            clock,
+            sent: 0,
+            missed: 0,
+        }
+    }
+
+    /// Waits for the next tick, sleeping with `sleep`, which returns `false`
+    /// to stop the run and may wake early. Returns `false` once the run is
+    /// over, because `count` ticks were taken or `sleep` stopped it.
+    pub fn wait(&mut self, mut sleep: impl FnMut(Duration) -> bool) -> bool {
+        loop {
+            let Some(due) = self.scheduler.next_due() else {
+                return false;
+            };
+            let now = self.clock.now();
+            if due > now {
+                if !sleep(due - now) {
+                    return false;
+                }
+                continue;
+            }
+            // Ticks sharing a due time, such as a burst, all fire; they are
+            // only missed once a later tick is due as well.
+            if self
+                .scheduler
+                .following_due()
+                .is_some_and(|following| following <= now)
+            {
+                self.scheduler.skip();
+                self.missed += 1;
+                continue;
+            }
+            self.scheduler.advance();
+            self.sent += 1;
+            return true;
+        }
+    }
+
+    /// Ticks taken so far.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Ticks skipped because the run had fallen behind.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::MockClock;
+    use std::time::Instant;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Sleeps by moving `clock`, `overshoot` later than asked, as a loaded
+    /// system would.
+    fn sleeper(clock: &MockClock, overshoot: Duration) -> impl FnMut(Duration) -> bool + '_ {
+        move |duration| {
+            clock.advance(duration + overshoot);
+            true
+        }
+    }
+
+    fn offset(clock: &MockClock, start: Instant) -> Duration {
+        clock.now() - start
+    }
+
+    #[test]
+    fn test_no_cumulative_drift() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut ticker = Ticker::new(Schedule::Steady, SECOND, None, clock.clone());
+        let work = Duration::from_millis(150);
+        let overshoot = Duration::from_millis(3);
+        for n in 0..5_000u32 {
+            assert!(ticker.wait(sleeper(&clock, overshoot)));
+            let late = offset(&clock, start) - SECOND * n;
+            // Each tick is at most one oversleep late, however many came before.
+            assert!(late <= overshoot, "tick {} late by {:?}", n, late);
+            clock.advance(work);
+        }
+        assert_eq!(ticker.sent(), 5_000);
+        assert_eq!(ticker.missed(), 0);
+    }
+
+    #[test]
+    fn test_catch_up_after_a_stall() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut ticker = Ticker::new(Schedule::Steady, SECOND, Some(20), clock.clone());
+        for _ in 0..3 {
+            assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        }
+        assert_eq!(offset(&clock, start), SECOND * 2);
+
+        // Suspended from 2s to 7.5s: the ticks at 3s to 6s are missed, the one
+        // at 7s fires at once and the next one keeps to the grid.
+        clock.advance(Duration::from_millis(5_500));
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        assert_eq!(offset(&clock, start), Duration::from_millis(7_500));
+        assert_eq!(ticker.missed(), 4);
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        assert_eq!(offset(&clock, start), SECOND * 8);
+
+        // Missed ticks do not count towards the limit.
+        let mut more = 0;
+        while ticker.wait(sleeper(&clock, Duration::ZERO)) {
+            more += 1;
+        }
+        assert_eq!(ticker.sent(), 20);
+        assert_eq!(more, 15);
+        assert_eq!(offset(&clock, start), SECOND * 23);
+    }
+
+    #[test]
+    fn test_burst_ticks_are_not_missed() {
+        let clock = MockClock::new();
+        let burst = Schedule::Burst {
+            size: 3,
+            interval: SECOND * 10,
+        };
+        let mut ticker = Ticker::new(burst, SECOND, None, clock.clone());
+        // Sending the first reading of the burst takes a while.
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        clock.advance(SECOND * 2);
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        assert_eq!(ticker.missed(), 0);
+
+        // A stall past the next burst skips the rest of this one.
+        let mut ticker = Ticker::new(burst, SECOND, None, clock.clone());
+        assert!(ticker.wait(sleeper(&clock, Duration::ZERO)));
+        clock.advance(SECOND * 15);
+        for _ in 0..3 {
+            assert!(ticker.wait(|_| panic!("overdue ticks must not wait")));
+        }
+        assert_eq!(ticker.missed(), 2);
+    }
+
+    #[test]
+    fn test_stopped_sleep_ends_the_run() {
+        let clock = MockClock::new();
+        let mut ticker = Ticker::new(Schedule::Steady, SECOND, None, clock.clone());
+        assert!(ticker.wait(|_| false));
+        assert!(!ticker.wait(|_| false));
+        assert_eq!(ticker.sent(), 1);
+    }
+}
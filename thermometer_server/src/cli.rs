@@ -0,0 +1,337 @@
+//! Command line of the binary: no subcommand runs the server, `check` runs
+//! [`preflight`](crate::preflight), and `query` reads the temperature from a
+//! running server's query socket once, for shell monitoring.
+
+use smart_socket_client::{ClientConfig, SmartSocketClient};
+use smart_socket_protocol::{Address, ProtocolError, Response};
+use std::fmt;
+use std::time::Duration;
+
+/// The server could not be reached or stopped answering.
+pub const EXIT_UNREACHABLE: i32 = 3;
+/// The server answered, but its reading is older than `--stale-after`.
+pub const EXIT_STALE: i32 = 4;
+/// The server answered something other than a temperature.
+pub const EXIT_QUERY_FAILED: i32 = 1;
+/// The command line itself is wrong.
+pub const EXIT_USAGE: i32 = 2;
+
+pub const USAGE: &str = "Usage: thermometer_server [--verbose]\n       \
+     thermometer_server check\n       \
+     thermometer_server query [--address <host:port>] [--json] [--stale-after <secs>] [--timeout <ms>]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invocation {
+    Serve,
+    Check,
+    Query(QueryOptions),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryOptions {
+    /// The server's query socket; `None` leaves it to `THERMOMETER_QUERY_ADDRESS`.
+    pub address: Option<Address>,
+    pub json: bool,
+    /// Readings older than this count as stale, as on the server by default.
+    pub stale_after: Duration,
+    /// Limit for connecting and for the answer each.
+    pub timeout: Duration,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            address: None,
+            json: false,
+            stale_after: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Parses the arguments after the program name. Flags of the server itself,
+/// such as `--verbose`, are left to the caller; `--check` still means `check`.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Invocation, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    match args.first().map(String::as_str) {
+        Some("query") => parse_query(&args[1..]).map(Invocation::Query),
+        Some("check") => Ok(Invocation::Check),
+        Some(other) if !other.starts_with('-') => Err(format!(
+            "Unknown subcommand '{}', expected query or check",
+            other
+        )),
+        _ if args.iter().any(|arg| arg == "--check") => Ok(Invocation::Check),
+        _ => Ok(Invocation::Serve),
+    }
+}
+
+fn parse_query(args: &[String]) -> Result<QueryOptions, String> {
+    let mut options = QueryOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--address" => {
+                let value = value()?;
+                options.address = Some(value.parse().map_err(|e| format!("--address: {}", e))?);
+            }
+            "--json" => options.json = true,
+            "--stale-after" => {
+                let value = value()?;
+                options.stale_after = value
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| format!("--stale-after must be seconds, got '{}'", value))?;
+            }
+            "--timeout" => {
+                let value = value()?;
+                options.timeout = value
+                    .parse()
+                    .ok()
+                    .filter(|millis| *millis > 0)
+                    .map(Duration::from_millis)
+                    .ok_or_else(|| format!("--timeout must be milliseconds, got '{}'", value))?;
+            }
+            other => return Err(format!("Unknown option '{}' for query", other)),
+        }
+    }
+    Ok(options)
+}
+
+/// One reading as the query socket reported it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryReading {
+    pub celsius: f64,
+    pub age_secs: u64,
+    pub stale: bool,
+}
+
+impl QueryReading {
+    /// `22.5`, or `{"celsius":22.5,"age_secs":3,"stale":false}` with `json`.
+    pub fn render(&self, json: bool) -> String {
+        if json {
+            format!(
+                "{{\"celsius\":{},\"age_secs\":{},\"stale\":{}}}",
+                self.celsius, self.age_secs, self.stale
+            )
+        } else {
+            self.celsius.to_string()
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.stale {
+            EXIT_STALE
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    Unreachable(ProtocolError),
+    /// The server answered, but not with a temperature.
+    Unexpected(String),
+}
+
+impl QueryError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            QueryError::Unreachable(_) => EXIT_UNREACHABLE,
+            QueryError::Unexpected(_) => EXIT_QUERY_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Unreachable(e) => write!(f, "Thermometer unreachable: {}", e),
+            QueryError::Unexpected(reply) => write!(f, "Unexpected reply: {}", reply),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<ProtocolError> for QueryError {
+    fn from(e: ProtocolError) -> Self {
+        match e {
+            ProtocolError::ConnectionError(_)
+            | ProtocolError::ConnectionClosed
+            | ProtocolError::PeerUnreachable(_) => QueryError::Unreachable(e),
+            other => QueryError::Unexpected(other.to_string()),
+        }
+    }
+}
+
+/// Asks the query socket at `address` for `STATUS` once.
+pub fn query(address: &Address, options: &QueryOptions) -> Result<QueryReading, QueryError> {
+    let mut config = ClientConfig::default();
+    config.address = address.clone();
+    config.read_timeout = options.timeout;
+    config.write_timeout = options.timeout;
+    config.connect_timeout = options.timeout;
+    // The query socket sends no banner.
+    config.banner_timeout = None;
+    let mut client = SmartSocketClient::with_config(config)?;
+    let reply = client.get_status()?;
+    // Best effort: the answer is in already.
+    let _ = client.close();
+    match reply {
+        Response::Temperature { celsius, age_secs } => Ok(QueryReading {
+            celsius,
+            age_secs,
+            stale: Duration::from_secs(age_secs) > options.stale_after,
+        }),
+        other => Err(QueryError::Unexpected(other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{self, QueryContext};
+    use crate::{run_server, ServerConfig, ThermometerState};
+    use smart_home::devices::thermometer::Thermometer;
+    use smart_socket_protocol::clock::MockClock;
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::thread;
+    use std::time::Instant;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(args("")), Ok(Invocation::Serve));
+        assert_eq!(parse_args(args("--verbose")), Ok(Invocation::Serve));
+        assert_eq!(parse_args(args("check")), Ok(Invocation::Check));
+        assert_eq!(parse_args(args("--verbose --check")), Ok(Invocation::Check));
+        assert_eq!(
+            parse_args(args("query")),
+            Ok(Invocation::Query(QueryOptions::default()))
+        );
+        assert_eq!(
+            parse_args(args(
+                "query --address 127.0.0.1:8082 --json --stale-after 2.5 --timeout 300"
+            )),
+            Ok(Invocation::Query(QueryOptions {
+                address: Some("127.0.0.1:8082".parse().unwrap()),
+                json: true,
+                stale_after: Duration::from_millis(2500),
+                timeout: Duration::from_millis(300),
+            }))
+        );
+        for bad in [
+            "serve",
+            "query --address",
+            "query --address localhost",
+            "query --stale-after soon",
+            "query --timeout 0",
+            "query --verbose",
+        ] {
+            assert!(parse_args(args(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let reading = QueryReading {
+            celsius: 22.5,
+            age_secs: 3,
+            stale: false,
+        };
+        assert_eq!(reading.render(false), "22.5");
+        assert_eq!(
+            reading.render(true),
+            r#"{"celsius":22.5,"age_secs":3,"stale":false}"#
+        );
+        assert_eq!(reading.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_query_running_server() {
+        let any_port = || Address::Ip(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let config = ServerConfig {
+            address: any_port(),
+            query_address: Some(any_port()),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&f64::to_be_bytes(22.5), server.local_addr())
+            .unwrap();
+        let state = server.state();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while state.temperature() != 22.5 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let address = Address::Ip(server.query_addr().unwrap());
+        let reading = query(&address, &QueryOptions::default()).unwrap();
+        assert_eq!(reading.render(false), "22.5");
+        assert_eq!(reading.exit_code(), 0);
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_stale_reading() {
+        let clock = Arc::new(MockClock::new());
+        let thermometer = Thermometer::new("Attic", 18.0).unwrap();
+        let context = QueryContext {
+            state: Arc::new(ThermometerState::with_clock(
+                thermometer,
+                Arc::new(RwLock::new(Default::default())),
+                clock.clone(),
+            )),
+            name: "Attic".to_string(),
+            started_at: Instant::now(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::Ip(listener.local_addr().unwrap());
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = query::spawn(listener, context, running.clone()).unwrap();
+        clock.advance(Duration::from_secs(30));
+
+        let reading = query(&address, &QueryOptions::default()).unwrap();
+        assert_eq!(
+            reading,
+            QueryReading {
+                celsius: 18.0,
+                age_secs: 30,
+                stale: true,
+            }
+        );
+        assert_eq!(reading.exit_code(), EXIT_STALE);
+        let relaxed = QueryOptions {
+            stale_after: Duration::from_secs(60),
+            ..Default::default()
+        };
+        assert_eq!(query(&address, &relaxed).unwrap().exit_code(), 0);
+
+        running.store(false, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_server() {
+        // Bound and dropped at once, so nothing listens on it.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = query(&Address::Ip(closed), &QueryOptions::default()).unwrap_err();
+        assert!(matches!(error, QueryError::Unreachable(_)));
+        assert_eq!(error.exit_code(), EXIT_UNREACHABLE);
+    }
+}
@@ -0,0 +1,194 @@
+//! TCP control socket for changing [`RuntimeSettings`] without a restart.
+//!
+//! Requests and replies use the length-prefixed framing of the socket protocol:
+//! `GET <key>` and `SET <key> <value>` are answered with `OK:<value>` or
+//! `ERROR:<code>:<message>`. `DAILY` lists the finished days' summaries, oldest
+//! first and separated by ` | `. `TREND` is answered with
+//! `OK:<moving average>:<°C per minute>`, either being `none` until known.
+
+use crate::daily::{DailyStats, DaySummary};
+use crate::log;
+use crate::settings::SettingError;
+use crate::ThermometerState;
+use smart_socket_protocol::{read_message, write_message};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Control connections idle for longer than this are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Answers one control request; `SET` changes the settings only when the value is valid.
+pub fn handle_request(
+    request: &str,
+    state: &ThermometerState,
+    daily: &Mutex<DailyStats>,
+) -> String {
+    let settings = state.settings();
+    let parts: Vec<&str> = request.split_whitespace().collect();
+    let result = match parts[..] {
+        ["TREND"] => {
+            let trend = state.trend();
+            let value = |value: Option<f64>| value.map_or("none".to_string(), |v| format!("{:.2}", v));
+            return format!(
+                "OK:{}:{}",
+                value(trend.ema),
+                value(trend.rate_per_minute)
+            );
+        }
+        ["DAILY"] => {
+            let daily = daily.lock().unwrap_or_else(PoisonError::into_inner);
+            let days: Vec<String> = daily.finished().map(DaySummary::to_string).collect();
+            if days.is_empty() {
+                return "OK:none".to_string();
+            }
+            return format!("OK:{}", days.join(" | "));
+        }
+        ["GET", key] => settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key),
+        ["SET", key, value] => {
+            let mut settings = settings.write().unwrap_or_else(PoisonError::into_inner);
+            settings.set(key, value).and_then(|()| settings.get(key))
+        }
+        _ => {
+            return "ERROR:E_INVALID_COMMAND:Expected 'GET <key>', 'SET <key> <value>', 'DAILY' or 'TREND'"
+                .to_string()
+        }
+    };
+    match result {
+        Ok(value) => format!("OK:{}", value),
+        Err(e) => error_reply(&e),
+    }
+}
+
+fn error_reply(error: &SettingError) -> String {
+    format!("ERROR:{}:{}", error.code(), error)
+}
+
+fn serve(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    state: &ThermometerState,
+    daily: &Mutex<DailyStats>,
+) {
+    if let Err(e) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+        log(&format!("Failed to configure control connection: {}", e));
+        return;
+    }
+    while let Ok(request) = read_message(&mut stream) {
+        let reply = handle_request(&request, state, daily);
+        if request.starts_with("SET ") && reply.starts_with("OK:") {
+            log(&format!("Control {}: {} -> {}", peer, request, reply));
+        }
+        if write_message(&mut stream, &reply).is_err() {
+            break;
+        }
+    }
+}
+
+/// Serves control connections on `listener` until `running` is cleared.
+pub fn spawn(
+    listener: TcpListener,
+    state: Arc<ThermometerState>,
+    daily: Arc<Mutex<DailyStats>>,
+    running: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    listener.set_nonblocking(true)?;
+    Ok(thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let state = state.clone();
+                    let daily = daily.clone();
+                    thread::spawn(move || {
+                        if stream.set_nonblocking(false).is_ok() {
+                            serve(stream, peer, &state, &daily);
+                        }
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => log(&format!("Control socket accept error: {}", e)),
+            }
+        }
+        log("Control socket stopped");
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_home::devices::thermometer::Thermometer;
+    use std::time::{Instant, SystemTime};
+
+    fn state() -> ThermometerState {
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        ThermometerState::new(thermometer, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let state = state();
+        let daily = Mutex::new(DailyStats::default());
+        assert_eq!(handle_request("GET alert_high", &state, &daily), "OK:off");
+        assert_eq!(handle_request("SET alert_high 28", &state, &daily), "OK:28");
+        assert_eq!(handle_request("SET display_unit f", &state, &daily), "OK:F");
+        assert_eq!(state.current_settings().alert_high, Some(28.0));
+        assert_eq!(
+            handle_request("SET calibration_offset.porch -1.4", &state, &daily),
+            "OK:-1.4"
+        );
+
+        assert!(handle_request("SET alert_low 30", &state, &daily)
+            .starts_with("ERROR:E_INVALID_VALUE:"));
+        assert!(handle_request("GET colour", &state, &daily).starts_with("ERROR:E_UNKNOWN_KEY:"));
+        for malformed in ["", "GET", "SET alert_high", "DELETE alert_high", "GET a b"] {
+            assert!(
+                handle_request(malformed, &state, &daily).starts_with("ERROR:E_INVALID_COMMAND:"),
+                "{:?}",
+                malformed
+            );
+        }
+        assert_eq!(state.current_settings().alert_low, None);
+    }
+
+    #[test]
+    fn test_daily_lists_finished_days() {
+        let state = state();
+        let daily = Mutex::new(DailyStats::default());
+        assert_eq!(handle_request("DAILY", &state, &daily), "OK:none");
+
+        let day = |n: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(n * 86_400 + 3600);
+        {
+            let mut daily = daily.lock().unwrap();
+            daily.record(20.0, day(19_846));
+            daily.record(21.0, day(19_847));
+            daily.record(22.0, day(19_849));
+        }
+        assert_eq!(
+            handle_request("DAILY", &state, &daily),
+            "OK:2024-05-03: min 20.0°C at 01:00, max 20.0°C at 01:00, 1 sample \
+             | 2024-05-04: min 21.0°C at 01:00, max 21.0°C at 01:00, 1 sample \
+             | 2024-05-05: no samples"
+        );
+    }
+
+    #[test]
+    fn test_trend() {
+        let state = state();
+        let daily = Mutex::new(DailyStats::default());
+        assert_eq!(handle_request("TREND", &state, &daily), "OK:none:none");
+
+        let start = Instant::now();
+        state.record(20.0, start);
+        assert_eq!(handle_request("TREND", &state, &daily), "OK:20.00:none");
+        state.record(21.0, start + Duration::from_secs(30));
+        assert_eq!(handle_request("TREND", &state, &daily), "OK:20.20:2.00");
+    }
+}
@@ -0,0 +1,342 @@
+//! Daily minimum and maximum of the accepted readings.
+//!
+//! Days are calendar days at a fixed [`UtcOffset`]: the standard library cannot
+//! read the system time zone, so "local" means whatever offset is configured.
+//! The caller passes the time of every reading, which keeps rollover testable.
+
+use smart_socket_protocol::clock::{civil_date, epoch_secs};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// How many finished days are kept.
+pub const MAX_DAYS: usize = 7;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Offset of local time from UTC, such as `+03:00`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UtcOffset {
+    seconds: i32,
+}
+
+impl UtcOffset {
+    pub const UTC: Self = Self { seconds: 0 };
+
+    pub fn from_minutes(minutes: i32) -> Self {
+        Self {
+            seconds: minutes * 60,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00 local time; times before that count as 0.
+    fn local_seconds(self, at: SystemTime) -> i64 {
+        let utc = epoch_secs(at) as i64;
+        (utc + i64::from(self.seconds)).max(0)
+    }
+}
+
+impl FromStr for UtcOffset {
+    type Err = String;
+
+    /// Accepts `Z`, `UTC` or `±HH:MM`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid UTC offset '{}', expected e.g. +03:00 or Z", s);
+        if s == "Z" || s.eq_ignore_ascii_case("utc") {
+            return Ok(Self::UTC);
+        }
+        let (sign, rest) = match s.split_at_checked(1) {
+            Some(("+", rest)) => (1, rest),
+            Some(("-", rest)) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = rest.split_once(':').ok_or_else(invalid)?;
+        let digits = |part: &str| part.len() == 2 && part.chars().all(|c| c.is_ascii_digit());
+        if !digits(hours) || !digits(minutes) {
+            return Err(invalid());
+        }
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 14 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self::from_minutes(sign * (hours * 60 + minutes)))
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.seconds < 0 { '-' } else { '+' };
+        let minutes = self.seconds.unsigned_abs() / 60;
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+/// A calendar day, printed as `2024-05-03`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+}
+
+impl Date {
+    fn from_day_number(days: i64) -> Self {
+        let (year, month, day) = civil_date(days as u64);
+        Self { year, month, day }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// The lowest or highest reading of a day and the local time it arrived.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extreme {
+    pub celsius: f64,
+    /// Seconds since local midnight.
+    pub seconds: u32,
+}
+
+impl Extreme {
+    /// `HH:MM`.
+    pub fn time(&self) -> String {
+        format!("{:02}:{:02}", self.seconds / 3600, self.seconds / 60 % 60)
+    }
+}
+
+/// One finished day, printed as
+/// `2024-05-03: min 17.2°C at 04:12, max 26.8°C at 15:40, 8640 samples`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DaySummary {
+    pub date: Date,
+    pub samples: u64,
+    /// `None` for a day without readings.
+    pub min: Option<Extreme>,
+    pub max: Option<Extreme>,
+}
+
+impl DaySummary {
+    fn empty(date: Date) -> Self {
+        Self {
+            date,
+            samples: 0,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn add(&mut self, reading: Extreme) {
+        self.samples += 1;
+        // Ties keep the earlier reading.
+        if self.min.is_none_or(|min| reading.celsius < min.celsius) {
+            self.min = Some(reading);
+        }
+        if self.max.is_none_or(|max| reading.celsius > max.celsius) {
+            self.max = Some(reading);
+        }
+    }
+}
+
+impl fmt::Display for DaySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Some(min), Some(max)) = (self.min, self.max) else {
+            return write!(f, "{}: no samples", self.date);
+        };
+        write!(
+            f,
+            "{}: min {:.1}°C at {}, max {:.1}°C at {}, {} sample{}",
+            self.date,
+            min.celsius,
+            min.time(),
+            max.celsius,
+            max.time(),
+            self.samples,
+            if self.samples == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Accumulates the current day and keeps the last [`MAX_DAYS`] finished ones.
+#[derive(Debug, Default)]
+pub struct DailyStats {
+    offset: UtcOffset,
+    /// Day number since the epoch and what has been seen so far.
+    current: Option<(i64, DaySummary)>,
+    finished: VecDeque<DaySummary>,
+}
+
+impl DailyStats {
+    pub fn new(offset: UtcOffset) -> Self {
+        Self {
+            offset,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a reading taken at `at`. When it is the first reading of a new day,
+    /// the previous day is finished and returned, preceded by an empty summary
+    /// for every day without readings in between. A reading dated before the
+    /// current day (the clock was set back) counts towards the current day.
+    pub fn record(&mut self, celsius: f64, at: SystemTime) -> Vec<DaySummary> {
+        let local = self.offset.local_seconds(at);
+        let day = local.div_euclid(SECS_PER_DAY);
+        let reading = Extreme {
+            celsius,
+            seconds: local.rem_euclid(SECS_PER_DAY) as u32,
+        };
+
+        let mut done = Vec::new();
+        match &mut self.current {
+            Some((current, summary)) if day <= *current => summary.add(reading),
+            current => {
+                if let Some((previous, summary)) = current.take() {
+                    done.push(summary);
+                    // Only the last few gap days can still be in the history.
+                    let first_gap = (previous + 1).max(day - MAX_DAYS as i64);
+                    done.extend(
+                        (first_gap..day).map(|d| DaySummary::empty(Date::from_day_number(d))),
+                    );
+                }
+                let mut summary = DaySummary::empty(Date::from_day_number(day));
+                summary.add(reading);
+                *current = Some((day, summary));
+            }
+        }
+        for summary in &done {
+            self.push_finished(*summary);
+        }
+        done
+    }
+
+    /// Finishes the current day early, for shutdown. Returns it if any reading
+    /// arrived since the last rollover.
+    pub fn finish(&mut self) -> Option<DaySummary> {
+        let (_, summary) = self.current.take()?;
+        self.push_finished(summary);
+        Some(summary)
+    }
+
+    fn push_finished(&mut self, summary: DaySummary) {
+        if self.finished.len() == MAX_DAYS {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(summary);
+    }
+
+    /// Finished days, oldest first; the last one is normally yesterday.
+    pub fn finished(&self) -> impl Iterator<Item = &DaySummary> {
+        self.finished.iter()
+    }
+
+    /// The day being accumulated, if it has readings.
+    pub fn today(&self) -> Option<&DaySummary> {
+        self.current.as_ref().map(|(_, summary)| summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 2024-05-03T00:00:00Z.
+    const MAY_3: u64 = 1_714_694_400;
+
+    fn at(day: u64, hour: u64, minute: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(MAY_3 + day * 86_400 + hour * 3600 + minute * 60)
+    }
+
+    #[test]
+    fn test_rollover_finishes_previous_day() {
+        let mut stats = DailyStats::new(UtcOffset::UTC);
+        assert!(stats.record(19.0, at(0, 0, 30)).is_empty());
+        assert!(stats.record(17.2, at(0, 4, 12)).is_empty());
+        assert!(stats.record(26.8, at(0, 15, 40)).is_empty());
+        assert!(stats.record(17.2, at(0, 23, 59)).is_empty());
+        assert_eq!(stats.finished().count(), 0);
+
+        let done = stats.record(20.0, at(1, 0, 0));
+        assert_eq!(done.len(), 1);
+        assert_eq!(
+            done[0].to_string(),
+            "2024-05-03: min 17.2°C at 04:12, max 26.8°C at 15:40, 4 samples"
+        );
+        assert_eq!(stats.finished().collect::<Vec<_>>(), [&done[0]]);
+        assert_eq!(stats.today().unwrap().samples, 1);
+    }
+
+    #[test]
+    fn test_days_without_readings_are_reported_empty() {
+        let mut stats = DailyStats::new(UtcOffset::UTC);
+        stats.record(20.0, at(0, 12, 0));
+        let done = stats.record(21.0, at(3, 8, 0));
+        let lines: Vec<String> = done.iter().map(DaySummary::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "2024-05-03: min 20.0°C at 12:00, max 20.0°C at 12:00, 1 sample",
+                "2024-05-04: no samples",
+                "2024-05-05: no samples",
+            ]
+        );
+
+        // A long outage only fills in the days that still fit in the history.
+        let done = stats.record(22.0, at(30, 8, 0));
+        assert_eq!(done.len(), 1 + MAX_DAYS);
+        assert_eq!(stats.finished().count(), MAX_DAYS);
+        assert_eq!(
+            stats.finished().last().unwrap().to_string(),
+            "2024-06-01: no samples"
+        );
+    }
+
+    #[test]
+    fn test_single_sample_day_is_finished_on_shutdown() {
+        let mut stats = DailyStats::new(UtcOffset::UTC);
+        assert_eq!(stats.finish(), None);
+        stats.record(-3.5, at(0, 6, 5));
+        let summary = stats.finish().unwrap();
+        assert_eq!(summary.samples, 1);
+        assert_eq!(summary.min, summary.max);
+        assert_eq!(
+            summary.to_string(),
+            "2024-05-03: min -3.5°C at 06:05, max -3.5°C at 06:05, 1 sample"
+        );
+        assert_eq!(stats.today(), None);
+        assert_eq!(stats.finish(), None);
+    }
+
+    #[test]
+    fn test_days_follow_the_utc_offset() {
+        let mut stats = DailyStats::new("+03:00".parse().unwrap());
+        // 22:30 UTC on May 3 is already 01:30 on May 4 at +03:00.
+        stats.record(18.0, at(0, 20, 0));
+        let done = stats.record(19.0, at(0, 22, 30));
+        assert_eq!(done[0].date.to_string(), "2024-05-03");
+        assert_eq!(stats.today().unwrap().min.unwrap().time(), "01:30");
+
+        // A clock set back does not reopen a finished day.
+        assert!(stats.record(30.0, at(0, 20, 0)).is_empty());
+        assert_eq!(stats.today().unwrap().samples, 2);
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!("Z".parse(), Ok(UtcOffset::UTC));
+        assert_eq!("utc".parse(), Ok(UtcOffset::UTC));
+        assert_eq!("-05:30".parse(), Ok(UtcOffset::from_minutes(-330)));
+        assert_eq!(UtcOffset::from_minutes(-330).to_string(), "-05:30");
+        assert_eq!(UtcOffset::from_minutes(180).to_string(), "+03:00");
+        for invalid in [
+            "", "3", "+3:00", "+03", "+15:00", "+03:60", "03:00", "+ab:00",
+        ] {
+            assert!(invalid.parse::<UtcOffset>().is_err(), "{:?}", invalid);
+        }
+    }
+}
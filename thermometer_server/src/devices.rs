@@ -0,0 +1,113 @@
+//! Thermometers reporting to the same UDP port under their own device ids.
+//! Readings under the default id go to the server's main thermometer, which the
+//! query socket, thermostat, daily summary and recorder follow; every other id
+//! gets a thermometer of its own here, created by its first reading.
+
+use smart_home::devices::thermometer::Thermometer;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What became of a reading from another device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceOutcome {
+    /// The first reading from this id.
+    Created,
+    Updated,
+    Rejected,
+}
+
+struct Device {
+    thermometer: Thermometer,
+    last_update: Instant,
+}
+
+/// The latest reading of one device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceReading {
+    pub device_id: String,
+    pub value: f64,
+    pub age: Duration,
+}
+
+#[derive(Default)]
+pub struct Devices {
+    devices: Mutex<BTreeMap<String, Device>>,
+}
+
+impl Devices {
+    pub fn record(&self, device_id: &str, celsius: f64, now: Instant) -> DeviceOutcome {
+        let mut devices = self.devices.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(device) = devices.get_mut(device_id) {
+            if device.thermometer.set_temp(celsius).is_err() {
+                return DeviceOutcome::Rejected;
+            }
+            device.last_update = now;
+            return DeviceOutcome::Updated;
+        }
+        match Thermometer::new(device_id, celsius) {
+            Ok(thermometer) => {
+                devices.insert(
+                    device_id.to_string(),
+                    Device {
+                        thermometer,
+                        last_update: now,
+                    },
+                );
+                DeviceOutcome::Created
+            }
+            Err(_) => DeviceOutcome::Rejected,
+        }
+    }
+
+    /// Every device that has reported, sorted by id.
+    pub fn readings(&self, now: Instant) -> Vec<DeviceReading> {
+        let devices = self.devices.lock().unwrap_or_else(PoisonError::into_inner);
+        devices
+            .iter()
+            .map(|(device_id, device)| DeviceReading {
+                device_id: device_id.clone(),
+                value: device.thermometer.get_temp(),
+                age: now.saturating_duration_since(device.last_update),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices_are_created_on_first_reading() {
+        let start = Instant::now();
+        let devices = Devices::default();
+        assert_eq!(
+            devices.record("bedroom", 19.0, start),
+            DeviceOutcome::Created
+        );
+        assert_eq!(devices.record("attic", 12.5, start), DeviceOutcome::Created);
+        let later = start + Duration::from_secs(3);
+        assert_eq!(
+            devices.record("bedroom", 19.5, later),
+            DeviceOutcome::Updated
+        );
+
+        let readings = devices.readings(later + Duration::from_secs(1));
+        assert_eq!(
+            readings,
+            [
+                DeviceReading {
+                    device_id: "attic".to_string(),
+                    value: 12.5,
+                    age: Duration::from_secs(4),
+                },
+                DeviceReading {
+                    device_id: "bedroom".to_string(),
+                    value: 19.5,
+                    age: Duration::from_secs(1),
+                },
+            ]
+        );
+    }
+}
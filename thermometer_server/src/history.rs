@@ -0,0 +1,68 @@
+//! The most recent accepted readings of the default thermometer, for the
+//! `HIST:<n>` query on the UDP port. Nothing older than a single reply can
+//! return is kept.
+
+use smart_socket_protocol::clock::epoch_secs;
+use smart_socket_protocol::thermometer::{HistoryEntry, MAX_HISTORY_ENTRIES};
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+#[derive(Debug, Default)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    /// Adds a reading taken at `at`, dropping the oldest once full.
+    pub fn record(&mut self, celsius: f64, at: SystemTime) {
+        if self.entries.len() == MAX_HISTORY_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            epoch_secs: epoch_secs(at),
+            celsius,
+        });
+    }
+
+    /// Up to `count` of the most recent readings, oldest first.
+    pub fn latest(&self, count: usize) -> Vec<HistoryEntry> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries.iter().skip(skip).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latest_readings_oldest_first() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut history = History::default();
+        assert!(history.latest(5).is_empty());
+        for i in 0..MAX_HISTORY_ENTRIES as u64 + 3 {
+            history.record(i as f64, start + Duration::from_secs(i));
+        }
+
+        let latest = history.latest(2);
+        let last = MAX_HISTORY_ENTRIES as u64 + 2;
+        assert_eq!(
+            latest,
+            [
+                HistoryEntry {
+                    epoch_secs: 1_700_000_000 + last - 1,
+                    celsius: (last - 1) as f64,
+                },
+                HistoryEntry {
+                    epoch_secs: 1_700_000_000 + last,
+                    celsius: last as f64,
+                },
+            ]
+        );
+        // The three oldest were dropped.
+        let all = history.latest(usize::MAX);
+        assert_eq!(all.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(all[0].celsius, 3.0);
+    }
+}
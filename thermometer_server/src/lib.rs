@@ -0,0 +1,1406 @@
+pub mod cli;
+pub mod control;
+pub mod daily;
+pub mod devices;
+pub mod history;
+pub mod metrics;
+pub mod publisher;
+pub mod query;
+pub mod recorder;
+pub mod settings;
+mod state;
+pub mod thermostat;
+pub mod trend;
+pub mod update_log;
+
+pub use state::{AlertChange, Reading, RecordOutcome, ThermometerState};
+
+use daily::{DailyStats, DaySummary, UtcOffset};
+use devices::{DeviceOutcome, Devices};
+use history::History;
+use metrics::{PacketCounts, PacketMetrics};
+use publisher::{Outbox, PublisherConfig};
+use recorder::{RecordedReading, Recorder, RecorderConfig};
+use settings::{Alert, Calibration, DisplayUnit, RuntimeSettings};
+use smart_home::devices::thermometer::Thermometer;
+use smart_socket_protocol::logging::Logger;
+use smart_socket_protocol::thermometer::{self, Packet};
+use smart_socket_protocol::{validate_device_name, Address, ConfigError, IntoAddress};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use thermostat::ThermostatConfig;
+use trend::TrendConfig;
+use update_log::{UpdateLogAggregator, DEFAULT_LOG_INTERVAL};
+
+static LOGGER: Logger = Logger::new();
+
+pub fn log(message: &str) {
+    LOGGER.log(message);
+}
+
+/// Built with [`ServerConfig::builder`], which checks the values; the fields stay
+/// public for reading and for adjusting a config that has been built.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ServerConfig {
+    pub address: Address,
+    pub thermometer_name: String,
+    pub initial_temperature: f64,
+    pub stale_after: Duration,
+    /// Readings above / below these (°C) are logged as alerts.
+    pub alert_high: Option<f64>,
+    pub alert_low: Option<f64>,
+    pub display_unit: DisplayUnit,
+    /// Corrects every reading before it is checked and stored.
+    pub calibration: Calibration,
+    /// Per device id, overriding `calibration`.
+    pub device_calibrations: BTreeMap<String, Calibration>,
+    /// Moving average and rate-of-change window of the main thermometer.
+    pub trend: TrendConfig,
+    /// TCP address of the control socket for changing settings at runtime.
+    pub control_address: Option<Address>,
+    /// TCP address answering `STATUS`, `INFO` and `PING` in the smart socket protocol.
+    pub query_address: Option<Address>,
+    /// When set, a controller thread switches a heater socket from the readings.
+    pub thermostat: Option<ThermostatConfig>,
+    /// When set, accepted readings are appended to daily CSV or JSON-lines files.
+    pub recorder: Option<RecorderConfig>,
+    /// When set, accepted readings of every device are pushed to a collector.
+    pub publisher: Option<PublisherConfig>,
+    /// Where local days start and end for the daily min/max summary.
+    pub utc_offset: UtcOffset,
+    /// Accepted readings are summarised in one log line per interval.
+    pub log_interval: Duration,
+    /// Log every accepted reading instead of summaries, for debugging.
+    pub verbose: bool,
+    /// Device id of the main thermometer, which plain 8-byte readings are
+    /// credited to; readings under other ids go to [`Devices`].
+    pub default_device_id: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: Address::Ip(SocketAddr::from(([127, 0, 0, 1], 8081))),
+            thermometer_name: "Kitchen Thermometer".to_string(),
+            initial_temperature: 20.0,
+            stale_after: Duration::from_secs(10),
+            alert_high: None,
+            alert_low: None,
+            display_unit: DisplayUnit::default(),
+            calibration: Calibration::IDENTITY,
+            device_calibrations: BTreeMap::new(),
+            trend: TrendConfig::default(),
+            control_address: None,
+            query_address: None,
+            thermostat: None,
+            recorder: None,
+            publisher: None,
+            utc_offset: UtcOffset::UTC,
+            log_interval: DEFAULT_LOG_INTERVAL,
+            verbose: false,
+            default_device_id: "default".to_string(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Starts from the defaults.
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder {
+            config: Self::default(),
+            error: None,
+        }
+    }
+}
+
+/// Sets up a [`ServerConfig`]; nothing is checked until [`build`](Self::build).
+#[derive(Debug)]
+#[must_use]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+    /// The first address that failed to parse, reported by `build`.
+    error: Option<ConfigError>,
+}
+
+impl ServerConfigBuilder {
+    fn parse_address(&mut self, address: impl IntoAddress) -> Option<Address> {
+        match address.into_address() {
+            Ok(address) => Some(address),
+            Err(e) => {
+                self.error.get_or_insert(e);
+                None
+            }
+        }
+    }
+
+    /// The UDP address readings arrive on.
+    pub fn address(mut self, address: impl IntoAddress) -> Self {
+        if let Some(address) = self.parse_address(address) {
+            self.config.address = address;
+        }
+        self
+    }
+
+    pub fn thermometer_name(mut self, name: impl Into<String>) -> Self {
+        self.config.thermometer_name = name.into();
+        self
+    }
+
+    pub fn initial_temperature(mut self, celsius: f64) -> Self {
+        self.config.initial_temperature = celsius;
+        self
+    }
+
+    pub fn stale_after(mut self, after: Duration) -> Self {
+        self.config.stale_after = after;
+        self
+    }
+
+    pub fn alert_high(mut self, celsius: f64) -> Self {
+        self.config.alert_high = Some(celsius);
+        self
+    }
+
+    pub fn alert_low(mut self, celsius: f64) -> Self {
+        self.config.alert_low = Some(celsius);
+        self
+    }
+
+    pub fn display_unit(mut self, unit: DisplayUnit) -> Self {
+        self.config.display_unit = unit;
+        self
+    }
+
+    pub fn calibration(mut self, calibration: Calibration) -> Self {
+        self.config.calibration = calibration;
+        self
+    }
+
+    pub fn device_calibration(
+        mut self,
+        device_id: impl Into<String>,
+        calibration: Calibration,
+    ) -> Self {
+        self.config
+            .device_calibrations
+            .insert(device_id.into(), calibration);
+        self
+    }
+
+    pub fn trend(mut self, trend: TrendConfig) -> Self {
+        self.config.trend = trend;
+        self
+    }
+
+    /// Takes the thresholds, staleness, unit and calibrations from `settings`,
+    /// which the control socket can change later.
+    pub fn settings(mut self, settings: &RuntimeSettings) -> Self {
+        self.config.alert_high = settings.alert_high;
+        self.config.alert_low = settings.alert_low;
+        self.config.stale_after = settings.stale_after;
+        self.config.display_unit = settings.display_unit;
+        self.config.calibration = settings.calibration;
+        self.config.device_calibrations = settings.device_calibrations.clone();
+        self
+    }
+
+    pub fn control_address(mut self, address: impl IntoAddress) -> Self {
+        self.config.control_address = self.parse_address(address);
+        self
+    }
+
+    pub fn query_address(mut self, address: impl IntoAddress) -> Self {
+        self.config.query_address = self.parse_address(address);
+        self
+    }
+
+    pub fn thermostat(mut self, thermostat: ThermostatConfig) -> Self {
+        self.config.thermostat = Some(thermostat);
+        self
+    }
+
+    pub fn recorder(mut self, recorder: RecorderConfig) -> Self {
+        self.config.recorder = Some(recorder);
+        self
+    }
+
+    pub fn publisher(mut self, publisher: PublisherConfig) -> Self {
+        self.config.publisher = Some(publisher);
+        self
+    }
+
+    pub fn utc_offset(mut self, offset: UtcOffset) -> Self {
+        self.config.utc_offset = offset;
+        self
+    }
+
+    pub fn log_interval(mut self, interval: Duration) -> Self {
+        self.config.log_interval = interval;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    pub fn default_device_id(mut self, device_id: impl Into<String>) -> Self {
+        self.config.default_device_id = device_id.into();
+        self
+    }
+
+    /// Checks the values; binding the addresses is left to [`preflight`].
+    pub fn build(self) -> Result<ServerConfig, ConfigError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let config = self.config;
+        let invalid = |field, reason: &str| ConfigError::InvalidValue {
+            field,
+            reason: reason.to_string(),
+        };
+        validate_device_name(&config.thermometer_name)
+            .map_err(|reason| invalid("thermometer_name", &reason))?;
+        if !config.initial_temperature.is_finite() {
+            return Err(invalid("initial_temperature", "must be a finite number"));
+        }
+        for (field, threshold) in [
+            ("alert_high", config.alert_high),
+            ("alert_low", config.alert_low),
+        ] {
+            if threshold.is_some_and(|celsius| !celsius.is_finite()) {
+                return Err(invalid(field, "must be a finite number"));
+            }
+        }
+        if let (Some(low), Some(high)) = (config.alert_low, config.alert_high) {
+            if low >= high {
+                return Err(invalid("alert_low", "must be below alert_high"));
+            }
+        }
+        config
+            .calibration
+            .check()
+            .map_err(|reason| invalid("calibration", &reason))?;
+        for calibration in config.device_calibrations.values() {
+            calibration
+                .check()
+                .map_err(|reason| invalid("device_calibrations", &reason))?;
+        }
+        config
+            .trend
+            .check()
+            .map_err(|reason| invalid("trend", &reason))?;
+        if config.stale_after.is_zero() {
+            return Err(ConfigError::ZeroDuration("stale_after"));
+        }
+        if config.log_interval.is_zero() {
+            return Err(ConfigError::ZeroDuration("log_interval"));
+        }
+        if let Some(publisher) = &config.publisher {
+            if publisher.capacity == 0 {
+                return Err(invalid("publisher", "capacity must be at least 1"));
+            }
+            if publisher.reconnect_delay.is_zero() {
+                return Err(ConfigError::ZeroDuration("reconnect_delay"));
+            }
+        }
+        thermometer::check_device_id(&config.default_device_id)
+            .map_err(|reason| invalid("default_device_id", &reason))?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum PreflightError {
+    InvalidAddress { address: String, reason: String },
+    BindFailed { address: String, reason: String },
+    InvalidRecorder { path: String, reason: String },
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::InvalidAddress { address, reason } => {
+                write!(f, "Invalid address '{}': {}", address, reason)
+            }
+            PreflightError::BindFailed { address, reason } => {
+                write!(f, "Cannot bind {}: {}", address, reason)
+            }
+            PreflightError::InvalidRecorder { path, reason } => {
+                write!(f, "Cannot record readings to {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+/// Validates the configuration without starting the server, reporting every
+/// problem found. The UDP socket is bound and released immediately.
+pub fn preflight(config: &ServerConfig) -> Result<(), Vec<PreflightError>> {
+    let mut errors = Vec::new();
+
+    match config.address.to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.collect();
+            if let Err(e) = UdpSocket::bind(&addrs[..]) {
+                errors.push(PreflightError::BindFailed {
+                    address: config.address.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        Err(e) => errors.push(PreflightError::InvalidAddress {
+            address: config.address.to_string(),
+            reason: e.to_string(),
+        }),
+    }
+
+    for address in [&config.control_address, &config.query_address]
+        .into_iter()
+        .flatten()
+    {
+        match address.to_socket_addrs() {
+            Ok(addrs) => {
+                let addrs: Vec<_> = addrs.collect();
+                if let Err(e) = TcpListener::bind(&addrs[..]) {
+                    errors.push(PreflightError::BindFailed {
+                        address: address.to_string(),
+                        reason: e.to_string(),
+                    });
+                }
+            }
+            Err(e) => errors.push(PreflightError::InvalidAddress {
+                address: address.to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    if let Some(recorder) = &config.recorder {
+        if let Err(reason) = recorder::RecordFormat::from_path(&recorder.path)
+            .and_then(|_| recorder::check_directory(&recorder.path))
+        {
+            errors.push(PreflightError::InvalidRecorder {
+                path: recorder.path.display().to_string(),
+                reason,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Handle to a running thermometer server.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    state: Arc<ThermometerState>,
+    devices: Arc<Devices>,
+    daily: Arc<Mutex<DailyStats>>,
+    packets: Arc<PacketMetrics>,
+    handle: JoinHandle<()>,
+    thermostat: Option<JoinHandle<()>>,
+    publisher: Option<JoinHandle<()>>,
+    control: Option<(SocketAddr, JoinHandle<()>)>,
+    query: Option<(SocketAddr, JoinHandle<()>)>,
+}
+
+impl ServerHandle {
+    /// Address the UDP socket is actually bound to (useful when binding to port 0).
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn state(&self) -> Arc<ThermometerState> {
+        Arc::clone(&self.state)
+    }
+
+    /// Thermometers reporting under ids other than the default one.
+    pub fn devices(&self) -> Arc<Devices> {
+        Arc::clone(&self.devices)
+    }
+
+    /// Finished days, oldest first, up to [`daily::MAX_DAYS`].
+    pub fn daily_stats(&self) -> Vec<DaySummary> {
+        let daily = self.daily.lock().unwrap_or_else(PoisonError::into_inner);
+        daily.finished().copied().collect()
+    }
+
+    /// The day being accumulated, if any reading has arrived since it started.
+    pub fn today(&self) -> Option<DaySummary> {
+        let daily = self.daily.lock().unwrap_or_else(PoisonError::into_inner);
+        daily.today().copied()
+    }
+
+    /// Datagrams received so far, by kind.
+    pub fn packet_counts(&self) -> PacketCounts {
+        self.packets.snapshot()
+    }
+
+    /// Address of the control socket, if one was configured.
+    pub fn control_addr(&self) -> Option<SocketAddr> {
+        self.control.as_ref().map(|(addr, _)| *addr)
+    }
+
+    /// Address of the query socket, if one was configured.
+    pub fn query_addr(&self) -> Option<SocketAddr> {
+        self.query.as_ref().map(|(addr, _)| *addr)
+    }
+
+    /// Waits for the listener (and thermostat, publisher, control and query) threads
+    /// to exit. They stop once the `running` flag passed to [`run_server`] is cleared.
+    pub fn join(self) -> thread::Result<()> {
+        if let Some(thermostat) = self.thermostat {
+            thermostat.join()?;
+        }
+        if let Some(publisher) = self.publisher {
+            publisher.join()?;
+        }
+        if let Some((_, control)) = self.control {
+            control.join()?;
+        }
+        if let Some((_, query)) = self.query {
+            query.join()?;
+        }
+        self.handle.join()
+    }
+}
+
+/// Formats a calibrated reading, with the raw value if calibration changed it.
+fn format_reading(unit: DisplayUnit, temperature: f64, raw: f64) -> String {
+    if temperature == raw {
+        unit.format(temperature)
+    } else {
+        format!("{} (raw {})", unit.format(temperature), unit.format(raw))
+    }
+}
+
+/// Calibrates a raw reading of the main thermometer, `device_id`, then checks
+/// and stores it. Returns the outcome and the calibrated temperature; the range
+/// check of the thermometer applies to the calibrated value.
+pub fn handle_temperature_update(
+    device_id: &str,
+    raw: f64,
+    addr: SocketAddr,
+    state: &ThermometerState,
+) -> (RecordOutcome, f64) {
+    let settings = state.current_settings();
+    let temperature = settings.calibration_for(device_id).apply(raw);
+    let outcome = state.record(temperature, state.now());
+    let shown = settings.display_unit.format(temperature);
+    if outcome == RecordOutcome::Rejected {
+        log(&format!(
+            "Rejected temperature update from {}: {}",
+            addr,
+            format_reading(settings.display_unit, temperature, raw)
+        ));
+        return (outcome, temperature);
+    }
+    if outcome == RecordOutcome::Recovered {
+        log("Temperature reading is fresh again");
+    }
+    let threshold =
+        |value: Option<f64>| value.map_or_else(String::new, |v| settings.display_unit.format(v));
+    match state.update_alert(temperature) {
+        Some(AlertChange::Raised(Alert::High)) => log(&format!(
+            "Alert: temperature {} is above {}",
+            shown,
+            threshold(settings.alert_high)
+        )),
+        Some(AlertChange::Raised(Alert::Low)) => log(&format!(
+            "Alert: temperature {} is below {}",
+            shown,
+            threshold(settings.alert_low)
+        )),
+        Some(AlertChange::Cleared) => log(&format!("Alert cleared: temperature back to {}", shown)),
+        None => {}
+    }
+    (outcome, temperature)
+}
+
+/// Where accepted readings are logged: one line each, or summarised.
+enum UpdateLog {
+    Verbose,
+    Summarised(UpdateLogAggregator),
+}
+
+impl UpdateLog {
+    /// Logs the raw reading next to the calibrated one in verbose mode only.
+    fn record(
+        &mut self,
+        device_id: &str,
+        temperature: f64,
+        raw: f64,
+        addr: SocketAddr,
+        state: &ThermometerState,
+    ) {
+        let unit = state.current_settings().display_unit;
+        match self {
+            UpdateLog::Verbose => log(&format!(
+                "Received temperature update for {} from {}: {}",
+                device_id,
+                addr,
+                format_reading(unit, temperature, raw)
+            )),
+            UpdateLog::Summarised(aggregator) => {
+                if let Some(summary) = aggregator.record(device_id, temperature, state.now()) {
+                    log(&summary.describe(unit));
+                }
+            }
+        }
+    }
+
+    /// Logs the summary if its interval is over; with `flush`, whatever has accumulated.
+    fn emit_due(&mut self, state: &ThermometerState, flush: bool) {
+        if let UpdateLog::Summarised(aggregator) = self {
+            let now = state.now();
+            let summary = if flush {
+                aggregator.flush(now)
+            } else {
+                aggregator.tick(now)
+            };
+            if let Some(summary) = summary {
+                log(&summary.describe(state.current_settings().display_unit));
+            }
+        }
+    }
+}
+
+/// Where the default thermometer's accepted readings go besides its state.
+struct ReadingSinks {
+    daily: Arc<Mutex<DailyStats>>,
+    recorder: Option<Recorder<recorder::FileSink>>,
+    history: History,
+    publisher: Option<Arc<Outbox>>,
+    updates: UpdateLog,
+}
+
+impl ReadingSinks {
+    fn publish(&self, device_id: &str, temperature: f64, state: &ThermometerState) {
+        if let Some(publisher) = &self.publisher {
+            publisher.offer(device_id, temperature, state.system_time(), state.now());
+        }
+    }
+}
+
+/// Updates the state and, unless the reading was rejected, logs it, adds it to
+/// the daily stats and the history and hands it to the recorder and publisher.
+fn accept_reading(
+    device_id: &str,
+    raw: f64,
+    addr: SocketAddr,
+    state: &ThermometerState,
+    sinks: &mut ReadingSinks,
+) {
+    let (outcome, temperature) = handle_temperature_update(device_id, raw, addr, state);
+    if outcome == RecordOutcome::Rejected {
+        return;
+    }
+    sinks
+        .updates
+        .record(device_id, temperature, raw, addr, state);
+    let now = state.system_time();
+    sinks.history.record(temperature, now);
+    sinks.publish(device_id, temperature, state);
+    let finished = sinks
+        .daily
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(temperature, now);
+    for summary in finished {
+        log(&summary.to_string());
+    }
+    if let Some(recorder) = &mut sinks.recorder {
+        recorder.record(&RecordedReading::at(addr, temperature, now), state.now());
+    }
+}
+
+/// Answers a `HIST:<n>` query with the latest readings, or `ERR:too_many` if
+/// they might not fit in one datagram.
+fn history_reply(history: &History, count: usize) -> Vec<u8> {
+    if count > thermometer::MAX_HISTORY_ENTRIES {
+        return thermometer::encode_error(thermometer::TOO_MANY);
+    }
+    thermometer::encode_history_reply(&history.latest(count))
+        .expect("no more than MAX_HISTORY_ENTRIES readings")
+}
+
+/// Updates the thermometer of a device other than the default one.
+fn accept_device_reading(
+    device_id: &str,
+    raw: f64,
+    addr: SocketAddr,
+    devices: &Devices,
+    state: &ThermometerState,
+    sinks: &mut ReadingSinks,
+) {
+    let settings = state.current_settings();
+    let temperature = settings.calibration_for(device_id).apply(raw);
+    match devices.record(device_id, temperature, state.now()) {
+        DeviceOutcome::Rejected => {
+            log(&format!(
+                "Rejected temperature update for {} from {}: {}",
+                device_id,
+                addr,
+                format_reading(settings.display_unit, temperature, raw)
+            ));
+            return;
+        }
+        DeviceOutcome::Created => log(&format!(
+            "Thermometer {} started reporting from {}",
+            device_id, addr
+        )),
+        DeviceOutcome::Updated => {}
+    }
+    sinks
+        .updates
+        .record(device_id, temperature, raw, addr, state);
+    sinks.publish(device_id, temperature, state);
+}
+
+fn check_staleness(state: &ThermometerState) {
+    if state.check_staleness(state.now()) {
+        log(&format!(
+            "Warning: no temperature update for {:?}, reading is stale",
+            state.stale_after()
+        ));
+    }
+}
+
+/// Binds the UDP socket (and the control and query sockets, if configured) and spawns the listener thread.
+pub fn run_server(
+    config: ServerConfig,
+    running: Arc<AtomicBool>,
+) -> Result<ServerHandle, Box<dyn Error>> {
+    let thermometer = Thermometer::new(&config.thermometer_name, config.initial_temperature)?;
+    let settings = Arc::new(RwLock::new(RuntimeSettings {
+        alert_high: config.alert_high,
+        alert_low: config.alert_low,
+        stale_after: config.stale_after,
+        display_unit: config.display_unit,
+        calibration: config.calibration,
+        device_calibrations: config.device_calibrations,
+    }));
+    let state = Arc::new(
+        ThermometerState::with_settings(thermometer, settings, Instant::now())
+            .with_trend(config.trend),
+    );
+
+    let daily = Arc::new(Mutex::new(DailyStats::new(config.utc_offset)));
+    let devices = Arc::new(Devices::default());
+
+    let socket = UdpSocket::bind(&config.address)?;
+    socket.set_nonblocking(true)?;
+    let local_addr = socket.local_addr()?;
+
+    let control = match &config.control_address {
+        Some(address) => {
+            let listener = TcpListener::bind(address)?;
+            let control_addr = listener.local_addr()?;
+            log(&format!("Control socket listening on {}", control_addr));
+            Some((
+                control_addr,
+                control::spawn(listener, state.clone(), daily.clone(), running.clone())?,
+            ))
+        }
+        None => None,
+    };
+
+    let query = match &config.query_address {
+        Some(address) => {
+            let listener = TcpListener::bind(address)?;
+            let query_addr = listener.local_addr()?;
+            log(&format!("Query socket listening on {}", query_addr));
+            let context = query::QueryContext {
+                state: state.clone(),
+                name: config.thermometer_name.clone(),
+                started_at: state.now(),
+            };
+            Some((
+                query_addr,
+                query::spawn(listener, context, running.clone())?,
+            ))
+        }
+        None => None,
+    };
+
+    let state_clone = state.clone();
+    let devices_clone = devices.clone();
+    let packets = Arc::new(PacketMetrics::default());
+    let packets_clone = packets.clone();
+    let default_device_id = config.default_device_id;
+    if let Some(recorder) = &config.recorder {
+        log(&format!(
+            "Recording readings to daily files based on {}",
+            recorder.path.display()
+        ));
+    }
+    let publisher = config.publisher.map(|publisher| {
+        log(&format!(
+            "Publishing readings to collector {}",
+            publisher.collector
+        ));
+        publisher::spawn(publisher, running.clone())
+    });
+    let (outbox, publisher) = publisher.unzip();
+    let mut sinks = ReadingSinks {
+        daily: daily.clone(),
+        recorder: config.recorder.map(Recorder::open).transpose()?,
+        history: History::default(),
+        publisher: outbox,
+        updates: if config.verbose {
+            UpdateLog::Verbose
+        } else {
+            UpdateLog::Summarised(UpdateLogAggregator::new(config.log_interval))
+        },
+    };
+
+    let thermostat = config.thermostat.map(|thermostat| {
+        log(&format!(
+            "Thermostat enabled: setpoint {:.1}°C, hysteresis {:.1}°C, heater at {}",
+            thermostat.setpoint, thermostat.hysteresis, thermostat.socket_address
+        ));
+        thermostat::spawn(thermostat, state.clone(), running.clone())
+    });
+
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; thermometer::RECV_BUFFER_LEN];
+        while running.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                Ok((size, addr)) => {
+                    let decoded = thermometer::decode(&buf[..size]);
+                    packets_clone.count(decoded.as_ref());
+                    match decoded {
+                        Some(Packet::Reading(temperature)) => {
+                            accept_reading(
+                                &default_device_id,
+                                temperature,
+                                addr,
+                                &state_clone,
+                                &mut sinks,
+                            );
+                        }
+                        Some(Packet::DeviceReading {
+                            device_id,
+                            temperature,
+                        }) if device_id == default_device_id => {
+                            accept_reading(&device_id, temperature, addr, &state_clone, &mut sinks);
+                        }
+                        Some(Packet::DeviceReading {
+                            device_id,
+                            temperature,
+                        }) => {
+                            accept_device_reading(
+                                &device_id,
+                                temperature,
+                                addr,
+                                &devices_clone,
+                                &state_clone,
+                                &mut sinks,
+                            );
+                        }
+                        Some(Packet::Reliable { seq, temperature }) => {
+                            accept_reading(
+                                &default_device_id,
+                                temperature,
+                                addr,
+                                &state_clone,
+                                &mut sinks,
+                            );
+                            if let Err(e) = socket.send_to(&thermometer::encode_ack(seq), addr) {
+                                log(&format!(
+                                    "Failed to acknowledge reading {} from {}: {}",
+                                    seq, addr, e
+                                ));
+                            }
+                        }
+                        Some(Packet::HistoryRequest { count }) => {
+                            let reply = history_reply(&sinks.history, count);
+                            if let Err(e) = socket.send_to(&reply, addr) {
+                                log(&format!(
+                                    "Failed to answer history query from {}: {}",
+                                    addr, e
+                                ));
+                            }
+                        }
+                        None => log(&format!(
+                            "Ignoring packet of unknown format, {} bytes from {}",
+                            size, addr
+                        )),
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    check_staleness(&state_clone);
+                    sinks.updates.emit_due(&state_clone, false);
+                    if let Some(recorder) = &mut sinks.recorder {
+                        recorder.tick(state_clone.now());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+                Err(e) => log(&format!("Error receiving data: {}", e)),
+            }
+        }
+        sinks.updates.emit_due(&state_clone, true);
+        if let Some(recorder) = &mut sinks.recorder {
+            recorder.flush(state_clone.now());
+        }
+        let today = sinks
+            .daily
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .finish();
+        if let Some(summary) = today {
+            log(&summary.to_string());
+        }
+        log("UDP listener thread stopped");
+    });
+
+    log(&format!("Thermometer server is running on {}", local_addr));
+
+    Ok(ServerHandle {
+        local_addr,
+        state,
+        devices,
+        daily,
+        packets,
+        handle,
+        thermostat,
+        publisher,
+        control,
+        query,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::{Clock, MockClock};
+    use std::time::SystemTime;
+    use thermometer::HistoryEntry;
+
+    fn any_port() -> Address {
+        Address::Ip(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+
+    #[test]
+    fn test_handle_temperature_update() {
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        let state = ThermometerState::new(thermometer, Duration::from_secs(10));
+        let addr: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+
+        handle_temperature_update("default", 25.5, addr, &state);
+
+        let temp = state.temperature();
+        assert_eq!(temp, 25.5);
+    }
+
+    #[test]
+    fn test_calibration_applies_before_the_range_check() {
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        let state = ThermometerState::new(thermometer, Duration::from_secs(10));
+        let addr: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let set =
+            |key: &str, value: &str| state.settings().write().unwrap().set(key, value).unwrap();
+
+        // Out of range raw, in range once calibrated.
+        set("calibration_offset", "-999980");
+        let (outcome, celsius) = handle_temperature_update("default", 1e6, addr, &state);
+        assert_eq!((outcome, celsius), (RecordOutcome::Accepted, 20.0));
+
+        // In range raw, out of range once calibrated.
+        set("calibration_offset.default", "0");
+        set("calibration_scale.default", "100000");
+        let (outcome, celsius) = handle_temperature_update("default", 21.0, addr, &state);
+        assert_eq!((outcome, celsius), (RecordOutcome::Rejected, 2.1e6));
+        assert_eq!(state.temperature(), 20.0);
+    }
+
+    #[test]
+    fn test_readings_are_stamped_by_the_state_clock() {
+        let clock = MockClock::new();
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        let state = ThermometerState::with_clock(
+            thermometer,
+            Arc::new(RwLock::new(Default::default())),
+            Arc::new(clock.clone()),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("readings.csv");
+        let mut recorder = RecorderConfig::new(base.clone());
+        recorder.flush_every = 1;
+        let outbox = Arc::new(Outbox::new(Duration::from_secs(5), 10));
+        let mut sinks = ReadingSinks {
+            daily: Arc::new(Mutex::new(DailyStats::new(UtcOffset::UTC))),
+            recorder: Some(Recorder::open(recorder).unwrap()),
+            history: History::default(),
+            publisher: Some(outbox.clone()),
+            updates: UpdateLog::Verbose,
+        };
+        let devices = Devices::default();
+        let addr: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+
+        accept_reading("default", 21.5, addr, &state, &mut sinks);
+        clock.advance(Duration::from_secs(2));
+        accept_device_reading("attic", 18.0, addr, &devices, &state, &mut sinks);
+        clock.advance(Duration::from_secs(1));
+        // Within the publisher's interval of the first one by the mock clock.
+        accept_reading("default", 22.0, addr, &state, &mut sinks);
+
+        let start = MockClock::START;
+        assert_eq!(
+            sinks.history.latest(2),
+            vec![
+                HistoryEntry {
+                    epoch_secs: start,
+                    celsius: 21.5
+                },
+                HistoryEntry {
+                    epoch_secs: start + 3,
+                    celsius: 22.0
+                },
+            ]
+        );
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(devices.readings(clock.now())[0].age, Duration::from_secs(1));
+        assert_eq!(state.current_reading().age, Duration::ZERO);
+        let today = sinks.daily.lock().unwrap().today().copied().unwrap();
+        assert_eq!(today.samples, 2);
+        assert_eq!(today.max.unwrap().time(), "12:00");
+        let recorded = std::fs::read_to_string(recorder::daily_path(&base, start)).unwrap();
+        assert_eq!(
+            recorded,
+            format!("{},{},21.5\n{},{},22\n", start, addr, start + 3, addr)
+        );
+    }
+
+    #[test]
+    fn test_server_config_default() {
+        let config = ServerConfig::default();
+        assert_eq!(config.address.to_string(), "127.0.0.1:8081");
+        assert_eq!(config.thermometer_name, "Kitchen Thermometer");
+        assert_eq!(config.initial_temperature, 20.0);
+        assert_eq!(config.stale_after, Duration::from_secs(10));
+        assert!(config.thermostat.is_none());
+    }
+
+    #[test]
+    fn test_builder_defaults_and_overrides() {
+        let config = ServerConfig::builder().build().unwrap();
+        assert_eq!(config.address, ServerConfig::default().address);
+        assert_eq!(config.log_interval, DEFAULT_LOG_INTERVAL);
+
+        let settings = RuntimeSettings {
+            alert_high: Some(30.0),
+            display_unit: DisplayUnit::Fahrenheit,
+            ..Default::default()
+        };
+        let config = ServerConfig::builder()
+            .address("0.0.0.0:9081")
+            .thermometer_name("Porch")
+            .settings(&settings)
+            .alert_low(5.0)
+            .query_address("127.0.0.1:9082")
+            .build()
+            .unwrap();
+        assert_eq!(config.address.to_string(), "0.0.0.0:9081");
+        assert_eq!(config.thermometer_name, "Porch");
+        assert_eq!(
+            (config.alert_low, config.alert_high),
+            (Some(5.0), Some(30.0))
+        );
+        assert_eq!(config.display_unit, DisplayUnit::Fahrenheit);
+        assert_eq!(config.query_address.unwrap().to_string(), "127.0.0.1:9082");
+        assert!(config.control_address.is_none());
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_values() {
+        let error = |builder: ServerConfigBuilder| builder.build().unwrap_err();
+        assert_eq!(
+            error(ServerConfig::builder().control_address("localhost")),
+            ConfigError::MissingPort("localhost".to_string())
+        );
+        assert_eq!(
+            error(ServerConfig::builder().thermometer_name("")).to_string(),
+            "Invalid thermometer_name: Name must be 1 to 64 characters long"
+        );
+        assert_eq!(
+            error(ServerConfig::builder().stale_after(Duration::ZERO)),
+            ConfigError::ZeroDuration("stale_after")
+        );
+        assert_eq!(
+            error(ServerConfig::builder().log_interval(Duration::ZERO)),
+            ConfigError::ZeroDuration("log_interval")
+        );
+        assert_eq!(
+            error(ServerConfig::builder().alert_low(25.0).alert_high(20.0)).to_string(),
+            "Invalid alert_low: must be below alert_high"
+        );
+        assert_eq!(
+            error(ServerConfig::builder().trend(TrendConfig {
+                alpha: 0.0,
+                ..Default::default()
+            }))
+            .to_string(),
+            "Invalid trend: alpha must be greater than 0 and at most 1"
+        );
+        assert!(matches!(
+            error(ServerConfig::builder().default_device_id("")),
+            ConfigError::InvalidValue {
+                field: "default_device_id",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_preflight_accepts_valid_config() {
+        let config = ServerConfig {
+            address: any_port(),
+            ..Default::default()
+        };
+        assert!(preflight(&config).is_ok());
+    }
+
+    #[test]
+    fn test_preflight_reports_occupied_port() {
+        let blocker = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ServerConfig {
+            address: blocker.local_addr().unwrap().into(),
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert!(matches!(errors[..], [PreflightError::BindFailed { .. }]));
+    }
+
+    #[test]
+    fn test_preflight_reports_bad_recorder_path() {
+        for path in ["readings.txt", "/no/such/dir/readings.csv"] {
+            let config = ServerConfig {
+                address: any_port(),
+                recorder: Some(RecorderConfig::new(path.into())),
+                ..Default::default()
+            };
+            let errors = preflight(&config).unwrap_err();
+            assert!(matches!(
+                errors[..],
+                [PreflightError::InvalidRecorder { .. }]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_preflight_reports_bad_address() {
+        // Reserved by RFC 6761, so it never resolves.
+        let config = ServerConfig {
+            address: "thermometer.invalid:8081".parse().unwrap(),
+            ..Default::default()
+        };
+        let errors = preflight(&config).unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [PreflightError::InvalidAddress { .. }]
+        ));
+    }
+
+    fn wait_for_temp(state: &ThermometerState, expected: f64) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if state.temperature() == expected {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_run_server_end_to_end() {
+        let config = ServerConfig {
+            address: any_port(),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+        let state = server.state();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for temperature in [18.5, 21.0, 23.25] {
+            sender
+                .send_to(&f64::to_be_bytes(temperature), server.local_addr())
+                .unwrap();
+            assert!(wait_for_temp(&state, temperature));
+        }
+
+        // Reliable readings are applied and acknowledged.
+        sender
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        sender
+            .send_to(
+                &thermometer::encode_reliable(42, 19.75),
+                server.local_addr(),
+            )
+            .unwrap();
+        let mut ack = [0u8; 16];
+        let (size, _) = sender.recv_from(&mut ack).unwrap();
+        assert_eq!(thermometer::decode_ack(&ack[..size]), Some(42));
+        assert!(wait_for_temp(&state, 19.75));
+        sender
+            .send_to(&f64::to_be_bytes(23.25), server.local_addr())
+            .unwrap();
+        assert!(wait_for_temp(&state, 23.25));
+
+        // Datagrams of the wrong size are ignored.
+        sender.send_to(&[1, 2, 3], server.local_addr()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(state.temperature(), 23.25);
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_datagrams_of_unknown_size_are_counted() {
+        let config = ServerConfig {
+            address: any_port(),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for size in [7, 9, 64] {
+            sender
+                .send_to(&vec![0xAA; size], server.local_addr())
+                .unwrap();
+        }
+        sender
+            .send_to(&f64::to_be_bytes(21.5), server.local_addr())
+            .unwrap();
+        assert!(wait_for_temp(&server.state(), 21.5));
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while server.packet_counts().total() < 4 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            server.packet_counts(),
+            PacketCounts {
+                readings: 1,
+                queries: 0,
+                unknown: 3,
+            }
+        );
+        assert_eq!(server.state().accepted(), 1);
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_device_ids_share_one_port() {
+        let config = ServerConfig {
+            address: any_port(),
+            default_device_id: "kitchen".to_string(),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+        let (state, devices) = (server.state(), server.devices());
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send = |device_id: &str, temperature: f64| {
+            let packet = thermometer::encode_device_reading(device_id, temperature).unwrap();
+            sender.send_to(&packet, server.local_addr()).unwrap();
+        };
+
+        send("bedroom", 18.5);
+        send("attic", 12.0);
+        send("bedroom", 19.0);
+        // The default id, with or without the header, is the main thermometer.
+        send("kitchen", 22.5);
+        assert!(wait_for_temp(&state, 22.5));
+        sender
+            .send_to(&f64::to_be_bytes(23.0), server.local_addr())
+            .unwrap();
+        assert!(wait_for_temp(&state, 23.0));
+
+        let latest: Vec<_> = devices
+            .readings(Instant::now())
+            .into_iter()
+            .map(|reading| (reading.device_id, reading.value))
+            .collect();
+        assert_eq!(
+            latest,
+            [("attic".to_string(), 12.0), ("bedroom".to_string(), 19.0)]
+        );
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_readings_are_published_to_the_collector() {
+        // Bound and released, so the readings have to wait for the collector.
+        let collector = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = ServerConfig {
+            address: any_port(),
+            publisher: Some(PublisherConfig {
+                collector: Address::Ip(collector),
+                reconnect_delay: Duration::from_millis(20),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let before = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let packet = thermometer::encode_device_reading("attic", 4.25).unwrap();
+        sender.send_to(&packet, server.local_addr()).unwrap();
+        sender
+            .send_to(&f64::to_be_bytes(21.5), server.local_addr())
+            .unwrap();
+        assert!(wait_for_temp(&server.state(), 21.5));
+
+        let listener = TcpListener::bind(collector).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let message = smart_socket_protocol::read_message(&mut stream).unwrap();
+            match message.parse().unwrap() {
+                smart_socket_protocol::Response::Reading {
+                    device_id,
+                    celsius,
+                    epoch_secs,
+                } => {
+                    assert!(epoch_secs >= before, "{}", message);
+                    received.push((device_id, celsius));
+                }
+                other => panic!("Expected a reading, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            received,
+            [("attic".to_string(), 4.25), ("default".to_string(), 21.5)]
+        );
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    fn control_request(stream: &mut std::net::TcpStream, request: &str) -> String {
+        use std::io::Write;
+        stream
+            .write_all(&smart_socket_protocol::serialize_message(request).unwrap())
+            .unwrap();
+        smart_socket_protocol::read_message(stream).unwrap()
+    }
+
+    fn wait_for_alert(state: &ThermometerState, expected: Option<Alert>) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if state.alert() == expected {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_udp_reading_is_served_over_tcp() {
+        let config = ServerConfig {
+            address: any_port(),
+            query_address: Some(any_port()),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(&f64::to_be_bytes(24.75), server.local_addr())
+            .unwrap();
+        assert!(wait_for_temp(&server.state(), 24.75));
+
+        let mut stream = std::net::TcpStream::connect(server.query_addr().unwrap()).unwrap();
+        let reply = control_request(&mut stream, "STATUS");
+        assert!(matches!(
+            reply.parse().unwrap(),
+            smart_socket_protocol::Response::Temperature { celsius, age_secs: 0 } if celsius == 24.75
+        ));
+        assert_eq!(control_request(&mut stream, "PING"), "PONG");
+        assert!(control_request(&mut stream, "INFO").contains("name=Kitchen Thermometer"));
+
+        drop(stream);
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_control_socket_changes_alerts() {
+        let config = ServerConfig {
+            address: any_port(),
+            control_address: Some(any_port()),
+            ..Default::default()
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server = run_server(config, running.clone()).unwrap();
+        let state = server.state();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send = |temperature: f64| {
+            sender
+                .send_to(&f64::to_be_bytes(temperature), server.local_addr())
+                .unwrap();
+            assert!(wait_for_temp(&state, temperature));
+        };
+
+        // No thresholds yet: a hot reading raises nothing.
+        send(27.0);
+        assert_eq!(state.alert(), None);
+
+        let mut control = std::net::TcpStream::connect(server.control_addr().unwrap()).unwrap();
+        assert_eq!(control_request(&mut control, "GET alert_high"), "OK:off");
+        assert_eq!(control_request(&mut control, "SET alert_high 25"), "OK:25");
+        assert!(
+            control_request(&mut control, "SET alert_low 26").starts_with("ERROR:E_INVALID_VALUE:")
+        );
+        assert!(control_request(&mut control, "GET colour").starts_with("ERROR:E_UNKNOWN_KEY:"));
+
+        send(27.5);
+        assert!(wait_for_alert(&state, Some(Alert::High)));
+
+        assert_eq!(control_request(&mut control, "SET alert_high 30"), "OK:30");
+        send(28.0);
+        assert!(wait_for_alert(&state, None));
+
+        assert_eq!(
+            control_request(&mut control, "SET stale_after 0.5"),
+            "OK:0.5"
+        );
+        assert_eq!(state.stale_after(), Duration::from_millis(500));
+
+        running.store(false, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+}
@@ -1,124 +1,184 @@
-use smart_home::devices::thermometer::Thermometer;
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, SystemTime};
-
-fn get_timestamp() -> String {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs()
-        .to_string()
-}
-
-fn log(message: &str) {
-    println!("[{}] {}", get_timestamp(), message);
-}
-
-#[derive(Debug)]
-struct ServerConfig {
-    address: String,
-    thermometer_name: String,
-    initial_temperature: f64,
+use smart_socket_protocol::shutdown::{Shutdown, EXIT_STARTUP_FAILED, GRACE_PERIOD};
+use smart_socket_protocol::systemd;
+use smart_socket_protocol::Address;
+use std::time::Duration;
+use thermometer_server::cli::{self, Invocation, QueryOptions};
+use thermometer_server::publisher::PublisherConfig;
+use thermometer_server::recorder::RecorderConfig;
+use thermometer_server::settings::RuntimeSettings;
+use thermometer_server::thermostat::ThermostatConfig;
+use thermometer_server::trend::TrendConfig;
+use thermometer_server::{log, preflight, run_server, ServerConfig};
+
+fn env_f64(name: &str, default: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(value
+            .parse()
+            .map_err(|_| format!("{} must be a number, got '{}'", name, value))?),
+        Err(_) => Ok(default),
+    }
 }
 
-impl Default for ServerConfig {
-    fn default() -> Self {
-        Self {
-            address: "127.0.0.1:8081".to_string(),
-            thermometer_name: "Kitchen Thermometer".to_string(),
-            initial_temperature: 20.0,
-        }
+fn env_address(name: &str) -> Result<Option<Address>, Box<dyn std::error::Error>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value.parse().map_err(|e| format!("{}: {}", name, e))?)),
+        Err(_) => Ok(None),
     }
 }
 
-fn handle_temperature_update(
-    temperature: f64,
-    addr: std::net::SocketAddr,
-    thermometer: &Arc<Mutex<Thermometer>>,
-) {
-    let mut thermometer = thermometer.lock().unwrap();
-    if let Ok(()) = thermometer.set_temp(temperature) {
-        log(&format!(
-            "Received temperature update from {}: {:.1}°C",
-            addr, temperature
-        ));
+/// Prints the reading or the error and exits with the matching code.
+fn run_query(options: QueryOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(address) = options
+        .address
+        .clone()
+        .or(env_address("THERMOMETER_QUERY_ADDRESS")?)
+    else {
+        eprintln!("query needs --address or THERMOMETER_QUERY_ADDRESS");
+        std::process::exit(cli::EXIT_USAGE);
+    };
+    smart_socket_client::set_logging(false);
+    match cli::query(&address, &options) {
+        Ok(reading) => {
+            println!("{}", reading.render(options.json));
+            std::process::exit(reading.exit_code());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code());
+        }
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = ServerConfig::default();
-
-    let thermometer = Thermometer::new(&config.thermometer_name, config.initial_temperature)?;
-    let thermometer = Arc::new(Mutex::new(thermometer));
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    let invocation = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
+    if let Invocation::Query(options) = invocation {
+        return run_query(options);
+    }
 
-    ctrlc::set_handler(move || {
-        log("Shutdown signal received, stopping server...");
-        r.store(false, Ordering::SeqCst);
-    })?;
+    let mut builder =
+        ServerConfig::builder().verbose(std::env::args().any(|arg| arg == "--verbose"));
+    if let Some(socket_address) = env_address("THERMOSTAT_SOCKET")? {
+        let defaults = ThermostatConfig::default();
+        builder = builder.thermostat(ThermostatConfig {
+            socket_address,
+            setpoint: env_f64("THERMOSTAT_SETPOINT", defaults.setpoint)?,
+            hysteresis: env_f64("THERMOSTAT_HYSTERESIS", defaults.hysteresis)?,
+            ..defaults
+        });
+    }
 
-    let socket = UdpSocket::bind(&config.address)?;
-    socket.set_nonblocking(true)?;
+    // Initial values for the settings that the control socket can change later.
+    let mut settings = RuntimeSettings {
+        stale_after: ServerConfig::default().stale_after,
+        ..Default::default()
+    };
+    for (key, variable) in [
+        ("alert_high", "THERMOMETER_ALERT_HIGH"),
+        ("alert_low", "THERMOMETER_ALERT_LOW"),
+        ("stale_after", "THERMOMETER_STALE_AFTER"),
+        ("display_unit", "THERMOMETER_DISPLAY_UNIT"),
+        ("calibration_offset", "THERMOMETER_CALIBRATION_OFFSET"),
+        ("calibration_scale", "THERMOMETER_CALIBRATION_SCALE"),
+    ] {
+        if let Ok(value) = std::env::var(variable) {
+            settings.set(key, &value)?;
+        }
+    }
+    builder = builder.settings(&settings);
+    builder = builder.log_interval(Duration::try_from_secs_f64(env_f64(
+        "THERMOMETER_LOG_INTERVAL_SECS",
+        ServerConfig::default().log_interval.as_secs_f64(),
+    )?)?);
+    let trend = TrendConfig::default();
+    builder = builder.trend(TrendConfig {
+        alpha: env_f64("THERMOMETER_TREND_ALPHA", trend.alpha)?,
+        window: Duration::try_from_secs_f64(env_f64(
+            "THERMOMETER_TREND_WINDOW_SECS",
+            trend.window.as_secs_f64(),
+        )?)?,
+    });
+    if let Ok(device_id) = std::env::var("THERMOMETER_DEFAULT_DEVICE_ID") {
+        builder = builder.default_device_id(device_id);
+    }
+    if let Some(address) = env_address("THERMOMETER_CONTROL_ADDRESS")? {
+        builder = builder.control_address(address);
+    }
+    if let Some(address) = env_address("THERMOMETER_QUERY_ADDRESS")? {
+        builder = builder.query_address(address);
+    }
+    if let Ok(value) = std::env::var("THERMOMETER_UTC_OFFSET") {
+        builder = builder.utc_offset(
+            value
+                .parse()
+                .map_err(|e| format!("THERMOMETER_UTC_OFFSET: {}", e))?,
+        );
+    }
 
-    let thermometer_clone = thermometer.clone();
-    let running_clone = running.clone();
+    if let Some(path) = std::env::var_os("THERMOMETER_RECORD") {
+        let defaults = RecorderConfig::new(path.into());
+        builder = builder.recorder(RecorderConfig {
+            flush_every: env_f64(
+                "THERMOMETER_RECORD_FLUSH_EVERY",
+                defaults.flush_every as f64,
+            )? as usize,
+            flush_interval: Duration::from_secs_f64(env_f64(
+                "THERMOMETER_RECORD_FLUSH_SECS",
+                defaults.flush_interval.as_secs_f64(),
+            )?),
+            ..defaults
+        });
+    }
+    if let Some(collector) = env_address("THERMOMETER_PUBLISH_ADDRESS")? {
+        let defaults = PublisherConfig::default();
+        builder = builder.publisher(PublisherConfig {
+            collector,
+            min_interval: Duration::try_from_secs_f64(env_f64(
+                "THERMOMETER_PUBLISH_MIN_INTERVAL_SECS",
+                defaults.min_interval.as_secs_f64(),
+            )?)?,
+            capacity: env_f64("THERMOMETER_PUBLISH_BUFFER", defaults.capacity as f64)? as usize,
+            ..defaults
+        });
+    }
+    let config = builder.build()?;
 
-    let handle = thread::spawn(move || {
-        let mut buf = [0u8; 8];
-        while running_clone.load(Ordering::SeqCst) {
-            match socket.recv_from(&mut buf) {
-                Ok((size, addr)) => {
-                    if size == 8 {
-                        let temperature = f64::from_be_bytes(buf);
-                        handle_temperature_update(temperature, addr, &thermometer_clone);
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(100));
-                    continue;
+    if invocation == Invocation::Check {
+        match preflight(&config) {
+            Ok(()) => {
+                println!("Configuration OK");
+                std::process::exit(0);
+            }
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}", error);
                 }
-                Err(e) => log(&format!("Error receiving data: {}", e)),
+                std::process::exit(EXIT_STARTUP_FAILED);
             }
         }
-        log("UDP listener thread stopped");
-    });
+    }
+
+    let shutdown = Shutdown::new();
+    let s = shutdown.clone();
+
+    ctrlc::set_handler(move || {
+        log("Shutdown signal received, stopping server...");
+        systemd::notify_or_log("STOPPING=1", log);
+        s.interrupt(GRACE_PERIOD);
+    })?;
 
-    log(&format!(
-        "Thermometer server is running on {}",
-        config.address
-    ));
+    let server = run_server(config, shutdown.running())?;
     log("Press Ctrl+C to stop the server");
+    systemd::notify_or_log("READY=1", log);
 
-    handle.join().unwrap();
+    server
+        .join()
+        .unwrap_or_else(|e| log(&format!("Server thread join error: {:?}", e)));
     log("Server shutdown complete");
     Ok(())
 }
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::SocketAddr;
-
-    #[test]
-    fn test_handle_temperature_update() {
-        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
-        let thermometer = Arc::new(Mutex::new(thermometer));
-        let addr: SocketAddr = "127.0.0.1:8081".parse().unwrap();
-
-        handle_temperature_update(25.5, addr, &thermometer);
-
-        let temp = thermometer.lock().unwrap().get_temp();
-        assert_eq!(temp, 25.5);
-    }
-
-    #[test]
-    fn test_server_config_default() {
-        let config = ServerConfig::default();
-        assert_eq!(config.address, "127.0.0.1:8081");
-        assert_eq!(config.thermometer_name, "Kitchen Thermometer");
-        assert_eq!(config.initial_temperature, 20.0);
-    }
-}
@@ -0,0 +1,82 @@
+//! Counters of the datagrams the UDP listener received, so packets in a format
+//! the server does not know leave a trace beyond a log line.
+
+use smart_socket_protocol::thermometer::Packet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Shared between the listener thread, which counts, and the [`ServerHandle`](crate::ServerHandle).
+#[derive(Debug, Default)]
+pub struct PacketMetrics {
+    readings: AtomicU64,
+    queries: AtomicU64,
+    unknown: AtomicU64,
+}
+
+/// A snapshot of [`PacketMetrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketCounts {
+    /// Datagrams decoded as a reading of any format, whether or not the value
+    /// was then in range.
+    pub readings: u64,
+    /// `HIST:<n>` queries.
+    pub queries: u64,
+    /// Datagrams that match no known format, by size or content.
+    pub unknown: u64,
+}
+
+impl PacketCounts {
+    pub fn total(&self) -> u64 {
+        self.readings + self.queries + self.unknown
+    }
+}
+
+impl PacketMetrics {
+    /// Counts one datagram by what [`decode`](smart_socket_protocol::thermometer::decode) made of it.
+    pub fn count(&self, packet: Option<&Packet>) {
+        let counter = match packet {
+            Some(Packet::HistoryRequest { .. }) => &self.queries,
+            Some(_) => &self.readings,
+            None => &self.unknown,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> PacketCounts {
+        PacketCounts {
+            readings: self.readings.load(Ordering::Relaxed),
+            queries: self.queries.load(Ordering::Relaxed),
+            unknown: self.unknown.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::thermometer;
+
+    #[test]
+    fn test_count_by_kind() {
+        let metrics = PacketMetrics::default();
+        for datagram in [
+            &[0xAA; 7][..],
+            &f64::to_be_bytes(21.5),
+            &[0xAA; 9],
+            &thermometer::encode_reliable(1, 20.0),
+            b"HIST:10",
+            &[0xAA; 64],
+        ] {
+            metrics.count(thermometer::decode(datagram).as_ref());
+        }
+        let counts = metrics.snapshot();
+        assert_eq!(
+            counts,
+            PacketCounts {
+                readings: 2,
+                queries: 1,
+                unknown: 3,
+            }
+        );
+        assert_eq!(counts.total(), 6);
+    }
+}
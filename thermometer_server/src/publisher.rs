@@ -0,0 +1,259 @@
+//! Pushes accepted readings to a collector over a TCP connection, framed like
+//! the socket protocol, as `TEMP:<device id>:<celsius>:<Unix seconds>` messages.
+//!
+//! Readings wait in a bounded buffer while the collector is unreachable; once it
+//! is full, the oldest are dropped. The buffer lives in memory only, and a
+//! reading written just before the connection broke may be lost with it.
+
+use crate::log;
+use smart_socket_protocol::clock::epoch_secs;
+use smart_socket_protocol::{write_message, Address, Response};
+use std::collections::{HashMap, VecDeque};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long the publisher waits for new readings before checking whether the
+/// server is stopping.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    /// Address the collector listens on.
+    pub collector: Address,
+    /// Readings of one device closer together than this are not published;
+    /// zero publishes every reading.
+    pub min_interval: Duration,
+    /// Most readings kept while the collector is unreachable.
+    pub capacity: usize,
+    /// Pause between connection attempts; also the connect and write timeout.
+    pub reconnect_delay: Duration,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            collector: Address::Ip(([127, 0, 0, 1], 9090).into()),
+            min_interval: Duration::ZERO,
+            capacity: 256,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    readings: VecDeque<Response>,
+    /// When each device last had a reading queued, for the minimum interval.
+    last_queued: HashMap<String, Instant>,
+    dropped: u64,
+}
+
+/// Readings waiting to be published, shared between the UDP listener and the
+/// publisher thread.
+#[derive(Debug)]
+pub struct Outbox {
+    min_interval: Duration,
+    capacity: usize,
+    pending: Mutex<Pending>,
+    queued: Condvar,
+}
+
+impl Outbox {
+    pub fn new(min_interval: Duration, capacity: usize) -> Self {
+        Self {
+            min_interval,
+            capacity,
+            pending: Mutex::default(),
+            queued: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues a reading taken at `at`, unless the device had one queued less than
+    /// the minimum interval before `now`. Returns whether it was queued.
+    pub fn offer(&self, device_id: &str, celsius: f64, at: SystemTime, now: Instant) -> bool {
+        let mut pending = self.lock();
+        if let Some(last) = pending.last_queued.get(device_id) {
+            if now.saturating_duration_since(*last) < self.min_interval {
+                return false;
+            }
+        }
+        pending.last_queued.insert(device_id.to_string(), now);
+        if pending.readings.len() >= self.capacity {
+            pending.readings.pop_front();
+            pending.dropped += 1;
+        }
+        pending.readings.push_back(Response::Reading {
+            device_id: device_id.to_string(),
+            celsius,
+            epoch_secs: epoch_secs(at),
+        });
+        self.queued.notify_all();
+        true
+    }
+
+    /// Number of readings waiting.
+    pub fn len(&self) -> usize {
+        self.lock().readings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The oldest waiting reading, waiting up to `timeout` for one to arrive. It
+    /// stays queued until [`sent`](Self::sent) is called.
+    fn peek(&self, timeout: Duration) -> Option<Response> {
+        let pending = self.lock();
+        let (pending, _) = self
+            .queued
+            .wait_timeout_while(pending, timeout, |pending| pending.readings.is_empty())
+            .unwrap_or_else(PoisonError::into_inner);
+        pending.readings.front().cloned()
+    }
+
+    /// Removes the reading returned by the last `peek`, unless the buffer overflowed
+    /// and dropped it in the meantime. Returns how many readings were dropped
+    /// since the last call.
+    fn sent(&self, reading: &Response) -> u64 {
+        let mut pending = self.lock();
+        if pending.readings.front() == Some(reading) {
+            pending.readings.pop_front();
+        }
+        std::mem::take(&mut pending.dropped)
+    }
+}
+
+fn connect(config: &PublisherConfig) -> std::io::Result<TcpStream> {
+    let mut last_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses resolved");
+    for addr in config.collector.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, config.reconnect_delay) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(config.reconnect_delay))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Publishes readings from `outbox` until `running` is cleared, reconnecting
+/// after every failure. Only the first failure of an outage is logged.
+fn run(config: &PublisherConfig, outbox: &Outbox, running: &AtomicBool) {
+    let mut stream: Option<TcpStream> = None;
+    let mut reachable = true;
+    while running.load(Ordering::SeqCst) {
+        let Some(reading) = outbox.peek(POLL_INTERVAL) else {
+            continue;
+        };
+        if stream.is_none() {
+            match connect(config) {
+                Ok(connected) => {
+                    log(&format!("Connected to collector {}", config.collector));
+                    reachable = true;
+                    stream = Some(connected);
+                }
+                Err(e) => {
+                    if reachable {
+                        log(&format!(
+                            "Cannot reach collector {}: {}; buffering up to {} readings",
+                            config.collector, e, config.capacity
+                        ));
+                        reachable = false;
+                    }
+                    thread::sleep(config.reconnect_delay);
+                    continue;
+                }
+            }
+        }
+        let connection = stream.as_mut().expect("connected above");
+        match write_message(connection, &reading.to_string()) {
+            Ok(()) => {
+                let dropped = outbox.sent(&reading);
+                if dropped > 0 {
+                    log(&format!(
+                        "Dropped {} readings while the collector was unreachable",
+                        dropped
+                    ));
+                }
+            }
+            Err(e) => {
+                log(&format!(
+                    "Lost connection to collector {}: {}",
+                    config.collector, e
+                ));
+                stream = None;
+                reachable = false;
+            }
+        }
+    }
+    log("Publisher stopped");
+}
+
+pub(crate) fn spawn(
+    config: PublisherConfig,
+    running: Arc<AtomicBool>,
+) -> (Arc<Outbox>, JoinHandle<()>) {
+    let outbox = Arc::new(Outbox::new(config.min_interval, config.capacity));
+    let o = outbox.clone();
+    let handle = thread::spawn(move || run(&config, &o, &running));
+    (outbox, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::{Clock, MockClock};
+
+    fn offered(outbox: &Outbox, device_id: &str, celsius: f64, now: Instant) -> bool {
+        outbox.offer(device_id, celsius, SystemTime::UNIX_EPOCH, now)
+    }
+
+    #[test]
+    fn test_min_interval_applies_per_device() {
+        let clock = MockClock::new();
+        let outbox = Outbox::new(Duration::from_secs(10), 8);
+        let offered =
+            |device_id, celsius| outbox.offer(device_id, celsius, clock.system_time(), clock.now());
+        assert!(offered("hall", 20.0));
+        assert!(offered("attic", 5.0));
+        clock.advance(Duration::from_secs(9));
+        assert!(!offered("hall", 20.5));
+        clock.advance(Duration::from_secs(1));
+        assert!(offered("hall", 21.0));
+        assert_eq!(outbox.len(), 3);
+        let first = outbox.peek(Duration::ZERO).unwrap();
+        assert_eq!(
+            first.to_string(),
+            format!("TEMP:hall:20:{}", MockClock::START)
+        );
+    }
+
+    #[test]
+    fn test_full_outbox_drops_the_oldest() {
+        let start = Instant::now();
+        let outbox = Outbox::new(Duration::ZERO, 2);
+        for celsius in [1.0, 2.0, 3.0] {
+            assert!(offered(&outbox, "hall", celsius, start));
+        }
+        let oldest = outbox.peek(Duration::ZERO).unwrap();
+        assert_eq!(oldest.to_string(), "TEMP:hall:2:0");
+        assert_eq!(outbox.sent(&oldest), 1);
+        assert_eq!(outbox.len(), 1);
+
+        // A reading dropped while it was being written is not removed twice.
+        let sending = outbox.peek(Duration::ZERO).unwrap();
+        for celsius in [4.0, 5.0] {
+            offered(&outbox, "hall", celsius, start);
+        }
+        assert_eq!(outbox.sent(&sending), 1);
+        assert_eq!(outbox.len(), 2);
+    }
+}
@@ -0,0 +1,140 @@
+//! TCP query socket speaking the smart socket protocol, so the same client
+//! library can read a thermometer.
+//!
+//! `STATUS` is answered with `TEMP:<celsius>:<age_secs>`; `INFO` and `PING`
+//! work as on the socket server. Other commands get `E_UNSUPPORTED`.
+
+use crate::{log, ThermometerState};
+use smart_socket_protocol::{
+    read_message, write_message, Command, DeviceInfo, ErrorCode, Response, PROTOCOL_VERSION,
+};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Query connections idle for longer than this are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Commands a thermometer answers, as listed in `INFO`.
+pub const CAPABILITIES: [&str; 3] = ["STATUS", "INFO", "PING"];
+
+/// What a query connection needs to answer: the shared state and the
+/// thermometer's identity.
+#[derive(Clone)]
+pub struct QueryContext {
+    pub state: Arc<ThermometerState>,
+    pub name: String,
+    pub started_at: Instant,
+}
+
+/// Answers one command.
+pub fn handle_command(command: &str, context: &QueryContext) -> Response {
+    match Command::from_str(command) {
+        Ok(Command::GetStatus) => {
+            let reading = context.state.current_reading();
+            Response::Temperature {
+                celsius: reading.value,
+                age_secs: reading.age.as_secs(),
+            }
+        }
+        Ok(Command::GetInfo) => Response::Info(DeviceInfo {
+            name: context.name.clone(),
+            firmware: env!("CARGO_PKG_VERSION").to_string(),
+            uptime: context.started_at.elapsed().as_secs(),
+            protocol: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|kind| kind.to_string()).collect(),
+            ..Default::default()
+        }),
+        Ok(Command::Ping) => Response::Pong,
+        Ok(other) => Response::error(
+            ErrorCode::Unsupported,
+            &format!("Thermometers do not support {}", other.kind()),
+        ),
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
+
+fn serve(mut stream: TcpStream, context: &QueryContext) {
+    if let Err(e) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+        log(&format!("Failed to configure query connection: {}", e));
+        return;
+    }
+    while let Ok(command) = read_message(&mut stream) {
+        let response = handle_command(&command, context);
+        if write_message(&mut stream, &response.to_string()).is_err() {
+            break;
+        }
+    }
+}
+
+/// Serves query connections on `listener` until `running` is cleared.
+pub fn spawn(
+    listener: TcpListener,
+    context: QueryContext,
+    running: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    listener.set_nonblocking(true)?;
+    Ok(thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let context = context.clone();
+                    thread::spawn(move || {
+                        if stream.set_nonblocking(false).is_ok() {
+                            serve(stream, &context);
+                        }
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => log(&format!("Query socket accept error: {}", e)),
+            }
+        }
+        log("Query socket stopped");
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_home::devices::thermometer::Thermometer;
+
+    fn context() -> QueryContext {
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        QueryContext {
+            state: Arc::new(ThermometerState::new(thermometer, Duration::from_secs(10))),
+            name: "Test Thermometer".to_string(),
+            started_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let context = context();
+        context.state.record(22.5, Instant::now());
+        assert_eq!(
+            handle_command("STATUS", &context).to_string(),
+            "TEMP:22.5:0"
+        );
+        assert_eq!(handle_command("PING", &context).to_string(), "PONG");
+        let Response::Info(info) = handle_command("INFO", &context) else {
+            panic!("Expected INFO");
+        };
+        assert_eq!(info.name, "Test Thermometer");
+        assert_eq!(info.capabilities, CAPABILITIES);
+
+        assert_eq!(
+            handle_command("ON", &context).to_string(),
+            "ERROR:E_UNSUPPORTED:Thermometers do not support ON"
+        );
+        assert_eq!(
+            handle_command("xyz", &context).to_string(),
+            "ERROR:Invalid command: xyz"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Appends accepted readings to CSV or JSON-lines files, one file per UTC day.
+
+use crate::log;
+use smart_socket_protocol::clock::{civil_date, epoch_secs};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Write failures are reported at most this often, so a full disk does not flood the log.
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    /// Picks the format from the extension: `.csv` or `.jsonl`.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Ok(RecordFormat::Csv),
+            Some("jsonl") => Ok(RecordFormat::JsonLines),
+            _ => Err(format!(
+                "Recorder path {:?} must end in .csv or .jsonl",
+                path
+            )),
+        }
+    }
+
+    /// One line, including the trailing newline.
+    pub fn format(self, reading: &RecordedReading) -> String {
+        match self {
+            RecordFormat::Csv => format!(
+                "{},{},{}\n",
+                reading.timestamp, reading.source, reading.celsius
+            ),
+            RecordFormat::JsonLines => format!(
+                "{{\"timestamp\":{},\"source_addr\":\"{}\",\"celsius\":{}}}\n",
+                reading.timestamp, reading.source, reading.celsius
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedReading {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub source: SocketAddr,
+    pub celsius: f64,
+}
+
+impl RecordedReading {
+    pub fn at(source: SocketAddr, celsius: f64, at: SystemTime) -> Self {
+        Self {
+            timestamp: epoch_secs(at),
+            source,
+            celsius,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    /// Base path such as `data/readings.csv`; each day goes to `data/readings-2024-05-01.csv`.
+    pub path: PathBuf,
+    /// Flush after this many buffered records...
+    pub flush_every: usize,
+    /// ...or once the oldest buffered record is this old.
+    pub flush_interval: Duration,
+}
+
+impl RecorderConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            flush_every: 100,
+            flush_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Storage behind a [`Recorder`]: receives formatted lines for a given file.
+pub trait ReadingSink: Send {
+    fn append(&mut self, path: &Path, line: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Buffered appends to the current day's file; the file is reopened when the day changes.
+#[derive(Default)]
+pub struct FileSink {
+    current: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl ReadingSink for FileSink {
+    fn append(&mut self, path: &Path, line: &str) -> io::Result<()> {
+        if self.current.as_ref().map(|(p, _)| p.as_path()) != Some(path) {
+            self.flush()?;
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.current = Some((path.to_path_buf(), BufWriter::new(file)));
+        }
+        let (_, writer) = self.current.as_mut().expect("file opened above");
+        writer.write_all(line.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, writer)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Formats readings, picks the daily file and decides when to flush.
+pub struct Recorder<S: ReadingSink> {
+    sink: S,
+    config: RecorderConfig,
+    format: RecordFormat,
+    pending: usize,
+    oldest_pending: Option<Instant>,
+    last_error_log: Option<Instant>,
+}
+
+impl Recorder<FileSink> {
+    pub fn open(config: RecorderConfig) -> Result<Self, String> {
+        Self::with_sink(config, FileSink::default())
+    }
+}
+
+impl<S: ReadingSink> Recorder<S> {
+    pub fn with_sink(config: RecorderConfig, sink: S) -> Result<Self, String> {
+        Ok(Self {
+            format: RecordFormat::from_path(&config.path)?,
+            sink,
+            config,
+            pending: 0,
+            oldest_pending: None,
+            last_error_log: None,
+        })
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Buffers `reading` for its day's file and flushes if enough records are pending.
+    pub fn record(&mut self, reading: &RecordedReading, now: Instant) {
+        let path = daily_path(&self.config.path, reading.timestamp);
+        match self.sink.append(&path, &self.format.format(reading)) {
+            Ok(()) => {
+                self.pending += 1;
+                self.oldest_pending.get_or_insert(now);
+            }
+            Err(e) => self.report(
+                &format!("Failed to record reading to {:?}: {}", path, e),
+                now,
+            ),
+        }
+        if self.pending >= self.config.flush_every {
+            self.flush(now);
+        } else {
+            self.tick(now);
+        }
+    }
+
+    /// Flushes once the oldest buffered record is older than the flush interval.
+    pub fn tick(&mut self, now: Instant) {
+        if self
+            .oldest_pending
+            .is_some_and(|oldest| now.duration_since(oldest) >= self.config.flush_interval)
+        {
+            self.flush(now);
+        }
+    }
+
+    pub fn flush(&mut self, now: Instant) {
+        if let Err(e) = self.sink.flush() {
+            self.report(&format!("Failed to flush recorded readings: {}", e), now);
+        }
+        self.pending = 0;
+        self.oldest_pending = None;
+    }
+
+    fn report(&mut self, message: &str, now: Instant) {
+        if self
+            .last_error_log
+            .is_none_or(|last| now.duration_since(last) >= ERROR_LOG_INTERVAL)
+        {
+            log(message);
+            self.last_error_log = Some(now);
+        }
+    }
+}
+
+/// `data/readings.csv` and a timestamp on 2024-05-01 give `data/readings-2024-05-01.csv`.
+pub fn daily_path(base: &Path, timestamp: u64) -> PathBuf {
+    let (year, month, day) = civil_date(timestamp / 86_400);
+    let stem = base
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut name = format!("{}-{:04}-{:02}-{:02}", stem, year, month, day);
+    if let Some(ext) = base.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    base.with_file_name(name)
+}
+
+/// Checks that the recorder's directory exists, for the preflight check.
+pub fn check_directory(path: &Path) -> Result<(), String> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match fs::metadata(dir) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        _ => Err(format!("directory {:?} does not exist", dir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-05-01T00:00:00Z.
+    const MAY_1: u64 = 1_714_521_600;
+
+    #[derive(Default)]
+    struct MemorySink {
+        lines: Vec<(PathBuf, String)>,
+        flushes: usize,
+        fail: bool,
+    }
+
+    impl ReadingSink for MemorySink {
+        fn append(&mut self, path: &Path, line: &str) -> io::Result<()> {
+            if self.fail {
+                return Err(io::Error::other("disk full"));
+            }
+            self.lines.push((path.to_path_buf(), line.to_string()));
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    fn reading(timestamp: u64, celsius: f64) -> RecordedReading {
+        RecordedReading {
+            timestamp,
+            source: "127.0.0.1:5000".parse().unwrap(),
+            celsius,
+        }
+    }
+
+    fn recorder(path: &str, flush_every: usize) -> Recorder<MemorySink> {
+        let config = RecorderConfig {
+            flush_every,
+            flush_interval: Duration::from_secs(5),
+            ..RecorderConfig::new(path.into())
+        };
+        Recorder::with_sink(config, MemorySink::default()).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        let reading = reading(MAY_1, 21.5);
+        assert_eq!(
+            RecordFormat::Csv.format(&reading),
+            "1714521600,127.0.0.1:5000,21.5\n"
+        );
+        assert_eq!(
+            RecordFormat::JsonLines.format(&reading),
+            "{\"timestamp\":1714521600,\"source_addr\":\"127.0.0.1:5000\",\"celsius\":21.5}\n"
+        );
+        assert!(RecordFormat::from_path(Path::new("readings.txt")).is_err());
+    }
+
+    #[test]
+    fn test_daily_rotation_naming() {
+        assert_eq!(
+            daily_path(Path::new("data/readings.csv"), MAY_1),
+            Path::new("data/readings-2024-05-01.csv")
+        );
+        assert_eq!(
+            daily_path(Path::new("readings.jsonl"), MAY_1 - 1),
+            Path::new("readings-2024-04-30.jsonl")
+        );
+        // Leap day.
+        assert_eq!(
+            daily_path(Path::new("r.csv"), 1_709_164_800),
+            Path::new("r-2024-02-29.csv")
+        );
+
+        let mut recorder = recorder("readings.csv", 100);
+        let now = Instant::now();
+        recorder.record(&reading(MAY_1 - 1, 20.0), now);
+        recorder.record(&reading(MAY_1, 21.0), now);
+        let paths: Vec<_> = recorder.sink().lines.iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("readings-2024-04-30.csv"),
+                Path::new("readings-2024-05-01.csv")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flush_cadence() {
+        let mut recorder = recorder("readings.csv", 3);
+        let start = Instant::now();
+
+        // Every third record triggers a flush.
+        for i in 0..7 {
+            recorder.record(&reading(MAY_1 + i, 20.0), start);
+        }
+        assert_eq!(recorder.sink().flushes, 2);
+
+        // The seventh record is flushed once it has waited for the interval.
+        recorder.tick(start + Duration::from_secs(4));
+        assert_eq!(recorder.sink().flushes, 2);
+        recorder.tick(start + Duration::from_secs(5));
+        assert_eq!(recorder.sink().flushes, 3);
+
+        // Nothing pending, nothing to flush.
+        recorder.tick(start + Duration::from_secs(60));
+        assert_eq!(recorder.sink().flushes, 3);
+    }
+
+    #[test]
+    fn test_write_failures_are_not_fatal() {
+        let mut recorder = recorder("readings.csv", 100);
+        recorder.sink.fail = true;
+        let now = Instant::now();
+        recorder.record(&reading(MAY_1, 20.0), now);
+        recorder.record(&reading(MAY_1, 20.5), now);
+        assert_eq!(recorder.last_error_log, Some(now));
+
+        recorder.sink.fail = false;
+        recorder.record(&reading(MAY_1, 21.0), now);
+        assert_eq!(recorder.sink().lines.len(), 1);
+    }
+
+    #[test]
+    fn test_file_sink_appends_to_daily_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RecorderConfig::new(dir.path().join("readings.csv"));
+        let mut recorder = Recorder::open(config).unwrap();
+        let now = Instant::now();
+        recorder.record(&reading(MAY_1, 20.0), now);
+        recorder.record(&reading(MAY_1 + 86_400, 21.0), now);
+        recorder.flush(now);
+
+        let day = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(
+            day("readings-2024-05-01.csv"),
+            "1714521600,127.0.0.1:5000,20\n"
+        );
+        assert_eq!(
+            day("readings-2024-05-02.csv"),
+            "1714608000,127.0.0.1:5000,21\n"
+        );
+    }
+}
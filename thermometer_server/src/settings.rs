@@ -0,0 +1,411 @@
+//! Settings that can be changed while the server runs, through the control socket.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Keys accepted by [`RuntimeSettings::get`] and [`RuntimeSettings::set`]. The
+/// calibration keys also take a device id, as in `calibration_offset.bedroom`.
+pub const KEYS: [&str; 6] = [
+    "alert_high",
+    "alert_low",
+    "stale_after",
+    "display_unit",
+    "calibration_offset",
+    "calibration_scale",
+];
+
+/// Correction for a sensor that reads off, applied as `raw * scale + offset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Added after scaling, in °C.
+    pub offset: f64,
+    pub scale: f64,
+}
+
+impl Calibration {
+    /// Leaves readings as they are.
+    pub const IDENTITY: Calibration = Calibration {
+        offset: 0.0,
+        scale: 1.0,
+    };
+
+    pub fn apply(&self, raw: f64) -> f64 {
+        raw * self.scale + self.offset
+    }
+
+    /// Rejects a calibration that would make every reading meaningless.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.offset.is_finite() {
+            return Err("offset must be a finite number".to_string());
+        }
+        if !(self.scale.is_finite() && self.scale > 0.0) {
+            return Err("scale must be a positive number".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplayUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl DisplayUnit {
+    /// Formats a Celsius value in this unit, e.g. `21.5°C` or `70.7°F`.
+    pub fn format(self, celsius: f64) -> String {
+        match self {
+            DisplayUnit::Celsius => format!("{:.1}°C", celsius),
+            DisplayUnit::Fahrenheit => format!("{:.1}°F", celsius * 9.0 / 5.0 + 32.0),
+        }
+    }
+}
+
+impl FromStr for DisplayUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(DisplayUnit::Celsius),
+            "f" | "fahrenheit" => Ok(DisplayUnit::Fahrenheit),
+            _ => Err("expected C or F".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DisplayUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayUnit::Celsius => write!(f, "C"),
+            DisplayUnit::Fahrenheit => write!(f, "F"),
+        }
+    }
+}
+
+/// A reading outside the configured thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alert {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingError {
+    UnknownKey(String),
+    InvalidValue { key: String, reason: String },
+}
+
+impl SettingError {
+    /// Machine-readable code sent in control socket errors.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SettingError::UnknownKey(_) => "E_UNKNOWN_KEY",
+            SettingError::InvalidValue { .. } => "E_INVALID_VALUE",
+        }
+    }
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::UnknownKey(key) => {
+                write!(
+                    f,
+                    "Unknown key '{}', expected one of {}",
+                    key,
+                    KEYS.join(", ")
+                )
+            }
+            SettingError::InvalidValue { key, reason } => {
+                write!(f, "Invalid value for {}: {}", key, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingError {}
+
+/// Thresholds and presentation read by the UDP listener for every reading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeSettings {
+    /// Readings above this (°C) raise an alert; `None` disables the check.
+    pub alert_high: Option<f64>,
+    /// Readings below this (°C) raise an alert; `None` disables the check.
+    pub alert_low: Option<f64>,
+    pub stale_after: Duration,
+    pub display_unit: DisplayUnit,
+    /// Applied to readings of every device without a calibration of its own.
+    pub calibration: Calibration,
+    pub device_calibrations: BTreeMap<String, Calibration>,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            alert_high: None,
+            alert_low: None,
+            stale_after: Duration::from_secs(10),
+            display_unit: DisplayUnit::default(),
+            calibration: Calibration::IDENTITY,
+            device_calibrations: BTreeMap::new(),
+        }
+    }
+}
+
+/// Splits `calibration_offset.bedroom` into the key and the device id.
+fn split_device_key(key: &str) -> (&str, Option<&str>) {
+    match key.split_once('.') {
+        Some((base, device_id)) if base.starts_with("calibration_") => (base, Some(device_id)),
+        _ => (key, None),
+    }
+}
+
+impl RuntimeSettings {
+    pub fn alert(&self, celsius: f64) -> Option<Alert> {
+        if self.alert_high.is_some_and(|high| celsius > high) {
+            Some(Alert::High)
+        } else if self.alert_low.is_some_and(|low| celsius < low) {
+            Some(Alert::Low)
+        } else {
+            None
+        }
+    }
+
+    /// The calibration of `device_id`, or the global one if it has none.
+    pub fn calibration_for(&self, device_id: &str) -> Calibration {
+        self.device_calibrations
+            .get(device_id)
+            .copied()
+            .unwrap_or(self.calibration)
+    }
+
+    /// Current value of `key`, formatted as `set` accepts it.
+    pub fn get(&self, key: &str) -> Result<String, SettingError> {
+        let threshold = |value: Option<f64>| value.map_or("off".to_string(), |v| v.to_string());
+        let (base, device_id) = split_device_key(key);
+        let calibration = match device_id {
+            Some(device_id) => self.calibration_for(device_id),
+            None => self.calibration,
+        };
+        match base {
+            "alert_high" if device_id.is_none() => Ok(threshold(self.alert_high)),
+            "alert_low" if device_id.is_none() => Ok(threshold(self.alert_low)),
+            "stale_after" if device_id.is_none() => Ok(self.stale_after.as_secs_f64().to_string()),
+            "display_unit" if device_id.is_none() => Ok(self.display_unit.to_string()),
+            "calibration_offset" => Ok(calibration.offset.to_string()),
+            "calibration_scale" => Ok(calibration.scale.to_string()),
+            _ => Err(SettingError::UnknownKey(key.to_string())),
+        }
+    }
+
+    /// Validates and applies `value`; on error the settings are left unchanged.
+    ///
+    /// Thresholds are °C or `off`, `stale_after` is in seconds, `display_unit` is `C` or `F`.
+    /// A device's first calibration key starts it from a copy of the global calibration.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingError> {
+        let invalid = |reason: &str| SettingError::InvalidValue {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut updated = self.clone();
+        let (base, device_id) = split_device_key(key);
+        if let "calibration_offset" | "calibration_scale" = base {
+            let number = value
+                .parse::<f64>()
+                .map_err(|_| invalid("expected a number"))?;
+            let global = updated.calibration;
+            let calibration = match device_id {
+                Some(device_id) => updated
+                    .device_calibrations
+                    .entry(device_id.to_string())
+                    .or_insert(global),
+                None => &mut updated.calibration,
+            };
+            if base == "calibration_offset" {
+                calibration.offset = number;
+            } else {
+                calibration.scale = number;
+            }
+            calibration.check().map_err(|e| invalid(&e))?;
+            *self = updated;
+            return Ok(());
+        }
+        if device_id.is_some() {
+            return Err(SettingError::UnknownKey(key.to_string()));
+        }
+        match key {
+            "alert_high" => updated.alert_high = parse_threshold(value).map_err(|e| invalid(&e))?,
+            "alert_low" => updated.alert_low = parse_threshold(value).map_err(|e| invalid(&e))?,
+            "stale_after" => {
+                updated.stale_after = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| *secs > 0.0)
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| invalid("expected a positive number of seconds"))?
+            }
+            "display_unit" => {
+                updated.display_unit = value.parse().map_err(|e: String| invalid(&e))?
+            }
+            _ => return Err(SettingError::UnknownKey(key.to_string())),
+        }
+        if let (Some(low), Some(high)) = (updated.alert_low, updated.alert_high) {
+            if low >= high {
+                return Err(invalid("alert_low must be below alert_high"));
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+}
+
+fn parse_threshold(value: &str) -> Result<Option<f64>, String> {
+    if value.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|celsius| celsius.is_finite())
+        .map(Some)
+        .ok_or_else(|| "expected a temperature in °C or 'off'".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set() {
+        let mut settings = RuntimeSettings::default();
+        assert_eq!(settings.get("alert_high").unwrap(), "off");
+
+        settings.set("alert_high", "28.5").unwrap();
+        settings.set("stale_after", "2.5").unwrap();
+        settings.set("display_unit", "f").unwrap();
+        assert_eq!(settings.get("alert_high").unwrap(), "28.5");
+        assert_eq!(settings.stale_after, Duration::from_millis(2500));
+        assert_eq!(settings.get("display_unit").unwrap(), "F");
+
+        settings.set("alert_high", "off").unwrap();
+        assert_eq!(settings.alert_high, None);
+    }
+
+    #[test]
+    fn test_invalid_values_leave_settings_unchanged() {
+        let mut settings = RuntimeSettings::default();
+        settings.set("alert_high", "25").unwrap();
+        let before = settings.clone();
+
+        for (key, value) in [
+            ("alert_low", "30"),
+            ("alert_high", "warm"),
+            ("alert_high", "NaN"),
+            ("stale_after", "0"),
+            ("stale_after", "-1"),
+            ("stale_after", "1e30"),
+            ("display_unit", "K"),
+        ] {
+            assert!(
+                matches!(
+                    settings.set(key, value),
+                    Err(SettingError::InvalidValue { .. })
+                ),
+                "{} {}",
+                key,
+                value
+            );
+        }
+        assert_eq!(settings, before);
+
+        let error = settings.set("colour", "red").unwrap_err();
+        assert_eq!(error, SettingError::UnknownKey("colour".to_string()));
+        assert_eq!(error.code(), "E_UNKNOWN_KEY");
+    }
+
+    #[test]
+    fn test_alert_thresholds() {
+        let mut settings = RuntimeSettings::default();
+        assert_eq!(settings.alert(100.0), None);
+        settings.set("alert_low", "16").unwrap();
+        settings.set("alert_high", "28").unwrap();
+        assert_eq!(settings.alert(28.5), Some(Alert::High));
+        assert_eq!(settings.alert(15.0), Some(Alert::Low));
+        assert_eq!(settings.alert(28.0), None);
+    }
+
+    #[test]
+    fn test_calibration_apply() {
+        assert_eq!(Calibration::IDENTITY.apply(21.5), 21.5);
+        let offset = Calibration {
+            offset: -1.4,
+            ..Calibration::IDENTITY
+        };
+        assert!((offset.apply(22.9) - 21.5).abs() < 1e-9);
+        let scale = Calibration {
+            scale: 0.5,
+            ..Calibration::IDENTITY
+        };
+        assert_eq!(scale.apply(40.0), 20.0);
+        let combined = Calibration {
+            offset: 2.0,
+            scale: 1.1,
+        };
+        assert!((combined.apply(10.0) - 13.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_calibration_per_device() {
+        let mut settings = RuntimeSettings::default();
+        settings.set("calibration_offset", "-1.4").unwrap();
+        settings.set("calibration_scale.attic", "1.1").unwrap();
+        assert_eq!(settings.get("calibration_offset.kitchen").unwrap(), "-1.4");
+        // The attic started from the global calibration and kept its offset.
+        assert_eq!(
+            settings.calibration_for("attic"),
+            Calibration {
+                offset: -1.4,
+                scale: 1.1
+            }
+        );
+        assert_eq!(settings.calibration_for("kitchen").scale, 1.0);
+
+        let before = settings.clone();
+        for (key, value) in [
+            ("calibration_scale", "0"),
+            ("calibration_scale.attic", "-2"),
+            ("calibration_offset", "inf"),
+            ("calibration_offset.attic", "warm"),
+        ] {
+            assert!(
+                matches!(
+                    settings.set(key, value),
+                    Err(SettingError::InvalidValue { .. })
+                ),
+                "{} {}",
+                key,
+                value
+            );
+        }
+        assert_eq!(settings, before);
+        assert!(matches!(
+            settings.set("alert_high.attic", "30"),
+            Err(SettingError::UnknownKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_display_unit_format() {
+        assert_eq!(DisplayUnit::Celsius.format(21.5), "21.5°C");
+        assert_eq!(DisplayUnit::Fahrenheit.format(100.0), "212.0°F");
+    }
+}
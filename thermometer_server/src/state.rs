@@ -0,0 +1,314 @@
+use crate::settings::{Alert, RuntimeSettings};
+use crate::trend::{Trend, TrendConfig, TrendMetrics};
+use smart_home::devices::thermometer::Thermometer;
+use smart_socket_protocol::clock::{Clock, SystemClock};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Latest temperature together with how old it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub value: f64,
+    pub age: Duration,
+    pub stale: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordOutcome {
+    Accepted,
+    /// Accepted, and the reading was stale before this update.
+    Recovered,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertChange {
+    Raised(Alert),
+    Cleared,
+}
+
+struct Inner {
+    thermometer: Thermometer,
+    last_update: Instant,
+    stale: bool,
+    alert: Option<Alert>,
+    /// Readings accepted since the server started.
+    accepted: u64,
+    trend: Trend,
+}
+
+/// Thermometer shared between the UDP listener and query interfaces.
+pub struct ThermometerState {
+    inner: Mutex<Inner>,
+    settings: Arc<RwLock<RuntimeSettings>>,
+    /// Signalled whenever a reading is accepted.
+    updated: Condvar,
+    /// Ages readings for [`current_reading`](Self::current_reading).
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+impl ThermometerState {
+    pub fn new(thermometer: Thermometer, stale_after: Duration) -> Self {
+        Self::with_start(thermometer, stale_after, Instant::now())
+    }
+
+    pub fn with_start(thermometer: Thermometer, stale_after: Duration, start: Instant) -> Self {
+        let settings = RuntimeSettings {
+            stale_after,
+            ..Default::default()
+        };
+        Self::with_settings(thermometer, Arc::new(RwLock::new(settings)), start)
+    }
+
+    pub fn with_settings(
+        thermometer: Thermometer,
+        settings: Arc<RwLock<RuntimeSettings>>,
+        start: Instant,
+    ) -> Self {
+        Self::with_clock_at(thermometer, settings, Arc::new(SystemClock), start)
+    }
+
+    /// Reads the time from `clock` instead of the system clocks, starting now.
+    pub fn with_clock(
+        thermometer: Thermometer,
+        settings: Arc<RwLock<RuntimeSettings>>,
+        clock: Arc<dyn Clock + Send + Sync>,
+    ) -> Self {
+        let start = clock.now();
+        Self::with_clock_at(thermometer, settings, clock, start)
+    }
+
+    fn with_clock_at(
+        thermometer: Thermometer,
+        settings: Arc<RwLock<RuntimeSettings>>,
+        clock: Arc<dyn Clock + Send + Sync>,
+        start: Instant,
+    ) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                thermometer,
+                last_update: start,
+                stale: false,
+                alert: None,
+                accepted: 0,
+                trend: Trend::new(TrendConfig::default()),
+            }),
+            settings,
+            updated: Condvar::new(),
+            clock,
+        }
+    }
+
+    /// The current time of the clock this state was built with.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The wall time of the same clock, for timestamps of readings.
+    pub fn system_time(&self) -> SystemTime {
+        self.clock.system_time()
+    }
+
+    /// Replaces the default moving average and rate window.
+    pub fn with_trend(mut self, config: TrendConfig) -> Self {
+        self.inner
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .trend = Trend::new(config);
+        self
+    }
+
+    /// Settings shared with the control socket; changes apply to the next reading.
+    pub fn settings(&self) -> Arc<RwLock<RuntimeSettings>> {
+        Arc::clone(&self.settings)
+    }
+
+    /// A copy of the current settings.
+    pub fn current_settings(&self) -> RuntimeSettings {
+        self.settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        self.current_settings().stale_after
+    }
+
+    /// Checks `temperature` against the current thresholds and remembers the
+    /// result; returns the change when the alert state is different from before.
+    pub fn update_alert(&self, temperature: f64) -> Option<AlertChange> {
+        let alert = self.current_settings().alert(temperature);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.alert == alert {
+            return None;
+        }
+        inner.alert = alert;
+        Some(alert.map_or(AlertChange::Cleared, AlertChange::Raised))
+    }
+
+    pub fn alert(&self) -> Option<Alert> {
+        self.inner.lock().unwrap().alert
+    }
+
+    pub fn record(&self, temperature: f64, now: Instant) -> RecordOutcome {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.thermometer.set_temp(temperature).is_err() {
+            return RecordOutcome::Rejected;
+        }
+        inner.last_update = now;
+        inner.accepted += 1;
+        inner.trend.record(temperature, now);
+        self.updated.notify_all();
+        if inner.stale {
+            inner.stale = false;
+            RecordOutcome::Recovered
+        } else {
+            RecordOutcome::Accepted
+        }
+    }
+
+    /// Number of readings accepted so far.
+    pub fn accepted(&self) -> u64 {
+        self.inner.lock().unwrap().accepted
+    }
+
+    /// Blocks until at least `count` readings have been accepted or `timeout`
+    /// has passed, and returns the number accepted.
+    pub fn wait_for_accepted(&self, count: u64, timeout: Duration) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        let (inner, _) = self
+            .updated
+            .wait_timeout_while(inner, timeout, |inner| inner.accepted < count)
+            .unwrap();
+        inner.accepted
+    }
+
+    /// Returns `true` exactly once when the reading transitions into staleness.
+    pub fn check_staleness(&self, now: Instant) -> bool {
+        let stale_after = self.stale_after();
+        let mut inner = self.inner.lock().unwrap();
+        let expired = now.saturating_duration_since(inner.last_update) > stale_after;
+        if expired && !inner.stale {
+            inner.stale = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn current_reading(&self) -> Reading {
+        self.reading_at(self.now())
+    }
+
+    pub fn reading_at(&self, now: Instant) -> Reading {
+        let stale_after = self.stale_after();
+        let inner = self.inner.lock().unwrap();
+        let age = now.saturating_duration_since(inner.last_update);
+        Reading {
+            value: inner.thermometer.get_temp(),
+            age,
+            stale: age > stale_after,
+        }
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.inner.lock().unwrap().thermometer.get_temp()
+    }
+
+    /// Moving average and rate of change of the accepted readings.
+    pub fn trend(&self) -> TrendMetrics {
+        self.inner.lock().unwrap().trend.metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_socket_protocol::clock::MockClock;
+
+    fn state(start: Instant) -> ThermometerState {
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        ThermometerState::with_start(thermometer, Duration::from_secs(5), start)
+    }
+
+    #[test]
+    fn test_fresh_reading() {
+        let start = Instant::now();
+        let state = state(start);
+
+        assert_eq!(state.record(22.0, start), RecordOutcome::Accepted);
+        let reading = state.reading_at(start + Duration::from_secs(2));
+        assert_eq!(reading.value, 22.0);
+        assert_eq!(reading.age, Duration::from_secs(2));
+        assert!(!reading.stale);
+    }
+
+    #[test]
+    fn test_wait_for_accepted_readings() {
+        let state = Arc::new(state(Instant::now()));
+        assert_eq!(state.wait_for_accepted(1, Duration::ZERO), 0);
+
+        let writer = Arc::clone(&state);
+        let handle = std::thread::spawn(move || {
+            writer.record(21.0, Instant::now());
+            writer.record(22.0, Instant::now());
+        });
+        assert_eq!(state.wait_for_accepted(2, Duration::from_secs(5)), 2);
+        handle.join().unwrap();
+        assert_eq!(state.accepted(), 2);
+    }
+
+    #[test]
+    fn test_stale_transitions_once() {
+        let clock = MockClock::new();
+        let thermometer = Thermometer::new("Test Thermometer", 20.0).unwrap();
+        let settings = RuntimeSettings {
+            stale_after: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let state = ThermometerState::with_clock(
+            thermometer,
+            Arc::new(RwLock::new(settings)),
+            Arc::new(clock.clone()),
+        );
+
+        clock.advance(Duration::from_secs(5));
+        assert!(!state.check_staleness(state.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(state.check_staleness(state.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(!state.check_staleness(state.now()));
+        assert!(state.current_reading().stale);
+        assert_eq!(state.current_reading().age, Duration::from_secs(7));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(state.record(21.0, state.now()), RecordOutcome::Recovered);
+        assert_eq!(state.record(21.5, state.now()), RecordOutcome::Accepted);
+        assert!(!state.current_reading().stale);
+        clock.advance(Duration::from_secs(1));
+        assert!(!state.check_staleness(state.now()));
+        clock.advance(Duration::from_secs(5));
+        assert!(state.check_staleness(state.now()));
+    }
+
+    #[test]
+    fn test_alert_changes_are_reported_once() {
+        let state = state(Instant::now());
+        state
+            .settings()
+            .write()
+            .unwrap()
+            .set("alert_high", "25")
+            .unwrap();
+
+        assert_eq!(state.update_alert(22.0), None);
+        assert_eq!(
+            state.update_alert(26.0),
+            Some(AlertChange::Raised(Alert::High))
+        );
+        assert_eq!(state.update_alert(27.0), None);
+        assert_eq!(state.alert(), Some(Alert::High));
+        assert_eq!(state.update_alert(24.0), Some(AlertChange::Cleared));
+        assert_eq!(state.alert(), None);
+    }
+}
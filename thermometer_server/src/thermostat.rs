@@ -0,0 +1,299 @@
+use crate::{log, ThermometerState};
+use smart_socket_client::{ClientConfig, ClientStream, SmartSocketClient};
+use smart_socket_protocol::{Address, Command, ProtocolError, Response};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Drives a heater socket from the latest temperature reading.
+#[derive(Debug, Clone)]
+pub struct ThermostatConfig {
+    /// Address of the smart socket server the heater is plugged into.
+    pub socket_address: Address,
+    /// The heater is switched on below this temperature.
+    pub setpoint: f64,
+    /// The heater is switched off above `setpoint + hysteresis`.
+    pub hysteresis: f64,
+    pub poll_interval: Duration,
+}
+
+impl Default for ThermostatConfig {
+    fn default() -> Self {
+        Self {
+            socket_address: ClientConfig::default().address,
+            setpoint: 20.0,
+            hysteresis: 1.0,
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Something that can deliver ON/OFF commands to the heater socket.
+pub trait CommandSender {
+    fn send(&mut self, command: Command) -> Result<(), ProtocolError>;
+}
+
+/// Sends commands over the socket protocol, reconnecting after failures.
+pub struct SocketSender {
+    config: ClientConfig,
+    client: Option<SmartSocketClient<ClientStream>>,
+}
+
+impl SocketSender {
+    pub fn new(address: &Address) -> Self {
+        let mut config = ClientConfig::default();
+        config.address = address.clone();
+        Self {
+            config,
+            client: None,
+        }
+    }
+}
+
+impl CommandSender for SocketSender {
+    fn send(&mut self, command: Command) -> Result<(), ProtocolError> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => self
+                .client
+                .insert(SmartSocketClient::with_config(self.config.clone())?),
+        };
+        match client.send_command(command) {
+            Ok(Response::Error(err)) => Err(ProtocolError::InvalidResponse(err)),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.client = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// On/off decision with hysteresis. The heater state is only considered known
+/// once a command has been delivered, so nothing is re-sent while it matches.
+#[derive(Debug)]
+pub struct Controller {
+    setpoint: f64,
+    hysteresis: f64,
+    heater_on: Option<bool>,
+}
+
+impl Controller {
+    pub fn new(setpoint: f64, hysteresis: f64) -> Self {
+        Self {
+            setpoint,
+            hysteresis,
+            heater_on: None,
+        }
+    }
+
+    /// Returns the command needed for `temperature`, if the heater must change state.
+    pub fn decide(&self, temperature: f64) -> Option<Command> {
+        let wanted = if temperature < self.setpoint {
+            true
+        } else if temperature > self.setpoint + self.hysteresis {
+            false
+        } else {
+            return None;
+        };
+        if self.heater_on == Some(wanted) {
+            return None;
+        }
+        Some(if wanted {
+            Command::TurnOn
+        } else {
+            Command::TurnOff
+        })
+    }
+
+    /// Records that `command` reached the socket.
+    pub fn confirm(&mut self, command: &Command) {
+        match command {
+            Command::TurnOn => self.heater_on = Some(true),
+            Command::TurnOff => self.heater_on = Some(false),
+            _ => {}
+        }
+    }
+
+    pub fn heater_on(&self) -> Option<bool> {
+        self.heater_on
+    }
+
+    /// Decides and, if needed, sends the command for `temperature`.
+    pub fn update<S: CommandSender>(
+        &mut self,
+        temperature: f64,
+        sender: &mut S,
+    ) -> Result<(), ProtocolError> {
+        if let Some(command) = self.decide(temperature) {
+            sender.send(command.clone())?;
+            log(&format!(
+                "Thermostat: {:.1}°C, heater {}",
+                temperature,
+                if matches!(command, Command::TurnOn) {
+                    "on"
+                } else {
+                    "off"
+                }
+            ));
+            self.confirm(&command);
+        }
+        Ok(())
+    }
+}
+
+/// Exponential retry delay, doubled after each failure up to `max`.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+fn sleep_while_running(duration: Duration, running: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while running.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+/// Polls `state` and drives the heater until `running` is cleared. Stale readings
+/// are ignored; delivery failures are logged and retried with backoff.
+pub fn run_controller<S: CommandSender>(
+    config: &ThermostatConfig,
+    state: &ThermometerState,
+    mut sender: S,
+    running: &AtomicBool,
+) {
+    let mut controller = Controller::new(config.setpoint, config.hysteresis);
+    let mut backoff = Backoff::new(config.poll_interval, Duration::from_secs(60));
+
+    while running.load(Ordering::SeqCst) {
+        let reading = state.current_reading();
+        let delay = if reading.stale {
+            config.poll_interval
+        } else {
+            match controller.update(reading.value, &mut sender) {
+                Ok(()) => {
+                    backoff.reset();
+                    config.poll_interval
+                }
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    log(&format!(
+                        "Thermostat failed to reach {}: {}; retrying in {:?}",
+                        config.socket_address, e, delay
+                    ));
+                    delay
+                }
+            }
+        };
+        sleep_while_running(delay, running);
+    }
+    log("Thermostat controller stopped");
+}
+
+pub(crate) fn spawn(
+    config: ThermostatConfig,
+    state: Arc<ThermometerState>,
+    running: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let sender = SocketSender::new(&config.socket_address);
+        run_controller(&config, &state, sender, &running);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockSender {
+        sent: Vec<String>,
+        failures: usize,
+    }
+
+    impl CommandSender for MockSender {
+        fn send(&mut self, command: Command) -> Result<(), ProtocolError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(ProtocolError::ConnectionError("refused".to_string()));
+            }
+            self.sent.push(command.to_string());
+            Ok(())
+        }
+    }
+
+    fn drive(temperatures: &[f64], sender: &mut MockSender) -> Controller {
+        let mut controller = Controller::new(20.0, 1.0);
+        for &temperature in temperatures {
+            let _ = controller.update(temperature, sender);
+        }
+        controller
+    }
+
+    #[test]
+    fn test_transitions_respect_hysteresis() {
+        let mut sender = MockSender::default();
+        let controller = drive(
+            &[19.5, 19.0, 20.0, 20.5, 21.0, 21.2, 21.5, 20.5, 20.0, 19.9],
+            &mut sender,
+        );
+        assert_eq!(sender.sent, ["ON", "OFF", "ON"]);
+        assert_eq!(controller.heater_on(), Some(true));
+    }
+
+    #[test]
+    fn test_within_band_from_unknown_state_sends_nothing() {
+        let mut sender = MockSender::default();
+        let controller = drive(&[20.0, 20.5, 21.0], &mut sender);
+        assert!(sender.sent.is_empty());
+        assert_eq!(controller.heater_on(), None);
+    }
+
+    #[test]
+    fn test_failed_command_is_retried() {
+        let mut sender = MockSender {
+            failures: 2,
+            ..Default::default()
+        };
+        let controller = drive(&[18.0, 18.0, 18.0, 18.0], &mut sender);
+        assert_eq!(sender.sent, ["ON"]);
+        assert_eq!(controller.heater_on(), Some(true));
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+}
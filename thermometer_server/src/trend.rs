@@ -0,0 +1,180 @@
+//! Metrics derived from the accepted readings: an exponential moving average
+//! and the rate of change over a sliding window, for controllers that need to
+//! know where the temperature is heading rather than where it is.
+//!
+//! The caller passes the time of every reading, which keeps the numbers testable.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Readings closer together than this give no rate: a fraction of a second
+/// between two noisy samples would extrapolate to absurd °C per minute.
+pub const MIN_RATE_SPAN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrendConfig {
+    /// Weight of the newest reading in the average, in `(0, 1]`; 1 follows the
+    /// readings exactly.
+    pub alpha: f64,
+    /// How far back the rate of change looks.
+    pub window: Duration,
+}
+
+impl Default for TrendConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 0.2,
+            window: Duration::from_secs(300),
+        }
+    }
+}
+
+impl TrendConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.alpha > 0.0 && self.alpha <= 1.0) {
+            return Err("alpha must be greater than 0 and at most 1".to_string());
+        }
+        if self.window.is_zero() {
+            return Err("window must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The derived metrics at one point in time; `None` until enough readings arrived.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrendMetrics {
+    pub ema: Option<f64>,
+    /// °C per minute between the oldest and newest reading in the window.
+    pub rate_per_minute: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct Trend {
+    config: TrendConfig,
+    ema: Option<f64>,
+    /// Readings in the window, oldest first, plus the last one before it so that
+    /// a rate can still be given after a gap longer than the window.
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl Trend {
+    pub fn new(config: TrendConfig) -> Self {
+        Self {
+            config,
+            ema: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Adds an accepted reading. A reading timed before the previous one means
+    /// the clock went back: the window starts over from it instead of giving a
+    /// negative time span.
+    pub fn record(&mut self, celsius: f64, now: Instant) {
+        if !celsius.is_finite() {
+            return;
+        }
+        self.ema = Some(match self.ema {
+            Some(ema) => ema + self.config.alpha * (celsius - ema),
+            None => celsius,
+        });
+        if self.samples.back().is_some_and(|(last, _)| now < *last) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, celsius));
+        while self.samples.len() > 1
+            && now.saturating_duration_since(self.samples[1].0) >= self.config.window
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn metrics(&self) -> TrendMetrics {
+        let rate_per_minute = match (self.samples.front(), self.samples.back()) {
+            (Some((first_at, first)), Some((last_at, last))) => {
+                let span = last_at.saturating_duration_since(*first_at);
+                (span >= MIN_RATE_SPAN).then(|| (last - first) / span.as_secs_f64() * 60.0)
+            }
+            _ => None,
+        };
+        TrendMetrics {
+            ema: self.ema,
+            rate_per_minute,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: TrendConfig = TrendConfig {
+        alpha: 0.5,
+        window: Duration::from_secs(120),
+    };
+
+    /// Feeds `(seconds after start, °C)` readings.
+    fn scripted(start: Instant, readings: &[(u64, f64)]) -> Trend {
+        let mut trend = Trend::new(CONFIG);
+        for (secs, celsius) in readings {
+            trend.record(*celsius, start + Duration::from_secs(*secs));
+        }
+        trend
+    }
+
+    fn assert_rate(trend: &Trend, expected: f64) {
+        let rate = trend.metrics().rate_per_minute.unwrap();
+        assert!((rate - expected).abs() < 1e-9, "{} != {}", rate, expected);
+    }
+
+    #[test]
+    fn test_first_sample() {
+        let start = Instant::now();
+        assert_eq!(Trend::new(CONFIG).metrics(), TrendMetrics::default());
+        let metrics = scripted(start, &[(0, 21.0)]).metrics();
+        assert_eq!(metrics.ema, Some(21.0));
+        assert_eq!(metrics.rate_per_minute, None);
+    }
+
+    #[test]
+    fn test_ema_and_rate_over_the_window() {
+        let start = Instant::now();
+        let trend = scripted(start, &[(0, 20.0), (60, 21.0), (120, 22.0), (180, 24.0)]);
+        // 20 -> 20.5 -> 21.25 -> 22.625
+        assert_eq!(trend.metrics().ema, Some(22.625));
+        // The window holds the readings at 60 s to 180 s.
+        assert_rate(&trend, 1.5);
+    }
+
+    #[test]
+    fn test_long_gap_uses_the_actual_elapsed_time() {
+        let start = Instant::now();
+        let trend = scripted(start, &[(0, 20.0), (1200, 18.0)]);
+        assert_rate(&trend, -0.1);
+    }
+
+    #[test]
+    fn test_clock_going_backwards_restarts_the_window() {
+        let start = Instant::now();
+        let trend = scripted(start, &[(100, 20.0), (160, 21.0), (10, 30.0)]);
+        let metrics = trend.metrics();
+        assert_eq!(metrics.rate_per_minute, None);
+        assert!(metrics.ema.is_some_and(f64::is_finite));
+
+        let mut trend = trend;
+        trend.record(31.0, start + Duration::from_secs(70));
+        assert_rate(&trend, 1.0);
+    }
+
+    #[test]
+    fn test_readings_too_close_together_give_no_rate() {
+        let start = Instant::now();
+        let mut trend = Trend::new(CONFIG);
+        trend.record(20.0, start);
+        trend.record(25.0, start + Duration::from_millis(10));
+        trend.record(f64::NAN, start + Duration::from_millis(20));
+        let metrics = trend.metrics();
+        assert_eq!(metrics.rate_per_minute, None);
+        assert_eq!(metrics.ema, Some(22.5));
+    }
+}
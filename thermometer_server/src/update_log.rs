@@ -0,0 +1,186 @@
+//! Summarised logging of accepted readings. At high update rates one log line
+//! per packet costs more than handling the packet, so updates are counted and a
+//! single line with their range, and the latest value of every device that
+//! reported, is logged per interval instead.
+//!
+//! The caller passes the time of every update, which keeps the intervals testable.
+
+use crate::settings::DisplayUnit;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How often a summary is logged unless configured otherwise.
+pub const DEFAULT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Updates accepted within one interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateSummary {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    /// The last reading of each device id that reported.
+    pub latest: BTreeMap<String, f64>,
+    /// From the first update of the interval to the time the summary was taken.
+    pub period: Duration,
+}
+
+impl UpdateSummary {
+    /// The log line, with temperatures shown in `unit`.
+    pub fn describe(&self, unit: DisplayUnit) -> String {
+        let latest: Vec<String> = self
+            .latest
+            .iter()
+            .map(|(device_id, celsius)| format!("{} {}", device_id, unit.format(*celsius)))
+            .collect();
+        format!(
+            "{} temperature updates in {:.1}s: min {}, max {}; latest: {}",
+            self.count,
+            self.period.as_secs_f64(),
+            unit.format(self.min),
+            unit.format(self.max),
+            latest.join(", ")
+        )
+    }
+}
+
+/// Counts updates and tracks their range until a summary is due. An interval
+/// starts with its first update, so quiet periods produce no log lines.
+#[derive(Debug)]
+pub struct UpdateLogAggregator {
+    interval: Duration,
+    started: Option<Instant>,
+    count: u64,
+    min: f64,
+    max: f64,
+    latest: BTreeMap<String, f64>,
+}
+
+impl UpdateLogAggregator {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            started: None,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            latest: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an accepted reading from `device_id`; returns the summary once the
+    /// interval is over.
+    pub fn record(&mut self, device_id: &str, celsius: f64, now: Instant) -> Option<UpdateSummary> {
+        self.started.get_or_insert(now);
+        self.count += 1;
+        self.min = self.min.min(celsius);
+        self.max = self.max.max(celsius);
+        match self.latest.get_mut(device_id) {
+            Some(latest) => *latest = celsius,
+            None => {
+                self.latest.insert(device_id.to_string(), celsius);
+            }
+        }
+        self.tick(now)
+    }
+
+    /// Returns the summary if the interval is over, also when no update arrived
+    /// to notice it.
+    pub fn tick(&mut self, now: Instant) -> Option<UpdateSummary> {
+        let started = self.started?;
+        if now.saturating_duration_since(started) < self.interval {
+            return None;
+        }
+        self.flush(now)
+    }
+
+    /// Returns what has accumulated so far, if anything, and starts over; for
+    /// shutdown, so the last partial interval is not lost.
+    pub fn flush(&mut self, now: Instant) -> Option<UpdateSummary> {
+        let started = self.started?;
+        let fresh = Self::new(self.interval);
+        let done = std::mem::replace(self, fresh);
+        Some(UpdateSummary {
+            count: done.count,
+            min: done.min,
+            max: done.max,
+            latest: done.latest,
+            period: now.saturating_duration_since(started),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_updates_are_summarised_once_per_interval() {
+        let start = Instant::now();
+        let mut log = UpdateLogAggregator::new(INTERVAL);
+        for (i, celsius) in [21.0, 19.5, 22.5, 20.0].into_iter().enumerate() {
+            let at = start + Duration::from_secs(2 * i as u64);
+            assert_eq!(log.record("default", celsius, at), None);
+        }
+
+        let summary = log.record("default", 20.5, start + INTERVAL).unwrap();
+        assert_eq!(
+            summary,
+            UpdateSummary {
+                count: 5,
+                min: 19.5,
+                max: 22.5,
+                latest: BTreeMap::from([("default".to_string(), 20.5)]),
+                period: INTERVAL,
+            }
+        );
+        assert_eq!(
+            summary.describe(DisplayUnit::Celsius),
+            "5 temperature updates in 10.0s: min 19.5°C, max 22.5°C; latest: default 20.5°C"
+        );
+
+        // The next interval starts from scratch with its first update.
+        let later = start + INTERVAL * 5;
+        assert_eq!(log.record("default", 25.0, later), None);
+        let summary = log.tick(later + INTERVAL).unwrap();
+        assert_eq!((summary.count, summary.min, summary.max), (1, 25.0, 25.0));
+    }
+
+    #[test]
+    fn test_quiet_periods_log_nothing() {
+        let start = Instant::now();
+        let mut log = UpdateLogAggregator::new(INTERVAL);
+        assert_eq!(log.tick(start + INTERVAL * 3), None);
+        assert_eq!(log.flush(start + INTERVAL * 3), None);
+    }
+
+    #[test]
+    fn test_flush_returns_the_partial_interval_and_resets() {
+        let start = Instant::now();
+        let mut log = UpdateLogAggregator::new(INTERVAL);
+        log.record("default", 18.0, start);
+        log.record("default", 19.0, start + Duration::from_secs(1));
+
+        let summary = log.flush(start + Duration::from_secs(3)).unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.period, Duration::from_secs(3));
+        assert_eq!(log.flush(start + Duration::from_secs(4)), None);
+    }
+
+    #[test]
+    fn test_summary_lists_every_device() {
+        let start = Instant::now();
+        let mut log = UpdateLogAggregator::new(INTERVAL);
+        log.record("kitchen", 21.0, start);
+        log.record("bedroom", 18.5, start);
+        log.record("kitchen", 22.0, start + Duration::from_secs(1));
+
+        let summary = log.flush(start + Duration::from_secs(2)).unwrap();
+        assert_eq!(
+            summary.describe(DisplayUnit::Celsius),
+            "3 temperature updates in 2.0s: min 18.5°C, max 22.0°C; \
+             latest: bedroom 18.5°C, kitchen 22.0°C"
+        );
+    }
+}